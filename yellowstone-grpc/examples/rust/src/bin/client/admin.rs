@@ -0,0 +1,433 @@
+//! Admin HTTP API of the running client: runtime settings, status, metrics, recent updates
+//! and the web UI built on them, see `serve` for the endpoints.
+
+mod metrics;
+
+use {
+    crate::{
+        bandwidth::BandwidthMeter,
+        budget::MemoryBudget,
+        capture::CaptureReader,
+        catchup::CatchUp,
+        checkpoint::Checkpoint,
+        coalesce::AccountCoalescer,
+        correlate::TxCorrelator,
+        decode::DecodePool,
+        dedup::DedupCache,
+        discover::AccountDiscovery,
+        events::EventBus,
+        expr::ExprFilter,
+        facade::RpcFacade,
+        filters::{FeePayerFilter, LamportsFilter, LogFilter},
+        forks::ForkDetector,
+        gaps::GapDetector,
+        hold::CommitmentHold,
+        lifecycle::{Lifecycle, LifecycleQuery, LifecycleResponse},
+        mints::MintTracker,
+        multi::MultiMerge,
+        owners::{OwnerTokens, TokenOwners},
+        policy::ErrorPolicy,
+        poll::PollValues,
+        pool::ConnectionPool,
+        priority::PriorityLanes,
+        progress::{Progress, ProgressReport},
+        queue::UpdateQueue,
+        reassemble::BlockAssembler,
+        recent::{RecentQuery, RecentUpdates, RecentUpdatesResponse},
+        reconnects::{HistorySnapshot, ReconnectHistory},
+        sampler::StreamSampler,
+        schema::SchemaCheck,
+        serve::Broadcast,
+        settings::{RuntimeSettings, SettingsPatch, SettingsSnapshot},
+        sink::{SinkHealth, Sinks},
+        slo::{LatencySlos, SloStatus},
+        statesync::StateSync,
+        stats::{update_slot, StreamStats, SubscribedFilter},
+        tags::{FilterTags, Tags},
+    },
+    axum::{
+        extract::{Path, Query, State},
+        http::StatusCode,
+        response::Html,
+        routing::{get, post},
+        Json, Router,
+    },
+    log::info,
+    serde::{Deserialize, Serialize},
+    serde_json::{json, Value},
+    std::{
+        collections::{BTreeMap, VecDeque},
+        net::SocketAddr,
+        path::{self, PathBuf},
+        sync::Arc,
+    },
+    tokio::net::TcpListener,
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+const DEFAULT_REPLAY_COUNT: usize = 1_000;
+/// Status page of `GET /`, it polls the JSON endpoints
+const UI_PAGE: &str = include_str!("admin/ui.html");
+
+#[derive(Clone)]
+pub struct AdminState {
+    pub settings: Arc<RuntimeSettings>,
+    pub stats: Arc<StreamStats>,
+    pub poll: Arc<PollValues>,
+    pub queue: Arc<UpdateQueue>,
+    pub budget: Option<Arc<MemoryBudget>>,
+    pub bandwidth: Arc<BandwidthMeter>,
+    pub dedup: Option<Arc<DedupCache>>,
+    pub forks: Option<Arc<ForkDetector>>,
+    pub gaps: Option<Arc<GapDetector>>,
+    pub hold: Option<Arc<CommitmentHold>>,
+    pub priority: Option<Arc<PriorityLanes>>,
+    pub assembler: Option<Arc<BlockAssembler>>,
+    pub coalescer: Option<Arc<AccountCoalescer>>,
+    pub events: Arc<EventBus>,
+    pub lamports: Option<Arc<LamportsFilter>>,
+    pub logs: Option<Arc<LogFilter>>,
+    pub fee_payers: Option<Arc<FeePayerFilter>>,
+    pub exprs: Option<Arc<ExprFilter>>,
+    pub correlator: Option<Arc<TxCorrelator>>,
+    pub progress: Option<Arc<Progress>>,
+    pub discovery: Option<Arc<AccountDiscovery>>,
+    pub decoder: Option<Arc<DecodePool>>,
+    pub sampler: Option<Arc<StreamSampler>>,
+    pub slos: Option<Arc<LatencySlos>>,
+    pub errors: Arc<ErrorPolicy>,
+    pub schema: Arc<SchemaCheck>,
+    pub multi: Option<Arc<MultiMerge>>,
+    pub pool: Option<Arc<ConnectionPool>>,
+    pub catchup: Option<Arc<CatchUp>>,
+    pub serve: Option<Arc<Broadcast>>,
+    pub recent: Option<Arc<RecentUpdates>>,
+    pub token_owners: Option<Arc<TokenOwners>>,
+    pub mints: Option<Arc<MintTracker>>,
+    pub facade: Option<Arc<RpcFacade>>,
+    pub state_sync: Option<Arc<StateSync>>,
+    pub tags: Arc<FilterTags>,
+    pub reconnects: Arc<ReconnectHistory>,
+    pub lifecycle: Arc<Lifecycle>,
+    pub sinks: Arc<Sinks>,
+    pub checkpoint: Arc<Checkpoint>,
+    /// `ADMIN_REPLAY_DIR`, the only directory `POST /replay` reads capture files from
+    pub replay_dir: Option<PathBuf>,
+}
+
+impl AdminState {
+    /// Polled slot minus the highest slot received from the stream
+    fn slot_lag(&self) -> Option<u64> {
+        self.poll
+            .snapshot()
+            .slot
+            .zip(self.stats.last_slot())
+            .map(|(polled, streamed)| polled.saturating_sub(streamed))
+    }
+
+    /// Highest received slot minus the slot acknowledged by the sink
+    fn sink_lag(&self, health: &SinkHealth) -> Option<u64> {
+        self.stats
+            .last_slot()
+            .zip(health.acked_slot)
+            .map(|(received, acked)| received.saturating_sub(acked))
+    }
+}
+
+/// Body of `POST /replay`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplayRequest {
+    /// Name of the sink, as in `GET /status`
+    sink: String,
+    /// Number of the newest updates to replay
+    last: Option<usize>,
+    /// Only updates of this and later slots
+    from_slot: Option<u64>,
+    /// Capture file of `ACTION=Record` in `ADMIN_REPLAY_DIR` to read updates from instead of
+    /// `RECENT_SLOTS`
+    path: Option<String>,
+}
+
+/// Resolves `name` in the replay directory, files outside of it (absolute paths, `..` or
+/// symlinks leading out) are rejected
+fn capture_path(dir: Option<&path::Path>, name: &str) -> anyhow::Result<PathBuf> {
+    let dir = dir.ok_or_else(|| anyhow::anyhow!("replay from `path` requires ADMIN_REPLAY_DIR"))?;
+    let dir = dir
+        .canonicalize()
+        .map_err(|error| anyhow::anyhow!("failed to open {}: {error}", dir.display()))?;
+    let path = dir
+        .join(name)
+        .canonicalize()
+        .map_err(|error| anyhow::anyhow!("failed to open {name}: {error}"))?;
+    anyhow::ensure!(
+        path.starts_with(&dir) && path.is_file(),
+        "{name} is not a file in ADMIN_REPLAY_DIR"
+    );
+    Ok(path)
+}
+
+/// Last `count` updates of the capture file from `from_slot`
+fn read_capture(
+    path: &path::Path,
+    count: usize,
+    from_slot: Option<u64>,
+) -> anyhow::Result<Vec<SubscribeUpdate>> {
+    let mut reader = CaptureReader::open(path)
+        .map_err(|error| anyhow::anyhow!("failed to open {}: {error}", path.display()))?;
+    let mut updates = VecDeque::new();
+    while let Some((_, msg)) = reader.read()? {
+        if update_slot(&msg) < from_slot {
+            continue;
+        }
+        if updates.len() == count {
+            updates.pop_front();
+        }
+        updates.push_back(msg);
+    }
+    Ok(updates.into())
+}
+
+#[derive(Debug, Serialize)]
+struct QueueStatus {
+    depth: usize,
+    capacity: usize,
+    dropped: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct SinkStatus {
+    #[serde(flatten)]
+    health: SinkHealth,
+    /// Highest received slot minus the acknowledged slot
+    lag_slots: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    #[serde(flatten)]
+    reconnects: HistorySnapshot,
+    messages: u64,
+    last_slot: Option<u64>,
+    slot_lag: Option<u64>,
+    /// Received messages per filter
+    filters: BTreeMap<String, u64>,
+    queue: QueueStatus,
+    sinks: Vec<SinkStatus>,
+    /// Highest slot acknowledged by the quorum of sinks
+    checkpoint: Option<u64>,
+    /// Processed slots by update type, `PROGRESS_PATH`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<ProgressReport>,
+    /// Latency objectives of delivery to sinks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slo: Option<Vec<SloStatus>>,
+}
+
+#[derive(Debug, Serialize)]
+struct FilterConfig {
+    #[serde(flatten)]
+    filter: SubscribedFilter,
+    tags: Tags,
+}
+
+/// Serve the admin HTTP API:
+///   - `GET /` — web page with status, filters, throughput and a searchable tail of recent
+///     updates, built on the endpoints below
+///   - `GET /settings` — current runtime settings
+///   - `PATCH /settings` — update some of the runtime settings, body is a JSON object
+///   - `GET /metrics` — stream counters and polled values in Prometheus text format
+///   - `GET /bandwidth` — received bytes per filter with monthly bandwidth and cost projection
+///   - `GET /status` — active endpoint, open stream, history of stream ends, counters of
+///     filters, queue and sinks
+///   - `GET /filters` — filters of the last sent subscribe request with their tags
+///   - `GET /recent` — updates of the last `RECENT_SLOTS` slots, query by `slot`, `type`,
+///     `pubkey`, `filter` and `limit`
+///   - `POST /replay` — pass recent or recorded updates again to one sink, body is a JSON
+///     object
+///   - `GET /owner/<pubkey>/tokens` — token accounts of a wallet indexed from the stream
+///   - `GET /lifecycle` — current phase and state transitions, query by `since`
+pub async fn serve(addr: SocketAddr, state: AdminState) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/", get(get_ui))
+        .route("/settings", get(get_settings).patch(patch_settings))
+        .route("/metrics", get(metrics::get_metrics))
+        .route("/bandwidth", get(get_bandwidth))
+        .route("/status", get(get_status))
+        .route("/filters", get(get_filters))
+        .route("/recent", get(get_recent))
+        .route("/replay", post(post_replay))
+        .route("/owner/{pubkey}/tokens", get(get_owner_tokens))
+        .route("/lifecycle", get(get_lifecycle))
+        .with_state(state);
+
+    let listener = TcpListener::bind(addr).await?;
+    info!("admin api listening on {addr}");
+    axum::serve(listener, app).await.map_err(Into::into)
+}
+
+async fn get_ui() -> Html<&'static str> {
+    Html(UI_PAGE)
+}
+
+async fn get_settings(State(state): State<AdminState>) -> Json<SettingsSnapshot> {
+    Json(state.settings.snapshot())
+}
+
+async fn patch_settings(
+    State(state): State<AdminState>,
+    Json(patch): Json<SettingsPatch>,
+) -> Result<Json<SettingsSnapshot>, (StatusCode, String)> {
+    info!("admin: update settings {patch:?}");
+    state
+        .settings
+        .apply(patch)
+        .map(Json)
+        .map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))
+}
+
+async fn get_bandwidth(State(state): State<AdminState>) -> Json<Value> {
+    Json(state.bandwidth.report())
+}
+
+async fn get_status(State(state): State<AdminState>) -> Json<Status> {
+    Json(Status {
+        reconnects: state.reconnects.snapshot(),
+        messages: state.stats.messages(),
+        last_slot: state.stats.last_slot(),
+        slot_lag: state.slot_lag(),
+        filters: state.stats.filters(),
+        queue: QueueStatus {
+            depth: state.queue.depth(),
+            capacity: state.queue.capacity(),
+            dropped: state.queue.dropped(),
+        },
+        sinks: state
+            .sinks
+            .health()
+            .into_iter()
+            .map(|health| SinkStatus {
+                lag_slots: state.sink_lag(&health),
+                health,
+            })
+            .collect(),
+        checkpoint: state.checkpoint.slot(),
+        progress: state.progress.as_ref().map(|progress| progress.report()),
+        slo: state.slos.as_ref().map(|slos| slos.status()),
+    })
+}
+
+async fn get_filters(State(state): State<AdminState>) -> Json<BTreeMap<String, FilterConfig>> {
+    Json(
+        state
+            .stats
+            .subscription()
+            .into_iter()
+            .map(|(name, filter)| {
+                let tags = state.tags.filter(&name);
+                (name, FilterConfig { filter, tags })
+            })
+            .collect(),
+    )
+}
+
+async fn get_recent(
+    State(state): State<AdminState>,
+    Query(query): Query<RecentQuery>,
+) -> Result<Json<RecentUpdatesResponse>, (StatusCode, String)> {
+    let Some(recent) = state.recent.as_ref() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "recent updates are not kept, set RECENT_SLOTS".to_owned(),
+        ));
+    };
+    recent
+        .query(query)
+        .map(Json)
+        .map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))
+}
+
+async fn get_lifecycle(
+    State(state): State<AdminState>,
+    Query(query): Query<LifecycleQuery>,
+) -> Json<LifecycleResponse> {
+    Json(state.lifecycle.query(query))
+}
+
+async fn get_owner_tokens(
+    State(state): State<AdminState>,
+    Path(pubkey): Path<String>,
+) -> Result<Json<OwnerTokens>, (StatusCode, String)> {
+    let Some(token_owners) = state.token_owners.as_ref() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "token accounts are not indexed, set TOKEN_OWNERS=true".to_owned(),
+        ));
+    };
+    token_owners
+        .owner(&pubkey)
+        .map(Json)
+        .map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()))
+}
+
+async fn post_replay(
+    State(state): State<AdminState>,
+    Json(request): Json<ReplayRequest>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let bad_request = |error: anyhow::Error| (StatusCode::BAD_REQUEST, format!("{error:#}"));
+    let count = request.last.unwrap_or(DEFAULT_REPLAY_COUNT);
+    let updates = match (request.path, state.recent.as_ref()) {
+        (Some(name), _) => {
+            let path = capture_path(state.replay_dir.as_deref(), &name).map_err(bad_request)?;
+            tokio::task::spawn_blocking(move || read_capture(&path, count, request.from_slot))
+                .await
+                .map_err(|error| bad_request(error.into()))?
+                .map_err(bad_request)?
+        }
+        (None, Some(recent)) => recent.last(count, request.from_slot),
+        (None, None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                "recent updates are not kept, set RECENT_SLOTS or replay from `path`".to_owned(),
+            ))
+        }
+    };
+
+    state
+        .sinks
+        .replay(&request.sink, &updates)
+        .map_err(bad_request)?;
+    info!(
+        "admin: replayed {} updates to sink {}",
+        updates.len(),
+        request.sink
+    );
+    Ok(Json(json!({
+        "sink": request.sink,
+        "replayed": updates.len(),
+        "oldest_slot": updates.first().and_then(update_slot),
+        "newest_slot": updates.last().and_then(update_slot),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::capture_path, std::fs};
+
+    #[test]
+    fn capture_path_stays_in_replay_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let dir = root.join("captures");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("capture.bin"), b"").unwrap();
+        fs::write(root.join("secret.bin"), b"").unwrap();
+
+        assert!(capture_path(None, "capture.bin").is_err());
+        assert!(capture_path(Some(&dir), "capture.bin").is_ok());
+        assert!(capture_path(Some(&dir), "../secret.bin").is_err());
+        let absolute = root.join("secret.bin");
+        assert!(capture_path(Some(&dir), absolute.to_str().unwrap()).is_err());
+        assert!(capture_path(Some(&dir), ".").is_err());
+    }
+}
@@ -0,0 +1,874 @@
+//! `GET /metrics` of the admin API in the Prometheus text format.
+//!
+//! Counts which only grow while the client runs are counters named with a `_total` suffix,
+//! current values are gauges. Metrics of an optional part of the client are only written
+//! when it is enabled, every part writes its metrics with its own function below.
+
+use {
+    super::AdminState,
+    crate::{lifecycle::Phase, tags::FilterTags},
+    axum::extract::State,
+    std::fmt::{Display, Write},
+};
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+    Summary,
+}
+
+/// Metrics text being built
+#[derive(Debug, Default)]
+struct Metrics(String);
+
+impl Metrics {
+    /// Start a metric with its `# HELP` and `# TYPE` lines, counters get the `_total` suffix
+    fn family(&mut self, name: &str, kind: Kind, help: &str) -> Family<'_> {
+        let (name, kind) = match kind {
+            Kind::Counter => (format!("{name}_total"), "counter"),
+            Kind::Gauge => (name.to_owned(), "gauge"),
+            Kind::Summary => (name.to_owned(), "summary"),
+        };
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} {kind}");
+        Family {
+            metrics: self,
+            name,
+        }
+    }
+
+    /// Gauge without labels, skipped without a value
+    fn gauge(&mut self, name: &str, help: &str, value: Option<impl Display>) {
+        if let Some(value) = value {
+            self.family(name, Kind::Gauge, help).sample(&[], value);
+        }
+    }
+
+    /// Counter without labels, skipped without a value
+    fn counter(&mut self, name: &str, help: &str, value: Option<u64>) {
+        if let Some(value) = value {
+            self.family(name, Kind::Counter, help).sample(&[], value);
+        }
+    }
+}
+
+/// Samples of one metric
+struct Family<'a> {
+    metrics: &'a mut Metrics,
+    name: String,
+}
+
+impl Family<'_> {
+    fn sample(&mut self, labels: &[(&str, &str)], value: impl Display) {
+        let out = &mut self.metrics.0;
+        out.push_str(&self.name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+                .collect::<Vec<_>>();
+            let _ = write!(out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(out, " {value}");
+    }
+}
+
+/// Label value with backslash, double quote and line feed escaped as the text format requires
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `filter` label and tags of the filter, tag keys are reduced to valid label names
+fn filter_labels(tags: &FilterTags, filter: &str) -> Vec<(String, String)> {
+    let mut labels = vec![("filter".to_owned(), filter.to_owned())];
+    for (key, value) in tags.filter(filter) {
+        let key = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        if key.is_empty() || key == "filter" || key.starts_with(|c: char| c.is_ascii_digit()) {
+            continue;
+        }
+        labels.push((key, value));
+    }
+    labels
+}
+
+pub async fn get_metrics(State(state): State<AdminState>) -> String {
+    let mut metrics = Metrics::default();
+    stream(&mut metrics, &state);
+    queue(&mut metrics, &state);
+    filters(&mut metrics, &state);
+    slots(&mut metrics, &state);
+    indexes(&mut metrics, &state);
+    poll(&mut metrics, &state);
+    pipeline(&mut metrics, &state);
+    sinks(&mut metrics, &state);
+    connections(&mut metrics, &state);
+    correlate(&mut metrics, &state);
+    progress(&mut metrics, &state);
+    slos(&mut metrics, &state);
+    metrics.0
+}
+
+/// Received messages, their filters and why streams ended
+fn stream(metrics: &mut Metrics, state: &AdminState) {
+    metrics.counter(
+        "client_stream_messages",
+        "Number of received stream messages",
+        Some(state.stats.messages()),
+    );
+    metrics.counter(
+        "client_stream_recoveries",
+        "Number of streams subscribed again on the same connection after a message error",
+        Some(state.reconnects.recoveries()),
+    );
+    metrics.gauge(
+        "client_stream_last_slot",
+        "Highest slot received from the stream",
+        state.stats.last_slot(),
+    );
+    metrics.counter(
+        "client_stream_bytes",
+        "Uncompressed size of received stream messages",
+        Some(state.bandwidth.total_bytes()),
+    );
+    metrics.gauge(
+        "client_stream_slot_lag",
+        "Polled slot minus the highest slot received from the stream",
+        state.slot_lag(),
+    );
+    metrics.gauge(
+        "client_catchup_caught_up",
+        "1 once the stream reached the tip and CATCHUP_MAX_RATE no longer applies",
+        state
+            .catchup
+            .as_ref()
+            .map(|catchup| u8::from(catchup.caught_up())),
+    );
+
+    let filters = state.stats.filters();
+    if !filters.is_empty() {
+        let mut family = metrics.family(
+            "client_stream_filter_messages",
+            Kind::Counter,
+            "Number of received messages per filter",
+        );
+        for (filter, count) in filters {
+            let labels = filter_labels(&state.tags, &filter);
+            family.sample(&borrow(&labels), count);
+        }
+
+        let mut family = metrics.family(
+            "client_stream_filter_bytes",
+            Kind::Counter,
+            "Uncompressed size of received messages per filter",
+        );
+        for (filter, bytes) in state.bandwidth.filter_bytes() {
+            let labels = filter_labels(&state.tags, &filter);
+            family.sample(&borrow(&labels), bytes);
+        }
+    }
+
+    let ends = state.reconnects.counts();
+    if !ends.is_empty() {
+        let mut family = metrics.family(
+            "client_stream_ends",
+            Kind::Counter,
+            "Number of ended streams and failed connects by reason",
+        );
+        for (reason, count) in ends {
+            family.sample(&[("reason", reason)], count);
+        }
+    }
+
+    let mut family = metrics.family(
+        "client_lifecycle_phase",
+        Kind::Gauge,
+        "Current phase of the client, 1 for the active one",
+    );
+    let current = state.lifecycle.phase();
+    for phase in Phase::ALL {
+        family.sample(&[("phase", phase.name())], u8::from(*phase == current));
+    }
+
+    let mut family = metrics.family(
+        "client_schema_unknown",
+        Kind::Counter,
+        "Number of updates with an update type or enum value of a newer proto",
+    );
+    for (kind, count) in state.schema.unknown() {
+        family.sample(&[("kind", &kind)], count);
+    }
+}
+
+/// Processing queue, memory budget and decode pool
+fn queue(metrics: &mut Metrics, state: &AdminState) {
+    metrics.gauge(
+        "client_queue_depth",
+        "Number of messages waiting for processing",
+        Some(state.queue.depth()),
+    );
+    metrics.gauge(
+        "client_queue_capacity",
+        "Capacity of the processing queue",
+        Some(state.queue.capacity()),
+    );
+    metrics.counter(
+        "client_queue_dropped",
+        "Number of messages dropped because the queue was full",
+        Some(state.queue.dropped()),
+    );
+    metrics.gauge(
+        "client_memory_budget_bytes",
+        "Memory budget of buffered updates",
+        state.budget.as_ref().map(|budget| budget.limit()),
+    );
+    metrics.gauge(
+        "client_memory_buffered_bytes",
+        "Encoded size of updates in the processing queue and hold",
+        state.budget.as_ref().map(|budget| budget.used()),
+    );
+    metrics.counter(
+        "client_memory_budget_exhausted",
+        "Number of updates which didn't fit into the memory budget",
+        state.budget.as_ref().map(|budget| budget.exhausted()),
+    );
+    metrics.counter(
+        "client_decode_dropped",
+        "Number of updates not logged because the decode queue was full",
+        state.decoder.as_ref().map(|decoder| decoder.dropped()),
+    );
+    metrics.counter(
+        "client_priority_updates",
+        "Number of updates of PRIORITY_FILTERS handled on the stream task",
+        state.priority.as_ref().map(|priority| priority.updates()),
+    );
+    metrics.counter(
+        "client_events_published",
+        "Number of updates sent to in-process event bus subscribers",
+        Some(state.events.published()),
+    );
+}
+
+/// Updates dropped on the client side
+fn filters(metrics: &mut Metrics, state: &AdminState) {
+    metrics.counter(
+        "client_dedup_duplicates",
+        "Number of duplicate updates dropped before sinks",
+        state.dedup.as_ref().map(|dedup| dedup.duplicates()),
+    );
+    metrics.counter(
+        "client_lamports_filtered",
+        "Number of account updates dropped by ACCOUNTS_MIN_LAMPORTS and ACCOUNTS_MAX_LAMPORTS",
+        state.lamports.as_ref().map(|lamports| lamports.filtered()),
+    );
+    metrics.counter(
+        "client_log_filter_filtered",
+        "Number of transactions dropped by TRANSACTIONS_LOG_CONTAINS and TRANSACTIONS_LOG_REGEX",
+        state.logs.as_ref().map(|logs| logs.filtered()),
+    );
+    metrics.counter(
+        "client_log_filter_passed",
+        "Number of transactions with a log message matched by the log filter",
+        state.logs.as_ref().map(|logs| logs.passed()),
+    );
+    metrics.counter(
+        "client_fee_payer_filtered",
+        "Number of transactions dropped by TRANSACTIONS_FEE_PAYER",
+        state
+            .fee_payers
+            .as_ref()
+            .map(|fee_payers| fee_payers.filtered()),
+    );
+    metrics.counter(
+        "client_filter_expr_filtered",
+        "Number of updates dropped by FILTER_EXPR",
+        state.exprs.as_ref().map(|exprs| exprs.filtered()),
+    );
+
+    if let Some(sampler) = state.sampler.as_ref() {
+        let mut family = metrics.family(
+            "client_sampler_dropped",
+            Kind::Counter,
+            "Number of updates dropped by SAMPLE_RATE and MAX_MSGS_PER_SEC",
+        );
+        for (kind, reason, count) in sampler.dropped() {
+            family.sample(&[("kind", kind), ("reason", reason)], count);
+        }
+    }
+}
+
+/// Forks, gaps, held, reassembled and coalesced updates
+fn slots(metrics: &mut Metrics, state: &AdminState) {
+    metrics.counter(
+        "client_slot_rollbacks",
+        "Number of detected forks whose slots were rolled back",
+        state.forks.as_ref().map(|forks| forks.rollbacks()),
+    );
+    metrics.counter(
+        "client_slots_rolled_back",
+        "Number of slots not finalized because their fork was abandoned",
+        state.forks.as_ref().map(|forks| forks.rolled_back()),
+    );
+    metrics.counter(
+        "client_slot_gaps",
+        "Number of gaps between slot updates",
+        state.gaps.as_ref().map(|gaps| gaps.slot_gaps()),
+    );
+    metrics.counter(
+        "client_gap_missed_slots",
+        "Number of slots not received in all slot gaps",
+        state.gaps.as_ref().map(|gaps| gaps.missed_slots()),
+    );
+    metrics.counter(
+        "client_write_version_gaps",
+        "Number of account updates with a lower write version than the previous one of the pubkey",
+        state.gaps.as_ref().map(|gaps| gaps.write_version_gaps()),
+    );
+    metrics.gauge(
+        "client_hold_buffered",
+        "Number of updates held until their slot reaches HOLD_UNTIL",
+        state.hold.as_ref().map(|hold| hold.buffered()),
+    );
+    metrics.counter(
+        "client_hold_released",
+        "Number of held updates released when their slot reached HOLD_UNTIL",
+        state.hold.as_ref().map(|hold| hold.released()),
+    );
+    metrics.counter(
+        "client_hold_dropped",
+        "Number of held updates dropped because their slot never reached HOLD_UNTIL",
+        state.hold.as_ref().map(|hold| hold.dropped()),
+    );
+    metrics.counter(
+        "client_reassemble_blocks",
+        "Number of blocks built from their parts with every part of the block meta",
+        state
+            .assembler
+            .as_ref()
+            .map(|assembler| assembler.assembled()),
+    );
+    metrics.counter(
+        "client_reassemble_incomplete",
+        "Number of blocks built from their parts after REASSEMBLE_TIMEOUT_MS with parts missing",
+        state
+            .assembler
+            .as_ref()
+            .map(|assembler| assembler.incomplete()),
+    );
+    metrics.counter(
+        "client_reassemble_dropped",
+        "Number of block parts dropped without a block meta or after their block",
+        state
+            .assembler
+            .as_ref()
+            .map(|assembler| assembler.dropped()),
+    );
+    metrics.counter(
+        "client_coalesce_collapsed",
+        "Number of account writes replaced by a newer write within COALESCE_WINDOW_MS",
+        state
+            .coalescer
+            .as_ref()
+            .map(|coalescer| coalescer.collapsed()),
+    );
+}
+
+/// Accounts, owners and mints kept from the stream, and the RPC facade serving them
+fn indexes(metrics: &mut Metrics, state: &AdminState) {
+    metrics.gauge(
+        "client_discovered_accounts",
+        "Number of accounts of matching transactions in the discovered accounts filter",
+        state
+            .discovery
+            .as_ref()
+            .map(|discovery| discovery.accounts()),
+    );
+    metrics.counter(
+        "client_discover_added",
+        "Number of accounts added to the discovered accounts filter",
+        state.discovery.as_ref().map(|discovery| discovery.added()),
+    );
+    metrics.counter(
+        "client_discover_evicted",
+        "Number of discovered accounts removed after DISCOVER_TTL_SECS or beyond DISCOVER_MAX_ACCOUNTS",
+        state.discovery.as_ref().map(|discovery| discovery.evicted()),
+    );
+    metrics.gauge(
+        "client_state_sync_accounts",
+        "Number of accounts kept for state snapshots over all filters",
+        state
+            .state_sync
+            .as_ref()
+            .map(|state_sync| state_sync.accounts()),
+    );
+    let token_owners = state.token_owners.as_ref().map(|owners| owners.counts());
+    metrics.gauge(
+        "client_token_owners_accounts",
+        "Number of token accounts in the owner index",
+        token_owners.map(|(accounts, _)| accounts),
+    );
+    metrics.gauge(
+        "client_token_owners_wallets",
+        "Number of owners with token accounts in the owner index",
+        token_owners.map(|(_, wallets)| wallets),
+    );
+
+    if let Some(mints) = state.mints.as_ref() {
+        let mints = mints.snapshot();
+        let mut family = metrics.family(
+            "client_mint_supply",
+            Kind::Gauge,
+            "Supply of the watched mint, in base units",
+        );
+        for mint in mints.iter() {
+            if let Some(supply) = mint.supply {
+                family.sample(&[("mint", &mint.mint)], supply);
+            }
+        }
+
+        let mut family = metrics.family(
+            "client_mint_minted",
+            Kind::Counter,
+            "Total supply increases of the watched mint since start, in base units",
+        );
+        for mint in mints.iter() {
+            family.sample(&[("mint", &mint.mint)], mint.minted);
+        }
+
+        let mut family = metrics.family(
+            "client_mint_burned",
+            Kind::Counter,
+            "Total supply decreases of the watched mint since start, in base units",
+        );
+        for mint in mints.iter() {
+            family.sample(&[("mint", &mint.mint)], mint.burned);
+        }
+
+        let mut family = metrics.family(
+            "client_mint_holders",
+            Kind::Gauge,
+            "Owners with a positive balance of the watched mint, seen on the stream",
+        );
+        for mint in mints.iter() {
+            family.sample(&[("mint", &mint.mint)], mint.holders);
+        }
+    }
+
+    if let Some(facade) = state.facade.as_ref() {
+        let (accounts, answered, forwarded, errors) = facade.counts();
+        metrics.gauge(
+            "client_rpc_facade_accounts",
+            "Number of accounts cached by the RPC facade",
+            Some(accounts),
+        );
+        let mut family = metrics.family(
+            "client_rpc_facade_requests",
+            Kind::Counter,
+            "Number of JSON-RPC requests of the RPC facade by result",
+        );
+        family.sample(&[("result", "answered")], answered);
+        family.sample(&[("result", "forwarded")], forwarded);
+        family.sample(&[("result", "error")], errors);
+    }
+}
+
+/// Values of the unary calls polled alongside the stream
+fn poll(metrics: &mut Metrics, state: &AdminState) {
+    let poll = state.poll.snapshot();
+    metrics.gauge("client_poll_slot", "Slot from GetSlot", poll.slot);
+    metrics.gauge(
+        "client_poll_block_height",
+        "Block height from GetBlockHeight",
+        poll.block_height,
+    );
+    metrics.gauge(
+        "client_poll_blockhash_slot",
+        "Slot of the blockhash from GetLatestBlockhash",
+        poll.blockhash_slot,
+    );
+    metrics.gauge(
+        "client_poll_last_valid_block_height",
+        "Last valid block height of the blockhash from GetLatestBlockhash",
+        poll.last_valid_block_height,
+    );
+    metrics.counter(
+        "client_poll_errors",
+        "Number of failed polls",
+        Some(poll.errors),
+    );
+}
+
+/// Failures of the decode, enrich and sink stages
+fn pipeline(metrics: &mut Metrics, state: &AdminState) {
+    let stages = state.errors.report();
+    let mut family = metrics.family(
+        "client_pipeline_failed",
+        Kind::Counter,
+        "Number of updates a pipeline stage failed for after all retries",
+    );
+    for stage in stages.iter() {
+        family.sample(&[("stage", stage.stage)], stage.failed);
+    }
+
+    let mut family = metrics.family(
+        "client_pipeline_retries",
+        Kind::Counter,
+        "Number of times a pipeline stage was run again after a failure",
+    );
+    for stage in stages.iter() {
+        family.sample(&[("stage", stage.stage)], stage.retries);
+    }
+
+    let mut family = metrics.family(
+        "client_pipeline_quarantined",
+        Kind::Counter,
+        "Number of failed updates written to ERROR_QUARANTINE_PATH",
+    );
+    for stage in stages.iter() {
+        family.sample(&[("stage", stage.stage)], stage.quarantined);
+    }
+}
+
+/// Drops, errors, acknowledgements and dry runs of the sinks
+fn sinks(metrics: &mut Metrics, state: &AdminState) {
+    metrics.gauge(
+        "client_checkpoint_slot",
+        "Highest slot acknowledged by the quorum of sinks",
+        state.checkpoint.slot(),
+    );
+
+    let sinks = state.sinks.health();
+    if sinks.is_empty() {
+        return;
+    }
+    let mut family = metrics.family(
+        "client_sink_dropped",
+        Kind::Counter,
+        "Number of updates dropped because the sink queue was full",
+    );
+    for sink in sinks.iter() {
+        family.sample(&[("sink", sink.name)], sink.dropped);
+    }
+
+    let mut family = metrics.family(
+        "client_sink_errors",
+        Kind::Counter,
+        "Number of failed writes, requests or deliveries of the sink",
+    );
+    for sink in sinks.iter() {
+        family.sample(&[("sink", sink.name)], sink.errors);
+    }
+
+    let mut family = metrics.family(
+        "client_sink_acked_slot",
+        Kind::Gauge,
+        "Highest slot up to which the destination confirmed all updates",
+    );
+    for sink in sinks.iter() {
+        if let Some(slot) = sink.acked_slot {
+            family.sample(&[("sink", sink.name)], slot);
+        }
+    }
+
+    let mut family = metrics.family(
+        "client_sink_lag_slots",
+        Kind::Gauge,
+        "Highest received slot minus the acknowledged slot of the sink",
+    );
+    for sink in sinks.iter() {
+        if let Some(lag) = state.sink_lag(sink) {
+            family.sample(&[("sink", sink.name)], lag);
+        }
+    }
+
+    let dry_runs = sinks
+        .iter()
+        .filter_map(|sink| Some((sink.name, sink.dry_run.as_ref()?)))
+        .collect::<Vec<_>>();
+    if dry_runs.is_empty() {
+        return;
+    }
+    let mut family = metrics.family(
+        "client_sink_dry_run_payloads",
+        Kind::Counter,
+        "Number of payloads built but not sent by a sink in dry run",
+    );
+    for (sink, stats) in dry_runs.iter() {
+        family.sample(&[("sink", sink)], stats.payloads);
+    }
+
+    let mut family = metrics.family(
+        "client_sink_dry_run_bytes",
+        Kind::Counter,
+        "Size of the payloads built but not sent by a sink in dry run",
+    );
+    for (sink, stats) in dry_runs.iter() {
+        family.sample(&[("sink", sink)], stats.bytes);
+    }
+
+    let mut family = metrics.family(
+        "client_sink_dry_run_invalid",
+        Kind::Counter,
+        "Number of payloads of a sink in dry run the destination would reject",
+    );
+    for (sink, stats) in dry_runs.iter() {
+        family.sample(&[("sink", sink)], stats.invalid);
+    }
+}
+
+/// Endpoints of MultiSubscribe, connections of the pool and WebSocket clients of Serve
+fn connections(metrics: &mut Metrics, state: &AdminState) {
+    if let Some(multi) = state.multi.as_ref() {
+        let report = multi.report();
+        let mut family = metrics.family(
+            "client_multi_received",
+            Kind::Counter,
+            "Number of updates received per endpoint by MultiSubscribe",
+        );
+        for endpoint in report.iter() {
+            family.sample(&[("endpoint", &endpoint.endpoint)], endpoint.received);
+        }
+
+        let mut family = metrics.family(
+            "client_multi_wins",
+            Kind::Counter,
+            "Number of updates received first per endpoint by MultiSubscribe",
+        );
+        for endpoint in report.iter() {
+            family.sample(&[("endpoint", &endpoint.endpoint)], endpoint.wins);
+        }
+
+        let mut family = metrics.family(
+            "client_multi_suppressed",
+            Kind::Counter,
+            "Number of first copies suppressed per endpoint by MULTI_PRECEDENCE",
+        );
+        for endpoint in report.iter() {
+            family.sample(&[("endpoint", &endpoint.endpoint)], endpoint.suppressed);
+        }
+    }
+
+    if let Some(pool) = state.pool.as_ref() {
+        let report = pool.report();
+        let connections = report
+            .iter()
+            .map(|connection| connection.connection.to_string())
+            .collect::<Vec<_>>();
+        let mut family = metrics.family(
+            "client_pool_connection_up",
+            Kind::Gauge,
+            "Whether the stream of the pool connection is open",
+        );
+        for (connection, name) in report.iter().zip(connections.iter()) {
+            family.sample(&[("connection", name)], u8::from(connection.up));
+        }
+
+        let mut family = metrics.family(
+            "client_pool_filters",
+            Kind::Gauge,
+            "Number of filters served by the pool connection",
+        );
+        for (connection, name) in report.iter().zip(connections.iter()) {
+            family.sample(&[("connection", name)], connection.filters.len());
+        }
+
+        let mut family = metrics.family(
+            "client_pool_received",
+            Kind::Counter,
+            "Number of messages received by the pool connection",
+        );
+        for (connection, name) in report.iter().zip(connections.iter()) {
+            family.sample(&[("connection", name)], connection.received);
+        }
+
+        let mut family = metrics.family(
+            "client_pool_failures",
+            Kind::Counter,
+            "Number of failed connects and streams of the pool connection",
+        );
+        for (connection, name) in report.iter().zip(connections.iter()) {
+            family.sample(&[("connection", name)], connection.failures);
+        }
+    }
+
+    metrics.gauge(
+        "client_serve_clients",
+        "Number of connected WebSocket clients of Serve",
+        state.serve.as_ref().map(|serve| serve.clients()),
+    );
+}
+
+/// Transaction statuses joined with full transactions
+fn correlate(metrics: &mut Metrics, state: &AdminState) {
+    let Some(correlator) = state.correlator.as_ref() else {
+        return;
+    };
+    let report = correlator.report();
+    metrics.counter(
+        "client_correlate_matched",
+        "Number of transaction statuses joined with their full transaction",
+        Some(report.matched),
+    );
+
+    let mut family = metrics.family(
+        "client_correlate_evicted",
+        Kind::Counter,
+        "Number of signatures evicted before the other update arrived, by the update which did",
+    );
+    family.sample(&[("kind", "transaction")], report.evicted_transactions);
+    family.sample(&[("kind", "transaction_status")], report.evicted_statuses);
+
+    let mut family = metrics.family(
+        "client_correlate_gap_ms",
+        Kind::Summary,
+        "Status receive time minus transaction receive time, over recent pairs",
+    );
+    for (quantile, gap) in [
+        ("0.5", report.gap_p50_ms),
+        ("0.9", report.gap_p90_ms),
+        ("0.99", report.gap_p99_ms),
+    ] {
+        if let Some(gap) = gap {
+            family.sample(&[("quantile", quantile)], gap);
+        }
+    }
+}
+
+/// Processed slots saved to `PROGRESS_PATH`
+fn progress(metrics: &mut Metrics, state: &AdminState) {
+    let Some(progress) = state.progress.as_ref() else {
+        return;
+    };
+    let report = progress.report();
+    let mut family = metrics.family(
+        "client_progress_slot",
+        Kind::Gauge,
+        "Highest processed slot by update type, saved to PROGRESS_PATH",
+    );
+    for (kind, slot) in report.slots.iter() {
+        family.sample(&[("kind", kind)], slot);
+    }
+
+    let mut family = metrics.family(
+        "client_progress_gap_slots",
+        Kind::Gauge,
+        "Slots between the previous run and the first update of this one, by update type",
+    );
+    for (kind, missed) in report.gaps.iter() {
+        family.sample(&[("kind", kind)], missed);
+    }
+}
+
+/// Latency objectives of delivery to sinks
+fn slos(metrics: &mut Metrics, state: &AdminState) {
+    let Some(slos) = state.slos.as_ref() else {
+        return;
+    };
+    let slos = slos.status();
+    let mut family = metrics.family(
+        "client_slo_updates",
+        Kind::Counter,
+        "Number of updates counted by the latency objective",
+    );
+    for slo in slos.iter() {
+        family.sample(&[("slo", &slo.slo)], slo.updates);
+    }
+
+    let mut family = metrics.family(
+        "client_slo_late",
+        Kind::Counter,
+        "Number of updates passed to sinks later than the objective threshold",
+    );
+    for slo in slos.iter() {
+        family.sample(&[("slo", &slo.slo)], slo.late);
+    }
+
+    let mut family = metrics.family(
+        "client_slo_compliance_percent",
+        Kind::Gauge,
+        "Percentage of updates within the threshold over the window",
+    );
+    for slo in slos.iter() {
+        for (window, status) in slo.windows.iter() {
+            if let Some(percent) = status.compliance_percent {
+                family.sample(&[("slo", &slo.slo), ("window", window)], percent);
+            }
+        }
+    }
+
+    let mut family = metrics.family(
+        "client_slo_burn_rate",
+        Kind::Gauge,
+        "Late updates over the window divided by the share the objective allows",
+    );
+    for slo in slos.iter() {
+        for (window, status) in slo.windows.iter() {
+            if let Some(rate) = status.burn_rate {
+                family.sample(&[("slo", &slo.slo), ("window", window)], rate);
+            }
+        }
+    }
+
+    let mut family = metrics.family(
+        "client_slo_met",
+        Kind::Gauge,
+        "1 if the objective is met over the last hour",
+    );
+    for slo in slos.iter() {
+        family.sample(&[("slo", &slo.slo)], u8::from(slo.met));
+    }
+}
+
+/// Owned labels as taken by `Family::sample`
+fn borrow(labels: &[(String, String)]) -> Vec<(&str, &str)> {
+    labels
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("swaps"), "swaps");
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn counters_have_total_suffix() {
+        let mut metrics = Metrics::default();
+        metrics.counter("client_x", "Number of x", Some(3));
+        metrics.gauge("client_y", "Current y", Some(4));
+        metrics.gauge("client_z", "Unset z", None::<u64>);
+        let mut family = metrics.family("client_w", Kind::Counter, "Number of w");
+        family.sample(&[("sink", "a\"b"), ("filter", "c")], 5);
+        assert_eq!(
+            metrics.0,
+            "# HELP client_x_total Number of x\n\
+            # TYPE client_x_total counter\n\
+            client_x_total 3\n\
+            # HELP client_y Current y\n\
+            # TYPE client_y gauge\n\
+            client_y 4\n\
+            # HELP client_w_total Number of w\n\
+            # TYPE client_w_total counter\n\
+            client_w_total{sink=\"a\\\"b\",filter=\"c\"} 5\n"
+        );
+    }
+}
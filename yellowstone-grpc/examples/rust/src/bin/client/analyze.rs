@@ -0,0 +1,275 @@
+//! Distributions of a subscription, `ACTION=Analyze`.
+//!
+//! The stream is subscribed with the usual filter variables for `ANALYZE_DURATION_SECS`, like
+//! `ACTION=Bench` its updates are discarded, but they are counted by shape instead of size:
+//! a histogram of account data sizes, the `ANALYZE_TOP` most updated pubkeys and the programs
+//! invoked by the most transactions. This tells which filters would cut most of the volume
+//! and how large account caches and tables get before writing a single filter.
+
+use {
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        collections::{HashMap, HashSet},
+        env,
+        time::Duration,
+    },
+    tokio::time::Instant,
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateAccountInfo,
+        SubscribeUpdateTransactionInfo,
+    },
+};
+
+const DEFAULT_DURATION_SECS: u64 = 60;
+const DEFAULT_TOP: usize = 20;
+/// Upper bounds of the data size buckets, accounts are at most 10 MiB
+const SIZE_BOUNDS: &[u64] = &[
+    0, 128, 256, 512, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576, 10_485_760,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnalyzeConfig {
+    pub duration: Duration,
+    top: usize,
+}
+
+impl AnalyzeConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let duration = match env::var("ANALYZE_DURATION_SECS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid ANALYZE_DURATION_SECS"))?,
+            Err(_) => DEFAULT_DURATION_SECS,
+        };
+        let top = match env::var("ANALYZE_TOP") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|top| *top > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid ANALYZE_TOP"))?,
+            Err(_) => DEFAULT_TOP,
+        };
+        Ok(Self {
+            duration: Duration::from_secs(duration),
+            top,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct PubkeyCounts {
+    updates: u64,
+    /// Owner of the latest update
+    owner: Vec<u8>,
+    data_bytes: u64,
+}
+
+/// Bucket of the data size histogram
+#[derive(Debug, Serialize)]
+struct SizeBucket {
+    /// Data sizes up to this many bytes, above the previous bucket
+    le: u64,
+    updates: u64,
+}
+
+#[derive(Debug)]
+pub struct Analyzer {
+    top: usize,
+    started: Instant,
+    messages: u64,
+    account_updates: u64,
+    account_bytes: u64,
+    largest_account: u64,
+    /// Updates per bucket of `SIZE_BOUNDS`
+    sizes: Vec<u64>,
+    pubkeys: HashMap<Vec<u8>, PubkeyCounts>,
+    transactions: u64,
+    failed: u64,
+    votes: u64,
+    /// Transactions which invoked the program, directly or by CPI
+    programs: HashMap<Vec<u8>, u64>,
+}
+
+impl Analyzer {
+    pub fn start(config: &AnalyzeConfig) -> Self {
+        Self {
+            top: config.top,
+            started: Instant::now(),
+            messages: 0,
+            account_updates: 0,
+            account_bytes: 0,
+            largest_account: 0,
+            sizes: vec![0; SIZE_BOUNDS.len()],
+            pubkeys: HashMap::new(),
+            transactions: 0,
+            failed: 0,
+            votes: 0,
+            programs: HashMap::new(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Accounts and transactions of blocks are counted like separate updates
+    pub fn observe(&mut self, msg: &SubscribeUpdate) {
+        self.messages += 1;
+        match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Account(update)) => {
+                if let Some(account) = update.account.as_ref() {
+                    self.observe_account(account);
+                }
+            }
+            Some(UpdateOneof::Transaction(update)) => {
+                if let Some(tx) = update.transaction.as_ref() {
+                    self.observe_transaction(tx);
+                }
+            }
+            Some(UpdateOneof::Block(block)) => {
+                for account in block.accounts.iter() {
+                    self.observe_account(account);
+                }
+                for tx in block.transactions.iter() {
+                    self.observe_transaction(tx);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn observe_account(&mut self, account: &SubscribeUpdateAccountInfo) {
+        let size = account.data.len() as u64;
+        self.account_updates += 1;
+        self.account_bytes += size;
+        self.largest_account = self.largest_account.max(size);
+        let bucket = SIZE_BOUNDS
+            .iter()
+            .position(|bound| size <= *bound)
+            .unwrap_or(SIZE_BOUNDS.len() - 1);
+        self.sizes[bucket] += 1;
+        let counts = self.pubkeys.entry(account.pubkey.clone()).or_default();
+        counts.updates += 1;
+        counts.data_bytes += size;
+        counts.owner.clone_from(&account.owner);
+    }
+
+    fn observe_transaction(&mut self, tx: &SubscribeUpdateTransactionInfo) {
+        self.transactions += 1;
+        if tx.is_vote {
+            self.votes += 1;
+        }
+        let meta = tx.meta.as_ref();
+        if meta.is_some_and(|meta| meta.err.is_some()) {
+            self.failed += 1;
+        }
+        let Some(message) = tx.transaction.as_ref().and_then(|tx| tx.message.as_ref()) else {
+            return;
+        };
+        // Indexes of inner instructions count addresses loaded from lookup tables too
+        let keys = message
+            .account_keys
+            .iter()
+            .chain(meta.into_iter().flat_map(|meta| {
+                meta.loaded_writable_addresses
+                    .iter()
+                    .chain(meta.loaded_readonly_addresses.iter())
+            }))
+            .collect::<Vec<_>>();
+        let inner = meta
+            .into_iter()
+            .flat_map(|meta| meta.inner_instructions.iter())
+            .flat_map(|inner| inner.instructions.iter())
+            .map(|instruction| instruction.program_id_index);
+        let programs = message
+            .instructions
+            .iter()
+            .map(|instruction| instruction.program_id_index)
+            .chain(inner)
+            .filter_map(|index| keys.get(index as usize))
+            .collect::<HashSet<_>>();
+        for program in programs {
+            *self.programs.entry(program.to_vec()).or_default() += 1;
+        }
+    }
+
+    /// One line for progress logs
+    pub fn progress(&self) -> String {
+        format!(
+            "{} updates, {} account updates of {} pubkeys, {} transactions of {} programs",
+            self.messages,
+            self.account_updates,
+            self.pubkeys.len(),
+            self.transactions,
+            self.programs.len()
+        )
+    }
+
+    /// Distributions so far, `completed` once the full duration ran
+    pub fn report(&self, endpoint: &str, completed: bool) -> Value {
+        let share = |count: u64, total: u64| (total > 0).then(|| count as f64 / total as f64);
+        let histogram = SIZE_BOUNDS
+            .iter()
+            .zip(self.sizes.iter())
+            .map(|(le, updates)| SizeBucket {
+                le: *le,
+                updates: *updates,
+            })
+            .collect::<Vec<_>>();
+
+        let mut pubkeys = self.pubkeys.iter().collect::<Vec<_>>();
+        pubkeys.sort_by(|(a_key, a), (b_key, b)| b.updates.cmp(&a.updates).then(a_key.cmp(b_key)));
+        let top_pubkeys = pubkeys
+            .into_iter()
+            .take(self.top)
+            .map(|(pubkey, counts)| {
+                json!({
+                    "pubkey": bs58::encode(pubkey).into_string(),
+                    "owner": bs58::encode(&counts.owner).into_string(),
+                    "updates": counts.updates,
+                    "share": share(counts.updates, self.account_updates),
+                    "avg_data_bytes": counts.data_bytes / counts.updates,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut programs = self.programs.iter().collect::<Vec<_>>();
+        programs.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then(a_key.cmp(b_key)));
+        let top_programs = programs
+            .into_iter()
+            .take(self.top)
+            .map(|(program, transactions)| {
+                json!({
+                    "program": bs58::encode(program).into_string(),
+                    "transactions": transactions,
+                    "share": share(*transactions, self.transactions),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "endpoint": endpoint,
+            "completed": completed,
+            "elapsed_secs": self.elapsed().as_secs_f64(),
+            "messages": self.messages,
+            "accounts": {
+                "updates": self.account_updates,
+                "pubkeys": self.pubkeys.len(),
+                "data_bytes": self.account_bytes,
+                "largest_data_bytes": self.largest_account,
+                "data_size_histogram": histogram,
+                "top_pubkeys": top_pubkeys,
+            },
+            "transactions": {
+                "count": self.transactions,
+                "failed": self.failed,
+                "votes": self.votes,
+                "programs": self.programs.len(),
+                "top_programs": top_programs,
+            },
+        })
+    }
+}
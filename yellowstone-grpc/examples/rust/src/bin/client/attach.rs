@@ -0,0 +1,310 @@
+//! Read-only terminal view of a running instance, `ACTION=Status`.
+//!
+//! `GET /status` of the admin API at `STATUS_ATTACH` is fetched every `REFRESH_INTERVAL`,
+//! rates are computed from the difference of two fetches. Nothing is changed on the
+//! attached instance, quitting the view does not stop it.
+
+use {
+    crate::{logging, reconnects::StreamEnd},
+    ratatui::{
+        crossterm::event::{self, Event, KeyCode, KeyEventKind},
+        layout::{Constraint, Layout},
+        style::{Modifier, Style},
+        widgets::{Block, Paragraph, Row, Table},
+        Frame,
+    },
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        time::{Duration, Instant},
+    },
+    tokio::{sync::watch, time::interval},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+const RECENT_RECONNECTS: usize = 10;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct QueueStatus {
+    depth: usize,
+    capacity: usize,
+    dropped: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SinkStatus {
+    name: String,
+    dropped: u64,
+    errors: u64,
+    lag_slots: Option<u64>,
+}
+
+/// Response of `GET /status`, fields missing in older versions are left empty
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Status {
+    endpoint: String,
+    connected_since: Option<String>,
+    ends: BTreeMap<String, u64>,
+    history: Vec<StreamEnd>,
+    messages: u64,
+    last_slot: Option<u64>,
+    slot_lag: Option<u64>,
+    filters: BTreeMap<String, u64>,
+    queue: QueueStatus,
+    sinks: Vec<SinkStatus>,
+}
+
+/// Result of the last fetch
+#[derive(Debug, Default)]
+struct Fetched {
+    status: Option<(Instant, Status)>,
+    error: Option<String>,
+}
+
+/// `http://{addr}/status`, `addr` may also be a URL with scheme
+fn status_url(addr: &str) -> String {
+    let addr = addr.trim_end_matches('/');
+    if addr.starts_with("http://") || addr.starts_with("https://") {
+        format!("{addr}/status")
+    } else {
+        format!("http://{addr}/status")
+    }
+}
+
+async fn fetch(http: &reqwest::Client, url: &str) -> anyhow::Result<Status> {
+    Ok(http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+async fn fetch_loop(http: reqwest::Client, url: String, tx: watch::Sender<Fetched>) {
+    let mut ticker = interval(REFRESH_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let result = fetch(&http, &url).await;
+        tx.send_modify(|fetched| match result {
+            Ok(status) => {
+                fetched.status = Some((Instant::now(), status));
+                fetched.error = None;
+            }
+            // Keep showing the last status with the error
+            Err(error) => fetched.error = Some(error.to_string()),
+        });
+    }
+}
+
+/// Terminal state kept between frames
+struct View {
+    url: String,
+    rx: watch::Receiver<Fetched>,
+    /// Messages in total and per filter of the previous fetch
+    previous: Option<(Instant, u64, BTreeMap<String, u64>)>,
+    /// Messages per second in total and per filter
+    rates: (f64, BTreeMap<String, f64>),
+}
+
+impl View {
+    fn refresh(&mut self) {
+        if !self.rx.has_changed().unwrap_or(false) {
+            return;
+        }
+        let fetched = self.rx.borrow_and_update();
+        let Some((time, status)) = fetched.status.as_ref() else {
+            return;
+        };
+        if let Some((previous_time, messages, filters)) = self.previous.as_ref() {
+            if time > previous_time {
+                let elapsed = time.duration_since(*previous_time).as_secs_f64();
+                let rate =
+                    |current: u64, previous: u64| current.saturating_sub(previous) as f64 / elapsed;
+                self.rates = (
+                    rate(status.messages, *messages),
+                    status
+                        .filters
+                        .iter()
+                        .map(|(name, count)| {
+                            let previous = filters.get(name).copied().unwrap_or(0);
+                            (name.clone(), rate(*count, previous))
+                        })
+                        .collect(),
+                );
+            }
+        }
+        self.previous = Some((*time, status.messages, status.filters.clone()));
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let fetched = self.rx.borrow();
+        let [header, top, bottom, footer] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Percentage(40),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let optional =
+            |value: Option<u64>| value.map_or_else(|| "none".to_owned(), |v| v.to_string());
+        let mut header_text = match fetched.status.as_ref() {
+            Some((_, status)) => format!(
+                "{} {} | {} messages, {:.1} msg/s | last slot {} | lag {} | queue {}/{}, {} dropped",
+                status.endpoint,
+                status.connected_since.as_deref().map_or_else(
+                    || "disconnected".to_owned(),
+                    |since| format!("since {since}")
+                ),
+                status.messages,
+                self.rates.0,
+                optional(status.last_slot),
+                optional(status.slot_lag),
+                status.queue.depth,
+                status.queue.capacity,
+                status.queue.dropped,
+            ),
+            None => format!("waiting for {}", self.url),
+        };
+        if let Some(error) = fetched.error.as_ref() {
+            header_text.push_str(&format!("\nfailed to fetch {}: {error}", self.url));
+        }
+        frame.render_widget(Paragraph::new(header_text), header);
+
+        let status = fetched.status.as_ref().map(|(_, status)| status);
+        let [filters_area, sinks_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Fill(1)]).areas(top);
+        let filters = Table::new(
+            status
+                .map(|status| status.filters.iter())
+                .into_iter()
+                .flatten()
+                .map(|(name, count)| {
+                    let rate = self.rates.1.get(name).copied().unwrap_or(0.0);
+                    Row::new(vec![name.clone(), format!("{rate:.1}"), count.to_string()])
+                }),
+            [
+                Constraint::Fill(1),
+                Constraint::Length(10),
+                Constraint::Length(12),
+            ],
+        )
+        .header(Row::new(vec!["filter", "msg/s", "total"]).style(bold()))
+        .block(Block::bordered().title(" Filters "));
+        frame.render_widget(filters, filters_area);
+
+        let sinks = Table::new(
+            status
+                .map(|status| status.sinks.iter())
+                .into_iter()
+                .flatten()
+                .map(|sink| {
+                    Row::new(vec![
+                        sink.name.clone(),
+                        sink.dropped.to_string(),
+                        sink.errors.to_string(),
+                        sink.lag_slots
+                            .map_or_else(|| "-".to_owned(), |lag| lag.to_string()),
+                    ])
+                }),
+            [
+                Constraint::Fill(1),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(Row::new(vec!["sink", "dropped", "errors", "lag"]).style(bold()))
+        .block(Block::bordered().title(" Sinks "));
+        frame.render_widget(sinks, sinks_area);
+
+        let ends = status.map_or_else(String::new, |status| {
+            status
+                .ends
+                .iter()
+                .map(|(reason, count)| format!("{reason} {count}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+        let reconnects = Table::new(
+            status
+                .map(|status| status.history.iter().rev().take(RECENT_RECONNECTS))
+                .into_iter()
+                .flatten()
+                .map(|end| {
+                    Row::new(vec![
+                        end.time.get(11..19).unwrap_or(&end.time).to_owned(),
+                        end.endpoint.clone(),
+                        end.reason.name().to_owned(),
+                        end.detail.clone(),
+                    ])
+                }),
+            [
+                Constraint::Length(8),
+                Constraint::Length(11),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(vec!["time", "endpoint", "reason", "detail"]).style(bold()))
+        .block(Block::bordered().title(format!(" Reconnects {ends} ")));
+        frame.render_widget(reconnects, bottom);
+
+        frame.render_widget(Paragraph::new("q quit | read-only"), footer);
+    }
+}
+
+fn bold() -> Style {
+    Style::default().add_modifier(Modifier::BOLD)
+}
+
+/// Draw the view until `q` is pressed, blocks the thread
+fn run_view(mut view: View) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = loop {
+        view.refresh();
+        if let Err(error) = terminal.draw(|frame| view.draw(frame)) {
+            break Err(error.into());
+        }
+        match event::poll(Duration::from_millis(250))
+            .and_then(|ready| ready.then(event::read).transpose())
+        {
+            Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    break Ok(());
+                }
+            }
+            Ok(_) => {}
+            Err(error) => break Err(error.into()),
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+/// Show the status of the instance with the admin API at `addr` until `q` is pressed
+pub async fn run(addr: &str) -> anyhow::Result<()> {
+    let url = status_url(addr);
+    let http = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let (tx, rx) = watch::channel(Fetched::default());
+    let fetcher = tokio::spawn(fetch_loop(http, url.clone(), tx));
+
+    // Log lines would break the terminal view
+    logging::set_filters("off")?;
+    let view = View {
+        url,
+        rx,
+        previous: None,
+        rates: Default::default(),
+    };
+    let result = tokio::task::spawn_blocking(move || run_view(view)).await;
+    fetcher.abort();
+    result?
+}
@@ -0,0 +1,234 @@
+//! Who gained or lost what in a transaction, `BALANCE_CHANGES`.
+//!
+//! SOL changes come from the pre and post balances of every account of the transaction,
+//! token changes from the pre and post token balances, by token account. A token account
+//! created or closed by the transaction has a zero balance on the missing side. Token
+//! changes are shown with the owner of the token account, so a wallet can be found by the
+//! same address for SOL and tokens.
+
+use {
+    solana_transaction_status::{TransactionTokenBalance, VersionedTransactionWithStatusMeta},
+    std::{collections::BTreeMap, fmt},
+};
+
+const SOL_DECIMALS: u8 = 9;
+
+pub struct BalanceChange {
+    /// Account for SOL, owner of the token account for tokens
+    owner: String,
+    /// `None` for SOL
+    mint: Option<String>,
+    /// In base units
+    delta: i128,
+    decimals: u8,
+}
+
+impl fmt::Debug for BalanceChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.delta < 0 { '-' } else { '+' };
+        // Raw units for decimals which don't fit, no real token has them
+        let scale = 10u128.checked_pow(self.decimals as u32).unwrap_or(1);
+        let (units, fraction) = (
+            self.delta.unsigned_abs() / scale,
+            self.delta.unsigned_abs() % scale,
+        );
+        write!(f, "{} {sign}{units}", self.owner)?;
+        if fraction > 0 {
+            let fraction = format!("{fraction:0width$}", width = self.decimals as usize);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        write!(f, " {}", self.mint.as_deref().unwrap_or("SOL"))
+    }
+}
+
+/// SOL changes in account order, then token changes in account order
+pub fn balance_changes(tx: &VersionedTransactionWithStatusMeta) -> Vec<BalanceChange> {
+    let account_keys = tx.account_keys();
+    let meta = &tx.meta;
+
+    let mut changes = meta
+        .pre_balances
+        .iter()
+        .zip(meta.post_balances.iter())
+        .enumerate()
+        .filter(|(_, (pre, post))| pre != post)
+        .filter_map(|(index, (pre, post))| {
+            Some(BalanceChange {
+                owner: account_keys.get(index)?.to_string(),
+                mint: None,
+                delta: *post as i128 - *pre as i128,
+                decimals: SOL_DECIMALS,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Pre and post balances by token account index
+    let mut tokens = BTreeMap::<
+        u8,
+        (
+            Option<&TransactionTokenBalance>,
+            Option<&TransactionTokenBalance>,
+        ),
+    >::new();
+    for balance in meta.pre_token_balances.iter().flatten() {
+        tokens.entry(balance.account_index).or_default().0 = Some(balance);
+    }
+    for balance in meta.post_token_balances.iter().flatten() {
+        tokens.entry(balance.account_index).or_default().1 = Some(balance);
+    }
+    let amount = |balance: Option<&TransactionTokenBalance>| {
+        balance.map_or(0, |balance| {
+            balance
+                .ui_token_amount
+                .amount
+                .parse::<i128>()
+                .unwrap_or_default()
+        })
+    };
+    for (index, (pre, post)) in tokens {
+        let Some(balance) = post.or(pre) else {
+            continue;
+        };
+        let delta = amount(post) - amount(pre);
+        if delta == 0 {
+            continue;
+        }
+        let owner = if balance.owner.is_empty() {
+            // Owners are not recorded by old validators, fall back to the token account
+            account_keys
+                .get(index as usize)
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        } else {
+            balance.owner.clone()
+        };
+        changes.push(BalanceChange {
+            owner,
+            mint: Some(balance.mint.clone()),
+            delta,
+            decimals: balance.ui_token_amount.decimals,
+        });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::pubkey::Pubkey,
+        solana_transaction_status::TransactionWithStatusMeta,
+        yellowstone_grpc_proto::{
+            convert_from::create_tx_with_meta,
+            prelude::{
+                Message, MessageHeader, SubscribeUpdateTransactionInfo, TokenBalance, Transaction,
+                TransactionStatusMeta, UiTokenAmount,
+            },
+        },
+    };
+
+    fn key(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    fn token(
+        account_index: u32,
+        mint: &str,
+        owner: &str,
+        amount: &str,
+        decimals: u32,
+    ) -> TokenBalance {
+        TokenBalance {
+            account_index,
+            mint: mint.to_owned(),
+            ui_token_amount: Some(UiTokenAmount {
+                decimals,
+                amount: amount.to_owned(),
+                ..Default::default()
+            }),
+            owner: owner.to_owned(),
+            program_id: String::new(),
+        }
+    }
+
+    /// Transaction of the accounts `key(1)` to `key(4)`
+    fn tx(meta: TransactionStatusMeta) -> VersionedTransactionWithStatusMeta {
+        let info = SubscribeUpdateTransactionInfo {
+            signature: vec![7; 64],
+            transaction: Some(Transaction {
+                signatures: vec![vec![7; 64]],
+                message: Some(Message {
+                    header: Some(MessageHeader {
+                        num_required_signatures: 1,
+                        ..Default::default()
+                    }),
+                    account_keys: (1..=4).map(|byte| vec![byte; 32]).collect(),
+                    recent_blockhash: vec![0; 32],
+                    ..Default::default()
+                }),
+            }),
+            meta: Some(TransactionStatusMeta {
+                return_data_none: true,
+                ..meta
+            }),
+            ..Default::default()
+        };
+        match create_tx_with_meta(info).unwrap() {
+            TransactionWithStatusMeta::Complete(tx) => tx,
+            TransactionWithStatusMeta::MissingMetadata(_) => unreachable!(),
+        }
+    }
+
+    fn formatted(meta: TransactionStatusMeta) -> Vec<String> {
+        balance_changes(&tx(meta))
+            .iter()
+            .map(|change| format!("{change:?}"))
+            .collect()
+    }
+
+    #[test]
+    fn sol_then_tokens_in_account_order() {
+        let changes = formatted(TransactionStatusMeta {
+            pre_balances: vec![10_000_000_000, 5, 7, 0],
+            post_balances: vec![8_500_000_000, 5, 8, 0],
+            pre_token_balances: vec![
+                token(0, "mintA", "same", "100", 0),
+                token(1, "mintA", "wallet", "1000000", 6),
+                // Closed
+                token(2, "mintA", "other", "42", 2),
+            ],
+            post_token_balances: vec![
+                // Created, without owner
+                token(3, "mintB", "", "5", 0),
+                token(1, "mintA", "wallet", "250000", 6),
+                token(0, "mintA", "same", "100", 0),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(
+            changes,
+            [
+                format!("{} -1.5 SOL", key(1)),
+                format!("{} +0.000000001 SOL", key(3)),
+                "wallet -0.75 mintA".to_owned(),
+                "other -0.42 mintA".to_owned(),
+                format!("{} +5 mintB", key(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn whole_units_and_unknown_decimals() {
+        let changes = formatted(TransactionStatusMeta {
+            pre_balances: vec![3_000_000_000],
+            post_balances: vec![1_000_000_000],
+            pre_token_balances: vec![token(1, "mintA", "wallet", "0", 200)],
+            post_token_balances: vec![token(1, "mintA", "wallet", "123", 200)],
+            ..Default::default()
+        });
+        assert_eq!(
+            changes,
+            [format!("{} -2 SOL", key(1)), "wallet +123 mintA".to_owned()]
+        );
+    }
+}
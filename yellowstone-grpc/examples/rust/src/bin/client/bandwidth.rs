@@ -0,0 +1,171 @@
+//! Received bytes per filter and projected monthly bandwidth and cost.
+//!
+//! gRPC decompresses messages before the client sees them, so only the uncompressed size is
+//! exact. With compression enabled the compressed size is estimated by compressing every
+//! `BANDWIDTH_SAMPLE_EVERY`-th message with the same algorithm.
+
+use {
+    flate2::{write::GzEncoder, Compression},
+    serde_json::{json, Value},
+    std::{
+        collections::BTreeMap,
+        env,
+        io::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Instant,
+    },
+    yellowstone_grpc_proto::{
+        prelude::SubscribeUpdate, prost::Message, tonic::codec::CompressionEncoding,
+    },
+};
+
+const SECONDS_PER_MONTH: f64 = 30.0 * 24.0 * 3600.0;
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    messages: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    uncompressed: u64,
+    compressed: u64,
+}
+
+#[derive(Debug)]
+pub struct BandwidthMeter {
+    started: Instant,
+    compression: Option<CompressionEncoding>,
+    price_per_gb: Option<f64>,
+    sample_every: u64,
+    received: AtomicU64,
+    total: Mutex<Counters>,
+    /// Message matched by several filters is counted for every filter
+    filters: Mutex<BTreeMap<String, Counters>>,
+    samples: Mutex<Samples>,
+}
+
+impl BandwidthMeter {
+    pub fn from_env(compression: Option<CompressionEncoding>) -> anyhow::Result<Self> {
+        Ok(Self {
+            started: Instant::now(),
+            compression,
+            price_per_gb: env::var("BANDWIDTH_PRICE_PER_GB")
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid BANDWIDTH_PRICE_PER_GB"))?,
+            sample_every: env::var("BANDWIDTH_SAMPLE_EVERY")
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid BANDWIDTH_SAMPLE_EVERY"))?
+                .unwrap_or(100u64)
+                .max(1),
+            received: AtomicU64::new(0),
+            total: Mutex::default(),
+            filters: Mutex::default(),
+            samples: Mutex::default(),
+        })
+    }
+
+    pub fn observe(&self, msg: &SubscribeUpdate) {
+        let bytes = msg.encoded_len() as u64;
+        let index = self.received.fetch_add(1, Ordering::Relaxed);
+
+        {
+            let mut total = self.total.lock().expect("poisoned");
+            total.messages += 1;
+            total.bytes += bytes;
+        }
+        if !msg.filters.is_empty() {
+            let mut filters = self.filters.lock().expect("poisoned");
+            for filter in msg.filters.iter() {
+                let counters = filters.entry(filter.clone()).or_default();
+                counters.messages += 1;
+                counters.bytes += bytes;
+            }
+        }
+
+        if let Some(encoding) = self.compression {
+            if index.is_multiple_of(self.sample_every) {
+                let compressed = compressed_len(encoding, &msg.encode_to_vec());
+                let mut samples = self.samples.lock().expect("poisoned");
+                samples.uncompressed += bytes;
+                samples.compressed += compressed;
+            }
+        }
+    }
+
+    /// Compressed / uncompressed, `1.0` without compression or samples
+    fn ratio(&self) -> f64 {
+        let samples = self.samples.lock().expect("poisoned");
+        if samples.uncompressed == 0 {
+            1.0
+        } else {
+            samples.compressed as f64 / samples.uncompressed as f64
+        }
+    }
+
+    fn group_report(&self, counters: Counters, ratio: f64, elapsed: f64) -> Value {
+        let wire_bytes = counters.bytes as f64 * ratio;
+        let monthly_gb = wire_bytes / elapsed * SECONDS_PER_MONTH / BYTES_PER_GB;
+        json!({
+            "messages": counters.messages,
+            "uncompressed_bytes": counters.bytes,
+            "compressed_bytes": self.compression.map(|_| wire_bytes as u64),
+            "monthly_gb": monthly_gb,
+            "monthly_cost": self.price_per_gb.map(|price| monthly_gb * price),
+        })
+    }
+
+    /// Totals and per filter counters with monthly projection based on the rate since start
+    pub fn report(&self) -> Value {
+        let elapsed = self.started.elapsed().as_secs_f64().max(1.0);
+        let ratio = self.ratio();
+        let total = *self.total.lock().expect("poisoned");
+        let filters = self
+            .filters
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .map(|(name, counters)| (name.clone(), self.group_report(*counters, ratio, elapsed)))
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "elapsed_secs": elapsed as u64,
+            "compression_ratio": self.compression.map(|_| ratio),
+            "total": self.group_report(total, ratio, elapsed),
+            "filters": filters,
+        })
+    }
+
+    /// Uncompressed bytes per filter, for metrics
+    pub fn filter_bytes(&self) -> Vec<(String, u64)> {
+        self.filters
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .map(|(name, counters)| (name.clone(), counters.bytes))
+            .collect()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total.lock().expect("poisoned").bytes
+    }
+}
+
+fn compressed_len(encoding: CompressionEncoding, data: &[u8]) -> u64 {
+    match encoding {
+        CompressionEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let _ = encoder.write_all(data);
+            encoder.finish().map_or(data.len(), |out| out.len()) as u64
+        }
+        _ => data.len() as u64,
+    }
+}
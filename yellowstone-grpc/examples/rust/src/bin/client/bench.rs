@@ -0,0 +1,92 @@
+//! Sustained throughput of a subscription, `ACTION=Bench`.
+//!
+//! The stream is subscribed with the usual filter variables for `BENCH_DURATION_SECS`, its
+//! updates are decoded and discarded, nothing is logged or passed to sinks. Decode time is
+//! the time spent polling the stream: tonic decompresses and decodes messages while it is
+//! polled, so this is the CPU time of receiving the stream, waiting for the network is not
+//! part of it. Its share of the elapsed time is the load of one core, near 1 the client
+//! can't keep up and the rate is limited by the CPU, not the endpoint.
+
+use {
+    crate::stats::update_kind,
+    serde_json::{json, Value},
+    std::{collections::BTreeMap, env, time::Duration},
+    tokio::time::Instant,
+    yellowstone_grpc_proto::{prelude::SubscribeUpdate, prost::Message},
+};
+
+const DEFAULT_DURATION_SECS: u64 = 60;
+
+/// `BENCH_DURATION_SECS`
+pub fn duration_from_env() -> anyhow::Result<Duration> {
+    match env::var("BENCH_DURATION_SECS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| anyhow::anyhow!("invalid BENCH_DURATION_SECS")),
+        Err(_) => Ok(Duration::from_secs(DEFAULT_DURATION_SECS)),
+    }
+}
+
+#[derive(Debug)]
+pub struct BenchMeter {
+    started: Instant,
+    messages: u64,
+    /// Encoded size of the messages
+    bytes: u64,
+    decode: Duration,
+    largest: u64,
+    kinds: BTreeMap<&'static str, u64>,
+}
+
+impl BenchMeter {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            messages: 0,
+            bytes: 0,
+            decode: Duration::ZERO,
+            largest: 0,
+            kinds: BTreeMap::new(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Update received after polling the stream for `decode`
+    pub fn observe(&mut self, msg: &SubscribeUpdate, decode: Duration) {
+        self.decode += decode;
+        let bytes = msg.encoded_len() as u64;
+        self.messages += 1;
+        self.bytes += bytes;
+        self.largest = self.largest.max(bytes);
+        if let Some(update) = msg.update_oneof.as_ref() {
+            *self.kinds.entry(update_kind(update)).or_default() += 1;
+        }
+    }
+
+    /// Summary so far, `completed` once the full duration ran
+    pub fn report(&self, endpoint: &str, completed: bool) -> Value {
+        let elapsed = self.elapsed().as_secs_f64();
+        let per_sec = |value: f64| (elapsed > 0.0).then(|| value / elapsed);
+        json!({
+            "endpoint": endpoint,
+            "completed": completed,
+            "elapsed_secs": elapsed,
+            "messages": self.messages,
+            "bytes": self.bytes,
+            "messages_per_sec": per_sec(self.messages as f64),
+            "mb_per_sec": per_sec(self.bytes as f64 / 1_000_000.0),
+            "largest_message_bytes": self.largest,
+            "decode_cpu_secs": self.decode.as_secs_f64(),
+            "decode_cpu_share": per_sec(self.decode.as_secs_f64()),
+            "decode_us_per_message": (self.messages > 0)
+                .then(|| self.decode.as_secs_f64() * 1_000_000.0 / self.messages as f64),
+            "kinds": self.kinds,
+        })
+    }
+}
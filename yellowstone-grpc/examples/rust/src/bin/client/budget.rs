@@ -0,0 +1,111 @@
+//! Memory budget of buffered updates, `MEMORY_BUDGET`.
+//!
+//! Updates waiting in the processing queue and updates held by `HOLD_UNTIL` are counted by
+//! their encoded size against one budget of `MEMORY_BUDGET` bytes. When it is used up the
+//! queue is full, `QUEUE_OVERFLOW` decides between pausing the stream and dropping updates,
+//! and the hold drops the updates of its oldest slots. A buffer which is empty always takes
+//! one update, even one larger than the whole budget, so neither a full block nor the other
+//! buffer stalls it, and the budget is exceeded by at most two updates. Sinks buffer in
+//! their own queues, bounded by their `*_QUEUE_SIZE`.
+
+use {
+    log::warn,
+    std::{
+        env,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+    tokio::sync::Notify,
+};
+
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    used: AtomicU64,
+    freed: Notify,
+    /// Updates which didn't fit
+    exhausted: AtomicU64,
+}
+
+impl MemoryBudget {
+    /// Returns `None` if `MEMORY_BUDGET` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("MEMORY_BUDGET") else {
+            return Ok(None);
+        };
+        let limit = value
+            .parse::<u64>()
+            .ok()
+            .filter(|limit| *limit > 0)
+            .ok_or_else(|| anyhow::anyhow!("invalid MEMORY_BUDGET"))?;
+        Ok(Some(Self::new(limit)))
+    }
+
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+            freed: Notify::new(),
+            exhausted: AtomicU64::new(0),
+        }
+    }
+
+    pub const fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Bytes of updates buffered now
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn exhausted(&self) -> u64 {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Takes `bytes` of the budget if they fit, or regardless with `empty` for a buffer
+    /// which has nothing buffered
+    pub fn try_reserve(&self, bytes: u64, empty: bool) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                (empty || used + bytes <= self.limit).then_some(used + bytes)
+            })
+            .is_ok()
+    }
+
+    /// Waits until `bytes` fit or the buffer is `empty`
+    pub async fn reserve(&self, bytes: u64, buffer: &str, empty: impl Fn() -> bool) {
+        let mut waited = false;
+        loop {
+            // Registered before the check, a release in between is not missed
+            let freed = self.freed.notified();
+            if self.try_reserve(bytes, empty()) {
+                return;
+            }
+            if !waited {
+                waited = true;
+                self.full(bytes, buffer);
+            }
+            freed.await;
+        }
+    }
+
+    pub fn release(&self, bytes: u64) {
+        if bytes > 0 {
+            self.used.fetch_sub(bytes, Ordering::AcqRel);
+            self.freed.notify_waiters();
+        }
+    }
+
+    /// Update of `bytes` for `buffer` didn't fit
+    pub fn full(&self, bytes: u64, buffer: &str) {
+        let exhausted = self.exhausted.fetch_add(1, Ordering::Relaxed) + 1;
+        if exhausted % 10_000 == 1 {
+            warn!(
+                "MEMORY_BUDGET of {} bytes is used up ({} bytes buffered), {buffer} can't take \
+                an update of {bytes} bytes, {exhausted} times in total",
+                self.limit,
+                self.used()
+            );
+        }
+    }
+}
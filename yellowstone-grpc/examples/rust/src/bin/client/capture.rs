@@ -0,0 +1,554 @@
+//! Capture file with raw `SubscribeUpdate` messages.
+//!
+//! Every record is `timestamp (u64 LE, microseconds since UNIX epoch)`, `length (u32 LE)`
+//! and `length` bytes of protobuf encoded `SubscribeUpdate`.
+//!
+//! A framed capture (`RECORD_FORMAT=framed`) starts with `MAGIC`, the format version
+//! (u32 LE), the length of the header (u32 LE), the header as JSON and the CRC32 of the
+//! version, length and header (u32 LE). Each of its records is followed by the CRC32 of the
+//! record (u32 LE). Readers tell the formats apart by the magic, a raw capture starts with a
+//! timestamp instead.
+
+use {
+    crate::stats::{describe_filters, update_slot},
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    std::{
+        fs::{self, File, OpenOptions},
+        io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    yellowstone_grpc_proto::{
+        prelude::{SubscribeRequest, SubscribeUpdate},
+        prost::Message,
+    },
+};
+
+/// First bytes of a framed capture
+const MAGIC: &[u8; 8] = b"YGRPCCAP";
+const FRAMED_VERSION: u32 = 1;
+/// Problems listed in the verify report, the rest are only counted
+const MAX_VERIFY_ERRORS: usize = 10;
+/// Minor release of the `yellowstone-grpc-proto` dependency, the Geyser proto of the records.
+/// Patch releases don't change the proto, a test checks it against `Cargo.toml`.
+pub const PROTO_SCHEMA: &str = "1.14";
+
+/// Format of `RECORD_PATH`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Raw,
+    Framed,
+}
+
+impl CaptureFormat {
+    pub fn from_env_value(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "raw" => Ok(Self::Raw),
+            "framed" => Ok(Self::Framed),
+            _ => anyhow::bail!("invalid RECORD_FORMAT, expected `raw` or `framed`"),
+        }
+    }
+}
+
+/// Written once at the start of a framed capture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    /// `PROTO_SCHEMA` of the client which wrote the capture
+    pub proto_schema: String,
+    /// Redacted like in `ACTION=ConfigDump`
+    pub endpoint: String,
+    /// Filters of the request by name, as in `GET /filters`
+    pub filters: Value,
+    /// RFC 3339
+    pub started_at: String,
+}
+
+impl CaptureHeader {
+    pub fn new(endpoint: &str, request: &SubscribeRequest) -> Self {
+        Self {
+            proto_schema: PROTO_SCHEMA.to_owned(),
+            endpoint: crate::config::redact("ENDPOINT", endpoint),
+            filters: serde_json::to_value(describe_filters(request)).unwrap_or_default(),
+            started_at: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+/// Whether the file starts with `MAGIC`, `false` for an empty or missing file
+fn is_framed(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 8];
+    match File::open(path).and_then(|mut file| file.read_exact(&mut magic)) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(error)
+            if matches!(
+                error.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::UnexpectedEof
+            ) =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    framed: bool,
+}
+
+impl CaptureWriter {
+    /// Open capture for writing, new records are appended to existing file in its format
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let framed = is_framed(path.as_ref())?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: BufWriter::new(file),
+            framed,
+        })
+    }
+
+    /// Open a framed capture, the header is written if the file is new, records are
+    /// appended to an existing framed capture under its header
+    pub fn open_framed(path: impl AsRef<Path>, header: &CaptureHeader) -> io::Result<Self> {
+        let path = path.as_ref();
+        let existing = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        if existing > 0 && !is_framed(path)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is a raw capture, framed records can't be appended",
+                    path.display()
+                ),
+            ));
+        }
+        let mut writer = Self::open(path)?;
+        writer.framed = true;
+        if existing == 0 {
+            let header = serde_json::to_vec(header)?;
+            let version = FRAMED_VERSION.to_le_bytes();
+            let length = u32::try_from(header.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "header is too big"))?
+                .to_le_bytes();
+            writer.file.write_all(MAGIC)?;
+            writer.file.write_all(&version)?;
+            writer.file.write_all(&length)?;
+            writer.file.write_all(&header)?;
+            writer
+                .file
+                .write_all(&crc32(&[&version, &length, &header]).to_le_bytes())?;
+            writer.file.flush()?;
+        }
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, update: &SubscribeUpdate) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        self.write_at(update, timestamp)
+    }
+
+    /// Write a record with the given timestamp, microseconds since UNIX epoch
+    pub fn write_at(&mut self, update: &SubscribeUpdate, timestamp: u64) -> io::Result<()> {
+        let data = update.encode_to_vec();
+        let length = u32::try_from(data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message is too big"))?;
+
+        let (timestamp, length) = (timestamp.to_le_bytes(), length.to_le_bytes());
+        self.file.write_all(&timestamp)?;
+        self.file.write_all(&length)?;
+        self.file.write_all(&data)?;
+        if self.framed {
+            self.file
+                .write_all(&crc32(&[&timestamp, &length, &data]).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Record as stored, before decoding
+struct RawRecord {
+    timestamp: u64,
+    data: Vec<u8>,
+    /// The CRC matches, always `true` in raw captures
+    valid: bool,
+}
+
+pub struct CaptureReader {
+    file: BufReader<File>,
+    header: Option<CaptureHeader>,
+    /// Offset of the next record
+    offset: u64,
+    len: u64,
+}
+
+impl CaptureReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = Self {
+            file: BufReader::new(file),
+            header: None,
+            offset: 0,
+            len,
+        };
+        let mut magic = [0u8; 8];
+        match reader.file.read_exact(&mut magic) {
+            Ok(()) if &magic == MAGIC => reader.read_header()?,
+            Ok(()) | Err(_) => {
+                reader.file.seek(SeekFrom::Start(0))?;
+            }
+        }
+        Ok(reader)
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut version = [0u8; 4];
+        let mut length = [0u8; 4];
+        self.file.read_exact(&mut version)?;
+        self.file.read_exact(&mut length)?;
+        if u32::from_le_bytes(version) != FRAMED_VERSION {
+            return Err(invalid(&format!(
+                "unsupported capture format version {}",
+                u32::from_le_bytes(version)
+            )));
+        }
+        let header_len = u32::from_le_bytes(length) as u64;
+        if MAGIC.len() as u64 + 12 + header_len > self.len {
+            return Err(invalid("capture header is longer than the file"));
+        }
+        let mut header = vec![0u8; header_len as usize];
+        let mut crc = [0u8; 4];
+        self.file.read_exact(&mut header)?;
+        self.file.read_exact(&mut crc)?;
+        if crc32(&[&version, &length, &header]) != u32::from_le_bytes(crc) {
+            return Err(invalid("capture header CRC mismatch"));
+        }
+        self.header = Some(serde_json::from_slice(&header)?);
+        self.offset = MAGIC.len() as u64 + 12 + header_len;
+        Ok(())
+    }
+
+    /// Header of a framed capture, `None` for raw captures
+    pub fn header(&self) -> Option<&CaptureHeader> {
+        self.header.as_ref()
+    }
+
+    /// Offset of the next record
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Next record, `None` at the end of the file, an error if it is cut off
+    fn read_raw(&mut self) -> io::Result<Option<RawRecord>> {
+        let mut timestamp = [0u8; 8];
+        match self.file.read_exact(&mut timestamp) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        }
+
+        let mut length = [0u8; 4];
+        self.file.read_exact(&mut length)?;
+        let crc_len = if self.header.is_some() { 4 } else { 0 };
+        let record_len = 12 + u32::from_le_bytes(length) as u64 + crc_len;
+        // A corrupted length must not allocate gigabytes
+        if self.offset + record_len > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut data = vec![0u8; u32::from_le_bytes(length) as usize];
+        self.file.read_exact(&mut data)?;
+        let valid = match self.header {
+            Some(_) => {
+                let mut crc = [0u8; 4];
+                self.file.read_exact(&mut crc)?;
+                crc32(&[&timestamp, &length, &data]) == u32::from_le_bytes(crc)
+            }
+            None => true,
+        };
+        self.offset += record_len;
+        Ok(Some(RawRecord {
+            timestamp: u64::from_le_bytes(timestamp),
+            data,
+            valid,
+        }))
+    }
+
+    /// Read next record, returns `None` at the end of the file
+    pub fn read(&mut self) -> anyhow::Result<Option<(u64, SubscribeUpdate)>> {
+        let offset = self.offset;
+        let Some(record) = self.read_raw().map_err(|error| {
+            anyhow::anyhow!("failed to read the capture record at offset {offset}: {error}")
+        })?
+        else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            record.valid,
+            "capture record at offset {offset} is corrupted, CRC mismatch"
+        );
+        Ok(Some((
+            record.timestamp,
+            SubscribeUpdate::decode(record.data.as_slice())?,
+        )))
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub path: String,
+    /// `raw` or `framed`
+    pub format: &'static str,
+    pub header: Option<CaptureHeader>,
+    pub records: u64,
+    pub bytes: u64,
+    pub first_timestamp: Option<String>,
+    pub last_timestamp: Option<String>,
+    pub first_slot: Option<u64>,
+    pub last_slot: Option<u64>,
+    /// Records whose CRC doesn't match, framed captures only
+    pub crc_errors: u64,
+    /// Records which are not a valid `SubscribeUpdate`
+    pub decode_errors: u64,
+    /// Offset of a record cut off by the end of the file
+    pub truncated_at: Option<u64>,
+    /// First problems with their offsets
+    pub errors: Vec<String>,
+    pub valid: bool,
+}
+
+impl VerifyReport {
+    fn error(&mut self, error: String) {
+        if self.errors.len() < MAX_VERIFY_ERRORS {
+            self.errors.push(error);
+        }
+    }
+}
+
+fn format_timestamp(micros: u64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp_micros(micros as i64).map(|time| time.to_rfc3339())
+}
+
+/// Read the whole capture and check the header, every CRC and every message
+pub fn verify(path: &str) -> anyhow::Result<VerifyReport> {
+    let mut reader = CaptureReader::open(path)
+        .map_err(|error| anyhow::anyhow!("failed to open {path}: {error}"))?;
+    let mut report = VerifyReport {
+        path: path.to_owned(),
+        format: if reader.header().is_some() {
+            "framed"
+        } else {
+            "raw"
+        },
+        header: reader.header().cloned(),
+        bytes: reader.len,
+        ..Default::default()
+    };
+    let mut last_timestamp = None;
+    loop {
+        let offset = reader.offset();
+        let record = match reader.read_raw() {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(error) => {
+                // The length can't be trusted any more, nothing after it can be read
+                report.truncated_at = Some(offset);
+                report.error(format!("record at offset {offset} is cut off: {error}"));
+                break;
+            }
+        };
+        report.records += 1;
+        if !record.valid {
+            report.crc_errors += 1;
+            report.error(format!("record at offset {offset}: CRC mismatch"));
+            continue;
+        }
+        match SubscribeUpdate::decode(record.data.as_slice()) {
+            Ok(msg) => {
+                if let Some(slot) = update_slot(&msg) {
+                    report.first_slot =
+                        Some(report.first_slot.map_or(slot, |first| first.min(slot)));
+                    report.last_slot = report.last_slot.max(Some(slot));
+                }
+            }
+            Err(error) => {
+                report.decode_errors += 1;
+                report.error(format!("record at offset {offset}: {error}"));
+            }
+        }
+        if report.first_timestamp.is_none() {
+            report.first_timestamp = format_timestamp(record.timestamp);
+        }
+        last_timestamp = Some(record.timestamp);
+    }
+    report.last_timestamp = last_timestamp.and_then(format_timestamp);
+    report.valid =
+        report.crc_errors == 0 && report.decode_errors == 0 && report.truncated_at.is_none();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        tempfile::TempDir,
+        yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdateSlot},
+    };
+
+    fn slot(slot: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: Some(slot - 1),
+                status: 0,
+            })),
+        }
+    }
+
+    /// Framed capture of slots 100, 101 and 102 with timestamps 1, 2 and 3
+    fn framed(dir: &TempDir) -> String {
+        let path = dir.path().join("capture.bin");
+        let header = CaptureHeader::new("https://example.com", &SubscribeRequest::default());
+        let mut writer = CaptureWriter::open_framed(&path, &header).unwrap();
+        for (timestamp, update) in (100..103).map(slot).enumerate() {
+            writer.write_at(&update, timestamp as u64 + 1).unwrap();
+        }
+        writer.flush().unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    /// Offset of the first record of a framed capture
+    fn first_record(path: &str) -> usize {
+        CaptureReader::open(path).unwrap().offset() as usize
+    }
+
+    #[test]
+    fn proto_schema_matches_dependency() {
+        let manifest = include_str!("../../../Cargo.toml");
+        let requirement = format!("yellowstone-grpc-proto = \"{PROTO_SCHEMA}.");
+        assert!(
+            manifest.lines().any(|line| line.starts_with(&requirement)),
+            "update PROTO_SCHEMA to the yellowstone-grpc-proto version of Cargo.toml"
+        );
+    }
+
+    #[test]
+    fn framed_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = framed(&dir);
+
+        // Records are appended under the existing header
+        let other = CaptureHeader::new("https://other.com", &SubscribeRequest::default());
+        let mut writer = CaptureWriter::open_framed(&path, &other).unwrap();
+        writer.write_at(&slot(103), 4).unwrap();
+        writer.flush().unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let header = reader.header().unwrap();
+        assert_eq!(header.proto_schema, PROTO_SCHEMA);
+        assert_eq!(header.endpoint, "https://example.com");
+        for (timestamp, expected) in (100..104).map(slot).enumerate() {
+            let (read_timestamp, update) = reader.read().unwrap().unwrap();
+            assert_eq!(read_timestamp, timestamp as u64 + 1);
+            assert_eq!(update, expected);
+        }
+        assert!(reader.read().unwrap().is_none());
+
+        let report = verify(&path).unwrap();
+        assert_eq!(report.format, "framed");
+        assert_eq!(report.records, 4);
+        assert_eq!(
+            (report.first_slot, report.last_slot),
+            (Some(100), Some(103))
+        );
+        assert!(report.valid);
+    }
+
+    #[test]
+    fn raw_round_trip_and_no_framed_append() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture.bin");
+        let mut writer = CaptureWriter::open(&path).unwrap();
+        writer.write_at(&slot(100), 1).unwrap();
+        writer.flush().unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert!(reader.header().is_none());
+        assert_eq!(reader.read().unwrap().unwrap(), (1, slot(100)));
+        assert!(reader.read().unwrap().is_none());
+
+        let header = CaptureHeader::new("https://example.com", &SubscribeRequest::default());
+        assert!(CaptureWriter::open_framed(&path, &header).is_err());
+    }
+
+    #[test]
+    fn corrupted_record_fails_its_crc() {
+        let dir = TempDir::new().unwrap();
+        let path = framed(&dir);
+        let mut bytes = fs::read(&path).unwrap();
+        // Last byte of the message of the first record, before its CRC
+        let record_len = 12 + slot(100).encoded_len() + 4;
+        bytes[first_record(&path) + record_len - 5] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let error = reader.read().unwrap_err().to_string();
+        assert!(error.contains("CRC mismatch"), "{error}");
+        // The length is intact, the following records are still read
+        assert_eq!(reader.read().unwrap().unwrap().1, slot(101));
+
+        let report = verify(&path).unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(report.crc_errors, 1);
+        assert_eq!(report.truncated_at, None);
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn truncated_record_is_reported() {
+        let dir = TempDir::new().unwrap();
+        let path = framed(&dir);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+
+        let record_len = (12 + slot(100).encoded_len() + 4) as u64;
+        let report = verify(&path).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(
+            report.truncated_at,
+            Some(first_record(&path) as u64 + 2 * record_len)
+        );
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn corrupted_header_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let path = framed(&dir);
+        let mut bytes = fs::read(&path).unwrap();
+        // A byte of the header JSON
+        bytes[MAGIC.len() + 10] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let error = CaptureReader::open(&path).err().unwrap().to_string();
+        assert!(error.contains("header CRC mismatch"), "{error}");
+        assert!(verify(&path).is_err());
+    }
+}
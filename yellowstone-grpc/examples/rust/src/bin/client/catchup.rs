@@ -0,0 +1,209 @@
+//! Rate limit until the stream reaches the tip, `CATCHUP_MAX_RATE`.
+//!
+//! A client which starts far behind (an account snapshot, a backlog of the stream) pushes
+//! updates as fast as the endpoint sends them and can overwhelm sinks. With
+//! `CATCHUP_MAX_RATE` updates are passed on at most at that rate per second until the
+//! highest slot of the stream is within `CATCHUP_LAG_SLOTS` of the tip, the slot polled with
+//! `POLL_INTERVAL_MS`. Reading the stream pauses meanwhile, the backlog stays on the
+//! endpoint. A `catchup` event reports progress every `CATCHUP_REPORT_SECS` and a
+//! `caught_up` event marks the switch to live, after which updates are not limited anymore.
+
+use {
+    log::info,
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        env,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    },
+    tokio::time::{sleep, Instant},
+};
+
+const DEFAULT_LAG_SLOTS: u64 = 32;
+const DEFAULT_REPORT_SECS: u64 = 5;
+/// Interval of checks whether the stream reached the tip
+pub const CATCHUP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+/// Unused rate is not saved up for longer than this, a pause isn't followed by a burst
+const MAX_IDLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    /// Updates passed since `started`
+    passed: u64,
+}
+
+#[derive(Debug)]
+struct Progress {
+    started: Instant,
+    first_slot: Option<u64>,
+    last_report: Instant,
+    /// Updates and stream slot at the last report
+    reported: (u64, Option<u64>),
+}
+
+/// Event `catchup`
+#[derive(Debug, Serialize)]
+pub struct CatchUpProgress {
+    /// Highest slot of the stream
+    pub slot: Option<u64>,
+    /// Polled slot
+    pub tip: Option<u64>,
+    pub behind: Option<u64>,
+    /// Updates passed on since start
+    pub updates: u64,
+    /// Updates and stream slots per second since the last report
+    pub rate: f64,
+    pub slots_per_sec: f64,
+    /// Time to reach the tip at the current pace, the tip moves on meanwhile
+    pub eta_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct CatchUp {
+    max_rate: u64,
+    lag_slots: u64,
+    report: Duration,
+    caught_up: AtomicBool,
+    updates: AtomicU64,
+    window: Mutex<Option<Window>>,
+    progress: Mutex<Progress>,
+}
+
+impl CatchUp {
+    /// Returns `None` if `CATCHUP_MAX_RATE` is not set
+    pub fn from_env(poll_interval: Option<Duration>) -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("CATCHUP_MAX_RATE") else {
+            return Ok(None);
+        };
+        let max_rate = value
+            .parse::<u64>()
+            .ok()
+            .filter(|rate| *rate > 0)
+            .ok_or_else(|| anyhow::anyhow!("invalid CATCHUP_MAX_RATE"))?;
+        anyhow::ensure!(
+            poll_interval.is_some(),
+            "CATCHUP_MAX_RATE needs POLL_INTERVAL_MS to know the tip"
+        );
+        let lag_slots = match env::var("CATCHUP_LAG_SLOTS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid CATCHUP_LAG_SLOTS"))?,
+            Err(_) => DEFAULT_LAG_SLOTS,
+        };
+        let report = match env::var("CATCHUP_REPORT_SECS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid CATCHUP_REPORT_SECS"))?,
+            Err(_) => DEFAULT_REPORT_SECS,
+        };
+        let now = Instant::now();
+        Ok(Some(Self {
+            max_rate,
+            lag_slots,
+            report: Duration::from_secs(report),
+            caught_up: AtomicBool::new(false),
+            updates: AtomicU64::new(0),
+            window: Mutex::default(),
+            progress: Mutex::new(Progress {
+                started: now,
+                first_slot: None,
+                last_report: now,
+                reported: (0, None),
+            }),
+        }))
+    }
+
+    pub fn caught_up(&self) -> bool {
+        self.caught_up.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the next update may pass, right away once caught up
+    pub async fn throttle(&self) {
+        if self.caught_up() {
+            return;
+        }
+        self.updates.fetch_add(1, Ordering::Relaxed);
+        let delay = {
+            let mut window = self.window.lock().expect("poisoned");
+            let window = window.get_or_insert_with(|| Window {
+                started: Instant::now(),
+                passed: 0,
+            });
+            let due = Duration::from_secs_f64(window.passed as f64 / self.max_rate as f64);
+            if window.started.elapsed() > due + MAX_IDLE {
+                window.started = Instant::now();
+                window.passed = 0;
+            }
+            window.passed += 1;
+            let due = Duration::from_secs_f64(window.passed as f64 / self.max_rate as f64);
+            due.checked_sub(window.started.elapsed())
+        };
+        if let Some(delay) = delay {
+            sleep(delay).await;
+        }
+    }
+
+    /// Event to print for the highest slot of the stream and the polled tip: `caught_up`
+    /// once the stream is close enough to the tip, `catchup` every `CATCHUP_REPORT_SECS`
+    /// before
+    pub fn check(&self, slot: Option<u64>, tip: Option<u64>) -> Option<(&'static str, Value)> {
+        if self.caught_up() {
+            return None;
+        }
+        let mut progress = self.progress.lock().expect("poisoned");
+        if progress.first_slot.is_none() {
+            progress.first_slot = slot;
+        }
+        let updates = self.updates.load(Ordering::Relaxed);
+        let behind = slot.zip(tip).map(|(slot, tip)| tip.saturating_sub(slot));
+        if behind.is_some_and(|behind| behind <= self.lag_slots) {
+            self.caught_up.store(true, Ordering::Relaxed);
+            let elapsed = progress.started.elapsed();
+            info!(
+                "catchup: caught up to live at slot {} after {elapsed:.1?}, {updates} updates",
+                slot.unwrap_or_default()
+            );
+            return Some((
+                "caught_up",
+                json!({
+                    "slot": slot,
+                    "tip": tip,
+                    "from_slot": progress.first_slot,
+                    "updates": updates,
+                    "duration_ms": elapsed.as_millis() as u64,
+                }),
+            ));
+        }
+
+        let elapsed = progress.last_report.elapsed();
+        if elapsed < self.report {
+            return None;
+        }
+        let (reported_updates, reported_slot) = progress.reported;
+        let secs = elapsed.as_secs_f64();
+        let slots_per_sec = slot.zip(reported_slot).map_or(0.0, |(slot, reported)| {
+            slot.saturating_sub(reported) as f64 / secs
+        });
+        let report = CatchUpProgress {
+            slot,
+            tip,
+            behind,
+            updates,
+            rate: (updates - reported_updates) as f64 / secs,
+            slots_per_sec,
+            eta_secs: behind
+                .filter(|_| slots_per_sec > 0.0)
+                .map(|behind| (behind as f64 / slots_per_sec) as u64),
+        };
+        progress.last_report = Instant::now();
+        progress.reported = (updates, slot);
+        Some(("catchup", json!(report)))
+    }
+}
@@ -0,0 +1,116 @@
+//! Slot up to which sinks durably received all updates, `CHECKPOINT_PATH`.
+//!
+//! Sinks which write to external storage (CSV, PostgreSQL, Kafka) acknowledge updates once
+//! the destination confirmed them, see `sink::AckTracker`. The checkpoint is the highest
+//! slot acknowledged by all of them, or by `CHECKPOINT_QUORUM` of them, and never moves
+//! backwards. It is written to `CHECKPOINT_PATH` every `SAVE_INTERVAL` and on exit, after
+//! sinks are flushed, so after a restart it tells from which slot data may be missing.
+
+use {
+    crate::sink::Sinks,
+    log::{info, warn},
+    std::{
+        env, fs, io,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+};
+
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+fn load(path: &Path) -> anyhow::Result<Option<u64>> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .trim()
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("invalid checkpoint in {}", path.display())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => anyhow::bail!("failed to read {}: {error}", path.display()),
+    }
+}
+
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: Option<PathBuf>,
+    /// Number of sinks which should acknowledge a slot, all if `None`
+    quorum: Option<usize>,
+    /// Current checkpoint, `u64::MAX` if there is none yet
+    slot: AtomicU64,
+    /// Last saved checkpoint
+    saved: AtomicU64,
+}
+
+impl Checkpoint {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let quorum = env::var("CHECKPOINT_QUORUM")
+            .ok()
+            .map(|value| {
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|quorum| *quorum > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid CHECKPOINT_QUORUM"))
+            })
+            .transpose()?;
+        let path = env::var("CHECKPOINT_PATH").ok().map(PathBuf::from);
+
+        let previous = match path.as_ref() {
+            Some(path) => load(path)?,
+            None => None,
+        };
+        if let Some(slot) = previous {
+            // Subscribe requests of this protocol version can't start from a slot
+            info!(
+                "checkpoint of the previous run: slot {slot}, updates after it may be missing in sinks"
+            );
+        }
+
+        let previous = previous.unwrap_or(u64::MAX);
+        Ok(Self {
+            path,
+            quorum,
+            slot: AtomicU64::new(previous),
+            saved: AtomicU64::new(previous),
+        })
+    }
+
+    pub fn slot(&self) -> Option<u64> {
+        match self.slot.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            slot => Some(slot),
+        }
+    }
+
+    /// Advance the checkpoint to the slot acknowledged by the quorum of sinks
+    pub fn update(&self, sinks: &Sinks) -> Option<u64> {
+        if let Some(acked) = sinks.checkpoint(self.quorum) {
+            let _ = self
+                .slot
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |slot| {
+                    (slot == u64::MAX || acked > slot).then_some(acked)
+                });
+        }
+        self.slot()
+    }
+
+    /// Write the checkpoint to `CHECKPOINT_PATH` if it changed since the last save
+    pub fn save(&self) {
+        let (Some(path), Some(slot)) = (self.path.as_ref(), self.slot()) else {
+            return;
+        };
+        if self.saved.swap(slot, Ordering::Relaxed) == slot {
+            return;
+        }
+        // Replace the file at once, a crash never leaves a partial checkpoint
+        let tmp = path.with_extension("tmp");
+        if let Err(error) =
+            fs::write(&tmp, format!("{slot}\n")).and_then(|()| fs::rename(&tmp, path))
+        {
+            warn!("failed to save checkpoint to {}: {error}", path.display());
+            // Retry on the next save
+            self.saved.store(u64::MAX, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Command line, `client [flags] <subcommand>`.
+//!
+//! The client is configured with environment variables, the command line sets the same
+//! variables. A subcommand sets `ACTION` (`client get-slot` is `ACTION=GetSlot`), every
+//! variable of the configuration dump has a flag with its name in kebab case
+//! (`--queue-workers 4` is `QUEUE_WORKERS=4`) and `--set KEY=VALUE` sets any other, like
+//! `ENDPOINT_<n>` or named filters. Flags are applied before `.env` and `PROFILE` are loaded,
+//! so they override both as well as the process environment. Without arguments nothing
+//! changes. `client completions <shell>` prints a completion script of all of it.
+
+use {
+    crate::config::{self, VARIABLES},
+    clap::{
+        builder::PossibleValuesParser, Arg, ArgAction, ArgMatches, Command, CommandFactory,
+        FromArgMatches, Parser, Subcommand,
+    },
+    clap_complete::Shell,
+    std::{env, io},
+};
+
+/// Flags of the previous command line, kept for existing scripts, `-e` is `--endpoint`
+const ALIASES: &[(&str, &str)] = &[
+    ("SUBSCRIBE_ACCOUNTS", "accounts"),
+    ("SUBSCRIBE_SLOTS", "slots"),
+    ("SUBSCRIBE_TRANSACTIONS", "transactions"),
+    ("SUBSCRIBE_TRANSACTIONS_STATUS", "transactions-status"),
+    ("SUBSCRIBE_ENTRY", "entry"),
+    ("SUBSCRIBE_BLOCKS", "blocks"),
+    ("SUBSCRIBE_BLOCKS_META", "blocks-meta"),
+];
+
+/// Values of `COMMITMENT`, accepted in any case on the command line
+const COMMITMENTS: [&str; 3] = ["Processed", "Confirmed", "Finalized"];
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "client",
+    about = "Solana Geyser gRPC client, configured with flags or environment variables",
+    after_help = "Every flag sets the environment variable of its name, e.g. --queue-workers sets QUEUE_WORKERS. Flags override the environment, PROFILE and .env."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Action>,
+
+    /// Set any variable, e.g. ENDPOINT_1=https://.. or ACCOUNTS_FILTER_usdc=.., can be repeated
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    set: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Action {
+    /// Check the health of the endpoint
+    HealthCheck,
+    /// Log every health status of the endpoint, run hooks on changes
+    HealthWatch,
+    /// Send unary pings
+    Ping,
+    /// Get the latest blockhash
+    GetLatestBlockhash,
+    /// Get the block height
+    GetBlockHeight,
+    /// Get the slot
+    GetSlot,
+    /// Check --blockhash
+    IsBlockhashValid,
+    /// Get the version of the server
+    GetVersion,
+    /// Subscribe with the configured filters
+    Subscribe,
+    /// Subscribe to all endpoints, take every update from the first one
+    MultiSubscribe,
+    /// Make the unary calls of --queries, print one JSON report
+    Query,
+    /// Record the stream to --record-path
+    Record,
+    /// Replay --replay-path
+    Replay,
+    /// Run the filters over the capture of --simulate-path
+    Simulate,
+    /// Terminal view of the stream
+    Dashboard,
+    /// Re-broadcast updates to WebSocket clients
+    Serve,
+    /// Terminal view of a running instance at --status-attach
+    Status,
+    /// Poll GetSlot, GetBlockHeight and GetLatestBlockhash
+    Poll,
+    /// Compare the block time of updates with the time they were received
+    LatencyBench,
+    /// Push synthetic updates of a local mock server through the sinks at rising rates
+    LoadGen,
+    /// Measure throughput of the subscription
+    Bench,
+    /// Report what the subscription delivers
+    Analyze,
+    /// Write a synthetic capture to --generate-path
+    Generate,
+    /// Check the integrity of --verify-path
+    Verify,
+    /// Print the effective configuration with the source of every value
+    ConfigDump,
+    /// Print the profiles of --profiles-path
+    ListProfiles,
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+impl Action {
+    /// Value of `ACTION`, `None` for subcommands which are handled here
+    const fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::HealthCheck => "HealthCheck",
+            Self::HealthWatch => "HealthWatch",
+            Self::Ping => "Ping",
+            Self::GetLatestBlockhash => "GetLatestBlockhash",
+            Self::GetBlockHeight => "GetBlockHeight",
+            Self::GetSlot => "GetSlot",
+            Self::IsBlockhashValid => "IsBlockhashValid",
+            Self::GetVersion => "GetVersion",
+            Self::Subscribe => "Subscribe",
+            Self::MultiSubscribe => "MultiSubscribe",
+            Self::Query => "Query",
+            Self::Record => "Record",
+            Self::Replay => "Replay",
+            Self::Simulate => "Simulate",
+            Self::Dashboard => "Dashboard",
+            Self::Serve => "Serve",
+            Self::Status => "Status",
+            Self::Poll => "Poll",
+            Self::LatencyBench => "LatencyBench",
+            Self::LoadGen => "LoadGen",
+            Self::Bench => "Bench",
+            Self::Analyze => "Analyze",
+            Self::Generate => "Generate",
+            Self::Verify => "Verify",
+            Self::ConfigDump => "ConfigDump",
+            Self::ListProfiles => "ListProfiles",
+            Self::Completions { .. } => return None,
+        })
+    }
+}
+
+fn flag_name(key: &str) -> String {
+    key.to_lowercase().replace('_', "-")
+}
+
+/// The derived command with a flag for every variable
+fn command() -> Command {
+    let mut command = Cli::command();
+    for (key, default) in VARIABLES.iter().filter(|(key, _)| *key != "ACTION") {
+        let mut arg = Arg::new(*key)
+            .long(flag_name(key))
+            .value_name("VALUE")
+            .action(ArgAction::Set)
+            .global(true)
+            .help(match default {
+                Some(default) => format!("{key} [default: {default}]"),
+                None => key.to_string(),
+            });
+        // `--subscribe-slots` alone is `true`
+        if matches!(default, Some("true" | "false")) {
+            arg = arg.num_args(0..=1).default_missing_value("true");
+        }
+        if *key == "ENDPOINT" {
+            arg = arg.short('e');
+        }
+        if *key == "COMMITMENT" {
+            arg = arg
+                .value_parser(PossibleValuesParser::new(COMMITMENTS))
+                .ignore_case(true);
+        }
+        for (_, alias) in ALIASES.iter().filter(|(alias_key, _)| alias_key == key) {
+            arg = arg.visible_alias(*alias);
+        }
+        command = command.arg(arg);
+    }
+    command
+}
+
+/// Set the variables of the command line, returns `false` if it was handled already
+pub fn apply() -> anyhow::Result<bool> {
+    if env::args_os().len() <= 1 {
+        return Ok(true);
+    }
+    let matches = command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    if let Some(Action::Completions { shell }) = cli.command {
+        print_completions(shell);
+        return Ok(false);
+    }
+
+    let mut vars = Vec::new();
+    if let Some(action) = cli.command.as_ref().and_then(Action::name) {
+        vars.push(("ACTION".to_owned(), action.to_owned()));
+    }
+    for (key, _) in VARIABLES {
+        if let Some(mut value) = flag_value(&matches, key) {
+            if *key == "COMMITMENT" {
+                if let Some(commitment) = COMMITMENTS
+                    .iter()
+                    .find(|commitment| commitment.eq_ignore_ascii_case(&value))
+                {
+                    value = commitment.to_string();
+                }
+            }
+            vars.push((key.to_string(), value));
+        }
+    }
+    for pair in cli.set {
+        let (key, value) = pair
+            .split_once('=')
+            .filter(|(key, _)| !key.is_empty() && !key.contains('\0'))
+            .ok_or_else(|| anyhow::anyhow!("invalid --set {pair}, expected KEY=VALUE"))?;
+        vars.push((key.to_owned(), value.to_owned()));
+    }
+    config::set_from_command_line(vars);
+    Ok(true)
+}
+
+/// Value of a global flag, given before or after the subcommand
+fn flag_value(matches: &ArgMatches, key: &str) -> Option<String> {
+    let value = matches.try_get_one::<String>(key).ok().flatten().cloned();
+    value.or_else(|| {
+        matches
+            .subcommand()
+            .and_then(|(_, matches)| flag_value(matches, key))
+    })
+}
+
+fn print_completions(shell: Shell) {
+    let mut command = command();
+    let name = command.get_name().to_owned();
+    clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+}
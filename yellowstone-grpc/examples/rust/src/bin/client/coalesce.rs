@@ -0,0 +1,231 @@
+//! Only the latest write of an account within a short window, `COALESCE_WINDOW_MS`.
+//!
+//! Consumers which keep the latest state of accounts don't need every write of a hot account.
+//! With `COALESCE_WINDOW_MS` account updates are held until the end of the current window, a
+//! newer write of the same pubkey replaces the held one, and at the end of every window the
+//! held updates are passed to sinks and logging together with the number of writes they
+//! replaced. Other updates are not held, so account updates arrive at sinks up to one window
+//! later than slots and transactions of the same slot.
+
+use {
+    std::{
+        collections::HashMap,
+        env, fmt,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+/// Held update and the number of older writes it replaced
+#[derive(Debug)]
+struct Held {
+    msg: SubscribeUpdate,
+    /// Slot and write version
+    version: (u64, u64),
+    collapsed: u64,
+}
+
+/// Slot and write version of an account update
+fn account_version(msg: &SubscribeUpdate) -> Option<(Vec<u8>, (u64, u64))> {
+    let Some(UpdateOneof::Account(update)) = msg.update_oneof.as_ref() else {
+        return None;
+    };
+    let account = update.account.as_ref()?;
+    Some((account.pubkey.clone(), (update.slot, account.write_version)))
+}
+
+#[derive(Debug)]
+pub struct AccountCoalescer {
+    pub window: Duration,
+    held: Mutex<HashMap<Vec<u8>, Held>>,
+    collapsed: AtomicU64,
+}
+
+impl AccountCoalescer {
+    /// Returns `None` if `COALESCE_WINDOW_MS` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("COALESCE_WINDOW_MS") else {
+            return Ok(None);
+        };
+        let window = value
+            .parse::<u64>()
+            .ok()
+            .filter(|window| *window > 0)
+            .ok_or_else(|| anyhow::anyhow!("invalid COALESCE_WINDOW_MS"))?;
+        Ok(Some(Self::new(Duration::from_millis(window))))
+    }
+
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            held: Mutex::default(),
+            collapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Account writes replaced by a newer write of the same pubkey
+    pub fn collapsed(&self) -> u64 {
+        self.collapsed.load(Ordering::Relaxed)
+    }
+
+    /// Hold an account update until the end of the window, other updates are returned
+    pub fn hold(&self, msg: SubscribeUpdate) -> Option<SubscribeUpdate> {
+        let Some((pubkey, version)) = account_version(&msg) else {
+            return Some(msg);
+        };
+        let mut held = self.held.lock().expect("poisoned");
+        match held.get_mut(&pubkey) {
+            Some(current) => {
+                // Workers can process writes of one account out of order
+                if version > current.version {
+                    current.msg = msg;
+                    current.version = version;
+                }
+                current.collapsed += 1;
+                self.collapsed.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                held.insert(
+                    pubkey,
+                    Held {
+                        msg,
+                        version,
+                        collapsed: 0,
+                    },
+                );
+            }
+        }
+        None
+    }
+
+    /// Held updates ordered by slot and write version, with the number of writes each one
+    /// replaced
+    pub fn drain(&self) -> Vec<(SubscribeUpdate, u64)> {
+        let held = std::mem::take(&mut *self.held.lock().expect("poisoned"));
+        let mut held = held.into_values().collect::<Vec<_>>();
+        held.sort_unstable_by_key(|held| held.version);
+        held.into_iter()
+            .map(|held| (held.msg, held.collapsed))
+            .collect()
+    }
+}
+
+/// Logged update with the number of writes it replaced
+pub struct Coalesced<'a> {
+    pub update: &'a dyn fmt::Debug,
+    pub collapsed: u64,
+}
+
+impl fmt::Debug for Coalesced<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.update.fmt(f)?;
+        if self.collapsed > 0 {
+            write!(f, ", collapsed {} older writes", self.collapsed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateSlot,
+        },
+    };
+
+    fn account(pubkey: u8, slot: u64, write_version: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["accounts".to_owned()],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: vec![pubkey; 32],
+                    write_version,
+                    ..Default::default()
+                }),
+                slot,
+                is_startup: false,
+            })),
+        }
+    }
+
+    fn drained(coalescer: &AccountCoalescer) -> Vec<(u8, (u64, u64), u64)> {
+        coalescer
+            .drain()
+            .into_iter()
+            .map(|(msg, collapsed)| {
+                let (pubkey, version) = account_version(&msg).unwrap();
+                (pubkey[0], version, collapsed)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn newest_write_of_each_account_in_version_order() {
+        let coalescer = AccountCoalescer::new(Duration::from_millis(100));
+        for msg in [
+            account(1, 10, 1),
+            account(2, 10, 2),
+            account(1, 11, 3),
+            // Older write processed late is counted but doesn't replace the newer one
+            account(1, 10, 0),
+            account(3, 9, 5),
+        ] {
+            assert!(coalescer.hold(msg).is_none());
+        }
+        assert_eq!(
+            drained(&coalescer),
+            [(3, (9, 5), 0), (2, (10, 2), 0), (1, (11, 3), 2)]
+        );
+        assert_eq!(coalescer.collapsed(), 2);
+    }
+
+    #[test]
+    fn every_window_starts_empty() {
+        let coalescer = AccountCoalescer::new(Duration::from_millis(100));
+        coalescer.hold(account(1, 10, 1));
+        coalescer.hold(account(1, 10, 2));
+        assert_eq!(drained(&coalescer), [(1, (10, 2), 1)]);
+        assert!(coalescer.drain().is_empty());
+
+        // A write older than the one of the previous window is passed on again
+        coalescer.hold(account(1, 10, 1));
+        assert_eq!(drained(&coalescer), [(1, (10, 1), 0)]);
+        assert_eq!(coalescer.collapsed(), 1);
+    }
+
+    #[test]
+    fn other_updates_are_not_held() {
+        let coalescer = AccountCoalescer::new(Duration::from_millis(100));
+        let slot = SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot: 10,
+                parent: None,
+                status: 0,
+            })),
+        };
+        assert_eq!(coalescer.hold(slot.clone()), Some(slot));
+        assert!(coalescer.drain().is_empty());
+    }
+
+    #[test]
+    fn logs_collapsed_writes() {
+        let format = |collapsed| {
+            format!(
+                "{:?}",
+                Coalesced {
+                    update: &"update",
+                    collapsed,
+                }
+            )
+        };
+        assert_eq!(format(0), r#""update""#);
+        assert_eq!(format(3), r#""update", collapsed 3 older writes"#);
+    }
+}
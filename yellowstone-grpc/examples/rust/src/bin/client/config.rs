@@ -0,0 +1,583 @@
+//! Effective configuration with provenance, `ACTION=ConfigDump`.
+//!
+//! The client is configured only by environment variables, a `.env` file fills in the ones
+//! not set in the process environment and a `PROFILE` overrides the file. Variables present
+//! before `.env` is loaded are remembered, so every value can be reported as coming from the
+//! environment, the file, the profile or the built-in default. Variables listed in `SECRETS`,
+//! passwords and paths of URLs are redacted.
+
+use {
+    crate::profiles,
+    dotenv::dotenv,
+    log::info,
+    serde_json::{json, Map, Value},
+    std::{collections::BTreeMap, env, path::PathBuf, sync::OnceLock},
+};
+
+/// Variables with a fixed name and their defaults, `None` if unset means disabled
+pub const VARIABLES: &[(&str, Option<&str>)] = &[
+    ("ACTION", None),
+    ("PROFILE", None),
+    ("PROFILES_PATH", Some("profiles.toml")),
+    ("RUST_LOG", Some("info")),
+    ("ENDPOINT", None),
+    ("X_TOKEN", None),
+    ("X_TOKEN_CMD", None),
+    ("X_TOKEN_FILE", None),
+    ("X_TOKEN_REFRESH_SECS", None),
+    ("TLS_CA_CERTIFICATE", None),
+    ("TLS_CA_PATH", None),
+    ("TLS_DOMAIN_NAME", None),
+    ("TLS_CLIENT_CERTIFICATE", None),
+    ("TLS_CLIENT_CERT", None),
+    ("TLS_CLIENT_KEY", None),
+    ("TLS_INSECURE", Some("false")),
+    ("GRPC_COMPRESSION", Some("none")),
+    ("COMPRESSION", None),
+    ("MAX_DECODING_MESSAGE_SIZE", None),
+    ("MAX_DECODED_MESSAGE_SIZE", None),
+    ("RESOLVE", Some("system")),
+    ("PIN_IP", None),
+    ("HTTP2_KEEPALIVE_INTERVAL_SECS", None),
+    ("HTTP2_KEEPALIVE_TIMEOUT_SECS", None),
+    ("HTTP2_KEEPALIVE_WHILE_IDLE", None),
+    ("TCP_NODELAY", None),
+    ("TCP_KEEPALIVE_SECS", None),
+    ("INITIAL_STREAM_WINDOW_SIZE", None),
+    ("INITIAL_CONNECTION_WINDOW_SIZE", None),
+    ("HTTP2_ADAPTIVE_WINDOW", None),
+    ("RETRY_INITIAL_MS", Some("500")),
+    ("RETRY_MAX_MS", Some("60000")),
+    ("RETRY_MULTIPLIER", Some("1.5")),
+    ("RETRY_JITTER", Some("0.5")),
+    ("RETRY_MAX_ELAPSED_SECS", Some("900")),
+    ("RETRY_STREAM_RECOVERIES", Some("3")),
+    ("COMMITMENT", None),
+    ("ADMIN_ADDR", None),
+    ("ADMIN_REPLAY_DIR", None),
+    ("OUTPUT", Some("text")),
+    (
+        "CSV_COLUMNS",
+        Some("kind,slot,pubkey,owner,lamports,write_version,signature,is_vote,index,err"),
+    ),
+    ("CSV_PATH", None),
+    ("OUTPUT_TEMPLATE", None),
+    ("SHUTDOWN_GRACE_MS", Some("2000")),
+    ("POLL_INTERVAL_MS", None),
+    ("CATCHUP_MAX_RATE", None),
+    ("CATCHUP_LAG_SLOTS", Some("32")),
+    ("CATCHUP_REPORT_SECS", Some("5")),
+    ("QUEUE_CAPACITY", Some("10000")),
+    ("QUEUE_WORKERS", Some("1")),
+    ("QUEUE_OVERFLOW", Some("block")),
+    ("DECODE_WORKERS", None),
+    ("DECODE_ORDER", Some("ordered")),
+    ("DECODE_QUEUE_SIZE", Some("10000")),
+    ("MEMORY_BUDGET", None),
+    ("BANDWIDTH_REPORT_SECS", None),
+    ("STATS_INTERVAL_SECS", None),
+    ("LATENCY_SLO", None),
+    ("BANDWIDTH_PRICE_PER_GB", None),
+    ("BANDWIDTH_SAMPLE_EVERY", Some("100")),
+    ("WATCHDOG_MAX_SILENCE_MS", None),
+    ("WATCHDOG_MAX_LAG_SLOTS", None),
+    ("WATCHDOG_SLOT_MS", Some("450")),
+    ("DEDUP_CAPACITY", None),
+    ("CORRELATE_TRANSACTIONS", Some("false")),
+    ("CORRELATE_CAPACITY", Some("10000")),
+    ("FORK_DETECTION", Some("false")),
+    ("FORK_MAX_SLOTS", Some("10000")),
+    ("GAP_DETECTION", Some("false")),
+    ("GAP_MAX_ACCOUNTS", Some("100000")),
+    ("HOLD_UNTIL", None),
+    ("HOLD_MAX_SLOTS", Some("1000")),
+    ("COALESCE_WINDOW_MS", None),
+    ("REASSEMBLE_BLOCKS", Some("false")),
+    ("REASSEMBLE_WAIT", Some("transactions")),
+    ("REASSEMBLE_TIMEOUT_MS", Some("2000")),
+    ("REASSEMBLE_MAX_SLOTS", Some("100")),
+    ("EVENT_BUS_CAPACITY", Some("4096")),
+    ("DIFF_ACCOUNTS", Some("false")),
+    ("DIFF_ACCOUNTS_CAPACITY", Some("100000")),
+    ("SAMPLE_RATE", None),
+    ("MAX_MSGS_PER_SEC", None),
+    ("PARSE_INSTRUCTIONS", Some("false")),
+    ("BALANCE_CHANGES", Some("false")),
+    ("TRACE_IDS", Some("false")),
+    ("PREFLIGHT", Some("true")),
+    ("DRY_RUN_LOG_EVERY", Some("1000")),
+    ("NAMESPACE_BY_FILTER", Some("false")),
+    ("CHECKPOINT_PATH", None),
+    ("CHECKPOINT_QUORUM", None),
+    ("PROGRESS_PATH", None),
+    ("PROGRESS_INTERVAL_MS", Some("1000")),
+    ("PROGRESS_SIGNATURES", Some("1000")),
+    ("ERROR_POLICY_DECODE", Some("skip")),
+    ("ERROR_POLICY_ENRICH", Some("skip")),
+    ("ERROR_POLICY_SINK", Some("retry:3")),
+    ("ERROR_QUARANTINE_PATH", Some("quarantine.bin")),
+    ("SCHEMA_STRICT", Some("false")),
+    ("FILTERS_PATH", None),
+    ("DISCOVER_ACCOUNTS", Some("false")),
+    ("DISCOVER_FILTERS", None),
+    ("DISCOVER_MAX_ACCOUNTS", Some("1000")),
+    ("DISCOVER_TTL_SECS", Some("3600")),
+    ("DISCOVER_INTERVAL_MS", Some("1000")),
+    ("RECONNECT_HISTORY_SIZE", Some("100")),
+    ("RECONNECT_HISTORY_PATH", None),
+    ("RECENT_SLOTS", None),
+    ("RECENT_SLOT_UPDATES", Some("10000")),
+    ("TOKEN_OWNERS", Some("false")),
+    ("TOKEN_OWNERS_WALLETS", None),
+    ("TOKEN_OWNERS_CAPACITY", Some("1000000")),
+    ("MINT_WATCH", None),
+    ("MINT_REPORT_SECS", Some("60")),
+    ("RPC_FACADE_ADDR", None),
+    ("RPC_FACADE_CAPACITY", Some("1000000")),
+    ("STATE_SNAPSHOT_SECS", None),
+    ("STATE_SNAPSHOT_FULL_EVERY", Some("10")),
+    ("STATE_SNAPSHOT_FILTERS", None),
+    ("STATE_SNAPSHOT_CAPACITY", Some("1000000")),
+    ("NOTIFY_SLACK_WEBHOOK_URL", None),
+    ("NOTIFY_TELEGRAM_BOT_TOKEN", None),
+    ("NOTIFY_TELEGRAM_CHAT_ID", None),
+    ("NOTIFY_FILTERS", None),
+    ("NOTIFY_DIGEST_SECS", None),
+    ("NOTIFY_DIGEST_SAMPLES", Some("5")),
+    ("NOTIFY_LAMPORTS_BELOW", None),
+    ("NOTIFY_COOLDOWN_SECS", None),
+    ("NOTIFY_DRY_RUN", Some("false")),
+    ("WATCHLIST_PATH", None),
+    ("WATCHLIST_WEBHOOK_URL", None),
+    ("WATCHLIST_ALERT_CMD", None),
+    ("WATCHLIST_COOLDOWN_SECS", None),
+    ("HOOK_CMD", None),
+    ("HOOK_MODE", Some("spawn")),
+    ("HOOK_FILTERS", None),
+    ("HOOK_TYPES", None),
+    ("HOOK_CONCURRENCY", Some("4")),
+    ("HOOK_TIMEOUT_MS", Some("5000")),
+    ("HOOK_QUEUE_SIZE", Some("1000")),
+    ("SOCKET_PATH", None),
+    ("SOCKET_FILTERS", None),
+    ("SOCKET_CLIENT_BUFFER", Some("1000")),
+    ("PRIORITY_FILTERS", None),
+    ("PRIORITY_SINKS", Some("socket")),
+    ("POSTGRES_URL", None),
+    ("POSTGRES_ACCOUNTS_TABLE", Some("accounts")),
+    ("POSTGRES_TRANSACTIONS_TABLE", Some("transactions")),
+    ("POSTGRES_BATCH_SIZE", Some("500")),
+    ("POSTGRES_BATCH_MAX_DELAY_MS", Some("100")),
+    ("POSTGRES_QUEUE_SIZE", Some("100000")),
+    ("NO_MIGRATE", Some("false")),
+    ("POSTGRES_DRY_RUN", Some("false")),
+    ("POSTGRES_NAMESPACE_BY_FILTER", None),
+    ("SQLITE_PATH", None),
+    ("SQLITE_BATCH_SIZE", Some("500")),
+    ("SQLITE_BATCH_MAX_DELAY_MS", Some("100")),
+    ("SQLITE_QUEUE_SIZE", Some("100000")),
+    ("SQLITE_SYNCHRONOUS", Some("normal")),
+    ("CLICKHOUSE_URL", None),
+    ("CLICKHOUSE_USER", None),
+    ("CLICKHOUSE_PASSWORD", None),
+    ("CLICKHOUSE_DATABASE", Some("default")),
+    ("CLICKHOUSE_ACCOUNTS_TABLE", Some("accounts")),
+    ("CLICKHOUSE_TRANSACTIONS_TABLE", Some("transactions")),
+    ("CLICKHOUSE_BLOCKS_META_TABLE", Some("blocks_meta")),
+    ("CLICKHOUSE_BATCH_SIZE", Some("100000")),
+    ("CLICKHOUSE_FLUSH_INTERVAL_MS", Some("1000")),
+    ("CLICKHOUSE_QUEUE_SIZE", Some("1000000")),
+    ("CLICKHOUSE_COMPRESS", Some("true")),
+    ("CLICKHOUSE_CREATE_TABLES", Some("true")),
+    ("CLICKHOUSE_DRY_RUN", Some("false")),
+    ("CLICKHOUSE_NAMESPACE_BY_FILTER", None),
+    ("PARQUET_DIR", None),
+    ("PARQUET_PARTITION_SLOTS", Some("10000")),
+    ("PARQUET_PARTITION_SECS", None),
+    ("PARQUET_BATCH_SIZE", Some("100000")),
+    ("PARQUET_FLUSH_INTERVAL_MS", Some("1000")),
+    ("PARQUET_QUEUE_SIZE", Some("1000000")),
+    ("PARQUET_COMPRESSION", Some("zstd")),
+    ("PARQUET_NAMESPACE_BY_FILTER", None),
+    ("KAFKA_BROKERS", None),
+    ("KAFKA_TOPIC_PREFIX", Some("grpc")),
+    ("KAFKA_ACKS", Some("all")),
+    ("KAFKA_LINGER_MS", Some("5")),
+    ("KAFKA_BATCH_SIZE", Some("10000")),
+    ("KAFKA_COMPRESSION", Some("none")),
+    ("KAFKA_QUEUE_SIZE", Some("100000")),
+    ("KAFKA_DRY_RUN", Some("false")),
+    ("KAFKA_PARTITIONER", Some("key")),
+    ("KAFKA_SLOT_BUCKET", Some("100")),
+    ("KAFKA_CREATE_TOPICS", Some("false")),
+    ("KAFKA_PARTITIONS", Some("6")),
+    ("KAFKA_REPLICATION", Some("1")),
+    ("KAFKA_NAMESPACE_BY_FILTER", None),
+    ("REDIS_URL", None),
+    ("REDIS_USERNAME", None),
+    ("REDIS_PASSWORD", None),
+    ("REDIS_MODE", Some("publish")),
+    ("REDIS_PREFIX", Some("geyser:")),
+    ("REDIS_TYPES", None),
+    ("REDIS_STREAM_MAXLEN", Some("100000")),
+    ("REDIS_BATCH_SIZE", Some("1000")),
+    ("REDIS_QUEUE_SIZE", Some("100000")),
+    ("REDIS_DRY_RUN", Some("false")),
+    ("REDIS_NAMESPACE_BY_FILTER", None),
+    ("REDIS_RETRIES", Some("3")),
+    ("REDIS_DEAD_LETTER_PATH", None),
+    ("TRUNCATE_DATA_BYTES", None),
+    ("TRUNCATE_LOGS_BYTES", None),
+    ("HEALTH_WEBHOOK_URL", None),
+    ("HEALTH_HOOK_SCRIPT", None),
+    ("HEALTH_FAILOVER", Some("false")),
+    ("HEALTH_INCIDENT_LOG", None),
+    ("LIFECYCLE_LOG", None),
+    ("LIFECYCLE_WEBHOOK_URL", None),
+    ("LIFECYCLE_DEGRADED_SLOTS", None),
+    ("LIVENESS_FILE", None),
+    ("LIVENESS_TOUCH_MS", Some("1000")),
+    ("PING_COUNT", None),
+    ("BLOCKHASH", None),
+    ("QUERIES", None),
+    ("ONESHOT", Some("false")),
+    ("ONESHOT_TIMEOUT_MS", Some("10000")),
+    ("RECORD_PATH", None),
+    ("RECORD_FORMAT", Some("raw")),
+    ("REPLAY_PATH", None),
+    ("REPLAY_SPEED", Some("1.0")),
+    ("SIMULATE_PATH", None),
+    ("STATUS_ATTACH", None),
+    ("SERVE_ADDR", Some("127.0.0.1:8901")),
+    ("SERVE_CLIENT_BUFFER", Some("1024")),
+    ("JSON_STYLE", Some("client")),
+    ("JSON_FIELD_CASE", None),
+    ("JSON_ENUMS", None),
+    ("JSON_BYTES", None),
+    ("JSON_DATA", None),
+    ("MULTI_DEDUP_CAPACITY", Some("100000")),
+    ("MULTI_REPORT_SECS", Some("60")),
+    ("MULTI_PRECEDENCE", None),
+    ("MULTI_STALE_MS", Some("2000")),
+    ("POOL_CONNECTIONS", None),
+    ("POOL_REPORT_SECS", Some("60")),
+    ("POOL_STALE_SECS", None),
+    ("LATENCY_INTERVAL_SECS", Some("10")),
+    ("LATENCY_WINDOW_SECS", Some("60")),
+    ("BENCH_DURATION_SECS", Some("60")),
+    ("ANALYZE_DURATION_SECS", Some("60")),
+    ("ANALYZE_TOP", Some("20")),
+    ("LOADGEN_ADDR", Some("127.0.0.1:0")),
+    ("LOADGEN_RATE", Some("1000")),
+    ("LOADGEN_MAX_RATE", Some("1000000")),
+    ("LOADGEN_STEP_SECS", Some("10")),
+    ("LOADGEN_DATA_BYTES", Some("165")),
+    ("LOADGEN_ACCOUNTS", Some("1000")),
+    ("GENERATE_PATH", None),
+    ("SYNTH_SEED", Some("0")),
+    ("SYNTH_SLOTS", Some("100")),
+    ("SYNTH_SWAPS_PER_SLOT", Some("20")),
+    ("SYNTH_WALLETS", Some("1000")),
+    ("SYNTH_POOLS", Some("10")),
+    ("VERIFY_PATH", None),
+    ("SUBSCRIBE_ACCOUNTS", Some("false")),
+    ("ACCOUNTS_ACCOUNT", None),
+    ("ACCOUNTS_ACCOUNT_PATH", None),
+    ("ACCOUNTS_OWNER", None),
+    ("ACCOUNTS_MEMCMP", None),
+    ("ACCOUNTS_DATASIZE", None),
+    ("ACCOUNTS_TOKEN_ACCOUNT_STATE", Some("false")),
+    ("ACCOUNTS_DATA_SLICE", None),
+    ("ACCOUNTS_MIN_LAMPORTS", None),
+    ("ACCOUNTS_MAX_LAMPORTS", None),
+    ("TRANSACTIONS_LOG_CONTAINS", None),
+    ("TRANSACTIONS_LOG_REGEX", None),
+    ("TRANSACTIONS_FEE_PAYER", None),
+    ("FILTER_EXPR", None),
+    ("RPC_URL", None),
+    ("SUBSCRIBE_SLOTS", Some("false")),
+    ("SLOTS_FILTER_BY_COMMITMENT", Some("false")),
+    ("SUBSCRIBE_TRANSACTIONS", Some("false")),
+    ("TRANSACTIONS_VOTE", None),
+    ("TRANSACTIONS_FAILED", None),
+    ("TRANSACTIONS_SIGNATURE", None),
+    ("TRANSACTIONS_ACCOUNT_INCLUDE", None),
+    ("TRANSACTIONS_ACCOUNT_EXCLUDE", None),
+    ("TRANSACTIONS_ACCOUNT_REQUIRED", None),
+    ("SUBSCRIBE_TRANSACTIONS_STATUS", Some("false")),
+    ("TRANSACTIONS_STATUS_VOTE", None),
+    ("TRANSACTIONS_STATUS_FAILED", None),
+    ("TRANSACTIONS_STATUS_SIGNATURE", None),
+    ("TRANSACTIONS_STATUS_ACCOUNT_INCLUDE", None),
+    ("TRANSACTIONS_STATUS_ACCOUNT_EXCLUDE", None),
+    ("TRANSACTIONS_STATUS_ACCOUNT_REQUIRED", None),
+    ("SUBSCRIBE_ENTRY", Some("false")),
+    ("SUBSCRIBE_BLOCKS", Some("false")),
+    ("BLOCKS_ACCOUNT_INCLUDE", None),
+    ("BLOCKS_INCLUDE_TRANSACTIONS", None),
+    ("BLOCKS_INCLUDE_ACCOUNTS", None),
+    ("BLOCKS_INCLUDE_ENTRIES", None),
+    ("SUBSCRIBE_BLOCKS_META", Some("false")),
+    ("RESUB", None),
+];
+
+/// Variables whose whole value is masked, others with `TOKEN` in the name are shown
+const SECRETS: &[&str] = &[
+    "X_TOKEN",
+    "NOTIFY_TELEGRAM_BOT_TOKEN",
+    "CLICKHOUSE_PASSWORD",
+    "REDIS_PASSWORD",
+];
+
+/// Families of variables with a name part, listed when set
+const PREFIXES: &[&str] = &[
+    "ENDPOINT_",
+    "ACCOUNTS_FILTER_",
+    "TRANSACTIONS_FILTER_",
+    "TRANSACTIONS_STATUS_FILTER_",
+    "FILTER_TAGS_",
+    "KAFKA_TOPIC_",
+    "KAFKA_PARTITIONER_",
+];
+
+#[derive(Debug)]
+struct DotEnv {
+    path: Option<PathBuf>,
+    /// Variables which were set by the file
+    keys: Vec<String>,
+}
+
+static DOTENV: OnceLock<DotEnv> = OnceLock::new();
+
+#[derive(Debug)]
+struct AppliedProfile {
+    name: String,
+    path: PathBuf,
+    /// Variables which were set by the profile
+    keys: Vec<String>,
+    /// Variables of the profile kept from the process environment
+    overridden: usize,
+}
+
+static PROFILE: OnceLock<AppliedProfile> = OnceLock::new();
+
+/// Variables which were set by the command line
+static COMMAND_LINE: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Set the variables of the command line, before `.env` is loaded so they take precedence
+pub fn set_from_command_line(vars: Vec<(String, String)>) {
+    let keys = vars
+        .into_iter()
+        .map(|(key, value)| {
+            env::set_var(&key, value);
+            key
+        })
+        .collect();
+    let _ = COMMAND_LINE.set(keys);
+}
+
+/// Load `.env` once, remembering which variables it set
+pub fn load_dotenv() {
+    DOTENV.get_or_init(|| {
+        let before = env::vars_os().map(|(key, _)| key).collect::<Vec<_>>();
+        let path = dotenv().ok();
+        let keys = env::vars()
+            .map(|(key, _)| key)
+            .filter(|key| !before.iter().any(|existing| existing == key.as_str()))
+            .collect();
+        DotEnv { path, keys }
+    });
+}
+
+/// Apply `PROFILE` after `.env`, its variables replace the ones of the file but not the ones
+/// of the process environment
+pub fn load_profile() -> anyhow::Result<()> {
+    let Some(profile) = profiles::selected()? else {
+        return Ok(());
+    };
+    let from_dotenv = |key: &str| {
+        DOTENV
+            .get()
+            .is_some_and(|dotenv| dotenv.keys.iter().any(|existing| existing == key))
+    };
+    let mut keys = Vec::new();
+    let mut overridden = 0;
+    for (key, value) in profile.vars {
+        if env::var_os(&key).is_none() || from_dotenv(&key) {
+            env::set_var(&key, value);
+            keys.push(key);
+        } else {
+            overridden += 1;
+        }
+    }
+    let _ = PROFILE.set(AppliedProfile {
+        name: profile.name,
+        path: profile.path,
+        keys,
+        overridden,
+    });
+    Ok(())
+}
+
+/// Log the applied profile, once logging is initialized
+pub fn log_profile() {
+    if let Some(profile) = PROFILE.get() {
+        info!(
+            "profile {} from {}: {} variables set, {} kept from the environment",
+            profile.name,
+            profile.path.display(),
+            profile.keys.len(),
+            profile.overridden
+        );
+    }
+}
+
+fn source(key: &str) -> String {
+    if COMMAND_LINE
+        .get()
+        .is_some_and(|keys| keys.iter().any(|existing| existing == key))
+    {
+        return "command line".to_owned();
+    }
+    if let Some(profile) = PROFILE
+        .get()
+        .filter(|profile| profile.keys.iter().any(|existing| existing == key))
+    {
+        return format!("profile {} {}", profile.name, profile.path.display());
+    }
+    match DOTENV.get() {
+        Some(DotEnv {
+            path: Some(path),
+            keys,
+        }) if keys.iter().any(|existing| existing == key) => {
+            format!("file {}", path.display())
+        }
+        _ => "env".to_owned(),
+    }
+}
+
+/// Whether `key` is listed in `SECRETS`, also as an option of a failover endpoint, e.g.
+/// `ENDPOINT_1_X_TOKEN`
+fn is_secret(key: &str) -> bool {
+    let key = key
+        .strip_prefix("ENDPOINT_")
+        .and_then(|rest| rest.split_once('_'))
+        .filter(|(index, _)| index.parse::<usize>().is_ok())
+        .map_or(key, |(_, option)| option);
+    SECRETS.contains(&key)
+}
+
+/// Mask secrets: whole value of secret variables, password and path of URLs
+pub fn redact(key: &str, value: &str) -> String {
+    if is_secret(key) {
+        return "***".to_owned();
+    }
+
+    if let Some((scheme, rest)) = value.split_once("://") {
+        let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+        let authority = match authority.rsplit_once('@') {
+            Some((user, host)) => match user.split_once(':') {
+                Some((user, _)) => format!("{user}:***@{host}"),
+                None => format!("{user}@{host}"),
+            },
+            None => authority.to_owned(),
+        };
+        // Providers and webhooks put tokens into the path
+        let path = if path.is_empty() || path == "/" {
+            path
+        } else {
+            "/***"
+        };
+        return format!("{scheme}://{authority}{path}");
+    }
+
+    // libpq key-value connection strings
+    value
+        .split(' ')
+        .map(|pair| match pair.split_once('=') {
+            Some(("password", _)) => "password=***".to_owned(),
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Effective value and source of every set variable and every variable with a default
+fn effective() -> BTreeMap<String, (String, String)> {
+    let mut config = BTreeMap::new();
+    for (key, default) in VARIABLES {
+        match env::var(key) {
+            Ok(value) => {
+                config.insert(key.to_string(), (redact(key, &value), source(key)));
+            }
+            Err(_) => {
+                if let Some(default) = default {
+                    config.insert(key.to_string(), (default.to_string(), "default".to_owned()));
+                }
+            }
+        }
+    }
+    for (key, value) in env::vars() {
+        if PREFIXES.iter().any(|prefix| key.starts_with(prefix)) {
+            let value = redact(&key, &value);
+            let source = source(&key);
+            config.insert(key, (value, source));
+        }
+    }
+    config
+}
+
+/// Print the effective configuration to stdout, as `.env` lines annotated with the source,
+/// or as one JSON object with `OUTPUT=json`
+pub fn dump() {
+    let config = effective();
+    if env::var("OUTPUT").as_deref() == Ok("json") {
+        let object = config
+            .into_iter()
+            .map(|(key, (value, source))| (key, json!({ "value": value, "source": source })))
+            .collect::<Map<String, Value>>();
+        println!("{}", Value::Object(object));
+    } else {
+        for (key, (value, source)) in config {
+            println!("{key}={value}  # {source}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_only_secret_variables() {
+        assert_eq!(redact("X_TOKEN", "abc"), "***");
+        assert_eq!(redact("ENDPOINT_2_X_TOKEN", "abc"), "***");
+        assert_eq!(redact("NOTIFY_TELEGRAM_BOT_TOKEN", "abc"), "***");
+        assert_eq!(redact("CLICKHOUSE_PASSWORD", "abc"), "***");
+        assert_eq!(redact("REDIS_PASSWORD", "abc"), "***");
+        assert_eq!(redact("X_TOKEN_FILE", "/run/token"), "/run/token");
+        assert_eq!(redact("X_TOKEN_REFRESH_SECS", "60"), "60");
+        assert_eq!(redact("TOKEN_OWNERS_CAPACITY", "1000"), "1000");
+        assert_eq!(redact("ENDPOINT_2_X_TOKEN_CMD", "vault read"), "vault read");
+    }
+
+    #[test]
+    fn redacts_urls_and_connection_strings() {
+        assert_eq!(
+            redact("ENDPOINT", "https://api.example.com/abc123"),
+            "https://api.example.com/***"
+        );
+        assert_eq!(
+            redact("POSTGRES_URL", "postgres://user:secret@db:5432/geyser"),
+            "postgres://user:***@db:5432/***"
+        );
+        assert_eq!(
+            redact("POSTGRES_URL", "host=db user=geyser password=secret"),
+            "host=db user=geyser password=***"
+        );
+        assert_eq!(
+            redact("ENDPOINT", "http://127.0.0.1:10000"),
+            "http://127.0.0.1:10000"
+        );
+    }
+}
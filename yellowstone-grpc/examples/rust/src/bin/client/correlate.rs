@@ -0,0 +1,391 @@
+//! Transaction statuses joined with full transactions by signature, `CORRELATE_TRANSACTIONS`.
+//!
+//! With `SUBSCRIBE_TRANSACTIONS` and `SUBSCRIBE_TRANSACTIONS_STATUS` every transaction arrives
+//! twice, as the full transaction and as its status. Whichever comes first waits for the other
+//! among the last `CORRELATE_CAPACITY` unmatched signatures, once both arrived a `correlated`
+//! event with the merged record and the gap between them is printed. Gap percentiles tell how
+//! differently an endpoint propagates the two streams; signatures evicted before the other
+//! half arrived point to filters which don't match the same transactions.
+
+use {
+    crate::output::OutputFormat,
+    serde::Serialize,
+    serde_json::json,
+    std::{
+        collections::{BTreeMap, HashMap, VecDeque},
+        env,
+        sync::Mutex,
+    },
+    tokio::time::Instant,
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_CAPACITY: usize = 10_000;
+/// Gaps kept for percentiles
+const MAX_GAPS: usize = 10_000;
+
+/// Half of a pair which arrived first
+#[derive(Debug)]
+enum Half {
+    Transaction {
+        slot: u64,
+        is_vote: bool,
+        index: u64,
+        fee: Option<u64>,
+        compute_units_consumed: Option<u64>,
+        filters: Vec<String>,
+    },
+    Status {
+        failed: bool,
+        filters: Vec<String>,
+    },
+}
+
+impl Half {
+    fn from_update(msg: &SubscribeUpdate) -> Option<(Vec<u8>, Self)> {
+        match msg.update_oneof.as_ref()? {
+            UpdateOneof::Transaction(update) => {
+                let tx = update.transaction.as_ref()?;
+                let meta = tx.meta.as_ref();
+                Some((
+                    tx.signature.clone(),
+                    Self::Transaction {
+                        slot: update.slot,
+                        is_vote: tx.is_vote,
+                        index: tx.index,
+                        fee: meta.map(|meta| meta.fee),
+                        compute_units_consumed: meta.and_then(|meta| meta.compute_units_consumed),
+                        filters: msg.filters.clone(),
+                    },
+                ))
+            }
+            UpdateOneof::TransactionStatus(update) => Some((
+                update.signature.clone(),
+                Self::Status {
+                    failed: update.err.is_some(),
+                    filters: msg.filters.clone(),
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    const fn is_transaction(&self) -> bool {
+        matches!(self, Self::Transaction { .. })
+    }
+}
+
+/// Event `correlated`
+#[derive(Debug, Serialize)]
+struct Correlated {
+    signature: String,
+    slot: u64,
+    is_vote: bool,
+    index: u64,
+    /// From the status
+    failed: bool,
+    /// From the transaction meta
+    fee: Option<u64>,
+    compute_units_consumed: Option<u64>,
+    transaction_filters: Vec<String>,
+    status_filters: Vec<String>,
+    /// `transaction` or `transaction_status`
+    first: &'static str,
+    /// Status receive time minus transaction receive time, negative if the status was first
+    gap_ms: f64,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    halves: HashMap<Vec<u8>, (u64, Instant, Half)>,
+    /// Signatures by arrival, the oldest is evicted first
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    matched: u64,
+    /// Signatures evicted before their status, or their transaction, arrived
+    evicted_transactions: u64,
+    evicted_statuses: u64,
+    gaps: VecDeque<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorrelateReport {
+    pub matched: u64,
+    pub pending: usize,
+    pub evicted_transactions: u64,
+    pub evicted_statuses: u64,
+    /// Percentiles of `gap_ms` over the last `MAX_GAPS` pairs
+    pub gap_p50_ms: Option<f64>,
+    pub gap_p90_ms: Option<f64>,
+    pub gap_p99_ms: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct TxCorrelator {
+    capacity: usize,
+    output: OutputFormat,
+    pending: Mutex<Pending>,
+}
+
+impl TxCorrelator {
+    /// Returns `None` unless `CORRELATE_TRANSACTIONS=true`
+    pub fn from_env(output: OutputFormat) -> anyhow::Result<Option<Self>> {
+        match env::var("CORRELATE_TRANSACTIONS").as_deref() {
+            Ok("true") => {}
+            Ok("false") | Err(_) => return Ok(None),
+            Ok(_) => anyhow::bail!("invalid CORRELATE_TRANSACTIONS, expected `true` or `false`"),
+        }
+        let capacity = match env::var("CORRELATE_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid CORRELATE_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        Ok(Some(Self::new(capacity, output)))
+    }
+
+    pub fn new(capacity: usize, output: OutputFormat) -> Self {
+        Self {
+            capacity,
+            output,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Keep the first half of a signature, print the merged record once the second arrives
+    pub fn observe(&self, msg: &SubscribeUpdate, received: Instant) {
+        let Some((signature, half)) = Half::from_update(msg) else {
+            return;
+        };
+
+        let mut pending = self.pending.lock().expect("poisoned");
+        let first = match pending.halves.remove(&signature) {
+            Some((tick, first_received, first))
+                if first.is_transaction() != half.is_transaction() =>
+            {
+                pending.order.remove(&tick);
+                (first_received, first)
+            }
+            // The same half again, e.g. after a reconnect, keeps the first receive time
+            Some(existing) => {
+                pending.halves.insert(signature, existing);
+                return;
+            }
+            None => {
+                pending.tick += 1;
+                let tick = pending.tick;
+                pending.order.insert(tick, signature.clone());
+                pending.halves.insert(signature, (tick, received, half));
+                while pending.halves.len() > self.capacity {
+                    let Some((_, oldest)) = pending.order.pop_first() else {
+                        break;
+                    };
+                    match pending.halves.remove(&oldest) {
+                        Some((_, _, half)) if half.is_transaction() => {
+                            pending.evicted_transactions += 1
+                        }
+                        Some(_) => pending.evicted_statuses += 1,
+                        None => {}
+                    }
+                }
+                return;
+            }
+        };
+
+        let (first_received, first) = first;
+        let first_kind = if first.is_transaction() {
+            "transaction"
+        } else {
+            "transaction_status"
+        };
+        let (transaction, status, gap) = if first.is_transaction() {
+            (
+                first,
+                half,
+                received.duration_since(first_received).as_secs_f64(),
+            )
+        } else {
+            (
+                half,
+                first,
+                -received.duration_since(first_received).as_secs_f64(),
+            )
+        };
+        let (
+            Half::Transaction {
+                slot,
+                is_vote,
+                index,
+                fee,
+                compute_units_consumed,
+                filters: transaction_filters,
+            },
+            Half::Status {
+                failed,
+                filters: status_filters,
+            },
+        ) = (transaction, status)
+        else {
+            return;
+        };
+        let record = Correlated {
+            signature: bs58::encode(&signature).into_string(),
+            slot,
+            is_vote,
+            index,
+            failed,
+            fee,
+            compute_units_consumed,
+            transaction_filters,
+            status_filters,
+            first: first_kind,
+            gap_ms: gap * 1_000.0,
+        };
+        pending.matched += 1;
+        pending.gaps.push_back(record.gap_ms);
+        while pending.gaps.len() > MAX_GAPS {
+            pending.gaps.pop_front();
+        }
+        drop(pending);
+        self.output.print_event("correlated", &json!(record));
+    }
+
+    pub fn report(&self) -> CorrelateReport {
+        let pending = self.pending.lock().expect("poisoned");
+        let mut gaps = pending.gaps.iter().copied().collect::<Vec<_>>();
+        gaps.sort_unstable_by(f64::total_cmp);
+        let percentile = |p: usize| -> Option<f64> {
+            let index = (gaps.len() * p / 100).min(gaps.len().checked_sub(1)?);
+            gaps.get(index).copied()
+        };
+        CorrelateReport {
+            matched: pending.matched,
+            pending: pending.halves.len(),
+            evicted_transactions: pending.evicted_transactions,
+            evicted_statuses: pending.evicted_statuses,
+            gap_p50_ms: percentile(50),
+            gap_p90_ms: percentile(90),
+            gap_p99_ms: percentile(99),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::time::Duration,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeUpdateSlot, SubscribeUpdateTransaction, SubscribeUpdateTransactionInfo,
+            SubscribeUpdateTransactionStatus,
+        },
+    };
+
+    fn transaction(signature: u8) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["transactions".to_owned()],
+            update_oneof: Some(UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                transaction: Some(SubscribeUpdateTransactionInfo {
+                    signature: vec![signature; 64],
+                    ..Default::default()
+                }),
+                slot: 10,
+            })),
+        }
+    }
+
+    fn status(signature: u8) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["statuses".to_owned()],
+            update_oneof: Some(UpdateOneof::TransactionStatus(
+                SubscribeUpdateTransactionStatus {
+                    slot: 10,
+                    signature: vec![signature; 64],
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Matched, pending, evicted transactions and evicted statuses
+    fn counts(correlator: &TxCorrelator) -> (u64, usize, u64, u64) {
+        let report = correlator.report();
+        (
+            report.matched,
+            report.pending,
+            report.evicted_transactions,
+            report.evicted_statuses,
+        )
+    }
+
+    #[test]
+    fn gap_is_negative_when_status_is_first() {
+        let correlator = TxCorrelator::new(10, OutputFormat::Text);
+        let start = Instant::now();
+        correlator.observe(&transaction(1), start);
+        correlator.observe(&status(1), start + ms(30));
+        correlator.observe(&status(2), start);
+        correlator.observe(&transaction(2), start + ms(10));
+        correlator.observe(&status(3), start);
+
+        assert_eq!(counts(&correlator), (2, 1, 0, 0));
+        let report = correlator.report();
+        assert_eq!(report.gap_p50_ms, Some(30.0));
+        assert_eq!(report.gap_p99_ms, Some(30.0));
+        assert_eq!(correlator.pending.lock().unwrap().gaps, [30.0, -10.0]);
+    }
+
+    #[test]
+    fn repeated_half_keeps_first_receive_time() {
+        let correlator = TxCorrelator::new(10, OutputFormat::Text);
+        let start = Instant::now();
+        correlator.observe(&transaction(1), start);
+        correlator.observe(&transaction(1), start + ms(20));
+        correlator.observe(&status(1), start + ms(50));
+        assert_eq!(counts(&correlator), (1, 0, 0, 0));
+        assert_eq!(correlator.report().gap_p50_ms, Some(50.0));
+    }
+
+    #[test]
+    fn oldest_unmatched_halves_are_evicted() {
+        let correlator = TxCorrelator::new(2, OutputFormat::Text);
+        let start = Instant::now();
+        correlator.observe(&transaction(1), start);
+        // Matched signatures leave the eviction order
+        correlator.observe(&transaction(9), start);
+        correlator.observe(&status(9), start);
+        correlator.observe(&status(2), start);
+        correlator.observe(&transaction(3), start);
+        assert_eq!(counts(&correlator), (1, 2, 1, 0));
+        correlator.observe(&status(4), start);
+        assert_eq!(counts(&correlator), (1, 2, 1, 1));
+
+        // The other half of an evicted signature waits as a new one
+        correlator.observe(&status(1), start);
+        assert_eq!(counts(&correlator), (1, 2, 2, 1));
+        correlator.observe(&transaction(4), start);
+        assert_eq!(counts(&correlator), (2, 1, 2, 1));
+    }
+
+    #[test]
+    fn other_updates_are_ignored() {
+        let correlator = TxCorrelator::new(10, OutputFormat::Text);
+        let slot = SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot: 10,
+                parent: None,
+                status: 0,
+            })),
+        };
+        correlator.observe(&slot, Instant::now());
+        assert_eq!(counts(&correlator), (0, 0, 0, 0));
+        assert_eq!(correlator.report().gap_p50_ms, None);
+    }
+}
@@ -0,0 +1,695 @@
+//! Live terminal view of a subscription, `ACTION=Dashboard`.
+//!
+//! Updates are taken from the [event bus](crate::events) by one task per update type, the
+//! terminal is drawn on a blocking thread every `REFRESH_INTERVAL`. The view never slows
+//! down the pipeline: events it falls behind on are skipped and counted as lagged. Filters switched off in the view are removed
+//! from the live subscription and stay off after reconnects and filter reloads.
+
+use {
+    crate::{
+        events::{self, EventBus},
+        forks::SlotRollback,
+        gaps::Gap,
+        reconnects::ReconnectHistory,
+    },
+    ratatui::{
+        crossterm::event::{self, Event, KeyCode, KeyEventKind},
+        layout::{Constraint, Layout},
+        style::{Modifier, Style},
+        widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table},
+        Frame,
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+        future::{self, Future},
+        ops::Deref,
+        sync::{Arc, Mutex, MutexGuard},
+        time::{Duration, Instant},
+    },
+    tokio::sync::{
+        broadcast::{self, error::RecvError},
+        watch, Notify,
+    },
+    yellowstone_grpc_proto::prelude::{
+        CommitmentLevel, SubscribeRequest, SubscribeUpdateAccount, SubscribeUpdateBlock,
+        SubscribeUpdateBlockMeta, SubscribeUpdateEntry, SubscribeUpdateSlot,
+        SubscribeUpdateTransaction, SubscribeUpdateTransactionStatus,
+    },
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// Messages per second are averaged over this window
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+const TOP_ACCOUNTS: usize = 10;
+const RECENT_TRANSACTIONS: usize = 20;
+const RECENT_RECONNECTS: usize = 10;
+/// Update counts are halved when more accounts are tracked, rarely updated ones drop out
+const MAX_ACCOUNTS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct RecentTransaction {
+    slot: u64,
+    signature: String,
+    failed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    totals: BTreeMap<&'static str, u64>,
+    /// Updates by matched filter
+    filters: HashMap<FilterName, u64>,
+    /// Newest slot by commitment of slot updates
+    slots: BTreeMap<&'static str, u64>,
+    accounts: HashMap<String, u64>,
+    transactions: VecDeque<RecentTransaction>,
+    rollbacks: u64,
+    gaps: u64,
+    /// Events skipped because the dashboard fell behind the bus
+    lagged: u64,
+}
+
+/// Filter of the subscription request, `kind` is the request field
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct FilterName {
+    kind: &'static str,
+    name: String,
+}
+
+#[derive(Debug, Default)]
+struct Filters {
+    names: Vec<FilterName>,
+    disabled: BTreeSet<FilterName>,
+    commitment: Option<CommitmentLevel>,
+}
+
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    counters: Mutex<Counters>,
+    filters: Mutex<Filters>,
+    filters_changed: Notify,
+}
+
+impl Dashboard {
+    fn counters(&self) -> MutexGuard<'_, Counters> {
+        self.counters.lock().expect("poisoned")
+    }
+
+    fn observe_account(&self, event: &events::Event<SubscribeUpdateAccount>) {
+        let mut counters = self.counters();
+        counters.count("account", "accounts", &event.filters);
+        if let Some(account) = event.update.account.as_ref() {
+            let pubkey = bs58::encode(&account.pubkey).into_string();
+            *counters.accounts.entry(pubkey).or_default() += 1;
+            if counters.accounts.len() > MAX_ACCOUNTS {
+                counters.accounts.retain(|_, count| {
+                    *count /= 2;
+                    *count > 0
+                });
+            }
+        }
+    }
+
+    fn observe_slot(&self, event: &events::Event<SubscribeUpdateSlot>) {
+        let mut counters = self.counters();
+        counters.count("slot", "slots", &event.filters);
+        let commitment = CommitmentLevel::try_from(event.update.status)
+            .map_or("unknown", |commitment| commitment.as_str_name());
+        let newest = counters.slots.entry(commitment).or_default();
+        *newest = (*newest).max(event.update.slot);
+    }
+
+    fn observe_transaction(&self, event: &events::Event<SubscribeUpdateTransaction>) {
+        let mut counters = self.counters();
+        counters.count("transaction", "transactions", &event.filters);
+        if let Some(tx) = event.update.transaction.as_ref() {
+            counters.push_transaction(RecentTransaction {
+                slot: event.update.slot,
+                signature: bs58::encode(&tx.signature).into_string(),
+                failed: tx.meta.as_ref().is_some_and(|meta| meta.err.is_some()),
+            });
+        }
+    }
+
+    fn observe_transaction_status(&self, event: &events::Event<SubscribeUpdateTransactionStatus>) {
+        let mut counters = self.counters();
+        counters.count("transaction_status", "transactions_status", &event.filters);
+        counters.push_transaction(RecentTransaction {
+            slot: event.update.slot,
+            signature: bs58::encode(&event.update.signature).into_string(),
+            failed: event.update.err.is_some(),
+        });
+    }
+
+    fn observe_block(&self, event: &events::Event<SubscribeUpdateBlock>) {
+        self.counters().count("block", "blocks", &event.filters);
+    }
+
+    fn observe_block_meta(&self, event: &events::Event<SubscribeUpdateBlockMeta>) {
+        self.counters()
+            .count("block_meta", "blocks_meta", &event.filters);
+    }
+
+    fn observe_entry(&self, event: &events::Event<SubscribeUpdateEntry>) {
+        self.counters().count("entry", "entry", &event.filters);
+    }
+
+    fn observe_rollback(&self, _rollback: &SlotRollback) {
+        self.counters().rollbacks += 1;
+    }
+
+    fn observe_gap(&self, _gap: &Gap) {
+        self.counters().gaps += 1;
+    }
+
+    /// `request` without filters switched off in the view, remembers filter names
+    pub fn apply(&self, request: &SubscribeRequest) -> SubscribeRequest {
+        let mut filters = self.filters.lock().expect("poisoned");
+        let mut names = vec![];
+        let mut request = request.clone();
+        retain_enabled(
+            "accounts",
+            &mut request.accounts,
+            &mut names,
+            &filters.disabled,
+        );
+        retain_enabled("slots", &mut request.slots, &mut names, &filters.disabled);
+        retain_enabled(
+            "transactions",
+            &mut request.transactions,
+            &mut names,
+            &filters.disabled,
+        );
+        retain_enabled(
+            "transactions_status",
+            &mut request.transactions_status,
+            &mut names,
+            &filters.disabled,
+        );
+        retain_enabled("blocks", &mut request.blocks, &mut names, &filters.disabled);
+        retain_enabled(
+            "blocks_meta",
+            &mut request.blocks_meta,
+            &mut names,
+            &filters.disabled,
+        );
+        retain_enabled("entry", &mut request.entry, &mut names, &filters.disabled);
+        names.sort();
+        filters.names = names;
+        filters.commitment = request
+            .commitment
+            .and_then(|commitment| CommitmentLevel::try_from(commitment).ok());
+        request
+    }
+
+    /// Resolves when filters were switched in the view, never without dashboard
+    pub async fn changed(dashboard: Option<&Self>) {
+        match dashboard {
+            Some(dashboard) => dashboard.filters_changed.notified().await,
+            None => future::pending().await,
+        }
+    }
+
+    fn toggle(&self, index: usize) {
+        let mut filters = self.filters.lock().expect("poisoned");
+        let Some(filter) = filters.names.get(index).cloned() else {
+            return;
+        };
+        if !filters.disabled.remove(&filter) {
+            filters.disabled.insert(filter);
+        }
+        self.filters_changed.notify_one();
+    }
+}
+
+/// Collect names of `filters` and remove disabled ones
+fn retain_enabled<T>(
+    kind: &'static str,
+    filters: &mut HashMap<String, T>,
+    names: &mut Vec<FilterName>,
+    disabled: &BTreeSet<FilterName>,
+) {
+    filters.retain(|name, _| {
+        let filter = FilterName {
+            kind,
+            name: name.clone(),
+        };
+        let enabled = !disabled.contains(&filter);
+        names.push(filter);
+        enabled
+    });
+}
+
+impl Counters {
+    /// Count an update of `kind` which matched `filters` of the request field `field`
+    fn count(&mut self, kind: &'static str, field: &'static str, filters: &[String]) {
+        *self.totals.entry(kind).or_default() += 1;
+        for name in filters {
+            let filter = FilterName {
+                kind: field,
+                name: name.clone(),
+            };
+            *self.filters.entry(filter).or_default() += 1;
+        }
+    }
+
+    fn push_transaction(&mut self, tx: RecentTransaction) {
+        self.transactions.push_front(tx);
+        self.transactions.truncate(RECENT_TRANSACTIONS);
+    }
+}
+
+/// Observe events of one channel until the bus is dropped
+async fn observe<T: Clone + Deref>(
+    dashboard: &Dashboard,
+    mut events: broadcast::Receiver<T>,
+    observe: fn(&Dashboard, &T::Target),
+) {
+    loop {
+        match events.recv().await {
+            Ok(event) => observe(dashboard, &event),
+            Err(RecvError::Lagged(skipped)) => {
+                dashboard.counters().lagged += skipped;
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Feed the dashboard from the event bus, subscribes right away so no update published
+/// after the call is missed
+pub fn consume(
+    dashboard: Arc<Dashboard>,
+    events: &EventBus,
+) -> impl Future<Output = ()> + Send + 'static {
+    let accounts = events.accounts();
+    let slots = events.slots();
+    let transactions = events.transactions();
+    let transaction_statuses = events.transaction_statuses();
+    let blocks = events.blocks();
+    let blocks_meta = events.blocks_meta();
+    let entries = events.entries();
+    let rollbacks = events.rollbacks();
+    let gaps = events.gaps();
+    async move {
+        let dashboard = dashboard.as_ref();
+        tokio::join!(
+            observe(dashboard, accounts, Dashboard::observe_account),
+            observe(dashboard, slots, Dashboard::observe_slot),
+            observe(dashboard, transactions, Dashboard::observe_transaction),
+            observe(
+                dashboard,
+                transaction_statuses,
+                Dashboard::observe_transaction_status
+            ),
+            observe(dashboard, blocks, Dashboard::observe_block),
+            observe(dashboard, blocks_meta, Dashboard::observe_block_meta),
+            observe(dashboard, entries, Dashboard::observe_entry),
+            observe(dashboard, rollbacks, Dashboard::observe_rollback),
+            observe(dashboard, gaps, Dashboard::observe_gap),
+        );
+    }
+}
+
+/// Values shown in one frame
+struct Snapshot {
+    rates: Vec<(&'static str, f64, u64)>,
+    slots: BTreeMap<&'static str, u64>,
+    latest_slot: Option<SubscribeUpdateSlot>,
+    rollbacks: u64,
+    gaps: u64,
+    lagged: u64,
+    commitment: Option<CommitmentLevel>,
+    accounts: Vec<(String, u64)>,
+    transactions: Vec<RecentTransaction>,
+    /// Name, enabled and received updates
+    filters: Vec<(String, bool, u64)>,
+}
+
+/// Terminal state kept between frames
+struct View {
+    dashboard: Arc<Dashboard>,
+    reconnects: Arc<ReconnectHistory>,
+    latest_slot: watch::Receiver<Option<SubscribeUpdateSlot>>,
+    /// Totals by update kind over the last `RATE_WINDOW`
+    samples: VecDeque<(Instant, BTreeMap<&'static str, u64>)>,
+    snapshot: Option<Snapshot>,
+    paused: bool,
+    selected: ListState,
+}
+
+impl View {
+    fn refresh(&mut self) {
+        let now = Instant::now();
+        let counters = self.dashboard.counters();
+        let totals = counters.totals.clone();
+        let mut accounts = counters
+            .accounts
+            .iter()
+            .map(|(pubkey, count)| (pubkey.clone(), *count))
+            .collect::<Vec<_>>();
+        accounts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        accounts.truncate(TOP_ACCOUNTS);
+
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        let rates = totals
+            .iter()
+            .map(|(kind, total)| {
+                let rate = match self.samples.front() {
+                    Some((time, previous)) if now > *time => {
+                        let delta = total - previous.get(kind).copied().unwrap_or(0);
+                        delta as f64 / now.duration_since(*time).as_secs_f64()
+                    }
+                    _ => 0.0,
+                };
+                (*kind, rate, *total)
+            })
+            .collect();
+        self.samples.push_back((now, totals));
+
+        let filters = self.dashboard.filters.lock().expect("poisoned");
+        self.snapshot = Some(Snapshot {
+            rates,
+            slots: counters.slots.clone(),
+            latest_slot: self.latest_slot.borrow().clone(),
+            rollbacks: counters.rollbacks,
+            gaps: counters.gaps,
+            lagged: counters.lagged,
+            commitment: filters.commitment,
+            accounts,
+            transactions: counters.transactions.iter().cloned().collect(),
+            filters: filters
+                .names
+                .iter()
+                .map(|filter| {
+                    (
+                        format!("{}/{}", filter.kind, filter.name),
+                        !filters.disabled.contains(filter),
+                        counters.filters.get(filter).copied().unwrap_or(0),
+                    )
+                })
+                .collect(),
+        });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let Some(snapshot) = self.snapshot.as_ref() else {
+            return;
+        };
+        let status = self.reconnects.snapshot();
+        let [header, top, bottom, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Percentage(50),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let slots = snapshot
+            .slots
+            .iter()
+            .map(|(commitment, slot)| format!("{} {slot}", commitment.to_lowercase()))
+            .collect::<Vec<_>>();
+        let latest_slot = snapshot.latest_slot.as_ref().map_or_else(
+            || "none".to_owned(),
+            |slot| {
+                let status = CommitmentLevel::try_from(slot.status)
+                    .map_or("unknown", |commitment| commitment.as_str_name());
+                format!("{} {}", slot.slot, status.to_lowercase())
+            },
+        );
+        let header_text = format!(
+            "{} {} | commitment {} | latest slot {latest_slot} | slots: {} | rollbacks {} | gaps {}{}{}",
+            status.endpoint,
+            status.connected_since.as_deref().map_or_else(
+                || "disconnected".to_owned(),
+                |since| format!("since {since}")
+            ),
+            snapshot.commitment.map_or_else(
+                || "default".to_owned(),
+                |commitment| commitment.as_str_name().to_lowercase()
+            ),
+            if slots.is_empty() {
+                "none".to_owned()
+            } else {
+                slots.join(", ")
+            },
+            snapshot.rollbacks,
+            snapshot.gaps,
+            if snapshot.lagged > 0 {
+                format!(" | lagged {}", snapshot.lagged)
+            } else {
+                String::new()
+            },
+            if self.paused { " | PAUSED" } else { "" },
+        );
+        frame.render_widget(Paragraph::new(header_text), header);
+
+        let [rates_area, accounts_area, filters_area] = Layout::horizontal([
+            Constraint::Length(42),
+            Constraint::Fill(1),
+            Constraint::Length(36),
+        ])
+        .areas(top);
+        let rates = Table::new(
+            snapshot.rates.iter().map(|(kind, rate, total)| {
+                Row::new(vec![
+                    kind.to_string(),
+                    format!("{rate:.1}"),
+                    total.to_string(),
+                ])
+            }),
+            [
+                Constraint::Length(20),
+                Constraint::Length(10),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(vec!["update", "msg/s", "total"]).style(bold()))
+        .block(Block::bordered().title(" Messages "));
+        frame.render_widget(rates, rates_area);
+
+        let accounts = Table::new(
+            snapshot
+                .accounts
+                .iter()
+                .map(|(pubkey, count)| Row::new(vec![pubkey.clone(), count.to_string()])),
+            [Constraint::Length(45), Constraint::Fill(1)],
+        )
+        .header(Row::new(vec!["pubkey", "updates"]).style(bold()))
+        .block(Block::bordered().title(" Top accounts "));
+        frame.render_widget(accounts, accounts_area);
+
+        let filters = List::new(snapshot.filters.iter().map(|(name, enabled, count)| {
+            ListItem::new(format!(
+                "[{}] {name} {count}",
+                if *enabled { "x" } else { " " }
+            ))
+        }))
+        .highlight_symbol("> ")
+        .highlight_style(bold())
+        .block(Block::bordered().title(" Filters "));
+        frame.render_stateful_widget(filters, filters_area, &mut self.selected);
+
+        let [transactions_area, reconnects_area] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Fill(1)]).areas(bottom);
+        let transactions = Table::new(
+            snapshot.transactions.iter().map(|tx| {
+                Row::new(vec![
+                    tx.slot.to_string(),
+                    tx.signature.clone(),
+                    if tx.failed { "failed" } else { "ok" }.to_owned(),
+                ])
+            }),
+            [
+                Constraint::Length(11),
+                Constraint::Fill(1),
+                Constraint::Length(6),
+            ],
+        )
+        .header(Row::new(vec!["slot", "signature", "status"]).style(bold()))
+        .block(Block::bordered().title(" Recent transactions "));
+        frame.render_widget(transactions, transactions_area);
+
+        let reconnects = Table::new(
+            status
+                .history
+                .iter()
+                .rev()
+                .take(RECENT_RECONNECTS)
+                .map(|end| {
+                    Row::new(vec![
+                        end.time.get(11..19).unwrap_or(&end.time).to_owned(),
+                        end.endpoint.clone(),
+                        end.reason.name().to_owned(),
+                        end.detail.clone(),
+                    ])
+                }),
+            [
+                Constraint::Length(8),
+                Constraint::Length(11),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(vec!["time", "endpoint", "reason", "detail"]).style(bold()))
+        .block(Block::bordered().title(" Reconnects "));
+        frame.render_widget(reconnects, reconnects_area);
+
+        frame.render_widget(
+            Paragraph::new("q quit | p pause | up/down select filter | space switch filter"),
+            footer,
+        );
+    }
+
+    /// Returns `false` to quit
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        let filters = self
+            .snapshot
+            .as_ref()
+            .map_or(0, |snapshot| snapshot.filters.len());
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('p') => self.paused = !self.paused,
+            KeyCode::Down if filters > 0 => {
+                let index = self.selected.selected().map_or(0, |index| index + 1);
+                self.selected.select(Some(index.min(filters - 1)));
+            }
+            KeyCode::Up if filters > 0 => {
+                let index = self.selected.selected().unwrap_or(0);
+                self.selected.select(Some(index.saturating_sub(1)));
+            }
+            KeyCode::Char(' ') | KeyCode::Enter => {
+                if let Some(index) = self.selected.selected() {
+                    self.dashboard.toggle(index);
+                    // Show the switched filter even when paused
+                    if let Some(snapshot) = self.snapshot.as_mut() {
+                        if let Some((_, enabled, _)) = snapshot.filters.get_mut(index) {
+                            *enabled = !*enabled;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+fn bold() -> Style {
+    Style::default().add_modifier(Modifier::BOLD)
+}
+
+/// Draw the dashboard until `q` is pressed or the client shuts down, `q` requests shutdown.
+/// Blocks the thread, run with `spawn_blocking`.
+pub fn run(
+    dashboard: Arc<Dashboard>,
+    reconnects: Arc<ReconnectHistory>,
+    latest_slot: watch::Receiver<Option<SubscribeUpdateSlot>>,
+    shutdown: Arc<watch::Sender<bool>>,
+) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut view = View {
+        dashboard,
+        reconnects,
+        latest_slot,
+        samples: VecDeque::new(),
+        snapshot: None,
+        paused: false,
+        selected: ListState::default(),
+    };
+
+    let result = loop {
+        if *shutdown.borrow() {
+            break Ok(());
+        }
+        if !view.paused || view.snapshot.is_none() {
+            view.refresh();
+        }
+        if let Err(error) = terminal.draw(|frame| view.draw(frame)) {
+            break Err(error.into());
+        }
+        match event::poll(REFRESH_INTERVAL).and_then(|ready| ready.then(event::read).transpose()) {
+            Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                if !view.handle_key(key.code) {
+                    shutdown.send_replace(true);
+                }
+            }
+            Ok(_) => {}
+            Err(error) => break Err(error.into()),
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateAccountInfo,
+        },
+    };
+
+    fn update(filters: &[&str], update: UpdateOneof) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: filters.iter().map(|name| name.to_string()).collect(),
+            update_oneof: Some(update),
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_events_of_the_bus() {
+        let events = EventBus::from_env().unwrap();
+        let dashboard = Arc::new(Dashboard::default());
+        let consumer = tokio::spawn(consume(Arc::clone(&dashboard), &events));
+
+        events.publish(&update(
+            &["slots"],
+            UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot: 10,
+                parent: Some(9),
+                status: CommitmentLevel::Confirmed as i32,
+            }),
+        ));
+        for _ in 0..2 {
+            events.publish(&update(
+                &["pools", "vaults"],
+                UpdateOneof::Account(SubscribeUpdateAccount {
+                    account: Some(SubscribeUpdateAccountInfo {
+                        pubkey: vec![1; 32],
+                        ..Default::default()
+                    }),
+                    slot: 10,
+                    is_startup: false,
+                }),
+            ));
+        }
+        // The consumer ends when the bus is dropped, after all published events
+        drop(events);
+        consumer.await.unwrap();
+
+        let counters = dashboard.counters();
+        assert_eq!(counters.totals.get("slot"), Some(&1));
+        assert_eq!(counters.totals.get("account"), Some(&2));
+        assert_eq!(counters.slots.get("CONFIRMED"), Some(&10));
+        assert_eq!(counters.accounts.values().sum::<u64>(), 2);
+        let filter = |kind, name: &str| FilterName {
+            kind,
+            name: name.to_owned(),
+        };
+        assert_eq!(counters.filters.get(&filter("slots", "slots")), Some(&1));
+        assert_eq!(counters.filters.get(&filter("accounts", "pools")), Some(&2));
+        assert_eq!(
+            counters.filters.get(&filter("accounts", "vaults")),
+            Some(&2)
+        );
+        assert_eq!(counters.lagged, 0);
+    }
+}
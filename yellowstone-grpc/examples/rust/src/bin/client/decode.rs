@@ -0,0 +1,184 @@
+//! Decoding of logged updates on a pool of blocking threads, `DECODE_WORKERS`.
+//!
+//! A pretty transaction is converted with `create_tx_with_meta`, encoded to base64 and
+//! serialized to JSON for every logged update, which under full-block subscriptions costs more
+//! CPU than everything else the client does. Processing workers are tokio tasks sharing the
+//! runtime threads with the stream reader, so decoding there slows reading down. With
+//! `DECODE_WORKERS` the workers only keep per-update state (tags, account diffs) and pass
+//! decoding and formatting of log lines to that many blocking threads.
+//!
+//! `DECODE_ORDER=ordered` (default) logs lines in the order of updates, a slow update holds
+//! back the lines after it; `unordered` logs every line as soon as it is ready. At most
+//! `DECODE_QUEUE_SIZE` updates wait for a thread, newer ones are not logged while it is full.
+//! Sinks are not affected, they take updates on the workers as before.
+
+use {
+    futures::stream::{FuturesOrdered, FuturesUnordered, StreamExt},
+    log::{error, warn},
+    std::{
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::{mpsc, oneshot},
+        task::{self, JoinError, JoinHandle},
+        time::timeout,
+    },
+};
+
+const DEFAULT_QUEUE_SIZE: usize = 10_000;
+
+/// Decode an update and format its log line, `None` if there is nothing to log
+pub type DecodeJob = Box<dyn FnOnce() -> Option<String> + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeOrder {
+    Ordered,
+    Unordered,
+}
+
+/// Jobs running on blocking threads
+enum Running {
+    Ordered(FuturesOrdered<JoinHandle<Option<String>>>),
+    Unordered(FuturesUnordered<JoinHandle<Option<String>>>),
+}
+
+impl Running {
+    fn new(order: DecodeOrder) -> Self {
+        match order {
+            DecodeOrder::Ordered => Self::Ordered(FuturesOrdered::new()),
+            DecodeOrder::Unordered => Self::Unordered(FuturesUnordered::new()),
+        }
+    }
+
+    fn push(&mut self, job: DecodeJob) {
+        let handle = task::spawn_blocking(job);
+        match self {
+            Self::Ordered(running) => running.push_back(handle),
+            Self::Unordered(running) => running.push(handle),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Ordered(running) => running.len(),
+            Self::Unordered(running) => running.len(),
+        }
+    }
+
+    async fn next(&mut self) -> Option<Result<Option<String>, JoinError>> {
+        match self {
+            Self::Ordered(running) => running.next().await,
+            Self::Unordered(running) => running.next().await,
+        }
+    }
+}
+
+pub struct DecodePool {
+    workers: usize,
+    jobs: mpsc::Sender<DecodeJob>,
+    close: Mutex<Option<oneshot::Sender<()>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    dropped: AtomicU64,
+}
+
+impl DecodePool {
+    /// Returns `None` if `DECODE_WORKERS` is not set, lines are passed to `log` once decoded
+    pub fn from_env(log: fn(&str)) -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("DECODE_WORKERS") else {
+            return Ok(None);
+        };
+        let workers = value
+            .parse::<usize>()
+            .ok()
+            .filter(|workers| *workers > 0)
+            .ok_or_else(|| anyhow::anyhow!("invalid DECODE_WORKERS"))?;
+        let order = match env::var("DECODE_ORDER").as_deref() {
+            Ok("ordered") | Err(_) => DecodeOrder::Ordered,
+            Ok("unordered") => DecodeOrder::Unordered,
+            Ok(_) => anyhow::bail!("invalid DECODE_ORDER, expected `ordered` or `unordered`"),
+        };
+        let queue_size = match env::var("DECODE_QUEUE_SIZE") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid DECODE_QUEUE_SIZE"))?,
+            Err(_) => DEFAULT_QUEUE_SIZE,
+        };
+        let (jobs, rx) = mpsc::channel(queue_size);
+        let (close, closed) = oneshot::channel();
+        let task = tokio::spawn(dispatch(rx, closed, workers, order, log));
+        Ok(Some(Self {
+            workers,
+            jobs,
+            close: Mutex::new(Some(close)),
+            task: Mutex::new(Some(task)),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    pub const fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Queue the job, it is dropped if the queue is full or the pool is closed
+    pub fn submit(&self, job: DecodeJob) {
+        if self.jobs.try_send(job).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 1_000 == 1 {
+                warn!("decode: queue is full, {dropped} updates not logged in total");
+            }
+        }
+    }
+
+    /// Updates not logged because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stop taking jobs and wait up to `wait` until queued ones are logged
+    pub async fn close(&self, wait: Duration) {
+        if let Some(close) = self.close.lock().expect("poisoned").take() {
+            let _ = close.send(());
+        }
+        let task = self.task.lock().expect("poisoned").take();
+        if let Some(task) = task {
+            if timeout(wait, task).await.is_err() {
+                warn!("decode: queued updates were not logged before exit");
+            }
+        }
+    }
+}
+
+/// Run up to `workers` jobs at a time and pass their lines to `log`
+async fn dispatch(
+    mut jobs: mpsc::Receiver<DecodeJob>,
+    mut closed: oneshot::Receiver<()>,
+    workers: usize,
+    order: DecodeOrder,
+    log: fn(&str),
+) {
+    let mut running = Running::new(order);
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            _ = &mut closed, if !closing => {
+                // Queued jobs are still received, new ones are refused
+                jobs.close();
+                closing = true;
+            }
+            Some(job) = jobs.recv(), if running.len() < workers => running.push(job),
+            Some(result) = running.next(), if running.len() > 0 => match result {
+                Ok(Some(line)) => log(&line),
+                Ok(None) => {}
+                Err(error) => error!("decode: worker failed: {error}"),
+            },
+            else => break,
+        }
+    }
+}
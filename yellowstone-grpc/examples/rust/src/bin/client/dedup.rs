@@ -0,0 +1,241 @@
+//! Drop repeated account and transaction updates before they reach sinks.
+//!
+//! After a reconnect, or when several subscriptions overlap, the same update can be received
+//! more than once. Accounts are identified by pubkey and write version, transactions and
+//! transaction statuses by signature and slot. Only the last `DEDUP_CAPACITY` keys are kept.
+
+use {
+    std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DedupKey {
+    Account { pubkey: Vec<u8>, write_version: u64 },
+    Transaction { signature: Vec<u8>, slot: u64 },
+    TransactionStatus { signature: Vec<u8>, slot: u64 },
+}
+
+impl DedupKey {
+    fn from_update(msg: &SubscribeUpdate) -> Option<Self> {
+        match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                Some(Self::Account {
+                    pubkey: account.pubkey.clone(),
+                    write_version: account.write_version,
+                })
+            }
+            UpdateOneof::Transaction(update) => Some(Self::Transaction {
+                signature: update.transaction.as_ref()?.signature.clone(),
+                slot: update.slot,
+            }),
+            UpdateOneof::TransactionStatus(update) => Some(Self::TransactionStatus {
+                signature: update.signature.clone(),
+                slot: update.slot,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Keys ordered by last use, the least recently seen key is evicted first
+#[derive(Debug, Default)]
+struct Lru {
+    keys: HashMap<DedupKey, u64>,
+    order: BTreeMap<u64, DedupKey>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub struct DedupCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+    duplicates: AtomicU64,
+}
+
+impl DedupCache {
+    /// Returns `None` if `DEDUP_CAPACITY` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("DEDUP_CAPACITY") else {
+            return Ok(None);
+        };
+        let capacity = value
+            .parse::<usize>()
+            .ok()
+            .filter(|capacity| *capacity > 0)
+            .ok_or_else(|| anyhow::anyhow!("invalid DEDUP_CAPACITY"))?;
+        Ok(Some(Self {
+            capacity,
+            lru: Mutex::default(),
+            duplicates: AtomicU64::new(0),
+        }))
+    }
+
+    /// Remember the update, returns `true` if it was already seen
+    pub fn is_duplicate(&self, msg: &SubscribeUpdate) -> bool {
+        let Some(key) = DedupKey::from_update(msg) else {
+            return false;
+        };
+        if self.insert(key) {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// Remember a transaction, or a transaction status, processed before a restart
+    pub fn seed_signature(&self, signature: Vec<u8>, slot: u64, status: bool) {
+        self.insert(if status {
+            DedupKey::TransactionStatus { signature, slot }
+        } else {
+            DedupKey::Transaction { signature, slot }
+        });
+    }
+
+    /// Returns `true` if the key was already known
+    fn insert(&self, key: DedupKey) -> bool {
+        let mut lru = self.lru.lock().expect("poisoned");
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some(previous) = lru.keys.insert(key.clone(), tick) {
+            lru.order.remove(&previous);
+            lru.order.insert(tick, key);
+            return true;
+        }
+
+        lru.order.insert(tick, key);
+        while lru.keys.len() > self.capacity {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            lru.keys.remove(&oldest);
+        }
+        false
+    }
+
+    /// Number of dropped duplicates
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateSlot,
+            SubscribeUpdateTransaction, SubscribeUpdateTransactionInfo,
+            SubscribeUpdateTransactionStatus,
+        },
+    };
+
+    fn cache(capacity: usize) -> DedupCache {
+        DedupCache {
+            capacity,
+            lru: Mutex::default(),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    fn account(pubkey: u8, write_version: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: vec![pubkey; 32],
+                    write_version,
+                    ..Default::default()
+                }),
+                slot: 1,
+                is_startup: false,
+            })),
+        }
+    }
+
+    fn transaction(signature: u8, slot: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                transaction: Some(SubscribeUpdateTransactionInfo {
+                    signature: vec![signature; 64],
+                    ..Default::default()
+                }),
+                slot,
+            })),
+        }
+    }
+
+    fn status(signature: u8, slot: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::TransactionStatus(
+                SubscribeUpdateTransactionStatus {
+                    slot,
+                    signature: vec![signature; 64],
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    #[test]
+    fn drops_repeated_updates() {
+        let cache = cache(100);
+        assert!(!cache.is_duplicate(&account(1, 5)));
+        assert!(cache.is_duplicate(&account(1, 5)));
+        assert!(!cache.is_duplicate(&account(1, 6)));
+        assert!(!cache.is_duplicate(&account(2, 5)));
+
+        assert!(!cache.is_duplicate(&transaction(1, 10)));
+        assert!(cache.is_duplicate(&transaction(1, 10)));
+        assert!(!cache.is_duplicate(&transaction(1, 11)));
+        // A status is not a duplicate of the transaction with the same signature
+        assert!(!cache.is_duplicate(&status(1, 10)));
+        assert!(cache.is_duplicate(&status(1, 10)));
+
+        let slot = SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot: 10,
+                ..Default::default()
+            })),
+        };
+        assert!(!cache.is_duplicate(&slot));
+        assert!(!cache.is_duplicate(&slot));
+        assert_eq!(cache.duplicates(), 3);
+    }
+
+    #[test]
+    fn evicts_least_recently_seen() {
+        let cache = cache(2);
+        assert!(!cache.is_duplicate(&account(1, 1)));
+        assert!(!cache.is_duplicate(&account(2, 1)));
+        // Seeing 1 again makes 2 the oldest
+        assert!(cache.is_duplicate(&account(1, 1)));
+        assert!(!cache.is_duplicate(&account(3, 1)));
+        assert!(cache.is_duplicate(&account(1, 1)));
+        assert!(!cache.is_duplicate(&account(2, 1)));
+        assert_eq!(cache.lru.lock().unwrap().keys.len(), 2);
+    }
+
+    #[test]
+    fn seeded_signatures_are_duplicates() {
+        let cache = cache(100);
+        cache.seed_signature(vec![1; 64], 10, false);
+        cache.seed_signature(vec![2; 64], 10, true);
+        assert!(cache.is_duplicate(&transaction(1, 10)));
+        assert!(!cache.is_duplicate(&status(1, 10)));
+        assert!(cache.is_duplicate(&status(2, 10)));
+        assert!(!cache.is_duplicate(&transaction(2, 10)));
+        assert_eq!(cache.duplicates(), 2);
+    }
+}
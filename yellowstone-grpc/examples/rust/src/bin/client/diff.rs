@@ -0,0 +1,197 @@
+//! Changes between consecutive updates of an account, `DIFF_ACCOUNTS`.
+//!
+//! Frequently updated accounts (pools, oracles) repeat the same large data in every update.
+//! With `DIFF_ACCOUNTS=true` the previous state of every pubkey is kept and account updates
+//! are logged as `AccountDiff`: lamports delta, owner and executable changes, data length
+//! change and the changed byte ranges of data with their new bytes. The first update of a
+//! pubkey is logged in full. States of the `DIFF_ACCOUNTS_CAPACITY` most recently updated
+//! pubkeys are kept, an evicted pubkey is logged in full again.
+
+use {
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::{BTreeMap, HashMap},
+        env, fmt,
+        sync::Mutex,
+    },
+    yellowstone_grpc_proto::prelude::SubscribeUpdateAccount,
+};
+
+const DEFAULT_CAPACITY: usize = 100_000;
+/// Changed ranges closer than this are merged, so a few unchanged bytes in between don't
+/// split the output
+const MERGE_GAP: usize = 8;
+
+#[derive(Debug)]
+struct AccountState {
+    slot: u64,
+    lamports: u64,
+    owner: Vec<u8>,
+    executable: bool,
+    data: Vec<u8>,
+    /// Position in `Lru::order`
+    tick: u64,
+}
+
+/// States ordered by last update, the least recently updated pubkey is evicted first
+#[derive(Debug, Default)]
+struct Lru {
+    states: HashMap<Vec<u8>, AccountState>,
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+/// Bytes of data which changed, `bytes` are the new bytes starting at `offset`
+pub struct DataChange {
+    offset: usize,
+    bytes: Vec<u8>,
+}
+
+impl fmt::Debug for DataChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}..{}: {}",
+            self.offset,
+            self.offset + self.bytes.len(),
+            hex::encode(&self.bytes)
+        )
+    }
+}
+
+/// Changed fields of an account since its previous update, unchanged fields are omitted
+pub struct AccountDiff {
+    slot: u64,
+    previous_slot: u64,
+    pubkey: Pubkey,
+    write_version: u64,
+    lamports: Option<(u64, i128)>,
+    owner: Option<(Pubkey, Pubkey)>,
+    executable: Option<bool>,
+    data_len: Option<(usize, usize)>,
+    data: Vec<DataChange>,
+}
+
+impl fmt::Debug for AccountDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("AccountDiff");
+        f.field("slot", &self.slot)
+            .field("previous_slot", &self.previous_slot)
+            .field("pubkey", &self.pubkey)
+            .field("write_version", &self.write_version);
+        if let Some((lamports, delta)) = self.lamports {
+            f.field("lamports", &format_args!("{lamports} ({delta:+})"));
+        }
+        if let Some((from, to)) = self.owner.as_ref() {
+            f.field("owner", &format_args!("{from} -> {to}"));
+        }
+        if let Some(executable) = self.executable {
+            f.field("executable", &executable);
+        }
+        if let Some((from, to)) = self.data_len {
+            f.field("data_len", &format_args!("{from} -> {to}"));
+        }
+        if !self.data.is_empty() {
+            f.field("data", &self.data);
+        }
+        f.finish()
+    }
+}
+
+/// Ranges of `new` which differ from `old`, bytes past the end of `old` are changed
+fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<DataChange> {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (index, byte) in new.iter().enumerate() {
+        if old.get(index) == Some(byte) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((_, end)) if index - *end <= MERGE_GAP => *end = index + 1,
+            _ => ranges.push((index, index + 1)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| DataChange {
+            offset: start,
+            bytes: new[start..end].to_vec(),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct AccountDiffs {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+impl AccountDiffs {
+    /// Returns `None` unless `DIFF_ACCOUNTS=true`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match env::var("DIFF_ACCOUNTS").as_deref() {
+            Ok("true") => {}
+            Ok("false") | Err(_) => return Ok(None),
+            Ok(_) => anyhow::bail!("invalid DIFF_ACCOUNTS, expected `true` or `false`"),
+        }
+        let capacity = match env::var("DIFF_ACCOUNTS_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid DIFF_ACCOUNTS_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        Ok(Some(Self {
+            capacity,
+            lru: Mutex::default(),
+        }))
+    }
+
+    /// Remember the account state, returns the changes since the previous update of the
+    /// pubkey or `None` if there is no previous state
+    pub fn diff(&self, update: &SubscribeUpdateAccount) -> Option<AccountDiff> {
+        let account = update.account.as_ref()?;
+        let mut lru = self.lru.lock().expect("poisoned");
+        lru.tick += 1;
+        let state = AccountState {
+            slot: update.slot,
+            lamports: account.lamports,
+            owner: account.owner.clone(),
+            executable: account.executable,
+            data: account.data.clone(),
+            tick: lru.tick,
+        };
+        lru.order.insert(state.tick, account.pubkey.clone());
+        let Some(previous) = lru.states.insert(account.pubkey.clone(), state) else {
+            while lru.states.len() > self.capacity {
+                let Some((_, oldest)) = lru.order.pop_first() else {
+                    break;
+                };
+                lru.states.remove(&oldest);
+            }
+            return None;
+        };
+        lru.order.remove(&previous.tick);
+        drop(lru);
+
+        let pubkey = |bytes: &[u8]| Pubkey::try_from(bytes).unwrap_or_default();
+        Some(AccountDiff {
+            slot: update.slot,
+            previous_slot: previous.slot,
+            pubkey: pubkey(&account.pubkey),
+            write_version: account.write_version,
+            lamports: (account.lamports != previous.lamports).then(|| {
+                (
+                    account.lamports,
+                    account.lamports as i128 - previous.lamports as i128,
+                )
+            }),
+            owner: (account.owner != previous.owner)
+                .then(|| (pubkey(&previous.owner), pubkey(&account.owner))),
+            executable: (account.executable != previous.executable).then_some(account.executable),
+            data_len: (account.data.len() != previous.data.len())
+                .then_some((previous.data.len(), account.data.len())),
+            data: changed_ranges(&previous.data, &account.data),
+        })
+    }
+}
@@ -0,0 +1,248 @@
+//! Accounts discovered in transactions, `DISCOVER_ACCOUNTS`.
+//!
+//! Pools, positions and other accounts created while the client runs can't be listed in an
+//! accounts filter up front. With discovery the writable accounts of transactions matched by
+//! `DISCOVER_FILTERS` (all transaction filters by default) which did not sign them, so not
+//! wallets paying fees, are added to the accounts filter `discovered` and subscribed on the
+//! open stream. At most `DISCOVER_MAX_ACCOUNTS` are kept, an account which does not appear
+//! in a transaction for `DISCOVER_TTL_SECS` is removed, the least recently seen first when
+//! the cap is reached. The request is sent again at most every `DISCOVER_INTERVAL_MS` and
+//! only if the accounts changed.
+
+use {
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        env,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    },
+    tokio::time::Instant,
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, SubscribeRequest, SubscribeRequestFilterAccounts,
+        SubscribeUpdate,
+    },
+};
+
+/// Name of the accounts filter with discovered accounts
+pub const FILTER_NAME: &str = "discovered";
+
+const DEFAULT_MAX_ACCOUNTS: usize = 1_000;
+const DEFAULT_TTL_SECS: u64 = 3_600;
+const DEFAULT_INTERVAL_MS: u64 = 1_000;
+
+#[derive(Debug)]
+struct Seen {
+    pubkey: Vec<u8>,
+    at: Instant,
+}
+
+/// Accounts ordered by the last transaction they appeared in
+#[derive(Debug, Default)]
+struct Discovered {
+    accounts: HashMap<Vec<u8>, u64>,
+    order: BTreeMap<u64, Seen>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub struct AccountDiscovery {
+    /// Transaction filters whose transactions are searched, all if empty
+    filters: HashSet<String>,
+    max_accounts: usize,
+    ttl: Duration,
+    interval: Duration,
+    discovered: Mutex<Discovered>,
+    /// Accounts were added or removed since the last request
+    changed: AtomicBool,
+    added: AtomicU64,
+    evicted: AtomicU64,
+}
+
+impl AccountDiscovery {
+    /// Returns `None` unless `DISCOVER_ACCOUNTS=true`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let enabled = match env::var("DISCOVER_ACCOUNTS") {
+            Ok(value) => value
+                .parse::<bool>()
+                .map_err(|_| anyhow::anyhow!("invalid DISCOVER_ACCOUNTS"))?,
+            Err(_) => false,
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let filters = env::var("DISCOVER_FILTERS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let max_accounts = match env::var("DISCOVER_MAX_ACCOUNTS") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|max_accounts| *max_accounts > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid DISCOVER_MAX_ACCOUNTS"))?,
+            Err(_) => DEFAULT_MAX_ACCOUNTS,
+        };
+        let ttl = match env::var("DISCOVER_TTL_SECS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid DISCOVER_TTL_SECS"))?,
+            Err(_) => DEFAULT_TTL_SECS,
+        };
+        let interval = match env::var("DISCOVER_INTERVAL_MS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid DISCOVER_INTERVAL_MS"))?,
+            Err(_) => DEFAULT_INTERVAL_MS,
+        };
+        Ok(Some(Self {
+            filters,
+            max_accounts,
+            ttl: Duration::from_secs(ttl),
+            interval: Duration::from_millis(interval),
+            discovered: Mutex::default(),
+            changed: AtomicBool::new(false),
+            added: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        }))
+    }
+
+    pub const fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Number of accounts in the `discovered` filter
+    pub fn accounts(&self) -> usize {
+        self.discovered.lock().expect("poisoned").accounts.len()
+    }
+
+    /// Number of accounts added since start
+    pub fn added(&self) -> u64 {
+        self.added.load(Ordering::Relaxed)
+    }
+
+    /// Number of accounts removed by TTL or the cap
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Add the writable accounts of a matching transaction which did not sign it
+    pub fn observe(&self, msg: &SubscribeUpdate) {
+        let Some(UpdateOneof::Transaction(update)) = msg.update_oneof.as_ref() else {
+            return;
+        };
+        if !self.filters.is_empty() && !msg.filters.iter().any(|name| self.filters.contains(name)) {
+            return;
+        }
+        let Some(tx) = update.transaction.as_ref() else {
+            return;
+        };
+        let Some((message, header)) = tx
+            .transaction
+            .as_ref()
+            .and_then(|tx| tx.message.as_ref())
+            .and_then(|message| Some((message, message.header.as_ref()?)))
+        else {
+            return;
+        };
+        let signers = header.num_required_signatures as usize;
+        let writable = message
+            .account_keys
+            .len()
+            .saturating_sub(header.num_readonly_unsigned_accounts as usize);
+        let loaded = tx
+            .meta
+            .iter()
+            .flat_map(|meta| meta.loaded_writable_addresses.iter());
+        let keys = message
+            .account_keys
+            .get(signers..writable)
+            .unwrap_or_default()
+            .iter()
+            .chain(loaded);
+
+        let now = Instant::now();
+        let mut discovered = self.discovered.lock().expect("poisoned");
+        for pubkey in keys.filter(|pubkey| pubkey.len() == 32) {
+            discovered.tick += 1;
+            let tick = discovered.tick;
+            match discovered.accounts.insert(pubkey.clone(), tick) {
+                Some(previous) => {
+                    discovered.order.remove(&previous);
+                }
+                None => {
+                    self.added.fetch_add(1, Ordering::Relaxed);
+                    self.changed.store(true, Ordering::Relaxed);
+                }
+            }
+            discovered.order.insert(
+                tick,
+                Seen {
+                    pubkey: pubkey.clone(),
+                    at: now,
+                },
+            );
+        }
+        while discovered.accounts.len() > self.max_accounts {
+            self.evict_oldest(&mut discovered);
+        }
+    }
+
+    fn evict_oldest(&self, discovered: &mut Discovered) {
+        if let Some((_, seen)) = discovered.order.pop_first() {
+            discovered.accounts.remove(&seen.pubkey);
+            self.evicted.fetch_add(1, Ordering::Relaxed);
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Remove expired accounts, returns `true` if accounts changed since the last call
+    pub fn take_changed(&self) -> bool {
+        let mut discovered = self.discovered.lock().expect("poisoned");
+        while discovered
+            .order
+            .first_key_value()
+            .is_some_and(|(_, seen)| seen.at.elapsed() >= self.ttl)
+        {
+            self.evict_oldest(&mut discovered);
+        }
+        drop(discovered);
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+
+    /// The request with the `discovered` accounts filter. Without accounts the filter is
+    /// left out, an accounts filter with no accounts would match every account.
+    pub fn apply(&self, request: &SubscribeRequest) -> SubscribeRequest {
+        let mut request = request.clone();
+        let discovered = self.discovered.lock().expect("poisoned");
+        if discovered.accounts.is_empty() {
+            request.accounts.remove(FILTER_NAME);
+        } else {
+            let account = discovered
+                .order
+                .values()
+                .map(|seen| bs58::encode(&seen.pubkey).into_string())
+                .collect();
+            request.accounts.insert(
+                FILTER_NAME.to_owned(),
+                SubscribeRequestFilterAccounts {
+                    account,
+                    ..Default::default()
+                },
+            );
+        }
+        request
+    }
+}
@@ -0,0 +1,452 @@
+//! Endpoints with their own credentials and connection settings.
+//!
+//! The main endpoint is configured with `ENDPOINT`, `X_TOKEN` (or `X_TOKEN_CMD` and
+//! `X_TOKEN_FILE`, see `token`), `TLS_*`, `COMPRESSION` and `MAX_DECODING_MESSAGE_SIZE`.
+//! Failover endpoints are `ENDPOINT_1`, `ENDPOINT_2`, ... with the same options prefixed by
+//! `ENDPOINT_<n>_`, e.g. `ENDPOINT_1_X_TOKEN`.
+//! `GRPC_COMPRESSION` is the compression of endpoints without their own `COMPRESSION`.
+//! `TLS_CA_PATH` and `TLS_CLIENT_CERT` are accepted as aliases of `TLS_CA_CERTIFICATE` and
+//! `TLS_CLIENT_CERTIFICATE`, the canonical name wins when both are set.
+//! `TLS_INSECURE=true` disables server certificate verification.
+//! `MAX_DECODED_MESSAGE_SIZE` is an alias of `MAX_DECODING_MESSAGE_SIZE`, which wins when
+//! both are set. Without either the limit is the 4 MiB of tonic, too small for blocks with
+//! transactions or accounts.
+//!
+//! `RESOLVE` and `PIN_IP` control which address is used: by default the hostname is
+//! resolved by the transport, with `RESOLVE=ipv4|ipv6|any` it is resolved by the client on
+//! every reconnect and the first address of the preferred family is used, `PIN_IP` always
+//! connects to the given address.
+//!
+//! HTTP/2 keepalive, TCP options and flow control windows of the channel are set with
+//! `HTTP2_KEEPALIVE_*`, `TCP_*` and `INITIAL_*_WINDOW_SIZE`, see `ChannelSettings`. An
+//! endpoint `unix:///path/to/socket` connects over a Unix domain socket, without TLS.
+
+use {
+    crate::{tls, token::XTokenConfig},
+    log::{info, warn},
+    std::{
+        env, fs,
+        net::{IpAddr, SocketAddr},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    },
+    tokio::net::{lookup_host, UnixStream},
+    tonic_health::pb::health_client::HealthClient,
+    tower::service_fn,
+    yellowstone_grpc_client::{GeyserGrpcClient, Interceptor},
+    yellowstone_grpc_proto::{
+        prelude::geyser_client::GeyserClient,
+        tonic::{
+            codec::CompressionEncoding,
+            service::interceptor::InterceptedService,
+            transport::{Certificate, ClientTlsConfig, Identity, Uri},
+        },
+    },
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// PEM file with CA certificate, system roots are used if not set
+    pub ca_certificate: Option<String>,
+    pub domain_name: Option<String>,
+    /// PEM files with client certificate and key for mutual TLS
+    pub client_certificate: Option<String>,
+    pub client_key: Option<String>,
+    /// Accept any server certificate, only for `https://` endpoints
+    pub insecure: bool,
+}
+
+impl TlsSettings {
+    fn is_empty(&self) -> bool {
+        self.ca_certificate.is_none()
+            && self.domain_name.is_none()
+            && self.client_certificate.is_none()
+            && self.client_key.is_none()
+    }
+
+    /// `default_domain` is used for SNI and certificate verification if `domain_name` is not
+    /// set, required when connecting to a resolved IP address
+    fn identity(&self) -> anyhow::Result<Option<(&str, &str)>> {
+        match (&self.client_certificate, &self.client_key) {
+            (Some(certificate), Some(key)) => Ok(Some((certificate, key))),
+            (None, None) => Ok(None),
+            _ => anyhow::bail!("both TLS client certificate and key are required"),
+        }
+    }
+
+    fn to_config(&self, default_domain: Option<&str>) -> anyhow::Result<ClientTlsConfig> {
+        let mut config = ClientTlsConfig::new();
+        if let Some(path) = &self.ca_certificate {
+            config = config.ca_certificate(Certificate::from_pem(fs::read(path)?));
+        }
+        if let Some(domain_name) = self.domain_name.as_deref().or(default_domain) {
+            config = config.domain_name(domain_name);
+        }
+        if let Some((certificate, key)) = self.identity()? {
+            config = config.identity(Identity::from_pem(fs::read(certificate)?, fs::read(key)?));
+        }
+        Ok(config)
+    }
+}
+
+/// Options of the gRPC channel, transport defaults are used for options which are not set
+#[derive(Debug, Clone, Default)]
+pub struct ChannelSettings {
+    /// Interval of HTTP/2 pings, keeps NAT and load balancer mappings of idle streams alive
+    pub keep_alive_interval: Option<Duration>,
+    /// Connection is closed if a ping is not acknowledged in time
+    pub keep_alive_timeout: Option<Duration>,
+    /// Send pings also without active streams
+    pub keep_alive_while_idle: Option<bool>,
+    pub tcp_nodelay: Option<bool>,
+    pub tcp_keepalive: Option<Duration>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    pub adaptive_window: Option<bool>,
+}
+
+impl ChannelSettings {
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        fn parse<T: std::str::FromStr>(prefix: &str, key: &str) -> anyhow::Result<Option<T>> {
+            env::var(format!("{prefix}{key}"))
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {prefix}{key}"))
+        }
+        let secs = |key: &str| parse::<u64>(prefix, key).map(|secs| secs.map(Duration::from_secs));
+        Ok(Self {
+            keep_alive_interval: secs("HTTP2_KEEPALIVE_INTERVAL_SECS")?,
+            keep_alive_timeout: secs("HTTP2_KEEPALIVE_TIMEOUT_SECS")?,
+            keep_alive_while_idle: parse(prefix, "HTTP2_KEEPALIVE_WHILE_IDLE")?,
+            tcp_nodelay: parse(prefix, "TCP_NODELAY")?,
+            tcp_keepalive: secs("TCP_KEEPALIVE_SECS")?,
+            initial_stream_window_size: parse(prefix, "INITIAL_STREAM_WINDOW_SIZE")?,
+            initial_connection_window_size: parse(prefix, "INITIAL_CONNECTION_WINDOW_SIZE")?,
+            adaptive_window: parse(prefix, "HTTP2_ADAPTIVE_WINDOW")?,
+        })
+    }
+}
+
+/// How the endpoint hostname is turned into an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolve {
+    /// Pass hostname to the transport
+    System,
+    /// Resolve on every connect, prefer address family
+    Ipv4,
+    Ipv6,
+    Any,
+    /// Always connect to this address
+    Pinned(IpAddr),
+}
+
+impl Resolve {
+    fn accepts(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Ipv4 => addr.is_ipv4(),
+            Self::Ipv6 => addr.is_ipv6(),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EndpointConfig {
+    pub url: String,
+    pub x_token: XTokenConfig,
+    pub tls: TlsSettings,
+    /// Compression for both requests and responses
+    pub compression: Option<CompressionEncoding>,
+    pub max_decoding_message_size: Option<usize>,
+    pub resolve: Resolve,
+    pub channel: ChannelSettings,
+}
+
+/// `gzip` or `none`, tonic 0.10 used by the published client has no zstd
+fn parse_compression(key: &str, value: &str) -> anyhow::Result<Option<CompressionEncoding>> {
+    match value {
+        "gzip" => Ok(Some(CompressionEncoding::Gzip)),
+        "none" => Ok(None),
+        "zstd" => anyhow::bail!("invalid {key}, zstd is not supported, expected `gzip` or `none`"),
+        _ => anyhow::bail!("invalid {key}, expected `gzip` or `none`"),
+    }
+}
+
+impl EndpointConfig {
+    /// Options are read from `<prefix>X_TOKEN`, `<prefix>TLS_CA_CERTIFICATE`, ...
+    fn from_env(
+        url: String,
+        prefix: &str,
+        default_compression: Option<CompressionEncoding>,
+    ) -> anyhow::Result<Self> {
+        let var = |key: &str| env::var(format!("{prefix}{key}")).ok();
+        // The canonical variable wins over its alias
+        let aliased = |key: &str, alias: &str| match (var(key), var(alias)) {
+            (Some(value), Some(_)) => {
+                warn!("{prefix}{alias} is ignored, {prefix}{key} is set as well");
+                Some(value)
+            }
+            (value, alias) => value.or(alias),
+        };
+        Ok(Self {
+            url,
+            x_token: XTokenConfig::from_env(prefix)?,
+            tls: TlsSettings {
+                ca_certificate: aliased("TLS_CA_CERTIFICATE", "TLS_CA_PATH"),
+                domain_name: var("TLS_DOMAIN_NAME"),
+                client_certificate: aliased("TLS_CLIENT_CERTIFICATE", "TLS_CLIENT_CERT"),
+                client_key: var("TLS_CLIENT_KEY"),
+                insecure: var("TLS_INSECURE")
+                    .map(|value| value.parse())
+                    .transpose()
+                    .map_err(|_| anyhow::anyhow!("invalid {prefix}TLS_INSECURE"))?
+                    .unwrap_or(false),
+            },
+            compression: match var("COMPRESSION") {
+                Some(value) => parse_compression(&format!("{prefix}COMPRESSION"), &value)?,
+                None => default_compression,
+            },
+            max_decoding_message_size: aliased(
+                "MAX_DECODING_MESSAGE_SIZE",
+                "MAX_DECODED_MESSAGE_SIZE",
+            )
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("invalid {prefix}MAX_DECODING_MESSAGE_SIZE"))?,
+            resolve: match (var("PIN_IP"), var("RESOLVE").as_deref()) {
+                (Some(ip), _) => Resolve::Pinned(
+                    ip.parse()
+                        .map_err(|_| anyhow::anyhow!("invalid {prefix}PIN_IP"))?,
+                ),
+                (None, None | Some("system")) => Resolve::System,
+                (None, Some("ipv4")) => Resolve::Ipv4,
+                (None, Some("ipv6")) => Resolve::Ipv6,
+                (None, Some("any")) => Resolve::Any,
+                (None, Some(_)) => anyhow::bail!(
+                    "invalid {prefix}RESOLVE, expected `system`, `ipv4`, `ipv6` or `any`"
+                ),
+            },
+            channel: ChannelSettings::from_env(prefix)?,
+        })
+    }
+
+    fn compression_name(&self) -> String {
+        self.compression
+            .map_or_else(|| "none".to_owned(), |encoding| encoding.to_string())
+    }
+
+    /// Replace hostname in the endpoint URL with the address selected by `resolve`, returns
+    /// the new URL and the original hostname
+    async fn resolve_url(&self) -> anyhow::Result<(String, Option<String>)> {
+        if self.resolve == Resolve::System {
+            return Ok((self.url.clone(), None));
+        }
+
+        let uri: Uri = self.url.parse()?;
+        let host = uri
+            .host()
+            .ok_or_else(|| anyhow::anyhow!("endpoint without host: {}", self.url))?
+            .to_owned();
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        let addr = match self.resolve {
+            Resolve::Pinned(ip) => SocketAddr::new(ip, port),
+            resolve => lookup_host((host.as_str(), port))
+                .await?
+                .find(|addr| resolve.accepts(addr))
+                .ok_or_else(|| anyhow::anyhow!("no {resolve:?} address found for {host}"))?,
+        };
+        info!("connect to {host} via {addr}");
+
+        let mut parts = uri.into_parts();
+        parts.authority = Some(addr.to_string().parse()?);
+        Ok((Uri::from_parts(parts)?.to_string(), Some(host)))
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<GeyserGrpcClient<impl Interceptor>> {
+        // Loaded on every connect, a token which expired since the last one is replaced
+        let interceptor = self.x_token.interceptor().await?;
+        // The URL of a Unix socket channel is only used for the `:authority` header
+        let unix_path = self.url.strip_prefix("unix://").map(str::to_owned);
+        let (url, host) = match unix_path {
+            Some(_) => ("http://localhost".to_owned(), None),
+            None => self.resolve_url().await?,
+        };
+        let insecure = self.tls.insecure && url.starts_with("https://");
+        let builder_url = if insecure {
+            tls::plain_url(&url)?
+        } else {
+            url.clone()
+        };
+        let mut builder = GeyserGrpcClient::build_from_shared(builder_url)?
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(10));
+        // Certificate is issued for the hostname, not for the resolved address
+        if !insecure && (!self.tls.is_empty() || (host.is_some() && url.starts_with("https://"))) {
+            builder = builder.tls_config(self.tls.to_config(host.as_deref())?)?;
+        }
+        if let Some(encoding) = self.compression {
+            builder = builder
+                .send_compressed(encoding)
+                .accept_compressed(encoding);
+        }
+        if let Some(limit) = self.max_decoding_message_size {
+            builder = builder.max_decoding_message_size(limit);
+        }
+        let channel = &self.channel;
+        if let Some(interval) = channel.keep_alive_interval {
+            builder = builder.http2_keep_alive_interval(interval);
+        }
+        if let Some(timeout) = channel.keep_alive_timeout {
+            builder = builder.keep_alive_timeout(timeout);
+        }
+        if let Some(enabled) = channel.keep_alive_while_idle {
+            builder = builder.keep_alive_while_idle(enabled);
+        }
+        if let Some(enabled) = channel.tcp_nodelay {
+            builder = builder.tcp_nodelay(enabled);
+        }
+        if let Some(keepalive) = channel.tcp_keepalive {
+            builder = builder.tcp_keepalive(Some(keepalive));
+        }
+        if let Some(size) = channel.initial_stream_window_size {
+            builder = builder.initial_stream_window_size(size);
+        }
+        if let Some(size) = channel.initial_connection_window_size {
+            builder = builder.initial_connection_window_size(size);
+        }
+        if let Some(enabled) = channel.adaptive_window {
+            builder = builder.http2_adaptive_window(enabled);
+        }
+
+        let channel = if let Some(path) = unix_path {
+            builder
+                .endpoint
+                .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+                .await?
+        } else if insecure {
+            let uri: Uri = url.parse()?;
+            let domain_name = self
+                .tls
+                .domain_name
+                .as_deref()
+                .or(host.as_deref())
+                .or(uri.host())
+                .unwrap_or_default();
+            tls::connect_insecure(&builder.endpoint, domain_name, self.tls.identity()?).await?
+        } else {
+            builder.endpoint.connect().await?
+        };
+
+        // Same client as `GeyserGrpcBuilder::connect` builds, for both kinds of channel
+        let mut geyser = GeyserClient::new(InterceptedService::new(
+            channel.clone(),
+            interceptor.clone(),
+        ));
+        if let Some(encoding) = builder.send_compressed {
+            geyser = geyser.send_compressed(encoding);
+        }
+        if let Some(encoding) = builder.accept_compressed {
+            geyser = geyser.accept_compressed(encoding);
+        }
+        if let Some(limit) = builder.max_decoding_message_size {
+            geyser = geyser.max_decoding_message_size(limit);
+        }
+        Ok(GeyserGrpcClient::new(
+            HealthClient::with_interceptor(channel, interceptor),
+            geyser,
+        ))
+    }
+}
+
+/// Main endpoint and failover endpoints, only one of them is active
+#[derive(Debug)]
+pub struct Endpoints {
+    list: Vec<EndpointConfig>,
+    active: AtomicUsize,
+}
+
+impl Endpoints {
+    /// Returns `None` if `ENDPOINT` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = env::var("ENDPOINT") else {
+            return Ok(None);
+        };
+
+        let compression = match env::var("GRPC_COMPRESSION") {
+            Ok(value) => parse_compression("GRPC_COMPRESSION", &value)?,
+            Err(_) => None,
+        };
+        let mut list = vec![EndpointConfig::from_env(url, "", compression)?];
+        for index in 1.. {
+            let Ok(url) = env::var(format!("ENDPOINT_{index}")) else {
+                break;
+            };
+            list.push(EndpointConfig::from_env(
+                url,
+                &format!("ENDPOINT_{index}_"),
+                compression,
+            )?);
+        }
+        // URL is not logged, some providers put the token into it
+        for (index, endpoint) in list.iter().enumerate() {
+            let name = Self::name(index);
+            info!("{name} compression: {}", endpoint.compression_name());
+            if endpoint.tls.insecure {
+                warn!("{name}: TLS certificate verification is disabled");
+            }
+        }
+
+        Ok(Some(Self {
+            list,
+            active: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Placeholder for actions which do not connect to the server
+    pub fn offline() -> Self {
+        Self {
+            list: vec![],
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    fn name(index: usize) -> String {
+        match index {
+            0 => "ENDPOINT".to_owned(),
+            index => format!("ENDPOINT_{index}"),
+        }
+    }
+
+    /// Name of the variable with the current endpoint, the URL may contain a token
+    pub fn current_name(&self) -> String {
+        match self.list.len() {
+            0 => "none".to_owned(),
+            len => Self::name(self.active.load(Ordering::Relaxed) % len),
+        }
+    }
+
+    pub fn current(&self) -> &EndpointConfig {
+        &self.list[self.active.load(Ordering::Relaxed) % self.list.len()]
+    }
+
+    /// All endpoints with the names of their variables
+    pub fn all(&self) -> impl Iterator<Item = (String, &EndpointConfig)> {
+        self.list
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| (Self::name(index), endpoint))
+    }
+
+    /// Switch to the next endpoint, returns `None` if there is nothing to switch to
+    pub fn failover(&self) -> Option<&EndpointConfig> {
+        if self.list.len() < 2 {
+            return None;
+        }
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Some(self.current())
+    }
+}
@@ -0,0 +1,174 @@
+//! Errors of connecting, building the subscribe request and streaming.
+//!
+//! Retrying can't fix a rejected token or invalid filters, so these are permanent and the
+//! client exits with them; all other errors, a stream finished by the server included,
+//! reconnect with the `retry` backoff. A token loaded by `token` may only have expired,
+//! rejections of it reconnect as well. Statuses are classified by their code, and whether
+//! tonic created them locally, never by their message.
+
+use {
+    std::io,
+    yellowstone_grpc_client::GeyserGrpcClientError,
+    yellowstone_grpc_proto::tonic::{Code, Status},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// Endpoint could not be resolved or reached, or the TLS handshake failed
+    #[error("failed to connect: {0:#}")]
+    Connection(anyhow::Error),
+    /// Server rejected the `x-token`
+    #[error("authentication failed: {}", .0.message())]
+    Auth(Status),
+    /// Filters, or a file they are read from, are invalid
+    #[error("invalid filters: {0:#}")]
+    FilterParse(anyhow::Error),
+    /// Stream failed or a subscribe request could not be sent
+    #[error(transparent)]
+    Stream(GeyserGrpcClientError),
+    /// Server finished the stream without an error, it is subscribed again
+    #[error("stream finished by server")]
+    Closed,
+    /// Stream closed by the client because nothing was received in time
+    #[error("{0}")]
+    IdleTimeout(String),
+    /// Update received from the server could not be decoded, the connection itself is
+    /// still usable
+    #[error("failed to decode update: {}", .0.message())]
+    Decode(Status),
+    /// Update received from the server exceeded `MAX_DECODING_MESSAGE_SIZE`, nothing is
+    /// truncated, the update is lost
+    #[error(
+        "update exceeds the message size limit: {}, raise MAX_DECODING_MESSAGE_SIZE to receive it",
+        .0.message()
+    )]
+    TooLarge(Status),
+    /// Writing updates to the capture file failed
+    #[error("failed to write capture: {0}")]
+    Sink(#[from] io::Error),
+}
+
+impl ClientError {
+    /// Retrying won't help without a configuration change
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::Auth(_) | Self::FilterParse(_))
+    }
+
+    /// Permanent errors stop `backoff::future::retry`, others are retried
+    pub fn into_backoff(self) -> backoff::Error<anyhow::Error> {
+        if self.is_permanent() {
+            backoff::Error::permanent(self.into())
+        } else {
+            backoff::Error::transient(self.into())
+        }
+    }
+
+    /// `into_backoff` of an error which may be a `ClientError`, other errors are retried
+    pub fn classify(error: anyhow::Error) -> backoff::Error<anyhow::Error> {
+        match error.downcast::<Self>() {
+            Ok(error) => error.into_backoff(),
+            Err(error) => backoff::Error::transient(error),
+        }
+    }
+
+    /// Status returned by the server
+    pub fn status(&self) -> Option<&Status> {
+        match self {
+            Self::Auth(status)
+            | Self::Decode(status)
+            | Self::TooLarge(status)
+            | Self::Stream(GeyserGrpcClientError::TonicStatus(status)) => Some(status),
+            _ => None,
+        }
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        // tonic fails messages it can't decode or which are over the limit with a status of its
+        // own, without a source; statuses of transport errors keep the error as their source
+        let local = std::error::Error::source(&status).is_none();
+        match status.code() {
+            Code::Unauthenticated | Code::PermissionDenied => Self::Auth(status),
+            Code::Internal if local => Self::Decode(status),
+            Code::OutOfRange if local => Self::TooLarge(status),
+            _ => Self::Stream(GeyserGrpcClientError::TonicStatus(status)),
+        }
+    }
+}
+
+impl From<GeyserGrpcClientError> for ClientError {
+    fn from(error: GeyserGrpcClientError) -> Self {
+        match error {
+            GeyserGrpcClientError::TonicStatus(status) => status.into(),
+            error => Self::Stream(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::sync::Arc};
+
+    fn with_source(code: Code) -> Status {
+        let mut status = Status::new(code, "transport");
+        status.set_source(Arc::new(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        status
+    }
+
+    #[test]
+    fn classifies_statuses() {
+        let cases = [
+            (Status::unauthenticated("expired"), "Auth", true),
+            (Status::permission_denied("denied"), "Auth", true),
+            (
+                Status::internal("failed to decode Protobuf message"),
+                "Decode",
+                false,
+            ),
+            (
+                Status::out_of_range("message length too large"),
+                "TooLarge",
+                false,
+            ),
+            (with_source(Code::Internal), "Stream", false),
+            (with_source(Code::OutOfRange), "Stream", false),
+            (Status::unavailable("restarting"), "Stream", false),
+            (Status::invalid_argument("bad filter"), "Stream", false),
+        ];
+        for (status, variant, permanent) in cases {
+            let code = status.code();
+            let error = ClientError::from(status);
+            let name = match error {
+                ClientError::Auth(_) => "Auth",
+                ClientError::Decode(_) => "Decode",
+                ClientError::TooLarge(_) => "TooLarge",
+                ClientError::Stream(_) => "Stream",
+                _ => "other",
+            };
+            assert_eq!(
+                (name, error.is_permanent()),
+                (variant, permanent),
+                "{code:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_errors_for_backoff() {
+        let permanent = |error: anyhow::Error| {
+            matches!(ClientError::classify(error), backoff::Error::Permanent(_))
+        };
+        assert!(permanent(
+            ClientError::FilterParse(anyhow::anyhow!("bad")).into()
+        ));
+        assert!(permanent(
+            ClientError::from(Status::unauthenticated("expired")).into()
+        ));
+        assert!(!permanent(ClientError::Closed.into()));
+        assert!(!permanent(
+            ClientError::IdleTimeout("watchdog".to_owned()).into()
+        ));
+        assert!(!permanent(anyhow::anyhow!("other error")));
+    }
+}
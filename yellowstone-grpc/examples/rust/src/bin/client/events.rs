@@ -0,0 +1,191 @@
+//! In-process event bus for consumers running in the same process as the stream.
+//!
+//! Code embedding the client, e.g. several strategy tasks, takes `StreamContext::events` and
+//! subscribes to the update types it needs instead of running its own stream or pipeline.
+//! Every type has its own broadcast channel of `EVENT_BUS_CAPACITY` events, an update is
+//! published after dedup, filters and coalescing, right where it is passed to sinks. Updates
+//! are copied only for types with at least one subscriber, a subscriber which falls more than
+//! the capacity behind skips the oldest events and gets `RecvError::Lagged`. The latest slot
+//! status is also kept in a watch channel, for tasks which only need the current slot.
+
+use {
+    crate::{forks::SlotRollback, gaps::Gap},
+    std::{
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    },
+    tokio::sync::{broadcast, watch},
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateAccount,
+        SubscribeUpdateBlock, SubscribeUpdateBlockMeta, SubscribeUpdateEntry, SubscribeUpdateSlot,
+        SubscribeUpdateTransaction, SubscribeUpdateTransactionStatus,
+    },
+};
+
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// Update of one type with the names of the filters it matched
+#[derive(Debug)]
+pub struct Event<T> {
+    pub filters: Vec<String>,
+    pub update: T,
+}
+
+pub type EventReceiver<T> = broadcast::Receiver<Arc<Event<T>>>;
+
+#[derive(Debug)]
+pub struct EventBus {
+    accounts: broadcast::Sender<Arc<Event<SubscribeUpdateAccount>>>,
+    slots: broadcast::Sender<Arc<Event<SubscribeUpdateSlot>>>,
+    transactions: broadcast::Sender<Arc<Event<SubscribeUpdateTransaction>>>,
+    transaction_statuses: broadcast::Sender<Arc<Event<SubscribeUpdateTransactionStatus>>>,
+    blocks: broadcast::Sender<Arc<Event<SubscribeUpdateBlock>>>,
+    blocks_meta: broadcast::Sender<Arc<Event<SubscribeUpdateBlockMeta>>>,
+    entries: broadcast::Sender<Arc<Event<SubscribeUpdateEntry>>>,
+    rollbacks: broadcast::Sender<Arc<SlotRollback>>,
+    gaps: broadcast::Sender<Arc<Gap>>,
+    latest_slot: watch::Sender<Option<SubscribeUpdateSlot>>,
+    /// Events sent to at least one subscriber
+    published: AtomicU64,
+}
+
+/// Send the update if the channel has subscribers, returns `true` if it was sent
+fn send<T: Clone>(
+    tx: &broadcast::Sender<Arc<Event<T>>>,
+    msg: &SubscribeUpdate,
+    update: &T,
+) -> bool {
+    if tx.receiver_count() == 0 {
+        return false;
+    }
+    tx.send(Arc::new(Event {
+        filters: msg.filters.clone(),
+        update: update.clone(),
+    }))
+    .is_ok()
+}
+
+impl EventBus {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let capacity = match env::var("EVENT_BUS_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid EVENT_BUS_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        Ok(Self {
+            accounts: broadcast::channel(capacity).0,
+            slots: broadcast::channel(capacity).0,
+            transactions: broadcast::channel(capacity).0,
+            transaction_statuses: broadcast::channel(capacity).0,
+            blocks: broadcast::channel(capacity).0,
+            blocks_meta: broadcast::channel(capacity).0,
+            entries: broadcast::channel(capacity).0,
+            rollbacks: broadcast::channel(capacity).0,
+            gaps: broadcast::channel(capacity).0,
+            latest_slot: watch::channel(None).0,
+            published: AtomicU64::new(0),
+        })
+    }
+
+    pub fn published(&self) -> u64 {
+        self.published.load(Ordering::Relaxed)
+    }
+
+    /// Send the update to the subscribers of its type
+    pub fn publish(&self, msg: &SubscribeUpdate) {
+        let sent = match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Account(update)) => send(&self.accounts, msg, update),
+            Some(UpdateOneof::Slot(update)) => {
+                self.latest_slot.send_if_modified(|latest| {
+                    // Statuses of older slots arrive after newer slots are processed
+                    if latest
+                        .as_ref()
+                        .is_some_and(|latest| latest.slot > update.slot)
+                    {
+                        return false;
+                    }
+                    *latest = Some(update.clone());
+                    true
+                });
+                send(&self.slots, msg, update)
+            }
+            Some(UpdateOneof::Transaction(update)) => send(&self.transactions, msg, update),
+            Some(UpdateOneof::TransactionStatus(update)) => {
+                send(&self.transaction_statuses, msg, update)
+            }
+            Some(UpdateOneof::Block(update)) => send(&self.blocks, msg, update),
+            Some(UpdateOneof::BlockMeta(update)) => send(&self.blocks_meta, msg, update),
+            Some(UpdateOneof::Entry(update)) => send(&self.entries, msg, update),
+            Some(UpdateOneof::Ping(_)) | Some(UpdateOneof::Pong(_)) | None => false,
+        };
+        if sent {
+            self.published.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn publish_rollback(&self, rollback: &SlotRollback) {
+        if self.rollbacks.receiver_count() > 0
+            && self.rollbacks.send(Arc::new(rollback.clone())).is_ok()
+        {
+            self.published.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn publish_gap(&self, gap: &Gap) {
+        if self.gaps.receiver_count() > 0 && self.gaps.send(Arc::new(gap.clone())).is_ok() {
+            self.published.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Subscriptions for tasks in the process, the dashboard is fed by them
+impl EventBus {
+    pub fn accounts(&self) -> EventReceiver<SubscribeUpdateAccount> {
+        self.accounts.subscribe()
+    }
+
+    pub fn slots(&self) -> EventReceiver<SubscribeUpdateSlot> {
+        self.slots.subscribe()
+    }
+
+    pub fn transactions(&self) -> EventReceiver<SubscribeUpdateTransaction> {
+        self.transactions.subscribe()
+    }
+
+    pub fn transaction_statuses(&self) -> EventReceiver<SubscribeUpdateTransactionStatus> {
+        self.transaction_statuses.subscribe()
+    }
+
+    pub fn blocks(&self) -> EventReceiver<SubscribeUpdateBlock> {
+        self.blocks.subscribe()
+    }
+
+    pub fn blocks_meta(&self) -> EventReceiver<SubscribeUpdateBlockMeta> {
+        self.blocks_meta.subscribe()
+    }
+
+    pub fn entries(&self) -> EventReceiver<SubscribeUpdateEntry> {
+        self.entries.subscribe()
+    }
+
+    /// Slots of dead forks, see `forks`
+    pub fn rollbacks(&self) -> broadcast::Receiver<Arc<SlotRollback>> {
+        self.rollbacks.subscribe()
+    }
+
+    /// Slots which were not received and write versions out of order, see `gaps`
+    pub fn gaps(&self) -> broadcast::Receiver<Arc<Gap>> {
+        self.gaps.subscribe()
+    }
+
+    /// Latest slot status, `None` until the first slot update
+    pub fn latest_slot(&self) -> watch::Receiver<Option<SubscribeUpdateSlot>> {
+        self.latest_slot.subscribe()
+    }
+}
@@ -0,0 +1,767 @@
+//! Client-side filter expressions, `FILTER_EXPR`.
+//!
+//! A small expression language evaluated against every account, transaction and transaction
+//! status update, updates for which it is not true are dropped. Fields are resolved when
+//! the expression is parsed, so a typo fails at startup:
+//!
+//! ```text
+//! lamports > 1000000 && owner == "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+//! tx.err == null && tx.accounts contains "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4"
+//! type == "transaction" || data_len in [165, 82]
+//! ```
+//!
+//! Literals are unsigned integers, strings in double quotes, `true`, `false`, `null` and
+//! lists in brackets. Operators by precedence: `!`, comparisons (`==`, `!=`, `<`, `<=`,
+//! `>`, `>=`, `contains` for an element of a list or a substring, `in` for an element of a
+//! list), `&&`, `||`, with parentheses for grouping. A field which the update doesn't have
+//! is `null`, ordering with `null` or values of different types is false.
+
+use {
+    crate::stats::{update_kind, update_slot},
+    std::{
+        env, fmt,
+        iter::Peekable,
+        str::Chars,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+    yellowstone_grpc_proto::{
+        convert_from,
+        prelude::{subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateTransactionInfo},
+    },
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Str(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn pubkey(bytes: &[u8]) -> Self {
+        Self::Str(bs58::encode(bytes).into_string())
+    }
+
+    fn pubkeys<'a>(keys: impl Iterator<Item = &'a Vec<u8>>) -> Self {
+        Self::List(keys.map(|key| Self::pubkey(key)).collect())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Str(value) => write!(f, "{value:?}"),
+            Self::List(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Fields of updates, `name` is how they are written in expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Type,
+    Slot,
+    Filters,
+    Pubkey,
+    Owner,
+    Lamports,
+    Executable,
+    RentEpoch,
+    WriteVersion,
+    DataLen,
+    TxnSignature,
+    TxSignature,
+    TxIsVote,
+    TxIndex,
+    TxErr,
+    TxFee,
+    TxFeePayer,
+    TxAccounts,
+    TxPrograms,
+    TxLogs,
+    TxComputeUnits,
+}
+
+impl Field {
+    const ALL: &'static [(&'static str, Field)] = &[
+        ("type", Self::Type),
+        ("slot", Self::Slot),
+        ("filters", Self::Filters),
+        ("pubkey", Self::Pubkey),
+        ("owner", Self::Owner),
+        ("lamports", Self::Lamports),
+        ("executable", Self::Executable),
+        ("rent_epoch", Self::RentEpoch),
+        ("write_version", Self::WriteVersion),
+        ("data_len", Self::DataLen),
+        ("txn_signature", Self::TxnSignature),
+        ("tx.signature", Self::TxSignature),
+        ("tx.is_vote", Self::TxIsVote),
+        ("tx.index", Self::TxIndex),
+        ("tx.err", Self::TxErr),
+        ("tx.fee", Self::TxFee),
+        ("tx.fee_payer", Self::TxFeePayer),
+        ("tx.accounts", Self::TxAccounts),
+        ("tx.programs", Self::TxPrograms),
+        ("tx.logs", Self::TxLogs),
+        ("tx.compute_units", Self::TxComputeUnits),
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, field)| *field)
+    }
+
+    fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, field)| *field == self)
+            .map(|(name, _)| *name)
+            .expect("every field has a name")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    In,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Field(Field),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(u64),
+    Str(String),
+    Ident(String),
+    Op(Op),
+    Not,
+    And,
+    Or,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+fn tokenize(source: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars: Peekable<Chars> = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '_') {
+                if c != '_' {
+                    digits.push(c);
+                }
+                chars.next();
+            }
+            let value = digits
+                .parse()
+                .map_err(|_| anyhow::anyhow!("number {digits} is too large"))?;
+            tokens.push(Token::Int(value));
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.')
+            {
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(match ident.as_str() {
+                "contains" => Token::Op(Op::Contains),
+                "in" => Token::Op(Op::In),
+                _ => Token::Ident(ident),
+            });
+            continue;
+        }
+        chars.next();
+        let next = chars.peek().copied();
+        let token = match (c, next) {
+            ('"', _) => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => anyhow::bail!("unterminated string"),
+                        },
+                        Some(c) => value.push(c),
+                        None => anyhow::bail!("unterminated string"),
+                    }
+                }
+                Token::Str(value)
+            }
+            ('=', Some('=')) | ('!', Some('=')) | ('<', Some('=')) | ('>', Some('=')) => {
+                chars.next();
+                Token::Op(match c {
+                    '=' => Op::Eq,
+                    '!' => Op::Ne,
+                    '<' => Op::Le,
+                    _ => Op::Ge,
+                })
+            }
+            ('&', Some('&')) => {
+                chars.next();
+                Token::And
+            }
+            ('|', Some('|')) => {
+                chars.next();
+                Token::Or
+            }
+            ('<', _) => Token::Op(Op::Lt),
+            ('>', _) => Token::Op(Op::Gt),
+            ('!', _) => Token::Not,
+            ('(', _) => Token::LParen,
+            (')', _) => Token::RParen,
+            ('[', _) => Token::LBracket,
+            (']', _) => Token::RBracket,
+            (',', _) => Token::Comma,
+            _ => anyhow::bail!("unexpected character {c:?}"),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> anyhow::Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => anyhow::bail!("expected {expected:?}, found {token:?}"),
+            None => anyhow::bail!("expected {expected:?}, found the end"),
+        }
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let mut left = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> anyhow::Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        let left = self.primary()?;
+        match self.peek() {
+            Some(Token::Op(op)) => {
+                let op = *op;
+                self.next();
+                Ok(Expr::Compare(op, Box::new(left), Box::new(self.primary()?)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::LBracket) => {
+                let mut items = vec![];
+                if self.peek() == Some(&Token::RBracket) {
+                    self.next();
+                    return Ok(Expr::List(items));
+                }
+                loop {
+                    items.push(self.primary()?);
+                    match self.next() {
+                        Some(Token::Comma) => {}
+                        Some(Token::RBracket) => return Ok(Expr::List(items)),
+                        other => anyhow::bail!("expected `,` or `]` in list, found {other:?}"),
+                    }
+                }
+            }
+            Some(Token::Int(value)) => Ok(Expr::Literal(Value::Int(value))),
+            Some(Token::Str(value)) => Ok(Expr::Literal(Value::Str(value))),
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                name => Field::parse(name).map(Expr::Field).ok_or_else(|| {
+                    let fields = Field::ALL.iter().map(|(name, _)| *name);
+                    anyhow::anyhow!(
+                        "unknown field {name}, expected one of {}",
+                        fields.collect::<Vec<_>>().join(", ")
+                    )
+                }),
+            },
+            Some(token) => anyhow::bail!("unexpected {token:?}"),
+            None => anyhow::bail!("unexpected end of the expression"),
+        }
+    }
+}
+
+fn transaction_field(tx: &SubscribeUpdateTransactionInfo, field: Field) -> Value {
+    let meta = tx.meta.as_ref();
+    let message = tx.transaction.as_ref().and_then(|tx| tx.message.as_ref());
+    match field {
+        Field::TxSignature => Value::Str(bs58::encode(&tx.signature).into_string()),
+        Field::TxIsVote => Value::Bool(tx.is_vote),
+        Field::TxIndex => Value::Int(tx.index),
+        Field::TxErr => meta.map_or(Value::Null, |meta| error_value(meta.err.as_ref())),
+        Field::TxFee => meta.map_or(Value::Null, |meta| Value::Int(meta.fee)),
+        Field::TxFeePayer => message
+            .and_then(|message| message.account_keys.first())
+            .map_or(Value::Null, |payer| Value::pubkey(payer)),
+        // Static keys and addresses loaded from lookup tables, in the order of the transaction
+        Field::TxAccounts => Value::pubkeys(
+            message
+                .map(|message| message.account_keys.iter())
+                .into_iter()
+                .flatten()
+                .chain(meta.into_iter().flat_map(|meta| {
+                    meta.loaded_writable_addresses
+                        .iter()
+                        .chain(meta.loaded_readonly_addresses.iter())
+                })),
+        ),
+        Field::TxPrograms => Value::pubkeys(message.into_iter().flat_map(|message| {
+            message.instructions.iter().filter_map(|instruction| {
+                message
+                    .account_keys
+                    .get(instruction.program_id_index as usize)
+            })
+        })),
+        Field::TxLogs => meta.map_or(Value::Null, |meta| {
+            Value::List(
+                meta.log_messages
+                    .iter()
+                    .map(|line| Value::Str(line.clone()))
+                    .collect(),
+            )
+        }),
+        Field::TxComputeUnits => meta
+            .and_then(|meta| meta.compute_units_consumed)
+            .map_or(Value::Null, Value::Int),
+        _ => Value::Null,
+    }
+}
+
+/// `null` for a successful transaction, otherwise the decoded error like
+/// `InstructionError(2, Custom(6001))`
+fn error_value(err: Option<&yellowstone_grpc_proto::prelude::TransactionError>) -> Value {
+    match err {
+        None => Value::Null,
+        Some(err) => match convert_from::create_tx_error(Some(err)) {
+            Ok(Some(err)) => Value::Str(format!("{err:?}")),
+            _ => Value::Str("invalid error".to_owned()),
+        },
+    }
+}
+
+fn field_value(msg: &SubscribeUpdate, field: Field) -> Value {
+    let Some(update) = msg.update_oneof.as_ref() else {
+        return Value::Null;
+    };
+    match (field, update) {
+        (Field::Type, update) => Value::Str(update_kind(update).to_owned()),
+        (Field::Slot, _) => update_slot(msg).map_or(Value::Null, Value::Int),
+        (Field::Filters, _) => Value::List(
+            msg.filters
+                .iter()
+                .map(|filter| Value::Str(filter.clone()))
+                .collect(),
+        ),
+        (_, UpdateOneof::Account(update)) => {
+            let Some(account) = update.account.as_ref() else {
+                return Value::Null;
+            };
+            match field {
+                Field::Pubkey => Value::pubkey(&account.pubkey),
+                Field::Owner => Value::pubkey(&account.owner),
+                Field::Lamports => Value::Int(account.lamports),
+                Field::Executable => Value::Bool(account.executable),
+                Field::RentEpoch => Value::Int(account.rent_epoch),
+                Field::WriteVersion => Value::Int(account.write_version),
+                Field::DataLen => Value::Int(account.data.len() as u64),
+                Field::TxnSignature => account
+                    .txn_signature
+                    .as_ref()
+                    .map_or(Value::Null, |signature| {
+                        Value::Str(bs58::encode(signature).into_string())
+                    }),
+                _ => Value::Null,
+            }
+        }
+        (_, UpdateOneof::Transaction(update)) => update
+            .transaction
+            .as_ref()
+            .map_or(Value::Null, |tx| transaction_field(tx, field)),
+        (_, UpdateOneof::TransactionStatus(status)) => match field {
+            Field::TxSignature => Value::Str(bs58::encode(&status.signature).into_string()),
+            Field::TxIsVote => Value::Bool(status.is_vote),
+            Field::TxIndex => Value::Int(status.index),
+            Field::TxErr => error_value(status.err.as_ref()),
+            _ => Value::Null,
+        },
+        _ => Value::Null,
+    }
+}
+
+impl Expr {
+    fn eval(&self, msg: &SubscribeUpdate) -> Value {
+        match self {
+            Self::Literal(value) => value.clone(),
+            Self::Field(field) => field_value(msg, *field),
+            Self::List(items) => Value::List(items.iter().map(|item| item.eval(msg)).collect()),
+            Self::Not(expr) => Value::Bool(!expr.is_true(msg)),
+            Self::And(left, right) => Value::Bool(left.is_true(msg) && right.is_true(msg)),
+            Self::Or(left, right) => Value::Bool(left.is_true(msg) || right.is_true(msg)),
+            Self::Compare(op, left, right) => {
+                Value::Bool(compare(*op, &left.eval(msg), &right.eval(msg)))
+            }
+        }
+    }
+
+    fn is_true(&self, msg: &SubscribeUpdate) -> bool {
+        self.eval(msg) == Value::Bool(true)
+    }
+}
+
+fn compare(op: Op, left: &Value, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+        (Value::Str(left), Value::Str(right)) => Some(left.cmp(right)),
+        _ => None,
+    };
+    match op {
+        Op::Eq => left == right,
+        Op::Ne => left != right,
+        Op::Lt => ordering.is_some_and(|ordering| ordering.is_lt()),
+        Op::Le => ordering.is_some_and(|ordering| ordering.is_le()),
+        Op::Gt => ordering.is_some_and(|ordering| ordering.is_gt()),
+        Op::Ge => ordering.is_some_and(|ordering| ordering.is_ge()),
+        Op::Contains => match (left, right) {
+            (Value::List(items), value) => items.contains(value),
+            (Value::Str(text), Value::Str(part)) => text.contains(part.as_str()),
+            _ => false,
+        },
+        Op::In => compare(Op::Contains, right, left),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Literal(value) => write!(f, "{value}"),
+            Self::Field(field) => write!(f, "{}", field.name()),
+            Self::List(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Not(expr) => write!(f, "!{expr}"),
+            Self::And(left, right) => write!(f, "({left} && {right})"),
+            Self::Or(left, right) => write!(f, "({left} || {right})"),
+            Self::Compare(op, left, right) => {
+                let op = match op {
+                    Op::Eq => "==",
+                    Op::Ne => "!=",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                    Op::Contains => "contains",
+                    Op::In => "in",
+                };
+                write!(f, "{left} {op} {right}")
+            }
+        }
+    }
+}
+
+fn parse(source: &str) -> anyhow::Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+    };
+    let expr = parser.or()?;
+    if let Some(token) = parser.peek() {
+        anyhow::bail!("unexpected {token:?} after the expression");
+    }
+    Ok(expr)
+}
+
+/// Client-side filter expression on account, transaction and transaction status updates
+#[derive(Debug)]
+pub struct ExprFilter {
+    expr: Expr,
+    filtered: AtomicU64,
+}
+
+impl ExprFilter {
+    /// Returns `None` if `FILTER_EXPR` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(source) = env::var("FILTER_EXPR") else {
+            return Ok(None);
+        };
+        let expr =
+            parse(&source).map_err(|error| anyhow::anyhow!("invalid FILTER_EXPR: {error}"))?;
+        Ok(Some(Self {
+            expr,
+            filtered: AtomicU64::new(0),
+        }))
+    }
+
+    /// The parsed expression with explicit grouping, to check precedence
+    pub fn describe(&self) -> String {
+        self.expr.to_string()
+    }
+
+    /// Returns `true` for updates the expression is not true for, other update types are
+    /// kept
+    pub fn is_filtered(&self, msg: &SubscribeUpdate) -> bool {
+        if !matches!(
+            msg.update_oneof,
+            Some(
+                UpdateOneof::Account(_)
+                    | UpdateOneof::Transaction(_)
+                    | UpdateOneof::TransactionStatus(_)
+            )
+        ) {
+            return false;
+        }
+        let matched = self.expr.is_true(msg);
+        if !matched {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        !matched
+    }
+
+    /// Number of dropped updates
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            CompiledInstruction, Message, SubscribeUpdateAccount, SubscribeUpdateAccountInfo,
+            SubscribeUpdateSlot, SubscribeUpdateTransaction, Transaction, TransactionStatusMeta,
+        },
+    };
+
+    fn filter(source: &str) -> ExprFilter {
+        ExprFilter {
+            expr: parse(source).unwrap(),
+            filtered: AtomicU64::new(0),
+        }
+    }
+
+    fn key(byte: u8) -> Vec<u8> {
+        vec![byte; 32]
+    }
+
+    fn account(lamports: u64, data_len: usize) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["tokens".to_owned()],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: key(1),
+                    owner: key(2),
+                    lamports,
+                    data: vec![0; data_len],
+                    ..Default::default()
+                }),
+                slot: 10,
+                is_startup: false,
+            })),
+        }
+    }
+
+    fn transaction() -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                transaction: Some(SubscribeUpdateTransactionInfo {
+                    signature: vec![9; 64],
+                    transaction: Some(Transaction {
+                        message: Some(Message {
+                            account_keys: vec![key(3), key(4)],
+                            instructions: vec![CompiledInstruction {
+                                program_id_index: 1,
+                                ..Default::default()
+                            }],
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    meta: Some(TransactionStatusMeta {
+                        fee: 5000,
+                        log_messages: vec!["Program log: swap".to_owned()],
+                        loaded_writable_addresses: vec![key(5)],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                slot: 11,
+            })),
+        }
+    }
+
+    fn base58(byte: u8) -> String {
+        bs58::encode(key(byte)).into_string()
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        assert_eq!(
+            filter("!executable || slot > 1 && lamports <= 2").describe(),
+            "(!executable || (slot > 1 && lamports <= 2))"
+        );
+        assert_eq!(
+            filter("(type == \"account\" || slot >= 1_000) && data_len in [165, 82]").describe(),
+            "((type == \"account\" || slot >= 1000) && data_len in [165, 82])"
+        );
+    }
+
+    #[test]
+    fn account_fields() {
+        let msg = account(2_000_000, 165);
+        let matches = |source: &str| !filter(source).is_filtered(&msg);
+        assert!(matches(&format!(
+            "lamports > 1000000 && owner == \"{}\"",
+            base58(2)
+        )));
+        assert!(matches("data_len in [165, 82] && type == \"account\""));
+        assert!(matches("slot == 10 && filters contains \"tokens\""));
+        assert!(matches("executable == false && txn_signature == null"));
+        assert!(!matches("lamports < 1000"));
+        // Fields of other updates are null, ordering with null is false
+        assert!(matches("tx.err == null"));
+        assert!(!matches("tx.fee >= 0"));
+        assert!(!matches("lamports > \"1\""));
+    }
+
+    #[test]
+    fn transaction_fields() {
+        let msg = transaction();
+        let matches = |source: &str| !filter(source).is_filtered(&msg);
+        assert!(matches(
+            "type == \"transaction\" && tx.err == null && tx.fee == 5000"
+        ));
+        assert!(matches(&format!("tx.fee_payer == \"{}\"", base58(3))));
+        assert!(matches(&format!(
+            "tx.accounts == [\"{}\", \"{}\", \"{}\"]",
+            base58(3),
+            base58(4),
+            base58(5)
+        )));
+        assert!(matches(&format!("tx.programs contains \"{}\"", base58(4))));
+        assert!(!matches(&format!("tx.programs contains \"{}\"", base58(3))));
+        assert!(matches("tx.logs contains \"Program log: swap\""));
+        assert!(matches("\"Program log: swap\" contains \"swap\""));
+        assert!(matches("tx.compute_units == null && !tx.is_vote"));
+    }
+
+    #[test]
+    fn other_updates_are_kept_and_counted() {
+        let filter = filter("lamports > 1000");
+        let slot = SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot::default())),
+        };
+        assert!(!filter.is_filtered(&slot));
+        assert!(filter.is_filtered(&transaction()));
+        assert!(filter.is_filtered(&account(10, 0)));
+        assert!(!filter.is_filtered(&account(10_000, 0)));
+        assert_eq!(filter.filtered(), 2);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for (source, error) in [
+            ("lamport > 1", "unknown field lamport"),
+            ("owner == \"abc", "unterminated string"),
+            ("slot > 99999999999999999999", "too large"),
+            ("slot > 1 slot", "after the expression"),
+            ("slot >", "unexpected end"),
+            ("data_len in [1 2]", "in list"),
+            ("slot = 1", "unexpected character"),
+        ] {
+            let message = parse(source).unwrap_err().to_string();
+            assert!(message.contains(error), "{source}: {message}");
+        }
+    }
+}
@@ -0,0 +1,675 @@
+//! Solana JSON-RPC facade over accounts of the stream, `RPC_FACADE_ADDR`.
+//!
+//! Account updates passed to sinks are kept in memory, the versions of every account since
+//! its newest finalized one, and `POST /` answers `getAccountInfo`, `getMultipleAccounts`,
+//! `getTokenAccountBalance` and `getSlot` from them, so existing SDK clients read hot state
+//! from this process. A version is visible at the commitment its slot reached: at least the
+//! commitment of the subscription, raised by slot status updates. Accounts which were never
+//! received or have no version at the requested commitment are `null`, with `RPC_URL` such
+//! requests and all other methods are forwarded to the node instead. At most
+//! `RPC_FACADE_CAPACITY` accounts are kept, new accounts over the limit are dropped.
+
+use {
+    crate::{
+        forks::SlotRollback,
+        mints::decode_mint,
+        owners::{token_program, TokenAccount},
+        sink::{SinkHealth, UpdateSink},
+        stats::update_slot,
+    },
+    axum::{extract::State, routing::post, Json, Router},
+    base64::{engine::general_purpose::STANDARD, Engine},
+    log::{error, info},
+    serde::Deserialize,
+    serde_json::{json, Value},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{net::TcpListener, task::JoinHandle},
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeUpdate,
+    },
+};
+
+const DEFAULT_CAPACITY: usize = 1_000_000;
+/// Versions kept per account while none of them is finalized
+const MAX_VERSIONS: usize = 32;
+/// Slots of which the status is remembered
+const SLOT_STATUS_CAPACITY: usize = 4_096;
+/// Limits of Solana RPC
+const MULTIPLE_ACCOUNTS_LIMIT: usize = 100;
+const BASE58_DATA_LIMIT: usize = 128;
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Error codes of Solana RPC
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const MIN_CONTEXT_SLOT_NOT_REACHED: i64 = -32016;
+
+const PROCESSED: i32 = CommitmentLevel::Processed as i32;
+const FINALIZED: i32 = CommitmentLevel::Finalized as i32;
+
+#[derive(Debug, Clone)]
+struct Version {
+    slot: u64,
+    write_version: u64,
+    /// Commitment of the subscription the update was received with
+    commitment: i32,
+    lamports: u64,
+    owner: Pubkey,
+    executable: bool,
+    rent_epoch: u64,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    /// Versions by pubkey, oldest first
+    accounts: HashMap<Pubkey, Vec<Version>>,
+    /// Highest status by slot
+    slots: BTreeMap<u64, i32>,
+    /// Highest slot of processed, confirmed and finalized
+    highest: [Option<u64>; 3],
+}
+
+impl Cache {
+    /// Commitment the slot of the version reached
+    fn status(&self, version: &Version) -> i32 {
+        let status = match self.slots.get(&version.slot) {
+            Some(status) => *status,
+            // Older than the remembered slots, the chain moved on
+            None if self
+                .slots
+                .first_key_value()
+                .is_some_and(|(first, _)| version.slot < *first)
+                && self.highest[FINALIZED as usize].is_some_and(|slot| version.slot <= slot) =>
+            {
+                FINALIZED
+            }
+            None => PROCESSED,
+        };
+        status.max(version.commitment)
+    }
+
+    fn observe_slot(&mut self, slot: u64, status: i32) {
+        let Some(level) = usize::try_from(status)
+            .ok()
+            .filter(|level| *level < self.highest.len())
+        else {
+            return;
+        };
+        // A slot at a commitment is also at the lower ones
+        for highest in self.highest[..=level].iter_mut() {
+            *highest = (*highest).max(Some(slot));
+        }
+        let entry = self.slots.entry(slot).or_insert(status);
+        *entry = (*entry).max(status);
+        while self.slots.len() > SLOT_STATUS_CAPACITY {
+            self.slots.pop_first();
+        }
+    }
+
+    /// Add the version in order of slot and write version, updates of one account can
+    /// arrive out of order from several workers
+    fn insert(&mut self, pubkey: Pubkey, version: Version) {
+        let versions = self.accounts.entry(pubkey).or_default();
+        let key = (version.slot, version.write_version);
+        let position =
+            versions.partition_point(|existing| (existing.slot, existing.write_version) < key);
+        if versions
+            .get(position)
+            .is_some_and(|existing| (existing.slot, existing.write_version) == key)
+        {
+            return;
+        }
+        versions.insert(position, version);
+        self.prune(&pubkey);
+    }
+
+    /// Drop versions older than the newest finalized one
+    fn prune(&mut self, pubkey: &Pubkey) {
+        let Some(versions) = self.accounts.get(pubkey) else {
+            return;
+        };
+        let finalized = versions
+            .iter()
+            .rposition(|version| self.status(version) >= FINALIZED)
+            .unwrap_or(0);
+        let keep_from = finalized.max(versions.len().saturating_sub(MAX_VERSIONS));
+        if keep_from > 0 {
+            if let Some(versions) = self.accounts.get_mut(pubkey) {
+                versions.drain(..keep_from);
+            }
+        }
+    }
+
+    /// Newest version visible at the commitment
+    fn visible(&self, pubkey: &Pubkey, commitment: i32) -> Option<&Version> {
+        self.accounts
+            .get(pubkey)?
+            .iter()
+            .rev()
+            .find(|version| self.status(version) >= commitment)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RequestConfig {
+    commitment: Option<String>,
+    encoding: Option<String>,
+    data_slice: Option<DataSlice>,
+    min_context_slot: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct DataSlice {
+    offset: usize,
+    length: usize,
+}
+
+#[derive(Debug)]
+enum Reply {
+    Error {
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+    /// Not answered from the cache, for `RPC_URL`
+    Forward,
+}
+
+fn invalid_params(message: impl Into<String>) -> Reply {
+    Reply::Error {
+        code: INVALID_PARAMS,
+        message: message.into(),
+        data: None,
+    }
+}
+
+fn parse_commitment(config: &RequestConfig) -> Result<i32, Reply> {
+    let level = match config.commitment.as_deref().unwrap_or("finalized") {
+        "processed" | "recent" => CommitmentLevel::Processed,
+        "confirmed" | "single" | "singleGossip" => CommitmentLevel::Confirmed,
+        "finalized" | "max" | "root" => CommitmentLevel::Finalized,
+        other => return Err(invalid_params(format!("Invalid commitment: {other}"))),
+    };
+    Ok(level as i32)
+}
+
+fn parse_pubkey(value: Option<&Value>) -> Result<Pubkey, Reply> {
+    value
+        .and_then(Value::as_str)
+        .and_then(|pubkey| pubkey.parse().ok())
+        .ok_or_else(|| invalid_params("Invalid param: Invalid"))
+}
+
+fn parse_config(value: Option<&Value>) -> Result<RequestConfig, Reply> {
+    match value {
+        None | Some(Value::Null) => Ok(RequestConfig::default()),
+        Some(value) => serde_json::from_value(value.clone())
+            .map_err(|error| invalid_params(format!("Invalid params: {error}"))),
+    }
+}
+
+/// Account in the shape of Solana RPC, `jsonParsed` falls back to base64 as for accounts
+/// without a parser
+fn account_json(version: &Version, config: &RequestConfig, default: &str) -> Result<Value, Reply> {
+    let data = match config.data_slice {
+        Some(DataSlice { offset, length }) => {
+            let start = offset.min(version.data.len());
+            let end = offset.saturating_add(length).min(version.data.len());
+            &version.data[start..end]
+        }
+        None => &version.data[..],
+    };
+    let encoding = config.encoding.as_deref().unwrap_or(default);
+    if matches!(encoding, "binary" | "base58") && data.len() > BASE58_DATA_LIMIT {
+        return Err(Reply::Error {
+            code: INVALID_REQUEST,
+            message: format!(
+                "Encoded binary (base 58) data should be less than {BASE58_DATA_LIMIT} bytes, \
+                please use Base64 encoding."
+            ),
+            data: None,
+        });
+    }
+    let data = match encoding {
+        "binary" => json!(bs58::encode(data).into_string()),
+        "base58" => json!([bs58::encode(data).into_string(), "base58"]),
+        "base64" | "jsonParsed" => json!([STANDARD.encode(data), "base64"]),
+        "base64+zstd" => {
+            let compressed = zstd::bulk::compress(data, 0).map_err(|error| Reply::Error {
+                code: INTERNAL_ERROR,
+                message: error.to_string(),
+                data: None,
+            })?;
+            json!([STANDARD.encode(compressed), "base64+zstd"])
+        }
+        other => return Err(invalid_params(format!("Invalid encoding: {other}"))),
+    };
+    Ok(json!({
+        "data": data,
+        "executable": version.executable,
+        "lamports": version.lamports,
+        "owner": version.owner.to_string(),
+        "rentEpoch": version.rent_epoch,
+        "space": version.data.len(),
+    }))
+}
+
+/// Amount in whole tokens without trailing zeros, as `uiAmountString`
+fn ui_amount_string(amount: u64, decimals: u8) -> String {
+    let decimals = usize::from(decimals);
+    if decimals == 0 {
+        return amount.to_string();
+    }
+    let digits = format!("{amount:0>width$}", width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_owned()
+    } else {
+        format!("{whole}.{fraction}")
+    }
+}
+
+#[derive(Debug)]
+pub struct RpcFacade {
+    addr: SocketAddr,
+    /// Commitment of the subscription
+    commitment: i32,
+    capacity: usize,
+    /// `RPC_URL` for requests which are not answered from the cache
+    forward: Option<(String, reqwest::Client)>,
+    cache: Mutex<Cache>,
+    /// New accounts not cached because the cache was full
+    dropped: AtomicU64,
+    answered: AtomicU64,
+    forwarded: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl RpcFacade {
+    /// Returns `None` if `RPC_FACADE_ADDR` is not set
+    pub fn from_env(commitment: CommitmentLevel) -> anyhow::Result<Option<Self>> {
+        let Ok(addr) = env::var("RPC_FACADE_ADDR") else {
+            return Ok(None);
+        };
+        let addr = addr
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid RPC_FACADE_ADDR: {addr}"))?;
+        let capacity = match env::var("RPC_FACADE_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid RPC_FACADE_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        let forward = match env::var("RPC_URL") {
+            Ok(url) => Some((
+                url,
+                reqwest::Client::builder()
+                    .timeout(FORWARD_TIMEOUT)
+                    .build()?,
+            )),
+            Err(_) => None,
+        };
+        Ok(Some(Self {
+            addr,
+            commitment: commitment as i32,
+            capacity,
+            forward,
+            cache: Mutex::default(),
+            dropped: AtomicU64::new(0),
+            answered: AtomicU64::new(0),
+            forwarded: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }))
+    }
+
+    /// Number of cached accounts, requests answered from the cache, forwarded and failed
+    pub fn counts(&self) -> (usize, u64, u64, u64) {
+        (
+            self.cache.lock().expect("poisoned").accounts.len(),
+            self.answered.load(Ordering::Relaxed),
+            self.forwarded.load(Ordering::Relaxed),
+            self.errors.load(Ordering::Relaxed),
+        )
+    }
+
+    fn observe(&self, msg: &SubscribeUpdate) {
+        let mut cache = self.cache.lock().expect("poisoned");
+        match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Slot(update)) => cache.observe_slot(update.slot, update.status),
+            Some(UpdateOneof::Account(update)) => {
+                cache.observe_slot(update.slot, self.commitment);
+                let Some(account) = update.account.as_ref() else {
+                    return;
+                };
+                let (Ok(pubkey), Ok(owner)) = (
+                    Pubkey::try_from(account.pubkey.as_slice()),
+                    Pubkey::try_from(account.owner.as_slice()),
+                ) else {
+                    return;
+                };
+                if !cache.accounts.contains_key(&pubkey) && cache.accounts.len() >= self.capacity {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                let version = Version {
+                    slot: update.slot,
+                    write_version: account.write_version,
+                    commitment: self.commitment,
+                    lamports: account.lamports,
+                    owner,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                    data: account.data.clone(),
+                };
+                cache.insert(pubkey, version);
+            }
+            _ => {
+                if let Some(slot) = update_slot(msg) {
+                    cache.observe_slot(slot, self.commitment);
+                }
+            }
+        }
+    }
+
+    /// Context slot of a response at the commitment, at least the slot of returned accounts
+    fn context_slot<'a>(
+        cache: &Cache,
+        commitment: i32,
+        versions: impl Iterator<Item = &'a Version>,
+    ) -> u64 {
+        versions
+            .map(|version| version.slot)
+            .chain(cache.highest[commitment as usize])
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn check_min_context_slot(config: &RequestConfig, slot: u64) -> Result<(), Reply> {
+        match config.min_context_slot {
+            Some(min) if slot < min => Err(Reply::Error {
+                code: MIN_CONTEXT_SLOT_NOT_REACHED,
+                message: "Minimum context slot has not been reached".to_owned(),
+                data: Some(json!({ "contextSlot": slot })),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answer the method from the cache
+    fn call(&self, method: &str, params: &[Value]) -> Result<Value, Reply> {
+        let forward = self.forward.is_some();
+        match method {
+            "getSlot" => {
+                let config = parse_config(params.first())?;
+                let commitment = parse_commitment(&config)?;
+                let slot = self.cache.lock().expect("poisoned").highest[commitment as usize];
+                let Some(slot) = slot else {
+                    return Err(if forward {
+                        Reply::Forward
+                    } else {
+                        Reply::Error {
+                            code: INTERNAL_ERROR,
+                            message: "No slot received at this commitment yet".to_owned(),
+                            data: None,
+                        }
+                    });
+                };
+                Self::check_min_context_slot(&config, slot)?;
+                Ok(json!(slot))
+            }
+            "getAccountInfo" => {
+                let pubkey = parse_pubkey(params.first())?;
+                let config = parse_config(params.get(1))?;
+                let commitment = parse_commitment(&config)?;
+                let (version, slot) = {
+                    let cache = self.cache.lock().expect("poisoned");
+                    let version = cache.visible(&pubkey, commitment).cloned();
+                    let slot = Self::context_slot(&cache, commitment, version.iter());
+                    (version, slot)
+                };
+                if version.is_none() && forward {
+                    return Err(Reply::Forward);
+                }
+                Self::check_min_context_slot(&config, slot)?;
+                let value = match version {
+                    Some(version) => account_json(&version, &config, "binary")?,
+                    None => Value::Null,
+                };
+                Ok(json!({ "context": { "slot": slot }, "value": value }))
+            }
+            "getMultipleAccounts" => {
+                let pubkeys = params
+                    .first()
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| invalid_params("Invalid params: expected an array of pubkeys"))?
+                    .iter()
+                    .map(|pubkey| parse_pubkey(Some(pubkey)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if pubkeys.len() > MULTIPLE_ACCOUNTS_LIMIT {
+                    return Err(invalid_params(format!(
+                        "Too many inputs provided; max {MULTIPLE_ACCOUNTS_LIMIT}"
+                    )));
+                }
+                let config = parse_config(params.get(1))?;
+                let commitment = parse_commitment(&config)?;
+                let (versions, slot) = {
+                    let cache = self.cache.lock().expect("poisoned");
+                    let versions = pubkeys
+                        .iter()
+                        .map(|pubkey| cache.visible(pubkey, commitment).cloned())
+                        .collect::<Vec<_>>();
+                    let slot = Self::context_slot(&cache, commitment, versions.iter().flatten());
+                    (versions, slot)
+                };
+                if versions.iter().any(Option::is_none) && forward {
+                    return Err(Reply::Forward);
+                }
+                Self::check_min_context_slot(&config, slot)?;
+                let value = versions
+                    .iter()
+                    .map(|version| match version {
+                        Some(version) => account_json(version, &config, "base64"),
+                        None => Ok(Value::Null),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(json!({ "context": { "slot": slot }, "value": value }))
+            }
+            "getTokenAccountBalance" => {
+                let pubkey = parse_pubkey(params.first())?;
+                let config = parse_config(params.get(1))?;
+                let commitment = parse_commitment(&config)?;
+                let cache = self.cache.lock().expect("poisoned");
+                let Some(version) = cache.visible(&pubkey, commitment) else {
+                    return Err(if forward {
+                        Reply::Forward
+                    } else {
+                        invalid_params("Invalid param: could not find account")
+                    });
+                };
+                let account = token_program(version.owner.as_ref()).and_then(|program| {
+                    TokenAccount::decode(program, &version.data, version.slot, 0)
+                        .map(|account| (program, account))
+                });
+                let Some((program, account)) = account else {
+                    return Err(invalid_params("Invalid param: not a Token account"));
+                };
+                let mint = cache
+                    .visible(&account.mint, commitment)
+                    .and_then(|mint| decode_mint(program, &mint.data));
+                let Some((_, decimals)) = mint else {
+                    return Err(if forward {
+                        Reply::Forward
+                    } else {
+                        invalid_params("Invalid param: could not find mint")
+                    });
+                };
+                let slot = Self::context_slot(&cache, commitment, [version].into_iter());
+                Self::check_min_context_slot(&config, slot)?;
+                Ok(json!({
+                    "context": { "slot": slot },
+                    "value": {
+                        "amount": account.amount.to_string(),
+                        "decimals": decimals,
+                        "uiAmount": account.amount as f64 / 10f64.powi(decimals.into()),
+                        "uiAmountString": ui_amount_string(account.amount, decimals),
+                    },
+                }))
+            }
+            _ if forward => Err(Reply::Forward),
+            _ => Err(Reply::Error {
+                code: METHOD_NOT_FOUND,
+                message: "Method not found".to_owned(),
+                data: None,
+            }),
+        }
+    }
+
+    /// Response to one request of the body
+    async fn answer(&self, request: Value) -> Value {
+        let parsed = match serde_json::from_value::<Request>(request.clone()) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                return json!({
+                    "jsonrpc": "2.0",
+                    "error": { "code": INVALID_REQUEST, "message": "Invalid request" },
+                    "id": request.get("id").cloned().unwrap_or(Value::Null),
+                });
+            }
+        };
+        let error = match self.call(&parsed.method, &parsed.params) {
+            Ok(result) => {
+                self.answered.fetch_add(1, Ordering::Relaxed);
+                return json!({ "jsonrpc": "2.0", "result": result, "id": parsed.id });
+            }
+            Err(Reply::Forward) => match self.forward(&request).await {
+                Ok(response) => {
+                    self.forwarded.fetch_add(1, Ordering::Relaxed);
+                    return response;
+                }
+                Err(error) => json!({
+                    "code": INTERNAL_ERROR,
+                    "message": format!("failed to forward to RPC_URL: {error}"),
+                }),
+            },
+            Err(Reply::Error {
+                code,
+                message,
+                data,
+            }) => match data {
+                Some(data) => json!({ "code": code, "message": message, "data": data }),
+                None => json!({ "code": code, "message": message }),
+            },
+        };
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        json!({ "jsonrpc": "2.0", "error": error, "id": parsed.id })
+    }
+
+    async fn forward(&self, request: &Value) -> anyhow::Result<Value> {
+        let (url, http) = self.forward.as_ref().expect("forward is set");
+        let response = http
+            .post(url)
+            .json(request)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?
+            .json()
+            .await?;
+        Ok(response)
+    }
+}
+
+/// Caches accounts and slots of updates passed to sinks in `RpcFacade`
+pub struct RpcFacadeSink(pub Arc<RpcFacade>);
+
+impl UpdateSink for RpcFacadeSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        self.0.observe(msg);
+        Ok(())
+    }
+
+    /// Versions of dead forks are never visible again
+    fn rollback(&self, rollback: &SlotRollback) {
+        let mut cache = self.0.cache.lock().expect("poisoned");
+        for versions in cache.accounts.values_mut() {
+            versions.retain(|version| {
+                !rollback
+                    .slots
+                    .iter()
+                    .any(|rolled_back| rolled_back.slot == version.slot)
+            });
+        }
+        cache.accounts.retain(|_, versions| !versions.is_empty());
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "rpc_facade",
+            dropped: self.0.dropped.load(Ordering::Relaxed),
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+}
+
+pub async fn spawn(facade: Arc<RpcFacade>) -> anyhow::Result<JoinHandle<()>> {
+    let addr = facade.addr;
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|error| anyhow::anyhow!("failed to bind RPC_FACADE_ADDR {addr}: {error}"))?;
+    info!("rpc facade listening on http://{addr}");
+
+    let app = Router::new().route("/", post(handle)).with_state(facade);
+    Ok(tokio::spawn(async move {
+        if let Err(error) = axum::serve(listener, app).await {
+            error!("rpc facade failed: {error}");
+        }
+    }))
+}
+
+/// A request or a batch of requests
+async fn handle(State(facade): State<Arc<RpcFacade>>, body: String) -> Json<Value> {
+    let response = match serde_json::from_str::<Value>(&body) {
+        Ok(Value::Array(requests)) => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(facade.answer(request).await);
+            }
+            Value::Array(responses)
+        }
+        Ok(request) => facade.answer(request).await,
+        Err(_) => json!({
+            "jsonrpc": "2.0",
+            "error": { "code": PARSE_ERROR, "message": "Parse error" },
+            "id": null,
+        }),
+    };
+    Json(response)
+}
@@ -0,0 +1,403 @@
+//! Named subscription filters configured with environment variables.
+//!
+//! `ACCOUNTS_FILTER_<name>_<FIELD>`, `TRANSACTIONS_FILTER_<name>_<FIELD>` and
+//! `TRANSACTIONS_STATUS_FILTER_<name>_<FIELD>` define one filter per `<name>`, the name is
+//! sent to the server as is and returned in `SubscribeUpdate::filters` of matched messages.
+//! The same filters can be defined in `FILTERS_PATH`, see `reload`.
+//!
+//! `ACCOUNTS_MIN_LAMPORTS` and `ACCOUNTS_MAX_LAMPORTS` have no server-side equivalent, they
+//! are applied by the client to received account updates of all filters. Likewise
+//! `TRANSACTIONS_LOG_CONTAINS` and `TRANSACTIONS_LOG_REGEX` keep only transactions with a
+//! matching log message, and `TRANSACTIONS_FEE_PAYER` only transactions paid by one of the
+//! listed pubkeys.
+
+use {
+    regex::Regex,
+    serde::Deserialize,
+    std::{
+        collections::{BTreeMap, HashSet},
+        env,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+    yellowstone_grpc_proto::prelude::{
+        subscribe_request_filter_accounts_filter::Filter as AccountsFilterDataOneof,
+        subscribe_request_filter_accounts_filter_memcmp::Data as AccountsFilterMemcmpOneof,
+        subscribe_update::UpdateOneof, SubscribeRequestFilterAccounts,
+        SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
+        SubscribeRequestFilterTransactions, SubscribeUpdate,
+    },
+};
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsFilterArgs {
+    pub account: Vec<String>,
+    pub owner: Vec<String>,
+    /// Format: `offset,data in base58`
+    pub memcmp: Vec<String>,
+    pub datasize: Option<u64>,
+    pub token_account_state: bool,
+}
+
+impl NamedFilter for AccountsFilterArgs {
+    const FIELDS: &'static [&'static str] = &[
+        "ACCOUNT",
+        "OWNER",
+        "MEMCMP",
+        "DATASIZE",
+        "TOKEN_ACCOUNT_STATE",
+    ];
+
+    fn set(&mut self, field: &str, value: &str) -> anyhow::Result<()> {
+        match field {
+            "ACCOUNT" => self.account = parse_list(value),
+            "OWNER" => self.owner = parse_list(value),
+            "MEMCMP" => self.memcmp = parse_list(value),
+            "DATASIZE" => self.datasize = Some(value.parse()?),
+            "TOKEN_ACCOUNT_STATE" => self.token_account_state = value.parse()?,
+            _ => unreachable!("unknown field {field}"),
+        }
+        Ok(())
+    }
+}
+
+impl AccountsFilterArgs {
+    pub fn to_filter(&self) -> anyhow::Result<SubscribeRequestFilterAccounts> {
+        let mut filters = vec![];
+        for filter in self.memcmp.iter() {
+            match filter.split_once(',') {
+                Some((offset, data)) => {
+                    filters.push(SubscribeRequestFilterAccountsFilter {
+                        filter: Some(AccountsFilterDataOneof::Memcmp(
+                            SubscribeRequestFilterAccountsFilterMemcmp {
+                                offset: offset
+                                    .parse()
+                                    .map_err(|_| anyhow::anyhow!("invalid offset"))?,
+                                data: Some(AccountsFilterMemcmpOneof::Base58(
+                                    data.trim().to_string(),
+                                )),
+                            },
+                        )),
+                    });
+                }
+                _ => anyhow::bail!("invalid memcmp"),
+            }
+        }
+        if let Some(datasize) = self.datasize {
+            filters.push(SubscribeRequestFilterAccountsFilter {
+                filter: Some(AccountsFilterDataOneof::Datasize(datasize)),
+            });
+        }
+        if self.token_account_state {
+            filters.push(SubscribeRequestFilterAccountsFilter {
+                filter: Some(AccountsFilterDataOneof::TokenAccountState(true)),
+            });
+        }
+
+        Ok(SubscribeRequestFilterAccounts {
+            account: self.account.clone(),
+            owner: self.owner.clone(),
+            filters,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactionsFilterArgs {
+    pub vote: Option<bool>,
+    pub failed: Option<bool>,
+    pub signature: Option<String>,
+    pub account_include: Vec<String>,
+    pub account_exclude: Vec<String>,
+    pub account_required: Vec<String>,
+}
+
+impl NamedFilter for TransactionsFilterArgs {
+    const FIELDS: &'static [&'static str] = &[
+        "VOTE",
+        "FAILED",
+        "SIGNATURE",
+        "ACCOUNT_INCLUDE",
+        "ACCOUNT_EXCLUDE",
+        "ACCOUNT_REQUIRED",
+    ];
+
+    fn set(&mut self, field: &str, value: &str) -> anyhow::Result<()> {
+        match field {
+            "VOTE" => self.vote = Some(value.parse()?),
+            "FAILED" => self.failed = Some(value.parse()?),
+            "SIGNATURE" => self.signature = Some(value.to_owned()),
+            "ACCOUNT_INCLUDE" => self.account_include = parse_list(value),
+            "ACCOUNT_EXCLUDE" => self.account_exclude = parse_list(value),
+            "ACCOUNT_REQUIRED" => self.account_required = parse_list(value),
+            _ => unreachable!("unknown field {field}"),
+        }
+        Ok(())
+    }
+}
+
+impl TransactionsFilterArgs {
+    pub fn to_filter(&self) -> SubscribeRequestFilterTransactions {
+        SubscribeRequestFilterTransactions {
+            vote: self.vote,
+            failed: self.failed,
+            signature: self.signature.clone(),
+            account_include: self.account_include.clone(),
+            account_exclude: self.account_exclude.clone(),
+            account_required: self.account_required.clone(),
+        }
+    }
+}
+
+/// All named filters defined in the environment
+#[derive(Debug, Default, Clone)]
+pub struct NamedFilters {
+    pub accounts: BTreeMap<String, AccountsFilterArgs>,
+    pub transactions: BTreeMap<String, TransactionsFilterArgs>,
+    pub transactions_status: BTreeMap<String, TransactionsFilterArgs>,
+}
+
+impl NamedFilters {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut filters = Self::default();
+        for (key, value) in env::vars() {
+            // `TRANSACTIONS_STATUS_FILTER_` first, `TRANSACTIONS_` is a prefix of it
+            let result = if let Some(rest) = key.strip_prefix("TRANSACTIONS_STATUS_FILTER_") {
+                set_named(&mut filters.transactions_status, rest, &value)
+            } else if let Some(rest) = key.strip_prefix("TRANSACTIONS_FILTER_") {
+                set_named(&mut filters.transactions, rest, &value)
+            } else if let Some(rest) = key.strip_prefix("ACCOUNTS_FILTER_") {
+                set_named(&mut filters.accounts, rest, &value)
+            } else {
+                continue;
+            };
+            result.map_err(|error| anyhow::anyhow!("invalid {key}: {error}"))?;
+        }
+        Ok(filters)
+    }
+}
+
+/// Client-side lamports range of account updates, bounds are inclusive
+#[derive(Debug)]
+pub struct LamportsFilter {
+    min: Option<u64>,
+    max: Option<u64>,
+    filtered: AtomicU64,
+}
+
+impl LamportsFilter {
+    /// Returns `None` if neither `ACCOUNTS_MIN_LAMPORTS` nor `ACCOUNTS_MAX_LAMPORTS` is set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let parse = |key: &str| -> anyhow::Result<Option<u64>> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+        let (min, max) = (
+            parse("ACCOUNTS_MIN_LAMPORTS")?,
+            parse("ACCOUNTS_MAX_LAMPORTS")?,
+        );
+        if let (Some(min), Some(max)) = (min, max) {
+            anyhow::ensure!(
+                min <= max,
+                "ACCOUNTS_MIN_LAMPORTS is greater than ACCOUNTS_MAX_LAMPORTS"
+            );
+        }
+        if min.is_none() && max.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            min,
+            max,
+            filtered: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns `true` for account updates outside of the range, other updates are kept
+    pub fn is_filtered(&self, msg: &SubscribeUpdate) -> bool {
+        let Some(UpdateOneof::Account(update)) = msg.update_oneof.as_ref() else {
+            return false;
+        };
+        let Some(account) = update.account.as_ref() else {
+            return false;
+        };
+        let outside = self.min.is_some_and(|min| account.lamports < min)
+            || self.max.is_some_and(|max| account.lamports > max);
+        if outside {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        outside
+    }
+
+    /// Number of dropped account updates
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+}
+
+/// Client-side match of transaction log messages. With both variables set a transaction
+/// needs a line containing the substring and a line matching the regex, not necessarily the
+/// same one.
+#[derive(Debug)]
+pub struct LogFilter {
+    contains: Option<String>,
+    regex: Option<Regex>,
+    filtered: AtomicU64,
+    passed: AtomicU64,
+}
+
+impl LogFilter {
+    /// Returns `None` if neither `TRANSACTIONS_LOG_CONTAINS` nor `TRANSACTIONS_LOG_REGEX` is
+    /// set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let contains = env::var("TRANSACTIONS_LOG_CONTAINS").ok();
+        let regex = env::var("TRANSACTIONS_LOG_REGEX")
+            .ok()
+            .map(|value| Regex::new(&value))
+            .transpose()
+            .map_err(|error| anyhow::anyhow!("invalid TRANSACTIONS_LOG_REGEX: {error}"))?;
+        if contains.is_none() && regex.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            contains,
+            regex,
+            filtered: AtomicU64::new(0),
+            passed: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns `true` for transactions without a matching log message, including those
+    /// without logs, other updates are kept
+    pub fn is_filtered(&self, msg: &SubscribeUpdate) -> bool {
+        let Some(UpdateOneof::Transaction(update)) = msg.update_oneof.as_ref() else {
+            return false;
+        };
+        let logs = update
+            .transaction
+            .as_ref()
+            .and_then(|tx| tx.meta.as_ref())
+            .map(|meta| meta.log_messages.as_slice())
+            .unwrap_or_default();
+        let matched = self
+            .contains
+            .as_ref()
+            .is_none_or(|contains| logs.iter().any(|line| line.contains(contains.as_str())))
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|regex| logs.iter().any(|line| regex.is_match(line)));
+        let counter = if matched {
+            &self.passed
+        } else {
+            &self.filtered
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        !matched
+    }
+
+    /// Number of dropped transactions
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
+    /// Number of transactions with a matching log message
+    pub fn passed(&self) -> u64 {
+        self.passed.load(Ordering::Relaxed)
+    }
+}
+
+/// Client-side match of the fee payer, the first account key of the transaction message.
+/// `account_include` of the server matches a pubkey in any position.
+#[derive(Debug)]
+pub struct FeePayerFilter {
+    payers: HashSet<Vec<u8>>,
+    filtered: AtomicU64,
+}
+
+impl FeePayerFilter {
+    /// Returns `None` if `TRANSACTIONS_FEE_PAYER` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("TRANSACTIONS_FEE_PAYER") else {
+            return Ok(None);
+        };
+        let payers = parse_list(&value)
+            .into_iter()
+            .filter(|pubkey| !pubkey.is_empty())
+            .map(|pubkey| {
+                bs58::decode(&pubkey)
+                    .into_vec()
+                    .ok()
+                    .filter(|decoded| decoded.len() == 32)
+                    .ok_or_else(|| anyhow::anyhow!("invalid TRANSACTIONS_FEE_PAYER: {pubkey}"))
+            })
+            .collect::<anyhow::Result<HashSet<_>>>()?;
+        anyhow::ensure!(
+            !payers.is_empty(),
+            "invalid TRANSACTIONS_FEE_PAYER, no pubkeys"
+        );
+
+        Ok(Some(Self {
+            payers,
+            filtered: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns `true` for transactions paid by other pubkeys, other updates are kept
+    pub fn is_filtered(&self, msg: &SubscribeUpdate) -> bool {
+        let Some(UpdateOneof::Transaction(update)) = msg.update_oneof.as_ref() else {
+            return false;
+        };
+        let matched = update
+            .transaction
+            .as_ref()
+            .and_then(|tx| tx.transaction.as_ref())
+            .and_then(|tx| tx.message.as_ref())
+            .and_then(|message| message.account_keys.first())
+            .is_some_and(|payer| self.payers.contains(payer));
+        if !matched {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        !matched
+    }
+
+    /// Number of dropped transactions
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+}
+
+trait NamedFilter: Default {
+    const FIELDS: &'static [&'static str];
+
+    fn set(&mut self, field: &str, value: &str) -> anyhow::Result<()>;
+}
+
+/// Split `<name>_<FIELD>` and set the field of the filter `<name>`
+fn set_named<T: NamedFilter>(
+    filters: &mut BTreeMap<String, T>,
+    key: &str,
+    value: &str,
+) -> anyhow::Result<()> {
+    let (name, field) = T::FIELDS
+        .iter()
+        .filter_map(|field| {
+            let name = key.strip_suffix(field)?.strip_suffix('_')?;
+            (!name.is_empty()).then_some((name, *field))
+        })
+        .max_by_key(|(_, field)| field.len())
+        .ok_or_else(|| anyhow::anyhow!("expected <name>_<{}>", T::FIELDS.join("|")))?;
+    filters
+        .entry(name.to_owned())
+        .or_default()
+        .set(field, value)
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim().to_string()).collect()
+}
@@ -0,0 +1,296 @@
+//! Slots of dead forks, detected from slot status updates.
+//!
+//! Every processed or confirmed slot is remembered with its parent. When a slot is finalized
+//! its ancestors are followed through the remembered parents down to the previous finalized
+//! slot, and every remembered slot in between which is not one of them was on a fork that
+//! will never be finalized. Such slots are reported once as `SlotRollback` to sinks, event
+//! bus subscribers and the log, so consumers can drop what they received at those slots.
+
+use {
+    log::warn,
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        collections::{BTreeMap, HashSet},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeUpdate,
+    },
+};
+
+const DEFAULT_MAX_SLOTS: usize = 10_000;
+
+/// Slot seen on a fork which was not finalized
+#[derive(Debug, Clone, Serialize)]
+pub struct RolledBackSlot {
+    pub slot: u64,
+    pub parent: Option<u64>,
+    /// Highest status the slot reached, `processed` or `confirmed`
+    pub status: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotRollback {
+    /// Finalized slot whose ancestors don't include the rolled back slots
+    pub finalized: u64,
+    pub slots: Vec<RolledBackSlot>,
+}
+
+impl SlotRollback {
+    /// Same shape as updates encoded by `json::update_json`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": "slot_rolled_back",
+            "slot": self.finalized,
+            "slot_rolled_back": self,
+        })
+    }
+
+    /// `123 (processed), 124 (confirmed)`
+    pub fn describe(&self) -> String {
+        self.slots
+            .iter()
+            .map(|slot| format!("{} ({})", slot.slot, slot.status))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SlotState {
+    parent: Option<u64>,
+    status: i32,
+}
+
+#[derive(Debug, Default)]
+struct ForkState {
+    /// Slots above the last finalized slot
+    slots: BTreeMap<u64, SlotState>,
+    finalized: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ForkDetector {
+    /// Remembered slots above the last finalized slot, the oldest are forgotten first
+    max_slots: usize,
+    state: Mutex<ForkState>,
+    rollbacks: AtomicU64,
+    rolled_back: AtomicU64,
+}
+
+impl ForkDetector {
+    /// Returns `None` unless `FORK_DETECTION=true`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let enabled = match env::var("FORK_DETECTION") {
+            Ok(value) => value
+                .parse::<bool>()
+                .map_err(|_| anyhow::anyhow!("invalid FORK_DETECTION"))?,
+            Err(_) => false,
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let max_slots = match env::var("FORK_MAX_SLOTS") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|max_slots| *max_slots > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid FORK_MAX_SLOTS"))?,
+            Err(_) => DEFAULT_MAX_SLOTS,
+        };
+        Ok(Some(Self::new(max_slots)))
+    }
+
+    fn new(max_slots: usize) -> Self {
+        Self {
+            max_slots,
+            state: Mutex::default(),
+            rollbacks: AtomicU64::new(0),
+            rolled_back: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of detected rollbacks
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks.load(Ordering::Relaxed)
+    }
+
+    /// Number of rolled back slots in all rollbacks
+    pub fn rolled_back(&self) -> u64 {
+        self.rolled_back.load(Ordering::Relaxed)
+    }
+
+    /// Remember a slot status, returns the rolled back slots when a finalized slot shows
+    /// that some remembered slots are not its ancestors
+    pub fn observe(&self, msg: &SubscribeUpdate) -> Option<SlotRollback> {
+        let Some(UpdateOneof::Slot(update)) = msg.update_oneof.as_ref() else {
+            return None;
+        };
+        let mut state = self.state.lock().expect("poisoned");
+        let previous = state.finalized;
+        if previous.is_some_and(|finalized| update.slot <= finalized) {
+            return None;
+        }
+
+        let entry = state.slots.entry(update.slot).or_insert(SlotState {
+            parent: None,
+            status: update.status,
+        });
+        entry.parent = update.parent.or(entry.parent);
+        entry.status = entry.status.max(update.status);
+        if update.status != CommitmentLevel::Finalized as i32 {
+            while state.slots.len() > self.max_slots {
+                state.slots.pop_first();
+            }
+            return None;
+        }
+
+        // Ancestors down to the previous finalized slot, or to the first slot whose parent
+        // is unknown: slots below it can't be judged
+        let mut ancestors = HashSet::new();
+        let mut lowest = update.slot;
+        loop {
+            ancestors.insert(lowest);
+            match state.slots.get(&lowest).and_then(|slot| slot.parent) {
+                Some(parent) if previous.is_none_or(|finalized| parent > finalized) => {
+                    lowest = parent;
+                }
+                Some(_) => {
+                    lowest = previous.unwrap_or_default();
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let slots = state
+            .slots
+            .range(lowest + 1..update.slot)
+            .filter(|(slot, _)| !ancestors.contains(*slot))
+            .map(|(slot, state)| RolledBackSlot {
+                slot: *slot,
+                parent: state.parent,
+                status: if state.status >= CommitmentLevel::Confirmed as i32 {
+                    "confirmed"
+                } else {
+                    "processed"
+                },
+            })
+            .collect::<Vec<_>>();
+        state.slots = state.slots.split_off(&(update.slot + 1));
+        state.finalized = Some(update.slot);
+        drop(state);
+
+        if slots.is_empty() {
+            return None;
+        }
+        let rollback = SlotRollback {
+            finalized: update.slot,
+            slots,
+        };
+        self.rollbacks.fetch_add(1, Ordering::Relaxed);
+        self.rolled_back
+            .fetch_add(rollback.slots.len() as u64, Ordering::Relaxed);
+        warn!(
+            "slots rolled back, not ancestors of finalized slot {}: {}",
+            rollback.finalized,
+            rollback.describe()
+        );
+        Some(rollback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, yellowstone_grpc_proto::prelude::SubscribeUpdateSlot};
+
+    fn slot(slot: u64, parent: u64, status: CommitmentLevel) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: Some(parent),
+                status: status as i32,
+            })),
+        }
+    }
+
+    fn rolled_back(rollback: Option<SlotRollback>) -> Vec<(u64, &'static str)> {
+        rollback
+            .map(|rollback| {
+                rollback
+                    .slots
+                    .iter()
+                    .map(|slot| (slot.slot, slot.status))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn reports_slots_which_are_not_ancestors_of_finalized() {
+        let forks = ForkDetector::new(DEFAULT_MAX_SLOTS);
+        assert!(forks
+            .observe(&slot(100, 99, CommitmentLevel::Finalized))
+            .is_none());
+        // 102 and 103 are on a fork from 101, 104 builds on 101 as well
+        for (n, parent) in [(101, 100), (102, 101), (103, 102), (104, 101)] {
+            assert!(forks
+                .observe(&slot(n, parent, CommitmentLevel::Processed))
+                .is_none());
+        }
+        forks.observe(&slot(102, 101, CommitmentLevel::Confirmed));
+
+        let rollback = forks.observe(&slot(104, 101, CommitmentLevel::Finalized));
+        assert_eq!(
+            rollback.as_ref().map(|rollback| rollback.finalized),
+            Some(104)
+        );
+        assert_eq!(
+            rolled_back(rollback),
+            [(102, "confirmed"), (103, "processed")]
+        );
+        assert_eq!((forks.rollbacks(), forks.rolled_back()), (1, 2));
+
+        // Slots at or below the finalized one are ignored, a straight chain rolls nothing back
+        assert!(forks
+            .observe(&slot(103, 102, CommitmentLevel::Confirmed))
+            .is_none());
+        forks.observe(&slot(105, 104, CommitmentLevel::Processed));
+        assert!(forks
+            .observe(&slot(106, 105, CommitmentLevel::Finalized))
+            .is_none());
+        assert_eq!(forks.rollbacks(), 1);
+    }
+
+    #[test]
+    fn forgets_oldest_slots_over_max_slots() {
+        let forks = ForkDetector::new(2);
+        forks.observe(&slot(10, 9, CommitmentLevel::Finalized));
+        // 11 is on a fork but forgotten before 14 is finalized
+        for (n, parent) in [(11, 10), (12, 10), (13, 12)] {
+            forks.observe(&slot(n, parent, CommitmentLevel::Processed));
+        }
+        assert_eq!(
+            forks
+                .state
+                .lock()
+                .unwrap()
+                .slots
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            [12, 13]
+        );
+        assert!(forks
+            .observe(&slot(14, 13, CommitmentLevel::Finalized))
+            .is_none());
+        assert_eq!(forks.rollbacks(), 0);
+        assert!(forks.state.lock().unwrap().slots.is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+//! Gaps in the received stream, detected from slot and account updates.
+//!
+//! Slots follow each other, so a slot update whose slot is more than one above the highest
+//! slot seen before means the slots in between were not received, unless its parent shows
+//! that the cluster skipped them. Write versions of an account only grow, so an update with a
+//! lower write version than the previous update of the same pubkey was reordered or comes
+//! from another validator. Both are reported as `Gap` to sinks, event bus subscribers and the
+//! log, so consumers can tell whether their capture is complete.
+
+use {
+    log::warn,
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_MAX_ACCOUNTS: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Gap {
+    /// Slots `first..=last` were not received between `previous` and `slot`
+    Slot {
+        previous: u64,
+        slot: u64,
+        first: u64,
+        last: u64,
+    },
+    /// Account update with a lower write version than the previous one of the pubkey
+    WriteVersion {
+        /// Base58
+        pubkey: String,
+        previous_slot: u64,
+        previous_write_version: u64,
+        slot: u64,
+        write_version: u64,
+    },
+}
+
+impl Gap {
+    /// `slot` or `write_version`
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Slot { .. } => "slot",
+            Self::WriteVersion { .. } => "write_version",
+        }
+    }
+
+    pub const fn slot(&self) -> u64 {
+        match self {
+            Self::Slot { slot, .. } | Self::WriteVersion { slot, .. } => *slot,
+        }
+    }
+
+    /// Number of slots which were not received, 0 for write versions
+    pub const fn missed_slots(&self) -> u64 {
+        match self {
+            Self::Slot { first, last, .. } => *last - *first + 1,
+            Self::WriteVersion { .. } => 0,
+        }
+    }
+
+    /// Same shape as updates encoded by `json::update_json`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": "gap",
+            "slot": self.slot(),
+            "gap": self,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Slot {
+                previous,
+                slot,
+                first,
+                last,
+            } => format!(
+                "slots {first}..={last} not received between slot {previous} and {slot}"
+            ),
+            Self::WriteVersion {
+                pubkey,
+                previous_slot,
+                previous_write_version,
+                slot,
+                write_version,
+            } => format!(
+                "account {pubkey} write version {write_version} at slot {slot} after {previous_write_version} at slot {previous_slot}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AccountVersion {
+    slot: u64,
+    write_version: u64,
+    /// Position in `GapState::order`
+    tick: u64,
+}
+
+#[derive(Debug, Default)]
+struct GapState {
+    highest_slot: Option<u64>,
+    accounts: HashMap<Vec<u8>, AccountVersion>,
+    /// Pubkeys by last update, the least recently updated is forgotten first
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub struct GapDetector {
+    max_accounts: usize,
+    state: Mutex<GapState>,
+    slot_gaps: AtomicU64,
+    missed_slots: AtomicU64,
+    write_version_gaps: AtomicU64,
+}
+
+impl GapDetector {
+    /// Returns `None` unless `GAP_DETECTION=true`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let enabled = match env::var("GAP_DETECTION") {
+            Ok(value) => value
+                .parse::<bool>()
+                .map_err(|_| anyhow::anyhow!("invalid GAP_DETECTION"))?,
+            Err(_) => false,
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let max_accounts = match env::var("GAP_MAX_ACCOUNTS") {
+            Ok(value) => value
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("invalid GAP_MAX_ACCOUNTS"))?,
+            Err(_) => DEFAULT_MAX_ACCOUNTS,
+        };
+        Ok(Some(Self {
+            max_accounts,
+            state: Mutex::default(),
+            slot_gaps: AtomicU64::new(0),
+            missed_slots: AtomicU64::new(0),
+            write_version_gaps: AtomicU64::new(0),
+        }))
+    }
+
+    /// Number of gaps between slot updates
+    pub fn slot_gaps(&self) -> u64 {
+        self.slot_gaps.load(Ordering::Relaxed)
+    }
+
+    /// Number of slots not received in all slot gaps
+    pub fn missed_slots(&self) -> u64 {
+        self.missed_slots.load(Ordering::Relaxed)
+    }
+
+    /// Number of account updates whose write version went backwards
+    pub fn write_version_gaps(&self) -> u64 {
+        self.write_version_gaps.load(Ordering::Relaxed)
+    }
+
+    /// Check the update against the previous ones, returns the gap it reveals
+    pub fn observe(&self, msg: &SubscribeUpdate) -> Option<Gap> {
+        let gap = match msg.update_oneof.as_ref()? {
+            UpdateOneof::Slot(update) => self.observe_slot(update.slot, update.parent),
+            UpdateOneof::Account(update) if !update.is_startup => {
+                let account = update.account.as_ref()?;
+                self.observe_account(&account.pubkey, update.slot, account.write_version)
+            }
+            _ => None,
+        }?;
+        match &gap {
+            Gap::Slot { .. } => {
+                self.slot_gaps.fetch_add(1, Ordering::Relaxed);
+                self.missed_slots
+                    .fetch_add(gap.missed_slots(), Ordering::Relaxed);
+            }
+            Gap::WriteVersion { .. } => {
+                self.write_version_gaps.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        warn!("gap: {}", gap.describe());
+        Some(gap)
+    }
+
+    fn observe_slot(&self, slot: u64, parent: Option<u64>) -> Option<Gap> {
+        let mut state = self.state.lock().expect("poisoned");
+        let previous = state.highest_slot;
+        // Statuses of older slots arrive after newer slots are processed
+        if previous.is_some_and(|highest| slot <= highest) {
+            return None;
+        }
+        state.highest_slot = Some(slot);
+        let previous = previous?;
+        // Slots between the parent and the slot were skipped by the cluster, the parent and
+        // slots below it down to the previous one may be missing
+        let last = match parent {
+            Some(parent) if parent < slot => parent,
+            _ => slot - 1,
+        };
+        (last > previous).then_some(Gap::Slot {
+            previous,
+            slot,
+            first: previous + 1,
+            last,
+        })
+    }
+
+    fn observe_account(&self, pubkey: &[u8], slot: u64, write_version: u64) -> Option<Gap> {
+        if self.max_accounts == 0 {
+            return None;
+        }
+        let mut state = self.state.lock().expect("poisoned");
+        state.tick += 1;
+        let tick = state.tick;
+        let current = AccountVersion {
+            slot,
+            write_version,
+            tick,
+        };
+        let Some(previous) = state.accounts.get(pubkey).copied() else {
+            state.accounts.insert(pubkey.to_vec(), current);
+            state.order.insert(tick, pubkey.to_vec());
+            while state.accounts.len() > self.max_accounts {
+                let Some((_, oldest)) = state.order.pop_first() else {
+                    break;
+                };
+                state.accounts.remove(&oldest);
+            }
+            return None;
+        };
+
+        // An equal write version is a duplicate, left to deduplication
+        let gap = (write_version < previous.write_version).then(|| Gap::WriteVersion {
+            pubkey: bs58::encode(pubkey).into_string(),
+            previous_slot: previous.slot,
+            previous_write_version: previous.write_version,
+            slot,
+            write_version,
+        });
+        // Later updates are compared with the highest write version
+        let kept = if gap.is_some() {
+            AccountVersion { tick, ..previous }
+        } else {
+            current
+        };
+        state.order.remove(&previous.tick);
+        state.order.insert(tick, pubkey.to_vec());
+        state.accounts.insert(pubkey.to_vec(), kept);
+        gap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateSlot,
+        },
+    };
+
+    fn detector(max_accounts: usize) -> GapDetector {
+        GapDetector {
+            max_accounts,
+            state: Mutex::default(),
+            slot_gaps: AtomicU64::new(0),
+            missed_slots: AtomicU64::new(0),
+            write_version_gaps: AtomicU64::new(0),
+        }
+    }
+
+    fn slot(slot: u64, parent: Option<u64>) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn account(pubkey: u8, slot: u64, write_version: u64, is_startup: bool) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: vec![pubkey; 32],
+                    write_version,
+                    ..Default::default()
+                }),
+                slot,
+                is_startup,
+            })),
+        }
+    }
+
+    fn slot_gap(gap: Option<Gap>) -> Option<(u64, u64, u64, u64)> {
+        match gap? {
+            Gap::Slot {
+                previous,
+                slot,
+                first,
+                last,
+            } => Some((previous, slot, first, last)),
+            gap => panic!("unexpected {gap:?}"),
+        }
+    }
+
+    #[test]
+    fn reports_missing_slots() {
+        let detector = detector(0);
+        assert!(detector.observe(&slot(10, Some(9))).is_none());
+        assert!(detector.observe(&slot(11, Some(10))).is_none());
+        assert_eq!(
+            slot_gap(detector.observe(&slot(14, None))),
+            Some((11, 14, 12, 13))
+        );
+        // Older slots and other statuses of seen slots
+        assert!(detector.observe(&slot(12, Some(11))).is_none());
+        assert!(detector.observe(&slot(14, Some(13))).is_none());
+        assert_eq!(detector.slot_gaps(), 1);
+        assert_eq!(detector.missed_slots(), 2);
+    }
+
+    #[test]
+    fn slots_skipped_by_the_cluster_are_not_gaps() {
+        let detector = detector(0);
+        assert!(detector.observe(&slot(10, Some(9))).is_none());
+        assert!(detector.observe(&slot(13, Some(10))).is_none());
+        // 14 and 15 were skipped, 16 was not received
+        assert_eq!(
+            slot_gap(detector.observe(&slot(17, Some(16)))),
+            Some((13, 17, 14, 16))
+        );
+        assert_eq!(detector.missed_slots(), 3);
+    }
+
+    #[test]
+    fn reports_write_version_regressions() {
+        let detector = detector(100);
+        assert!(detector.observe(&account(1, 10, 5, false)).is_none());
+        assert!(detector.observe(&account(1, 11, 7, false)).is_none());
+        match detector.observe(&account(1, 11, 6, false)) {
+            Some(Gap::WriteVersion {
+                previous_slot,
+                previous_write_version,
+                slot,
+                write_version,
+                ..
+            }) => assert_eq!(
+                (previous_slot, previous_write_version, slot, write_version),
+                (11, 7, 11, 6)
+            ),
+            gap => panic!("unexpected {gap:?}"),
+        }
+        // Compared with the highest write version, duplicates are not gaps
+        assert!(detector.observe(&account(1, 12, 7, false)).is_none());
+        assert!(detector.observe(&account(1, 12, 6, false)).is_some());
+        // Other pubkeys and startup updates are independent
+        assert!(detector.observe(&account(2, 12, 1, false)).is_none());
+        assert!(detector.observe(&account(1, 12, 1, true)).is_none());
+        assert_eq!(detector.write_version_gaps(), 2);
+    }
+
+    #[test]
+    fn forgets_least_recently_updated_accounts() {
+        let detector = detector(2);
+        assert!(detector.observe(&account(1, 10, 5, false)).is_none());
+        assert!(detector.observe(&account(2, 10, 5, false)).is_none());
+        assert!(detector.observe(&account(1, 10, 6, false)).is_none());
+        assert!(detector.observe(&account(3, 10, 5, false)).is_none());
+        // 1 is still known, 2 was forgotten
+        assert!(detector.observe(&account(1, 10, 1, false)).is_some());
+        assert!(detector.observe(&account(2, 10, 1, false)).is_none());
+        assert_eq!(detector.state.lock().unwrap().accounts.len(), 2);
+    }
+
+    #[test]
+    fn disabled_without_accounts() {
+        let detector = detector(0);
+        assert!(detector.observe(&account(1, 10, 5, false)).is_none());
+        assert!(detector.observe(&account(1, 10, 1, false)).is_none());
+    }
+}
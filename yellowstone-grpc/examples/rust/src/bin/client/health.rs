@@ -0,0 +1,216 @@
+//! Hooks executed by `HealthWatch` when the server goes in or out of `NOT_SERVING`.
+
+use {
+    crate::endpoint::Endpoints,
+    chrono::Utc,
+    log::{error, info, warn},
+    serde::Serialize,
+    serde_json::json,
+    std::{
+        env,
+        fs::OpenOptions,
+        io::Write,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::{process::Command, time::timeout},
+    tonic_health::pb::health_check_response::ServingStatus,
+};
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Transition {
+    NotServing,
+    Recovered,
+}
+
+/// One line of the incident timeline
+#[derive(Debug, Serialize)]
+struct TimelineEvent<'a> {
+    time: String,
+    incident: i64,
+    endpoint: &'a str,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct HealthHooks {
+    webhook: Option<String>,
+    script: Option<String>,
+    incident_log: Option<String>,
+    /// Switch to the next endpoint on `NOT_SERVING`
+    failover: bool,
+    endpoints: Arc<Endpoints>,
+    /// Start of the current incident in milliseconds, used as incident id
+    incident: Mutex<Option<i64>>,
+    http: reqwest::Client,
+}
+
+impl HealthHooks {
+    pub fn from_env(endpoints: Arc<Endpoints>) -> anyhow::Result<Self> {
+        Ok(Self {
+            webhook: env::var("HEALTH_WEBHOOK_URL").ok(),
+            script: env::var("HEALTH_HOOK_SCRIPT").ok(),
+            incident_log: env::var("HEALTH_INCIDENT_LOG").ok(),
+            failover: env::var("HEALTH_FAILOVER")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(false),
+            endpoints,
+            incident: Mutex::new(None),
+            http: reqwest::Client::builder().timeout(HOOK_TIMEOUT).build()?,
+        })
+    }
+
+    /// Called for every received status, runs hooks when the server goes in or out of
+    /// `NOT_SERVING`. Returns the next endpoint if the client should fail over to it.
+    pub async fn on_status(
+        &self,
+        previous: Option<ServingStatus>,
+        status: ServingStatus,
+    ) -> Option<String> {
+        // After failover the new connection has no previous status, an open incident is
+        // resolved by the first `SERVING` status from any endpoint
+        let incident_open = self.incident.lock().expect("poisoned").is_some();
+        let transition = match (previous, status) {
+            (Some(previous), status) if previous == status => return None,
+            (_, ServingStatus::NotServing) => Transition::NotServing,
+            (_, ServingStatus::Serving) if incident_open => Transition::Recovered,
+            _ => return None,
+        };
+
+        let endpoint = self.endpoints.current().url.clone();
+        let now = Utc::now().timestamp_millis();
+        let incident = {
+            let mut incident = self.incident.lock().expect("poisoned");
+            match transition {
+                Transition::NotServing => *incident.get_or_insert(now),
+                Transition::Recovered => incident.take().unwrap_or(now),
+            }
+        };
+        match transition {
+            Transition::NotServing => {
+                warn!("health: {endpoint} is not serving, incident {incident}");
+                self.record(incident, &endpoint, "not_serving", None);
+            }
+            Transition::Recovered => {
+                let duration = Duration::from_millis((now - incident).max(0) as u64);
+                info!("health: {endpoint} recovered after {duration:?}, incident {incident}");
+                self.record(
+                    incident,
+                    &endpoint,
+                    "recovered",
+                    Some(format!("{duration:?}")),
+                );
+            }
+        }
+
+        let payload = json!({
+            "event": transition,
+            "endpoint": endpoint,
+            "status": status.as_str_name(),
+            "previous_status": previous.map(|status| status.as_str_name()),
+            "incident": incident,
+        });
+        if let Some(url) = &self.webhook {
+            let result = self.call_webhook(url, &payload).await;
+            self.record_hook(incident, &endpoint, "webhook", result);
+        }
+        if let Some(script) = &self.script {
+            let result = self.run_script(script, &payload).await;
+            self.record_hook(incident, &endpoint, "script", result);
+        }
+
+        if transition == Transition::NotServing && self.failover {
+            if let Some(next) = self.endpoints.failover() {
+                self.record(incident, &endpoint, "failover", Some(next.url.clone()));
+                return Some(next.url.clone());
+            }
+        }
+        None
+    }
+
+    async fn call_webhook(&self, url: &str, payload: &serde_json::Value) -> anyhow::Result<String> {
+        let response = self.http.post(url).json(payload).send().await?;
+        let status = response.status();
+        anyhow::ensure!(status.is_success(), "webhook returned {status}");
+        Ok(status.to_string())
+    }
+
+    /// Run `sh -c <script>`, transition details are passed with `HEALTH_*` env variables
+    async fn run_script(
+        &self,
+        script: &str,
+        payload: &serde_json::Value,
+    ) -> anyhow::Result<String> {
+        let field = |key: &str| payload[key].as_str().unwrap_or_default().to_owned();
+        let status = timeout(
+            HOOK_TIMEOUT,
+            Command::new("sh")
+                .arg("-c")
+                .arg(script)
+                .env("HEALTH_EVENT", field("event"))
+                .env("HEALTH_ENDPOINT", field("endpoint"))
+                .env("HEALTH_STATUS", field("status"))
+                .env("HEALTH_PREVIOUS_STATUS", field("previous_status"))
+                .env("HEALTH_INCIDENT", payload["incident"].to_string())
+                .kill_on_drop(true)
+                .status(),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("script timed out"))??;
+        anyhow::ensure!(status.success(), "script failed: {status}");
+        Ok(status.to_string())
+    }
+
+    fn record_hook(
+        &self,
+        incident: i64,
+        endpoint: &str,
+        hook: &str,
+        result: anyhow::Result<String>,
+    ) {
+        match result {
+            Ok(detail) => self.record(incident, endpoint, &format!("{hook}_ok"), Some(detail)),
+            Err(error) => {
+                error!("health: {hook} hook failed: {error}");
+                self.record(
+                    incident,
+                    endpoint,
+                    &format!("{hook}_failed"),
+                    Some(error.to_string()),
+                );
+            }
+        }
+    }
+
+    /// Append event to `HEALTH_INCIDENT_LOG` as a JSON line
+    fn record(&self, incident: i64, endpoint: &str, event: &str, detail: Option<String>) {
+        let Some(path) = &self.incident_log else {
+            return;
+        };
+
+        let event = TimelineEvent {
+            time: Utc::now().to_rfc3339(),
+            incident,
+            endpoint,
+            event,
+            detail,
+        };
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(&event).map_err(std::io::Error::other)?;
+                writeln!(file, "{line}")
+            });
+        if let Err(error) = result {
+            error!("health: failed to write incident log {path}: {error}");
+        }
+    }
+}
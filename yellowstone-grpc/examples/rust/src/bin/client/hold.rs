@@ -0,0 +1,343 @@
+//! Hold updates until their slot reaches a commitment, `HOLD_UNTIL`.
+//!
+//! Account, transaction and transaction status updates are buffered by slot and released to
+//! sinks and logging only when a slot update shows that their slot reached the target
+//! commitment. Once a slot is finalized, held updates of lower slots which never reached the
+//! target were on a dead fork and are dropped. Slots and other updates are never held, and
+//! updates of a slot which already reached the target pass right away. Held updates count
+//! against `MEMORY_BUDGET`, when it is used up the updates of the oldest slots are dropped.
+
+use {
+    crate::budget::MemoryBudget,
+    log::warn,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    yellowstone_grpc_proto::{
+        prelude::{
+            subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest, SubscribeUpdate,
+        },
+        prost::Message,
+    },
+};
+
+const DEFAULT_MAX_SLOTS: usize = 1_000;
+
+/// Slot of an update which is held
+fn held_slot(msg: &SubscribeUpdate) -> Option<u64> {
+    match msg.update_oneof.as_ref()? {
+        UpdateOneof::Account(update) => Some(update.slot),
+        UpdateOneof::Transaction(update) => Some(update.slot),
+        UpdateOneof::TransactionStatus(update) => Some(update.slot),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+struct HoldState {
+    held: BTreeMap<u64, Vec<SubscribeUpdate>>,
+    /// Slots which reached the target commitment, their late updates pass right away
+    reached: BTreeSet<u64>,
+    finalized: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct CommitmentHold {
+    target: CommitmentLevel,
+    /// Slots with held updates, updates of the oldest are dropped first
+    max_slots: usize,
+    budget: Option<Arc<MemoryBudget>>,
+    state: Mutex<HoldState>,
+    buffered: AtomicU64,
+    released: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl CommitmentHold {
+    /// Returns `None` if `HOLD_UNTIL` is not set
+    pub fn from_env(budget: Option<Arc<MemoryBudget>>) -> anyhow::Result<Option<Self>> {
+        let target = match env::var("HOLD_UNTIL").as_deref() {
+            Err(_) => return Ok(None),
+            Ok("confirmed") => CommitmentLevel::Confirmed,
+            Ok("finalized") => CommitmentLevel::Finalized,
+            Ok(_) => anyhow::bail!("invalid HOLD_UNTIL value, expected `confirmed` or `finalized`"),
+        };
+        let max_slots = match env::var("HOLD_MAX_SLOTS") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|max_slots| *max_slots > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid HOLD_MAX_SLOTS"))?,
+            Err(_) => DEFAULT_MAX_SLOTS,
+        };
+        Ok(Some(Self::new(target, max_slots, budget)))
+    }
+
+    fn new(target: CommitmentLevel, max_slots: usize, budget: Option<Arc<MemoryBudget>>) -> Self {
+        Self {
+            target,
+            max_slots,
+            budget,
+            state: Mutex::default(),
+            buffered: AtomicU64::new(0),
+            released: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Updates are only released by slot updates of every commitment, without them
+    /// everything would be held until it is dropped
+    pub fn check(&self, request: &SubscribeRequest) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            request
+                .slots
+                .values()
+                .any(|filter| filter.filter_by_commitment != Some(true)),
+            "HOLD_UNTIL requires slot updates, set SUBSCRIBE_SLOTS=true without SLOTS_FILTER_BY_COMMITMENT"
+        );
+        Ok(())
+    }
+
+    pub fn target(&self) -> &'static str {
+        match self.target {
+            CommitmentLevel::Confirmed => "confirmed",
+            _ => "finalized",
+        }
+    }
+
+    /// Updates held now
+    pub fn buffered(&self) -> u64 {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    /// Held updates passed on once their slot reached the target
+    pub fn released(&self) -> u64 {
+        self.released.load(Ordering::Relaxed)
+    }
+
+    /// Held updates of dead forks, of slots over `HOLD_MAX_SLOTS` and left on exit
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Updates which pass now, oldest first: the update itself if it is not held, and for
+    /// a slot update also the held updates of the slot which reached the target
+    pub fn pass(&self, msg: SubscribeUpdate) -> Vec<SubscribeUpdate> {
+        let mut state = self.state.lock().expect("poisoned");
+        if let Some(slot) = held_slot(&msg) {
+            if state.reached.contains(&slot) {
+                return vec![msg];
+            }
+            if state.finalized.is_some_and(|finalized| slot <= finalized) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return vec![];
+            }
+            if let Some(budget) = self.budget.as_ref() {
+                let bytes = msg.encoded_len() as u64;
+                while !budget.try_reserve(bytes, state.held.is_empty()) {
+                    budget.full(bytes, "hold");
+                    if let Some((slot, updates)) = state.held.pop_first() {
+                        self.forget(&updates);
+                        warn!(
+                            "hold: {} updates of slot {slot} dropped, MEMORY_BUDGET is used up",
+                            updates.len()
+                        );
+                    }
+                }
+            }
+            state.held.entry(slot).or_default().push(msg);
+            self.buffered.fetch_add(1, Ordering::Relaxed);
+            while state.held.len() > self.max_slots {
+                if let Some((slot, updates)) = state.held.pop_first() {
+                    self.forget(&updates);
+                    warn!(
+                        "hold: {} updates of slot {slot} dropped, more than {} slots are held",
+                        updates.len(),
+                        self.max_slots
+                    );
+                }
+            }
+            return vec![];
+        }
+
+        let Some(UpdateOneof::Slot(update)) = msg.update_oneof.as_ref() else {
+            return vec![msg];
+        };
+        let slot = update.slot;
+        let finalized = update.status == CommitmentLevel::Finalized as i32;
+        let mut updates = vec![];
+        if update.status >= self.target as i32 && state.reached.insert(slot) {
+            updates = state.held.remove(&slot).unwrap_or_default();
+            self.release(&updates);
+            self.released
+                .fetch_add(updates.len() as u64, Ordering::Relaxed);
+            while state.reached.len() > self.max_slots {
+                state.reached.pop_first();
+            }
+        }
+        if finalized && state.finalized.is_none_or(|current| slot > current) {
+            state.finalized = Some(slot);
+            // Lower slots which didn't reach the target by now never will
+            let above = state.held.split_off(&slot);
+            let dead = std::mem::replace(&mut state.held, above);
+            for (slot, held) in dead {
+                self.forget(&held);
+                warn!(
+                    "hold: {} updates of slot {slot} dropped, the slot was not {}",
+                    held.len(),
+                    self.target()
+                );
+            }
+        }
+        drop(state);
+
+        updates.push(msg);
+        updates
+    }
+
+    /// Drop updates held on exit, their slots didn't reach the target
+    pub fn clear(&self) -> u64 {
+        let held = std::mem::take(&mut self.state.lock().expect("poisoned").held);
+        held.values().map(|updates| self.forget(updates)).sum()
+    }
+
+    /// Updates are no longer held, returns their number
+    fn release(&self, updates: &[SubscribeUpdate]) -> u64 {
+        let count = updates.len() as u64;
+        self.buffered.fetch_sub(count, Ordering::Relaxed);
+        if let Some(budget) = self.budget.as_ref() {
+            budget.release(updates.iter().map(|msg| msg.encoded_len() as u64).sum());
+        }
+        count
+    }
+
+    fn forget(&self, updates: &[SubscribeUpdate]) -> u64 {
+        let count = self.release(updates);
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeRequestFilterSlots, SubscribeUpdateAccount, SubscribeUpdateSlot,
+        },
+    };
+
+    fn account(slot: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["accounts".to_owned()],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: None,
+                slot,
+                is_startup: false,
+            })),
+        }
+    }
+
+    fn slot(slot: u64, status: CommitmentLevel) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: None,
+                status: status as i32,
+            })),
+        }
+    }
+
+    /// Slots of passed updates, `None` for slot updates
+    fn passed(updates: Vec<SubscribeUpdate>) -> Vec<Option<u64>> {
+        updates.iter().map(held_slot).collect()
+    }
+
+    #[test]
+    fn releases_on_target_commitment() {
+        let hold = CommitmentHold::new(CommitmentLevel::Confirmed, DEFAULT_MAX_SLOTS, None);
+        assert!(hold.pass(account(10)).is_empty());
+        assert!(hold.pass(account(10)).is_empty());
+        assert!(hold.pass(account(11)).is_empty());
+        assert_eq!(hold.buffered(), 3);
+
+        assert_eq!(
+            passed(hold.pass(slot(10, CommitmentLevel::Processed))),
+            [None]
+        );
+        assert_eq!(
+            passed(hold.pass(slot(10, CommitmentLevel::Confirmed))),
+            [Some(10), Some(10), None]
+        );
+        // Late updates of a slot which reached the target pass right away
+        assert_eq!(passed(hold.pass(account(10))), [Some(10)]);
+        assert_eq!((hold.buffered(), hold.released()), (1, 2));
+
+        // Finalized 12 shows that 11 was on a dead fork
+        assert_eq!(
+            passed(hold.pass(slot(12, CommitmentLevel::Finalized))),
+            [None]
+        );
+        assert!(hold.pass(account(11)).is_empty());
+        assert_eq!((hold.buffered(), hold.dropped()), (0, 2));
+    }
+
+    #[test]
+    fn drops_oldest_slots_over_max_slots() {
+        let hold = CommitmentHold::new(CommitmentLevel::Finalized, 2, None);
+        for n in [10, 11, 11, 12] {
+            hold.pass(account(n));
+        }
+        assert_eq!((hold.buffered(), hold.dropped()), (3, 1));
+        assert_eq!(
+            passed(hold.pass(slot(11, CommitmentLevel::Finalized))),
+            [Some(11), Some(11), None]
+        );
+        assert_eq!(hold.clear(), 1);
+        assert_eq!((hold.buffered(), hold.dropped()), (0, 2));
+    }
+
+    #[test]
+    fn drops_oldest_slots_over_memory_budget() {
+        let bytes = account(10).encoded_len() as u64;
+        let budget = Arc::new(MemoryBudget::new(2 * bytes));
+        let hold = CommitmentHold::new(
+            CommitmentLevel::Confirmed,
+            DEFAULT_MAX_SLOTS,
+            Some(Arc::clone(&budget)),
+        );
+        for n in [10, 11, 12] {
+            hold.pass(account(n));
+        }
+        assert_eq!((hold.buffered(), hold.dropped()), (2, 1));
+        assert_eq!(
+            passed(hold.pass(slot(11, CommitmentLevel::Confirmed))),
+            [Some(11), None]
+        );
+        assert_eq!(budget.used(), bytes);
+    }
+
+    #[test]
+    fn requires_slot_updates() {
+        let hold = CommitmentHold::new(CommitmentLevel::Confirmed, DEFAULT_MAX_SLOTS, None);
+        let mut request = SubscribeRequest::default();
+        assert!(hold.check(&request).is_err());
+        request.slots.insert(
+            "slots".to_owned(),
+            SubscribeRequestFilterSlots {
+                filter_by_commitment: Some(true),
+            },
+        );
+        assert!(hold.check(&request).is_err());
+        request
+            .slots
+            .insert("all".to_owned(), SubscribeRequestFilterSlots::default());
+        assert!(hold.check(&request).is_ok());
+    }
+}
@@ -0,0 +1,243 @@
+//! Decoded instructions of well-known programs for transaction logs, `PARSE_INSTRUCTIONS`.
+//!
+//! Decoding is done by `solana-transaction-status`, the same as `jsonParsed` encoding of
+//! the RPC. Instructions of other programs, and ones which fail to decode, are left out.
+
+use {
+    serde_json::Value,
+    solana_sdk::instruction::CompiledInstruction,
+    solana_transaction_status::{parse_instruction::parse, VersionedTransactionWithStatusMeta},
+    std::fmt,
+};
+
+/// Programs as named by `parse_instruction`
+const PROGRAMS: [&str; 5] = ["system", "spl-token", "spl-memo", "stake", "vote"];
+
+pub struct InstructionPretty {
+    /// Index of the instruction, `parent.position` for inner instructions
+    index: String,
+    program: String,
+    name: String,
+    args: Value,
+}
+
+impl fmt::Debug for InstructionPretty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {} {}", self.index, self.program, self.name)?;
+        if !self.args.is_null() {
+            write!(f, " {}", self.args)?;
+        }
+        Ok(())
+    }
+}
+
+/// Top-level instructions in order, each followed by its inner instructions
+pub fn parse_instructions(tx: &VersionedTransactionWithStatusMeta) -> Vec<InstructionPretty> {
+    let account_keys = tx.account_keys();
+    let inner_instructions = tx.meta.inner_instructions.as_deref().unwrap_or_default();
+
+    let decode = |index: String, instruction: &CompiledInstruction, stack_height| {
+        let program_id = account_keys.get(instruction.program_id_index as usize)?;
+        let parsed = parse(program_id, instruction, &account_keys, stack_height).ok()?;
+        if !PROGRAMS.contains(&parsed.program.as_str()) {
+            return None;
+        }
+        let (name, args) = match parsed.parsed {
+            Value::Object(mut object) => (
+                object
+                    .remove("type")
+                    .and_then(|name| name.as_str().map(str::to_owned))
+                    .unwrap_or_default(),
+                object.remove("info").unwrap_or_default(),
+            ),
+            // Memos are plain text
+            memo => ("memo".to_owned(), memo),
+        };
+        Some(InstructionPretty {
+            index,
+            program: parsed.program,
+            name,
+            args,
+        })
+    };
+
+    let mut parsed = Vec::new();
+    for (index, instruction) in tx.transaction.message.instructions().iter().enumerate() {
+        parsed.extend(decode(index.to_string(), instruction, None));
+        let inner = inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == index)
+            .flat_map(|inner| inner.instructions.iter());
+        for (position, inner) in inner.enumerate() {
+            parsed.extend(decode(
+                format!("{index}.{position}"),
+                &inner.instruction,
+                inner.stack_height,
+            ));
+        }
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{pubkey::Pubkey, system_instruction, system_program},
+        solana_transaction_status::TransactionWithStatusMeta,
+        std::str::FromStr,
+        yellowstone_grpc_proto::{
+            convert_from::create_tx_with_meta,
+            prelude::{
+                self as proto, InnerInstruction, InnerInstructions, Message, MessageHeader,
+                SubscribeUpdateTransactionInfo, Transaction, TransactionStatusMeta,
+            },
+        },
+    };
+
+    const MEMO: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+    fn key(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    /// Account keys: payer, recipient, system program, memo program, unknown program
+    fn tx(
+        instructions: Vec<proto::CompiledInstruction>,
+        inner_instructions: Vec<InnerInstructions>,
+    ) -> VersionedTransactionWithStatusMeta {
+        let account_keys = [
+            key(1),
+            key(2),
+            system_program::id(),
+            Pubkey::from_str(MEMO).unwrap(),
+            key(9),
+        ];
+        let info = SubscribeUpdateTransactionInfo {
+            signature: vec![7; 64],
+            transaction: Some(Transaction {
+                signatures: vec![vec![7; 64]],
+                message: Some(Message {
+                    header: Some(MessageHeader {
+                        num_required_signatures: 1,
+                        num_readonly_signed_accounts: 0,
+                        num_readonly_unsigned_accounts: 3,
+                    }),
+                    account_keys: account_keys
+                        .iter()
+                        .map(|key| key.to_bytes().to_vec())
+                        .collect(),
+                    recent_blockhash: vec![0; 32],
+                    instructions,
+                    ..Default::default()
+                }),
+            }),
+            meta: Some(TransactionStatusMeta {
+                inner_instructions,
+                return_data_none: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        match create_tx_with_meta(info).unwrap() {
+            TransactionWithStatusMeta::Complete(tx) => tx,
+            TransactionWithStatusMeta::MissingMetadata(_) => unreachable!(),
+        }
+    }
+
+    fn transfer(from: u8, to: u8, lamports: u64) -> proto::CompiledInstruction {
+        proto::CompiledInstruction {
+            program_id_index: 2,
+            accounts: vec![from, to],
+            data: system_instruction::transfer(&key(from), &key(to), lamports).data,
+        }
+    }
+
+    fn unknown() -> proto::CompiledInstruction {
+        proto::CompiledInstruction {
+            program_id_index: 4,
+            accounts: vec![0],
+            data: vec![1, 2, 3],
+        }
+    }
+
+    fn inner(instruction: proto::CompiledInstruction) -> InnerInstruction {
+        InnerInstruction {
+            program_id_index: instruction.program_id_index,
+            accounts: instruction.accounts,
+            data: instruction.data,
+            stack_height: Some(2),
+        }
+    }
+
+    fn summary(parsed: &[InstructionPretty]) -> Vec<(&str, &str, &str, u64)> {
+        parsed
+            .iter()
+            .map(|instruction| {
+                (
+                    instruction.index.as_str(),
+                    instruction.program.as_str(),
+                    instruction.name.as_str(),
+                    instruction.args["lamports"].as_u64().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inner_instructions_follow_their_parent() {
+        let memo = proto::CompiledInstruction {
+            program_id_index: 3,
+            accounts: vec![],
+            data: b"hello".to_vec(),
+        };
+        let tx = tx(
+            vec![transfer(0, 1, 5), unknown(), memo],
+            vec![InnerInstructions {
+                index: 1,
+                instructions: vec![inner(unknown()), inner(transfer(1, 0, 2))],
+            }],
+        );
+        let parsed = parse_instructions(&tx);
+        assert_eq!(
+            summary(&parsed),
+            [
+                ("0", "system", "transfer", 5),
+                // The position counts the unknown inner instruction which is left out
+                ("1.1", "system", "transfer", 2),
+                ("2", "spl-memo", "memo", 0),
+            ]
+        );
+        assert_eq!(parsed[1].args["source"], key(2).to_string());
+        assert_eq!(format!("{:?}", parsed[2]), r#"#2 spl-memo memo "hello""#);
+    }
+
+    #[test]
+    fn invalid_instructions_are_left_out() {
+        let truncated = proto::CompiledInstruction {
+            data: vec![2, 0],
+            ..transfer(0, 1, 5)
+        };
+        let missing_account = proto::CompiledInstruction {
+            accounts: vec![0],
+            ..transfer(0, 1, 5)
+        };
+        let missing_program = proto::CompiledInstruction {
+            program_id_index: 10,
+            ..transfer(0, 1, 5)
+        };
+        let tx = tx(
+            vec![
+                truncated,
+                missing_account,
+                missing_program,
+                transfer(0, 1, 1),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            summary(&parse_instructions(&tx)),
+            [("3", "system", "transfer", 1)]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! JSON encoding of stream updates for consumers which don't read protobuf.
+//!
+//! Every update is an object with `type` (see `stats::update_kind`), `filters` and `slot`,
+//! and the update itself under the key of its type. Transactions are encoded like RPC
+//! `getTransaction` with base64 encoding. Blocks only have counts of their transactions,
+//! accounts and entries, subscribe to those separately to receive them.
+//!
+//! Field names, enums and bytes follow `JsonOptions`: by default snake_case fields, enum
+//! names, base58 pubkeys, signatures and hashes and hex account data like in the logs.
+//! `JSON_STYLE=proto` matches the proto3 JSON mapping and `JSON_STYLE=rpc` the Solana RPC
+//! conventions, single options can be overridden with `JSON_FIELD_CASE`, `JSON_ENUMS`,
+//! `JSON_BYTES` and `JSON_DATA`. With `TRACE_IDS=true` every update has a `trace_id` (see
+//! `trace`).
+
+use {
+    crate::{
+        stats::{update_kind, update_slot},
+        trace::{self, trace_id},
+        truncate::Truncated,
+    },
+    base64::{engine::general_purpose::STANDARD, Engine},
+    serde_json::{json, Value},
+    solana_transaction_status::UiTransactionEncoding,
+    std::env,
+    yellowstone_grpc_proto::{
+        convert_from,
+        prelude::{
+            subscribe_update::UpdateOneof, CommitmentLevel, SubscribeUpdate,
+            SubscribeUpdateTransactionInfo,
+        },
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldCase {
+    Snake,
+    Camel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnumFormat {
+    /// Name of the proto enum value, e.g. `CONFIRMED`
+    String,
+    Number,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BytesEncoding {
+    Base58,
+    Base64,
+    Hex,
+}
+
+impl BytesEncoding {
+    fn from_env_value(name: &str, value: &str) -> anyhow::Result<Self> {
+        match value {
+            "base58" => Ok(Self::Base58),
+            "base64" => Ok(Self::Base64),
+            "hex" => Ok(Self::Hex),
+            _ => anyhow::bail!("invalid {name} value, expected `base58`, `base64` or `hex`"),
+        }
+    }
+
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Base58 => bs58::encode(bytes).into_string(),
+            Self::Base64 => STANDARD.encode(bytes),
+            Self::Hex => hex::encode(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonOptions {
+    pub field_case: FieldCase,
+    pub enums: EnumFormat,
+    /// Pubkeys, signatures and hashes
+    pub bytes: BytesEncoding,
+    /// Account data
+    pub data: BytesEncoding,
+    /// Add `trace_id`
+    pub trace_ids: bool,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        Self {
+            field_case: FieldCase::Snake,
+            enums: EnumFormat::String,
+            bytes: BytesEncoding::Base58,
+            data: BytesEncoding::Hex,
+            trace_ids: false,
+        }
+    }
+}
+
+impl JsonOptions {
+    /// proto3 JSON mapping, except that 64-bit integers stay numbers
+    const PROTO: Self = Self {
+        field_case: FieldCase::Camel,
+        enums: EnumFormat::String,
+        bytes: BytesEncoding::Base64,
+        data: BytesEncoding::Base64,
+        trace_ids: false,
+    };
+    /// Solana RPC, like `accountSubscribe` with base64 encoding
+    const RPC: Self = Self {
+        field_case: FieldCase::Camel,
+        enums: EnumFormat::String,
+        bytes: BytesEncoding::Base58,
+        data: BytesEncoding::Base64,
+        trace_ids: false,
+    };
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut options = match env::var("JSON_STYLE").as_deref() {
+            Ok("client") | Err(_) => Self::default(),
+            Ok("proto") => Self::PROTO,
+            Ok("rpc") => Self::RPC,
+            Ok(_) => anyhow::bail!("invalid JSON_STYLE value, expected `client`, `proto` or `rpc`"),
+        };
+        if let Ok(value) = env::var("JSON_FIELD_CASE") {
+            options.field_case = match value.as_str() {
+                "snake" => FieldCase::Snake,
+                "camel" => FieldCase::Camel,
+                _ => anyhow::bail!("invalid JSON_FIELD_CASE value, expected `snake` or `camel`"),
+            };
+        }
+        if let Ok(value) = env::var("JSON_ENUMS") {
+            options.enums = match value.as_str() {
+                "string" => EnumFormat::String,
+                "number" => EnumFormat::Number,
+                _ => anyhow::bail!("invalid JSON_ENUMS value, expected `string` or `number`"),
+            };
+        }
+        if let Ok(value) = env::var("JSON_BYTES") {
+            options.bytes = BytesEncoding::from_env_value("JSON_BYTES", &value)?;
+        }
+        if let Ok(value) = env::var("JSON_DATA") {
+            options.data = BytesEncoding::from_env_value("JSON_DATA", &value)?;
+        }
+        options.trace_ids = trace::enabled();
+        Ok(options)
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> String {
+        self.bytes.encode(bytes)
+    }
+
+    fn commitment(&self, status: i32) -> Value {
+        match self.enums {
+            EnumFormat::String => CommitmentLevel::try_from(status)
+                .map_or("unknown", |commitment| commitment.as_str_name())
+                .into(),
+            EnumFormat::Number => status.into(),
+        }
+    }
+}
+
+/// `rent_epoch` to `rentEpoch`
+fn camel_case(name: &str) -> String {
+    let mut parts = name.split('_');
+    let mut camel = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+fn to_camel_case(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (camel_case(&name), to_camel_case(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(to_camel_case).collect()),
+        value => value,
+    }
+}
+
+fn transaction_json(tx: &SubscribeUpdateTransactionInfo, options: &JsonOptions) -> Value {
+    let encoded = convert_from::create_tx_with_meta(tx.clone())
+        .ok()
+        .and_then(|tx| {
+            tx.encode(UiTransactionEncoding::Base64, Some(u8::MAX), true)
+                .ok()
+        })
+        .and_then(|tx| serde_json::to_value(tx).ok());
+    json!({
+        "signature": options.bytes(&tx.signature),
+        "is_vote": tx.is_vote,
+        "index": tx.index,
+        "transaction": encoded,
+    })
+}
+
+/// Encode the update, `None` for pings and pongs. Updates truncated by `FieldBudget` have
+/// `truncated: true` and `original_len` with the length in bytes of every truncated field.
+pub fn update_json(
+    msg: &SubscribeUpdate,
+    options: &JsonOptions,
+    truncated: Option<&Truncated>,
+) -> Option<Value> {
+    let update = msg.update_oneof.as_ref()?;
+    let value = match update {
+        UpdateOneof::Account(update) => {
+            let account = update.account.as_ref()?;
+            json!({
+                "pubkey": options.bytes(&account.pubkey),
+                "owner": options.bytes(&account.owner),
+                "lamports": account.lamports,
+                "executable": account.executable,
+                "rent_epoch": account.rent_epoch,
+                "data": options.data.encode(&account.data),
+                "write_version": account.write_version,
+                "txn_signature": account
+                    .txn_signature
+                    .as_deref()
+                    .map(|signature| options.bytes(signature)),
+                "is_startup": update.is_startup,
+            })
+        }
+        UpdateOneof::Slot(update) => json!({
+            "parent": update.parent,
+            "status": options.commitment(update.status),
+        }),
+        UpdateOneof::Transaction(update) => transaction_json(update.transaction.as_ref()?, options),
+        UpdateOneof::TransactionStatus(update) => json!({
+            "signature": options.bytes(&update.signature),
+            "is_vote": update.is_vote,
+            "index": update.index,
+            "err": convert_from::create_tx_error(update.err.as_ref())
+                .ok()
+                .flatten()
+                .and_then(|err| serde_json::to_value(err).ok()),
+        }),
+        UpdateOneof::Block(update) => json!({
+            "blockhash": update.blockhash,
+            "block_time": update.block_time.as_ref().map(|time| time.timestamp),
+            "block_height": update.block_height.as_ref().map(|height| height.block_height),
+            "parent_slot": update.parent_slot,
+            "parent_blockhash": update.parent_blockhash,
+            "executed_transaction_count": update.executed_transaction_count,
+            "updated_account_count": update.updated_account_count,
+            "entries_count": update.entries_count,
+        }),
+        UpdateOneof::BlockMeta(update) => json!({
+            "blockhash": update.blockhash,
+            "block_time": update.block_time.as_ref().map(|time| time.timestamp),
+            "block_height": update.block_height.as_ref().map(|height| height.block_height),
+            "parent_slot": update.parent_slot,
+            "parent_blockhash": update.parent_blockhash,
+            "executed_transaction_count": update.executed_transaction_count,
+            "entries_count": update.entries_count,
+        }),
+        UpdateOneof::Entry(update) => json!({
+            "index": update.index,
+            "num_hashes": update.num_hashes,
+            "hash": options.bytes(&update.hash),
+            "executed_transaction_count": update.executed_transaction_count,
+            "starting_transaction_index": update.starting_transaction_index,
+        }),
+        UpdateOneof::Ping(_) | UpdateOneof::Pong(_) => return None,
+    };
+
+    // `type` keeps the update type names, subscriptions of `Serve` match on them
+    let kind = update_kind(update);
+    let mut value = json!({
+        "type": kind,
+        "filters": msg.filters,
+        "slot": update_slot(msg),
+        kind: value,
+    });
+    if options.trace_ids {
+        value["trace_id"] = trace_id(msg).into();
+    }
+    if let Some(truncated) = truncated {
+        value["truncated"] = true.into();
+        value["original_len"] = json!(truncated.0);
+    }
+    Some(match options.field_case {
+        FieldCase::Snake => value,
+        FieldCase::Camel => to_camel_case(value),
+    })
+}
@@ -0,0 +1,117 @@
+//! Latency of slot and block meta updates relative to the block time.
+//!
+//! Block time has a second resolution, so single samples are rough, percentiles over many
+//! blocks are still good enough to compare providers with each other.
+
+use {
+    serde_json::{json, Value},
+    std::{
+        collections::{BTreeMap, VecDeque},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+/// Slots without block meta are forgotten after this many newer slots
+const MAX_PENDING_SLOTS: usize = 1_000;
+
+/// Current time in milliseconds since UNIX epoch
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// Samples received during the last `window`
+#[derive(Debug)]
+struct Samples {
+    window: Duration,
+    values: VecDeque<(i64, i64)>,
+}
+
+impl Samples {
+    const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            values: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, now: i64, value: i64) {
+        self.values.push_back((now, value));
+    }
+
+    fn report(&mut self, now: i64) -> Value {
+        let start = now - self.window.as_millis() as i64;
+        while matches!(self.values.front(), Some((time, _)) if *time < start) {
+            self.values.pop_front();
+        }
+
+        let mut values = self
+            .values
+            .iter()
+            .map(|(_, value)| *value)
+            .collect::<Vec<_>>();
+        values.sort_unstable();
+        let percentile = |p: usize| -> Option<i64> {
+            let index = (values.len() * p / 100).min(values.len().checked_sub(1)?);
+            values.get(index).copied()
+        };
+        json!({
+            "count": values.len(),
+            "p50_ms": percentile(50),
+            "p95_ms": percentile(95),
+            "p99_ms": percentile(99),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct LatencyTracker {
+    /// First receive time of every slot, waiting for block meta with block time
+    slots_first_seen: BTreeMap<u64, i64>,
+    slot: Samples,
+    block_meta: Samples,
+}
+
+impl LatencyTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            slots_first_seen: BTreeMap::new(),
+            slot: Samples::new(window),
+            block_meta: Samples::new(window),
+        }
+    }
+
+    /// `received` is the local receive time in milliseconds since UNIX epoch
+    pub fn observe(&mut self, msg: &SubscribeUpdate, received: i64) {
+        match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Slot(update)) => {
+                self.slots_first_seen.entry(update.slot).or_insert(received);
+                while self.slots_first_seen.len() > MAX_PENDING_SLOTS {
+                    self.slots_first_seen.pop_first();
+                }
+            }
+            Some(UpdateOneof::BlockMeta(meta)) => {
+                let Some(block_time) = meta.block_time.as_ref() else {
+                    return;
+                };
+                let block_time = block_time.timestamp * 1_000;
+                self.block_meta.push(received, received - block_time);
+                if let Some(first_seen) = self.slots_first_seen.remove(&meta.slot) {
+                    self.slot.push(received, first_seen - block_time);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn report(&mut self) -> Value {
+        let now = now_ms();
+        json!({
+            "slot": self.slot.report(now),
+            "block_meta": self.block_meta.report(now),
+        })
+    }
+}
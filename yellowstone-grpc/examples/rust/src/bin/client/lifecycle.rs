@@ -0,0 +1,291 @@
+//! Pipeline state transitions for supervisors, `GET /lifecycle` and `LIFECYCLE_LOG`.
+//!
+//! Workflow engines which run backfills and migrations need to know when the client is
+//! live, when it fell behind and when it is done, without parsing logs. The client goes
+//! through `starting`, `connected`, `subscribed`, `caught_up`, `degraded`, `draining` and
+//! `stopped`, and every change is an event with a sequence number. `caught_up` needs the tip
+//! from `POLL_INTERVAL_MS`: the stream is within `CATCHUP_LAG_SLOTS` of it. `degraded` is a
+//! stream end other than shutdown, a failed connect, or a slot lag over
+//! `LIFECYCLE_DEGRADED_SLOTS`, which ends with the next `caught_up`. Events are kept for
+//! `GET /lifecycle`, appended to `LIFECYCLE_LOG` as JSON lines and posted in order to
+//! `LIFECYCLE_WEBHOOK_URL`.
+
+use {
+    chrono::Utc,
+    log::{error, info, warn},
+    serde::{Deserialize, Serialize},
+    serde_json::{json, Value},
+    std::{collections::VecDeque, env, fs::OpenOptions, io::Write, sync::Mutex, time::Duration},
+    tokio::{
+        sync::mpsc,
+        task::JoinHandle,
+        time::{timeout, Instant},
+    },
+};
+
+const DEFAULT_LAG_SLOTS: u64 = 32;
+/// Events kept for `GET /lifecycle`
+const HISTORY: usize = 100;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval of slot lag checks
+pub const LIFECYCLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Starting,
+    Connected,
+    Subscribed,
+    CaughtUp,
+    Degraded,
+    Draining,
+    Stopped,
+}
+
+impl Phase {
+    pub const ALL: &'static [Phase] = &[
+        Self::Starting,
+        Self::Connected,
+        Self::Subscribed,
+        Self::CaughtUp,
+        Self::Degraded,
+        Self::Draining,
+        Self::Stopped,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Connected => "connected",
+            Self::Subscribed => "subscribed",
+            Self::CaughtUp => "caught_up",
+            Self::Degraded => "degraded",
+            Self::Draining => "draining",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LifecycleEvent {
+    pub seq: u64,
+    pub time: String,
+    pub event: Phase,
+    pub previous: Phase,
+    /// Milliseconds since the client started
+    pub uptime_ms: u64,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub detail: Value,
+}
+
+/// Query of `GET /lifecycle`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LifecycleQuery {
+    /// Only events with a higher sequence number
+    since: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LifecycleResponse {
+    pub phase: Phase,
+    pub seq: u64,
+    pub events: Vec<LifecycleEvent>,
+}
+
+#[derive(Debug)]
+struct LifecycleState {
+    phase: Phase,
+    seq: u64,
+    /// `degraded` by slot lag, cleared by catching up instead of a new stream
+    lagging: bool,
+    history: VecDeque<LifecycleEvent>,
+}
+
+#[derive(Debug)]
+pub struct Lifecycle {
+    log: Option<String>,
+    lag_slots: u64,
+    degraded_slots: Option<u64>,
+    started: Instant,
+    state: Mutex<LifecycleState>,
+    /// Events for the webhook task, in order
+    webhook: Mutex<Option<mpsc::UnboundedSender<LifecycleEvent>>>,
+    webhook_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Lifecycle {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let lag_slots = match env::var("CATCHUP_LAG_SLOTS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid CATCHUP_LAG_SLOTS"))?,
+            Err(_) => DEFAULT_LAG_SLOTS,
+        };
+        let degraded_slots = match env::var("LIFECYCLE_DEGRADED_SLOTS") {
+            Ok(value) => Some(
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|slots| *slots > lag_slots)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "invalid LIFECYCLE_DEGRADED_SLOTS, expected more than CATCHUP_LAG_SLOTS"
+                        )
+                    })?,
+            ),
+            Err(_) => None,
+        };
+        let (webhook, webhook_task) = match env::var("LIFECYCLE_WEBHOOK_URL") {
+            Ok(url) => {
+                let http = reqwest::Client::builder()
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .build()?;
+                let (tx, rx) = mpsc::unbounded_channel();
+                (Some(tx), Some(tokio::spawn(post_events(http, url, rx))))
+            }
+            Err(_) => (None, None),
+        };
+        Ok(Self {
+            log: env::var("LIFECYCLE_LOG").ok(),
+            lag_slots,
+            degraded_slots,
+            started: Instant::now(),
+            state: Mutex::new(LifecycleState {
+                phase: Phase::Starting,
+                seq: 0,
+                lagging: false,
+                history: VecDeque::new(),
+            }),
+            webhook: Mutex::new(webhook),
+            webhook_task: Mutex::new(webhook_task),
+        })
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.state.lock().expect("poisoned").phase
+    }
+
+    /// Enter `phase`, nothing happens if the client is in it already or is shutting down
+    pub fn transition(&self, phase: Phase, detail: Value) {
+        let mut state = self.state.lock().expect("poisoned");
+        self.enter(&mut state, phase, detail);
+    }
+
+    fn enter(&self, state: &mut LifecycleState, phase: Phase, detail: Value) {
+        let shutting_down = matches!(state.phase, Phase::Draining | Phase::Stopped);
+        if state.phase == phase || (shutting_down && phase != Phase::Stopped) {
+            return;
+        }
+        state.seq += 1;
+        state.lagging = false;
+        let event = LifecycleEvent {
+            seq: state.seq,
+            time: Utc::now().to_rfc3339(),
+            event: phase,
+            previous: state.phase,
+            uptime_ms: self.started.elapsed().as_millis() as u64,
+            detail,
+        };
+        state.phase = phase;
+        info!("lifecycle: {}", json!(event));
+        self.record(&event);
+        if let Some(webhook) = self.webhook.lock().expect("poisoned").as_ref() {
+            let _ = webhook.send(event.clone());
+        }
+        state.history.push_back(event);
+        while state.history.len() > HISTORY {
+            state.history.pop_front();
+        }
+    }
+
+    /// Compare the highest slot of the stream with the polled tip: `caught_up` once close
+    /// enough, `degraded` if caught up before and the lag grew over `LIFECYCLE_DEGRADED_SLOTS`
+    pub fn check_lag(&self, slot: Option<u64>, tip: Option<u64>) {
+        let Some((slot, tip)) = slot.zip(tip) else {
+            return;
+        };
+        let lag = tip.saturating_sub(slot);
+        let detail = json!({ "slot": slot, "tip": tip, "lag": lag });
+        let mut state = self.state.lock().expect("poisoned");
+        match state.phase {
+            Phase::Subscribed if lag <= self.lag_slots => {
+                self.enter(&mut state, Phase::CaughtUp, detail);
+            }
+            Phase::Degraded if state.lagging && lag <= self.lag_slots => {
+                self.enter(&mut state, Phase::CaughtUp, detail);
+            }
+            Phase::CaughtUp if self.degraded_slots.is_some_and(|slots| lag > slots) => {
+                let detail = json!({ "reason": "slot_lag", "slot": slot, "tip": tip, "lag": lag });
+                self.enter(&mut state, Phase::Degraded, detail);
+                state.lagging = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Events after `since`, the last `HISTORY` events are kept
+    pub fn query(&self, query: LifecycleQuery) -> LifecycleResponse {
+        let state = self.state.lock().expect("poisoned");
+        let since = query.since.unwrap_or(0);
+        LifecycleResponse {
+            phase: state.phase,
+            seq: state.seq,
+            events: state
+                .history
+                .iter()
+                .filter(|event| event.seq > since)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Enter `stopped` and wait until the webhook received every event
+    pub async fn stop(&self, detail: Value) {
+        self.transition(Phase::Stopped, detail);
+        self.webhook.lock().expect("poisoned").take();
+        let task = self.webhook_task.lock().expect("poisoned").take();
+        if let Some(task) = task {
+            if timeout(WEBHOOK_TIMEOUT, task).await.is_err() {
+                warn!("lifecycle: webhook did not receive every event before exit");
+            }
+        }
+    }
+
+    /// Append event to `LIFECYCLE_LOG` as a JSON line
+    fn record(&self, event: &LifecycleEvent) {
+        let Some(path) = &self.log else {
+            return;
+        };
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(event).map_err(std::io::Error::other)?;
+                writeln!(file, "{line}")
+            });
+        if let Err(error) = result {
+            error!("lifecycle: failed to write {path}: {error}");
+        }
+    }
+}
+
+/// Post events one at a time, a failed post is logged and not retried
+async fn post_events(
+    http: reqwest::Client,
+    url: String,
+    mut rx: mpsc::UnboundedReceiver<LifecycleEvent>,
+) {
+    while let Some(event) = rx.recv().await {
+        let result = http.post(&url).json(&event).send().await;
+        match result.map(|response| response.status()) {
+            Ok(status) if status.is_success() => {}
+            Ok(status) => error!(
+                "lifecycle: webhook returned {status} for event {}",
+                event.seq
+            ),
+            Err(error) => error!("lifecycle: webhook failed for event {}: {error}", event.seq),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Liveness signals for process supervisors, `LIVENESS_FILE` and systemd notify.
+//!
+//! A supervisor sees that the process runs, not that its stream is stuck. Every received
+//! stream message, server pings and health statuses of `ACTION=HealthWatch` included, marks
+//! the client alive:
+//!
+//! - `LIVENESS_FILE` is rewritten with the current time, at most every `LIVENESS_TOUCH_MS`, so
+//!   a Kubernetes exec probe can check the age of the file
+//! - under systemd with `Type=notify` (`NOTIFY_SOCKET` is set) `READY=1` is sent once a stream
+//!   is open, `WATCHDOG=1` every half of `WatchdogSec` if a message arrived since the last
+//!   ping, and `STOPPING=1` on shutdown; a stream which stops delivering misses the watchdog
+//!   and systemd restarts the service
+
+use {
+    chrono::Utc,
+    log::{info, warn},
+    std::{
+        env, fs,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+        time::Duration,
+    },
+    tokio::time::{interval, Instant, MissedTickBehavior},
+};
+
+const DEFAULT_TOUCH_MS: u64 = 1_000;
+
+#[derive(Debug)]
+pub struct Liveness {
+    file: Option<PathBuf>,
+    touch_interval: u64,
+    /// Path of `NOTIFY_SOCKET`
+    notify: Option<PathBuf>,
+    /// Interval of watchdog pings, half of `WATCHDOG_USEC`
+    watchdog: Option<Duration>,
+    started: Instant,
+    /// Milliseconds since `started` of the last message and file write, `u64::MAX` for never
+    last_message: AtomicU64,
+    last_touch: AtomicU64,
+    /// Last message seen by the previous watchdog ping
+    pinged: AtomicU64,
+    ready: AtomicBool,
+}
+
+impl Liveness {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let touch_interval = match env::var("LIVENESS_TOUCH_MS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid LIVENESS_TOUCH_MS"))?,
+            Err(_) => DEFAULT_TOUCH_MS,
+        };
+        let notify = match env::var("NOTIFY_SOCKET") {
+            Ok(path) if path.starts_with('@') => {
+                warn!("liveness: abstract NOTIFY_SOCKET {path} is not supported, systemd is not notified");
+                None
+            }
+            Ok(path) if cfg!(unix) => Some(PathBuf::from(path)),
+            _ => None,
+        };
+        // The watchdog is meant for another process if `WATCHDOG_PID` is someone else
+        let watchdog_pid = env::var("WATCHDOG_PID").ok();
+        let watchdog = match env::var("WATCHDOG_USEC") {
+            Ok(_) if watchdog_pid.is_some_and(|pid| pid != std::process::id().to_string()) => None,
+            Ok(value) => Some(
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|usec| *usec > 0)
+                    .map(|usec| Duration::from_micros(usec / 2))
+                    .ok_or_else(|| anyhow::anyhow!("invalid WATCHDOG_USEC"))?,
+            ),
+            Err(_) => None,
+        }
+        .filter(|_| notify.is_some());
+        if let Some(watchdog) = watchdog {
+            info!("liveness: systemd watchdog ping every {watchdog:?} while messages arrive");
+        }
+        Ok(Self {
+            file: env::var_os("LIVENESS_FILE").map(PathBuf::from),
+            touch_interval,
+            notify,
+            watchdog,
+            started: Instant::now(),
+            last_message: AtomicU64::new(u64::MAX),
+            last_touch: AtomicU64::new(u64::MAX),
+            pinged: AtomicU64::new(u64::MAX),
+            ready: AtomicBool::new(false),
+        })
+    }
+
+    pub const fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// A stream is open, tell systemd the service started
+    pub fn ready(&self) {
+        if self.notify.is_some() && !self.ready.swap(true, Ordering::Relaxed) {
+            self.notify("READY=1");
+        }
+    }
+
+    /// A stream message was received
+    pub fn observe(&self) {
+        if self.file.is_none() && self.notify.is_none() {
+            return;
+        }
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_message.store(now, Ordering::Relaxed);
+        self.ready();
+        let Some(path) = self.file.as_ref() else {
+            return;
+        };
+        let last_touch = self.last_touch.load(Ordering::Relaxed);
+        if last_touch != u64::MAX && now.saturating_sub(last_touch) < self.touch_interval {
+            return;
+        }
+        // Only one of concurrent streams writes the file
+        if self
+            .last_touch
+            .compare_exchange(last_touch, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            if let Err(error) = fs::write(path, format!("{}\n", Utc::now().to_rfc3339())) {
+                warn!("liveness: failed to write {}: {error}", path.display());
+            }
+        }
+    }
+
+    /// Ping the systemd watchdog while messages arrive, until the task is aborted
+    pub async fn run_watchdog(&self) {
+        let Some(period) = self.watchdog else {
+            return;
+        };
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let last_message = self.last_message.load(Ordering::Relaxed);
+            if last_message != u64::MAX
+                && self.pinged.swap(last_message, Ordering::Relaxed) != last_message
+            {
+                self.notify("WATCHDOG=1");
+            }
+        }
+    }
+
+    /// The client shuts down
+    pub fn stopping(&self) {
+        if self.notify.is_some() {
+            self.notify("STOPPING=1");
+        }
+    }
+
+    fn notify(&self, state: &str) {
+        let Some(path) = self.notify.as_ref() else {
+            return;
+        };
+        if let Err(error) = send_notify(path, state) {
+            warn!("liveness: failed to notify systemd with {state}: {error}");
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send_notify(path: &Path, state: &str) -> std::io::Result<()> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.send_to(state.as_bytes(), path).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn send_notify(_path: &Path, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
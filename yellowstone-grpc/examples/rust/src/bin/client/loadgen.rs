@@ -0,0 +1,259 @@
+//! Load test of the processing pipeline against the mock server, `ACTION=LoadGen`.
+//!
+//! The [mock Geyser server](crate::mock) is started on `LOADGEN_ADDR` and the client
+//! subscribes to it over gRPC like to a live endpoint, received updates go to the update
+//! queue and through dedup, logging and all configured sinks. The server sends account
+//! updates of `LOADGEN_DATA_BYTES` at `LOADGEN_RATE` messages per second, every
+//! `LOADGEN_STEP_SECS` the rate is doubled, up to `LOADGEN_MAX_RATE`, until a step is not
+//! sustained: fewer messages were sent or processed than the target rate, or the queue or a
+//! sink dropped updates. The report lists every step and the highest sustained rate, which
+//! is the throughput of the sink configuration on this hardware.
+
+use {
+    crate::{
+        mock::{self, MockConfig, MockGeyser, FILTER},
+        queue::UpdateQueue,
+        sink::Sinks,
+        stats::StreamStats,
+    },
+    futures::stream::StreamExt,
+    log::info,
+    maplit::hashmap,
+    serde::Serialize,
+    serde_json::{json, Value},
+    std::{
+        env,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::watch,
+        time::{sleep, Instant},
+    },
+    yellowstone_grpc_client::GeyserGrpcClient,
+    yellowstone_grpc_proto::prelude::{
+        SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
+    },
+};
+
+/// Progress is checked at this interval
+const TICK: Duration = Duration::from_millis(10);
+/// Share of the target rate a step should reach to be sustained
+const SUSTAINED: f64 = 0.95;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoadGenConfig {
+    addr: SocketAddr,
+    rate: u64,
+    max_rate: u64,
+    step: Duration,
+    data_bytes: usize,
+    accounts: u64,
+}
+
+impl LoadGenConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse = |key: &str, default: u64| match env::var(key) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid {key}")),
+            Err(_) => Ok(default),
+        };
+        let config = Self {
+            addr: env::var("LOADGEN_ADDR")
+                .unwrap_or_else(|_| "127.0.0.1:0".to_owned())
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid LOADGEN_ADDR"))?,
+            rate: parse("LOADGEN_RATE", 1_000)?,
+            max_rate: parse("LOADGEN_MAX_RATE", 1_000_000)?,
+            step: Duration::from_secs(parse("LOADGEN_STEP_SECS", 10)?),
+            data_bytes: parse("LOADGEN_DATA_BYTES", 165)? as usize,
+            accounts: parse("LOADGEN_ACCOUNTS", 1_000)?,
+        };
+        anyhow::ensure!(
+            config.rate > 0 && config.rate <= config.max_rate,
+            "LOADGEN_RATE should be above 0 and not above LOADGEN_MAX_RATE"
+        );
+        anyhow::ensure!(
+            !config.step.is_zero() && config.accounts > 0,
+            "LOADGEN_STEP_SECS and LOADGEN_ACCOUNTS should be above 0"
+        );
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Step {
+    /// Target messages per second
+    rate: u64,
+    /// Messages per second sent by the mock server, lower than `rate` when the client or
+    /// the queue can't keep up
+    generated: f64,
+    /// Messages per second received over gRPC
+    received: f64,
+    /// Messages per second processed by the workers
+    processed: f64,
+    queue_dropped: u64,
+    sink_dropped: u64,
+    sink_errors: u64,
+    sustained: bool,
+}
+
+fn sink_counters(sinks: &Sinks) -> (u64, u64) {
+    sinks
+        .health()
+        .iter()
+        .fold((0, 0), |(dropped, errors), health| {
+            (dropped + health.dropped, errors + health.errors)
+        })
+}
+
+/// Serve the mock server and subscribe to it, run steps until one is not sustained,
+/// `LOADGEN_MAX_RATE` is reached or shutdown
+pub async fn run(
+    config: LoadGenConfig,
+    queue: &UpdateQueue,
+    stats: &StreamStats,
+    sinks: &Sinks,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<Value> {
+    let (rate_tx, rate_rx) = watch::channel(config.rate);
+    let sent = Arc::new(AtomicU64::new(0));
+    let mock = MockConfig {
+        data_bytes: config.data_bytes,
+        accounts: config.accounts,
+    };
+    let (addr, server) = mock::serve(
+        config.addr,
+        MockGeyser::new(mock, rate_rx, Arc::clone(&sent)),
+    )
+    .await?;
+    info!("loadgen: mock server on {addr}");
+
+    let result = async {
+        let mut client = GeyserGrpcClient::build_from_shared(format!("http://{addr}"))?
+            .max_decoding_message_size(usize::MAX)
+            .connect()
+            .await?;
+        let request = SubscribeRequest {
+            accounts: hashmap! { FILTER.to_owned() => SubscribeRequestFilterAccounts::default() },
+            slots: hashmap! { FILTER.to_owned() => SubscribeRequestFilterSlots::default() },
+            ..Default::default()
+        };
+        let mut stream = client.subscribe_once(request).await?;
+
+        let received = AtomicU64::new(0);
+        let receive = async {
+            while let Some(msg) = stream.next().await {
+                received.fetch_add(1, Ordering::Relaxed);
+                queue.push(msg?).await;
+            }
+            anyhow::bail!("mock server closed the stream")
+        };
+        let counters = Counters {
+            sent: &sent,
+            received: &received,
+        };
+        tokio::select! {
+            report = steps(config, &rate_tx, counters, queue, stats, sinks, shutdown) => Ok(report),
+            result = receive => result,
+        }
+    }
+    .await;
+    server.abort();
+    result
+}
+
+struct Counters<'a> {
+    sent: &'a AtomicU64,
+    received: &'a AtomicU64,
+}
+
+async fn steps(
+    config: LoadGenConfig,
+    rate_tx: &watch::Sender<u64>,
+    counters: Counters<'_>,
+    queue: &UpdateQueue,
+    stats: &StreamStats,
+    sinks: &Sinks,
+    mut shutdown: watch::Receiver<bool>,
+) -> Value {
+    let mut steps: Vec<Step> = vec![];
+    let mut rate = config.rate;
+    'steps: loop {
+        info!("loadgen: {rate} messages per second for {:?}", config.step);
+        let queue_dropped = queue.dropped();
+        let (sink_dropped, sink_errors) = sink_counters(sinks);
+        let processed = stats.messages();
+        let sent = counters.sent.load(Ordering::Relaxed);
+        let received = counters.received.load(Ordering::Relaxed);
+        let _ = rate_tx.send(rate);
+        let step_started = Instant::now();
+        while step_started.elapsed() < config.step {
+            tokio::select! {
+                () = sleep(TICK) => {}
+                Ok(_) = shutdown.wait_for(|stop| *stop) => break 'steps,
+            }
+        }
+        let elapsed = step_started.elapsed().as_secs_f64();
+        let sent = (counters.sent.load(Ordering::Relaxed) - sent) as f64 / elapsed;
+        let received = (counters.received.load(Ordering::Relaxed) - received) as f64 / elapsed;
+        let processed = (stats.messages() - processed) as f64 / elapsed;
+
+        // Backlog of a step which is processed slower than generated is not sustained
+        let _ = rate_tx.send(0);
+        let drain_deadline = Instant::now() + config.step;
+        while queue.depth() > 0 && Instant::now() < drain_deadline {
+            sleep(TICK).await;
+        }
+        let (dropped, errors) = sink_counters(sinks);
+        let step = Step {
+            rate,
+            generated: sent,
+            received,
+            processed,
+            queue_dropped: queue.dropped() - queue_dropped,
+            sink_dropped: dropped - sink_dropped,
+            sink_errors: errors - sink_errors,
+            sustained: false,
+        };
+        let sustained = step.generated >= rate as f64 * SUSTAINED
+            && step.processed >= rate as f64 * SUSTAINED
+            && queue.depth() == 0
+            && step.queue_dropped == 0
+            && step.sink_dropped == 0
+            && step.sink_errors == 0;
+        info!(
+            "loadgen: {rate}/s, sent {:.0}/s, received {:.0}/s, processed {:.0}/s, {}",
+            step.generated,
+            step.received,
+            step.processed,
+            if sustained {
+                "sustained"
+            } else {
+                "not sustained"
+            }
+        );
+        steps.push(Step { sustained, ..step });
+        if !sustained || rate >= config.max_rate {
+            break;
+        }
+        rate = (rate * 2).min(config.max_rate);
+    }
+
+    let max_sustained = steps
+        .iter()
+        .filter(|step| step.sustained)
+        .map(|step| step.rate)
+        .max();
+    json!({
+        "data_bytes": config.data_bytes,
+        "accounts": config.accounts,
+        "max_sustained_rate": max_sustained,
+        "steps": steps,
+    })
+}
@@ -0,0 +1,51 @@
+use {
+    log::{Log, Metadata, Record},
+    std::sync::{OnceLock, RwLock},
+};
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+/// `env_logger` wrapper whose filter directives can be replaced at runtime.
+struct ReloadableLogger {
+    inner: RwLock<env_logger::Logger>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().expect("poisoned").enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.read().expect("poisoned").log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.read().expect("poisoned").flush()
+    }
+}
+
+fn build(filters: &str) -> env_logger::Logger {
+    env_logger::Builder::new().parse_filters(filters).build()
+}
+
+/// Install the global logger with `RUST_LOG`-style filter directives.
+pub fn init(filters: &str) -> anyhow::Result<()> {
+    let logger = LOGGER.get_or_init(|| ReloadableLogger {
+        inner: RwLock::new(build(filters)),
+    });
+    log::set_logger(logger)?;
+    log::set_max_level(logger.inner.read().expect("poisoned").filter());
+    Ok(())
+}
+
+/// Replace filter directives of the installed logger, e.g. `info,client=debug`.
+pub fn set_filters(filters: &str) -> anyhow::Result<()> {
+    let logger = LOGGER
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("logger is not initialized"))?;
+    let new_logger = build(filters);
+    let level = new_logger.filter();
+    *logger.inner.write().expect("poisoned") = new_logger;
+    log::set_max_level(level);
+    Ok(())
+}
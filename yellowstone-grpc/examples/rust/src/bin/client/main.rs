@@ -0,0 +1,3264 @@
+mod admin;
+mod analyze;
+mod attach;
+mod balances;
+mod bandwidth;
+mod bench;
+mod budget;
+mod capture;
+mod catchup;
+mod checkpoint;
+mod cli;
+mod coalesce;
+mod config;
+mod correlate;
+mod dashboard;
+mod decode;
+mod dedup;
+mod diff;
+mod discover;
+mod endpoint;
+mod error;
+mod events;
+mod expr;
+mod facade;
+mod filters;
+mod forks;
+mod gaps;
+mod health;
+mod hold;
+mod instructions;
+mod json;
+mod latency;
+mod lifecycle;
+mod liveness;
+mod loadgen;
+mod logging;
+mod mints;
+mod mock;
+mod multi;
+mod output;
+mod owners;
+mod policy;
+mod priority;
+mod poll;
+mod pool;
+mod profiles;
+mod progress;
+mod queue;
+mod reassemble;
+mod recent;
+mod reconnects;
+mod reload;
+mod retry;
+mod sampler;
+mod schema;
+mod serve;
+mod settings;
+mod shutdown;
+mod simulate;
+mod sink;
+mod slo;
+mod snapshot;
+mod statesync;
+mod stats;
+mod synth;
+mod tags;
+#[cfg(feature = "template")]
+mod template;
+mod tls;
+mod token;
+mod trace;
+mod truncate;
+mod watchdog;
+mod watchlist;
+
+use {
+    crate::{
+        admin::AdminState,
+        analyze::{AnalyzeConfig, Analyzer},
+        balances::{balance_changes, BalanceChange},
+        bandwidth::BandwidthMeter,
+        bench::BenchMeter,
+        budget::MemoryBudget,
+        capture::{CaptureFormat, CaptureHeader, CaptureReader, CaptureWriter},
+        catchup::{CatchUp, CATCHUP_CHECK_INTERVAL},
+        checkpoint::{Checkpoint, SAVE_INTERVAL},
+        coalesce::{AccountCoalescer, Coalesced},
+        correlate::TxCorrelator,
+        dashboard::Dashboard,
+        decode::DecodePool,
+        dedup::DedupCache,
+        diff::{AccountDiff, AccountDiffs},
+        discover::AccountDiscovery,
+        endpoint::{EndpointConfig, Endpoints},
+        error::ClientError,
+        events::EventBus,
+        expr::ExprFilter,
+        facade::{RpcFacade, RpcFacadeSink},
+        filters::{
+            AccountsFilterArgs, FeePayerFilter, LamportsFilter, LogFilter, NamedFilters,
+            TransactionsFilterArgs,
+        },
+        forks::ForkDetector,
+        gaps::GapDetector,
+        health::HealthHooks,
+        hold::CommitmentHold,
+        instructions::{parse_instructions, InstructionPretty},
+        latency::LatencyTracker,
+        lifecycle::{Lifecycle, Phase, LIFECYCLE_CHECK_INTERVAL},
+        liveness::Liveness,
+        loadgen::LoadGenConfig,
+        mints::{MintTracker, MintTrackerSink},
+        multi::MultiMerge,
+        output::{OutputFormat, ToJson},
+        owners::{TokenOwners, TokenOwnersSink},
+        policy::{ErrorPolicy, Stage},
+        priority::PriorityLanes,
+        poll::PollValues,
+        pool::ConnectionPool,
+        progress::Progress,
+        queue::{OverflowPolicy, UpdateQueue},
+        reassemble::{BlockAssembler, REASSEMBLE_INTERVAL},
+        recent::{RecentSink, RecentUpdates},
+        reconnects::{EndReason, ReconnectHistory},
+        reload::{load_filters, FiltersWatcher},
+        retry::RetryConfig,
+        sampler::StreamSampler,
+        schema::SchemaCheck,
+        serve::{Broadcast, ServeSink},
+        settings::RuntimeSettings,
+        simulate::simulate,
+        sink::Sinks,
+        slo::LatencySlos,
+        snapshot::Snapshot,
+        statesync::{StateSync, StateSyncSink},
+        stats::{update_slot, StreamStats},
+        synth::SynthConfig,
+        tags::{format_tags, FilterTags, Tags},
+        trace::trace_id,
+        watchdog::{Watchdog, WatchdogConfig, CHECK_INTERVAL},
+    },
+    backoff::{backoff::Backoff, future::retry, ExponentialBackoff},
+    futures::{
+        future::{join_all, poll_fn, TryFutureExt},
+        sink::SinkExt,
+        stream::StreamExt,
+    },
+    log::{error, info, log_enabled, warn, Level},
+    solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::TransactionError},
+    solana_transaction_status::{
+        EncodedTransactionWithStatusMeta, TransactionWithStatusMeta, UiTransactionEncoding,
+    },
+    std::{
+        collections::HashMap, env, fmt, fs::File, net::SocketAddr, sync::Arc,
+        time::Duration,
+    },
+    tokio::{
+        sync::{watch, Mutex},
+        time::{interval, sleep, sleep_until, timeout, timeout_at, Instant, MissedTickBehavior},
+    },
+    yellowstone_grpc_client::{GeyserGrpcClient, GeyserGrpcClientError, Interceptor},
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
+        SubscribeRequestAccountsDataSlice, SubscribeRequestFilterAccounts,
+        SubscribeRequestFilterBlocks, SubscribeRequestFilterBlocksMeta,
+        SubscribeRequestFilterEntry, SubscribeRequestFilterSlots,
+        SubscribeRequestFilterTransactions, SubscribeRequestPing, SubscribeUpdate,
+        SubscribeUpdateAccount, SubscribeUpdateTransaction, SubscribeUpdateTransactionStatus,
+    },
+};
+
+type SlotsFilterMap = HashMap<String, SubscribeRequestFilterSlots>;
+type AccountFilterMap = HashMap<String, SubscribeRequestFilterAccounts>;
+type TransactionsFilterMap = HashMap<String, SubscribeRequestFilterTransactions>;
+type TransactionsStatusFilterMap = HashMap<String, SubscribeRequestFilterTransactions>;
+type EntryFilterMap = HashMap<String, SubscribeRequestFilterEntry>;
+type BlocksFilterMap = HashMap<String, SubscribeRequestFilterBlocks>;
+type BlocksMetaFilterMap = HashMap<String, SubscribeRequestFilterBlocksMeta>;
+
+#[derive(Debug, Clone)]
+struct Args {
+    endpoints: Arc<Endpoints>,
+    commitment: Option<ArgsCommitment>,
+    action: Action,
+    admin_addr: Option<SocketAddr>,
+    admin_replay_dir: Option<String>,
+    output: OutputFormat,
+    shutdown_grace: Duration,
+    poll_interval: Option<Duration>,
+    queue_capacity: usize,
+    queue_workers: usize,
+    queue_overflow: OverflowPolicy,
+    bandwidth_report: Option<Duration>,
+    stats_interval: Option<Duration>,
+    filters_path: Option<String>,
+    parse_instructions: bool,
+    balance_changes: bool,
+    preflight: bool,
+    retry: RetryConfig,
+    /// Single unary call within this time, without retries
+    oneshot: Option<Duration>,
+}
+
+impl Args {
+    fn new_from_env() -> anyhow::Result<Self> {
+        // Required environment variables (except offline actions), with optional X_TOKEN,
+        // TLS and compression settings and failover endpoints
+        let endpoints = Endpoints::from_env()?;
+        
+        // Parse commitment
+        let commitment = env::var("COMMITMENT").ok().map(|c| {
+            match c.as_str() {
+                "Processed" => ArgsCommitment::Processed,
+                "Confirmed" => ArgsCommitment::Confirmed,
+                "Finalized" => ArgsCommitment::Finalized,
+                _ => ArgsCommitment::Processed, // Default to Processed if invalid
+            }
+        });
+        
+        // Parse action
+        let action_str = env::var("ACTION")
+            .map_err(|_| anyhow::anyhow!("ACTION environment variable not set"))?;
+        
+        let action = match action_str.as_str() {
+            "HealthCheck" => Action::HealthCheck,
+            "HealthWatch" => Action::HealthWatch,
+            "Ping" => {
+                let count = env::var("PING_COUNT")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0);
+                Action::Ping { count }
+            },
+            "GetLatestBlockhash" => Action::GetLatestBlockhash,
+            "GetBlockHeight" => Action::GetBlockHeight,
+            "GetSlot" => Action::GetSlot,
+            "IsBlockhashValid" => {
+                let blockhash = env::var("BLOCKHASH")
+                    .map_err(|_| anyhow::anyhow!("BLOCKHASH environment variable required for IsBlockhashValid action"))?;
+                Action::IsBlockhashValid { blockhash }
+            },
+            "GetVersion" => Action::GetVersion,
+            "Subscribe" => {
+                // Create a new ActionSubscribe and populate from env vars
+                let subscribe_args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Subscribe(subscribe_args)
+            },
+            "MultiSubscribe" => {
+                let subscribe_args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::MultiSubscribe(subscribe_args)
+            },
+            "Query" => {
+                let queries = env::var("QUERIES")
+                    .map_err(|_| anyhow::anyhow!("QUERIES environment variable required for Query action"))?
+                    .split(',')
+                    .map(|query| Query::from_env_value(query.trim()))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Action::Query { queries }
+            },
+            "Record" => {
+                let path = env::var("RECORD_PATH")
+                    .map_err(|_| anyhow::anyhow!("RECORD_PATH environment variable required for Record action"))?;
+                let format = env::var("RECORD_FORMAT").map(|value| CaptureFormat::from_env_value(&value)).unwrap_or(Ok(CaptureFormat::Raw))?;
+                let args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Record { path, format, args }
+            },
+            "Replay" => {
+                let path = env::var("REPLAY_PATH")
+                    .map_err(|_| anyhow::anyhow!("REPLAY_PATH environment variable required for Replay action"))?;
+                let speed = env::var("REPLAY_SPEED").ok().and_then(|s| s.parse().ok()).unwrap_or(1.0);
+                Action::Replay { path, speed }
+            },
+            "Simulate" => {
+                let path = env::var("SIMULATE_PATH")
+                    .map_err(|_| anyhow::anyhow!("SIMULATE_PATH environment variable required for Simulate action"))?;
+                let args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Simulate { path, args }
+            },
+            "Dashboard" => {
+                let subscribe_args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Dashboard(subscribe_args)
+            },
+            "Serve" => {
+                let addr = env::var("SERVE_ADDR").unwrap_or_else(|_| "127.0.0.1:8901".to_owned()).parse()
+                    .map_err(|_| anyhow::anyhow!("invalid SERVE_ADDR"))?;
+                let args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Serve { addr, args }
+            },
+            "Status" => {
+                let addr = env::var("STATUS_ATTACH")
+                    .map_err(|_| anyhow::anyhow!("STATUS_ATTACH environment variable required for Status action"))?;
+                Action::Status { addr }
+            },
+            "Poll" => Action::Poll,
+            "LatencyBench" => {
+                let interval = env::var("LATENCY_INTERVAL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(10);
+                let window = env::var("LATENCY_WINDOW_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(60);
+                Action::LatencyBench {
+                    interval: Duration::from_secs(interval),
+                    window: Duration::from_secs(window),
+                }
+            },
+            "LoadGen" => Action::LoadGen(LoadGenConfig::from_env()?),
+            "Bench" => {
+                let duration = bench::duration_from_env()?;
+                let args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Bench { duration, args }
+            },
+            "Analyze" => {
+                let config = AnalyzeConfig::from_env()?;
+                let args = Box::new(self::parse_subscribe_args_from_env()?);
+                Action::Analyze { config, args }
+            },
+            "Generate" => {
+                let path = env::var("GENERATE_PATH")
+                    .map_err(|_| anyhow::anyhow!("GENERATE_PATH environment variable required for Generate action"))?;
+                Action::Generate { path, config: SynthConfig::from_env()? }
+            },
+            "Verify" => {
+                let path = env::var("VERIFY_PATH")
+                    .map_err(|_| anyhow::anyhow!("VERIFY_PATH environment variable required for Verify action"))?;
+                Action::Verify { path }
+            },
+            _ => return Err(anyhow::anyhow!("Invalid ACTION value")),
+        };
+
+        let endpoints = Arc::new(match endpoints {
+            Some(endpoints) => endpoints,
+            None if matches!(action, Action::Replay { .. } | Action::Simulate { .. } | Action::Status { .. } | Action::LoadGen(_) | Action::Generate { .. } | Action::Verify { .. }) => Endpoints::offline(),
+            None => anyhow::bail!("ENDPOINT environment variable not set"),
+        });
+        
+        // Output format for request/response actions
+        let output = env::var("OUTPUT")
+            .ok()
+            .map(|value| OutputFormat::from_env_value(&value))
+            .transpose()?
+            .unwrap_or_default();
+
+        // How long to process in-flight messages after SIGINT/SIGTERM
+        let shutdown_grace = Duration::from_millis(env::var("SHUTDOWN_GRACE_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(2_000));
+
+        // Poll GetSlot/GetBlockHeight/GetLatestBlockhash alongside the stream
+        let poll_interval = env::var("POLL_INTERVAL_MS").ok().and_then(|s| s.parse().ok()).map(Duration::from_millis);
+        let poll_interval = match action {
+            Action::Poll => Some(poll_interval.unwrap_or(Duration::from_secs(1))),
+            _ => poll_interval,
+        };
+
+        // Queue between the stream reader and processing workers
+        let queue_capacity = env::var("QUEUE_CAPACITY").ok().and_then(|s| s.parse().ok()).unwrap_or(10_000usize).max(1);
+        let queue_workers = env::var("QUEUE_WORKERS").ok().and_then(|s| s.parse().ok()).unwrap_or(1usize).max(1);
+        let queue_overflow = env::var("QUEUE_OVERFLOW")
+            .ok()
+            .map(|value| OverflowPolicy::from_env_value(&value))
+            .transpose()?
+            .unwrap_or_default();
+
+        // Subscription filters file, reloaded on change
+        let filters_path = env::var("FILTERS_PATH").ok();
+
+        // Print bandwidth usage and projected cost periodically
+        let bandwidth_report = env::var("BANDWIDTH_REPORT_SECS").ok().and_then(|s| s.parse().ok()).map(Duration::from_secs);
+
+        // Print per-filter totals periodically, they are printed on exit anyway
+        let stats_interval = env::var("STATS_INTERVAL_SECS").ok().and_then(|s| s.parse().ok()).filter(|secs| *secs > 0).map(Duration::from_secs);
+
+        // Log decoded instructions of well-known programs with transactions
+        let parse_instructions = env::var("PARSE_INSTRUCTIONS").ok().and_then(|s| s.parse().ok()).unwrap_or(false);
+
+        // Log who gained or lost SOL and tokens with transactions
+        let balance_changes = env::var("BALANCE_CHANGES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(false);
+
+        // Check sinks before subscribing
+        let preflight = env::var("PREFLIGHT").ok().and_then(|s| s.parse().ok()).unwrap_or(true);
+
+        // Reconnect backoff
+        let retry = RetryConfig::from_env()?;
+
+        // Single unary call printed as JSON, for scripts and monitoring probes
+        let oneshot = env::var("ONESHOT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(false);
+        let oneshot = oneshot.then(|| {
+            Duration::from_millis(
+                env::var("ONESHOT_TIMEOUT_MS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(10_000),
+            )
+        });
+        anyhow::ensure!(
+            oneshot.is_none() || action.oneshot_queries().is_some(),
+            "ONESHOT requires one of HealthCheck, Ping, GetLatestBlockhash, GetBlockHeight, GetSlot, IsBlockhashValid, GetVersion or Query actions"
+        );
+
+        // Admin API for runtime settings
+        let admin_addr = env::var("ADMIN_ADDR")
+            .ok()
+            .map(|addr| addr.parse())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("invalid ADMIN_ADDR"))?;
+        let admin_replay_dir = env::var("ADMIN_REPLAY_DIR").ok();
+
+        Ok(Args {
+            endpoints,
+            commitment,
+            action,
+            admin_addr,
+            admin_replay_dir,
+            output,
+            shutdown_grace,
+            poll_interval,
+            queue_capacity,
+            queue_workers,
+            queue_overflow,
+            bandwidth_report,
+            stats_interval,
+            filters_path,
+            parse_instructions,
+            balance_changes,
+            preflight,
+            retry,
+            oneshot,
+        })
+    }
+
+    fn get_commitment(&self) -> Option<CommitmentLevel> {
+        Some(self.commitment.unwrap_or_default().into())
+    }
+
+    async fn connect(&self) -> Result<GeyserGrpcClient<impl Interceptor>, ClientError> {
+        self.endpoints
+            .current()
+            .connect()
+            .await
+            .map_err(ClientError::Connection)
+    }
+}
+
+fn parse_subscribe_args_from_env() -> anyhow::Result<ActionSubscribe> {
+    // Helper function to parse boolean env vars
+    let parse_bool = |key: &str| -> bool {
+        env::var(key)
+            .ok()
+            .and_then(|val| val.parse::<bool>().ok())
+            .unwrap_or(false)
+    };
+    
+    // Helper function to parse comma-separated strings 
+    let parse_string_list = |key: &str| -> Vec<String> {
+        env::var(key)
+            .ok()
+            .map(|val| val.split(',').map(|s| s.trim().to_string()).collect())
+            .unwrap_or_default()
+    };
+    
+    Ok(ActionSubscribe {
+        accounts: parse_bool("SUBSCRIBE_ACCOUNTS"),
+        accounts_account: parse_string_list("ACCOUNTS_ACCOUNT"),
+        accounts_account_path: env::var("ACCOUNTS_ACCOUNT_PATH").ok(),
+        accounts_owner: parse_string_list("ACCOUNTS_OWNER"),
+        accounts_memcmp: parse_string_list("ACCOUNTS_MEMCMP"),
+        accounts_datasize: env::var("ACCOUNTS_DATASIZE").ok().and_then(|s| s.parse().ok()),
+        accounts_token_account_state: parse_bool("ACCOUNTS_TOKEN_ACCOUNT_STATE"),
+        accounts_data_slice: parse_string_list("ACCOUNTS_DATA_SLICE"),
+        slots: parse_bool("SUBSCRIBE_SLOTS"),
+        slots_filter_by_commitment: parse_bool("SLOTS_FILTER_BY_COMMITMENT"),
+        transactions: parse_bool("SUBSCRIBE_TRANSACTIONS"),
+        transactions_vote: env::var("TRANSACTIONS_VOTE").ok().and_then(|s| s.parse().ok()),
+        transactions_failed: env::var("TRANSACTIONS_FAILED").ok().and_then(|s| s.parse().ok()),
+        transactions_signature: env::var("TRANSACTIONS_SIGNATURE").ok(),
+        transactions_account_include: parse_string_list("TRANSACTIONS_ACCOUNT_INCLUDE"),
+        transactions_account_exclude: parse_string_list("TRANSACTIONS_ACCOUNT_EXCLUDE"),
+        transactions_account_required: parse_string_list("TRANSACTIONS_ACCOUNT_REQUIRED"),
+        transactions_status: parse_bool("SUBSCRIBE_TRANSACTIONS_STATUS"),
+        transactions_status_vote: env::var("TRANSACTIONS_STATUS_VOTE").ok().and_then(|s| s.parse().ok()),
+        transactions_status_failed: env::var("TRANSACTIONS_STATUS_FAILED").ok().and_then(|s| s.parse().ok()),
+        transactions_status_signature: env::var("TRANSACTIONS_STATUS_SIGNATURE").ok(),
+        transactions_status_account_include: parse_string_list("TRANSACTIONS_STATUS_ACCOUNT_INCLUDE"),
+        transactions_status_account_exclude: parse_string_list("TRANSACTIONS_STATUS_ACCOUNT_EXCLUDE"),
+        transactions_status_account_required: parse_string_list("TRANSACTIONS_STATUS_ACCOUNT_REQUIRED"),
+        entry: parse_bool("SUBSCRIBE_ENTRY"),
+        blocks: parse_bool("SUBSCRIBE_BLOCKS"),
+        blocks_account_include: parse_string_list("BLOCKS_ACCOUNT_INCLUDE"),
+        blocks_include_transactions: env::var("BLOCKS_INCLUDE_TRANSACTIONS").ok().and_then(|s| s.parse().ok()),
+        blocks_include_accounts: env::var("BLOCKS_INCLUDE_ACCOUNTS").ok().and_then(|s| s.parse().ok()),
+        blocks_include_entries: env::var("BLOCKS_INCLUDE_ENTRIES").ok().and_then(|s| s.parse().ok()),
+        blocks_meta: parse_bool("SUBSCRIBE_BLOCKS_META"),
+        ping: env::var("PING_COUNT").ok().and_then(|s| s.parse().ok()),
+        resub: env::var("RESUB").ok().and_then(|s| s.parse().ok()),
+        named_filters: NamedFilters::from_env()?,
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum ArgsCommitment {
+    #[default]
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<ArgsCommitment> for CommitmentLevel {
+    fn from(commitment: ArgsCommitment) -> Self {
+        match commitment {
+            ArgsCommitment::Processed => CommitmentLevel::Processed,
+            ArgsCommitment::Confirmed => CommitmentLevel::Confirmed,
+            ArgsCommitment::Finalized => CommitmentLevel::Finalized,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    HealthCheck,
+    HealthWatch,
+    Subscribe(Box<ActionSubscribe>),
+    /// Subscribe to all endpoints at once and process every update from the first endpoint
+    /// which delivers it
+    MultiSubscribe(Box<ActionSubscribe>),
+    Ping {
+        count: i32,
+    },
+    GetLatestBlockhash,
+    GetBlockHeight,
+    GetSlot,
+    IsBlockhashValid {
+        blockhash: String,
+    },
+    GetVersion,
+    /// Run multiple request/response calls over one connection and print combined JSON
+    Query {
+        queries: Vec<Query>,
+    },
+    /// Subscribe and write all received messages to the capture file
+    Record {
+        path: String,
+        format: CaptureFormat,
+        args: Box<ActionSubscribe>,
+    },
+    /// Process messages from the capture file, `speed` is a multiplier of the original
+    /// pace, `0` to process as fast as possible
+    Replay {
+        path: String,
+        speed: f64,
+    },
+    /// Compare filters built from Subscribe variables with filters recorded in the
+    /// capture file
+    Simulate {
+        path: String,
+        args: Box<ActionSubscribe>,
+    },
+    /// Subscribe and show a live view of the stream in the terminal
+    Dashboard(Box<ActionSubscribe>),
+    /// Subscribe and re-broadcast updates as JSON to WebSocket clients connected to `addr`
+    Serve {
+        addr: SocketAddr,
+        args: Box<ActionSubscribe>,
+    },
+    /// Show the status of a running instance from its admin API at `addr`, read-only
+    Status {
+        addr: String,
+    },
+    /// Only poll request/response endpoints, see `POLL_INTERVAL_MS`
+    Poll,
+    /// Subscribe to slots and blocks meta, print latency percentiles over the last
+    /// `window` every `interval`
+    LatencyBench {
+        interval: Duration,
+        window: Duration,
+    },
+    /// Subscribe to the mock server at increasing rates, pass its updates through dedup,
+    /// logging and sinks and report the highest sustained rate, see `loadgen`
+    LoadGen(LoadGenConfig),
+    /// Subscribe for `duration`, discard the updates and print the throughput, see `bench`
+    Bench {
+        duration: Duration,
+        args: Box<ActionSubscribe>,
+    },
+    /// Subscribe for a while, discard the updates and print distributions of account sizes,
+    /// updated pubkeys and invoked programs, see `analyze`
+    Analyze {
+        config: AnalyzeConfig,
+        args: Box<ActionSubscribe>,
+    },
+    /// Write a synthetic stream generated from a seed to the capture file, see `synth`
+    Generate {
+        path: String,
+        config: SynthConfig,
+    },
+    /// Check the header and every record of the capture file, see `capture::verify`
+    Verify {
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Query {
+    HealthCheck,
+    Ping,
+    GetLatestBlockhash,
+    GetBlockHeight,
+    GetSlot,
+    IsBlockhashValid { blockhash: String },
+    GetVersion,
+}
+
+impl Query {
+    fn from_env_value(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "HealthCheck" => Self::HealthCheck,
+            "Ping" => Self::Ping,
+            "GetLatestBlockhash" => Self::GetLatestBlockhash,
+            "GetBlockHeight" => Self::GetBlockHeight,
+            "GetSlot" => Self::GetSlot,
+            "IsBlockhashValid" => Self::IsBlockhashValid {
+                blockhash: env::var("BLOCKHASH").map_err(|_| {
+                    anyhow::anyhow!(
+                        "BLOCKHASH environment variable required for IsBlockhashValid query"
+                    )
+                })?,
+            },
+            "GetVersion" => Self::GetVersion,
+            _ => anyhow::bail!("invalid query: {value}"),
+        })
+    }
+
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::HealthCheck => "HealthCheck",
+            Self::Ping => "Ping",
+            Self::GetLatestBlockhash => "GetLatestBlockhash",
+            Self::GetBlockHeight => "GetBlockHeight",
+            Self::GetSlot => "GetSlot",
+            Self::IsBlockhashValid { .. } => "IsBlockhashValid",
+            Self::GetVersion => "GetVersion",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ActionSubscribe {
+    /// Subscribe on accounts updates
+    accounts: bool,
+
+    /// Filter by Account Pubkey
+    accounts_account: Vec<String>,
+
+    /// Path to a JSON array of account addresses
+    accounts_account_path: Option<String>,
+
+    /// Filter by Owner Pubkey
+    accounts_owner: Vec<String>,
+
+    /// Filter by Offset and Data, format: `offset,data in base58`
+    accounts_memcmp: Vec<String>,
+
+    /// Filter by Data size
+    accounts_datasize: Option<u64>,
+
+    /// Filter valid token accounts
+    accounts_token_account_state: bool,
+
+    /// Receive only part of updated data account, format: `offset,size`
+    accounts_data_slice: Vec<String>,
+
+    /// Subscribe on slots updates
+    slots: bool,
+
+    /// Filter slots by commitment
+    slots_filter_by_commitment: bool,
+
+    /// Subscribe on transactions updates
+    transactions: bool,
+
+    /// Filter vote transactions
+    transactions_vote: Option<bool>,
+
+    /// Filter failed transactions
+    transactions_failed: Option<bool>,
+
+    /// Filter by transaction signature
+    transactions_signature: Option<String>,
+
+    /// Filter included account in transactions
+    transactions_account_include: Vec<String>,
+
+    /// Filter excluded account in transactions
+    transactions_account_exclude: Vec<String>,
+
+    /// Filter required account in transactions
+    transactions_account_required: Vec<String>,
+
+    /// Subscribe on transactions_status updates
+    transactions_status: bool,
+
+    /// Filter vote transactions for transactions_status
+    transactions_status_vote: Option<bool>,
+
+    /// Filter failed transactions for transactions_status
+    transactions_status_failed: Option<bool>,
+
+    /// Filter by transaction signature for transactions_status
+    transactions_status_signature: Option<String>,
+
+    /// Filter included account in transactions for transactions_status
+    transactions_status_account_include: Vec<String>,
+
+    /// Filter excluded account in transactions for transactions_status
+    transactions_status_account_exclude: Vec<String>,
+
+    /// Filter required account in transactions for transactions_status
+    transactions_status_account_required: Vec<String>,
+
+    entry: bool,
+
+    /// Subscribe on block updates
+    blocks: bool,
+
+    /// Filter included account in transactions
+    blocks_account_include: Vec<String>,
+
+    /// Include transactions to block message
+    blocks_include_transactions: Option<bool>,
+
+    /// Include accounts to block message
+    blocks_include_accounts: Option<bool>,
+
+    /// Include entries to block message
+    blocks_include_entries: Option<bool>,
+
+    /// Subscribe on block meta updates (without transactions)
+    blocks_meta: bool,
+
+    /// Send ping in subscribe request
+    ping: Option<i32>,
+
+    // Resubscribe (only to slots) after
+    resub: Option<usize>,
+
+    /// Additional filters from `ACCOUNTS_FILTER_<name>_*`, `TRANSACTIONS_FILTER_<name>_*`
+    /// and `TRANSACTIONS_STATUS_FILTER_<name>_*`
+    named_filters: NamedFilters,
+}
+
+impl Action {
+    /// Calls made by unary actions, `None` for other actions
+    fn oneshot_queries(&self) -> Option<Vec<Query>> {
+        Some(match self {
+            Self::HealthCheck => vec![Query::HealthCheck],
+            Self::Ping { .. } => vec![Query::Ping],
+            Self::GetLatestBlockhash => vec![Query::GetLatestBlockhash],
+            Self::GetBlockHeight => vec![Query::GetBlockHeight],
+            Self::GetSlot => vec![Query::GetSlot],
+            Self::IsBlockhashValid { blockhash } => vec![Query::IsBlockhashValid {
+                blockhash: blockhash.clone(),
+            }],
+            Self::GetVersion => vec![Query::GetVersion],
+            Self::Query { queries } => queries.clone(),
+            _ => return None,
+        })
+    }
+
+    async fn get_subscribe_request(
+        &self,
+        commitment: Option<CommitmentLevel>,
+    ) -> Result<Option<(SubscribeRequest, usize)>, ClientError> {
+        Ok(match self {
+            Self::Subscribe(args)
+            | Self::MultiSubscribe(args)
+            | Self::Record { args, .. }
+            | Self::Simulate { args, .. }
+            | Self::Dashboard(args)
+            | Self::Serve { args, .. }
+            | Self::Bench { args, .. }
+            | Self::Analyze { args, .. } => {
+                let mut accounts: AccountFilterMap = HashMap::new();
+                if args.accounts {
+                    let mut accounts_account = args.accounts_account.clone();
+                    if let Some(path) = args.accounts_account_path.clone() {
+                        let accounts = tokio::task::block_in_place(move || {
+                            let file = File::open(path)?;
+                            Ok::<Vec<String>, anyhow::Error>(serde_json::from_reader(file)?)
+                        })
+                        .map_err(ClientError::FilterParse)?;
+                        accounts_account.extend(accounts);
+                    }
+
+                    let filter = AccountsFilterArgs {
+                        account: accounts_account,
+                        owner: args.accounts_owner.clone(),
+                        memcmp: args.accounts_memcmp.clone(),
+                        datasize: args.accounts_datasize,
+                        token_account_state: args.accounts_token_account_state,
+                    };
+                    let filter = filter.to_filter().map_err(ClientError::FilterParse)?;
+                    accounts.insert("client".to_owned(), filter);
+                }
+                for (name, filter) in args.named_filters.accounts.iter() {
+                    let filter = filter.to_filter().map_err(ClientError::FilterParse)?;
+                    accounts.insert(name.clone(), filter);
+                }
+
+                let mut slots: SlotsFilterMap = HashMap::new();
+                if args.slots {
+                    slots.insert(
+                        "client".to_owned(),
+                        SubscribeRequestFilterSlots {
+                            filter_by_commitment: Some(args.slots_filter_by_commitment),
+                        },
+                    );
+                }
+
+                let mut transactions: TransactionsFilterMap = HashMap::new();
+                if args.transactions {
+                    let filter = TransactionsFilterArgs {
+                        vote: args.transactions_vote,
+                        failed: args.transactions_failed,
+                        signature: args.transactions_signature.clone(),
+                        account_include: args.transactions_account_include.clone(),
+                        account_exclude: args.transactions_account_exclude.clone(),
+                        account_required: args.transactions_account_required.clone(),
+                    };
+                    transactions.insert("client".to_string(), filter.to_filter());
+                }
+                for (name, filter) in args.named_filters.transactions.iter() {
+                    transactions.insert(name.clone(), filter.to_filter());
+                }
+
+                let mut transactions_status: TransactionsStatusFilterMap = HashMap::new();
+                if args.transactions_status {
+                    let filter = TransactionsFilterArgs {
+                        vote: args.transactions_status_vote,
+                        failed: args.transactions_status_failed,
+                        signature: args.transactions_status_signature.clone(),
+                        account_include: args.transactions_status_account_include.clone(),
+                        account_exclude: args.transactions_status_account_exclude.clone(),
+                        account_required: args.transactions_status_account_required.clone(),
+                    };
+                    transactions_status.insert("client".to_string(), filter.to_filter());
+                }
+                for (name, filter) in args.named_filters.transactions_status.iter() {
+                    transactions_status.insert(name.clone(), filter.to_filter());
+                }
+
+                let mut entry: EntryFilterMap = HashMap::new();
+                if args.entry {
+                    entry.insert("client".to_owned(), SubscribeRequestFilterEntry {});
+                }
+
+                let mut blocks: BlocksFilterMap = HashMap::new();
+                if args.blocks {
+                    blocks.insert(
+                        "client".to_owned(),
+                        SubscribeRequestFilterBlocks {
+                            account_include: args.blocks_account_include.clone(),
+                            include_transactions: args.blocks_include_transactions,
+                            include_accounts: args.blocks_include_accounts,
+                            include_entries: args.blocks_include_entries,
+                        },
+                    );
+                }
+
+                let mut blocks_meta: BlocksMetaFilterMap = HashMap::new();
+                if args.blocks_meta {
+                    blocks_meta.insert("client".to_owned(), SubscribeRequestFilterBlocksMeta {});
+                }
+
+                let mut accounts_data_slice = Vec::new();
+                for data_slice in args.accounts_data_slice.iter() {
+                    let invalid =
+                        || ClientError::FilterParse(anyhow::anyhow!("invalid data_slice"));
+                    match data_slice.split_once(',') {
+                        Some((offset, length)) => match (offset.parse(), length.parse()) {
+                            (Ok(offset), Ok(length)) => {
+                                accounts_data_slice
+                                    .push(SubscribeRequestAccountsDataSlice { offset, length });
+                            }
+                            _ => return Err(invalid()),
+                        },
+                        _ => return Err(invalid()),
+                    }
+                }
+
+                let ping = args.ping.map(|id| SubscribeRequestPing { id });
+
+                Some((
+                    SubscribeRequest {
+                        slots,
+                        accounts,
+                        transactions,
+                        transactions_status,
+                        entry,
+                        blocks,
+                        blocks_meta,
+                        commitment: commitment.map(|x| x as i32),
+                        accounts_data_slice,
+                        ping,
+                    },
+                    args.resub.unwrap_or(0),
+                ))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct AccountPretty {
+    is_startup: bool,
+    slot: u64,
+    pubkey: Pubkey,
+    lamports: u64,
+    owner: Pubkey,
+    executable: bool,
+    rent_epoch: u64,
+    data: String,
+    write_version: u64,
+    txn_signature: String,
+}
+
+impl TryFrom<&SubscribeUpdateAccount> for AccountPretty {
+    type Error = anyhow::Error;
+
+    fn try_from(
+        SubscribeUpdateAccount {
+            is_startup,
+            slot,
+            account,
+        }: &SubscribeUpdateAccount,
+    ) -> anyhow::Result<Self> {
+        let account = account
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("account is not defined"))?;
+        Ok(Self {
+            is_startup: *is_startup,
+            slot: *slot,
+            pubkey: Pubkey::try_from(account.pubkey.as_slice())
+                .map_err(|_| anyhow::anyhow!("invalid pubkey"))?,
+            lamports: account.lamports,
+            owner: Pubkey::try_from(account.owner.as_slice())
+                .map_err(|_| anyhow::anyhow!("invalid owner"))?,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data: hex::encode(&account.data),
+            write_version: account.write_version,
+            txn_signature: bs58::encode(account.txn_signature.as_deref().unwrap_or_default())
+                .into_string(),
+        })
+    }
+}
+
+#[allow(dead_code)]
+pub struct TransactionPretty {
+    slot: u64,
+    signature: Signature,
+    is_vote: bool,
+    tx: EncodedTransactionWithStatusMeta,
+    /// Decoded instructions with `PARSE_INSTRUCTIONS`
+    instructions: Option<Vec<InstructionPretty>>,
+    /// SOL and token balance changes with `BALANCE_CHANGES`
+    balances: Option<Vec<BalanceChange>>,
+}
+
+impl fmt::Debug for TransactionPretty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct TxWrap<'a>(&'a EncodedTransactionWithStatusMeta);
+        impl<'a> fmt::Debug for TxWrap<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let serialized = serde_json::to_string(self.0).expect("failed to serialize");
+                fmt::Display::fmt(&serialized, f)
+            }
+        }
+
+        let mut f = f.debug_struct("TransactionPretty");
+        f.field("slot", &self.slot)
+            .field("signature", &self.signature)
+            .field("is_vote", &self.is_vote);
+        if let Some(instructions) = self.instructions.as_ref() {
+            f.field("instructions", instructions);
+        }
+        if let Some(balances) = self.balances.as_ref() {
+            f.field("balances", balances);
+        }
+        f.field("tx", &TxWrap(&self.tx)).finish()
+    }
+}
+
+impl TransactionPretty {
+    fn new(
+        SubscribeUpdateTransaction { transaction, slot }: &SubscribeUpdateTransaction,
+        settings: &RuntimeSettings,
+    ) -> anyhow::Result<Self> {
+        let tx = transaction
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("transaction is not defined"))?;
+        let signature = Signature::try_from(tx.signature.as_slice())
+            .map_err(|_| anyhow::anyhow!("invalid signature"))?;
+        let is_vote = tx.is_vote;
+        let tx = yellowstone_grpc_proto::convert_from::create_tx_with_meta(tx)
+            .map_err(|error| anyhow::anyhow!("invalid transaction: {error}"))?;
+        // Inner instructions and balances are part of the meta
+        let complete = match &tx {
+            TransactionWithStatusMeta::Complete(tx) => Some(tx),
+            TransactionWithStatusMeta::MissingMetadata(_) => None,
+        };
+        Ok(Self {
+            slot: *slot,
+            signature,
+            is_vote,
+            instructions: complete
+                .filter(|_| settings.parse_instructions())
+                .map(parse_instructions),
+            balances: complete
+                .filter(|_| settings.balance_changes())
+                .map(balance_changes),
+            tx: tx.encode(UiTransactionEncoding::Base64, Some(u8::MAX), true)?,
+        })
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct TransactionStatusPretty {
+    slot: u64,
+    signature: Signature,
+    is_vote: bool,
+    index: u64,
+    err: Option<TransactionError>,
+}
+
+impl TryFrom<&SubscribeUpdateTransactionStatus> for TransactionStatusPretty {
+    type Error = anyhow::Error;
+
+    fn try_from(status: &SubscribeUpdateTransactionStatus) -> anyhow::Result<Self> {
+        Ok(Self {
+            slot: status.slot,
+            signature: Signature::try_from(status.signature.as_slice())
+                .map_err(|_| anyhow::anyhow!("invalid signature"))?,
+            is_vote: status.is_vote,
+            index: status.index,
+            err: yellowstone_grpc_proto::convert_from::create_tx_error(status.err.as_ref())
+                .map_err(|error| anyhow::anyhow!("invalid transaction error: {error}"))?,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Flags and subcommands of the command line are variables set before everything else
+    if !cli::apply()? {
+        return Ok(());
+    }
+    // Before reading any variable, so `.env` applies to `RUST_LOG` too
+    config::load_dotenv();
+    if env::var("ACTION").as_deref() == Ok("ListProfiles") {
+        return profiles::list();
+    }
+    config::load_profile()?;
+    // Works with invalid or incomplete configuration, which is what it is used to debug
+    if env::var("ACTION").as_deref() == Ok("ConfigDump") {
+        config::dump();
+        return Ok(());
+    }
+
+    let log_filter = env::var(env_logger::DEFAULT_FILTER_ENV).unwrap_or_else(|_| "info".to_owned());
+    logging::init(&log_filter)?;
+    config::log_profile();
+
+    let args = Args::new_from_env()?;
+    if let Some(deadline) = args.oneshot {
+        std::process::exit(geyser_oneshot(&args, deadline).await);
+    }
+    if let Action::Status { addr } = &args.action {
+        return attach::run(addr).await;
+    }
+    if let Action::Generate { path, config } = &args.action {
+        let report = synth::generate(*config, path)?;
+        args.output.print_event("generate", &report);
+        return Ok(());
+    }
+    if let Action::Verify { path } = &args.action {
+        let report = capture::verify(path)?;
+        args.output
+            .print_event("verify", &serde_json::json!(report));
+        anyhow::ensure!(report.valid, "capture {path} is damaged");
+        return Ok(());
+    }
+    let settings = Arc::new(RuntimeSettings::new(
+        log_filter,
+        args.parse_instructions,
+        args.balance_changes,
+    ));
+    let stats = Arc::new(StreamStats::default());
+    let poll = Arc::new(PollValues::default());
+    let budget = MemoryBudget::from_env()?.map(Arc::new);
+    let queue = Arc::new(UpdateQueue::new(
+        args.queue_capacity,
+        args.queue_overflow,
+        budget.clone(),
+    ));
+    let compression = match args.action {
+        Action::Replay { .. } | Action::Simulate { .. } | Action::LoadGen(_) => None,
+        _ => args.endpoints.current().compression,
+    };
+    let bandwidth = Arc::new(BandwidthMeter::from_env(compression)?);
+    let dedup = DedupCache::from_env()?.map(Arc::new);
+    let forks = ForkDetector::from_env()?.map(Arc::new);
+    let gaps = GapDetector::from_env()?.map(Arc::new);
+    anyhow::ensure!(
+        gaps.is_none() || args.queue_workers == 1,
+        "GAP_DETECTION needs QUEUE_WORKERS=1, several workers reorder updates into false gaps"
+    );
+    let hold = CommitmentHold::from_env(budget.clone())?.map(Arc::new);
+    let priority = PriorityLanes::from_env()?.map(Arc::new);
+    let assembler = BlockAssembler::from_env()?.map(Arc::new);
+    let coalescer = AccountCoalescer::from_env()?.map(Arc::new);
+    let events = Arc::new(EventBus::from_env()?);
+    let lamports = LamportsFilter::from_env()?.map(Arc::new);
+    let logs = LogFilter::from_env()?.map(Arc::new);
+    let fee_payers = FeePayerFilter::from_env()?.map(Arc::new);
+    let exprs = ExprFilter::from_env()?.map(Arc::new);
+    if let Some(exprs) = exprs.as_ref() {
+        info!("filter expression: {}", exprs.describe());
+    }
+    let correlator = TxCorrelator::from_env(args.output)?.map(Arc::new);
+    let progress = Progress::from_env(args.output).await?.map(Arc::new);
+    if let (Some(progress), Some(dedup)) = (progress.as_ref(), dedup.as_ref()) {
+        progress.seed(dedup);
+    }
+    let decoder = DecodePool::from_env(|line| info!("{line}"))?.map(Arc::new);
+    if let Some(decoder) = decoder.as_ref() {
+        info!("decoding logged updates on {} threads", decoder.workers());
+    }
+    let sampler = StreamSampler::from_env()?.map(Arc::new);
+    let slos = LatencySlos::from_env()?.map(Arc::new);
+    let shutdown_tx = shutdown::spawn_signal_handler();
+    let errors = Arc::new(ErrorPolicy::from_env(Arc::clone(&shutdown_tx))?);
+    let schema = Arc::new(SchemaCheck::from_env(Arc::clone(&shutdown_tx))?);
+    let multi = match args.action {
+        Action::MultiSubscribe(_) => Some(Arc::new(MultiMerge::from_env(
+            args.endpoints.all().map(|(name, _)| name).collect(),
+        )?)),
+        _ => None,
+    };
+    let pool = match args.action {
+        Action::Subscribe(_) => ConnectionPool::from_env()?.map(Arc::new),
+        _ => None,
+    };
+    let discovery = AccountDiscovery::from_env()?.map(Arc::new);
+    anyhow::ensure!(
+        discovery.is_none() || (multi.is_none() && pool.is_none()),
+        "DISCOVER_ACCOUNTS needs a single stream, not MultiSubscribe or a connection pool"
+    );
+    // The tip is only polled for these actions
+    let catchup = match args.action {
+        Action::Subscribe(_) | Action::Record { .. } | Action::Serve { .. } => {
+            CatchUp::from_env(args.poll_interval)?.map(Arc::new)
+        }
+        _ => None,
+    };
+    let snapshot = match args.action {
+        Action::Subscribe(_)
+        | Action::MultiSubscribe(_)
+        | Action::Record { .. }
+        | Action::Dashboard(_)
+        | Action::Serve { .. } => Snapshot::from_env()?,
+        _ => None,
+    };
+    let tags = Arc::new(FilterTags::from_env()?);
+    let reconnects = Arc::new(ReconnectHistory::from_env(Arc::clone(&args.endpoints))?);
+    let lifecycle = Arc::new(Lifecycle::from_env()?);
+    let dashboard = matches!(args.action, Action::Dashboard(_)).then(Arc::<Dashboard>::default);
+    let mut sinks = Sinks::from_env(args.output, Arc::clone(&tags)).await?;
+    let dashboard_events = dashboard
+        .as_ref()
+        .map(|dashboard| tokio::spawn(dashboard::consume(Arc::clone(dashboard), &events)));
+    let broadcast = match args.action {
+        Action::Serve { addr, .. } => Some(Arc::new(Broadcast::from_env(addr)?)),
+        _ => None,
+    };
+    if let Some(broadcast) = broadcast.as_ref() {
+        sinks.add(Box::new(ServeSink(Arc::clone(broadcast))));
+    }
+    let recent = RecentUpdates::from_env()?.map(Arc::new);
+    if let Some(recent) = recent.as_ref() {
+        sinks.add(Box::new(RecentSink(Arc::clone(recent))));
+    }
+    let token_owners = TokenOwners::from_env()?.map(Arc::new);
+    if let Some(token_owners) = token_owners.as_ref() {
+        sinks.add(Box::new(TokenOwnersSink(Arc::clone(token_owners))));
+    }
+    let mints = MintTracker::from_env()?.map(Arc::new);
+    if let Some(mints) = mints.as_ref() {
+        sinks.add(Box::new(MintTrackerSink(Arc::clone(mints))));
+    }
+    let facade = RpcFacade::from_env(args.get_commitment().unwrap_or_default())?.map(Arc::new);
+    if let Some(facade) = facade.as_ref() {
+        sinks.add(Box::new(RpcFacadeSink(Arc::clone(facade))));
+    }
+    let state_sync = StateSync::from_env()?.map(Arc::new);
+    if let Some(state_sync) = state_sync.as_ref() {
+        sinks.add(Box::new(StateSyncSink(Arc::clone(state_sync))));
+    }
+    if let Some(priority) = priority.as_ref() {
+        sinks.prioritize(priority.sinks());
+    }
+    let sinks = Arc::new(sinks);
+    let checkpoint = Arc::new(Checkpoint::from_env()?);
+    // Fail fast instead of discovering a broken sink once the stream is live
+    if args.preflight
+        && matches!(
+            args.action,
+            Action::Subscribe(_)
+                | Action::MultiSubscribe(_)
+                | Action::Record { .. }
+                | Action::Replay { .. }
+                | Action::Dashboard(_)
+                | Action::Serve { .. }
+                | Action::LoadGen(_)
+        )
+    {
+        sinks.preflight().await?;
+    }
+    if let Some(hold) = hold.as_ref() {
+        if let Some((request, _)) = args
+            .action
+            .get_subscribe_request(args.get_commitment())
+            .await?
+        {
+            let request = match args.filters_path.as_deref() {
+                Some(filters_path) => load_filters(filters_path, &request)?.0,
+                None => request,
+            };
+            hold.check(&request)?;
+        }
+    }
+    if let Some(addr) = args.admin_addr {
+        let state = AdminState {
+            settings: Arc::clone(&settings),
+            stats: Arc::clone(&stats),
+            poll: Arc::clone(&poll),
+            queue: Arc::clone(&queue),
+            budget,
+            bandwidth: Arc::clone(&bandwidth),
+            dedup: dedup.clone(),
+            forks: forks.clone(),
+            gaps: gaps.clone(),
+            hold: hold.clone(),
+            priority: priority.clone(),
+            assembler: assembler.clone(),
+            coalescer: coalescer.clone(),
+            events: Arc::clone(&events),
+            lamports: lamports.clone(),
+            logs: logs.clone(),
+            fee_payers: fee_payers.clone(),
+            exprs: exprs.clone(),
+            correlator: correlator.clone(),
+            progress: progress.clone(),
+            discovery: discovery.clone(),
+            decoder: decoder.clone(),
+            sampler: sampler.clone(),
+            slos: slos.clone(),
+            errors: Arc::clone(&errors),
+            schema: Arc::clone(&schema),
+            multi: multi.clone(),
+            pool: pool.clone(),
+            catchup: catchup.clone(),
+            serve: broadcast.clone(),
+            recent: recent.clone(),
+            token_owners,
+            mints: mints.clone(),
+            facade: facade.clone(),
+            state_sync: state_sync.clone(),
+            tags: Arc::clone(&tags),
+            reconnects: Arc::clone(&reconnects),
+            lifecycle: Arc::clone(&lifecycle),
+            sinks: Arc::clone(&sinks),
+            checkpoint: Arc::clone(&checkpoint),
+            replay_dir: args.admin_replay_dir.clone().map(Into::into),
+        };
+        tokio::spawn(async move {
+            if let Err(error) = admin::serve(addr, state).await {
+                error!("admin api failed: {error}");
+            }
+        });
+    }
+
+    #[cfg(not(feature = "template"))]
+    anyhow::ensure!(
+        env::var_os("OUTPUT_TEMPLATE").is_none(),
+        "OUTPUT_TEMPLATE is set, but the client was built without the `template` feature"
+    );
+    let ctx = StreamContext {
+        settings,
+        sinks,
+        stats,
+        queue,
+        bandwidth,
+        dedup,
+        forks,
+        gaps,
+        hold,
+        priority,
+        assembler,
+        coalescer,
+        events,
+        lamports,
+        logs,
+        fee_payers,
+        exprs,
+        correlator,
+        progress,
+        discovery,
+        sampler,
+        catchup,
+        slos,
+        errors,
+        schema,
+        diffs: AccountDiffs::from_env()?.map(Arc::new),
+        tags,
+        #[cfg(feature = "template")]
+        template: template::OutputTemplate::from_env()?.map(Arc::new),
+        decoder,
+        trace_ids: trace::enabled(),
+        reconnects,
+        lifecycle,
+        health: Arc::new(HealthHooks::from_env(Arc::clone(&args.endpoints))?),
+        liveness: Arc::new(Liveness::from_env()?),
+        watchdog: WatchdogConfig::from_env()?,
+        stream_recoveries: args.retry.stream_recoveries(),
+        dashboard,
+        shutdown: shutdown_tx.subscribe(),
+        shutdown_grace: args.shutdown_grace,
+    };
+    let mut shutdown = ctx.shutdown.clone();
+    let shutdown_grace = args.shutdown_grace;
+    let is_stream = matches!(
+        args.action,
+        Action::Subscribe(_)
+            | Action::MultiSubscribe(_)
+            | Action::Record { .. }
+            | Action::Replay { .. }
+            | Action::Dashboard(_)
+            | Action::Serve { .. }
+    );
+    let output = args.output;
+
+    let workers = (0..args.queue_workers)
+        .map(|_| tokio::spawn(process_updates(ctx.clone())))
+        .collect::<Vec<_>>();
+    let poller = match args.poll_interval {
+        Some(interval)
+            if matches!(
+                args.action,
+                Action::Subscribe(_) | Action::Record { .. } | Action::Serve { .. }
+            ) =>
+        {
+            Some(tokio::spawn(geyser_poll(
+                args.clone(),
+                interval,
+                Arc::clone(&poll),
+                ctx.clone(),
+            )))
+        }
+        _ => None,
+    };
+    let bandwidth_reporter = match args.bandwidth_report {
+        Some(period)
+            if matches!(
+                args.action,
+                Action::Subscribe(_)
+                    | Action::MultiSubscribe(_)
+                    | Action::Record { .. }
+                    | Action::Serve { .. }
+            ) =>
+        {
+            let bandwidth = Arc::clone(&ctx.bandwidth);
+            Some(tokio::spawn(async move {
+                let mut ticker = interval(period);
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    output.print_event("bandwidth", &bandwidth.report());
+                }
+            }))
+        }
+        _ => None,
+    };
+    let catchup_monitor = ctx.catchup.clone().map(|catchup| {
+        let lifecycle = Arc::clone(&ctx.lifecycle);
+        let stats = Arc::clone(&ctx.stats);
+        let poll = Arc::clone(&poll);
+        tokio::spawn(async move {
+            let mut ticker = interval(CATCHUP_CHECK_INTERVAL);
+            while !catchup.caught_up() {
+                ticker.tick().await;
+                if let Some((name, event)) = catchup.check(stats.last_slot(), poll.snapshot().slot)
+                {
+                    if name == "caught_up" {
+                        lifecycle.transition(Phase::CaughtUp, event.clone());
+                    }
+                    output.print_event(name, &event);
+                }
+            }
+        })
+    });
+    let lifecycle_monitor = {
+        let lifecycle = Arc::clone(&ctx.lifecycle);
+        let stats = Arc::clone(&ctx.stats);
+        let poll = Arc::clone(&poll);
+        let mut shutdown = ctx.shutdown.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(LIFECYCLE_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        lifecycle.check_lag(stats.last_slot(), poll.snapshot().slot);
+                    }
+                    Ok(_) = shutdown.wait_for(|stop| *stop) => {
+                        lifecycle.transition(Phase::Draining, serde_json::json!({ "reason": "signal" }));
+                        break;
+                    }
+                }
+            }
+        })
+    };
+    let liveness_watchdog = ctx.liveness.watchdog().map(|_| {
+        let liveness = Arc::clone(&ctx.liveness);
+        tokio::spawn(async move { liveness.run_watchdog().await })
+    });
+    let reassemble_flusher = ctx.assembler.clone().map(|assembler| {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(REASSEMBLE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let expired = Instant::now();
+                for msg in assembler.expired() {
+                    coalesce_update(&ctx, msg, expired);
+                }
+            }
+        })
+    });
+    let coalesce_flusher = ctx.coalescer.clone().map(|coalescer| {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(coalescer.window);
+            loop {
+                ticker.tick().await;
+                flush_coalesced(&ctx, &coalescer);
+            }
+        })
+    });
+    let stats_reporter = match args.stats_interval {
+        Some(period) if is_stream => {
+            let stats = Arc::clone(&ctx.stats);
+            let slos = ctx.slos.clone();
+            Some(tokio::spawn(async move {
+                let mut ticker = interval(period);
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    stats.filter_summary().print(output);
+                    if let Some(slos) = slos.as_ref() {
+                        output.print_event("slo", &serde_json::json!(slos.status()));
+                    }
+                }
+            }))
+        }
+        _ => None,
+    };
+    let mint_reporter = match mints.clone() {
+        Some(mints) if is_stream => Some(tokio::spawn(async move {
+            let mut ticker = interval(mints.report);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                output.print_event("mints", &serde_json::json!(mints.snapshot()));
+            }
+        })),
+        _ => None,
+    };
+    let state_reporter = match state_sync.clone() {
+        Some(state_sync) if is_stream => Some(tokio::spawn(async move {
+            let mut ticker = interval(state_sync.interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                for event in state_sync.events() {
+                    output.print_event(event.name(), &serde_json::json!(event));
+                }
+            }
+        })),
+        _ => None,
+    };
+
+    let ws_server = match broadcast {
+        Some(broadcast) => Some(serve::spawn(broadcast).await?),
+        None => None,
+    };
+    let rpc_facade = match facade {
+        Some(facade) => Some(facade::spawn(facade).await?),
+        None => None,
+    };
+    let checkpoint_saver = is_stream.then(|| {
+        let checkpoint = Arc::clone(&checkpoint);
+        let sinks = Arc::clone(&ctx.sinks);
+        tokio::spawn(async move {
+            let mut ticker = interval(SAVE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                checkpoint.update(&sinks);
+                checkpoint.save();
+            }
+        })
+    });
+    let progress_saver = ctx.progress.clone().filter(|_| is_stream).map(|progress| {
+        tokio::spawn(async move {
+            let mut ticker = interval(progress.interval());
+            loop {
+                ticker.tick().await;
+                progress.save().await;
+            }
+        })
+    });
+
+    // Log lines would break the terminal view, they are enabled again when it is closed
+    let dashboard_ui = match ctx.dashboard.as_ref() {
+        Some(dashboard) => {
+            logging::set_filters("off")?;
+            let dashboard = Arc::clone(dashboard);
+            let reconnects = Arc::clone(&ctx.reconnects);
+            let latest_slot = ctx.events.latest_slot();
+            let shutdown_tx = Arc::clone(&shutdown_tx);
+            Some(tokio::task::spawn_blocking(move || {
+                dashboard::run(dashboard, reconnects, latest_slot, shutdown_tx)
+            }))
+        }
+        None => None,
+    };
+
+    // Initial state of subscribed accounts, queued before the first live update
+    if let Some(snapshot) = snapshot {
+        if let Some((request, _)) = args
+            .action
+            .get_subscribe_request(args.get_commitment())
+            .await?
+        {
+            for msg in snapshot.fetch(&request).await? {
+                if let Some(catchup) = ctx.catchup.as_ref() {
+                    catchup.throttle().await;
+                }
+                ctx.queue.push(msg).await;
+            }
+        }
+    }
+
+    let result = if let Action::Replay { path, speed } = &args.action {
+        geyser_replay(path, *speed, &ctx).await
+    } else if let Action::Simulate { path, .. } = &args.action {
+        geyser_simulate(path, &args).await
+    } else if let Action::LoadGen(config) = &args.action {
+        let report = loadgen::run(
+            *config,
+            &ctx.queue,
+            &ctx.stats,
+            &ctx.sinks,
+            ctx.shutdown.clone(),
+        )
+        .await?;
+        output.print_event("loadgen", &report);
+        Ok(())
+    } else if let Some(multi) = multi {
+        geyser_multi_subscribe(&args, &ctx, multi).await
+    } else if let Some(pool) = pool {
+        geyser_pool_subscribe(&args, &ctx, pool).await
+    } else if let (Action::Poll, Some(interval)) = (&args.action, args.poll_interval) {
+        geyser_poll(args.clone(), interval, poll, ctx.clone()).await;
+        Ok(())
+    } else {
+        tokio::select! {
+            result = run_with_retry(args, ctx.clone()) => result,
+            // Streams stop by themselves on shutdown, don't wait for other actions or
+            // reconnect backoff longer than the grace period
+            Ok(_) = async {
+                let result = shutdown.wait_for(|stop| *stop).await.map(|_| ());
+                sleep(shutdown_grace + Duration::from_secs(1)).await;
+                result
+            } => {
+                warn!("shutdown grace period is over");
+                Ok(())
+            }
+        }
+    };
+
+    for task in [
+        poller,
+        catchup_monitor,
+        Some(lifecycle_monitor),
+        liveness_watchdog,
+        bandwidth_reporter,
+        stats_reporter,
+        mint_reporter,
+        state_reporter,
+        reassemble_flusher,
+        coalesce_flusher,
+        ws_server,
+        rpc_facade,
+        checkpoint_saver,
+        progress_saver,
+        dashboard_events,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        task.abort();
+    }
+    let reason = if *ctx.shutdown.borrow() {
+        "signal"
+    } else {
+        "finished"
+    };
+    ctx.lifecycle.transition(
+        Phase::Draining,
+        serde_json::json!({ "reason": reason, "queued": ctx.queue.depth() }),
+    );
+    ctx.liveness.stopping();
+    if let Some(dashboard_ui) = dashboard_ui {
+        shutdown_tx.send_replace(true);
+        let ui_result = dashboard_ui.await;
+        logging::set_filters(&ctx.settings.snapshot().log_filter)?;
+        if let Err(error) = ui_result? {
+            error!("dashboard failed: {error}");
+        }
+    }
+    ctx.queue.close();
+    if timeout(shutdown_grace, join_all(workers)).await.is_err() {
+        warn!("{} queued messages were not processed", ctx.queue.depth());
+    }
+    if let Some(assembler) = ctx.assembler.as_ref() {
+        let drained = Instant::now();
+        for msg in assembler.drain() {
+            coalesce_update(&ctx, msg, drained);
+        }
+    }
+    if let Some(coalescer) = ctx.coalescer.as_ref() {
+        flush_coalesced(&ctx, coalescer);
+    }
+    let unreleased = ctx.hold.as_ref().map_or(0, |hold| hold.clear());
+    if let Some(decoder) = ctx.decoder.as_ref() {
+        decoder.close(shutdown_grace).await;
+    }
+    ctx.sinks.shutdown().await;
+    ctx.errors.flush();
+    if is_stream {
+        // Sinks are flushed, everything they acknowledged is durable
+        if let Some(slot) = checkpoint.update(&ctx.sinks) {
+            checkpoint.save();
+            info!("checkpoint: slot {slot}");
+        }
+        if let Some(progress) = ctx.progress.as_ref() {
+            progress.save().await;
+            info!("progress: slots {:?}", progress.report().slots);
+        }
+        info!(
+            "{} messages processed, last slot: {}",
+            ctx.stats.messages(),
+            ctx.stats
+                .last_slot()
+                .map_or_else(|| "none".to_owned(), |slot| slot.to_string())
+        );
+        if ctx.queue.dropped() > 0 {
+            warn!("{} messages dropped by the queue", ctx.queue.dropped());
+        }
+        if let Some(dedup) = ctx.dedup.as_ref() {
+            info!("{} duplicate updates dropped", dedup.duplicates());
+        }
+        if let Some(forks) = ctx.forks.as_ref().filter(|forks| forks.rollbacks() > 0) {
+            warn!(
+                "{} slots rolled back in {} forks",
+                forks.rolled_back(),
+                forks.rollbacks()
+            );
+        }
+        if let Some(gaps) = ctx
+            .gaps
+            .as_ref()
+            .filter(|gaps| gaps.slot_gaps() > 0 || gaps.write_version_gaps() > 0)
+        {
+            warn!(
+                "{} slots not received in {} gaps, {} account updates out of write version order",
+                gaps.missed_slots(),
+                gaps.slot_gaps(),
+                gaps.write_version_gaps()
+            );
+        }
+        if let Some(discovery) = ctx.discovery.as_ref() {
+            info!(
+                "{} accounts discovered, {} removed, {} subscribed on exit",
+                discovery.added(),
+                discovery.evicted(),
+                discovery.accounts()
+            );
+        }
+        if let Some(priority) = ctx.priority.as_ref() {
+            info!("{} updates took the priority lane", priority.updates());
+        }
+        if let Some(hold) = ctx.hold.as_ref() {
+            info!(
+                "{} held updates released when {}, {} dropped ({unreleased} not released on exit)",
+                hold.released(),
+                hold.target(),
+                hold.dropped()
+            );
+        }
+        if let Some(assembler) = ctx.assembler.as_ref() {
+            info!(
+                "{} blocks reassembled, {} incomplete, {} parts dropped",
+                assembler.assembled(),
+                assembler.incomplete(),
+                assembler.dropped()
+            );
+        }
+        if let Some(coalescer) = ctx.coalescer.as_ref() {
+            info!("{} account writes collapsed", coalescer.collapsed());
+        }
+        if let Some(lamports) = ctx.lamports.as_ref() {
+            info!(
+                "{} account updates dropped by lamports range",
+                lamports.filtered()
+            );
+        }
+        if let Some(logs) = ctx.logs.as_ref() {
+            info!(
+                "{} transactions dropped by log filter, {} passed",
+                logs.filtered(),
+                logs.passed()
+            );
+        }
+        if let Some(fee_payers) = ctx.fee_payers.as_ref() {
+            info!(
+                "{} transactions dropped by fee payer",
+                fee_payers.filtered()
+            );
+        }
+        if let Some(exprs) = ctx.exprs.as_ref() {
+            info!("{} updates dropped by FILTER_EXPR", exprs.filtered());
+        }
+        if let Some(decoder) = ctx.decoder.as_ref().filter(|decoder| decoder.dropped() > 0) {
+            warn!(
+                "{} updates not logged, the decode queue was full",
+                decoder.dropped()
+            );
+        }
+        if let Some(sampler) = ctx.sampler.as_ref() {
+            for (kind, reason, count) in sampler.dropped() {
+                info!("{count} {kind} updates dropped by {reason}");
+            }
+        }
+        for stage in ctx.errors.report() {
+            if stage.failed > 0 {
+                warn!(
+                    "{} updates failed at {} stage ({}), {} retries, {} quarantined",
+                    stage.failed, stage.stage, stage.action, stage.retries, stage.quarantined
+                );
+            }
+        }
+        for (reason, count) in ctx.reconnects.counts() {
+            info!("stream ends by {reason}: {count}");
+        }
+        for (kind, count) in ctx.schema.unknown() {
+            warn!("{count} updates with {kind}, the server runs a newer proto");
+        }
+        if ctx.reconnects.recoveries() > 0 {
+            info!(
+                "streams recovered without reconnect: {}",
+                ctx.reconnects.recoveries()
+            );
+        }
+        ctx.stats.filter_summary().print(output);
+        if let Some(slos) = ctx.slos.as_ref() {
+            output.print_event("slo", &serde_json::json!(slos.status()));
+        }
+        if let Some(correlator) = ctx.correlator.as_ref() {
+            output.print_event("correlate", &serde_json::json!(correlator.report()));
+        }
+        if let Some(mints) = mints.as_ref() {
+            output.print_event("mints", &serde_json::json!(mints.snapshot()));
+        }
+        if let Some(state_sync) = state_sync.as_ref() {
+            for event in state_sync.events() {
+                output.print_event(event.name(), &serde_json::json!(event));
+            }
+        }
+        if ctx.bandwidth.total_bytes() > 0 {
+            output.print_event("bandwidth", &ctx.bandwidth.report());
+        }
+    }
+    let result = match ctx.errors.halted() {
+        Some(reason) => result.and(Err(anyhow::anyhow!("pipeline halted, {reason}"))),
+        None => result,
+    };
+    let result = match ctx.schema.halted() {
+        Some(reason) => result.and(Err(anyhow::anyhow!("schema mismatch, {reason}"))),
+        None => result,
+    };
+    let error = result.as_ref().err().map(|error| error.to_string());
+    ctx.lifecycle
+        .stop(serde_json::json!({ "error": error }))
+        .await;
+    result.inspect_err(|error| output.print_error(error))
+}
+
+async fn run_with_retry(args: Args, ctx: StreamContext) -> anyhow::Result<()> {
+    let zero_attempts = Arc::new(Mutex::new(true));
+
+    let backoff = args.retry.stream_backoff(Arc::clone(&ctx.reconnects));
+    retry(backoff, move || {
+        let args = args.clone();
+        let ctx = ctx.clone();
+        let zero_attempts = Arc::clone(&zero_attempts);
+
+        async move {
+            let mut zero_attempts = zero_attempts.lock().await;
+            if *zero_attempts {
+                *zero_attempts = false;
+            } else {
+                info!("Retry to connect to the server");
+            }
+            drop(zero_attempts);
+
+            let commitment = args.get_commitment();
+            let mut client = args
+                .connect()
+                .await
+                .inspect_err(|error| {
+                    ctx.reconnects
+                        .record(EndReason::Connect, error.to_string(), 0);
+                    ctx.lifecycle.transition(
+                        Phase::Degraded,
+                        serde_json::json!({ "reason": "connect", "detail": error.to_string() }),
+                    );
+                })
+                .map_err(ClientError::into_backoff)?;
+            info!("Connected");
+            ctx.lifecycle.transition(
+                Phase::Connected,
+                serde_json::json!({ "endpoint": args.endpoints.current_name() }),
+            );
+
+            match &args.action {
+                Action::HealthCheck => client
+                    .health_check()
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::HealthWatch => {
+                    geyser_health_watch(client, &ctx.health, &ctx.liveness).await
+                }
+                Action::Subscribe(_)
+                | Action::Record { .. }
+                | Action::Dashboard(_)
+                | Action::Serve { .. } => {
+                    let (request, resub) = args
+                        .action
+                        .get_subscribe_request(commitment)
+                        .await
+                        .map_err(ClientError::into_backoff)?
+                        .expect("expect subscribe action");
+                    let (request, watcher) = match args.filters_path.clone() {
+                        Some(path) => {
+                            let (watcher, request) =
+                                FiltersWatcher::new(path, request, Arc::clone(&ctx.tags))
+                                    .map_err(ClientError::FilterParse)
+                                    .map_err(ClientError::into_backoff)?;
+                            (request, Some(watcher))
+                        }
+                        None => (request, None),
+                    };
+
+                    let recorder = match &args.action {
+                        Action::Record { path, format, .. } => Some(
+                            match format {
+                                CaptureFormat::Raw => CaptureWriter::open(path),
+                                CaptureFormat::Framed => CaptureWriter::open_framed(
+                                    path,
+                                    &CaptureHeader::new(&args.endpoints.current().url, &request),
+                                ),
+                            }
+                            .map_err(|error| backoff::Error::Permanent(error.into()))?,
+                        ),
+                        _ => None,
+                    };
+
+                    let token_reloads = args.endpoints.current().x_token.reloads();
+                    return geyser_subscribe(client, request, resub, ctx, recorder, watcher)
+                        .await
+                        .map_err(|error| match error {
+                            // Expired token, the next connect loads the current one
+                            ClientError::Auth(_) if token_reloads => {
+                                backoff::Error::transient(error.into())
+                            }
+                            error => error.into_backoff(),
+                        });
+                }
+                Action::Ping { count } => client
+                    .ping(*count)
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::GetLatestBlockhash => client
+                    .get_latest_blockhash(commitment)
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::GetBlockHeight => client
+                    .get_block_height(commitment)
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::GetSlot => client
+                    .get_slot(commitment)
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::IsBlockhashValid { blockhash } => client
+                    .is_blockhash_valid(blockhash.clone(), commitment)
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::GetVersion => client
+                    .get_version()
+                    .await
+                    .map_err(ClientError::from)
+                    .map_err(anyhow::Error::new)
+                    .map(|response| args.output.print_response(&response)),
+                Action::Query { queries } => {
+                    let (report, failed) = geyser_query(client, queries, commitment).await;
+                    println!("{report}");
+                    if failed > 0 {
+                        return Err(backoff::Error::Permanent(anyhow::anyhow!(
+                            "{failed} of {} queries failed",
+                            queries.len()
+                        )));
+                    }
+                    Ok(())
+                }
+                Action::LatencyBench { interval, window } => {
+                    geyser_latency_bench(client, commitment, *interval, *window, &args, &ctx).await
+                }
+                Action::Bench { duration, .. } => {
+                    let (request, _) = args
+                        .action
+                        .get_subscribe_request(commitment)
+                        .await
+                        .map_err(ClientError::into_backoff)?
+                        .expect("expect subscribe action");
+                    geyser_bench(client, request, *duration, &args, &ctx).await
+                }
+                Action::Analyze { config, .. } => {
+                    let (request, _) = args
+                        .action
+                        .get_subscribe_request(commitment)
+                        .await
+                        .map_err(ClientError::into_backoff)?
+                        .expect("expect subscribe action");
+                    geyser_analyze(client, request, config, &args, &ctx).await
+                }
+                Action::Replay { .. }
+                | Action::Simulate { .. }
+                | Action::Status { .. }
+                | Action::LoadGen(_)
+                | Action::Generate { .. }
+                | Action::Verify { .. } => unreachable!("offline actions are not retried"),
+                Action::MultiSubscribe(_) => unreachable!("multi subscribe is not retried"),
+                Action::Poll => unreachable!("poll is not retried"),
+            }
+            .map_err(ClientError::classify)?;
+
+            Ok::<(), backoff::Error<anyhow::Error>>(())
+        }
+        .inspect_err(|error| error!("failed to connect: {error}"))
+    })
+    .await
+}
+
+/// Print volume of the proposed filters against the capture file
+async fn geyser_simulate(path: &str, args: &Args) -> anyhow::Result<()> {
+    let (request, _) = args
+        .action
+        .get_subscribe_request(args.get_commitment())
+        .await?
+        .expect("expect subscribe action");
+    let request = match args.filters_path.as_deref() {
+        Some(filters_path) => load_filters(filters_path, &request)?.0,
+        None => request,
+    };
+    let path = path.to_owned();
+    let report = tokio::task::spawn_blocking(move || simulate(&path, &request)).await??;
+    println!("{report}");
+    Ok(())
+}
+
+/// Make the calls of a unary action once, without retries, and print the response as JSON.
+/// Returns the exit code: 0 on success, 1 if a call failed or timed out, 2 if the client
+/// could not connect, 3 if the health check reported that the server is not serving.
+async fn geyser_oneshot(args: &Args, deadline: Duration) -> i32 {
+    let queries = args.action.oneshot_queries().expect("checked by Args");
+    let deadline = Instant::now() + deadline;
+    let print_error = |error: String| println!("{}", serde_json::json!({ "error": error }));
+
+    let client = match timeout_at(deadline, args.connect()).await {
+        Ok(Ok(client)) => client,
+        Ok(Err(error)) => {
+            print_error(error.to_string());
+            return 2;
+        }
+        Err(_) => {
+            print_error("timed out while connecting".to_owned());
+            return 2;
+        }
+    };
+    let query = geyser_query(client, &queries, args.get_commitment());
+    let Ok((report, failed)) = timeout_at(deadline, query).await else {
+        print_error("timed out".to_owned());
+        return 1;
+    };
+
+    // `Query` prints responses by query name, other actions the response itself
+    match &args.action {
+        Action::Query { .. } => println!("{report}"),
+        _ => println!("{}", report[queries[0].name()]),
+    }
+    let serving = queries
+        .iter()
+        .filter(|query| matches!(query, Query::HealthCheck))
+        .all(|query| report[query.name()]["status"] == "SERVING");
+    if failed > 0 {
+        1
+    } else if !serving {
+        3
+    } else {
+        0
+    }
+}
+
+/// Run queries one by one, returns JSON object with results by query name and number
+/// of failed queries
+async fn geyser_query(
+    mut client: GeyserGrpcClient<impl Interceptor>,
+    queries: &[Query],
+    commitment: Option<CommitmentLevel>,
+) -> (serde_json::Value, usize) {
+    fn to_value<T: ToJson>(result: Result<T, GeyserGrpcClientError>) -> serde_json::Value {
+        match result {
+            Ok(response) => response.to_json(),
+            Err(error) => serde_json::json!({ "error": error.to_string() }),
+        }
+    }
+
+    let mut report = serde_json::Map::new();
+    let mut failed = 0;
+    for query in queries {
+        let value = match query {
+            Query::HealthCheck => to_value(client.health_check().await),
+            Query::Ping => to_value(client.ping(1).await),
+            Query::GetLatestBlockhash => to_value(client.get_latest_blockhash(commitment).await),
+            Query::GetBlockHeight => to_value(client.get_block_height(commitment).await),
+            Query::GetSlot => to_value(client.get_slot(commitment).await),
+            Query::IsBlockhashValid { blockhash } => to_value(
+                client
+                    .is_blockhash_valid(blockhash.clone(), commitment)
+                    .await,
+            ),
+            Query::GetVersion => to_value(client.get_version().await),
+        };
+        if value.get("error").is_some() {
+            failed += 1;
+        }
+        report.insert(query.name().to_owned(), value);
+    }
+    (report.into(), failed)
+}
+
+async fn geyser_health_watch(
+    mut client: GeyserGrpcClient<impl Interceptor>,
+    hooks: &HealthHooks,
+    liveness: &Liveness,
+) -> anyhow::Result<()> {
+    let mut stream = client.health_watch().await?;
+    info!("stream opened");
+    liveness.ready();
+    let mut previous = None;
+    while let Some(message) = stream.next().await {
+        info!("new message: {message:?}");
+        liveness.observe();
+        let Ok(response) = message else {
+            continue;
+        };
+
+        let status = response.status();
+        if let Some(endpoint) = hooks.on_status(previous, status).await {
+            anyhow::bail!("server is not serving, failover to {endpoint}");
+        }
+        previous = Some(status);
+    }
+    info!("stream closed");
+    Ok(())
+}
+
+async fn geyser_poll(args: Args, period: Duration, poll: Arc<PollValues>, ctx: StreamContext) {
+    let commitment = args.get_commitment();
+    let mut shutdown = ctx.shutdown.clone();
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut client = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break,
+        }
+
+        if client.is_none() {
+            match args.connect().await {
+                Ok(connected) => client = Some(connected),
+                Err(error) => {
+                    warn!("poll: failed to connect: {error}");
+                    poll.failed();
+                    continue;
+                }
+            }
+        }
+        let Some(connected) = client.as_mut() else {
+            continue;
+        };
+
+        let result = async {
+            Ok::<_, GeyserGrpcClientError>((
+                connected.get_slot(commitment).await?,
+                connected.get_block_height(commitment).await?,
+                connected.get_latest_blockhash(commitment).await?,
+            ))
+        }
+        .await;
+        let snapshot = match result {
+            Ok((slot, block_height, blockhash)) => poll.update(slot, block_height, blockhash),
+            Err(error) => {
+                // Reconnect on the next tick
+                warn!("poll: request failed: {error}");
+                client = None;
+                poll.failed()
+            }
+        };
+
+        let mut event = serde_json::to_value(&snapshot).unwrap_or_default();
+        event["stream_last_slot"] = serde_json::json!(ctx.stats.last_slot());
+        args.output.print_event("poll", &event);
+    }
+}
+
+async fn geyser_latency_bench(
+    mut client: GeyserGrpcClient<impl Interceptor>,
+    commitment: Option<CommitmentLevel>,
+    period: Duration,
+    window: Duration,
+    args: &Args,
+    ctx: &StreamContext,
+) -> anyhow::Result<()> {
+    let request = SubscribeRequest {
+        slots: HashMap::from([("client".to_owned(), SubscribeRequestFilterSlots::default())]),
+        blocks_meta: HashMap::from([("client".to_owned(), SubscribeRequestFilterBlocksMeta {})]),
+        commitment: commitment.map(|x| x as i32),
+        ..Default::default()
+    };
+    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
+    info!("stream opened, report every {period:?} over the last {window:?}");
+
+    let mut shutdown = ctx.shutdown.clone();
+    let mut tracker = LatencyTracker::new(window);
+    let mut ticker = interval(period);
+    ticker.tick().await;
+    loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            _ = ticker.tick() => {
+                args.output.print_event("latency", &tracker.report());
+                continue;
+            }
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break,
+        };
+        let received = latency::now_ms();
+        match message {
+            Some(Ok(msg)) => {
+                if matches!(msg.update_oneof, Some(UpdateOneof::Ping(_))) {
+                    subscribe_tx
+                        .send(SubscribeRequest {
+                            ping: Some(SubscribeRequestPing { id: 1 }),
+                            ..Default::default()
+                        })
+                        .await?;
+                }
+                tracker.observe(&msg, received);
+            }
+            Some(Err(status)) => {
+                args.output.print_event("latency", &tracker.report());
+                return Err(status.into());
+            }
+            None => break,
+        }
+    }
+    args.output.print_event("latency", &tracker.report());
+    info!("stream closed");
+    Ok(())
+}
+
+/// Subscribe for `duration` and discard updates, the summary is printed to stdout as JSON
+async fn geyser_bench(
+    mut client: GeyserGrpcClient<impl Interceptor>,
+    request: SubscribeRequest,
+    duration: Duration,
+    args: &Args,
+    ctx: &StreamContext,
+) -> anyhow::Result<()> {
+    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
+    let endpoint = args.endpoints.current_name();
+    info!("stream opened, bench {endpoint} for {duration:?}");
+
+    let mut shutdown = ctx.shutdown.clone();
+    let mut meter = BenchMeter::start();
+    let deadline = sleep(duration);
+    tokio::pin!(deadline);
+    let mut progress = interval(Duration::from_secs(10));
+    progress.tick().await;
+    let completed = loop {
+        // Time spent in polls of the stream, where tonic decompresses and decodes
+        let mut decode = Duration::ZERO;
+        let next = poll_fn(|cx| {
+            let started = Instant::now();
+            let poll = stream.poll_next_unpin(cx);
+            decode += started.elapsed();
+            poll
+        });
+        let message = tokio::select! {
+            message = next => message,
+            () = &mut deadline => break true,
+            _ = progress.tick() => {
+                info!("bench: {}", meter.report(&endpoint, false));
+                continue;
+            }
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break false,
+        };
+        match message {
+            Some(Ok(msg)) => {
+                meter.observe(&msg, decode);
+                if matches!(msg.update_oneof, Some(UpdateOneof::Ping(_))) {
+                    subscribe_tx
+                        .send(SubscribeRequest {
+                            ping: Some(SubscribeRequestPing { id: 1 }),
+                            ..Default::default()
+                        })
+                        .await?;
+                }
+            }
+            Some(Err(status)) => {
+                println!("{}", meter.report(&endpoint, false));
+                return Err(status.into());
+            }
+            None => break false,
+        }
+    };
+    println!("{}", meter.report(&endpoint, completed));
+    info!("stream closed");
+    Ok(())
+}
+
+/// Subscribe for `ANALYZE_DURATION_SECS` and count updates by shape, the distributions are
+/// printed to stdout as JSON
+async fn geyser_analyze(
+    mut client: GeyserGrpcClient<impl Interceptor>,
+    request: SubscribeRequest,
+    config: &AnalyzeConfig,
+    args: &Args,
+    ctx: &StreamContext,
+) -> anyhow::Result<()> {
+    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
+    let endpoint = args.endpoints.current_name();
+    info!(
+        "stream opened, analyze {endpoint} for {:?}",
+        config.duration
+    );
+
+    let mut shutdown = ctx.shutdown.clone();
+    let mut analyzer = Analyzer::start(config);
+    let deadline = sleep(config.duration);
+    tokio::pin!(deadline);
+    let mut progress = interval(Duration::from_secs(10));
+    progress.tick().await;
+    let completed = loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            () = &mut deadline => break true,
+            _ = progress.tick() => {
+                info!("analyze: {}", analyzer.progress());
+                continue;
+            }
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break false,
+        };
+        match message {
+            Some(Ok(msg)) => {
+                analyzer.observe(&msg);
+                if matches!(msg.update_oneof, Some(UpdateOneof::Ping(_))) {
+                    subscribe_tx
+                        .send(SubscribeRequest {
+                            ping: Some(SubscribeRequestPing { id: 1 }),
+                            ..Default::default()
+                        })
+                        .await?;
+                }
+            }
+            Some(Err(status)) => {
+                println!("{}", analyzer.report(&endpoint, false));
+                return Err(status.into());
+            }
+            None => break false,
+        }
+    };
+    println!("{}", analyzer.report(&endpoint, completed));
+    info!("stream closed");
+    Ok(())
+}
+
+/// Subscribe to every endpoint with the same request, each update is processed once when
+/// it arrives first, win rates of the endpoints are printed every `report_interval`
+async fn geyser_multi_subscribe(
+    args: &Args,
+    ctx: &StreamContext,
+    merge: Arc<MultiMerge>,
+) -> anyhow::Result<()> {
+    let (request, _) = args
+        .action
+        .get_subscribe_request(args.get_commitment())
+        .await?
+        .expect("expect subscribe action");
+    info!("multi subscribe to {}", merge.names().join(", "));
+    ctx.stats.subscribed(&request);
+
+    let streams = join_all(
+        args.endpoints
+            .all()
+            .enumerate()
+            .map(|(index, (name, endpoint))| {
+                multi_stream(
+                    index,
+                    name,
+                    endpoint,
+                    request.clone(),
+                    args.retry,
+                    ctx,
+                    &merge,
+                )
+            }),
+    );
+    tokio::pin!(streams);
+    let mut ticker = interval(merge.report_interval);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = &mut streams => break,
+            _ = ticker.tick() => {
+                args.output.print_event("multi", &serde_json::json!(merge.report()));
+            }
+        }
+    }
+    args.output
+        .print_event("multi", &serde_json::json!(merge.report()));
+    Ok(())
+}
+
+/// One stream of `MultiSubscribe`, reconnects with backoff until shutdown
+async fn multi_stream(
+    index: usize,
+    name: String,
+    endpoint: &EndpointConfig,
+    request: SubscribeRequest,
+    retry: RetryConfig,
+    ctx: &StreamContext,
+    merge: &MultiMerge,
+) {
+    let mut shutdown = ctx.shutdown.clone();
+    let mut backoff = retry.backoff();
+    // The other streams keep going, so this one never gives up
+    backoff.max_elapsed_time = None;
+    while !*shutdown.borrow() {
+        let result = multi_stream_once(
+            index,
+            &name,
+            endpoint,
+            request.clone(),
+            ctx,
+            merge,
+            &mut backoff,
+        )
+        .await;
+        let delay = backoff.next_backoff().unwrap_or(backoff.max_interval);
+        match result {
+            Ok(()) => info!("{name}: stream closed"),
+            Err(error) => warn!("{name}: stream failed: {error}, reconnect in {delay:?}"),
+        }
+        tokio::select! {
+            () = sleep(delay) => {}
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break,
+        }
+    }
+}
+
+async fn multi_stream_once(
+    index: usize,
+    name: &str,
+    endpoint: &EndpointConfig,
+    request: SubscribeRequest,
+    ctx: &StreamContext,
+    merge: &MultiMerge,
+    backoff: &mut ExponentialBackoff,
+) -> anyhow::Result<()> {
+    let mut client = endpoint.connect().await?;
+    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
+    info!("{name}: stream opened");
+    backoff.reset();
+    ctx.liveness.ready();
+    ctx.lifecycle
+        .transition(Phase::Subscribed, serde_json::json!({ "endpoint": name }));
+
+    let mut shutdown = ctx.shutdown.clone();
+    loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            Ok(_) = shutdown.wait_for(|stop| *stop) => return Ok(()),
+        };
+        let msg = match message {
+            Some(Ok(msg)) => msg,
+            Some(Err(status)) => return Err(status.into()),
+            None => return Ok(()),
+        };
+
+        ctx.bandwidth.observe(&msg);
+        ctx.liveness.observe();
+        if matches!(msg.update_oneof, Some(UpdateOneof::Ping(_))) {
+            subscribe_tx
+                .send(SubscribeRequest {
+                    ping: Some(SubscribeRequestPing { id: 1 }),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        if merge.first(index, &msg) {
+            dispatch_update(ctx, msg, false).await;
+        }
+    }
+}
+
+/// Split the filters over the connections of the pool and merge their streams, health of
+/// the connections is printed every `report_interval`
+async fn geyser_pool_subscribe(
+    args: &Args,
+    ctx: &StreamContext,
+    pool: Arc<ConnectionPool>,
+) -> anyhow::Result<()> {
+    let (request, _) = args
+        .action
+        .get_subscribe_request(args.get_commitment())
+        .await?
+        .expect("expect subscribe action");
+    let request = match args.filters_path.as_deref() {
+        Some(filters_path) => load_filters(filters_path, &request)?.0,
+        None => request,
+    };
+    info!(
+        "pool of {} connections to {}",
+        pool.connections,
+        args.endpoints.current_name()
+    );
+    ctx.stats.subscribed(&request);
+
+    let endpoint = args.endpoints.current();
+    let streams = join_all(
+        pool.start(request)
+            .into_iter()
+            .enumerate()
+            .map(|(index, requests)| {
+                pool_stream(index, endpoint, requests, args.retry, ctx, &pool)
+            }),
+    );
+    tokio::pin!(streams);
+    let mut ticker = interval(pool.report_interval);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = &mut streams => break,
+            _ = ticker.tick() => {
+                args.output.print_event("pool", &serde_json::json!(pool.report()));
+            }
+        }
+    }
+    args.output
+        .print_event("pool", &serde_json::json!(pool.report()));
+    Ok(())
+}
+
+/// One connection of the pool, reconnects with backoff until shutdown
+async fn pool_stream(
+    index: usize,
+    endpoint: &EndpointConfig,
+    mut requests: watch::Receiver<SubscribeRequest>,
+    retry: RetryConfig,
+    ctx: &StreamContext,
+    pool: &ConnectionPool,
+) {
+    let mut shutdown = ctx.shutdown.clone();
+    let mut backoff = retry.backoff();
+    // Filters of a dropped connection are served by the others, so it never gives up
+    backoff.max_elapsed_time = None;
+    while !*shutdown.borrow() {
+        let result =
+            pool_stream_once(index, endpoint, &mut requests, ctx, pool, &mut backoff).await;
+        pool.disconnected(index, result.is_err());
+        let delay = backoff.next_backoff().unwrap_or(backoff.max_interval);
+        match result {
+            Ok(()) => info!("pool: connection {index} closed"),
+            Err(error) => warn!("pool: connection {index} failed: {error}, reconnect in {delay:?}"),
+        }
+        tokio::select! {
+            () = sleep(delay) => {}
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break,
+        }
+    }
+}
+
+async fn pool_stream_once(
+    index: usize,
+    endpoint: &EndpointConfig,
+    requests: &mut watch::Receiver<SubscribeRequest>,
+    ctx: &StreamContext,
+    pool: &ConnectionPool,
+    backoff: &mut ExponentialBackoff,
+) -> anyhow::Result<()> {
+    let mut client = endpoint.connect().await?;
+    let request = requests.borrow_and_update().clone();
+    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
+    info!("pool: connection {index} opened");
+    backoff.reset();
+    ctx.liveness.ready();
+    ctx.lifecycle.transition(
+        Phase::Subscribed,
+        serde_json::json!({ "connection": index }),
+    );
+    // Takes over filters of dropped connections, sent below as a new request
+    pool.connected(index);
+
+    let mut shutdown = ctx.shutdown.clone();
+    loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            Ok(()) = requests.changed() => {
+                let request = requests.borrow_and_update().clone();
+                subscribe_tx.send(request).await?;
+                continue;
+            }
+            () = sleep(pool.stale.unwrap_or_default()), if pool.stale.is_some() => {
+                anyhow::bail!("no messages for {:?}", pool.stale.unwrap_or_default());
+            }
+            Ok(_) = shutdown.wait_for(|stop| *stop) => return Ok(()),
+        };
+        let msg = match message {
+            Some(Ok(msg)) => msg,
+            Some(Err(status)) => return Err(status.into()),
+            None => return Ok(()),
+        };
+
+        pool.received(index);
+        ctx.bandwidth.observe(&msg);
+        ctx.liveness.observe();
+        if matches!(msg.update_oneof, Some(UpdateOneof::Ping(_))) {
+            subscribe_tx
+                .send(SubscribeRequest {
+                    ping: Some(SubscribeRequestPing { id: 1 }),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        dispatch_update(ctx, msg, false).await;
+    }
+}
+
+async fn geyser_replay(path: &str, speed: f64, ctx: &StreamContext) -> anyhow::Result<()> {
+    let mut reader = CaptureReader::open(path)?;
+    info!("replay {path} with speed {speed}");
+    ctx.lifecycle
+        .transition(Phase::Subscribed, serde_json::json!({ "replay": path }));
+
+    let mut shutdown = ctx.shutdown.clone();
+    let mut started: Option<(u64, Instant)> = None;
+    let mut counter = 0;
+    while let Some((timestamp, msg)) = reader.read()? {
+        if speed > 0.0 {
+            let (first_timestamp, started_at) = *started.get_or_insert((timestamp, Instant::now()));
+            let offset = timestamp.saturating_sub(first_timestamp) as f64 / speed;
+            tokio::select! {
+                () = sleep_until(started_at + Duration::from_micros(offset as u64)) => {}
+                Ok(_) = shutdown.wait_for(|stop| *stop) => break,
+            }
+        } else if *shutdown.borrow() {
+            break;
+        }
+
+        ctx.queue.push(msg).await;
+        counter += 1;
+    }
+    info!("replay finished, {counter} messages processed");
+    Ok(())
+}
+
+fn update_line(
+    settings: &RuntimeSettings,
+    kind: &str,
+    filters: &[String],
+    tags: &Tags,
+    trace: Option<&str>,
+    update: &dyn fmt::Debug,
+) -> String {
+    let mut tags = if tags.is_empty() {
+        String::new()
+    } else {
+        format!(", tags {}", format_tags(tags))
+    };
+    if let Some(trace) = trace {
+        tags.push_str(&format!(", trace {trace}"));
+    }
+    if settings.pretty() {
+        format!("new {kind} update: filters {filters:?}{tags}, {kind}: {update:#?}")
+    } else {
+        format!("new {kind} update: filters {filters:?}{tags}, {kind}: {update:?}")
+    }
+}
+
+/// Decode the update and format its log line, `None` if decoding failed. `diff` is the
+/// change of an account update, tracked by the worker before.
+fn decode_update(
+    settings: &RuntimeSettings,
+    errors: &ErrorPolicy,
+    msg: &SubscribeUpdate,
+    diff: Option<&AccountDiff>,
+    collapsed: u64,
+    tags: &Tags,
+    trace: Option<&str>,
+) -> Option<String> {
+    let line = match msg.update_oneof.as_ref() {
+        Some(UpdateOneof::Account(account)) => {
+            let pretty;
+            let update: &dyn fmt::Debug = match diff {
+                Some(diff) => diff,
+                None => {
+                    let decode = || AccountPretty::try_from(account);
+                    pretty = errors.run(Stage::Decode, msg, decode)?;
+                    &pretty
+                }
+            };
+            let account = Coalesced { update, collapsed };
+            update_line(settings, "account", &msg.filters, tags, trace, &account)
+        }
+        Some(UpdateOneof::Transaction(tx)) => {
+            let decode = || TransactionPretty::new(tx, settings);
+            let tx = errors.run(Stage::Decode, msg, decode)?;
+            update_line(settings, "transaction", &msg.filters, tags, trace, &tx)
+        }
+        Some(UpdateOneof::TransactionStatus(status)) => {
+            let decode = || TransactionStatusPretty::try_from(status);
+            let status = errors.run(Stage::Decode, msg, decode)?;
+            let kind = "transaction status";
+            update_line(settings, kind, &msg.filters, tags, trace, &status)
+        }
+        // Counted and logged once by `SchemaCheck`
+        None => format!(
+            "new message: unknown update type, filters {:?}",
+            msg.filters
+        ),
+        _ => match trace {
+            Some(trace) => format!("new message: trace {trace}, {msg:?}"),
+            None => format!("new message: {msg:?}"),
+        },
+    };
+    Some(line)
+}
+
+/// State shared by stream handlers between reconnects
+#[derive(Clone)]
+struct StreamContext {
+    settings: Arc<RuntimeSettings>,
+    sinks: Arc<Sinks>,
+    stats: Arc<StreamStats>,
+    queue: Arc<UpdateQueue>,
+    bandwidth: Arc<BandwidthMeter>,
+    dedup: Option<Arc<DedupCache>>,
+    /// Slots of dead forks, passed to sinks and the event bus
+    forks: Option<Arc<ForkDetector>>,
+    /// Missed slots and write versions out of order, passed to sinks and the event bus
+    gaps: Option<Arc<GapDetector>>,
+    /// Updates held until their slot reaches `HOLD_UNTIL`
+    hold: Option<Arc<CommitmentHold>>,
+    /// Latency-critical filters which skip the queue, holding and coalescing
+    priority: Option<Arc<PriorityLanes>>,
+    /// Blocks built from their parts, `REASSEMBLE_BLOCKS`
+    assembler: Option<Arc<BlockAssembler>>,
+    coalescer: Option<Arc<AccountCoalescer>>,
+    /// Typed updates for tasks embedded in the process
+    events: Arc<EventBus>,
+    lamports: Option<Arc<LamportsFilter>>,
+    logs: Option<Arc<LogFilter>>,
+    fee_payers: Option<Arc<FeePayerFilter>>,
+    /// Client-side filter expression, `FILTER_EXPR`
+    exprs: Option<Arc<ExprFilter>>,
+    /// Transaction statuses joined with full transactions, `CORRELATE_TRANSACTIONS`
+    correlator: Option<Arc<TxCorrelator>>,
+    /// Processed slots and signatures saved across restarts, `PROGRESS_PATH`
+    progress: Option<Arc<Progress>>,
+    /// Accounts of matching transactions added to the subscription, `DISCOVER_ACCOUNTS`
+    discovery: Option<Arc<AccountDiscovery>>,
+    sampler: Option<Arc<StreamSampler>>,
+    /// Rate limit until the stream reaches the tip
+    catchup: Option<Arc<CatchUp>>,
+    /// Latency objectives of delivery to sinks
+    slos: Option<Arc<LatencySlos>>,
+    /// What happens to an update when decoding, enrichment or a sink fails
+    errors: Arc<ErrorPolicy>,
+    /// Update types and enum values of a newer proto, `SCHEMA_STRICT`
+    schema: Arc<SchemaCheck>,
+    diffs: Option<Arc<AccountDiffs>>,
+    tags: Arc<FilterTags>,
+    /// Printed instead of update log lines, `OUTPUT_TEMPLATE`
+    #[cfg(feature = "template")]
+    template: Option<Arc<template::OutputTemplate>>,
+    /// Blocking threads which decode and format log lines, `DECODE_WORKERS`
+    decoder: Option<Arc<DecodePool>>,
+    /// Log updates with their trace ID, `TRACE_IDS`
+    trace_ids: bool,
+    reconnects: Arc<ReconnectHistory>,
+    /// State transitions for supervisors, `GET /lifecycle`
+    lifecycle: Arc<Lifecycle>,
+    /// Liveness file and systemd notifications for supervisors
+    liveness: Arc<Liveness>,
+    health: Arc<HealthHooks>,
+    watchdog: Option<WatchdogConfig>,
+    /// Re-subscribes on the same connection after a message error before reconnecting
+    stream_recoveries: u32,
+    dashboard: Option<Arc<Dashboard>>,
+    shutdown: watch::Receiver<bool>,
+    shutdown_grace: Duration,
+}
+
+/// Worker which processes messages from the queue until it is closed
+async fn process_updates(ctx: StreamContext) {
+    while let Some((msg, received)) = ctx.queue.pop().await {
+        handle_update(&ctx, msg, received);
+    }
+}
+
+/// `received` is when the update was received from the stream, for latency objectives
+fn handle_update(ctx: &StreamContext, msg: SubscribeUpdate, received: Instant) {
+    ctx.stats.observe(&msg);
+    if !ctx.schema.check(&msg) {
+        return;
+    }
+    if ctx
+        .dedup
+        .as_ref()
+        .is_some_and(|dedup| dedup.is_duplicate(&msg))
+    {
+        return;
+    }
+    if let Some(correlator) = ctx.correlator.as_ref() {
+        correlator.observe(&msg, received);
+    }
+    if let Some(rollback) = ctx.forks.as_ref().and_then(|forks| forks.observe(&msg)) {
+        ctx.sinks.rollback(&rollback);
+        ctx.events.publish_rollback(&rollback);
+    }
+    if let Some(gap) = ctx.gaps.as_ref().and_then(|gaps| gaps.observe(&msg)) {
+        ctx.sinks.gap(&gap);
+        ctx.events.publish_gap(&gap);
+    }
+    if ctx
+        .lamports
+        .as_ref()
+        .is_some_and(|lamports| lamports.is_filtered(&msg))
+    {
+        return;
+    }
+    if ctx.logs.as_ref().is_some_and(|logs| logs.is_filtered(&msg)) {
+        return;
+    }
+    if ctx
+        .fee_payers
+        .as_ref()
+        .is_some_and(|fee_payers| fee_payers.is_filtered(&msg))
+    {
+        return;
+    }
+    if ctx
+        .exprs
+        .as_ref()
+        .is_some_and(|exprs| exprs.is_filtered(&msg))
+    {
+        return;
+    }
+    if let Some(discovery) = ctx.discovery.as_ref() {
+        discovery.observe(&msg);
+    }
+    if let Some(priority) = ctx.priority.as_ref().filter(|lanes| lanes.matches(&msg)) {
+        priority.observe();
+        emit_update(ctx, msg, 0, received);
+        return;
+    }
+    match ctx.hold.as_ref() {
+        Some(hold) => {
+            // Time held until the commitment is reached doesn't count as latency
+            let released = Instant::now();
+            for msg in hold.pass(msg) {
+                reassemble_update(ctx, msg, released);
+            }
+        }
+        None => reassemble_update(ctx, msg, received),
+    }
+}
+
+fn reassemble_update(ctx: &StreamContext, msg: SubscribeUpdate, received: Instant) {
+    match ctx.assembler.as_ref() {
+        Some(assembler) => {
+            for msg in assembler.pass(msg) {
+                coalesce_update(ctx, msg, received);
+            }
+        }
+        None => coalesce_update(ctx, msg, received),
+    }
+}
+
+fn coalesce_update(ctx: &StreamContext, msg: SubscribeUpdate, received: Instant) {
+    match ctx.coalescer.as_ref() {
+        Some(coalescer) => {
+            if let Some(msg) = coalescer.hold(msg) {
+                emit_update(ctx, msg, 0, received);
+            }
+        }
+        None => emit_update(ctx, msg, 0, received),
+    }
+}
+
+/// Pass held account updates to sinks and logging
+fn flush_coalesced(ctx: &StreamContext, coalescer: &AccountCoalescer) {
+    let flushed = Instant::now();
+    for (msg, collapsed) in coalescer.drain() {
+        emit_update(ctx, msg, collapsed, flushed);
+    }
+}
+
+/// Pass the update to sinks and log it, `collapsed` older writes of the account were
+/// replaced by it
+fn emit_update(ctx: &StreamContext, msg: SubscribeUpdate, collapsed: u64, received: Instant) {
+    let settings = &ctx.settings;
+    let errors = &ctx.errors;
+    ctx.sinks.handle(&msg, errors);
+    if let Some(progress) = ctx.progress.as_ref() {
+        progress.observe(&msg);
+    }
+    if let Some(slos) = ctx.slos.as_ref() {
+        slos.observe(&msg, received.elapsed());
+    }
+    ctx.events.publish(&msg);
+
+    #[cfg(feature = "template")]
+    if let Some(template) = ctx.template.as_ref() {
+        if !settings.log_sampled_out() {
+            let render = || template.render(&msg, &ctx.tags.update(&msg));
+            if let Some(Some(text)) = errors.run(Stage::Decode, &msg, render) {
+                println!("{text}");
+            }
+        }
+        return;
+    }
+
+    // Pretty updates decode signatures and errors, don't build them if they are not logged
+    let log = log_enabled!(Level::Info) && !settings.log_sampled_out();
+    let tags = if log {
+        let Some(tags) = errors.run(Stage::Enrich, &msg, || Ok(ctx.tags.update(&msg))) else {
+            return;
+        };
+        tags
+    } else {
+        Tags::default()
+    };
+    let trace = (log && ctx.trace_ids).then(|| trace_id(&msg)).flatten();
+    let diff = match msg.update_oneof.as_ref() {
+        // State is kept for every update, also for those which are not logged
+        Some(UpdateOneof::Account(account)) => {
+            let Some(diff) = errors.run(Stage::Enrich, &msg, || {
+                Ok(ctx.diffs.as_ref().and_then(|diffs| diffs.diff(account)))
+            }) else {
+                return;
+            };
+            diff
+        }
+        _ => None,
+    };
+    // Other updates are logged without sampling
+    let sampled = matches!(
+        msg.update_oneof,
+        Some(
+            UpdateOneof::Account(_)
+                | UpdateOneof::Transaction(_)
+                | UpdateOneof::TransactionStatus(_)
+        )
+    );
+    if (sampled && !log) || !log_enabled!(Level::Info) {
+        return;
+    }
+    let Some(decoder) = ctx.decoder.as_ref() else {
+        let diff = diff.as_ref();
+        let trace = trace.as_deref();
+        if let Some(line) = decode_update(settings, errors, &msg, diff, collapsed, &tags, trace) {
+            info!("{line}");
+        }
+        return;
+    };
+    let settings = Arc::clone(settings);
+    let errors = Arc::clone(errors);
+    decoder.submit(Box::new(move || {
+        let diff = diff.as_ref();
+        let trace = trace.as_deref();
+        decode_update(&settings, &errors, &msg, diff, collapsed, &tags, trace)
+    }));
+}
+
+/// Pass a received update to processing, returns `false` if it was dropped by sampling.
+/// Priority updates, and all updates of `inline` streams, are handled right on the stream
+/// task, others are queued for workers.
+async fn dispatch_update(ctx: &StreamContext, msg: SubscribeUpdate, inline: bool) -> bool {
+    let priority = ctx
+        .priority
+        .as_ref()
+        .is_some_and(|priority| priority.matches(&msg));
+    if !priority
+        && ctx
+            .sampler
+            .as_ref()
+            .is_some_and(|sampler| sampler.drop_update(&msg))
+    {
+        return false;
+    }
+    if let Some(catchup) = ctx.catchup.as_ref() {
+        catchup.throttle().await;
+    }
+    if inline || priority {
+        handle_update(ctx, msg, Instant::now());
+    } else {
+        ctx.queue.push(msg).await;
+    }
+    true
+}
+
+/// Only transaction statuses, and optionally slots, are subscribed. Statuses are small and
+/// cheap to handle, passing them through the queue to workers costs more CPU than handling
+/// them right on the stream task.
+fn is_status_only(request: &SubscribeRequest) -> bool {
+    !request.transactions_status.is_empty()
+        && request.accounts.is_empty()
+        && request.transactions.is_empty()
+        && request.blocks.is_empty()
+        && request.blocks_meta.is_empty()
+        && request.entry.is_empty()
+}
+
+/// Subscribe and record why the stream ended, returns an error to reconnect
+async fn geyser_subscribe(
+    client: GeyserGrpcClient<impl Interceptor>,
+    request: SubscribeRequest,
+    resub: usize,
+    ctx: StreamContext,
+    recorder: Option<CaptureWriter>,
+    watcher: Option<FiltersWatcher>,
+) -> Result<(), ClientError> {
+    let mut received = 0;
+    let result = geyser_stream(
+        client,
+        request,
+        resub,
+        &ctx,
+        recorder,
+        watcher,
+        &mut received,
+    )
+    .await;
+    let (reason, detail) = match &result {
+        Ok(()) => (EndReason::Shutdown, "shutdown requested".to_owned()),
+        Err(error) => EndReason::from_error(error),
+    };
+    info!("stream ended: {} ({detail})", reason.name());
+    if reason != EndReason::Shutdown {
+        ctx.lifecycle.transition(
+            Phase::Degraded,
+            serde_json::json!({ "reason": reason.name(), "detail": detail }),
+        );
+    }
+    ctx.reconnects.record(reason, detail, received);
+    result
+}
+
+/// Request sent for the configured filters: with discovered accounts, without filters
+/// switched off in the dashboard
+fn subscribe_request(ctx: &StreamContext, current: &SubscribeRequest) -> SubscribeRequest {
+    let request = match ctx.discovery.as_ref() {
+        Some(discovery) => discovery.apply(current),
+        None => current.clone(),
+    };
+    match ctx.dashboard.as_ref() {
+        Some(dashboard) => dashboard.apply(&request),
+        None => request,
+    }
+}
+
+/// Stream until shutdown, which is the only `Ok` end: a stream closed by the server or
+/// failed is an error, so `run_with_retry` connects and subscribes again
+async fn geyser_stream(
+    mut client: GeyserGrpcClient<impl Interceptor>,
+    request: SubscribeRequest,
+    resub: usize,
+    ctx: &StreamContext,
+    mut recorder: Option<CaptureWriter>,
+    mut watcher: Option<FiltersWatcher>,
+    received: &mut u64,
+) -> Result<(), ClientError> {
+    let mut current = request;
+    let (mut subscribe_tx, mut stream) = client
+        .subscribe_with_request(Some(subscribe_request(ctx, &current)))
+        .await?;
+
+    info!("stream opened");
+    ctx.reconnects.opened();
+    ctx.liveness.ready();
+    ctx.lifecycle
+        .transition(Phase::Subscribed, serde_json::Value::Null);
+    ctx.stats.subscribed(&current);
+    let inline = is_status_only(&current);
+    if inline {
+        info!("only transaction statuses are subscribed, handling updates on the stream task");
+    }
+    let mut shutdown = ctx.shutdown.clone();
+    let mut watchdog = ctx.watchdog.map(Watchdog::new);
+    let mut watchdog_check = interval(CHECK_INTERVAL);
+    watchdog_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut discover_check = interval(
+        ctx.discovery
+            .as_ref()
+            .map_or(CHECK_INTERVAL, |discovery| discovery.interval()),
+    );
+    discover_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut counter = 0;
+    // Failed re-subscribes since the last received message
+    let mut recoveries = 0;
+    let mut last_slot = None;
+    let end = loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            Ok(_) = shutdown.wait_for(|stop| *stop) => break Ok(()),
+            _ = watchdog_check.tick(), if watchdog.is_some() => {
+                if let Some(reason) = watchdog.as_ref().and_then(Watchdog::check) {
+                    warn!("watchdog: {reason}, reconnecting");
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.flush()?;
+                    }
+                    return Err(ClientError::IdleTimeout(format!("watchdog: {reason}")));
+                }
+                continue;
+            }
+            _ = FiltersWatcher::changed(watcher.as_mut()) => {
+                let Some(watcher) = watcher.as_ref() else {
+                    continue;
+                };
+                match watcher.reload() {
+                    Ok(request) => {
+                        current = request;
+                        ctx.stats.subscribed(&current);
+                        subscribe_tx
+                            .send(subscribe_request(ctx, &current))
+                            .await
+                            .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+                        info!("filters reloaded from {}", watcher.path());
+                    }
+                    Err(error) => warn!("failed to reload filters, keep current: {error}"),
+                }
+                continue;
+            }
+            _ = Dashboard::changed(ctx.dashboard.as_deref()) => {
+                if ctx.dashboard.is_some() {
+                    subscribe_tx
+                        .send(subscribe_request(ctx, &current))
+                        .await
+                        .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+                    info!("filters switched from the dashboard");
+                }
+                continue;
+            }
+            _ = discover_check.tick(), if ctx.discovery.is_some() => {
+                let Some(discovery) = ctx
+                    .discovery
+                    .as_ref()
+                    .filter(|discovery| discovery.take_changed())
+                else {
+                    continue;
+                };
+                subscribe_tx
+                    .send(subscribe_request(ctx, &current))
+                    .await
+                    .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+                info!(
+                    "discover: {} accounts subscribed, {} added and {} removed since start",
+                    discovery.accounts(),
+                    discovery.added(),
+                    discovery.evicted()
+                );
+                continue;
+            }
+        };
+        let Some(message) = message else {
+            break Err(ClientError::Closed);
+        };
+
+        match message {
+            Ok(msg) => {
+                *received += 1;
+                recoveries = 0;
+                last_slot = last_slot.max(update_slot(&msg));
+                ctx.bandwidth.observe(&msg);
+                ctx.liveness.observe();
+                if let Some(watchdog) = watchdog.as_mut() {
+                    watchdog.observe(&msg);
+                }
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.write(&msg)?;
+                }
+
+                if matches!(msg.update_oneof, Some(UpdateOneof::Ping(_))) {
+                    // This is necessary to keep load balancers that expect client pings alive. If your load balancer doesn't
+                    // require periodic client pings then this is unnecessary
+                    subscribe_tx
+                        .send(SubscribeRequest {
+                            ping: Some(SubscribeRequestPing { id: 1 }),
+                            ..Default::default()
+                        })
+                        .await
+                        .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+                }
+
+                let is_data_update = matches!(
+                    msg.update_oneof,
+                    Some(
+                        UpdateOneof::Account(_)
+                            | UpdateOneof::Transaction(_)
+                            | UpdateOneof::TransactionStatus(_)
+                    )
+                );
+                if !dispatch_update(ctx, msg, inline).await {
+                    continue;
+                }
+                if is_data_update {
+                    continue;
+                }
+            }
+            Err(error) => {
+                error!("error: {error:?}");
+                let error = ClientError::from(error);
+                // One bad message doesn't break the connection, subscribing again on it is
+                // much faster than a reconnect through the backoff
+                if matches!(error, ClientError::Decode(_) | ClientError::TooLarge(_))
+                    && recoveries < ctx.stream_recoveries
+                {
+                    recoveries += 1;
+                    ctx.reconnects.recovered();
+                    warn!(
+                        "{error}, subscribing again on the same connection after slot {} ({recoveries}/{})",
+                        last_slot.map_or_else(|| "none".to_owned(), |slot| slot.to_string()),
+                        ctx.stream_recoveries
+                    );
+                    let request = subscribe_request(ctx, &current);
+                    (subscribe_tx, stream) = client.subscribe_with_request(Some(request)).await?;
+                    continue;
+                }
+                break Err(error);
+            }
+        }
+
+        // Example to illustrate how to resubscribe/update the subscription
+        counter += 1;
+        if counter == resub {
+            let mut new_slots: SlotsFilterMap = HashMap::new();
+            new_slots.insert("client".to_owned(), SubscribeRequestFilterSlots::default());
+
+            subscribe_tx
+                .send(SubscribeRequest {
+                    slots: new_slots.clone(),
+                    accounts: HashMap::default(),
+                    transactions: HashMap::default(),
+                    transactions_status: HashMap::default(),
+                    entry: HashMap::default(),
+                    blocks: HashMap::default(),
+                    blocks_meta: HashMap::default(),
+                    commitment: None,
+                    accounts_data_slice: Vec::default(),
+                    ping: None,
+                })
+                .await
+                .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+        }
+    };
+
+    if end.is_ok() {
+        // Closing the request stream tells the server that we are done, process messages
+        // which are already in flight until the stream ends or the grace period is over
+        info!(
+            "closing subscription, draining for {:?}",
+            ctx.shutdown_grace
+        );
+        subscribe_tx
+            .close()
+            .await
+            .map_err(GeyserGrpcClientError::SubscribeSendError)?;
+        let deadline = Instant::now() + ctx.shutdown_grace;
+        while let Ok(Some(Ok(msg))) = timeout_at(deadline, stream.next()).await {
+            *received += 1;
+            ctx.bandwidth.observe(&msg);
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.write(&msg)?;
+            }
+            dispatch_update(ctx, msg, inline).await;
+        }
+    }
+
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.flush()?;
+    }
+    info!("stream closed");
+    end
+}
@@ -0,0 +1,267 @@
+//! Supply and holders of watched mints, `MINT_WATCH`.
+//!
+//! Updates of the mint accounts give the supply, an increase is counted as a mint event and
+//! a decrease as a burn. Updates of token accounts of the mints give the holders, owners with
+//! a positive balance in at least one token account of the mint. Holders are approximate:
+//! only token accounts received from the stream are known, so subscribe to the mint accounts
+//! and to their token accounts (memcmp on the mint at offset 0), `RPC_URL` fills in the
+//! existing ones. A snapshot of every mint is printed every `MINT_REPORT_SECS`.
+
+use {
+    crate::{
+        owners::{token_program, TokenAccount, TOKEN_ACCOUNT_LEN},
+        sink::{SinkHealth, UpdateSink},
+    },
+    serde::Serialize,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        env,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_REPORT_SECS: u64 = 60;
+const MINT_LEN: usize = 82;
+const MINT_SUPPLY_OFFSET: usize = 36;
+const MINT_DECIMALS_OFFSET: usize = 44;
+const MINT_INITIALIZED_OFFSET: usize = 45;
+/// Token-2022 mints with extensions are padded to the size of a token account, the account
+/// type follows
+const ACCOUNT_TYPE_MINT: u8 = 1;
+
+/// Supply and decimals of an initialized mint account
+pub fn decode_mint(program: &str, data: &[u8]) -> Option<(u64, u8)> {
+    let is_mint = match data.len() {
+        MINT_LEN => true,
+        len if len > TOKEN_ACCOUNT_LEN => {
+            program == "token-2022" && data[TOKEN_ACCOUNT_LEN] == ACCOUNT_TYPE_MINT
+        }
+        _ => false,
+    };
+    if !is_mint || data[MINT_INITIALIZED_OFFSET] == 0 {
+        return None;
+    }
+    let supply = data[MINT_SUPPLY_OFFSET..MINT_SUPPLY_OFFSET + 8]
+        .try_into()
+        .ok()?;
+    Some((u64::from_le_bytes(supply), data[MINT_DECIMALS_OFFSET]))
+}
+
+#[derive(Debug, Default)]
+struct MintState {
+    supply: Option<u64>,
+    decimals: Option<u8>,
+    /// Slot and write version of the last mint account update
+    version: Option<(u64, u64)>,
+    minted: u64,
+    burned: u64,
+    mint_events: u64,
+    burn_events: u64,
+    /// Token accounts with a positive balance by owner
+    holders: HashMap<Pubkey, u32>,
+    token_accounts: u64,
+}
+
+/// Positive balance of a token account of a watched mint
+#[derive(Debug)]
+struct Holding {
+    mint: Pubkey,
+    owner: Pubkey,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    mints: HashMap<Pubkey, MintState>,
+    holdings: HashMap<Pubkey, Holding>,
+}
+
+impl State {
+    fn remove_holding(&mut self, pubkey: &Pubkey) {
+        let Some(holding) = self.holdings.remove(pubkey) else {
+            return;
+        };
+        let Some(mint) = self.mints.get_mut(&holding.mint) else {
+            return;
+        };
+        mint.token_accounts -= 1;
+        if let Some(count) = mint.holders.get_mut(&holding.owner) {
+            *count -= 1;
+            if *count == 0 {
+                mint.holders.remove(&holding.owner);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MintSnapshot {
+    pub mint: String,
+    pub supply: Option<u64>,
+    /// Supply in whole tokens
+    pub ui_supply: Option<f64>,
+    pub decimals: Option<u8>,
+    pub slot: Option<u64>,
+    /// Total amount of supply increases and decreases since start, in base units
+    pub minted: u64,
+    pub burned: u64,
+    pub mint_events: u64,
+    pub burn_events: u64,
+    /// Owners with a positive balance
+    pub holders: usize,
+    /// Token accounts with a positive balance
+    pub token_accounts: u64,
+}
+
+#[derive(Debug)]
+pub struct MintTracker {
+    pub report: Duration,
+    state: Mutex<State>,
+}
+
+impl MintTracker {
+    /// Returns `None` if `MINT_WATCH` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("MINT_WATCH") else {
+            return Ok(None);
+        };
+        let mints = value
+            .split(',')
+            .map(str::trim)
+            .filter(|mint| !mint.is_empty())
+            .map(|mint| {
+                mint.parse::<Pubkey>()
+                    .map(|mint| (mint, MintState::default()))
+                    .map_err(|_| anyhow::anyhow!("invalid MINT_WATCH: {mint}"))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+        anyhow::ensure!(!mints.is_empty(), "invalid MINT_WATCH, no mints");
+        let report = match env::var("MINT_REPORT_SECS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid MINT_REPORT_SECS"))?,
+            Err(_) => DEFAULT_REPORT_SECS,
+        };
+        Ok(Some(Self {
+            report: Duration::from_secs(report),
+            state: Mutex::new(State {
+                mints,
+                holdings: HashMap::new(),
+            }),
+        }))
+    }
+
+    fn observe(&self, msg: &SubscribeUpdate) {
+        let Some(UpdateOneof::Account(update)) = msg.update_oneof.as_ref() else {
+            return;
+        };
+        let Some(account) = update.account.as_ref() else {
+            return;
+        };
+        let Some(program) = token_program(&account.owner) else {
+            return;
+        };
+        let Ok(pubkey) = Pubkey::try_from(account.pubkey.as_slice()) else {
+            return;
+        };
+        let mut state = self.state.lock().expect("poisoned");
+
+        if let Some(mint) = state.mints.get_mut(&pubkey) {
+            let version = (update.slot, account.write_version);
+            // Updates of one account can arrive out of order from several workers
+            if mint.version.is_some_and(|current| current > version) {
+                return;
+            }
+            let Some((supply, decimals)) = decode_mint(program, &account.data) else {
+                return;
+            };
+            match mint.supply {
+                Some(previous) if supply > previous => {
+                    mint.minted = mint.minted.saturating_add(supply - previous);
+                    mint.mint_events += 1;
+                }
+                Some(previous) if supply < previous => {
+                    mint.burned = mint.burned.saturating_add(previous - supply);
+                    mint.burn_events += 1;
+                }
+                _ => {}
+            }
+            mint.supply = Some(supply);
+            mint.decimals = Some(decimals);
+            mint.version = Some(version);
+            return;
+        }
+
+        let decoded = (account.lamports > 0)
+            .then(|| {
+                TokenAccount::decode(program, &account.data, update.slot, account.write_version)
+            })
+            .flatten()
+            .filter(|decoded| decoded.amount > 0 && state.mints.contains_key(&decoded.mint));
+        // Closed, emptied or transferred to another owner
+        state.remove_holding(&pubkey);
+        let Some(decoded) = decoded else {
+            return;
+        };
+        let mint = state.mints.get_mut(&decoded.mint).expect("checked");
+        mint.token_accounts += 1;
+        *mint.holders.entry(decoded.owner).or_default() += 1;
+        state.holdings.insert(
+            pubkey,
+            Holding {
+                mint: decoded.mint,
+                owner: decoded.owner,
+            },
+        );
+    }
+
+    pub fn snapshot(&self) -> Vec<MintSnapshot> {
+        let state = self.state.lock().expect("poisoned");
+        let mut snapshot = state
+            .mints
+            .iter()
+            .map(|(pubkey, mint)| MintSnapshot {
+                mint: pubkey.to_string(),
+                supply: mint.supply,
+                ui_supply: mint
+                    .supply
+                    .zip(mint.decimals)
+                    .map(|(supply, decimals)| supply as f64 / 10f64.powi(decimals.into())),
+                decimals: mint.decimals,
+                slot: mint.version.map(|(slot, _)| slot),
+                minted: mint.minted,
+                burned: mint.burned,
+                mint_events: mint.mint_events,
+                burn_events: mint.burn_events,
+                holders: mint.holders.len(),
+                token_accounts: mint.token_accounts,
+            })
+            .collect::<Vec<_>>();
+        snapshot.sort_by(|a, b| a.mint.cmp(&b.mint));
+        snapshot
+    }
+}
+
+/// Tracks mints and token accounts of updates passed to sinks in `MintTracker`
+pub struct MintTrackerSink(pub Arc<MintTracker>);
+
+impl UpdateSink for MintTrackerSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        self.0.observe(msg);
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "mints",
+            dropped: 0,
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+}
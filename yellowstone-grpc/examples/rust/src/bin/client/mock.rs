@@ -0,0 +1,316 @@
+//! Mock Geyser server with synthetic updates, used by `ACTION=LoadGen`.
+//!
+//! Every subscription gets account updates at the rate set through a watch channel, and a
+//! slot update every 400ms like on mainnet. Accounts are updated in turn with a counter in
+//! their data. Messages are paced in ticks and only generated when the stream is polled, so
+//! a client which can't keep up slows the server down instead of queueing in memory.
+//! Unary methods answer with the current generated slot.
+
+use {
+    futures::stream::{self, Stream},
+    log::error,
+    std::{
+        net::SocketAddr,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        net::TcpListener,
+        sync::watch,
+        task::JoinHandle,
+        time::{sleep, Instant},
+    },
+    yellowstone_grpc_proto::{
+        prelude::{
+            geyser_server::{Geyser, GeyserServer},
+            subscribe_update::UpdateOneof,
+            CommitmentLevel, GetBlockHeightRequest, GetBlockHeightResponse,
+            GetLatestBlockhashRequest, GetLatestBlockhashResponse, GetSlotRequest, GetSlotResponse,
+            GetVersionRequest, GetVersionResponse, IsBlockhashValidRequest,
+            IsBlockhashValidResponse, PingRequest, PongResponse, SubscribeRequest, SubscribeUpdate,
+            SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateSlot,
+        },
+        tonic::{self, transport::Server, Request, Response, Status, Streaming},
+    },
+};
+
+const SLOT_DURATION: Duration = Duration::from_millis(400);
+/// Messages are generated in bursts of this interval
+const TICK: Duration = Duration::from_millis(10);
+const FIRST_SLOT: u64 = 1_000_000;
+pub const FILTER: &str = "loadgen";
+
+#[derive(Debug, Clone, Copy)]
+pub struct MockConfig {
+    pub data_bytes: usize,
+    pub accounts: u64,
+}
+
+#[derive(Debug)]
+pub struct MockGeyser {
+    config: MockConfig,
+    rate: watch::Receiver<u64>,
+    started: Instant,
+    /// Account updates sent on all streams
+    sent: Arc<AtomicU64>,
+}
+
+impl MockGeyser {
+    pub fn new(config: MockConfig, rate: watch::Receiver<u64>, sent: Arc<AtomicU64>) -> Self {
+        Self {
+            config,
+            rate,
+            started: Instant::now(),
+            sent,
+        }
+    }
+
+    fn slot(&self) -> u64 {
+        current_slot(self.started)
+    }
+}
+
+fn current_slot(started: Instant) -> u64 {
+    FIRST_SLOT + (started.elapsed().as_millis() / SLOT_DURATION.as_millis()) as u64
+}
+
+/// Serve on `addr`, returns the bound address, a port 0 is replaced by the one picked
+pub async fn serve(
+    addr: SocketAddr,
+    geyser: MockGeyser,
+) -> anyhow::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|error| anyhow::anyhow!("failed to bind mock server to {addr}: {error}"))?;
+    let addr = listener.local_addr()?;
+    let incoming = stream::unfold(listener, |listener| async move {
+        let conn = listener.accept().await.map(|(stream, _)| stream);
+        Some((conn, listener))
+    });
+    let task = tokio::spawn(async move {
+        if let Err(error) = Server::builder()
+            .add_service(GeyserServer::new(geyser))
+            .serve_with_incoming(incoming)
+            .await
+        {
+            error!("mock server failed: {error}");
+        }
+    });
+    Ok((addr, task))
+}
+
+/// Updates of one subscription, the account counter is shared by all streams
+struct Generator {
+    config: MockConfig,
+    rate: watch::Receiver<u64>,
+    started: Instant,
+    sent: Arc<AtomicU64>,
+    slot: u64,
+    /// Start and generated messages of the current rate
+    step_started: Instant,
+    step_sent: u64,
+}
+
+impl Generator {
+    fn slot(&mut self) -> Option<SubscribeUpdate> {
+        let slot = current_slot(self.started);
+        if slot <= self.slot {
+            return None;
+        }
+        self.slot = slot;
+        Some(SubscribeUpdate {
+            filters: vec![FILTER.to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: Some(slot - 1),
+                status: CommitmentLevel::Processed as i32,
+            })),
+        })
+    }
+
+    fn account(&mut self) -> SubscribeUpdate {
+        let counter = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        self.step_sent += 1;
+        let mut pubkey = [0u8; 32];
+        pubkey[..8].copy_from_slice(&(counter % self.config.accounts).to_le_bytes());
+        let mut data = vec![0u8; self.config.data_bytes];
+        for (byte, value) in data.iter_mut().zip(counter.to_le_bytes()) {
+            *byte = value;
+        }
+        SubscribeUpdate {
+            filters: vec![FILTER.to_owned()],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: pubkey.to_vec(),
+                    lamports: 1_000_000 + counter,
+                    owner: vec![0; 32],
+                    executable: false,
+                    rent_epoch: 0,
+                    data,
+                    write_version: counter,
+                    txn_signature: None,
+                }),
+                slot: self.slot,
+                is_startup: false,
+            })),
+        }
+    }
+
+    /// Next update, waits for the next tick when the rate is reached
+    async fn next(&mut self) -> SubscribeUpdate {
+        loop {
+            if self.rate.has_changed().unwrap_or(false) {
+                self.rate.borrow_and_update();
+                self.step_started = Instant::now();
+                self.step_sent = 0;
+            }
+            if let Some(msg) = self.slot() {
+                return msg;
+            }
+            let rate = *self.rate.borrow();
+            let due = (rate as f64 * self.step_started.elapsed().as_secs_f64()) as u64;
+            if self.step_sent < due {
+                return self.account();
+            }
+            sleep(TICK).await;
+        }
+    }
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Geyser for MockGeyser {
+    type SubscribeStream = UpdateStream;
+
+    /// Filters of the request are ignored, every stream gets the same updates
+    async fn subscribe(
+        &self,
+        _request: Request<Streaming<SubscribeRequest>>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let mut rate = self.rate.clone();
+        rate.mark_unchanged();
+        let generator = Generator {
+            config: self.config,
+            rate,
+            started: self.started,
+            sent: Arc::clone(&self.sent),
+            slot: 0,
+            step_started: Instant::now(),
+            step_sent: 0,
+        };
+        let updates = stream::unfold(generator, |mut generator| async move {
+            let msg = generator.next().await;
+            Some((Ok(msg), generator))
+        });
+        Ok(Response::new(Box::pin(updates)))
+    }
+
+    async fn ping(&self, request: Request<PingRequest>) -> Result<Response<PongResponse>, Status> {
+        Ok(Response::new(PongResponse {
+            count: request.get_ref().count,
+        }))
+    }
+
+    async fn get_latest_blockhash(
+        &self,
+        _request: Request<GetLatestBlockhashRequest>,
+    ) -> Result<Response<GetLatestBlockhashResponse>, Status> {
+        let slot = self.slot();
+        Ok(Response::new(GetLatestBlockhashResponse {
+            slot,
+            blockhash: solana_sdk::hash::Hash::default().to_string(),
+            last_valid_block_height: slot + 150,
+        }))
+    }
+
+    async fn get_block_height(
+        &self,
+        _request: Request<GetBlockHeightRequest>,
+    ) -> Result<Response<GetBlockHeightResponse>, Status> {
+        Ok(Response::new(GetBlockHeightResponse {
+            block_height: self.slot(),
+        }))
+    }
+
+    async fn get_slot(
+        &self,
+        _request: Request<GetSlotRequest>,
+    ) -> Result<Response<GetSlotResponse>, Status> {
+        Ok(Response::new(GetSlotResponse { slot: self.slot() }))
+    }
+
+    async fn is_blockhash_valid(
+        &self,
+        _request: Request<IsBlockhashValidRequest>,
+    ) -> Result<Response<IsBlockhashValidResponse>, Status> {
+        Ok(Response::new(IsBlockhashValidResponse {
+            slot: self.slot(),
+            valid: true,
+        }))
+    }
+
+    async fn get_version(
+        &self,
+        _request: Request<GetVersionRequest>,
+    ) -> Result<Response<GetVersionResponse>, Status> {
+        Ok(Response::new(GetVersionResponse {
+            version: serde_json::json!({ "mock": env!("CARGO_PKG_VERSION") }).to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, futures::stream::StreamExt, yellowstone_grpc_client::GeyserGrpcClient};
+
+    #[tokio::test]
+    async fn streams_at_rate_over_grpc() {
+        let (rate_tx, rate_rx) = watch::channel(1_000);
+        let sent = Arc::new(AtomicU64::new(0));
+        let config = MockConfig {
+            data_bytes: 200,
+            accounts: 10,
+        };
+        let geyser = MockGeyser::new(config, rate_rx, Arc::clone(&sent));
+        let (addr, server) = serve("127.0.0.1:0".parse().unwrap(), geyser).await.unwrap();
+
+        let mut client = GeyserGrpcClient::build_from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut stream = client
+            .subscribe_once(SubscribeRequest::default())
+            .await
+            .unwrap();
+        let first = stream.next().await.unwrap().unwrap();
+        assert!(matches!(first.update_oneof, Some(UpdateOneof::Slot(_))));
+
+        let mut accounts = 0;
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(500) {
+            let msg = stream.next().await.unwrap().unwrap();
+            if let Some(UpdateOneof::Account(update)) = msg.update_oneof {
+                assert_eq!(update.account.unwrap().data.len(), 200);
+                accounts += 1;
+            }
+        }
+        assert!(
+            (400..=600).contains(&accounts),
+            "{accounts} accounts in 500ms"
+        );
+
+        // No account updates without a rate, slots keep coming
+        rate_tx.send(0).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let before = sent.load(Ordering::Relaxed);
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(sent.load(Ordering::Relaxed), before);
+        server.abort();
+    }
+}
@@ -0,0 +1,492 @@
+//! Identical subscriptions to all endpoints merged first-wins, `ACTION=MultiSubscribe`.
+//!
+//! Every configured endpoint (`ENDPOINT`, `ENDPOINT_1`, ...) gets its own stream with the
+//! same filters, each one reconnects on its own. An update is processed when it arrives
+//! first from any endpoint, later copies are dropped and counted as lost by their endpoint.
+//! Slot updates are identified by slot and status, blocks and block meta by slot, entries by
+//! slot and index, transactions and transaction statuses by signature, and accounts by
+//! pubkey, slot and transaction signature: the write version is assigned by each validator,
+//! so it differs between providers. Only the last `MULTI_DEDUP_CAPACITY` keys are kept.
+//!
+//! With `MULTI_PRECEDENCE` the endpoints are ranked, e.g. a dedicated node before a shared
+//! fallback, and the output follows the highest ranked endpoint which delivered anything in
+//! the last `MULTI_STALE_MS`: updates of lower ranked endpoints are suppressed, without
+//! remembering their key, so the authoritative copy is processed when it arrives. When the
+//! primary stalls the next live endpoint takes over, updates it already delivered are not
+//! repeated when the primary comes back. Independently of precedence, a slot status is
+//! dropped when the slot already reached a higher commitment from another endpoint, so a
+//! late `processed` never follows `finalized` of the same slot.
+
+use {
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashMap, VecDeque},
+        env,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_CAPACITY: usize = 100_000;
+/// Slots of which the highest emitted status is remembered
+const SLOT_STATUS_CAPACITY: usize = 1_024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MergeKey {
+    Slot {
+        slot: u64,
+        status: i32,
+    },
+    Account {
+        pubkey: Vec<u8>,
+        slot: u64,
+        signature: Option<Vec<u8>>,
+    },
+    Transaction {
+        signature: Vec<u8>,
+    },
+    TransactionStatus {
+        signature: Vec<u8>,
+    },
+    Block {
+        slot: u64,
+    },
+    BlockMeta {
+        slot: u64,
+    },
+    Entry {
+        slot: u64,
+        index: u64,
+    },
+}
+
+impl MergeKey {
+    fn from_update(msg: &SubscribeUpdate) -> Option<Self> {
+        Some(match msg.update_oneof.as_ref()? {
+            UpdateOneof::Slot(update) => Self::Slot {
+                slot: update.slot,
+                status: update.status,
+            },
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                Self::Account {
+                    pubkey: account.pubkey.clone(),
+                    slot: update.slot,
+                    signature: account.txn_signature.clone(),
+                }
+            }
+            UpdateOneof::Transaction(update) => Self::Transaction {
+                signature: update.transaction.as_ref()?.signature.clone(),
+            },
+            UpdateOneof::TransactionStatus(update) => Self::TransactionStatus {
+                signature: update.signature.clone(),
+            },
+            UpdateOneof::Block(update) => Self::Block { slot: update.slot },
+            UpdateOneof::BlockMeta(update) => Self::BlockMeta { slot: update.slot },
+            UpdateOneof::Entry(update) => Self::Entry {
+                slot: update.slot,
+                index: update.index,
+            },
+            UpdateOneof::Ping(_) | UpdateOneof::Pong(_) => return None,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    received: u64,
+    wins: u64,
+    /// Total delay behind the winner of lost updates
+    behind: Duration,
+    /// First copies dropped because a higher ranked endpoint was live
+    suppressed: u64,
+    /// Slot statuses dropped because the slot already reached a higher commitment
+    stale_status: u64,
+    last_received: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// When the key was first received
+    seen: HashMap<MergeKey, Instant>,
+    /// Keys in the order of arrival, the oldest is evicted first
+    order: VecDeque<MergeKey>,
+    counters: Vec<Counters>,
+    /// Highest emitted status by slot
+    slot_status: BTreeMap<u64, i32>,
+}
+
+/// Win-rate statistics of one endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointReport {
+    pub endpoint: String,
+    pub received: u64,
+    pub wins: u64,
+    /// Share of all unique updates received first from this endpoint
+    pub win_rate: f64,
+    /// Mean delay behind the winner of updates received from another endpoint first
+    pub mean_behind_ms: f64,
+    pub suppressed: u64,
+    pub stale_status: u64,
+    /// Output currently follows this endpoint, only reported with `MULTI_PRECEDENCE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authoritative: Option<bool>,
+}
+
+#[derive(Debug)]
+pub struct MultiMerge {
+    names: Vec<String>,
+    capacity: usize,
+    pub report_interval: Duration,
+    /// Rank of every endpoint from `MULTI_PRECEDENCE`, lower is preferred
+    ranks: Option<Vec<usize>>,
+    stale: Duration,
+    state: Mutex<State>,
+}
+
+impl MultiMerge {
+    /// Statistics for the endpoints, in the order of their indexes in `first`
+    pub fn from_env(names: Vec<String>) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            names.len() >= 2,
+            "MultiSubscribe requires ENDPOINT and at least ENDPOINT_1"
+        );
+        let capacity = match env::var("MULTI_DEDUP_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid MULTI_DEDUP_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        let report_interval = match env::var("MULTI_REPORT_SECS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .ok_or_else(|| anyhow::anyhow!("invalid MULTI_REPORT_SECS"))?,
+            Err(_) => Duration::from_secs(60),
+        };
+        let stale = match env::var("MULTI_STALE_MS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis)
+                .ok_or_else(|| anyhow::anyhow!("invalid MULTI_STALE_MS"))?,
+            Err(_) => Duration::from_secs(2),
+        };
+        let precedence = env::var("MULTI_PRECEDENCE").ok();
+        Self::new(
+            names,
+            capacity,
+            report_interval,
+            precedence.as_deref(),
+            stale,
+        )
+    }
+
+    pub fn new(
+        names: Vec<String>,
+        capacity: usize,
+        report_interval: Duration,
+        precedence: Option<&str>,
+        stale: Duration,
+    ) -> anyhow::Result<Self> {
+        let ranks = match precedence {
+            Some(value) => {
+                let order = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| {
+                        names.iter().position(|known| known == name).ok_or_else(|| {
+                            anyhow::anyhow!("invalid MULTI_PRECEDENCE, unknown endpoint {name}")
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                // Endpoints which are not listed rank last, in their own order
+                let ranks = (0..names.len())
+                    .map(|index| {
+                        order
+                            .iter()
+                            .position(|ranked| *ranked == index)
+                            .unwrap_or(order.len() + index)
+                    })
+                    .collect();
+                Some(ranks)
+            }
+            None => None,
+        };
+
+        let state = State {
+            counters: vec![Counters::default(); names.len()],
+            ..Default::default()
+        };
+        Ok(Self {
+            names,
+            capacity,
+            report_interval,
+            ranks,
+            stale,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Highest ranked endpoint which received anything within `MULTI_STALE_MS`
+    fn authoritative(&self, state: &State, now: Instant) -> Option<usize> {
+        let ranks = self.ranks.as_ref()?;
+        (0..self.names.len())
+            .filter(|index| {
+                state.counters[*index]
+                    .last_received
+                    .is_some_and(|last| now.duration_since(last) < self.stale)
+            })
+            .min_by_key(|index| ranks[*index])
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Count the update received from the endpoint, returns `true` if no other endpoint
+    /// delivered it before and it should be processed
+    pub fn first(&self, endpoint: usize, msg: &SubscribeUpdate) -> bool {
+        let key = MergeKey::from_update(msg);
+        let now = Instant::now();
+
+        let mut state = self.state.lock().expect("poisoned");
+        // Pings keep quiet streams live for precedence
+        state.counters[endpoint].last_received = Some(now);
+        // Pings and pongs belong to the connection
+        let Some(key) = key else {
+            return true;
+        };
+        state.counters[endpoint].received += 1;
+        if let Some(first) = state.seen.get(&key).copied() {
+            state.counters[endpoint].behind += now.duration_since(first);
+            return false;
+        }
+        if self
+            .authoritative(&state, now)
+            .is_some_and(|authoritative| authoritative != endpoint)
+        {
+            state.counters[endpoint].suppressed += 1;
+            return false;
+        }
+        if let MergeKey::Slot { slot, status } = key {
+            if state
+                .slot_status
+                .get(&slot)
+                .is_some_and(|highest| *highest > status)
+            {
+                state.counters[endpoint].stale_status += 1;
+                return false;
+            }
+            state.slot_status.insert(slot, status);
+            while state.slot_status.len() > SLOT_STATUS_CAPACITY {
+                state.slot_status.pop_first();
+            }
+        }
+
+        state.counters[endpoint].wins += 1;
+        state.seen.insert(key.clone(), now);
+        state.order.push_back(key);
+        while state.order.len() > self.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                state.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn report(&self) -> Vec<EndpointReport> {
+        let state = self.state.lock().expect("poisoned");
+        let authoritative = self.authoritative(&state, Instant::now());
+        let unique = state
+            .counters
+            .iter()
+            .map(|counters| counters.wins)
+            .sum::<u64>();
+        self.names
+            .iter()
+            .zip(state.counters.iter())
+            .enumerate()
+            .map(|(index, (name, counters))| {
+                let lost = counters
+                    .received
+                    .saturating_sub(counters.wins + counters.suppressed + counters.stale_status);
+                EndpointReport {
+                    endpoint: name.clone(),
+                    received: counters.received,
+                    wins: counters.wins,
+                    win_rate: if unique > 0 {
+                        counters.wins as f64 / unique as f64
+                    } else {
+                        0.0
+                    },
+                    mean_behind_ms: if lost > 0 {
+                        counters.behind.as_secs_f64() * 1000.0 / lost as f64
+                    } else {
+                        0.0
+                    },
+                    suppressed: counters.suppressed,
+                    stale_status: counters.stale_status,
+                    authoritative: self.ranks.as_ref().map(|_| authoritative == Some(index)),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeUpdatePing, SubscribeUpdateSlot, SubscribeUpdateTransactionStatus,
+        },
+    };
+
+    const STALE: Duration = Duration::from_millis(50);
+
+    fn merge(count: usize, capacity: usize, precedence: Option<&str>) -> MultiMerge {
+        let names = (0..count)
+            .map(|index| format!("endpoint-{index}"))
+            .collect();
+        MultiMerge::new(names, capacity, Duration::from_secs(60), precedence, STALE).unwrap()
+    }
+
+    fn slot(slot: u64, status: i32) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: None,
+                status,
+            })),
+        }
+    }
+
+    fn status(signature: u8) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["transactions".to_owned()],
+            update_oneof: Some(UpdateOneof::TransactionStatus(
+                SubscribeUpdateTransactionStatus {
+                    slot: 10,
+                    signature: vec![signature; 64],
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    fn ping() -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Ping(SubscribeUpdatePing {})),
+        }
+    }
+
+    /// Received, wins, suppressed and stale statuses of every endpoint
+    fn counts(merge: &MultiMerge) -> Vec<(u64, u64, u64, u64)> {
+        merge
+            .report()
+            .into_iter()
+            .map(|report| {
+                (
+                    report.received,
+                    report.wins,
+                    report.suppressed,
+                    report.stale_status,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_copy_wins() {
+        let merge = merge(2, 100, None);
+        assert!(merge.first(0, &status(1)));
+        assert!(!merge.first(1, &status(1)));
+        assert!(merge.first(1, &status(2)));
+        assert!(!merge.first(0, &status(2)));
+        assert!(!merge.first(0, &status(2)));
+        // Pings are not merged
+        assert!(merge.first(0, &ping()));
+        assert!(merge.first(1, &ping()));
+
+        assert_eq!(counts(&merge), [(3, 1, 0, 0), (2, 1, 0, 0)]);
+        let report = merge.report();
+        assert_eq!(report[0].win_rate, 0.5);
+        assert_eq!(report[0].authoritative, None);
+    }
+
+    #[test]
+    fn oldest_keys_are_evicted_at_capacity() {
+        let merge = merge(2, 2, None);
+        assert!(merge.first(0, &status(1)));
+        assert!(merge.first(0, &status(2)));
+        assert!(merge.first(0, &status(3)));
+        // The first key was evicted, the copy is processed again
+        assert!(merge.first(1, &status(1)));
+        assert!(!merge.first(1, &status(3)));
+        // And evicted the second key in turn
+        assert!(merge.first(1, &status(2)));
+    }
+
+    #[test]
+    fn lower_status_of_a_slot_is_dropped() {
+        let merge = merge(2, 100, None);
+        assert!(merge.first(0, &slot(10, 0)));
+        assert!(merge.first(0, &slot(10, 2)));
+        assert!(!merge.first(1, &slot(10, 1)));
+        assert!(merge.first(1, &slot(11, 1)));
+        assert_eq!(counts(&merge), [(2, 2, 0, 0), (2, 1, 0, 1)]);
+    }
+
+    #[test]
+    fn precedence_follows_highest_ranked_live_endpoint() {
+        let merge = merge(3, 100, Some("endpoint-1, endpoint-0"));
+        // Only the fallback is live yet
+        assert!(merge.first(0, &status(1)));
+        assert!(merge.first(1, &ping()));
+        // Suppressed without remembering the key, the primary's copy is processed
+        assert!(!merge.first(0, &status(2)));
+        assert!(merge.first(1, &status(2)));
+        assert!(merge.first(1, &status(3)));
+        // Unlisted endpoints rank last
+        assert!(!merge.first(2, &status(4)));
+
+        // The primary stalls and the next listed live endpoint takes over
+        std::thread::sleep(STALE * 2);
+        assert!(merge.first(2, &ping()));
+        assert!(merge.first(0, &status(4)));
+        assert!(!merge.first(2, &status(5)));
+        let authoritative = merge
+            .report()
+            .into_iter()
+            .map(|report| report.authoritative)
+            .collect::<Vec<_>>();
+        assert_eq!(authoritative, [Some(true), Some(false), Some(false)]);
+
+        // The primary comes back, updates the fallback delivered are not repeated
+        assert!(!merge.first(1, &status(4)));
+        assert!(merge.first(1, &status(5)));
+        assert!(!merge.first(0, &status(6)));
+        assert_eq!(counts(&merge), [(4, 2, 2, 0), (4, 3, 0, 0), (2, 0, 2, 0)]);
+    }
+
+    #[test]
+    fn precedence_names_known_endpoints() {
+        let names = vec!["a".to_owned(), "b".to_owned()];
+        let error =
+            MultiMerge::new(names, 100, Duration::from_secs(60), Some("a,c"), STALE).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid MULTI_PRECEDENCE, unknown endpoint c"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use {
+    crate::sink::csv::write_row,
+    log::info,
+    serde_json::{json, Value},
+    std::fmt,
+    tonic_health::pb::HealthCheckResponse,
+    yellowstone_grpc_proto::prelude::{
+        GetBlockHeightResponse, GetLatestBlockhashResponse, GetSlotResponse, GetVersionResponse,
+        IsBlockhashValidResponse, PongResponse,
+    },
+};
+
+/// How results of request/response actions are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Log response with `info!`
+    #[default]
+    Text,
+    /// Print one JSON object to stdout, logs are still written to stderr
+    Json,
+    /// Print header and one CSV row to stdout, stream updates are written by the CSV sink
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn from_env_value(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => anyhow::bail!("invalid OUTPUT value, expected `text`, `json` or `csv`"),
+        }
+    }
+
+    pub fn print_response<T: fmt::Debug + ToJson>(self, response: &T) {
+        match self {
+            Self::Text => info!("response: {response:?}"),
+            Self::Json => println!("{}", response.to_json()),
+            Self::Csv => {
+                let Value::Object(fields) = response.to_json() else {
+                    unreachable!("responses are JSON objects");
+                };
+                let (header, row): (Vec<_>, Vec<_>) = fields
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        Value::String(value) => (key, value),
+                        value => (key, value.to_string()),
+                    })
+                    .unzip();
+                let mut stdout = std::io::stdout().lock();
+                let _ = write_row(&mut stdout, &header);
+                let _ = write_row(&mut stdout, &row);
+            }
+        }
+    }
+
+    /// Print periodic event, in `Json` mode one object per line
+    pub fn print_event(self, name: &str, value: &Value) {
+        match self {
+            Self::Text | Self::Csv => info!("{name}: {value}"),
+            Self::Json => println!("{}", json!({ "event": name, "data": value })),
+        }
+    }
+
+    pub fn print_error(self, error: &anyhow::Error) {
+        if self == Self::Json {
+            println!("{}", json!({ "error": error.to_string() }));
+        }
+    }
+}
+
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+impl ToJson for HealthCheckResponse {
+    fn to_json(&self) -> Value {
+        json!({ "status": self.status().as_str_name() })
+    }
+}
+
+impl ToJson for PongResponse {
+    fn to_json(&self) -> Value {
+        json!({ "count": self.count })
+    }
+}
+
+impl ToJson for GetLatestBlockhashResponse {
+    fn to_json(&self) -> Value {
+        json!({
+            "slot": self.slot,
+            "blockhash": self.blockhash,
+            "last_valid_block_height": self.last_valid_block_height,
+        })
+    }
+}
+
+impl ToJson for GetBlockHeightResponse {
+    fn to_json(&self) -> Value {
+        json!({ "block_height": self.block_height })
+    }
+}
+
+impl ToJson for GetSlotResponse {
+    fn to_json(&self) -> Value {
+        json!({ "slot": self.slot })
+    }
+}
+
+impl ToJson for IsBlockhashValidResponse {
+    fn to_json(&self) -> Value {
+        json!({ "slot": self.slot, "valid": self.valid })
+    }
+}
+
+impl ToJson for GetVersionResponse {
+    fn to_json(&self) -> Value {
+        // Server returns version info as serialized JSON object
+        let version = serde_json::from_str::<Value>(&self.version)
+            .unwrap_or_else(|_| Value::String(self.version.clone()));
+        json!({ "version": version })
+    }
+}
@@ -0,0 +1,271 @@
+//! Token accounts by owner wallet built from the stream, `TOKEN_OWNERS=true`.
+//!
+//! Account updates of SPL Token and Token-2022 token accounts are decoded and indexed by
+//! their owner, `GET /owner/<pubkey>/tokens` of the admin API returns the current token
+//! accounts of a wallet with mint and amount, instead of calling `getTokenAccountsByOwner`
+//! over and over. Only token accounts received from the stream are known, subscribe to the
+//! token program with a memcmp on the owner at offset 32 for the watched wallets. A token
+//! account which moves to another owner is moved in the index, a closed account is removed.
+//! `TOKEN_OWNERS_WALLETS` limits the index to these owners, at most `TOKEN_OWNERS_CAPACITY`
+//! token accounts are kept and new accounts over the limit are counted as dropped.
+
+use {
+    crate::sink::{SinkHealth, UpdateSink},
+    serde::Serialize,
+    solana_sdk::{pubkey, pubkey::Pubkey},
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const TOKEN_PROGRAM: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const TOKEN_2022_PROGRAM: Pubkey = pubkey!("TokenzQdBNbLqP7VxXebDnTNmXnU6yHf3XPD2gkqwAg");
+pub const TOKEN_ACCOUNT_LEN: usize = 165;
+const TOKEN_ACCOUNT_STATE_OFFSET: usize = 108;
+/// Token-2022 accounts with extensions have the account type after the base account
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+const DEFAULT_CAPACITY: usize = 1_000_000;
+
+/// `token` or `token-2022` if the account is owned by one of the token programs
+pub fn token_program(owner: &[u8]) -> Option<&'static str> {
+    if owner == TOKEN_PROGRAM.as_ref() {
+        Some("token")
+    } else if owner == TOKEN_2022_PROGRAM.as_ref() {
+        Some("token-2022")
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenAccount {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    program: &'static str,
+    slot: u64,
+    write_version: u64,
+}
+
+impl TokenAccount {
+    /// `None` if the data is not an initialized token account
+    pub fn decode(
+        program: &'static str,
+        data: &[u8],
+        slot: u64,
+        write_version: u64,
+    ) -> Option<Self> {
+        let is_account = match data.len() {
+            TOKEN_ACCOUNT_LEN => true,
+            len if len > TOKEN_ACCOUNT_LEN => {
+                program == "token-2022" && data[TOKEN_ACCOUNT_LEN] == ACCOUNT_TYPE_ACCOUNT
+            }
+            _ => false,
+        };
+        if !is_account || data[TOKEN_ACCOUNT_STATE_OFFSET] == 0 {
+            return None;
+        }
+        Some(Self {
+            mint: Pubkey::try_from(&data[0..32]).ok()?,
+            owner: Pubkey::try_from(&data[32..64]).ok()?,
+            amount: u64::from_le_bytes(data[64..72].try_into().ok()?),
+            program,
+            slot,
+            write_version,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    accounts: HashMap<Pubkey, TokenAccount>,
+    by_owner: HashMap<Pubkey, BTreeSet<Pubkey>>,
+}
+
+impl Index {
+    fn remove(&mut self, pubkey: &Pubkey) -> Option<TokenAccount> {
+        let account = self.accounts.remove(pubkey)?;
+        if let Some(owned) = self.by_owner.get_mut(&account.owner) {
+            owned.remove(pubkey);
+            if owned.is_empty() {
+                self.by_owner.remove(&account.owner);
+            }
+        }
+        Some(account)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OwnedTokenAccount {
+    pubkey: String,
+    mint: String,
+    amount: u64,
+    program: &'static str,
+    slot: u64,
+}
+
+/// Response of `GET /owner/<pubkey>/tokens`
+#[derive(Debug, Serialize)]
+pub struct OwnerTokens {
+    owner: String,
+    accounts: Vec<OwnedTokenAccount>,
+    /// Sum of amounts by mint, in base units
+    mints: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub struct TokenOwners {
+    /// Only these owners are indexed, all if empty
+    wallets: HashSet<Pubkey>,
+    capacity: usize,
+    index: Mutex<Index>,
+    /// New token accounts not indexed because the index was full
+    dropped: AtomicU64,
+}
+
+impl TokenOwners {
+    /// Returns `None` unless `TOKEN_OWNERS=true`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match env::var("TOKEN_OWNERS").as_deref() {
+            Ok("true") => {}
+            Ok("false") | Err(_) => return Ok(None),
+            Ok(_) => anyhow::bail!("invalid TOKEN_OWNERS, expected `true` or `false`"),
+        }
+        let wallets = env::var("TOKEN_OWNERS_WALLETS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|wallet| !wallet.is_empty())
+            .map(|wallet| {
+                wallet
+                    .parse::<Pubkey>()
+                    .map_err(|_| anyhow::anyhow!("invalid TOKEN_OWNERS_WALLETS: {wallet}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let capacity = match env::var("TOKEN_OWNERS_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid TOKEN_OWNERS_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        Ok(Some(Self {
+            wallets,
+            capacity,
+            index: Mutex::default(),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of indexed token accounts and owners
+    pub fn counts(&self) -> (usize, usize) {
+        let index = self.index.lock().expect("poisoned");
+        (index.accounts.len(), index.by_owner.len())
+    }
+
+    fn observe(&self, msg: &SubscribeUpdate) {
+        let Some(UpdateOneof::Account(update)) = msg.update_oneof.as_ref() else {
+            return;
+        };
+        let Some(account) = update.account.as_ref() else {
+            return;
+        };
+        let Some(program) = token_program(&account.owner) else {
+            return;
+        };
+        let Ok(pubkey) = Pubkey::try_from(account.pubkey.as_slice()) else {
+            return;
+        };
+        let decoded = (account.lamports > 0)
+            .then(|| {
+                TokenAccount::decode(program, &account.data, update.slot, account.write_version)
+            })
+            .flatten()
+            .filter(|decoded| self.wallets.is_empty() || self.wallets.contains(&decoded.owner));
+
+        let mut index = self.index.lock().expect("poisoned");
+        // Updates of one account can arrive out of order from several workers
+        if index.accounts.get(&pubkey).is_some_and(|current| {
+            (current.slot, current.write_version) > (update.slot, account.write_version)
+        }) {
+            return;
+        }
+        let previous = index.remove(&pubkey);
+        // Closed, not a token account anymore or moved to an owner which is not watched
+        let Some(decoded) = decoded else {
+            return;
+        };
+        if previous.is_none() && index.accounts.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        index
+            .by_owner
+            .entry(decoded.owner)
+            .or_default()
+            .insert(pubkey);
+        index.accounts.insert(pubkey, decoded);
+    }
+
+    pub fn owner(&self, owner: &str) -> anyhow::Result<OwnerTokens> {
+        let owner = owner
+            .parse::<Pubkey>()
+            .map_err(|_| anyhow::anyhow!("invalid pubkey {owner:?}"))?;
+        let index = self.index.lock().expect("poisoned");
+        let mut mints = BTreeMap::new();
+        let accounts = index
+            .by_owner
+            .get(&owner)
+            .into_iter()
+            .flatten()
+            .filter_map(|pubkey| Some((pubkey, index.accounts.get(pubkey)?)))
+            .map(|(pubkey, account)| {
+                let total = mints.entry(account.mint.to_string()).or_insert(0u64);
+                *total = total.saturating_add(account.amount);
+                OwnedTokenAccount {
+                    pubkey: pubkey.to_string(),
+                    mint: account.mint.to_string(),
+                    amount: account.amount,
+                    program: account.program,
+                    slot: account.slot,
+                }
+            })
+            .collect();
+        Ok(OwnerTokens {
+            owner: owner.to_string(),
+            accounts,
+            mints,
+        })
+    }
+}
+
+/// Indexes token accounts of updates passed to sinks in `TokenOwners`
+pub struct TokenOwnersSink(pub Arc<TokenOwners>);
+
+impl UpdateSink for TokenOwnersSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        self.0.observe(msg);
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "token_owners",
+            dropped: self.0.dropped(),
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+}
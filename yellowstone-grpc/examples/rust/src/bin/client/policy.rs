@@ -0,0 +1,271 @@
+//! What happens to an update when a stage of the pipeline fails, `ERROR_POLICY_<STAGE>`.
+//!
+//! Updates go through three stages after filters: `decode` builds the pretty form of an
+//! update for logging (pubkeys, signatures, transactions and instructions), `enrich` adds
+//! tags and account diffs, and `sink` passes the update to every sink. An error or a panic
+//! in a stage is handled by the action of the stage: the update is skipped by the stage,
+//! written to the quarantine capture, retried a few times, or the client stops processing
+//! and shuts down. Panics are caught, so one malformed update never takes the process down
+//! unless `halt` is configured.
+
+use {
+    crate::{
+        capture::CaptureWriter,
+        stats::{update_kind, update_slot},
+        trace::{self, trace_id},
+    },
+    log::{error, warn},
+    serde::Serialize,
+    std::{
+        env,
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    tokio::sync::watch,
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+const DEFAULT_RETRIES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Decode,
+    Enrich,
+    Sink,
+}
+
+impl Stage {
+    const ALL: [Self; 3] = [Self::Decode, Self::Enrich, Self::Sink];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Decode => "decode",
+            Self::Enrich => "enrich",
+            Self::Sink => "sink",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    /// Log the error, the update does not go further in the stage
+    Skip,
+    /// Like `Skip`, and write the update to `ERROR_QUARANTINE_PATH`
+    Quarantine,
+    /// Run the stage again up to this many times, then skip
+    Retry(u32),
+    /// Stop processing updates and shut down, the client exits with an error
+    Halt,
+}
+
+impl ErrorAction {
+    pub fn from_env_value(key: &str, value: &str) -> anyhow::Result<Self> {
+        match value.split_once(':') {
+            None if value == "skip" => Ok(Self::Skip),
+            None if value == "quarantine" => Ok(Self::Quarantine),
+            None if value == "retry" => Ok(Self::Retry(DEFAULT_RETRIES)),
+            None if value == "halt" => Ok(Self::Halt),
+            Some(("retry", retries)) => retries
+                .parse()
+                .map(Self::Retry)
+                .map_err(|_| anyhow::anyhow!("invalid number of retries in {key}")),
+            _ => anyhow::bail!(
+                "invalid {key} value, expected `skip`, `quarantine`, `retry[:<n>]` or `halt`"
+            ),
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Self::Skip => "skip".to_owned(),
+            Self::Quarantine => "quarantine".to_owned(),
+            Self::Retry(retries) => format!("retry:{retries}"),
+            Self::Halt => "halt".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct StageCounters {
+    /// Updates the stage failed for after all retries
+    failed: AtomicU64,
+    /// Attempts after a failure
+    retries: AtomicU64,
+    /// Failed updates written to the quarantine capture
+    quarantined: AtomicU64,
+}
+
+/// Counters of one stage, shown by the admin API and on exit
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub stage: &'static str,
+    pub action: String,
+    pub failed: u64,
+    pub retries: u64,
+    pub quarantined: u64,
+}
+
+pub struct ErrorPolicy {
+    actions: [ErrorAction; 3],
+    counters: [StageCounters; 3],
+    quarantine: Option<Mutex<CaptureWriter>>,
+    shutdown: Arc<watch::Sender<bool>>,
+    halted: AtomicBool,
+    /// Error which halted the pipeline
+    halt_reason: Mutex<Option<String>>,
+    /// Name updates which failed by their trace ID
+    trace_ids: bool,
+}
+
+impl ErrorPolicy {
+    pub fn from_env(shutdown: Arc<watch::Sender<bool>>) -> anyhow::Result<Self> {
+        let action = |stage: Stage, default: ErrorAction| -> anyhow::Result<ErrorAction> {
+            let key = format!("ERROR_POLICY_{}", stage.name().to_uppercase());
+            match env::var(&key) {
+                Ok(value) => ErrorAction::from_env_value(&key, &value),
+                Err(_) => Ok(default),
+            }
+        };
+        // Decoding and enrichment are deterministic, retrying them rarely helps
+        let actions = [
+            action(Stage::Decode, ErrorAction::Skip)?,
+            action(Stage::Enrich, ErrorAction::Skip)?,
+            action(Stage::Sink, ErrorAction::Retry(DEFAULT_RETRIES))?,
+        ];
+
+        let quarantine = if actions.contains(&ErrorAction::Quarantine) {
+            let path =
+                env::var("ERROR_QUARANTINE_PATH").unwrap_or_else(|_| "quarantine.bin".to_owned());
+            let writer = CaptureWriter::open(&path)
+                .map_err(|error| anyhow::anyhow!("failed to open {path}: {error}"))?;
+            Some(Mutex::new(writer))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            actions,
+            counters: Default::default(),
+            quarantine,
+            shutdown,
+            halted: AtomicBool::new(false),
+            halt_reason: Mutex::default(),
+            trace_ids: trace::enabled(),
+        })
+    }
+
+    /// Run a stage for the update, returns `None` if it failed and the update should not
+    /// go further in the stage. Once the pipeline is halted no stage runs anymore.
+    pub fn run<T>(
+        &self,
+        stage: Stage,
+        msg: &SubscribeUpdate,
+        mut f: impl FnMut() -> anyhow::Result<T>,
+    ) -> Option<T> {
+        if self.halted.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let action = self.actions[stage as usize];
+        let counters = &self.counters[stage as usize];
+        let attempts = match action {
+            ErrorAction::Retry(retries) => retries.saturating_add(1),
+            _ => 1,
+        };
+        let mut last_error = None;
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                counters.retries.fetch_add(1, Ordering::Relaxed);
+            }
+            match catch_unwind(AssertUnwindSafe(&mut f)) {
+                Ok(Ok(value)) => return Some(value),
+                Ok(Err(error)) => last_error = Some(format!("{error:#}")),
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_owned());
+                    last_error = Some(format!("panicked: {message}"));
+                }
+            }
+        }
+
+        counters.failed.fetch_add(1, Ordering::Relaxed);
+        let error = last_error.unwrap_or_default();
+        let mut update = format!(
+            "{} update at slot {}",
+            msg.update_oneof.as_ref().map_or("empty", update_kind),
+            update_slot(msg).map_or_else(|| "none".to_owned(), |slot| slot.to_string())
+        );
+        if let Some(trace) = self.trace_ids.then(|| trace_id(msg)).flatten() {
+            update.push_str(&format!(" (trace {trace})"));
+        }
+        match action {
+            ErrorAction::Skip | ErrorAction::Retry(_) => {
+                warn!("{} failed for {update}, skipped: {error}", stage.name());
+            }
+            ErrorAction::Quarantine => {
+                let result = self
+                    .quarantine
+                    .as_ref()
+                    .expect("opened for quarantine action")
+                    .lock()
+                    .expect("poisoned")
+                    .write(msg);
+                match result {
+                    Ok(()) => {
+                        counters.quarantined.fetch_add(1, Ordering::Relaxed);
+                        warn!("{} failed for {update}, quarantined: {error}", stage.name());
+                    }
+                    Err(write_error) => error!(
+                        "{} failed for {update}: {error}, failed to quarantine: {write_error}",
+                        stage.name()
+                    ),
+                }
+            }
+            ErrorAction::Halt => {
+                if !self.halted.swap(true, Ordering::Relaxed) {
+                    error!("{} failed for {update}, halting: {error}", stage.name());
+                    *self.halt_reason.lock().expect("poisoned") =
+                        Some(format!("{} failed for {update}: {error}", stage.name()));
+                    self.shutdown.send_replace(true);
+                }
+            }
+        }
+        None
+    }
+
+    /// Error which halted the pipeline, `None` if it was not halted
+    pub fn halted(&self) -> Option<String> {
+        self.halt_reason.lock().expect("poisoned").clone()
+    }
+
+    pub fn report(&self) -> Vec<StageReport> {
+        Stage::ALL
+            .into_iter()
+            .map(|stage| {
+                let counters = &self.counters[stage as usize];
+                StageReport {
+                    stage: stage.name(),
+                    action: self.actions[stage as usize].name(),
+                    failed: counters.failed.load(Ordering::Relaxed),
+                    retries: counters.retries.load(Ordering::Relaxed),
+                    quarantined: counters.quarantined.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+
+    /// Write buffered quarantined updates, called once before exit
+    pub fn flush(&self) {
+        if let Some(quarantine) = self.quarantine.as_ref() {
+            if let Err(error) = quarantine.lock().expect("poisoned").flush() {
+                error!("failed to flush quarantine: {error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use {
+    serde::Serialize,
+    std::sync::Mutex,
+    yellowstone_grpc_proto::prelude::{
+        GetBlockHeightResponse, GetLatestBlockhashResponse, GetSlotResponse,
+    },
+};
+
+/// Values received by the last successful poll, `None` until the first one
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PollSnapshot {
+    pub slot: Option<u64>,
+    pub block_height: Option<u64>,
+    pub blockhash: Option<String>,
+    pub blockhash_slot: Option<u64>,
+    pub last_valid_block_height: Option<u64>,
+    pub errors: u64,
+}
+
+/// Latest values of `GetSlot`, `GetBlockHeight` and `GetLatestBlockhash`, shared between
+/// the poller and the admin API
+#[derive(Debug, Default)]
+pub struct PollValues {
+    inner: Mutex<PollSnapshot>,
+}
+
+impl PollValues {
+    pub fn snapshot(&self) -> PollSnapshot {
+        self.inner.lock().expect("poisoned").clone()
+    }
+
+    pub fn update(
+        &self,
+        slot: GetSlotResponse,
+        block_height: GetBlockHeightResponse,
+        blockhash: GetLatestBlockhashResponse,
+    ) -> PollSnapshot {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.slot = Some(slot.slot);
+        inner.block_height = Some(block_height.block_height);
+        inner.blockhash = Some(blockhash.blockhash);
+        inner.blockhash_slot = Some(blockhash.slot);
+        inner.last_valid_block_height = Some(blockhash.last_valid_block_height);
+        inner.clone()
+    }
+
+    pub fn failed(&self) -> PollSnapshot {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.errors += 1;
+        inner.clone()
+    }
+}
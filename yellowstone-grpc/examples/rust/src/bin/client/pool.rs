@@ -0,0 +1,469 @@
+//! Several connections to the same endpoint sharing one subscription, `POOL_CONNECTIONS`.
+//!
+//! A single HTTP/2 connection saturates with very heavy subscriptions, so the named filters
+//! of the request are split round-robin over `POOL_CONNECTIONS` connections to `ENDPOINT`,
+//! each with its own stream, and updates of all streams are merged into the queue. Every
+//! filter has a home connection. When a connection drops, its filters are moved to the live
+//! connections round-robin with a new request on their open streams, when it is back they
+//! move home again. Filters of other connections never move, so a drop only touches the
+//! filters of the dropped connection, and filters of a connection which did not try to
+//! connect yet wait for it. With `POOL_STALE_SECS` a connection without any
+//! message, pings included, for that long is reconnected.
+
+use {
+    log::info,
+    serde::Serialize,
+    std::{
+        env,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    tokio::sync::watch,
+    yellowstone_grpc_proto::prelude::SubscribeRequest,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum FilterKind {
+    Accounts,
+    Slots,
+    Transactions,
+    TransactionsStatus,
+    Blocks,
+    BlocksMeta,
+    Entry,
+}
+
+impl FilterKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Accounts => "accounts",
+            Self::Slots => "slots",
+            Self::Transactions => "transactions",
+            Self::TransactionsStatus => "transactions_status",
+            Self::Blocks => "blocks",
+            Self::BlocksMeta => "blocks_meta",
+            Self::Entry => "entry",
+        }
+    }
+}
+
+/// Names are unique per kind only
+type FilterKey = (FilterKind, String);
+
+fn filter_keys(request: &SubscribeRequest) -> Vec<FilterKey> {
+    let mut keys = [
+        (
+            FilterKind::Accounts,
+            request.accounts.keys().collect::<Vec<_>>(),
+        ),
+        (FilterKind::Slots, request.slots.keys().collect()),
+        (
+            FilterKind::Transactions,
+            request.transactions.keys().collect(),
+        ),
+        (
+            FilterKind::TransactionsStatus,
+            request.transactions_status.keys().collect(),
+        ),
+        (FilterKind::Blocks, request.blocks.keys().collect()),
+        (FilterKind::BlocksMeta, request.blocks_meta.keys().collect()),
+        (FilterKind::Entry, request.entry.keys().collect()),
+    ]
+    .into_iter()
+    .flat_map(|(kind, names)| names.into_iter().map(move |name| (kind, name.clone())))
+    .collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+/// The request with only these filters, commitment and data slices are kept
+fn subset(request: &SubscribeRequest, filters: &[FilterKey]) -> SubscribeRequest {
+    let keep = |kind: FilterKind, name: &String| {
+        filters
+            .iter()
+            .any(|(filter_kind, filter_name)| *filter_kind == kind && filter_name == name)
+    };
+    let mut request = request.clone();
+    request
+        .accounts
+        .retain(|name, _| keep(FilterKind::Accounts, name));
+    request
+        .slots
+        .retain(|name, _| keep(FilterKind::Slots, name));
+    request
+        .transactions
+        .retain(|name, _| keep(FilterKind::Transactions, name));
+    request
+        .transactions_status
+        .retain(|name, _| keep(FilterKind::TransactionsStatus, name));
+    request
+        .blocks
+        .retain(|name, _| keep(FilterKind::Blocks, name));
+    request
+        .blocks_meta
+        .retain(|name, _| keep(FilterKind::BlocksMeta, name));
+    request
+        .entry
+        .retain(|name, _| keep(FilterKind::Entry, name));
+    request.ping = None;
+    request
+}
+
+#[derive(Debug)]
+struct Connection {
+    up: bool,
+    /// The first connect finished, until then the filters wait for the connection instead of
+    /// moving to the first one which is up
+    started: bool,
+    /// Filters the stream is subscribed to, or reopens with while the connection is down
+    filters: Vec<FilterKey>,
+    request: watch::Sender<SubscribeRequest>,
+    received: u64,
+    connects: u64,
+    failures: u64,
+    last_received: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    request: SubscribeRequest,
+    filters: Vec<FilterKey>,
+    connections: Vec<Connection>,
+}
+
+/// Health of one connection
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionReport {
+    pub connection: usize,
+    pub up: bool,
+    /// `kind/name` of the filters served by the connection, or reopened with while it is down
+    pub filters: Vec<String>,
+    pub received: u64,
+    pub connects: u64,
+    pub failures: u64,
+    /// Time since the last message, pings included
+    pub idle_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ConnectionPool {
+    pub connections: usize,
+    pub report_interval: Duration,
+    /// Reconnect a stream without messages for this long
+    pub stale: Option<Duration>,
+    state: Mutex<State>,
+}
+
+impl ConnectionPool {
+    /// Returns `None` if `POOL_CONNECTIONS` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("POOL_CONNECTIONS") else {
+            return Ok(None);
+        };
+        let connections = value
+            .parse::<usize>()
+            .ok()
+            .filter(|connections| *connections >= 2)
+            .ok_or_else(|| anyhow::anyhow!("invalid POOL_CONNECTIONS, expected at least 2"))?;
+        let report_interval = match env::var("POOL_REPORT_SECS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .ok_or_else(|| anyhow::anyhow!("invalid POOL_REPORT_SECS"))?,
+            Err(_) => Duration::from_secs(60),
+        };
+        let stale = env::var("POOL_STALE_SECS")
+            .ok()
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| anyhow::anyhow!("invalid POOL_STALE_SECS"))
+            })
+            .transpose()?;
+        Ok(Some(Self::new(connections, report_interval, stale)))
+    }
+
+    pub fn new(connections: usize, report_interval: Duration, stale: Option<Duration>) -> Self {
+        Self {
+            connections,
+            report_interval,
+            stale,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Split the request over the connections, every connection subscribes with the latest
+    /// request of its receiver and sends a new one when it changes
+    pub fn start(&self, request: SubscribeRequest) -> Vec<watch::Receiver<SubscribeRequest>> {
+        let filters = filter_keys(&request);
+        let mut state = self.state.lock().expect("poisoned");
+        let mut receivers = Vec::with_capacity(self.connections);
+        state.connections = (0..self.connections)
+            .map(|index| {
+                let home = self.home(&filters, index);
+                let (tx, rx) = watch::channel(subset(&request, &home));
+                receivers.push(rx);
+                Connection {
+                    up: false,
+                    started: false,
+                    filters: home,
+                    request: tx,
+                    received: 0,
+                    connects: 0,
+                    failures: 0,
+                    last_received: None,
+                }
+            })
+            .collect();
+        state.request = request;
+        state.filters = filters;
+        receivers
+    }
+
+    /// Filters which belong to the connection while it is up
+    fn home(&self, filters: &[FilterKey], index: usize) -> Vec<FilterKey> {
+        filters
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| position % self.connections == index)
+            .map(|(_, filter)| filter.clone())
+            .collect()
+    }
+
+    /// Filters of dropped connections go to the live connections round-robin, down
+    /// connections keep their own filters to reopen with
+    fn rebalance(&self, state: &mut State) {
+        let live = (0..self.connections)
+            .filter(|index| state.connections[*index].up)
+            .collect::<Vec<_>>();
+        let mut assigned = vec![vec![]; self.connections];
+        let mut moved = 0;
+        for (position, filter) in state.filters.iter().enumerate() {
+            let home = position % self.connections;
+            let dropped = !state.connections[home].up && state.connections[home].started;
+            if !dropped || live.is_empty() {
+                assigned[home].push(filter.clone());
+            } else {
+                assigned[live[moved % live.len()]].push(filter.clone());
+                moved += 1;
+            }
+        }
+        for (index, filters) in assigned.into_iter().enumerate() {
+            let filters = if state.connections[index].up {
+                filters
+            } else {
+                self.home(&state.filters, index)
+            };
+            if state.connections[index].filters == filters {
+                continue;
+            }
+            if state.connections[index].up {
+                info!(
+                    "pool: connection {index} now serves {} filters",
+                    filters.len()
+                );
+            }
+            let request = subset(&state.request, &filters);
+            let connection = &mut state.connections[index];
+            connection.request.send_replace(request);
+            connection.filters = filters;
+        }
+    }
+
+    /// The stream of the connection is open
+    pub fn connected(&self, index: usize) {
+        let mut state = self.state.lock().expect("poisoned");
+        let connection = &mut state.connections[index];
+        connection.up = true;
+        connection.started = true;
+        connection.connects += 1;
+        connection.last_received = Some(Instant::now());
+        self.rebalance(&mut state);
+    }
+
+    /// The stream of the connection closed or failed, its filters move to the other ones
+    pub fn disconnected(&self, index: usize, failed: bool) {
+        let mut state = self.state.lock().expect("poisoned");
+        let connection = &mut state.connections[index];
+        if failed {
+            connection.failures += 1;
+        }
+        if connection.up || !connection.started {
+            connection.up = false;
+            connection.started = true;
+            self.rebalance(&mut state);
+        }
+    }
+
+    pub fn received(&self, index: usize) {
+        let mut state = self.state.lock().expect("poisoned");
+        let connection = &mut state.connections[index];
+        connection.received += 1;
+        connection.last_received = Some(Instant::now());
+    }
+
+    pub fn report(&self) -> Vec<ConnectionReport> {
+        let state = self.state.lock().expect("poisoned");
+        state
+            .connections
+            .iter()
+            .enumerate()
+            .map(|(index, connection)| ConnectionReport {
+                connection: index,
+                up: connection.up,
+                filters: connection
+                    .filters
+                    .iter()
+                    .map(|(kind, name)| format!("{}/{name}", kind.as_str()))
+                    .collect(),
+                received: connection.received,
+                connects: connection.connects,
+                failures: connection.failures,
+                idle_ms: connection
+                    .last_received
+                    .map(|last| last.elapsed().as_millis() as u64),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
+            SubscribeRequestFilterTransactions, SubscribeRequestPing,
+        },
+    };
+
+    /// Filters `accounts/a1`, `accounts/a2`, `slots/s1`, `transactions/t1` to `t3`
+    fn request() -> SubscribeRequest {
+        SubscribeRequest {
+            accounts: ["a1", "a2"]
+                .map(|name| (name.to_owned(), SubscribeRequestFilterAccounts::default()))
+                .into(),
+            slots: [("s1".to_owned(), SubscribeRequestFilterSlots::default())].into(),
+            transactions: ["t1", "t2", "t3"]
+                .map(|name| {
+                    (
+                        name.to_owned(),
+                        SubscribeRequestFilterTransactions::default(),
+                    )
+                })
+                .into(),
+            commitment: Some(CommitmentLevel::Confirmed as i32),
+            ping: Some(SubscribeRequestPing { id: 1 }),
+            ..Default::default()
+        }
+    }
+
+    fn names(request: &SubscribeRequest) -> Vec<String> {
+        filter_keys(request)
+            .into_iter()
+            .map(|(kind, name)| format!("{}/{name}", kind.as_str()))
+            .collect()
+    }
+
+    fn filters(pool: &ConnectionPool) -> Vec<Vec<String>> {
+        pool.report()
+            .into_iter()
+            .map(|report| report.filters)
+            .collect()
+    }
+
+    #[test]
+    fn splits_filters_round_robin() {
+        let pool = ConnectionPool::new(3, Duration::from_secs(60), None);
+        let receivers = pool.start(request());
+        assert_eq!(
+            filters(&pool),
+            [
+                ["accounts/a1", "transactions/t1"],
+                ["accounts/a2", "transactions/t2"],
+                ["slots/s1", "transactions/t3"],
+            ]
+        );
+        let first = receivers[0].borrow();
+        assert_eq!(names(&first), ["accounts/a1", "transactions/t1"]);
+        assert_eq!(first.commitment, Some(CommitmentLevel::Confirmed as i32));
+        assert_eq!(first.ping, None);
+    }
+
+    #[test]
+    fn filters_of_dropped_connections_move_and_return_home() {
+        let pool = ConnectionPool::new(3, Duration::from_secs(60), None);
+        let mut receivers = pool.start(request());
+        let home = filters(&pool);
+
+        // Connection 1 did not try to connect yet, its filters wait for it
+        pool.connected(0);
+        pool.connected(2);
+        assert_eq!(filters(&pool), home);
+        assert!(!receivers[0].has_changed().unwrap());
+
+        // Its first connect failed, the filters move to the live connections round-robin
+        pool.disconnected(1, true);
+        assert_eq!(
+            filters(&pool),
+            [
+                vec!["accounts/a1", "accounts/a2", "transactions/t1"],
+                vec!["accounts/a2", "transactions/t2"],
+                vec!["slots/s1", "transactions/t2", "transactions/t3"],
+            ]
+        );
+        assert_eq!(
+            names(&receivers[0].borrow_and_update()),
+            ["accounts/a1", "accounts/a2", "transactions/t1"]
+        );
+        assert!(!receivers[1].has_changed().unwrap());
+
+        pool.connected(1);
+        assert_eq!(filters(&pool), home);
+        assert_eq!(
+            names(&receivers[0].borrow_and_update()),
+            ["accounts/a1", "transactions/t1"]
+        );
+
+        // Filters of the other connections never move
+        pool.disconnected(0, false);
+        assert_eq!(
+            filters(&pool),
+            [
+                vec!["accounts/a1", "transactions/t1"],
+                vec!["accounts/a1", "accounts/a2", "transactions/t2"],
+                vec!["slots/s1", "transactions/t1", "transactions/t3"],
+            ]
+        );
+        assert!(!receivers[0].has_changed().unwrap());
+    }
+
+    #[test]
+    fn filters_stay_home_without_live_connections() {
+        let pool = ConnectionPool::new(2, Duration::from_secs(60), None);
+        pool.start(request());
+        let home = filters(&pool);
+        pool.connected(0);
+        pool.connected(1);
+        pool.disconnected(0, true);
+        assert_eq!(filters(&pool)[1].len(), 6);
+
+        pool.disconnected(1, true);
+        assert_eq!(filters(&pool), home);
+        // A connection which is already down is counted but not rebalanced again
+        pool.disconnected(1, true);
+        let report = pool.report();
+        assert_eq!(
+            report
+                .iter()
+                .map(|report| (report.up, report.connects, report.failures))
+                .collect::<Vec<_>>(),
+            [(false, 1, 1), (false, 1, 2)]
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Latency-critical filters, `PRIORITY_FILTERS`.
+//!
+//! Updates matched by one of the listed filters take a fast lane: they are handled right on
+//! the stream task instead of waiting in the queue behind bulk traffic, are never sampled,
+//! held by `HOLD_UNTIL` or coalesced, and reach the sinks listed in `PRIORITY_SINKS` before
+//! any other sink. Slot updates always take the normal path, they release held updates.
+
+use {
+    std::{
+        collections::HashSet,
+        env,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+#[derive(Debug)]
+pub struct PriorityLanes {
+    filters: HashSet<String>,
+    /// Sink names, in the order they receive updates before other sinks
+    sinks: Vec<String>,
+    updates: AtomicU64,
+}
+
+impl PriorityLanes {
+    /// Returns `None` if `PRIORITY_FILTERS` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let list = |value: String| {
+            value
+                .split(',')
+                .map(|name| name.trim().to_owned())
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let Ok(filters) = env::var("PRIORITY_FILTERS") else {
+            return Ok(None);
+        };
+        let filters = list(filters).into_iter().collect::<HashSet<_>>();
+        anyhow::ensure!(!filters.is_empty(), "invalid PRIORITY_FILTERS");
+        Ok(Some(Self {
+            filters,
+            sinks: list(env::var("PRIORITY_SINKS").unwrap_or_else(|_| "socket".to_owned())),
+            updates: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn sinks(&self) -> &[String] {
+        &self.sinks
+    }
+
+    /// Updates which took the fast lane
+    pub fn updates(&self) -> u64 {
+        self.updates.load(Ordering::Relaxed)
+    }
+
+    /// The update takes the fast lane
+    pub fn matches(&self, msg: &SubscribeUpdate) -> bool {
+        !matches!(msg.update_oneof, Some(UpdateOneof::Slot(_)) | None)
+            && msg.filters.iter().any(|name| self.filters.contains(name))
+    }
+
+    /// Count an update handled in the fast lane
+    pub fn observe(&self) {
+        self.updates.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,184 @@
+//! Named sets of variables, `PROFILE`.
+//!
+//! Switching between devnet, mainnet and providers changes the endpoint, the token, the
+//! commitment and the filters together. A profile bundles them under a name and
+//! `PROFILE=<name>` selects it. Profiles are the tables of `PROFILES_PATH` (`profiles.toml` by
+//! default) or, if it is a directory, its `<name>.toml` files. Keys are variable names, numbers
+//! and booleans are converted to strings. A profile overrides `.env` and is overridden by the
+//! process environment. `ACTION=ListProfiles` prints the available profiles.
+
+use {
+    crate::config::redact,
+    serde_json::{json, Map, Value},
+    std::{
+        collections::BTreeMap,
+        env, fs,
+        path::{Path, PathBuf},
+    },
+};
+
+const DEFAULT_PATH: &str = "profiles.toml";
+
+#[derive(Debug)]
+pub struct Profile {
+    pub name: String,
+    /// File the profile was read from
+    pub path: PathBuf,
+    pub vars: BTreeMap<String, String>,
+}
+
+fn profiles_path() -> PathBuf {
+    env::var_os("PROFILES_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_PATH))
+}
+
+fn read_table(path: &Path) -> anyhow::Result<toml::value::Table> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| anyhow::anyhow!("invalid PROFILES_PATH {}: {error}", path.display()))?;
+    toml::from_str(&content)
+        .map_err(|error| anyhow::anyhow!("invalid PROFILES_PATH {}: {error}", path.display()))
+}
+
+fn to_vars(
+    name: &str,
+    path: &Path,
+    table: toml::value::Table,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            anyhow::ensure!(
+                !key.is_empty() && !key.contains(['=', '\0']),
+                "invalid PROFILES_PATH {}: `{key}` of profile `{name}` is not a variable name",
+                path.display()
+            );
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => anyhow::bail!(
+                    "invalid PROFILES_PATH {}: `{key}` of profile `{name}` is not a string, number or boolean",
+                    path.display()
+                ),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// Profiles by name, none if the default `PROFILES_PATH` does not exist
+pub fn load_all() -> anyhow::Result<BTreeMap<String, Profile>> {
+    let path = profiles_path();
+    let mut profiles = BTreeMap::new();
+    if path.is_dir() {
+        let mut files = fs::read_dir(&path)
+            .map_err(|error| anyhow::anyhow!("invalid PROFILES_PATH {}: {error}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| {
+                file.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        for file in files {
+            let Some(name) = file
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            let vars = to_vars(&name, &file, read_table(&file)?)?;
+            profiles.insert(
+                name.clone(),
+                Profile {
+                    name,
+                    path: file,
+                    vars,
+                },
+            );
+        }
+    } else if path.exists() || env::var_os("PROFILES_PATH").is_some() {
+        for (name, value) in read_table(&path)? {
+            let toml::Value::Table(table) = value else {
+                anyhow::bail!(
+                    "invalid PROFILES_PATH {}: `{name}` is not a table of variables",
+                    path.display()
+                );
+            };
+            let vars = to_vars(&name, &path, table)?;
+            profiles.insert(
+                name.clone(),
+                Profile {
+                    name,
+                    path: path.clone(),
+                    vars,
+                },
+            );
+        }
+    }
+    Ok(profiles)
+}
+
+/// Profile selected with `PROFILE`
+pub fn selected() -> anyhow::Result<Option<Profile>> {
+    let Ok(name) = env::var("PROFILE") else {
+        return Ok(None);
+    };
+    let mut profiles = load_all()?;
+    match profiles.remove(&name) {
+        Some(profile) => Ok(Some(profile)),
+        None if profiles.is_empty() => anyhow::bail!(
+            "invalid PROFILE {name}, no profiles in {}",
+            profiles_path().display()
+        ),
+        None => anyhow::bail!(
+            "invalid PROFILE {name}, expected one of: {}",
+            profiles.into_keys().collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Print the profiles with their redacted variables to stdout, or as one JSON object with
+/// `OUTPUT=json`
+pub fn list() -> anyhow::Result<()> {
+    let profiles = load_all()?;
+    let active = env::var("PROFILE").ok();
+    if env::var("OUTPUT").as_deref() == Ok("json") {
+        let object = profiles
+            .into_values()
+            .map(|profile| {
+                let vars = profile
+                    .vars
+                    .iter()
+                    .map(|(key, value)| (key.clone(), json!(redact(key, value))))
+                    .collect::<Map<String, Value>>();
+                let value = json!({
+                    "path": profile.path.display().to_string(),
+                    "active": active.as_deref() == Some(profile.name.as_str()),
+                    "variables": vars,
+                });
+                (profile.name, value)
+            })
+            .collect::<Map<String, Value>>();
+        println!("{}", Value::Object(object));
+        return Ok(());
+    }
+
+    if profiles.is_empty() {
+        eprintln!("no profiles in {}", profiles_path().display());
+    }
+    for profile in profiles.values() {
+        let marker = if active.as_deref() == Some(profile.name.as_str()) {
+            ", active"
+        } else {
+            ""
+        };
+        println!("{}  # {}{marker}", profile.name, profile.path.display());
+        for (key, value) in profile.vars.iter() {
+            println!("  {key}={}", redact(key, value));
+        }
+    }
+    Ok(())
+}
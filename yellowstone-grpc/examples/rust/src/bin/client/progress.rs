@@ -0,0 +1,322 @@
+//! Progress of processed updates saved across restarts, `PROGRESS_PATH`.
+//!
+//! The checkpoint tells up to which slot sinks confirmed updates, progress tells what the
+//! client itself processed, with or without sinks: the highest slot of every update type and
+//! the signatures of the last `PROGRESS_SIGNATURES` transactions and transaction statuses. It
+//! is saved every `PROGRESS_INTERVAL_MS` and on exit, as JSON to a file or, with
+//! `PROGRESS_PATH=redis://host:port/db/key`, to a Redis key.
+//!
+//! On startup the saved progress is read back. Its signatures seed `DEDUP_CAPACITY`
+//! deduplication, so transactions processed before the restart and delivered again are
+//! dropped. The first update of every type is compared with the saved slot of the type, the
+//! slots in between were not received and are reported as a `progress_gap` event: after a
+//! crash it tells how much is missing.
+
+#[cfg(feature = "redis")]
+use crate::sink::redis::RedisKey;
+use {
+    crate::{
+        dedup::DedupCache,
+        output::OutputFormat,
+        stats::{update_kind, update_slot},
+    },
+    chrono::Utc,
+    log::{info, warn},
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    std::{
+        collections::{BTreeMap, HashSet, VecDeque},
+        env, fs, io,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_INTERVAL_MS: u64 = 1_000;
+const DEFAULT_SIGNATURES: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedSignature {
+    /// Base58
+    signature: String,
+    slot: u64,
+}
+
+/// Content of `PROGRESS_PATH`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ProgressState {
+    /// Highest processed slot by update type
+    slots: BTreeMap<String, u64>,
+    /// Last signatures, the newest last
+    transactions: VecDeque<SavedSignature>,
+    transaction_statuses: VecDeque<SavedSignature>,
+    /// Updates processed by the run which saved it
+    updates: u64,
+    saved_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProgressReport {
+    pub slots: BTreeMap<String, u64>,
+    /// Slots missing between the previous run and this one by update type
+    pub gaps: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Starts with the slots and signatures of the previous run, types which are not
+    /// received again keep their slot
+    saved: ProgressState,
+    /// Update types received by this run
+    seen: HashSet<&'static str>,
+}
+
+enum Store {
+    File(PathBuf),
+    #[cfg(feature = "redis")]
+    Redis(Box<RedisKey>),
+}
+
+impl Store {
+    fn name(&self) -> String {
+        match self {
+            Self::File(path) => path.display().to_string(),
+            #[cfg(feature = "redis")]
+            Self::Redis(key) => format!("redis key {}", key.name()),
+        }
+    }
+
+    async fn load(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            Self::File(path) => match fs::read(path) {
+                Ok(content) => Ok(Some(content)),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(error) => anyhow::bail!("failed to read {}: {error}", path.display()),
+            },
+            #[cfg(feature = "redis")]
+            Self::Redis(key) => key.get().await,
+        }
+    }
+
+    async fn save(&self, content: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Self::File(path) => {
+                // Replace the file at once, a crash never leaves partial progress
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, content).and_then(|()| fs::rename(&tmp, path))?;
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis(key) => key.set(content).await,
+        }
+    }
+}
+
+pub struct Progress {
+    store: Store,
+    interval: Duration,
+    max_signatures: usize,
+    output: OutputFormat,
+    /// Saved by the previous run
+    previous: ProgressState,
+    state: Mutex<State>,
+    gaps: Mutex<BTreeMap<String, u64>>,
+    /// Changed since the last save
+    dirty: AtomicBool,
+}
+
+impl Progress {
+    /// Returns `None` if `PROGRESS_PATH` is not set, reads the progress of the previous run
+    pub async fn from_env(output: OutputFormat) -> anyhow::Result<Option<Self>> {
+        let Ok(path) = env::var("PROGRESS_PATH") else {
+            return Ok(None);
+        };
+        let store = if path.starts_with("redis://") {
+            #[cfg(feature = "redis")]
+            {
+                Store::Redis(Box::new(RedisKey::from_url("PROGRESS_PATH", &path)?))
+            }
+            #[cfg(not(feature = "redis"))]
+            anyhow::bail!("invalid PROGRESS_PATH, Redis needs the `redis` feature")
+        } else {
+            Store::File(PathBuf::from(path))
+        };
+        let interval = match env::var("PROGRESS_INTERVAL_MS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid PROGRESS_INTERVAL_MS"))?,
+            Err(_) => DEFAULT_INTERVAL_MS,
+        };
+        let max_signatures = match env::var("PROGRESS_SIGNATURES") {
+            Ok(value) => value
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("invalid PROGRESS_SIGNATURES"))?,
+            Err(_) => DEFAULT_SIGNATURES,
+        };
+
+        let previous = match store.load().await? {
+            Some(content) => {
+                serde_json::from_slice::<ProgressState>(&content).map_err(|error| {
+                    anyhow::anyhow!("invalid progress in {}: {error}", store.name())
+                })?
+            }
+            None => ProgressState::default(),
+        };
+        match previous.saved_at.as_ref() {
+            Some(saved_at) => info!(
+                "progress of the previous run from {}, saved at {saved_at}: {} updates, slots {:?}",
+                store.name(),
+                previous.updates,
+                previous.slots
+            ),
+            None => info!("progress: no previous run in {}", store.name()),
+        }
+
+        Ok(Some(Self {
+            store,
+            interval: Duration::from_millis(interval),
+            max_signatures,
+            output,
+            state: Mutex::new(State {
+                saved: ProgressState {
+                    updates: 0,
+                    saved_at: None,
+                    ..previous.clone()
+                },
+                seen: HashSet::new(),
+            }),
+            previous,
+            gaps: Mutex::default(),
+            dirty: AtomicBool::new(false),
+        }))
+    }
+
+    pub const fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Remember signatures of the previous run as already seen
+    pub fn seed(&self, dedup: &DedupCache) {
+        let mut seeded = 0;
+        for (saved, status) in self
+            .previous
+            .transactions
+            .iter()
+            .map(|saved| (saved, false))
+            .chain(
+                self.previous
+                    .transaction_statuses
+                    .iter()
+                    .map(|saved| (saved, true)),
+            )
+        {
+            if let Ok(signature) = bs58::decode(&saved.signature).into_vec() {
+                dedup.seed_signature(signature, saved.slot, status);
+                seeded += 1;
+            }
+        }
+        if seeded > 0 {
+            info!("progress: {seeded} signatures of the previous run seeded deduplication");
+        }
+    }
+
+    /// Record a processed update, the first one of a type is checked for a gap
+    pub fn observe(&self, msg: &SubscribeUpdate) {
+        let (Some(update), Some(slot)) = (msg.update_oneof.as_ref(), update_slot(msg)) else {
+            return;
+        };
+        let kind = update_kind(update);
+        let signature = match update {
+            UpdateOneof::Transaction(update) => {
+                update.transaction.as_ref().map(|tx| (&tx.signature, false))
+            }
+            UpdateOneof::TransactionStatus(update) => Some((&update.signature, true)),
+            _ => None,
+        };
+
+        let mut state = self.state.lock().expect("poisoned");
+        let first = state.seen.insert(kind);
+        let current = &mut state.saved;
+        current.updates += 1;
+        let highest = current.slots.entry(kind.to_owned()).or_insert(slot);
+        *highest = (*highest).max(slot);
+        if let Some((signature, status)) = signature.filter(|_| self.max_signatures > 0) {
+            let signatures = if status {
+                &mut current.transaction_statuses
+            } else {
+                &mut current.transactions
+            };
+            signatures.push_back(SavedSignature {
+                signature: bs58::encode(signature).into_string(),
+                slot,
+            });
+            while signatures.len() > self.max_signatures {
+                signatures.pop_front();
+            }
+        }
+        drop(state);
+        self.dirty.store(true, Ordering::Relaxed);
+
+        if !first {
+            return;
+        }
+        let Some(previous) = self.previous.slots.get(kind).copied() else {
+            return;
+        };
+        if slot > previous + 1 {
+            let missed = slot - previous - 1;
+            self.gaps
+                .lock()
+                .expect("poisoned")
+                .insert(kind.to_owned(), missed);
+            warn!(
+                "progress: {kind} updates resume at slot {slot}, the previous run processed up to {previous}, {missed} slots in between were not received"
+            );
+            self.output.print_event(
+                "progress_gap",
+                &json!({
+                    "kind": kind,
+                    "previous_slot": previous,
+                    "slot": slot,
+                    "missed_slots": missed,
+                }),
+            );
+        }
+    }
+
+    /// Save the progress if it changed since the last save
+    pub async fn save(&self) {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let content = {
+            let mut state = self.state.lock().expect("poisoned");
+            state.saved.saved_at = Some(Utc::now().to_rfc3339());
+            serde_json::to_vec(&state.saved)
+        };
+        let result = match content {
+            Ok(content) => self.store.save(&content).await,
+            Err(error) => Err(error.into()),
+        };
+        if let Err(error) = result {
+            warn!("failed to save progress to {}: {error}", self.store.name());
+            // Retry on the next save
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn report(&self) -> ProgressReport {
+        ProgressReport {
+            slots: self.state.lock().expect("poisoned").saved.slots.clone(),
+            gaps: self.gaps.lock().expect("poisoned").clone(),
+        }
+    }
+}
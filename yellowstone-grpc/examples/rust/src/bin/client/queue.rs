@@ -0,0 +1,302 @@
+use {
+    crate::budget::MemoryBudget,
+    log::warn,
+    std::{
+        collections::VecDeque,
+        pin::pin,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex, MutexGuard,
+        },
+    },
+    tokio::{sync::Notify, time::Instant},
+    yellowstone_grpc_proto::{prelude::SubscribeUpdate, prost::Message},
+};
+
+/// What to do with a new message when the queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for free space, stream reading is paused
+    #[default]
+    Block,
+    /// Remove the oldest queued message
+    DropOldest,
+    /// Discard the new message
+    DropNewest,
+}
+
+impl OverflowPolicy {
+    pub fn from_env_value(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "block" => Ok(Self::Block),
+            "drop-oldest" => Ok(Self::DropOldest),
+            "drop-newest" => Ok(Self::DropNewest),
+            _ => anyhow::bail!(
+                "invalid QUEUE_OVERFLOW value, expected `block`, `drop-oldest` or `drop-newest`"
+            ),
+        }
+    }
+}
+
+/// Queued message with its size taken from the memory budget and the time it was pushed
+type Queued = (SubscribeUpdate, u64, Instant);
+
+/// Bounded queue between the stream reader and processing workers, messages are queued
+/// with their size taken from the memory budget and the time they were pushed.
+///
+/// The lock is only held to move a message in or out, waiting for a message or for free
+/// space happens outside of it, so any number of workers can wait in `pop` at the same time.
+pub struct UpdateQueue {
+    state: Mutex<QueueState>,
+    /// Notified when a message is pushed or the queue is closed
+    available: Notify,
+    /// Notified when a message is popped or the queue is closed
+    space: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+    budget: Option<Arc<MemoryBudget>>,
+    dropped: AtomicU64,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<Queued>,
+    closed: bool,
+}
+
+impl UpdateQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy, budget: Option<Arc<MemoryBudget>>) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            available: Notify::new(),
+            space: Notify::new(),
+            capacity,
+            policy,
+            budget,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn push(&self, msg: SubscribeUpdate) {
+        if self.lock().closed {
+            return;
+        }
+        let bytes = self.budget.as_ref().map_or(0, |_| msg.encoded_len() as u64);
+        // Before waiting for space, which is part of the latency
+        let pushed = Instant::now();
+
+        match self.policy {
+            OverflowPolicy::Block => {
+                if let Some(budget) = self.budget.as_ref() {
+                    budget.reserve(bytes, "queue", || self.depth() == 0).await;
+                }
+                loop {
+                    // Registered before the check, a pop in between is not missed
+                    let mut space = pin!(self.space.notified());
+                    space.as_mut().enable();
+                    {
+                        let mut state = self.lock();
+                        if state.closed {
+                            break self.release(bytes);
+                        }
+                        if state.messages.len() < self.capacity {
+                            state.messages.push_back((msg, bytes, pushed));
+                            break self.available.notify_one();
+                        }
+                    }
+                    space.await;
+                }
+            }
+            OverflowPolicy::DropNewest => {
+                if !self.try_reserve(bytes) {
+                    self.on_drop();
+                    return;
+                }
+                let mut state = self.lock();
+                if state.closed {
+                    self.release(bytes);
+                } else if state.messages.len() < self.capacity {
+                    state.messages.push_back((msg, bytes, pushed));
+                    self.available.notify_one();
+                } else {
+                    self.release(bytes);
+                    self.on_drop();
+                }
+            }
+            OverflowPolicy::DropOldest => {
+                while !self.try_reserve(bytes) {
+                    self.drop_oldest(&mut self.lock());
+                }
+                let mut state = self.lock();
+                if state.closed {
+                    self.release(bytes);
+                    return;
+                }
+                if state.messages.len() >= self.capacity {
+                    self.drop_oldest(&mut state);
+                }
+                state.messages.push_back((msg, bytes, pushed));
+                self.available.notify_one();
+            }
+        }
+    }
+
+    /// Next message with the time it was pushed, `None` once the queue is closed and empty
+    pub async fn pop(&self) -> Option<(SubscribeUpdate, Instant)> {
+        loop {
+            // Registered before the check, a push in between is not missed
+            let mut available = pin!(self.available.notified());
+            available.as_mut().enable();
+            {
+                let mut state = self.lock();
+                if let Some((msg, bytes, pushed)) = state.messages.pop_front() {
+                    drop(state);
+                    self.space.notify_one();
+                    self.release(bytes);
+                    return Some((msg, pushed));
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            available.await;
+        }
+    }
+
+    /// Stop accepting new messages, already queued messages are still returned by `pop`
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.available.notify_waiters();
+        self.space.notify_waiters();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.lock().messages.len()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().expect("poisoned")
+    }
+
+    fn drop_oldest(&self, state: &mut QueueState) {
+        if let Some((_, oldest, _)) = state.messages.pop_front() {
+            self.release(oldest);
+            self.on_drop();
+        }
+    }
+
+    fn try_reserve(&self, bytes: u64) -> bool {
+        match self.budget.as_ref() {
+            Some(budget) if !budget.try_reserve(bytes, self.depth() == 0) => {
+                budget.full(bytes, "queue");
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn release(&self, bytes: u64) {
+        if let Some(budget) = self.budget.as_ref() {
+            budget.release(bytes);
+        }
+    }
+
+    fn on_drop(&self) {
+        let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        if dropped % 10_000 == 1 {
+            warn!("queue is full, {dropped} messages dropped in total");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::time::Duration,
+        yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdateSlot},
+    };
+
+    fn slot(slot: u64) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["slots".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: None,
+                status: 0,
+            })),
+        }
+    }
+
+    async fn pop_slot(queue: &UpdateQueue) -> Option<u64> {
+        match queue.pop().await?.0.update_oneof {
+            Some(UpdateOneof::Slot(update)) => Some(update.slot),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_policies_keep_capacity() {
+        let oldest = UpdateQueue::new(2, OverflowPolicy::DropOldest, None);
+        let newest = UpdateQueue::new(2, OverflowPolicy::DropNewest, None);
+        for queue in [&oldest, &newest] {
+            for n in 1..=3 {
+                queue.push(slot(n)).await;
+            }
+            assert_eq!((queue.depth(), queue.dropped()), (2, 1));
+            queue.close();
+        }
+        assert_eq!(pop_slot(&oldest).await, Some(2));
+        assert_eq!(pop_slot(&oldest).await, Some(3));
+        assert_eq!(pop_slot(&newest).await, Some(1));
+        assert_eq!(pop_slot(&newest).await, Some(2));
+        assert_eq!(pop_slot(&newest).await, None);
+    }
+
+    #[tokio::test]
+    async fn workers_wait_concurrently() {
+        let queue = Arc::new(UpdateQueue::new(1, OverflowPolicy::Block, None));
+        let workers = (0..2)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                tokio::spawn(async move { pop_slot(&queue).await })
+            })
+            .collect::<Vec<_>>();
+        // Both workers wait in `pop`, none of them blocks the other or the pushes
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.push(slot(1)).await;
+        queue.push(slot(2)).await;
+        let mut popped = Vec::new();
+        for worker in workers {
+            popped.push(worker.await.unwrap());
+        }
+        popped.sort();
+        assert_eq!(popped, [Some(1), Some(2)]);
+    }
+
+    #[tokio::test]
+    async fn close_wakes_blocked_push_and_pop() {
+        let queue = Arc::new(UpdateQueue::new(1, OverflowPolicy::Block, None));
+        queue.push(slot(1)).await;
+        let pusher = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move { queue.push(slot(2)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.close();
+        pusher.await.unwrap();
+        assert_eq!(pop_slot(&queue).await, Some(1));
+        assert_eq!(pop_slot(&queue).await, None);
+    }
+}
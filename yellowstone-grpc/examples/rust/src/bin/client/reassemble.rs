@@ -0,0 +1,516 @@
+//! Full blocks from separate account, transaction and entry updates, `REASSEMBLE_BLOCKS=true`.
+//!
+//! Subscribing to full blocks sends every block whole, while subscribing to transactions,
+//! accounts and entries separately allows filters. With `REASSEMBLE_BLOCKS=true` account,
+//! transaction and entry updates are grouped by slot and the block meta update of the slot
+//! completes them: once the number of received transactions reaches its
+//! `executed_transaction_count` (and entries its `entries_count` with
+//! `REASSEMBLE_WAIT=transactions,entries`), a single block update is passed on instead of
+//! the parts. Filtered subscriptions never receive every transaction of a slot, their blocks
+//! are passed on incomplete `REASSEMBLE_TIMEOUT_MS` after the block meta. At most
+//! `REASSEMBLE_MAX_SLOTS` slots wait for their block meta, the oldest are dropped beyond it.
+
+use {
+    log::warn,
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    },
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateAccountInfo,
+        SubscribeUpdateBlock, SubscribeUpdateBlockMeta, SubscribeUpdateEntry,
+        SubscribeUpdateTransactionInfo,
+    },
+};
+
+const DEFAULT_TIMEOUT_MS: u64 = 2_000;
+const DEFAULT_MAX_SLOTS: usize = 100;
+/// Interval of checks for blocks which waited `REASSEMBLE_TIMEOUT_MS`
+pub const REASSEMBLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Parts of a slot received so far
+#[derive(Debug, Default)]
+struct Parts {
+    filters: BTreeSet<String>,
+    meta: Option<SubscribeUpdateBlockMeta>,
+    /// Block meta received at
+    meta_at: Option<Instant>,
+    transactions: Vec<SubscribeUpdateTransactionInfo>,
+    /// Latest write of every account by pubkey
+    accounts: HashMap<Vec<u8>, SubscribeUpdateAccountInfo>,
+    entries: Vec<SubscribeUpdateEntry>,
+}
+
+impl Parts {
+    fn into_block(mut self) -> SubscribeUpdate {
+        let meta = self.meta.unwrap_or_default();
+        self.transactions
+            .sort_by_key(|transaction| transaction.index);
+        self.entries.sort_by_key(|entry| entry.index);
+        let mut accounts = self.accounts.into_values().collect::<Vec<_>>();
+        accounts.sort_by_key(|account| account.write_version);
+        SubscribeUpdate {
+            filters: self.filters.into_iter().collect(),
+            update_oneof: Some(UpdateOneof::Block(SubscribeUpdateBlock {
+                slot: meta.slot,
+                blockhash: meta.blockhash,
+                rewards: meta.rewards,
+                block_time: meta.block_time,
+                block_height: meta.block_height,
+                parent_slot: meta.parent_slot,
+                parent_blockhash: meta.parent_blockhash,
+                executed_transaction_count: meta.executed_transaction_count,
+                transactions: self.transactions,
+                updated_account_count: accounts.len() as u64,
+                accounts,
+                entries_count: meta.entries_count,
+                entries: self.entries,
+            })),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AssemblerState {
+    slots: BTreeMap<u64, Parts>,
+    /// Slots passed on, their late parts are dropped
+    emitted: BTreeSet<u64>,
+}
+
+#[derive(Debug)]
+pub struct BlockAssembler {
+    /// Wait for every transaction of the block meta
+    wait_transactions: bool,
+    /// Wait for every entry of the block meta
+    wait_entries: bool,
+    timeout: Duration,
+    max_slots: usize,
+    state: Mutex<AssemblerState>,
+    assembled: AtomicU64,
+    incomplete: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl BlockAssembler {
+    /// Returns `None` unless `REASSEMBLE_BLOCKS=true`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match env::var("REASSEMBLE_BLOCKS").as_deref() {
+            Ok("true") => {}
+            Ok("false") | Err(_) => return Ok(None),
+            Ok(_) => anyhow::bail!("invalid REASSEMBLE_BLOCKS, expected `true` or `false`"),
+        }
+        let (mut wait_transactions, mut wait_entries) = (true, false);
+        if let Ok(value) = env::var("REASSEMBLE_WAIT") {
+            wait_transactions = false;
+            for part in value.split(',').map(str::trim) {
+                match part {
+                    "transactions" => wait_transactions = true,
+                    "entries" => wait_entries = true,
+                    "none" | "" => {}
+                    _ => anyhow::bail!(
+                        "invalid REASSEMBLE_WAIT: {part}, expected `transactions`, `entries` or `none`"
+                    ),
+                }
+            }
+        }
+        let timeout = match env::var("REASSEMBLE_TIMEOUT_MS") {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid REASSEMBLE_TIMEOUT_MS"))?,
+            Err(_) => DEFAULT_TIMEOUT_MS,
+        };
+        let max_slots = match env::var("REASSEMBLE_MAX_SLOTS") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|max_slots| *max_slots > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid REASSEMBLE_MAX_SLOTS"))?,
+            Err(_) => DEFAULT_MAX_SLOTS,
+        };
+        Ok(Some(Self::new(
+            wait_transactions,
+            wait_entries,
+            Duration::from_millis(timeout),
+            max_slots,
+        )))
+    }
+
+    pub fn new(
+        wait_transactions: bool,
+        wait_entries: bool,
+        timeout: Duration,
+        max_slots: usize,
+    ) -> Self {
+        Self {
+            wait_transactions,
+            wait_entries,
+            timeout,
+            max_slots,
+            state: Mutex::default(),
+            assembled: AtomicU64::new(0),
+            incomplete: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Blocks passed on with all the parts of their block meta
+    pub fn assembled(&self) -> u64 {
+        self.assembled.load(Ordering::Relaxed)
+    }
+
+    /// Blocks passed on after `REASSEMBLE_TIMEOUT_MS` or on exit with parts missing
+    pub fn incomplete(&self) -> u64 {
+        self.incomplete.load(Ordering::Relaxed)
+    }
+
+    /// Parts of slots over `REASSEMBLE_MAX_SLOTS`, without a block meta on exit and late
+    /// parts of slots already passed on
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn is_complete(&self, parts: &Parts) -> bool {
+        let Some(meta) = parts.meta.as_ref() else {
+            return false;
+        };
+        (!self.wait_transactions
+            || parts.transactions.len() as u64 >= meta.executed_transaction_count)
+            && (!self.wait_entries || parts.entries.len() as u64 >= meta.entries_count)
+    }
+
+    /// Updates which pass now: the update itself if it is not a part of a block, or the
+    /// block it completed
+    pub fn pass(&self, msg: SubscribeUpdate) -> Vec<SubscribeUpdate> {
+        let slot = match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Account(update)) => update.slot,
+            Some(UpdateOneof::Transaction(update)) => update.slot,
+            Some(UpdateOneof::Entry(entry)) => entry.slot,
+            Some(UpdateOneof::BlockMeta(meta)) => meta.slot,
+            _ => return vec![msg],
+        };
+        let mut state = self.state.lock().expect("poisoned");
+        if state.emitted.contains(&slot) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return vec![];
+        }
+        let parts = state.slots.entry(slot).or_default();
+        parts.filters.extend(msg.filters);
+        match msg.update_oneof {
+            Some(UpdateOneof::Account(update)) => {
+                if let Some(account) = update.account {
+                    // Workers can process writes of one account out of order
+                    match parts.accounts.get(&account.pubkey) {
+                        Some(current) if current.write_version > account.write_version => {}
+                        _ => {
+                            parts.accounts.insert(account.pubkey.clone(), account);
+                        }
+                    }
+                }
+            }
+            Some(UpdateOneof::Transaction(update)) => {
+                parts.transactions.extend(update.transaction);
+            }
+            Some(UpdateOneof::Entry(entry)) => parts.entries.push(entry),
+            Some(UpdateOneof::BlockMeta(meta)) => {
+                parts.meta = Some(meta);
+                parts.meta_at = Some(Instant::now());
+            }
+            _ => unreachable!("checked above"),
+        }
+
+        let mut blocks = vec![];
+        if state
+            .slots
+            .get(&slot)
+            .is_some_and(|parts| self.is_complete(parts))
+        {
+            let parts = state.slots.remove(&slot).expect("checked");
+            blocks.push(self.emit(&mut state, slot, parts));
+            self.assembled.fetch_add(1, Ordering::Relaxed);
+        }
+        // Slots without a block meta don't count, they have nothing to pass on
+        while state.slots.len() > self.max_slots {
+            let Some((oldest, parts)) = state.slots.pop_first() else {
+                break;
+            };
+            if parts.meta.is_some() {
+                blocks.push(self.emit(&mut state, oldest, parts));
+                self.incomplete.fetch_add(1, Ordering::Relaxed);
+            } else {
+                let count = parts.transactions.len() + parts.accounts.len() + parts.entries.len();
+                self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+                warn!(
+                    "reassemble: {count} updates of slot {oldest} dropped, no block meta within {} slots",
+                    self.max_slots
+                );
+            }
+        }
+        blocks
+    }
+
+    fn emit(&self, state: &mut AssemblerState, slot: u64, parts: Parts) -> SubscribeUpdate {
+        state.emitted.insert(slot);
+        while state.emitted.len() > self.max_slots {
+            state.emitted.pop_first();
+        }
+        parts.into_block()
+    }
+
+    /// Blocks which waited for their parts for `REASSEMBLE_TIMEOUT_MS`, oldest first
+    pub fn expired(&self) -> Vec<SubscribeUpdate> {
+        let mut state = self.state.lock().expect("poisoned");
+        let expired = state
+            .slots
+            .iter()
+            .filter(|(_, parts)| parts.meta_at.is_some_and(|at| at.elapsed() >= self.timeout))
+            .map(|(slot, _)| *slot)
+            .collect::<Vec<_>>();
+        self.incomplete
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+        expired
+            .into_iter()
+            .filter_map(|slot| {
+                let parts = state.slots.remove(&slot)?;
+                Some(self.emit(&mut state, slot, parts))
+            })
+            .collect()
+    }
+
+    /// Blocks with a block meta on exit, parts of other slots are dropped
+    pub fn drain(&self) -> Vec<SubscribeUpdate> {
+        let slots = std::mem::take(&mut self.state.lock().expect("poisoned").slots);
+        let mut blocks = vec![];
+        for (_, parts) in slots {
+            if parts.meta.is_some() {
+                self.incomplete.fetch_add(1, Ordering::Relaxed);
+                blocks.push(parts.into_block());
+            } else {
+                let count = parts.transactions.len() + parts.accounts.len() + parts.entries.len();
+                self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+            }
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            SubscribeUpdateAccount, SubscribeUpdateSlot, SubscribeUpdateTransaction,
+        },
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn update(filter: &str, update: UpdateOneof) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec![filter.to_owned()],
+            update_oneof: Some(update),
+        }
+    }
+
+    fn transaction(slot: u64, index: u64) -> SubscribeUpdate {
+        update(
+            "transactions",
+            UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                transaction: Some(SubscribeUpdateTransactionInfo {
+                    signature: vec![index as u8; 64],
+                    index,
+                    ..Default::default()
+                }),
+                slot,
+            }),
+        )
+    }
+
+    fn account(slot: u64, pubkey: u8, write_version: u64) -> SubscribeUpdate {
+        update(
+            "accounts",
+            UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: vec![pubkey; 32],
+                    write_version,
+                    ..Default::default()
+                }),
+                slot,
+                is_startup: false,
+            }),
+        )
+    }
+
+    fn entry(slot: u64, index: u64) -> SubscribeUpdate {
+        update(
+            "entries",
+            UpdateOneof::Entry(SubscribeUpdateEntry {
+                slot,
+                index,
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn meta(slot: u64, transactions: u64, entries: u64) -> SubscribeUpdate {
+        update(
+            "blocks_meta",
+            UpdateOneof::BlockMeta(SubscribeUpdateBlockMeta {
+                slot,
+                executed_transaction_count: transactions,
+                entries_count: entries,
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn block(msg: &SubscribeUpdate) -> &SubscribeUpdateBlock {
+        match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Block(block)) => block,
+            update => panic!("not a block: {update:?}"),
+        }
+    }
+
+    fn slots(blocks: &[SubscribeUpdate]) -> Vec<u64> {
+        blocks.iter().map(|msg| block(msg).slot).collect()
+    }
+
+    /// Assembled, incomplete and dropped
+    fn counts(assembler: &BlockAssembler) -> (u64, u64, u64) {
+        (
+            assembler.assembled(),
+            assembler.incomplete(),
+            assembler.dropped(),
+        )
+    }
+
+    #[test]
+    fn block_completed_by_last_transaction() {
+        let assembler = BlockAssembler::new(true, false, TIMEOUT, 10);
+        for msg in [
+            transaction(10, 2),
+            account(10, 1, 5),
+            account(10, 2, 4),
+            // Older write processed late
+            account(10, 1, 3),
+            meta(10, 3, 0),
+            transaction(10, 0),
+        ] {
+            assert!(assembler.pass(msg).is_empty());
+        }
+        let blocks = assembler.pass(transaction(10, 1));
+        let block = block(&blocks[0]);
+        assert_eq!(
+            blocks[0].filters,
+            ["accounts", "blocks_meta", "transactions"]
+        );
+        assert_eq!(
+            block
+                .transactions
+                .iter()
+                .map(|transaction| transaction.index)
+                .collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            block
+                .accounts
+                .iter()
+                .map(|account| (account.pubkey[0], account.write_version))
+                .collect::<Vec<_>>(),
+            [(2, 4), (1, 5)]
+        );
+        assert_eq!(block.updated_account_count, 2);
+
+        // Late parts of a passed slot are dropped
+        assert!(assembler.pass(transaction(10, 3)).is_empty());
+        assert_eq!(counts(&assembler), (1, 0, 1));
+    }
+
+    #[test]
+    fn block_meta_completes_received_parts() {
+        let assembler = BlockAssembler::new(true, false, TIMEOUT, 10);
+        assert!(assembler.pass(transaction(10, 0)).is_empty());
+        assert_eq!(slots(&assembler.pass(meta(10, 1, 0))), [10]);
+        // An empty block is complete with its block meta
+        assert_eq!(slots(&assembler.pass(meta(11, 0, 0))), [11]);
+    }
+
+    #[test]
+    fn waits_for_entries() {
+        let assembler = BlockAssembler::new(false, true, TIMEOUT, 10);
+        assert!(assembler.pass(meta(10, 5, 2)).is_empty());
+        assert!(assembler.pass(entry(10, 1)).is_empty());
+        let blocks = assembler.pass(entry(10, 0));
+        let entries = block(&blocks[0])
+            .entries
+            .iter()
+            .map(|entry| entry.index)
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [0, 1]);
+    }
+
+    #[test]
+    fn other_updates_pass() {
+        let assembler = BlockAssembler::new(true, false, TIMEOUT, 10);
+        let slot = update(
+            "slots",
+            UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot: 10,
+                parent: None,
+                status: 0,
+            }),
+        );
+        assert_eq!(assembler.pass(slot.clone()), [slot]);
+    }
+
+    #[test]
+    fn incomplete_blocks_expire_after_their_block_meta() {
+        let assembler = BlockAssembler::new(true, false, TIMEOUT, 10);
+        assembler.pass(meta(10, 2, 0));
+        assert!(assembler.expired().is_empty());
+
+        let assembler = BlockAssembler::new(true, false, Duration::ZERO, 10);
+        assembler.pass(transaction(9, 0));
+        assembler.pass(meta(11, 2, 0));
+        assembler.pass(transaction(10, 0));
+        assembler.pass(meta(10, 2, 0));
+        // Slots without block meta wait for it
+        assert_eq!(slots(&assembler.expired()), [10, 11]);
+        assert!(assembler.pass(transaction(10, 1)).is_empty());
+        assert_eq!(counts(&assembler), (0, 2, 1));
+    }
+
+    #[test]
+    fn oldest_slots_pass_or_drop_beyond_capacity() {
+        let assembler = BlockAssembler::new(true, false, TIMEOUT, 2);
+        assembler.pass(meta(1, 5, 0));
+        assembler.pass(transaction(2, 0));
+        assembler.pass(transaction(2, 1));
+        // Slot 1 has a block meta and passes incomplete
+        assert_eq!(slots(&assembler.pass(transaction(3, 0))), [1]);
+        // Slot 2 has nothing to pass on
+        assert!(assembler.pass(transaction(4, 0)).is_empty());
+        assert_eq!(counts(&assembler), (0, 1, 2));
+        // A late block meta of the dropped slot is still the oldest, it passes without the
+        // dropped parts
+        let blocks = assembler.pass(meta(2, 2, 0));
+        assert_eq!(slots(&blocks), [2]);
+        assert!(block(&blocks[0]).transactions.is_empty());
+    }
+
+    #[test]
+    fn drain_passes_slots_with_block_meta() {
+        let assembler = BlockAssembler::new(true, false, TIMEOUT, 10);
+        assembler.pass(account(10, 1, 1));
+        assembler.pass(meta(11, 2, 0));
+        assembler.pass(entry(12, 0));
+        assert_eq!(slots(&assembler.drain()), [11]);
+        assert!(assembler.drain().is_empty());
+        assert_eq!(counts(&assembler), (0, 1, 2));
+    }
+}
@@ -0,0 +1,239 @@
+//! Updates of the last slots kept in memory for incident investigation, `RECENT_SLOTS`.
+//!
+//! Every update passed to sinks is also kept in a ring buffer of the last `RECENT_SLOTS`
+//! slots, at most `RECENT_SLOT_UPDATES` per slot. `GET /recent` of the admin API returns
+//! them as JSON (see `json`), filtered by slot, update type, pubkey and filter name, so
+//! "what did we see in slot X five minutes ago" is answered without going to cold storage.
+
+use {
+    crate::{
+        json::{update_json, JsonOptions},
+        sink::{SinkHealth, UpdateSink},
+        stats::{update_kind, update_slot},
+    },
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::BTreeMap,
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_SLOT_UPDATES: usize = 10_000;
+const DEFAULT_LIMIT: usize = 1_000;
+
+/// Query of `GET /recent`, all fields are optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecentQuery {
+    slot: Option<u64>,
+    /// Update type, e.g. `account`
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// Account pubkey or owner, or an account key of the transaction
+    pubkey: Option<String>,
+    filter: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentUpdatesResponse {
+    /// Oldest and newest kept slot
+    oldest_slot: Option<u64>,
+    newest_slot: Option<u64>,
+    updates: Vec<Value>,
+    /// More updates matched than `limit`
+    more: bool,
+}
+
+/// Pubkey matched by `RecentQuery::pubkey`
+fn has_pubkey(update: &UpdateOneof, pubkey: &[u8]) -> bool {
+    match update {
+        UpdateOneof::Account(update) => update
+            .account
+            .as_ref()
+            .is_some_and(|account| account.pubkey == pubkey || account.owner == pubkey),
+        UpdateOneof::Transaction(update) => update.transaction.as_ref().is_some_and(|tx| {
+            let keys = tx
+                .transaction
+                .as_ref()
+                .and_then(|tx| tx.message.as_ref())
+                .map(|message| message.account_keys.iter())
+                .into_iter()
+                .flatten();
+            let loaded = tx
+                .meta
+                .as_ref()
+                .map(|meta| {
+                    meta.loaded_writable_addresses
+                        .iter()
+                        .chain(meta.loaded_readonly_addresses.iter())
+                })
+                .into_iter()
+                .flatten();
+            keys.chain(loaded).any(|key| key == pubkey)
+        }),
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub struct RecentUpdates {
+    slots: usize,
+    slot_updates: usize,
+    json: JsonOptions,
+    buffer: Mutex<BTreeMap<u64, Vec<SubscribeUpdate>>>,
+    /// Updates not kept because their slot was full or already evicted
+    dropped: AtomicU64,
+}
+
+impl RecentUpdates {
+    /// Returns `None` if `RECENT_SLOTS` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let parse = |key: &str| {
+            env::var(key)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|value| *value > 0)
+                        .ok_or_else(|| anyhow::anyhow!("invalid {key}"))
+                })
+                .transpose()
+        };
+        let Some(slots) = parse("RECENT_SLOTS")? else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            slots,
+            slot_updates: parse("RECENT_SLOT_UPDATES")?.unwrap_or(DEFAULT_SLOT_UPDATES),
+            json: JsonOptions::from_env()?,
+            buffer: Mutex::default(),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn push(&self, msg: &SubscribeUpdate) {
+        let Some(slot) = update_slot(msg) else {
+            return;
+        };
+        let mut buffer = self.buffer.lock().expect("poisoned");
+        // Late update of a slot which was already evicted
+        if buffer.len() >= self.slots
+            && buffer
+                .first_key_value()
+                .is_some_and(|(oldest, _)| slot < *oldest)
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let updates = buffer.entry(slot).or_default();
+        if updates.len() >= self.slot_updates {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        updates.push(msg.clone());
+        while buffer.len() > self.slots {
+            buffer.pop_first();
+        }
+    }
+
+    /// Last `count` kept updates of slots from `from_slot`, oldest first
+    pub fn last(&self, count: usize, from_slot: Option<u64>) -> Vec<SubscribeUpdate> {
+        let buffer = self.buffer.lock().expect("poisoned");
+        let mut updates = buffer
+            .range(from_slot.unwrap_or_default()..)
+            .rev()
+            .flat_map(|(_, updates)| updates.iter().rev())
+            .take(count)
+            .cloned()
+            .collect::<Vec<_>>();
+        updates.reverse();
+        updates
+    }
+
+    pub fn query(&self, query: RecentQuery) -> anyhow::Result<RecentUpdatesResponse> {
+        let pubkey = query
+            .pubkey
+            .as_ref()
+            .map(|pubkey| {
+                pubkey
+                    .parse::<Pubkey>()
+                    .map(|pubkey| pubkey.to_bytes())
+                    .map_err(|_| anyhow::anyhow!("invalid pubkey {pubkey:?}"))
+            })
+            .transpose()?;
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+        let buffer = self.buffer.lock().expect("poisoned");
+        let oldest_slot = buffer.first_key_value().map(|(slot, _)| *slot);
+        let newest_slot = buffer.last_key_value().map(|(slot, _)| *slot);
+        if let (Some(slot), Some(oldest)) = (query.slot, oldest_slot) {
+            anyhow::ensure!(
+                slot >= oldest,
+                "slot {slot} is not kept anymore, the oldest kept slot is {oldest}"
+            );
+        }
+
+        let mut matched = buffer
+            .iter()
+            .filter(|(slot, _)| query.slot.is_none_or(|query| **slot == query))
+            .flat_map(|(_, updates)| updates.iter())
+            .filter(|msg| {
+                let Some(update) = msg.update_oneof.as_ref() else {
+                    return false;
+                };
+                query
+                    .kind
+                    .as_ref()
+                    .is_none_or(|kind| kind == update_kind(update))
+                    && query
+                        .filter
+                        .as_ref()
+                        .is_none_or(|filter| msg.filters.contains(filter))
+                    && pubkey.is_none_or(|pubkey| has_pubkey(update, &pubkey))
+            });
+        let updates = matched
+            .by_ref()
+            .take(limit)
+            .filter_map(|msg| update_json(msg, &self.json, None))
+            .collect();
+        Ok(RecentUpdatesResponse {
+            oldest_slot,
+            newest_slot,
+            updates,
+            more: matched.next().is_some(),
+        })
+    }
+}
+
+/// Keeps updates passed to sinks in `RecentUpdates`
+pub struct RecentSink(pub Arc<RecentUpdates>);
+
+impl UpdateSink for RecentSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        self.0.push(msg);
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "recent",
+            dropped: self.0.dropped(),
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+}
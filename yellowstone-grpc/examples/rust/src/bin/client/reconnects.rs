@@ -0,0 +1,296 @@
+//! Why streams ended, kept as a ring buffer of the last `RECONNECT_HISTORY_SIZE` entries.
+//!
+//! Every end of a subscription stream and every failed connect is classified from the
+//! error: GOAWAY and RST_STREAM frames are found in the h2 error behind the gRPC status,
+//! timeouts and the slot watchdog count as idle timeouts, other statuses are reported with
+//! their code. With `RECONNECT_HISTORY_PATH` entries are appended as JSON lines and the last
+//! ones are loaded on start, so the history survives restarts.
+
+use {
+    crate::{endpoint::Endpoints, error::ClientError},
+    chrono::Utc,
+    log::{error, warn},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, VecDeque},
+        env,
+        fs::{self, OpenOptions},
+        io::{self, Write},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+    yellowstone_grpc_proto::tonic::{Code, Status},
+};
+
+const DEFAULT_HISTORY_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// Server finished the stream without an error
+    Closed,
+    /// Connection closed with a GOAWAY frame
+    GoAway,
+    /// Stream reset with a RST_STREAM frame
+    Reset,
+    /// Nothing received in time, by transport timeout or slot watchdog
+    IdleTimeout,
+    /// Server returned an error status
+    Status,
+    /// Connection failed without an HTTP/2 reason, e.g. a socket error
+    Transport,
+    /// Failed to connect
+    Connect,
+    /// Error on the client side, e.g. failed to write the capture file
+    Local,
+    /// Client shutdown
+    Shutdown,
+}
+
+impl EndReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::GoAway => "go_away",
+            Self::Reset => "reset",
+            Self::IdleTimeout => "idle_timeout",
+            Self::Status => "status",
+            Self::Transport => "transport",
+            Self::Connect => "connect",
+            Self::Local => "local",
+            Self::Shutdown => "shutdown",
+        }
+    }
+
+    /// Reason and detail of a stream which failed with `status`
+    pub fn from_status(status: &Status) -> (Self, String) {
+        let mut source = std::error::Error::source(status);
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<h2::Error>() {
+                let side = if error.is_remote() { "remote" } else { "local" };
+                let reason = error
+                    .reason()
+                    .map_or_else(|| "unknown".to_owned(), |reason| format!("{reason:?}"));
+                if error.is_go_away() {
+                    return (Self::GoAway, format!("{side} GOAWAY {reason}"));
+                }
+                if error.is_reset() {
+                    return (Self::Reset, format!("{side} RST_STREAM {reason}"));
+                }
+            }
+            if let Some(error) = error.downcast_ref::<io::Error>() {
+                if error.kind() == io::ErrorKind::TimedOut {
+                    return (Self::IdleTimeout, error.to_string());
+                }
+            }
+            source = error.source();
+        }
+
+        let detail = format!("{:?}: {}", status.code(), status.message());
+        let reason = match status.code() {
+            Code::DeadlineExceeded => Self::IdleTimeout,
+            // Transport errors are reported as statuses with the error as source
+            Code::Unavailable | Code::Unknown if std::error::Error::source(status).is_some() => {
+                Self::Transport
+            }
+            _ => Self::Status,
+        };
+        (reason, detail)
+    }
+
+    /// Reason of a stream which failed with `error`
+    pub fn from_error(error: &ClientError) -> (Self, String) {
+        match error {
+            ClientError::Closed => return (Self::Closed, error.to_string()),
+            ClientError::IdleTimeout(detail) => return (Self::IdleTimeout, detail.clone()),
+            _ => {}
+        }
+        match error.status() {
+            Some(status) => Self::from_status(status),
+            None => (Self::Local, error.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamEnd {
+    pub time: String,
+    pub endpoint: String,
+    pub reason: EndReason,
+    pub detail: String,
+    /// How long the stream was open, zero for failed connects
+    pub duration_ms: u64,
+    pub messages: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistorySnapshot {
+    pub endpoint: String,
+    /// Open time of the current stream
+    pub connected_since: Option<String>,
+    pub ends: BTreeMap<&'static str, u64>,
+    pub history: Vec<StreamEnd>,
+}
+
+#[derive(Debug)]
+pub struct ReconnectHistory {
+    capacity: usize,
+    path: Option<String>,
+    endpoints: Arc<Endpoints>,
+    entries: Mutex<VecDeque<StreamEnd>>,
+    /// Ends by reason since start, entries loaded from the file are not counted
+    counts: Mutex<BTreeMap<EndReason, u64>>,
+    connected: Mutex<Option<(String, Instant)>>,
+    /// Number of opened streams
+    opens: AtomicU64,
+    /// Streams subscribed again on the same connection after a message error
+    recoveries: AtomicU64,
+}
+
+impl ReconnectHistory {
+    pub fn from_env(endpoints: Arc<Endpoints>) -> anyhow::Result<Self> {
+        let capacity = match env::var("RECONNECT_HISTORY_SIZE") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid RECONNECT_HISTORY_SIZE"))?,
+            Err(_) => DEFAULT_HISTORY_SIZE,
+        };
+        let path = env::var("RECONNECT_HISTORY_PATH").ok();
+
+        let mut entries = VecDeque::with_capacity(capacity);
+        if let Some(path) = path.as_deref() {
+            match fs::read_to_string(path) {
+                Ok(content) => {
+                    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                        match serde_json::from_str::<StreamEnd>(line) {
+                            Ok(entry) => {
+                                if entries.len() == capacity {
+                                    entries.pop_front();
+                                }
+                                entries.push_back(entry);
+                            }
+                            Err(error) => warn!("skip invalid line of {path}: {error}"),
+                        }
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => anyhow::bail!("failed to read {path}: {error}"),
+            }
+        }
+
+        Ok(Self {
+            capacity,
+            path,
+            endpoints,
+            entries: Mutex::new(entries),
+            counts: Mutex::default(),
+            connected: Mutex::default(),
+            opens: AtomicU64::new(0),
+            recoveries: AtomicU64::new(0),
+        })
+    }
+
+    /// Called when a stream is opened
+    pub fn opened(&self) {
+        *self.connected.lock().expect("poisoned") = Some((Utc::now().to_rfc3339(), Instant::now()));
+        self.opens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of streams opened since start
+    pub fn opens(&self) -> u64 {
+        self.opens.load(Ordering::Relaxed)
+    }
+
+    /// Called when a stream is subscribed again on the same connection, the stream is not
+    /// counted as ended
+    pub fn recovered(&self) {
+        self.recoveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of streams recovered without a reconnect since start
+    pub fn recoveries(&self) -> u64 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+
+    /// Called when a stream ended or a connect failed
+    pub fn record(&self, reason: EndReason, detail: String, messages: u64) {
+        let duration = self
+            .connected
+            .lock()
+            .expect("poisoned")
+            .take()
+            .map_or(Duration::ZERO, |(_, since)| since.elapsed());
+        let entry = StreamEnd {
+            time: Utc::now().to_rfc3339(),
+            endpoint: self.endpoints.current_name(),
+            reason,
+            detail,
+            duration_ms: duration.as_millis() as u64,
+            messages,
+        };
+
+        if let Some(path) = self.path.as_deref() {
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| {
+                    writeln!(
+                        file,
+                        "{}",
+                        serde_json::to_string(&entry).map_err(io::Error::from)?
+                    )
+                });
+            if let Err(error) = result {
+                error!("failed to write reconnect history to {path}: {error}");
+            }
+        }
+
+        *self
+            .counts
+            .lock()
+            .expect("poisoned")
+            .entry(reason)
+            .or_default() += 1;
+        let mut entries = self.entries.lock().expect("poisoned");
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Ends by reason since start
+    pub fn counts(&self) -> BTreeMap<&'static str, u64> {
+        self.counts
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .map(|(reason, count)| (reason.name(), *count))
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot {
+            endpoint: self.endpoints.current_name(),
+            connected_since: self
+                .connected
+                .lock()
+                .expect("poisoned")
+                .as_ref()
+                .map(|(since, _)| since.clone()),
+            ends: self.counts(),
+            history: self
+                .entries
+                .lock()
+                .expect("poisoned")
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Subscription filters from `FILTERS_PATH`, applied again when the file changes.
+//!
+//! The file is JSON, or TOML if the name ends with `.toml`. Filters from the file are added
+//! to the filters configured with environment variables, a filter with the same name
+//! replaces the one from the environment. The directory of the file is watched with
+//! `notify` (inotify, FSEvents, ...), which also sees editors replacing the file on save and
+//! writes which keep the modification time, events within `RELOAD_DEBOUNCE` are merged.
+//! The `tags` section sets tags of filters by name, see `tags`.
+
+use {
+    crate::{
+        filters::{AccountsFilterArgs, TransactionsFilterArgs},
+        tags::{FilterTags, Tags},
+    },
+    log::warn,
+    notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher},
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        fs, future,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::{sync::watch, time::sleep},
+    yellowstone_grpc_proto::prelude::{
+        SubscribeRequest, SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterEntry,
+        SubscribeRequestFilterSlots,
+    },
+};
+
+/// Events of one save (truncate, write, rename) are reloaded once
+pub const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SlotsFilterArgs {
+    filter_by_commitment: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FiltersFile {
+    accounts: BTreeMap<String, AccountsFilterArgs>,
+    transactions: BTreeMap<String, TransactionsFilterArgs>,
+    transactions_status: BTreeMap<String, TransactionsFilterArgs>,
+    slots: BTreeMap<String, SlotsFilterArgs>,
+    /// Names of blocks meta filters
+    blocks_meta: Vec<String>,
+    /// Names of entry filters
+    entry: Vec<String>,
+    tags: BTreeMap<String, Tags>,
+}
+
+impl FiltersFile {
+    fn read(path: &str) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        if path.ends_with(".toml") {
+            Ok(toml::from_str(&content)?)
+        } else {
+            Ok(serde_json::from_str(&content)?)
+        }
+    }
+
+    fn apply(&self, request: &mut SubscribeRequest) -> anyhow::Result<()> {
+        for (name, filter) in self.accounts.iter() {
+            request.accounts.insert(name.clone(), filter.to_filter()?);
+        }
+        for (name, filter) in self.transactions.iter() {
+            request
+                .transactions
+                .insert(name.clone(), filter.to_filter());
+        }
+        for (name, filter) in self.transactions_status.iter() {
+            request
+                .transactions_status
+                .insert(name.clone(), filter.to_filter());
+        }
+        for (name, filter) in self.slots.iter() {
+            request.slots.insert(
+                name.clone(),
+                SubscribeRequestFilterSlots {
+                    filter_by_commitment: filter.filter_by_commitment,
+                },
+            );
+        }
+        for name in self.blocks_meta.iter() {
+            request
+                .blocks_meta
+                .insert(name.clone(), SubscribeRequestFilterBlocksMeta {});
+        }
+        for name in self.entry.iter() {
+            request
+                .entry
+                .insert(name.clone(), SubscribeRequestFilterEntry {});
+        }
+        Ok(())
+    }
+}
+
+/// `base` with filters from the file at `path`, and tags from the file
+pub fn load_filters(
+    path: &str,
+    base: &SubscribeRequest,
+) -> anyhow::Result<(SubscribeRequest, BTreeMap<String, Tags>)> {
+    let mut request = base.clone();
+    let file = FiltersFile::read(path)
+        .and_then(|file| file.apply(&mut request).map(|()| file))
+        .map_err(|error| anyhow::anyhow!("invalid filters file {path}: {error}"))?;
+    Ok((request, file.tags))
+}
+
+pub struct FiltersWatcher {
+    path: String,
+    /// Request built from environment variables
+    base: SubscribeRequest,
+    tags: Arc<FilterTags>,
+    /// Incremented by the watcher for every event of the file
+    events: watch::Receiver<u64>,
+    /// An event was received, the debounce was interrupted
+    pending: bool,
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl FiltersWatcher {
+    /// Returns the watcher and the request with filters from the file
+    pub fn new(
+        path: String,
+        base: SubscribeRequest,
+        tags: Arc<FilterTags>,
+    ) -> anyhow::Result<(Self, SubscribeRequest)> {
+        let (events_tx, events) = watch::channel(0);
+        let fs_watcher = watch_file(&path, events_tx)
+            .map_err(|error| anyhow::anyhow!("failed to watch filters file {path}: {error}"))?;
+        let watcher = Self {
+            path,
+            base,
+            tags,
+            events,
+            pending: false,
+            _watcher: fs_watcher,
+        };
+        let request = watcher.reload()?;
+        Ok((watcher, request))
+    }
+
+    /// The request with filters from the file as it is now, after `changed`
+    pub fn reload(&self) -> anyhow::Result<SubscribeRequest> {
+        let (request, tags) = load_filters(&self.path, &self.base)?;
+        self.tags.set_file_tags(tags);
+        Ok(request)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Resolves `RELOAD_DEBOUNCE` after the file was written, created, replaced or removed,
+    /// never without watcher. Cancel safe, an interrupted wait resumes with the debounce.
+    pub async fn changed(watcher: Option<&mut Self>) {
+        let Some(watcher) = watcher else {
+            return future::pending().await;
+        };
+        if !watcher.pending {
+            if watcher.events.changed().await.is_err() {
+                return future::pending().await;
+            }
+            watcher.pending = true;
+        }
+        sleep(RELOAD_DEBOUNCE).await;
+        watcher.events.borrow_and_update();
+        watcher.pending = false;
+    }
+}
+
+/// Watch the directory of `path`, editors often save by writing another file and renaming
+/// it over the watched one, which ends a watch on the file itself
+fn watch_file(path: &str, events: watch::Sender<u64>) -> anyhow::Result<RecommendedWatcher> {
+    let file = Path::new(path);
+    let name = file
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("not a file"))?
+        .to_owned();
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let dir = fs::canonicalize(dir)?;
+    let watched = dir.join(name);
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+        match result {
+            // Reading the file is reported as access, also by the reload itself
+            Ok(event)
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) && event.paths.contains(&watched) =>
+            {
+                events.send_modify(|count| *count += 1);
+            }
+            Ok(_) => {}
+            Err(error) => warn!("filters file watcher: {error}"),
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, tokio::time::timeout};
+
+    #[tokio::test]
+    async fn reloads_file_replaced_by_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filters.json");
+        fs::write(&path, r#"{"blocks_meta": ["first"]}"#).unwrap();
+        let (mut watcher, request) = FiltersWatcher::new(
+            path.to_str().unwrap().to_owned(),
+            SubscribeRequest::default(),
+            Arc::new(FilterTags::default()),
+        )
+        .unwrap();
+        assert!(request.blocks_meta.contains_key("first"));
+
+        // Like editors which write a temporary file and rename it over the original
+        let temporary = dir.path().join(".filters.json.swp");
+        fs::write(&temporary, r#"{"blocks_meta": ["second"]}"#).unwrap();
+        fs::rename(&temporary, &path).unwrap();
+        timeout(
+            Duration::from_secs(5),
+            FiltersWatcher::changed(Some(&mut watcher)),
+        )
+        .await
+        .expect("change of the filters file is not reported");
+        let request = watcher.reload().unwrap();
+        assert!(request.blocks_meta.contains_key("second"));
+        assert!(!request.blocks_meta.contains_key("first"));
+    }
+
+    #[tokio::test]
+    async fn ignores_other_files_in_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filters.toml");
+        fs::write(&path, "entry = [\"entries\"]\n").unwrap();
+        let (mut watcher, _) = FiltersWatcher::new(
+            path.to_str().unwrap().to_owned(),
+            SubscribeRequest::default(),
+            Arc::new(FilterTags::default()),
+        )
+        .unwrap();
+
+        fs::write(dir.path().join("other.json"), "{}").unwrap();
+        let changed = timeout(
+            RELOAD_DEBOUNCE * 5,
+            FiltersWatcher::changed(Some(&mut watcher)),
+        )
+        .await;
+        assert!(changed.is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Reconnect backoff policy, `RETRY_*`.
+//!
+//! Intervals start at `RETRY_INITIAL_MS`, grow by `RETRY_MULTIPLIER` up to `RETRY_MAX_MS`
+//! and are randomized by `RETRY_JITTER` (0.5 means ±50%). Retries stop once
+//! `RETRY_MAX_ELAPSED_SECS` passed since the first failure, `0` retries forever. The policy
+//! starts over when a stream was opened since the last failure. Defaults are
+//! those of `ExponentialBackoff::default()`:
+//! 500ms, 750ms, 1.125s, 1.6875s, 2.53125s, 3.796875s, 5.6953125s, 8.5s, 12.8s, 19.2s,
+//! 28.8s, 43.2s, 60s, 60s, ... for 15 minutes.
+//!
+//! A stream which fails on one malformed or oversized update is first subscribed again on
+//! the same connection, at most `RETRY_STREAM_RECOVERIES` times in a row, without backoff.
+
+use {
+    crate::reconnects::ReconnectHistory,
+    backoff::{backoff::Backoff, ExponentialBackoff, ExponentialBackoffBuilder},
+    std::{env, sync::Arc, time::Duration},
+};
+
+const DEFAULT_STREAM_RECOVERIES: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    initial: Duration,
+    max_interval: Duration,
+    multiplier: f64,
+    /// `None` retries forever
+    max_elapsed: Option<Duration>,
+    jitter: f64,
+    /// Re-subscribes on the same connection after a message error, `0` reconnects right away
+    stream_recoveries: u32,
+}
+
+impl RetryConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse_u64 = |key: &str| {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse::<u64>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+        let parse_f64 = |key: &str| {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse::<f64>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+
+        let defaults = ExponentialBackoff::default();
+        let initial =
+            parse_u64("RETRY_INITIAL_MS")?.map_or(defaults.initial_interval, Duration::from_millis);
+        let max_interval =
+            parse_u64("RETRY_MAX_MS")?.map_or(defaults.max_interval, Duration::from_millis);
+        anyhow::ensure!(
+            !initial.is_zero() && initial <= max_interval,
+            "RETRY_INITIAL_MS should be positive and not above RETRY_MAX_MS"
+        );
+        let multiplier = parse_f64("RETRY_MULTIPLIER")?.unwrap_or(defaults.multiplier);
+        anyhow::ensure!(multiplier >= 1.0, "RETRY_MULTIPLIER should be at least 1");
+        let jitter = parse_f64("RETRY_JITTER")?.unwrap_or(defaults.randomization_factor);
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&jitter),
+            "RETRY_JITTER should be between 0 and 1"
+        );
+        let max_elapsed = match parse_u64("RETRY_MAX_ELAPSED_SECS")? {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => defaults.max_elapsed_time,
+        };
+
+        let stream_recoveries = match env::var("RETRY_STREAM_RECOVERIES") {
+            Ok(value) => value
+                .parse::<u32>()
+                .map_err(|_| anyhow::anyhow!("invalid RETRY_STREAM_RECOVERIES"))?,
+            Err(_) => DEFAULT_STREAM_RECOVERIES,
+        };
+
+        Ok(Self {
+            initial,
+            max_interval,
+            multiplier,
+            max_elapsed,
+            jitter,
+            stream_recoveries,
+        })
+    }
+
+    pub const fn stream_recoveries(&self) -> u32 {
+        self.stream_recoveries
+    }
+
+    pub fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoffBuilder::new()
+            .with_initial_interval(self.initial)
+            .with_max_interval(self.max_interval)
+            .with_multiplier(self.multiplier)
+            .with_randomization_factor(self.jitter)
+            .with_max_elapsed_time(self.max_elapsed)
+            .build()
+    }
+
+    /// Backoff which starts over after a stream was opened, so a stream which fails after
+    /// running for a while is retried from `RETRY_INITIAL_MS` with the full
+    /// `RETRY_MAX_ELAPSED_SECS`
+    pub fn stream_backoff(&self, reconnects: Arc<ReconnectHistory>) -> StreamBackoff {
+        StreamBackoff {
+            inner: self.backoff(),
+            opens: reconnects.opens(),
+            reconnects,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamBackoff {
+    inner: ExponentialBackoff,
+    reconnects: Arc<ReconnectHistory>,
+    /// Opened streams when the policy was last reset
+    opens: u64,
+}
+
+impl Backoff for StreamBackoff {
+    fn reset(&mut self) {
+        self.opens = self.reconnects.opens();
+        self.inner.reset();
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        if self.reconnects.opens() != self.opens {
+            self.reset();
+        }
+        self.inner.next_backoff()
+    }
+}
@@ -0,0 +1,120 @@
+//! Sampling and rate limiting of high-volume streams before the processing queue.
+//!
+//! `SAMPLE_RATE` keeps a random fraction of updates, `MAX_MSGS_PER_SEC` caps how many are
+//! kept per second. Both apply to each update type separately, so a busy account filter
+//! does not starve transactions. Slots, pings and pongs are never dropped, the watchdog and
+//! slot tracking rely on them. Dropped updates are still metered by bandwidth and written
+//! by `ACTION=Record`, but don't reach stream statistics, logs and sinks.
+
+use {
+    crate::stats::update_kind,
+    std::{
+        collections::BTreeMap,
+        env,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Updates of one type kept in the current window and dropped since start
+#[derive(Debug, Default)]
+struct KindState {
+    window_start: Option<Instant>,
+    in_window: u64,
+    sampled_out: u64,
+    rate_limited: u64,
+}
+
+#[derive(Debug)]
+pub struct StreamSampler {
+    sample_rate: f64,
+    max_per_sec: Option<u64>,
+    kinds: Mutex<BTreeMap<&'static str, KindState>>,
+}
+
+impl StreamSampler {
+    /// Returns `None` if neither `SAMPLE_RATE` nor `MAX_MSGS_PER_SEC` is set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let sample_rate = env::var("SAMPLE_RATE")
+            .ok()
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|rate| (0.0..=1.0).contains(rate))
+                    .ok_or_else(|| anyhow::anyhow!("invalid SAMPLE_RATE, expected 0.0..=1.0"))
+            })
+            .transpose()?;
+        let max_per_sec = env::var("MAX_MSGS_PER_SEC")
+            .ok()
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| anyhow::anyhow!("invalid MAX_MSGS_PER_SEC"))
+            })
+            .transpose()?;
+        if sample_rate.is_none() && max_per_sec.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            sample_rate: sample_rate.unwrap_or(1.0),
+            max_per_sec,
+            kinds: Mutex::default(),
+        }))
+    }
+
+    /// Returns `true` if the update should be dropped
+    pub fn drop_update(&self, msg: &SubscribeUpdate) -> bool {
+        let Some(update) = msg.update_oneof.as_ref() else {
+            return false;
+        };
+        if matches!(
+            update,
+            UpdateOneof::Slot(_) | UpdateOneof::Ping(_) | UpdateOneof::Pong(_)
+        ) {
+            return false;
+        }
+
+        let mut kinds = self.kinds.lock().expect("poisoned");
+        let state = kinds.entry(update_kind(update)).or_default();
+        if self.sample_rate < 1.0 && rand::random::<f64>() >= self.sample_rate {
+            state.sampled_out += 1;
+            return true;
+        }
+        if let Some(max_per_sec) = self.max_per_sec {
+            let now = Instant::now();
+            if state
+                .window_start
+                .is_none_or(|start| now.duration_since(start) >= WINDOW)
+            {
+                state.window_start = Some(now);
+                state.in_window = 0;
+            }
+            if state.in_window >= max_per_sec {
+                state.rate_limited += 1;
+                return true;
+            }
+            state.in_window += 1;
+        }
+        false
+    }
+
+    /// Dropped updates by update type and reason, `sample_rate` or `max_msgs_per_sec`
+    pub fn dropped(&self) -> Vec<(&'static str, &'static str, u64)> {
+        let kinds = self.kinds.lock().expect("poisoned");
+        let mut dropped = Vec::new();
+        for (kind, state) in kinds.iter() {
+            if state.sampled_out > 0 {
+                dropped.push((*kind, "sample_rate", state.sampled_out));
+            }
+            if state.rate_limited > 0 {
+                dropped.push((*kind, "max_msgs_per_sec", state.rate_limited));
+            }
+        }
+        dropped
+    }
+}
@@ -0,0 +1,107 @@
+//! Updates from a server with a newer Geyser proto, `SCHEMA_STRICT`.
+//!
+//! prost decodes an update type added after the client's proto as an update without
+//! `update_oneof`, and keeps enum values it doesn't know, like new slot statuses, as plain
+//! numbers. Both are counted by kind and the first of each kind is logged, so a version
+//! mismatch is visible instead of updates silently falling through. Unknown fields of known
+//! messages are skipped by prost while decoding and never reach the client, a mismatch shows
+//! up as one of the above first. With `SCHEMA_STRICT=true` the first mismatch stops the
+//! client with an error, like `ERROR_POLICY_<STAGE>=halt`.
+
+use {
+    log::{error, warn},
+    std::{
+        collections::BTreeMap,
+        env,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    },
+    tokio::sync::watch,
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeUpdate,
+    },
+};
+
+pub struct SchemaCheck {
+    strict: bool,
+    shutdown: Arc<watch::Sender<bool>>,
+    /// Mismatches by kind, e.g. `unknown_update` or `unknown_slot_status:4`
+    unknown: Mutex<BTreeMap<String, u64>>,
+    halted: AtomicBool,
+    halt_reason: Mutex<Option<String>>,
+}
+
+impl SchemaCheck {
+    pub fn from_env(shutdown: Arc<watch::Sender<bool>>) -> anyhow::Result<Self> {
+        let strict = match env::var("SCHEMA_STRICT").as_deref() {
+            Ok("true") => true,
+            Ok("false") | Err(_) => false,
+            Ok(_) => anyhow::bail!("invalid SCHEMA_STRICT, expected `true` or `false`"),
+        };
+        Ok(Self {
+            strict,
+            shutdown,
+            unknown: Mutex::default(),
+            halted: AtomicBool::new(false),
+            halt_reason: Mutex::default(),
+        })
+    }
+
+    /// What the client's proto doesn't know in the update
+    fn mismatch(msg: &SubscribeUpdate) -> Option<(String, String)> {
+        match msg.update_oneof.as_ref() {
+            None => Some((
+                "unknown_update".to_owned(),
+                format!(
+                    "update of an unknown type, filters {:?}, the server runs a newer proto",
+                    msg.filters
+                ),
+            )),
+            Some(UpdateOneof::Slot(slot)) if CommitmentLevel::try_from(slot.status).is_err() => {
+                Some((
+                    format!("unknown_slot_status:{}", slot.status),
+                    format!("slot {} with unknown status {}", slot.slot, slot.status),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Count and log an update the client's proto doesn't fully know, returns `false` if it
+    /// should be dropped because `SCHEMA_STRICT` stops the client
+    pub fn check(&self, msg: &SubscribeUpdate) -> bool {
+        let Some((kind, detail)) = Self::mismatch(msg) else {
+            return true;
+        };
+        let first = {
+            let mut unknown = self.unknown.lock().expect("poisoned");
+            let count = unknown.entry(kind).or_default();
+            *count += 1;
+            *count == 1
+        };
+        if !self.strict {
+            if first {
+                warn!("schema: {detail}, passed on, further ones are only counted");
+            }
+            return true;
+        }
+        if !self.halted.swap(true, Ordering::Relaxed) {
+            error!("schema: {detail}, stopping because of SCHEMA_STRICT");
+            *self.halt_reason.lock().expect("poisoned") = Some(detail);
+            self.shutdown.send_replace(true);
+        }
+        false
+    }
+
+    /// Mismatches by kind
+    pub fn unknown(&self) -> BTreeMap<String, u64> {
+        self.unknown.lock().expect("poisoned").clone()
+    }
+
+    /// Mismatch which stopped the client with `SCHEMA_STRICT`
+    pub fn halted(&self) -> Option<String> {
+        self.halt_reason.lock().expect("poisoned").clone()
+    }
+}
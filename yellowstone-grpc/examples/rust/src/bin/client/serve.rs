@@ -0,0 +1,291 @@
+//! WebSocket re-broadcast of the stream for browser consumers, `ACTION=Serve`.
+//!
+//! The stream is processed like with `Subscribe`, and every update is also sent as a JSON
+//! text message (see `json`) to the WebSocket clients connected to `SERVE_ADDR`. A client
+//! receives all updates until it sends a subscription, which narrows them down:
+//!
+//! ```json
+//! {"types": ["account", "slot"], "filters": ["usdc"], "accounts": ["<pubkey>"], "owners": ["<pubkey>"]}
+//! ```
+//!
+//! Missing or empty fields match everything, `accounts` and `owners` only apply to account
+//! updates. A new subscription replaces the previous one and is answered with
+//! `{"event": "subscribed", ...}`, an invalid one with `{"event": "error", ...}`. Every client
+//! buffers up to `SERVE_CLIENT_BUFFER` updates, a client which falls further behind skips the
+//! oldest ones and receives `{"event": "lagged", "skipped": n}`. Fields over the `TRUNCATE_*`
+//! budget are cut, so large accounts don't exceed the message limits of browsers.
+
+use {
+    crate::{
+        forks::SlotRollback,
+        gaps::Gap,
+        json::{update_json, JsonOptions},
+        sink::{SinkHealth, UpdateSink},
+        stats::update_kind,
+        truncate::FieldBudget,
+    },
+    axum::{
+        extract::{
+            ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade},
+            State,
+        },
+        response::Response,
+        routing::get,
+        Router,
+    },
+    log::{error, info},
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        env,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    },
+    tokio::{
+        net::TcpListener,
+        sync::broadcast::{self, error::RecvError},
+        task::JoinHandle,
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_CLIENT_BUFFER: usize = 1024;
+const UPDATE_TYPES: [&str; 9] = [
+    "account",
+    "slot",
+    "transaction",
+    "transaction_status",
+    "block",
+    "block_meta",
+    "entry",
+    "slot_rolled_back",
+    "gap",
+];
+
+/// Update encoded once for all clients, with the fields subscriptions match on
+#[derive(Debug)]
+struct Encoded {
+    kind: &'static str,
+    filters: Vec<String>,
+    pubkey: Option<String>,
+    owner: Option<String>,
+    text: Utf8Bytes,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct Subscription {
+    types: Vec<String>,
+    filters: Vec<String>,
+    accounts: Vec<String>,
+    owners: Vec<String>,
+}
+
+impl Subscription {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let subscription = serde_json::from_str::<Self>(text)?;
+        if let Some(kind) = subscription
+            .types
+            .iter()
+            .find(|kind| !UPDATE_TYPES.contains(&kind.as_str()))
+        {
+            anyhow::bail!("unknown update type {kind:?}, expected one of {UPDATE_TYPES:?}");
+        }
+        for pubkey in subscription.accounts.iter().chain(&subscription.owners) {
+            pubkey
+                .parse::<Pubkey>()
+                .map_err(|_| anyhow::anyhow!("invalid pubkey {pubkey:?}"))?;
+        }
+        Ok(subscription)
+    }
+
+    fn matches(&self, update: &Encoded) -> bool {
+        fn any(values: &[String], value: Option<&String>) -> bool {
+            values.is_empty() || value.is_some_and(|value| values.contains(value))
+        }
+
+        // Rollbacks and gaps are not matched by filters and concern every client
+        (self.types.is_empty() || self.types.iter().any(|kind| kind == update.kind))
+            && (self.filters.is_empty()
+                || matches!(update.kind, "slot_rolled_back" | "gap")
+                || update
+                    .filters
+                    .iter()
+                    .any(|filter| self.filters.contains(filter)))
+            && (update.kind != "account"
+                || (any(&self.accounts, update.pubkey.as_ref())
+                    && any(&self.owners, update.owner.as_ref())))
+    }
+}
+
+#[derive(Debug)]
+pub struct Broadcast {
+    addr: SocketAddr,
+    tx: broadcast::Sender<Arc<Encoded>>,
+    json: JsonOptions,
+    truncate: FieldBudget,
+    clients: AtomicU64,
+    /// Updates skipped by clients which fell behind
+    skipped: AtomicU64,
+}
+
+impl Broadcast {
+    pub fn from_env(addr: SocketAddr) -> anyhow::Result<Self> {
+        let buffer = match env::var("SERVE_CLIENT_BUFFER") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|buffer| *buffer > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid SERVE_CLIENT_BUFFER"))?,
+            Err(_) => DEFAULT_CLIENT_BUFFER,
+        };
+        Ok(Self {
+            addr,
+            tx: broadcast::channel(buffer).0,
+            json: JsonOptions::from_env()?,
+            truncate: FieldBudget::from_env()?,
+            clients: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        })
+    }
+
+    /// Number of connected WebSocket clients
+    pub fn clients(&self) -> u64 {
+        self.clients.load(Ordering::Relaxed)
+    }
+}
+
+/// Sends updates to the connected clients, nothing is encoded while there are none
+pub struct ServeSink(pub Arc<Broadcast>);
+
+impl UpdateSink for ServeSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        if self.0.tx.receiver_count() == 0 {
+            return Ok(());
+        }
+        let truncated = self.0.truncate.apply(msg);
+        let (msg, truncated) = match truncated.as_ref() {
+            Some((msg, truncated)) => (msg, Some(truncated)),
+            None => (msg, None),
+        };
+        let (Some(update), Some(value)) = (
+            msg.update_oneof.as_ref(),
+            update_json(msg, &self.0.json, truncated),
+        ) else {
+            return Ok(());
+        };
+        let account = match update {
+            UpdateOneof::Account(update) => update.account.as_ref(),
+            _ => None,
+        };
+        let _ = self.0.tx.send(Arc::new(Encoded {
+            kind: update_kind(update),
+            filters: msg.filters.clone(),
+            pubkey: account.map(|account| bs58::encode(&account.pubkey).into_string()),
+            owner: account.map(|account| bs58::encode(&account.owner).into_string()),
+            text: value.to_string().into(),
+        }));
+        Ok(())
+    }
+
+    fn rollback(&self, rollback: &SlotRollback) {
+        if self.0.tx.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.0.tx.send(Arc::new(Encoded {
+            kind: "slot_rolled_back",
+            filters: vec![],
+            pubkey: None,
+            owner: None,
+            text: rollback.to_json().to_string().into(),
+        }));
+    }
+
+    fn gap(&self, gap: &Gap) {
+        if self.0.tx.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.0.tx.send(Arc::new(Encoded {
+            kind: "gap",
+            filters: vec![],
+            pubkey: None,
+            owner: None,
+            text: gap.to_json().to_string().into(),
+        }));
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "serve",
+            dropped: self.0.skipped.load(Ordering::Relaxed),
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+}
+
+/// Bind `SERVE_ADDR` and serve WebSocket clients on `/` in background
+pub async fn spawn(broadcast: Arc<Broadcast>) -> anyhow::Result<JoinHandle<()>> {
+    let addr = broadcast.addr;
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|error| anyhow::anyhow!("failed to bind SERVE_ADDR {addr}: {error}"))?;
+    info!("websocket server listening on ws://{addr}");
+
+    let app = Router::new().route("/", get(upgrade)).with_state(broadcast);
+    Ok(tokio::spawn(async move {
+        if let Err(error) = axum::serve(listener, app).await {
+            error!("websocket server failed: {error}");
+        }
+    }))
+}
+
+async fn upgrade(State(broadcast): State<Arc<Broadcast>>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| serve_client(socket, broadcast))
+}
+
+async fn serve_client(mut socket: WebSocket, broadcast: Arc<Broadcast>) {
+    let mut rx = broadcast.tx.subscribe();
+    broadcast.clients.fetch_add(1, Ordering::Relaxed);
+    let mut subscription = Subscription::default();
+
+    loop {
+        let message = tokio::select! {
+            update = rx.recv() => match update {
+                Ok(update) if subscription.matches(&update) => update.text.clone(),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    broadcast.skipped.fetch_add(skipped, Ordering::Relaxed);
+                    json!({ "event": "lagged", "skipped": skipped }).to_string().into()
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match Subscription::parse(&text) {
+                    Ok(new) => {
+                        subscription = new;
+                        json!({ "event": "subscribed", "subscription": subscription })
+                            .to_string()
+                            .into()
+                    }
+                    Err(error) => json!({ "event": "error", "error": error.to_string() })
+                        .to_string()
+                        .into(),
+                },
+                // Pings are answered by axum
+                Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+        };
+        if socket.send(Message::Text(message)).await.is_err() {
+            break;
+        }
+    }
+
+    broadcast.clients.fetch_sub(1, Ordering::Relaxed);
+}
@@ -0,0 +1,117 @@
+use {
+    crate::logging,
+    serde::{Deserialize, Serialize},
+    std::sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+/// Settings which can be changed through the admin API without restarting the stream.
+#[derive(Debug)]
+pub struct RuntimeSettings {
+    log_filter: RwLock<String>,
+    log_sample_rate: AtomicU64,
+    pretty: AtomicBool,
+    parse_instructions: AtomicBool,
+    balance_changes: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsSnapshot {
+    /// `RUST_LOG`-style filter directives
+    pub log_filter: String,
+    /// Fraction of stream updates written to the log, `0.0..=1.0`
+    pub log_sample_rate: f64,
+    /// Log updates with `{:#?}` instead of `{:?}`
+    pub pretty: bool,
+    /// Log decoded instructions of well-known programs with transactions
+    pub parse_instructions: bool,
+    /// Log SOL and token balance changes with transactions
+    pub balance_changes: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SettingsPatch {
+    pub log_filter: Option<String>,
+    pub log_sample_rate: Option<f64>,
+    pub pretty: Option<bool>,
+    pub parse_instructions: Option<bool>,
+    pub balance_changes: Option<bool>,
+}
+
+impl RuntimeSettings {
+    pub fn new(log_filter: String, parse_instructions: bool, balance_changes: bool) -> Self {
+        Self {
+            log_filter: RwLock::new(log_filter),
+            log_sample_rate: AtomicU64::new(1f64.to_bits()),
+            pretty: AtomicBool::new(true),
+            parse_instructions: AtomicBool::new(parse_instructions),
+            balance_changes: AtomicBool::new(balance_changes),
+        }
+    }
+
+    pub fn snapshot(&self) -> SettingsSnapshot {
+        SettingsSnapshot {
+            log_filter: self.log_filter.read().expect("poisoned").clone(),
+            log_sample_rate: self.log_sample_rate(),
+            pretty: self.pretty(),
+            parse_instructions: self.parse_instructions(),
+            balance_changes: self.balance_changes(),
+        }
+    }
+
+    pub fn log_sample_rate(&self) -> f64 {
+        f64::from_bits(self.log_sample_rate.load(Ordering::Relaxed))
+    }
+
+    pub fn pretty(&self) -> bool {
+        self.pretty.load(Ordering::Relaxed)
+    }
+
+    pub fn parse_instructions(&self) -> bool {
+        self.parse_instructions.load(Ordering::Relaxed)
+    }
+
+    pub fn balance_changes(&self) -> bool {
+        self.balance_changes.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if the current update should not be logged according to `log_sample_rate`.
+    pub fn log_sampled_out(&self) -> bool {
+        let rate = self.log_sample_rate();
+        rate < 1.0 && rand::random::<f64>() >= rate
+    }
+
+    /// Validate and apply a partial update, returning the resulting settings.
+    pub fn apply(&self, patch: SettingsPatch) -> anyhow::Result<SettingsSnapshot> {
+        if let Some(rate) = patch.log_sample_rate {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&rate),
+                "log_sample_rate should be in range 0.0..=1.0"
+            );
+        }
+
+        if let Some(log_filter) = patch.log_filter {
+            logging::set_filters(&log_filter)?;
+            *self.log_filter.write().expect("poisoned") = log_filter;
+        }
+        if let Some(rate) = patch.log_sample_rate {
+            self.log_sample_rate
+                .store(rate.to_bits(), Ordering::Relaxed);
+        }
+        if let Some(pretty) = patch.pretty {
+            self.pretty.store(pretty, Ordering::Relaxed);
+        }
+        if let Some(parse_instructions) = patch.parse_instructions {
+            self.parse_instructions
+                .store(parse_instructions, Ordering::Relaxed);
+        }
+        if let Some(balance_changes) = patch.balance_changes {
+            self.balance_changes
+                .store(balance_changes, Ordering::Relaxed);
+        }
+
+        Ok(self.snapshot())
+    }
+}
@@ -0,0 +1,46 @@
+use {
+    log::{info, warn},
+    std::sync::Arc,
+    tokio::sync::watch,
+};
+
+/// Spawn a task which waits for SIGINT/SIGTERM. The returned channel switches to `true`
+/// on the first signal, the second signal terminates the process immediately. Shutdown
+/// can also be requested by sending `true`.
+pub fn spawn_signal_handler() -> Arc<watch::Sender<bool>> {
+    let tx = Arc::new(watch::Sender::new(false));
+    let signal_tx = Arc::clone(&tx);
+    tokio::spawn(async move {
+        let tx = signal_tx;
+        loop {
+            if let Err(error) = wait_signal().await {
+                warn!("failed to listen for shutdown signals: {error}");
+                return;
+            }
+
+            if *tx.borrow() {
+                warn!("second shutdown signal received, exit immediately");
+                std::process::exit(130);
+            }
+            info!("shutdown signal received");
+            tx.send_replace(true);
+        }
+    });
+    tx
+}
+
+#[cfg(unix)]
+async fn wait_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = sigterm.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
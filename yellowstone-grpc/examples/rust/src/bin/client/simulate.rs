@@ -0,0 +1,398 @@
+//! Estimate message volume of a proposed filter config from recorded traffic.
+//!
+//! Filters of the `SubscribeRequest` built from the current environment are evaluated
+//! locally against every message of a capture file and compared with the filters recorded
+//! in `SubscribeUpdate::filters`. The capture only contains messages matched by the filters
+//! used while recording, so a broader filter can not be estimated beyond that traffic.
+
+use {
+    crate::capture::CaptureReader,
+    serde_json::{json, Map, Value},
+    std::collections::{BTreeMap, HashSet},
+    yellowstone_grpc_proto::{
+        prelude::{
+            subscribe_request_filter_accounts_filter::Filter as AccountsFilterDataOneof,
+            subscribe_request_filter_accounts_filter_memcmp::Data as AccountsFilterMemcmpOneof,
+            subscribe_update::UpdateOneof, SubscribeRequest, SubscribeRequestFilterAccounts,
+            SubscribeRequestFilterTransactions, SubscribeUpdate, SubscribeUpdateAccountInfo,
+        },
+        prost::Message,
+    },
+};
+
+/// Size of SPL token account and offset of its state
+const TOKEN_ACCOUNT_LEN: usize = 165;
+const TOKEN_ACCOUNT_STATE_OFFSET: usize = 108;
+
+fn decode_pubkeys(values: &[String]) -> anyhow::Result<HashSet<Vec<u8>>> {
+    values
+        .iter()
+        .map(|value| {
+            bs58::decode(value)
+                .into_vec()
+                .map_err(|_| anyhow::anyhow!("invalid pubkey: {value}"))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum AccountCondition {
+    Memcmp { offset: usize, data: Vec<u8> },
+    Datasize(usize),
+    TokenAccountState,
+}
+
+#[derive(Debug)]
+struct AccountsMatcher {
+    account: HashSet<Vec<u8>>,
+    owner: HashSet<Vec<u8>>,
+    conditions: Vec<AccountCondition>,
+}
+
+impl AccountsMatcher {
+    fn new(filter: &SubscribeRequestFilterAccounts) -> anyhow::Result<Self> {
+        let mut conditions = vec![];
+        for filter in filter.filters.iter() {
+            conditions.push(match filter.filter.as_ref() {
+                Some(AccountsFilterDataOneof::Memcmp(memcmp)) => AccountCondition::Memcmp {
+                    offset: memcmp.offset as usize,
+                    data: match memcmp.data.as_ref() {
+                        Some(AccountsFilterMemcmpOneof::Bytes(data)) => data.clone(),
+                        Some(AccountsFilterMemcmpOneof::Base58(data)) => bs58::decode(data)
+                            .into_vec()
+                            .map_err(|_| anyhow::anyhow!("invalid memcmp data: {data}"))?,
+                        _ => anyhow::bail!("unsupported memcmp data"),
+                    },
+                },
+                Some(AccountsFilterDataOneof::Datasize(size)) => {
+                    AccountCondition::Datasize(*size as usize)
+                }
+                Some(AccountsFilterDataOneof::TokenAccountState(true)) => {
+                    AccountCondition::TokenAccountState
+                }
+                _ => continue,
+            });
+        }
+
+        Ok(Self {
+            account: decode_pubkeys(&filter.account)?,
+            owner: decode_pubkeys(&filter.owner)?,
+            conditions,
+        })
+    }
+
+    fn matches(&self, account: &SubscribeUpdateAccountInfo) -> bool {
+        (self.account.is_empty() || self.account.contains(&account.pubkey))
+            && (self.owner.is_empty() || self.owner.contains(&account.owner))
+            && self.conditions.iter().all(|condition| match condition {
+                AccountCondition::Memcmp { offset, data } => account
+                    .data
+                    .get(*offset..*offset + data.len())
+                    .is_some_and(|slice| slice == data.as_slice()),
+                AccountCondition::Datasize(size) => account.data.len() == *size,
+                AccountCondition::TokenAccountState => {
+                    account.data.len() == TOKEN_ACCOUNT_LEN
+                        && account.data[TOKEN_ACCOUNT_STATE_OFFSET] != 0
+                }
+            })
+    }
+}
+
+#[derive(Debug)]
+struct TransactionsMatcher {
+    vote: Option<bool>,
+    failed: Option<bool>,
+    signature: Option<Vec<u8>>,
+    account_include: HashSet<Vec<u8>>,
+    account_exclude: HashSet<Vec<u8>>,
+    account_required: HashSet<Vec<u8>>,
+}
+
+impl TransactionsMatcher {
+    fn new(filter: &SubscribeRequestFilterTransactions) -> anyhow::Result<Self> {
+        Ok(Self {
+            vote: filter.vote,
+            failed: filter.failed,
+            signature: filter
+                .signature
+                .as_ref()
+                .map(|signature| {
+                    bs58::decode(signature)
+                        .into_vec()
+                        .map_err(|_| anyhow::anyhow!("invalid signature: {signature}"))
+                })
+                .transpose()?,
+            account_include: decode_pubkeys(&filter.account_include)?,
+            account_exclude: decode_pubkeys(&filter.account_exclude)?,
+            account_required: decode_pubkeys(&filter.account_required)?,
+        })
+    }
+
+    fn has_account_conditions(&self) -> bool {
+        !(self.account_include.is_empty()
+            && self.account_exclude.is_empty()
+            && self.account_required.is_empty())
+    }
+
+    /// `accounts` is `None` for transaction statuses, account conditions are ignored then
+    fn matches(
+        &self,
+        is_vote: bool,
+        failed: bool,
+        signature: &[u8],
+        accounts: Option<&HashSet<&[u8]>>,
+    ) -> bool {
+        if self.vote.is_some_and(|vote| vote != is_vote)
+            || self.failed.is_some_and(|expected| expected != failed)
+            || self
+                .signature
+                .as_ref()
+                .is_some_and(|expected| expected != signature)
+        {
+            return false;
+        }
+
+        let Some(accounts) = accounts else {
+            return true;
+        };
+        (self.account_include.is_empty()
+            || self
+                .account_include
+                .iter()
+                .any(|key| accounts.contains(key.as_slice())))
+            && !self
+                .account_exclude
+                .iter()
+                .any(|key| accounts.contains(key.as_slice()))
+            && self
+                .account_required
+                .iter()
+                .all(|key| accounts.contains(key.as_slice()))
+    }
+}
+
+/// Filters of the proposed `SubscribeRequest` by name
+#[derive(Debug)]
+struct ProposedFilters {
+    accounts: Vec<(String, AccountsMatcher)>,
+    transactions: Vec<(String, TransactionsMatcher)>,
+    transactions_status: Vec<(String, TransactionsMatcher)>,
+    slots: Vec<String>,
+    blocks: Vec<String>,
+    blocks_meta: Vec<String>,
+    entry: Vec<String>,
+}
+
+impl ProposedFilters {
+    fn new(request: &SubscribeRequest) -> anyhow::Result<Self> {
+        Ok(Self {
+            accounts: request
+                .accounts
+                .iter()
+                .map(|(name, filter)| Ok((name.clone(), AccountsMatcher::new(filter)?)))
+                .collect::<anyhow::Result<_>>()?,
+            transactions: request
+                .transactions
+                .iter()
+                .map(|(name, filter)| Ok((name.clone(), TransactionsMatcher::new(filter)?)))
+                .collect::<anyhow::Result<_>>()?,
+            transactions_status: request
+                .transactions_status
+                .iter()
+                .map(|(name, filter)| Ok((name.clone(), TransactionsMatcher::new(filter)?)))
+                .collect::<anyhow::Result<_>>()?,
+            slots: request.slots.keys().cloned().collect(),
+            blocks: request.blocks.keys().cloned().collect(),
+            blocks_meta: request.blocks_meta.keys().cloned().collect(),
+            entry: request.entry.keys().cloned().collect(),
+        })
+    }
+
+    /// Names of proposed filters whose results can not be computed exactly
+    fn approximate(&self) -> Vec<&str> {
+        let status = self
+            .transactions_status
+            .iter()
+            .filter(|(_, matcher)| matcher.has_account_conditions())
+            .map(|(name, _)| name.as_str());
+        // Account filter of blocks is not evaluated, every block matches
+        status
+            .chain(self.blocks.iter().map(String::as_str))
+            .collect()
+    }
+
+    fn matches<'a>(&'a self, msg: &SubscribeUpdate) -> Vec<&'a str> {
+        fn names(filters: &[String]) -> Vec<&str> {
+            filters.iter().map(String::as_str).collect()
+        }
+
+        match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Account(update)) => {
+                let Some(account) = update.account.as_ref() else {
+                    return vec![];
+                };
+                self.accounts
+                    .iter()
+                    .filter(|(_, matcher)| matcher.matches(account))
+                    .map(|(name, _)| name.as_str())
+                    .collect()
+            }
+            Some(UpdateOneof::Transaction(update)) => {
+                let Some(tx) = update.transaction.as_ref() else {
+                    return vec![];
+                };
+                let meta = tx.meta.as_ref();
+                let failed = meta.is_some_and(|meta| meta.err.is_some());
+                let accounts = tx
+                    .transaction
+                    .as_ref()
+                    .and_then(|tx| tx.message.as_ref())
+                    .map(|message| message.account_keys.iter())
+                    .into_iter()
+                    .flatten()
+                    .chain(meta.into_iter().flat_map(|meta| {
+                        meta.loaded_writable_addresses
+                            .iter()
+                            .chain(meta.loaded_readonly_addresses.iter())
+                    }))
+                    .map(Vec::as_slice)
+                    .collect::<HashSet<_>>();
+                self.transactions
+                    .iter()
+                    .filter(|(_, matcher)| {
+                        matcher.matches(tx.is_vote, failed, &tx.signature, Some(&accounts))
+                    })
+                    .map(|(name, _)| name.as_str())
+                    .collect()
+            }
+            Some(UpdateOneof::TransactionStatus(status)) => self
+                .transactions_status
+                .iter()
+                .filter(|(_, matcher)| {
+                    matcher.matches(
+                        status.is_vote,
+                        status.err.is_some(),
+                        &status.signature,
+                        None,
+                    )
+                })
+                .map(|(name, _)| name.as_str())
+                .collect(),
+            Some(UpdateOneof::Slot(_)) => names(&self.slots),
+            Some(UpdateOneof::Block(_)) => names(&self.blocks),
+            Some(UpdateOneof::BlockMeta(_)) => names(&self.blocks_meta),
+            Some(UpdateOneof::Entry(_)) => names(&self.entry),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Volume {
+    messages: u64,
+    bytes: u64,
+}
+
+impl Volume {
+    fn add(&mut self, bytes: u64) {
+        self.messages += 1;
+        self.bytes += bytes;
+    }
+
+    fn to_json(self) -> Value {
+        json!({ "messages": self.messages, "bytes": self.bytes })
+    }
+}
+
+#[derive(Debug, Default)]
+struct FilterReport {
+    current: Volume,
+    proposed: Volume,
+    /// Matched by the proposed filter, but recorded without a filter of the same name
+    gained: u64,
+    /// Recorded with this filter name, but not matched by the proposed filter
+    lost: u64,
+    accounts: HashSet<Vec<u8>>,
+}
+
+/// Read the whole capture and compare recorded filters with filters of `request`
+pub fn simulate(path: &str, request: &SubscribeRequest) -> anyhow::Result<Value> {
+    let proposed = ProposedFilters::new(request)?;
+    let mut reader = CaptureReader::open(path)?;
+
+    let mut first_timestamp = None;
+    let mut last_timestamp = 0;
+    let mut recorded = Volume::default();
+    let mut kept = Volume::default();
+    let mut removed = Volume::default();
+    let mut removed_accounts = HashSet::new();
+    let mut filters: BTreeMap<String, FilterReport> = BTreeMap::new();
+    while let Some((timestamp, msg)) = reader.read()? {
+        first_timestamp.get_or_insert(timestamp);
+        last_timestamp = timestamp;
+        // Pings and pongs are sent regardless of filters
+        if msg.filters.is_empty() {
+            continue;
+        }
+
+        let bytes = msg.encoded_len() as u64;
+        let pubkey = match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Account(update)) => {
+                update.account.as_ref().map(|account| &account.pubkey)
+            }
+            _ => None,
+        };
+        recorded.add(bytes);
+        for name in msg.filters.iter() {
+            filters.entry(name.clone()).or_default().current.add(bytes);
+        }
+
+        let matched = proposed.matches(&msg);
+        if matched.is_empty() {
+            removed.add(bytes);
+            removed_accounts.extend(pubkey.cloned());
+        } else {
+            kept.add(bytes);
+        }
+        for name in matched.iter() {
+            let report = filters.entry((*name).to_owned()).or_default();
+            report.proposed.add(bytes);
+            report.accounts.extend(pubkey.cloned());
+            if !msg.filters.iter().any(|recorded| recorded == name) {
+                report.gained += 1;
+            }
+        }
+        for name in msg.filters.iter() {
+            if !matched.contains(&name.as_str()) {
+                filters.entry(name.clone()).or_default().lost += 1;
+            }
+        }
+    }
+
+    let duration_secs =
+        last_timestamp.saturating_sub(first_timestamp.unwrap_or_default()) as f64 / 1_000_000.0;
+    let filters = filters
+        .into_iter()
+        .map(|(name, report)| {
+            let value = json!({
+                "current": report.current.to_json(),
+                "proposed": report.proposed.to_json(),
+                "gained": report.gained,
+                "lost": report.lost,
+                "accounts": report.accounts.len(),
+            });
+            (name, value)
+        })
+        .collect::<Map<_, _>>();
+    Ok(json!({
+        "path": path,
+        "duration_secs": duration_secs,
+        "recorded": recorded.to_json(),
+        "proposed": kept.to_json(),
+        "removed": removed.to_json(),
+        "removed_accounts": removed_accounts.len(),
+        "bytes_ratio": (recorded.bytes > 0).then(|| kept.bytes as f64 / recorded.bytes as f64),
+        "filters": filters,
+        "approximate": proposed.approximate(),
+    }))
+}
@@ -0,0 +1,560 @@
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+pub mod csv;
+pub mod hook;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod notify;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(unix)]
+pub mod socket;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod watchlist;
+
+use {
+    crate::{
+        forks::SlotRollback,
+        gaps::Gap,
+        output::OutputFormat,
+        policy::{ErrorPolicy, Stage},
+        stats::update_slot,
+        tags::FilterTags,
+    },
+    futures::future::{join_all, BoxFuture, FutureExt},
+    log::{info, warn},
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        env, fmt,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+/// Destinations by filter group, `<SINK>_NAMESPACE_BY_FILTER=true` or
+/// `NAMESPACE_BY_FILTER=true` for all sinks which support it. An update is written to the
+/// destination of every filter it matched, named with the filter name as suffix, e.g. topic
+/// `grpc.account.whales`; updates which matched no filter go to the destination without
+/// suffix.
+// Only feature sinks write to namespaced destinations
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    not(any(
+        feature = "clickhouse",
+        feature = "kafka",
+        feature = "parquet",
+        feature = "postgres",
+        feature = "redis"
+    )),
+    allow(dead_code)
+)]
+pub struct Namespaces {
+    enabled: bool,
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "clickhouse",
+        feature = "kafka",
+        feature = "parquet",
+        feature = "postgres",
+        feature = "redis"
+    )),
+    allow(dead_code)
+)]
+impl Namespaces {
+    pub fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let parse = |key: String| match env::var(&key).as_deref() {
+            Ok("true") => Ok(Some(true)),
+            Ok("false") => Ok(Some(false)),
+            Ok(_) => Err(anyhow::anyhow!("invalid {key}")),
+            Err(_) => Ok(None),
+        };
+        let enabled = match parse(format!("{prefix}NAMESPACE_BY_FILTER"))? {
+            Some(enabled) => enabled,
+            None => parse("NAMESPACE_BY_FILTER".to_owned())?.unwrap_or(false),
+        };
+        Ok(Self { enabled })
+    }
+
+    /// Used by sinks which create their destinations
+    #[cfg_attr(not(feature = "clickhouse"), allow(dead_code))]
+    pub const fn enabled(self) -> bool {
+        self.enabled
+    }
+
+    /// Suffixes of the destinations of `msg`, `None` is the destination without suffix
+    pub fn of(self, msg: &SubscribeUpdate) -> Vec<Option<String>> {
+        if !self.enabled || msg.filters.is_empty() {
+            return vec![None];
+        }
+        let mut namespaces = msg
+            .filters
+            .iter()
+            .map(|filter| Some(namespace(filter)))
+            .collect::<Vec<_>>();
+        namespaces.sort_unstable();
+        namespaces.dedup();
+        namespaces
+    }
+}
+
+/// Filter name usable in topic, table and key names: lowercase ASCII letters, digits and `_`
+#[cfg_attr(
+    not(any(
+        feature = "clickhouse",
+        feature = "kafka",
+        feature = "parquet",
+        feature = "postgres",
+        feature = "redis"
+    )),
+    allow(dead_code)
+)]
+fn namespace(filter: &str) -> String {
+    filter
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9') => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Counters of a sink, shown by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct SinkHealth {
+    pub name: &'static str,
+    /// Updates dropped because the sink queue was full
+    pub dropped: u64,
+    /// Failed writes or requests
+    pub errors: u64,
+    /// Highest slot with all updates acknowledged, `None` if the sink does not track acks
+    /// or nothing was acknowledged yet
+    pub acked_slot: Option<u64>,
+    /// Counters of a sink in dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<DryRunStats>,
+}
+
+/// Logged part of sampled dry run payloads, in characters
+const DRY_RUN_LOG_CHARS: usize = 2_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunStats {
+    /// Payloads which would have been sent
+    pub payloads: u64,
+    pub bytes: u64,
+    /// Payloads the destination would have rejected
+    pub invalid: u64,
+}
+
+/// Dry run of a sink, `<SINK>_DRY_RUN=true`. Payloads are built and validated as for the
+/// destination but not sent: they are counted, the first one and every
+/// `DRY_RUN_LOG_EVERY`-th after it are logged (1000 by default, 0 logs none) and the ones
+/// which fail validation are counted as invalid.
+#[derive(Debug)]
+pub struct DryRun {
+    sink: &'static str,
+    log_every: u64,
+    payloads: AtomicU64,
+    bytes: AtomicU64,
+    invalid: AtomicU64,
+}
+
+impl DryRun {
+    /// Returns `None` if `<prefix>DRY_RUN` is not `true`
+    pub fn from_env(prefix: &str, sink: &'static str) -> anyhow::Result<Option<Self>> {
+        let key = format!("{prefix}DRY_RUN");
+        let enabled = env::var(&key)
+            .ok()
+            .map(|value| value.parse::<bool>())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("invalid {key}"))?
+            .unwrap_or(false);
+        if !enabled {
+            return Ok(None);
+        }
+        let log_every = env::var("DRY_RUN_LOG_EVERY")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("invalid DRY_RUN_LOG_EVERY"))?
+            .unwrap_or(1_000);
+        warn!("{sink} sink: dry run, nothing is written to the destination");
+        Ok(Some(Self {
+            sink,
+            log_every,
+            payloads: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            invalid: AtomicU64::new(0),
+        }))
+    }
+
+    /// Payload of `len` bytes which would have been sent to `target`, a topic, table or
+    /// channel. `describe` renders it for the log and is only called for sampled payloads.
+    pub fn sent(&self, target: &str, len: usize, describe: impl FnOnce() -> String) {
+        let count = self.payloads.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        // Nothing with `DRY_RUN_LOG_EVERY=0`
+        if count.checked_rem(self.log_every) == Some(0) {
+            let payload = describe();
+            let shown = payload.chars().take(DRY_RUN_LOG_CHARS).collect::<String>();
+            let more = if shown.len() < payload.len() {
+                " ..."
+            } else {
+                ""
+            };
+            info!(
+                "{} dry run: {target}, {len} bytes, payload {}: {shown}{more}",
+                self.sink,
+                count + 1
+            );
+        }
+    }
+
+    /// Payload for `target` which the destination would have rejected, only features validate
+    #[cfg_attr(
+        not(any(
+            feature = "clickhouse",
+            feature = "kafka",
+            feature = "postgres",
+            feature = "redis"
+        )),
+        allow(dead_code)
+    )]
+    pub fn invalid(&self, target: &str, error: impl fmt::Display) {
+        let invalid = self.invalid.fetch_add(1, Ordering::Relaxed) + 1;
+        if invalid % 1_000 == 1 {
+            warn!(
+                "{} dry run: invalid payload for {target}: {error}, {invalid} invalid in total",
+                self.sink
+            );
+        }
+    }
+
+    pub fn stats(&self) -> DryRunStats {
+        DryRunStats {
+            payloads: self.payloads.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AckState {
+    /// Updates sent to the destination and not acknowledged yet, by slot
+    pending: BTreeMap<u64, u64>,
+    /// Highest slot passed to the sink
+    highest: Option<u64>,
+}
+
+/// Highest slot a sink has fully acknowledged. A sink calls `sent` when it takes an update
+/// and `acked` once the destination confirmed it; updates which are lost (dropped by a full
+/// queue or failed writes) are never acknowledged and hold the slot back.
+#[derive(Debug, Default)]
+pub struct AckTracker {
+    state: Mutex<AckState>,
+}
+
+impl AckTracker {
+    pub fn sent(&self, slot: u64) {
+        *self
+            .state
+            .lock()
+            .expect("poisoned")
+            .pending
+            .entry(slot)
+            .or_default() += 1;
+    }
+
+    // Only sinks which write in background acknowledge later, all of them are features
+    #[cfg_attr(
+        not(any(
+            feature = "clickhouse",
+            feature = "kafka",
+            feature = "parquet",
+            feature = "postgres",
+            feature = "redis",
+            feature = "sqlite"
+        )),
+        allow(dead_code)
+    )]
+    pub fn acked(&self, slot: u64) {
+        let mut state = self.state.lock().expect("poisoned");
+        if let Some(count) = state.pending.get_mut(&slot) {
+            *count -= 1;
+            if *count == 0 {
+                state.pending.remove(&slot);
+            }
+        }
+    }
+
+    /// Called for every update passed to the sink, after `handle`
+    fn observe(&self, slot: u64) {
+        let mut state = self.state.lock().expect("poisoned");
+        state.highest = state.highest.max(Some(slot));
+    }
+
+    pub fn acked_slot(&self) -> Option<u64> {
+        let state = self.state.lock().expect("poisoned");
+        match state.pending.first_key_value() {
+            Some((slot, _)) => slot.checked_sub(1).min(state.highest),
+            None => state.highest,
+        }
+    }
+}
+
+/// Destination for received updates. `handle` is called on the stream task and should
+/// not block, slow sinks are expected to queue updates and write them in background.
+pub trait UpdateSink: Send + Sync {
+    /// An error, like a panic, is handled by `ERROR_POLICY_SINK` and the update may be
+    /// passed again. Updates dropped by a full queue are counted, not returned as errors.
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()>;
+
+    fn health(&self) -> SinkHealth;
+
+    /// Slots which will never be finalized, updates of them received before are stale
+    fn rollback(&self, _rollback: &SlotRollback) {}
+
+    /// Slots which were not received, or an account update out of write version order
+    fn gap(&self, _gap: &Gap) {}
+
+    /// Acknowledgements of durable sinks, `None` for sinks which are not part of the
+    /// checkpoint, like sinks in dry run
+    fn acks(&self) -> Option<&AckTracker> {
+        None
+    }
+
+    /// Check that the destination is reachable and writable, called before subscribing
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+
+    /// Write queued updates, called once before exit
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {}.boxed()
+    }
+}
+
+/// Fails if `key` enables a sink which was not compiled in, instead of running without it
+#[cfg_attr(
+    all(
+        feature = "clickhouse",
+        feature = "kafka",
+        feature = "parquet",
+        feature = "postgres",
+        feature = "redis",
+        feature = "sqlite"
+    ),
+    allow(dead_code)
+)]
+fn ensure_feature(key: &str, feature: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        env::var_os(key).is_none(),
+        "{key} is set, but the client was built without the `{feature}` feature"
+    );
+    Ok(())
+}
+
+/// All sinks enabled by `OUTPUT`, cargo features and environment variables
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<Box<dyn UpdateSink>>,
+}
+
+impl Sinks {
+    pub async fn from_env(output: OutputFormat, tags: Arc<FilterTags>) -> anyhow::Result<Self> {
+        let mut sinks: Vec<Box<dyn UpdateSink>> = vec![];
+
+        if output == OutputFormat::Csv {
+            sinks.push(Box::new(csv::CsvSink::from_env(Arc::clone(&tags))?));
+        }
+
+        if let Some(watchlist) = tags.watchlist().filter(|watchlist| watchlist.has_alerts()) {
+            let config = watchlist::AlertConfig::from_env()?;
+            let sink = watchlist::WatchlistSink::spawn(Arc::clone(watchlist), config)?;
+            sinks.push(Box::new(sink));
+        }
+
+        if let Some(config) = notify::NotifyConfig::from_env()? {
+            sinks.push(Box::new(notify::NotifySink::spawn(config, tags)?));
+        }
+
+        if let Some(config) = hook::HookConfig::from_env()? {
+            sinks.push(Box::new(hook::HookSink::spawn(config)?));
+        }
+
+        #[cfg(unix)]
+        if let Some(config) = socket::SocketConfig::from_env()? {
+            sinks.push(Box::new(socket::SocketSink::spawn(config)?));
+        }
+
+        #[cfg(not(feature = "kafka"))]
+        ensure_feature("KAFKA_BROKERS", "kafka")?;
+        #[cfg(feature = "kafka")]
+        if let Some(config) = kafka::KafkaConfig::from_env()? {
+            sinks.push(Box::new(kafka::KafkaSink::spawn(config).await?));
+        }
+
+        #[cfg(not(feature = "postgres"))]
+        ensure_feature("POSTGRES_URL", "postgres")?;
+        #[cfg(feature = "postgres")]
+        if let Some(config) = postgres::PostgresConfig::from_env()? {
+            sinks.push(Box::new(postgres::PostgresSink::spawn(config).await?));
+        }
+
+        #[cfg(not(feature = "sqlite"))]
+        ensure_feature("SQLITE_PATH", "sqlite")?;
+        #[cfg(feature = "sqlite")]
+        if let Some(config) = sqlite::SqliteConfig::from_env()? {
+            sinks.push(Box::new(sqlite::SqliteSink::spawn(config).await?));
+        }
+
+        #[cfg(not(feature = "clickhouse"))]
+        ensure_feature("CLICKHOUSE_URL", "clickhouse")?;
+        #[cfg(feature = "clickhouse")]
+        if let Some(config) = clickhouse::ClickHouseConfig::from_env()? {
+            sinks.push(Box::new(clickhouse::ClickHouseSink::spawn(config).await?));
+        }
+
+        #[cfg(not(feature = "parquet"))]
+        ensure_feature("PARQUET_DIR", "parquet")?;
+        #[cfg(feature = "parquet")]
+        if let Some(config) = parquet::ParquetConfig::from_env()? {
+            sinks.push(Box::new(parquet::ParquetSink::spawn(config)?));
+        }
+
+        #[cfg(not(feature = "redis"))]
+        ensure_feature("REDIS_URL", "redis")?;
+        #[cfg(feature = "redis")]
+        if let Some(config) = redis::RedisConfig::from_env()? {
+            sinks.push(Box::new(redis::RedisSink::spawn(config).await?));
+        }
+
+        Ok(Self { sinks })
+    }
+
+    pub fn add(&mut self, sink: Box<dyn UpdateSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Pass updates to the sinks named in `names` first, in that order
+    pub fn prioritize(&mut self, names: &[String]) {
+        for name in names {
+            if !self.sinks.iter().any(|sink| sink.health().name == name) {
+                warn!("priority sink {name} is not enabled");
+            }
+        }
+        self.sinks.sort_by_key(|sink| {
+            let name = sink.health().name;
+            names
+                .iter()
+                .position(|priority| priority == name)
+                .unwrap_or(names.len())
+        });
+    }
+
+    pub fn handle(&self, msg: &SubscribeUpdate, errors: &ErrorPolicy) {
+        let slot = update_slot(msg);
+        for sink in self.sinks.iter() {
+            let handled = errors.run(Stage::Sink, msg, || sink.handle(msg)).is_some();
+            if let (Some(acks), Some(slot)) = (sink.acks(), slot) {
+                // A lost update is never acknowledged and holds the slot back
+                if !handled {
+                    acks.sent(slot);
+                }
+                acks.observe(slot);
+            }
+        }
+    }
+
+    pub fn rollback(&self, rollback: &SlotRollback) {
+        for sink in self.sinks.iter() {
+            sink.rollback(rollback);
+        }
+    }
+
+    pub fn gap(&self, gap: &Gap) {
+        for sink in self.sinks.iter() {
+            sink.gap(gap);
+        }
+    }
+
+    /// Pass the updates again to the sink named `name` only, other sinks don't see them
+    pub fn replay(&self, name: &str, updates: &[SubscribeUpdate]) -> anyhow::Result<()> {
+        let Some(sink) = self.sinks.iter().find(|sink| sink.health().name == name) else {
+            let names = self
+                .sinks
+                .iter()
+                .map(|sink| sink.health().name)
+                .collect::<Vec<_>>();
+            anyhow::bail!("unknown sink {name:?}, enabled sinks: {names:?}");
+        };
+        for msg in updates {
+            sink.handle(msg).map_err(|error| {
+                anyhow::anyhow!("failed at slot {:?}: {error:#}", update_slot(msg))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn health(&self) -> Vec<SinkHealth> {
+        self.sinks.iter().map(|sink| sink.health()).collect()
+    }
+
+    /// Highest slot acknowledged by at least `quorum` sinks which track acks, all of them
+    /// if `None`. Returns `None` if no sink tracks acks or the quorum has not acked yet.
+    pub fn checkpoint(&self, quorum: Option<usize>) -> Option<u64> {
+        let mut acked = self
+            .sinks
+            .iter()
+            .filter_map(|sink| sink.acks())
+            .map(AckTracker::acked_slot)
+            .collect::<Vec<_>>();
+        if acked.is_empty() {
+            return None;
+        }
+        // Descending, sinks which acked nothing yet are last
+        acked.sort_unstable_by(|a, b| b.cmp(a));
+        let quorum = quorum.unwrap_or(acked.len()).clamp(1, acked.len());
+        acked[quorum - 1]
+    }
+
+    /// Run checks of all sinks, the error lists every failed one
+    pub async fn preflight(&self) -> anyhow::Result<()> {
+        let results = join_all(self.sinks.iter().map(|sink| sink.preflight())).await;
+        let errors = self
+            .sinks
+            .iter()
+            .zip(results)
+            .filter_map(|(sink, result)| {
+                result
+                    .err()
+                    .map(|error| format!("{}: {error:#}", sink.health().name))
+            })
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            errors.is_empty(),
+            "sink preflight failed (set PREFLIGHT=false to skip):\n  {}",
+            errors.join("\n  ")
+        );
+        if !self.sinks.is_empty() {
+            info!("sink preflight passed");
+        }
+        Ok(())
+    }
+
+    pub async fn shutdown(&self) {
+        join_all(self.sinks.iter().map(|sink| sink.shutdown())).await;
+    }
+}
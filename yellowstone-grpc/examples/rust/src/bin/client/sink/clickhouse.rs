@@ -0,0 +1,659 @@
+use {
+    crate::sink::{AckTracker, DryRun, Namespaces, SinkHealth, UpdateSink},
+    chrono::{DateTime, Utc},
+    flate2::{write::GzEncoder, Compression},
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    serde::Serialize,
+    serde_json::Value,
+    std::{
+        collections::{BTreeMap, HashSet},
+        env,
+        io::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::{timeout_at, Instant},
+    },
+    yellowstone_grpc_proto::{
+        convert_from,
+        prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+    },
+};
+
+/// Tables created on start, names are substituted before applying
+const CREATE_TABLES: &str = include_str!("clickhouse/create_tables.sql");
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct ClickHouseConfig {
+    /// HTTP interface, e.g. `http://localhost:8123`
+    pub url: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub database: String,
+    pub accounts_table: String,
+    pub transactions_table: String,
+    pub blocks_meta_table: String,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub queue_size: usize,
+    /// Gzip request bodies
+    pub compress: bool,
+    /// Create missing tables on start, disabled with `CLICKHOUSE_CREATE_TABLES=false`
+    pub create_tables: bool,
+    /// Table of a filter group is `<table>_<filter>`
+    pub namespaces: Namespaces,
+}
+
+impl ClickHouseConfig {
+    /// Returns `None` if `CLICKHOUSE_URL` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = env::var("CLICKHOUSE_URL") else {
+            return Ok(None);
+        };
+
+        let parse_usize = |key: &str, default: usize| -> anyhow::Result<usize> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+                .map(|value| value.unwrap_or(default))
+        };
+        let parse_bool = |key: &str, default: bool| -> anyhow::Result<bool> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+                .map(|value| value.unwrap_or(default))
+        };
+        let table = |key: &str, default: &str| env::var(key).unwrap_or_else(|_| default.to_owned());
+
+        Ok(Some(Self {
+            url,
+            user: env::var("CLICKHOUSE_USER").ok(),
+            password: env::var("CLICKHOUSE_PASSWORD").ok(),
+            database: table("CLICKHOUSE_DATABASE", "default"),
+            accounts_table: table("CLICKHOUSE_ACCOUNTS_TABLE", "accounts"),
+            transactions_table: table("CLICKHOUSE_TRANSACTIONS_TABLE", "transactions"),
+            blocks_meta_table: table("CLICKHOUSE_BLOCKS_META_TABLE", "blocks_meta"),
+            batch_size: parse_usize("CLICKHOUSE_BATCH_SIZE", 100_000)?.max(1),
+            flush_interval: Duration::from_millis(parse_usize(
+                "CLICKHOUSE_FLUSH_INTERVAL_MS",
+                1_000,
+            )? as u64),
+            queue_size: parse_usize("CLICKHOUSE_QUEUE_SIZE", 1_000_000)?.max(1),
+            compress: parse_bool("CLICKHOUSE_COMPRESS", true)?,
+            create_tables: parse_bool("CLICKHOUSE_CREATE_TABLES", true)?,
+            namespaces: Namespaces::from_env("CLICKHOUSE_")?,
+        }))
+    }
+
+    fn tables(&self) -> [&str; 3] {
+        [
+            &self.accounts_table,
+            &self.transactions_table,
+            &self.blocks_meta_table,
+        ]
+    }
+
+    /// Table at `index` of `tables` for filter group `namespace`
+    fn table(&self, index: usize, namespace: Option<&str>) -> String {
+        let table = self.tables()[index];
+        match namespace {
+            Some(namespace) => format!("{table}_{namespace}"),
+            None => table.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AccountRow {
+    slot: u64,
+    pubkey: String,
+    owner: String,
+    lamports: u64,
+    executable: bool,
+    rent_epoch: u64,
+    /// Hex, `unhex(data)` in queries
+    data: String,
+    data_len: usize,
+    write_version: u64,
+    txn_signature: Option<String>,
+    is_startup: bool,
+    received_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TransactionRow {
+    slot: u64,
+    signature: String,
+    tx_index: u64,
+    is_vote: bool,
+    err: Option<String>,
+    fee: u64,
+    compute_units_consumed: Option<u64>,
+    signer: String,
+    /// Static keys followed by keys loaded from lookup tables, writable first
+    account_keys: Vec<String>,
+    instructions: usize,
+    inner_instructions: usize,
+    log_messages: usize,
+    received_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BlockMetaRow {
+    slot: u64,
+    blockhash: String,
+    parent_slot: u64,
+    parent_blockhash: String,
+    block_height: Option<u64>,
+    block_time: Option<i64>,
+    executed_transaction_count: u64,
+    entries_count: u64,
+    received_at: String,
+}
+
+#[derive(Debug, Clone)]
+enum Row {
+    Account(AccountRow),
+    Transaction(TransactionRow),
+    BlockMeta(BlockMetaRow),
+}
+
+/// Format of `DateTime64(3)` in `JSONEachRow`
+fn received_at(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+impl Row {
+    fn slot(&self) -> u64 {
+        match self {
+            Self::Account(row) => row.slot,
+            Self::Transaction(row) => row.slot,
+            Self::BlockMeta(row) => row.slot,
+        }
+    }
+
+    /// Index of the table in `ClickHouseConfig::tables`
+    fn table(&self) -> usize {
+        match self {
+            Self::Account(_) => 0,
+            Self::Transaction(_) => 1,
+            Self::BlockMeta(_) => 2,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Result<Value> {
+        match self {
+            Self::Account(row) => serde_json::to_value(row),
+            Self::Transaction(row) => serde_json::to_value(row),
+            Self::BlockMeta(row) => serde_json::to_value(row),
+        }
+    }
+
+    fn from_update(msg: &SubscribeUpdate) -> Option<Self> {
+        let encode = |bytes: &[u8]| bs58::encode(bytes).into_string();
+        let received_at = received_at(Utc::now());
+        match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                Some(Self::Account(AccountRow {
+                    slot: update.slot,
+                    pubkey: encode(&account.pubkey),
+                    owner: encode(&account.owner),
+                    lamports: account.lamports,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                    data: hex::encode(&account.data),
+                    data_len: account.data.len(),
+                    write_version: account.write_version,
+                    txn_signature: account.txn_signature.as_deref().map(encode),
+                    is_startup: update.is_startup,
+                    received_at,
+                }))
+            }
+            UpdateOneof::Transaction(update) => {
+                let tx = update.transaction.as_ref()?;
+                let message = tx.transaction.as_ref().and_then(|tx| tx.message.as_ref());
+                let meta = tx.meta.as_ref();
+                let account_keys = message
+                    .map(|message| message.account_keys.iter())
+                    .into_iter()
+                    .flatten()
+                    .chain(
+                        meta.map(|meta| {
+                            meta.loaded_writable_addresses
+                                .iter()
+                                .chain(meta.loaded_readonly_addresses.iter())
+                        })
+                        .into_iter()
+                        .flatten(),
+                    )
+                    .map(|key| encode(key))
+                    .collect::<Vec<_>>();
+                Some(Self::Transaction(TransactionRow {
+                    slot: update.slot,
+                    signature: encode(&tx.signature),
+                    tx_index: tx.index,
+                    is_vote: tx.is_vote,
+                    err: convert_from::create_tx_error(meta.and_then(|meta| meta.err.as_ref()))
+                        .ok()
+                        .flatten()
+                        .map(|err| err.to_string()),
+                    fee: meta.map_or(0, |meta| meta.fee),
+                    compute_units_consumed: meta.and_then(|meta| meta.compute_units_consumed),
+                    signer: account_keys.first().cloned().unwrap_or_default(),
+                    account_keys,
+                    instructions: message.map_or(0, |message| message.instructions.len()),
+                    inner_instructions: meta.map_or(0, |meta| {
+                        meta.inner_instructions
+                            .iter()
+                            .map(|inner| inner.instructions.len())
+                            .sum()
+                    }),
+                    log_messages: meta.map_or(0, |meta| meta.log_messages.len()),
+                    received_at,
+                }))
+            }
+            UpdateOneof::BlockMeta(update) => Some(Self::BlockMeta(BlockMetaRow {
+                slot: update.slot,
+                blockhash: update.blockhash.clone(),
+                parent_slot: update.parent_slot,
+                parent_blockhash: update.parent_blockhash.clone(),
+                block_height: update
+                    .block_height
+                    .as_ref()
+                    .map(|height| height.block_height),
+                block_time: update.block_time.as_ref().map(|time| time.timestamp),
+                executed_transaction_count: update.executed_transaction_count,
+                entries_count: update.entries_count,
+                received_at,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Rows of one table in `JSONEachRow` format
+#[derive(Debug, Default)]
+struct TableBatch {
+    body: Vec<u8>,
+    slots: Vec<u64>,
+}
+
+impl TableBatch {
+    fn push(&mut self, row: &impl Serialize, slot: u64) {
+        if serde_json::to_writer(&mut self.body, row).is_ok() {
+            self.body.push(b'\n');
+            self.slots.push(slot);
+        }
+    }
+}
+
+/// HTTP interface of ClickHouse
+struct Client {
+    http: reqwest::Client,
+    config: ClickHouseConfig,
+}
+
+impl Client {
+    fn new(config: ClickHouseConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            config,
+        })
+    }
+
+    /// Run `query` with `body` appended as data, returns the response text
+    async fn query(&self, query: &str, body: Vec<u8>) -> anyhow::Result<String> {
+        let mut request = self
+            .http
+            .post(&self.config.url)
+            .query(&[("query", query), ("database", &self.config.database)]);
+        if let Some(user) = self.config.user.as_ref() {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = self.config.password.as_ref() {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        let body = if self.config.compress && !body.is_empty() {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&body)?;
+            request = request.header("Content-Encoding", "gzip");
+            encoder.finish()?
+        } else {
+            body
+        };
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        anyhow::ensure!(status.is_success(), "{status}: {}", text.trim());
+        Ok(text)
+    }
+
+    async fn insert(&self, table: &str, batch: TableBatch) -> anyhow::Result<()> {
+        self.query(
+            &format!("INSERT INTO {table} FORMAT JSONEachRow"),
+            batch.body,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Table of a filter group with the columns and engine of `base`
+    async fn create_namespace(&self, table: &str, base: &str) -> anyhow::Result<()> {
+        self.query(
+            &format!("CREATE TABLE IF NOT EXISTS {table} AS {base}"),
+            vec![],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn create_tables(&self) -> anyhow::Result<()> {
+        let sql = CREATE_TABLES
+            .replace("{accounts_table}", &self.config.accounts_table)
+            .replace("{transactions_table}", &self.config.transactions_table)
+            .replace("{blocks_meta_table}", &self.config.blocks_meta_table);
+        // The HTTP interface runs one statement per request
+        for statement in sql.split(';').map(str::trim).filter(|sql| !sql.is_empty()) {
+            self.query(statement, vec![])
+                .await
+                .map_err(|error| anyhow::anyhow!("failed to create tables: {error}"))?;
+        }
+        Ok(())
+    }
+
+    /// Column names of the tables, in the order of `ClickHouseConfig::tables`
+    async fn columns(&self) -> anyhow::Result<[HashSet<String>; 3]> {
+        let mut columns: [HashSet<String>; 3] = Default::default();
+        for (table, names) in self.config.tables().into_iter().zip(columns.iter_mut()) {
+            let described = self
+                .query(
+                    &format!("DESCRIBE TABLE {table} FORMAT TabSeparated"),
+                    vec![],
+                )
+                .await?;
+            *names = described
+                .lines()
+                .filter_map(|line| line.split('\t').next())
+                .map(str::to_owned)
+                .collect();
+        }
+        Ok(columns)
+    }
+
+    async fn check_tables(&self) -> anyhow::Result<()> {
+        for table in self.config.tables() {
+            let exists = self.query(&format!("EXISTS TABLE {table}"), vec![]).await?;
+            anyhow::ensure!(
+                exists.trim() == "1",
+                "table {table} doesn't exist in database {}, see README or set \
+                CLICKHOUSE_CREATE_TABLES=true",
+                self.config.database
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Writes account updates, transactions and blocks meta to ClickHouse tables, one row per
+/// update.
+///
+/// Rows are queued in a bounded channel and inserted in batches by a background task, if the
+/// queue is full new rows are dropped so the gRPC stream is never blocked by the database.
+///
+/// With `CLICKHOUSE_NAMESPACE_BY_FILTER=true` rows are inserted into the table of every
+/// filter they matched, `<table>_<filter>`. These tables are created on first use as copies
+/// of the configured table if `CLICKHOUSE_CREATE_TABLES` is enabled.
+///
+/// With `CLICKHOUSE_DRY_RUN=true` rows are serialized and their fields checked against the
+/// columns of the tables but not inserted, tables are not created.
+pub struct ClickHouseSink {
+    client: Arc<Client>,
+    /// Rows with the filter group of their table
+    tx: mpsc::Sender<(Row, Option<String>)>,
+    dropped: AtomicU64,
+    /// Failed inserts
+    errors: Arc<AtomicU64>,
+    /// Rows are acknowledged once their insert succeeded
+    acks: Arc<AckTracker>,
+    dry_run: Option<DryRun>,
+    /// Columns of the tables in dry run, rows with other fields would be rejected
+    columns: [HashSet<String>; 3],
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ClickHouseSink {
+    pub async fn spawn(config: ClickHouseConfig) -> anyhow::Result<Self> {
+        let client = Arc::new(Client::new(config.clone())?);
+        client
+            .query("SELECT 1", vec![])
+            .await
+            .map_err(|error| anyhow::anyhow!("failed to connect, check CLICKHOUSE_URL: {error}"))?;
+        let dry_run = DryRun::from_env("CLICKHOUSE_", "clickhouse")?;
+        if config.create_tables && dry_run.is_some() {
+            info!("clickhouse: tables are not created in dry run");
+        } else if config.create_tables {
+            client.create_tables().await?;
+        }
+        client.check_tables().await?;
+        let columns = match dry_run {
+            Some(_) => client.columns().await?,
+            None => Default::default(),
+        };
+        info!(
+            "clickhouse sink connected, tables: {}{}",
+            config.tables().join(", "),
+            if config.namespaces.enabled() {
+                " with a table per filter"
+            } else {
+                ""
+            }
+        );
+
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let acks = Arc::new(AckTracker::default());
+        let task = tokio::spawn(Self::run(
+            Arc::clone(&client),
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+            Arc::clone(&acks),
+        ));
+
+        Ok(Self {
+            client,
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            acks,
+            dry_run,
+            columns,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    /// Serialize and check a row instead of inserting it, tables of filter groups have the
+    /// columns of the configured table
+    fn check(&self, dry_run: &DryRun, row: &Row, namespace: Option<&str>) {
+        let table = &self.client.config.table(row.table(), namespace);
+        let fields = match row.to_json() {
+            Ok(Value::Object(fields)) => fields,
+            Ok(_) => unreachable!("rows are structs"),
+            Err(error) => return dry_run.invalid(table, error),
+        };
+        let columns = &self.columns[row.table()];
+        if let Some(field) = fields.keys().find(|field| !columns.contains(*field)) {
+            return dry_run.invalid(table, format_args!("no column {field}"));
+        }
+        let line = Value::Object(fields).to_string();
+        dry_run.sent(table, line.len() + 1, || line);
+    }
+
+    async fn run(
+        client: Arc<Client>,
+        mut rx: mpsc::Receiver<(Row, Option<String>)>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+        acks: Arc<AckTracker>,
+    ) {
+        let config = &client.config;
+        // Tables of filter groups which exist or were created
+        let mut created = HashSet::new();
+        loop {
+            let row = tokio::select! {
+                row = rx.recv() => row,
+                () = shutdown.notified() => {
+                    // Stop accepting new rows, already queued rows are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(row) = row else {
+                break;
+            };
+
+            // By index of the table and filter group
+            let mut batches = BTreeMap::<(usize, Option<String>), TableBatch>::new();
+            let mut rows = 0;
+            let mut next = Some(row);
+            let deadline = Instant::now() + config.flush_interval;
+            loop {
+                if let Some((row, namespace)) = next.take() {
+                    let slot = row.slot();
+                    let batch = batches.entry((row.table(), namespace)).or_default();
+                    match row {
+                        Row::Account(row) => batch.push(&row, slot),
+                        Row::Transaction(row) => batch.push(&row, slot),
+                        Row::BlockMeta(row) => batch.push(&row, slot),
+                    }
+                    rows += 1;
+                }
+                if rows >= config.batch_size {
+                    break;
+                }
+                match timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(row)) => next = Some(row),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            for ((index, namespace), batch) in batches {
+                if batch.slots.is_empty() {
+                    continue;
+                }
+                let table = config.table(index, namespace.as_deref());
+                if namespace.is_some() && config.create_tables && !created.contains(&table) {
+                    match client
+                        .create_namespace(&table, config.tables()[index])
+                        .await
+                    {
+                        Ok(()) => {
+                            created.insert(table.clone());
+                        }
+                        Err(error) => {
+                            warn!("clickhouse: failed to create table {table}: {error}");
+                        }
+                    }
+                }
+                let slots = batch.slots.clone();
+                match client.insert(&table, batch).await {
+                    Ok(()) => {
+                        for slot in slots {
+                            acks.acked(slot);
+                        }
+                    }
+                    Err(error) => {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                        error!(
+                            "clickhouse: failed to insert {} rows into {table}: {error}",
+                            slots.len()
+                        );
+                    }
+                }
+            }
+        }
+        info!("clickhouse sink stopped");
+    }
+}
+
+impl UpdateSink for ClickHouseSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(row) = Row::from_update(msg) else {
+            return Ok(());
+        };
+        for namespace in self.client.config.namespaces.of(msg) {
+            if let Some(dry_run) = self.dry_run.as_ref() {
+                self.check(dry_run, &row, namespace.as_deref());
+                continue;
+            }
+            self.acks.sent(row.slot());
+
+            if self.tx.try_send((row.clone(), namespace)).is_err() {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped % 10_000 == 1 {
+                    warn!("clickhouse: queue is full, {dropped} rows dropped in total");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "clickhouse",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: self.dry_run.as_ref().map(DryRun::stats),
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        self.dry_run.is_none().then_some(self.acks.as_ref())
+    }
+
+    /// Tables are checked on start, this checks the user can insert into them with an
+    /// insert without rows
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async {
+            for table in self.client.config.tables() {
+                self.client
+                    .insert(table, TableBatch::default())
+                    .await
+                    .map_err(|error| anyhow::anyhow!("can't insert into {table}: {error}"))?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("clickhouse sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,47 @@
+CREATE TABLE IF NOT EXISTS {accounts_table} (
+    slot UInt64,
+    pubkey String,
+    owner LowCardinality(String),
+    lamports UInt64,
+    executable Bool,
+    rent_epoch UInt64,
+    data String CODEC(ZSTD(3)),
+    data_len UInt32,
+    write_version UInt64,
+    txn_signature Nullable(String),
+    is_startup Bool,
+    received_at DateTime64(3, 'UTC')
+) ENGINE = MergeTree
+PARTITION BY intDiv(slot, 216000)
+ORDER BY (owner, pubkey, slot, write_version);
+
+CREATE TABLE IF NOT EXISTS {transactions_table} (
+    slot UInt64,
+    signature String,
+    tx_index UInt64,
+    is_vote Bool,
+    err Nullable(String),
+    fee UInt64,
+    compute_units_consumed Nullable(UInt64),
+    signer String,
+    account_keys Array(String),
+    instructions UInt32,
+    inner_instructions UInt32,
+    log_messages UInt32,
+    received_at DateTime64(3, 'UTC')
+) ENGINE = MergeTree
+PARTITION BY intDiv(slot, 216000)
+ORDER BY (slot, tx_index);
+
+CREATE TABLE IF NOT EXISTS {blocks_meta_table} (
+    slot UInt64,
+    blockhash String,
+    parent_slot UInt64,
+    parent_blockhash String,
+    block_height Nullable(UInt64),
+    block_time Nullable(Int64),
+    executed_transaction_count UInt64,
+    entries_count UInt64,
+    received_at DateTime64(3, 'UTC')
+) ENGINE = ReplacingMergeTree
+ORDER BY slot
@@ -0,0 +1,236 @@
+use {
+    crate::{
+        sink::{AckTracker, SinkHealth, UpdateSink},
+        tags::{format_tags, FilterTags},
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::error,
+    std::{
+        env,
+        fs::File,
+        io::{self, BufWriter, Write},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const DEFAULT_COLUMNS: &str =
+    "kind,slot,pubkey,owner,lamports,write_version,signature,is_vote,index,err";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Kind,
+    Slot,
+    Filters,
+    Tags,
+    Pubkey,
+    Owner,
+    Lamports,
+    Executable,
+    RentEpoch,
+    WriteVersion,
+    TxnSignature,
+    IsStartup,
+    DataLen,
+    Data,
+    Signature,
+    IsVote,
+    Index,
+    Err,
+}
+
+impl Column {
+    const ALL: &'static [(&'static str, Self)] = &[
+        ("kind", Self::Kind),
+        ("slot", Self::Slot),
+        ("filters", Self::Filters),
+        ("tags", Self::Tags),
+        ("pubkey", Self::Pubkey),
+        ("owner", Self::Owner),
+        ("lamports", Self::Lamports),
+        ("executable", Self::Executable),
+        ("rent_epoch", Self::RentEpoch),
+        ("write_version", Self::WriteVersion),
+        ("txn_signature", Self::TxnSignature),
+        ("is_startup", Self::IsStartup),
+        ("data_len", Self::DataLen),
+        ("data", Self::Data),
+        ("signature", Self::Signature),
+        ("is_vote", Self::IsVote),
+        ("index", Self::Index),
+        ("err", Self::Err),
+    ];
+
+    fn from_name(name: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .iter()
+            .find(|(column, _)| *column == name)
+            .map(|(_, column)| *column)
+            .ok_or_else(|| {
+                let names = Self::ALL.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                anyhow::anyhow!(
+                    "unknown CSV column `{name}`, available: {}",
+                    names.join(",")
+                )
+            })
+    }
+
+    fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, column)| *column == self)
+            .map_or("", |(name, _)| *name)
+    }
+
+    /// Value of the column, empty if the update type does not have it
+    fn value(self, msg: &SubscribeUpdate, tags: &FilterTags) -> String {
+        let value = match (self, msg.update_oneof.as_ref()) {
+            (Self::Kind, Some(UpdateOneof::Account(_))) => Some("account".to_owned()),
+            (Self::Kind, Some(UpdateOneof::TransactionStatus(_))) => {
+                Some("transaction_status".to_owned())
+            }
+            (Self::Filters, _) => Some(msg.filters.join(",")),
+            (Self::Tags, _) => Some(format_tags(&tags.update(msg))),
+            (column, Some(UpdateOneof::Account(update))) => {
+                let account = update.account.as_ref();
+                match column {
+                    Self::Slot => Some(update.slot.to_string()),
+                    Self::IsStartup => Some(update.is_startup.to_string()),
+                    Self::Pubkey => account.map(|a| bs58::encode(&a.pubkey).into_string()),
+                    Self::Owner => account.map(|a| bs58::encode(&a.owner).into_string()),
+                    Self::Lamports => account.map(|a| a.lamports.to_string()),
+                    Self::Executable => account.map(|a| a.executable.to_string()),
+                    Self::RentEpoch => account.map(|a| a.rent_epoch.to_string()),
+                    Self::WriteVersion => account.map(|a| a.write_version.to_string()),
+                    Self::TxnSignature => account
+                        .and_then(|a| a.txn_signature.as_ref())
+                        .map(|signature| bs58::encode(signature).into_string()),
+                    Self::DataLen => account.map(|a| a.data.len().to_string()),
+                    Self::Data => account.map(|a| hex::encode(&a.data)),
+                    _ => None,
+                }
+            }
+            (column, Some(UpdateOneof::TransactionStatus(status))) => match column {
+                Self::Slot => Some(status.slot.to_string()),
+                Self::Signature => Some(bs58::encode(&status.signature).into_string()),
+                Self::IsVote => Some(status.is_vote.to_string()),
+                Self::Index => Some(status.index.to_string()),
+                Self::Err => {
+                    yellowstone_grpc_proto::convert_from::create_tx_error(status.err.as_ref())
+                        .ok()
+                        .flatten()
+                        .map(|err| err.to_string())
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        value.unwrap_or_default()
+    }
+}
+
+/// Quote field if it contains separator, quote or line break
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Write one CSV row, every field is escaped
+pub fn write_row<W: Write + ?Sized, S: AsRef<str>>(out: &mut W, fields: &[S]) -> io::Result<()> {
+    let row = fields
+        .iter()
+        .map(|field| escape(field.as_ref()))
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", row.join(","))
+}
+
+/// Writes account and transaction status updates as CSV rows, columns are selected with
+/// `CSV_COLUMNS`. Rows are written to `CSV_PATH`, or to stdout if it's not set.
+pub struct CsvSink {
+    columns: Vec<Column>,
+    tags: Arc<FilterTags>,
+    out: Mutex<Box<dyn Write + Send>>,
+    errors: AtomicU64,
+    acks: AckTracker,
+}
+
+impl CsvSink {
+    pub fn from_env(tags: Arc<FilterTags>) -> anyhow::Result<Self> {
+        let columns = env::var("CSV_COLUMNS")
+            .unwrap_or_else(|_| DEFAULT_COLUMNS.to_owned())
+            .split(',')
+            .map(|name| Column::from_name(name.trim()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut out: Box<dyn Write + Send> = match env::var("CSV_PATH") {
+            Ok(path) => Box::new(BufWriter::new(File::create(path)?)),
+            Err(_) => Box::new(io::stdout()),
+        };
+        let header = columns
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>();
+        write_row(&mut out, &header)?;
+
+        Ok(Self {
+            columns,
+            tags,
+            out: Mutex::new(out),
+            errors: AtomicU64::new(0),
+            acks: AckTracker::default(),
+        })
+    }
+}
+
+impl UpdateSink for CsvSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        if !matches!(
+            msg.update_oneof,
+            Some(UpdateOneof::Account(_) | UpdateOneof::TransactionStatus(_))
+        ) {
+            return Ok(());
+        }
+
+        let row = self
+            .columns
+            .iter()
+            .map(|column| column.value(msg, &self.tags))
+            .collect::<Vec<_>>();
+        // A written row is acknowledged right away, a row which failed after all retries is
+        // marked as lost by `Sinks::handle` and holds the checkpoint back
+        let mut out = self.out.lock().expect("poisoned");
+        write_row(&mut *out, &row).map_err(|error| {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            anyhow::anyhow!("csv: failed to write row: {error}")
+        })
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "csv",
+            dropped: 0,
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: None,
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        Some(&self.acks)
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            if let Err(error) = self.out.lock().expect("poisoned").flush() {
+                error!("csv: failed to flush: {error}");
+            }
+        }
+        .boxed()
+    }
+}
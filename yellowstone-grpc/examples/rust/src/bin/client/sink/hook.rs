@@ -0,0 +1,395 @@
+//! Run a command for matching updates, `HOOK_CMD`.
+//!
+//! Every update matched by `HOOK_FILTERS` and `HOOK_TYPES` is encoded as JSON (see `json`)
+//! and passed to `sh -c <HOOK_CMD>` on stdin. In `spawn` mode a process is started per
+//! update with the JSON document as its whole stdin, at most `HOOK_CONCURRENCY` of them run
+//! at once and a process is killed after `HOOK_TIMEOUT_MS`. In `stream` mode one long-lived
+//! process reads an update per line, it is restarted with the next update if it exits or
+//! does not read an update within the timeout. Lines printed by the command are logged.
+
+use {
+    crate::{
+        forks::SlotRollback,
+        gaps::Gap,
+        json::{update_json, JsonOptions},
+        sink::{SinkHealth, UpdateSink},
+        stats::update_kind,
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::{info, warn},
+    std::{
+        collections::HashSet,
+        env,
+        process::Stdio,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        process::{Child, ChildStdin, Command},
+        sync::{mpsc, Mutex, Notify, Semaphore},
+        task::JoinHandle,
+        time::timeout,
+    },
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookMode {
+    /// A process per update
+    Spawn,
+    /// One process reading JSON lines
+    Stream,
+}
+
+#[derive(Debug, Clone)]
+pub struct HookConfig {
+    pub command: String,
+    pub mode: HookMode,
+    /// Only updates matched by these filters, all if empty
+    pub filters: HashSet<String>,
+    /// Only updates of these types, all if empty
+    pub types: HashSet<String>,
+    /// Processes running at once in `spawn` mode
+    pub concurrency: usize,
+    pub timeout: Duration,
+    pub queue_size: usize,
+}
+
+impl HookConfig {
+    /// Returns `None` if `HOOK_CMD` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(command) = env::var("HOOK_CMD") else {
+            return Ok(None);
+        };
+
+        let parse_u64 = |key: &str| -> anyhow::Result<Option<u64>> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+        let list = |key: &str| -> HashSet<String> {
+            env::var(key)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|name| name.trim().to_owned())
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Ok(Some(Self {
+            command,
+            mode: match env::var("HOOK_MODE").as_deref() {
+                Ok("spawn") | Err(_) => HookMode::Spawn,
+                Ok("stream") => HookMode::Stream,
+                Ok(_) => anyhow::bail!("invalid HOOK_MODE value, expected `spawn` or `stream`"),
+            },
+            filters: list("HOOK_FILTERS"),
+            types: list("HOOK_TYPES"),
+            concurrency: parse_u64("HOOK_CONCURRENCY")?.unwrap_or(4).max(1) as usize,
+            timeout: Duration::from_millis(parse_u64("HOOK_TIMEOUT_MS")?.unwrap_or(5_000).max(1)),
+            queue_size: parse_u64("HOOK_QUEUE_SIZE")?.unwrap_or(1_000).max(1) as usize,
+        }))
+    }
+
+    fn shell(&self) -> Command {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        command
+    }
+}
+
+/// Log lines printed by the command
+fn log_lines(output: &[u8]) {
+    for line in String::from_utf8_lossy(output).lines() {
+        if !line.is_empty() {
+            info!("hook: {line}");
+        }
+    }
+}
+
+/// Run the command once with the update as its stdin
+async fn spawn_once(config: &HookConfig, payload: &str) -> anyhow::Result<()> {
+    let mut child = config.shell().stderr(Stdio::piped()).spawn()?;
+    let mut stdin = child.stdin.take().expect("piped");
+    // The child is killed when the future is dropped by the timeout
+    let run = async {
+        // A command which does not read its input closes stdin early, that is fine
+        let _ = stdin.write_all(payload.as_bytes()).await;
+        drop(stdin);
+        child.wait_with_output().await
+    };
+    let output = timeout(config.timeout, run)
+        .await
+        .map_err(|_| anyhow::anyhow!("killed after {:?}", config.timeout))??;
+    log_lines(&output.stdout);
+    anyhow::ensure!(
+        output.status.success(),
+        "{}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
+
+/// Long-lived process of `stream` mode
+struct StreamProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: JoinHandle<()>,
+}
+
+impl StreamProcess {
+    fn start(config: &HookConfig) -> anyhow::Result<Self> {
+        let mut child = config.shell().stderr(Stdio::inherit()).spawn()?;
+        let stdin = child.stdin.take().expect("piped");
+        let mut lines = BufReader::new(child.stdout.take().expect("piped")).lines();
+        let stdout = tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                if !line.is_empty() {
+                    info!("hook: {line}");
+                }
+            }
+        });
+        info!("hook process started");
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    async fn write(&mut self, payload: &str) -> std::io::Result<()> {
+        self.stdin.write_all(payload.as_bytes()).await?;
+        self.stdin.write_all(b"\n").await?;
+        self.stdin.flush().await
+    }
+
+    /// Close stdin and wait for the process to exit, it is killed after `wait`
+    async fn close(self, wait: Duration) {
+        let Self {
+            mut child,
+            stdin,
+            stdout,
+        } = self;
+        drop(stdin);
+        match timeout(wait, child.wait()).await {
+            Ok(Ok(status)) if status.success() => {}
+            Ok(Ok(status)) => warn!("hook process exited with {status}"),
+            Ok(Err(error)) => warn!("hook process failed: {error}"),
+            Err(_) => {
+                warn!("hook process did not exit within {wait:?}, killed");
+                let _ = child.kill().await;
+            }
+        }
+        let _ = stdout.await;
+    }
+}
+
+/// Write the update to the process of `stream` mode, a process which failed is dropped and
+/// started again with the next update
+async fn stream_line(
+    config: &HookConfig,
+    process: &mut Option<StreamProcess>,
+    payload: &str,
+) -> anyhow::Result<()> {
+    let current = match process {
+        Some(current) => current,
+        None => process.insert(StreamProcess::start(config)?),
+    };
+    let error = match timeout(config.timeout, current.write(payload)).await {
+        Ok(Ok(())) => return Ok(()),
+        Ok(Err(error)) => {
+            let status = current.child.try_wait().ok().flatten();
+            match status {
+                Some(status) => format!("process exited with {status}"),
+                None => format!("failed to write to the process: {error}"),
+            }
+        }
+        Err(_) => format!(
+            "process did not read the update within {:?}",
+            config.timeout
+        ),
+    };
+    // Killed on drop
+    *process = None;
+    anyhow::bail!("{error}, restarting with the next update")
+}
+
+/// Passes matching updates as JSON to a command, see the module docs
+pub struct HookSink {
+    filters: HashSet<String>,
+    types: HashSet<String>,
+    json: JsonOptions,
+    tx: mpsc::Sender<String>,
+    dropped: AtomicU64,
+    /// Failed, killed or restarted processes
+    errors: Arc<AtomicU64>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HookSink {
+    pub fn spawn(config: HookConfig) -> anyhow::Result<Self> {
+        let mode = match config.mode {
+            HookMode::Spawn => "spawn",
+            HookMode::Stream => "stream",
+        };
+        info!("hook sink started in {mode} mode: {}", config.command);
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let filters = config.filters.clone();
+        let types = config.types.clone();
+        let task = tokio::spawn(Self::run(
+            Arc::new(config),
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+        ));
+
+        Ok(Self {
+            filters,
+            types,
+            json: JsonOptions::from_env()?,
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        config: Arc<HookConfig>,
+        mut rx: mpsc::Receiver<String>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+    ) {
+        let running = Arc::new(Semaphore::new(config.concurrency));
+        let mut process = None;
+        loop {
+            let payload = tokio::select! {
+                payload = rx.recv() => payload,
+                () = shutdown.notified() => {
+                    // Stop accepting new updates, already queued updates are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(payload) = payload else {
+                break;
+            };
+
+            match config.mode {
+                HookMode::Spawn => {
+                    let permit = Arc::clone(&running)
+                        .acquire_owned()
+                        .await
+                        .expect("never closed");
+                    let config = Arc::clone(&config);
+                    let errors = Arc::clone(&errors);
+                    tokio::spawn(async move {
+                        if let Err(error) = spawn_once(&config, &payload).await {
+                            errors.fetch_add(1, Ordering::Relaxed);
+                            warn!("hook failed: {error}");
+                        }
+                        drop(permit);
+                    });
+                }
+                HookMode::Stream => {
+                    if let Err(error) = stream_line(&config, &mut process, &payload).await {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                        warn!("hook failed: {error}");
+                    }
+                }
+            }
+        }
+
+        // Wait for running processes
+        let _ = running.acquire_many(config.concurrency as u32).await;
+        if let Some(process) = process {
+            process.close(config.timeout).await;
+        }
+        info!("hook sink stopped");
+    }
+}
+
+impl HookSink {
+    fn push(&self, payload: String) {
+        if self.tx.try_send(payload).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 1_000 == 1 {
+                warn!("hook: queue is full, {dropped} updates dropped in total");
+            }
+        }
+    }
+}
+
+impl UpdateSink for HookSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(update) = msg.update_oneof.as_ref() else {
+            return Ok(());
+        };
+        if !self.types.is_empty() && !self.types.contains(update_kind(update)) {
+            return Ok(());
+        }
+        if !self.filters.is_empty() && !msg.filters.iter().any(|name| self.filters.contains(name)) {
+            return Ok(());
+        }
+        let Some(value) = update_json(msg, &self.json, None) else {
+            return Ok(());
+        };
+        self.push(value.to_string());
+        Ok(())
+    }
+
+    /// Passed regardless of `HOOK_FILTERS`, `HOOK_TYPES` can exclude `slot_rolled_back`
+    fn rollback(&self, rollback: &SlotRollback) {
+        if self.types.is_empty() || self.types.contains("slot_rolled_back") {
+            self.push(rollback.to_json().to_string());
+        }
+    }
+
+    /// Passed regardless of `HOOK_FILTERS`, `HOOK_TYPES` can exclude `gap`
+    fn gap(&self, gap: &Gap) {
+        if self.types.is_empty() || self.types.contains("gap") {
+            self.push(gap.to_json().to_string());
+        }
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "hook",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    warn!("hook sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,587 @@
+use {
+    crate::{
+        sink::{AckTracker, DryRun, Namespaces, SinkHealth, UpdateSink},
+        stats::{update_kind, update_slot},
+        trace::{self, trace_id},
+        truncate::FieldBudget,
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    rdkafka::{
+        admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+        client::DefaultClientContext,
+        config::ClientConfig,
+        error::RDKafkaErrorCode,
+        message::{Header, Headers, OwnedHeaders},
+        producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer},
+        util::Timeout,
+        ClientContext,
+    },
+    std::{
+        borrow::Cow,
+        collections::HashMap,
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, RwLock,
+        },
+        time::Duration,
+    },
+    tokio::runtime::Handle,
+    yellowstone_grpc_proto::{
+        prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+        prost::Message,
+    },
+};
+
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default `message.max.bytes` of librdkafka and the brokers
+const MAX_MESSAGE_BYTES: usize = 1_000_000;
+
+/// Update types published to Kafka, pings and pongs are skipped
+const KINDS: [&str; 7] = [
+    "account",
+    "slot",
+    "transaction",
+    "transaction_status",
+    "block",
+    "block_meta",
+    "entry",
+];
+
+/// How messages of one update type are assigned to partitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partitioner {
+    /// By message key: pubkey for accounts, signature for transactions and transaction
+    /// statuses, slot for slot updates, other types have no key
+    Key,
+    /// By slot divided by the bucket size, consecutive slots land in the same partition
+    SlotBucket(u64),
+    /// Partitions in turn, without key
+    RoundRobin,
+}
+
+impl Partitioner {
+    fn from_env_value(value: &str, slot_bucket: u64) -> anyhow::Result<Self> {
+        Ok(match value {
+            "key" => Self::Key,
+            "slot_bucket" => Self::SlotBucket(slot_bucket),
+            "round_robin" => Self::RoundRobin,
+            _ => anyhow::bail!("expected `key`, `slot_bucket` or `round_robin`"),
+        })
+    }
+}
+
+/// Topic and partitioner of one update type
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub topic: String,
+    pub partitioner: Partitioner,
+}
+
+/// Topics created on start with `KAFKA_CREATE_TOPICS=true`
+#[derive(Debug, Clone, Copy)]
+pub struct NewTopics {
+    pub partitions: i32,
+    pub replication: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    /// Route by update type
+    pub routes: HashMap<&'static str, Route>,
+    /// Topic of a filter group is `<topic>.<filter>`
+    pub namespaces: Namespaces,
+    pub create_topics: Option<NewTopics>,
+    pub acks: String,
+    pub linger_ms: u64,
+    pub batch_size: usize,
+    pub compression: String,
+    pub queue_size: usize,
+    pub truncate: FieldBudget,
+}
+
+impl KafkaConfig {
+    /// Returns `None` if `KAFKA_BROKERS` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(brokers) = env::var("KAFKA_BROKERS") else {
+            return Ok(None);
+        };
+
+        let parse = |key: &str, default: u64| -> anyhow::Result<u64> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+                .map(|value| value.unwrap_or(default))
+        };
+
+        let prefix = env::var("KAFKA_TOPIC_PREFIX").unwrap_or_else(|_| "grpc".to_owned());
+        let slot_bucket = parse("KAFKA_SLOT_BUCKET", 100)?.max(1);
+        let partitioner = |key: &str, default: Partitioner| -> anyhow::Result<Partitioner> {
+            match env::var(key) {
+                Ok(value) => Partitioner::from_env_value(&value, slot_bucket)
+                    .map_err(|error| anyhow::anyhow!("invalid {key}: {error}")),
+                Err(_) => Ok(default),
+            }
+        };
+        let default_partitioner = partitioner("KAFKA_PARTITIONER", Partitioner::Key)?;
+        let routes = KINDS
+            .iter()
+            .map(|kind| {
+                let kind_upper = kind.to_uppercase();
+                let topic = env::var(format!("KAFKA_TOPIC_{kind_upper}"))
+                    .unwrap_or_else(|_| format!("{prefix}.{kind}"));
+                let partitioner = partitioner(
+                    &format!("KAFKA_PARTITIONER_{kind_upper}"),
+                    default_partitioner,
+                )?;
+                Ok((*kind, Route { topic, partitioner }))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let create_topics = match env::var("KAFKA_CREATE_TOPICS").as_deref() {
+            Ok("true") => Some(NewTopics {
+                partitions: parse("KAFKA_PARTITIONS", 6)?.clamp(1, i32::MAX as u64) as i32,
+                replication: parse("KAFKA_REPLICATION", 1)?.clamp(1, i32::MAX as u64) as i32,
+            }),
+            Ok("false") | Err(_) => None,
+            Ok(_) => anyhow::bail!("invalid KAFKA_CREATE_TOPICS"),
+        };
+
+        let acks = env::var("KAFKA_ACKS").unwrap_or_else(|_| "all".to_owned());
+        anyhow::ensure!(
+            matches!(acks.as_str(), "all" | "-1" | "0" | "1"),
+            "invalid KAFKA_ACKS, expected `all`, `1` or `0`"
+        );
+
+        Ok(Some(Self {
+            brokers,
+            routes,
+            namespaces: Namespaces::from_env("KAFKA_")?,
+            create_topics,
+            acks,
+            linger_ms: parse("KAFKA_LINGER_MS", 5)?,
+            batch_size: parse("KAFKA_BATCH_SIZE", 10_000)?.max(1) as usize,
+            compression: env::var("KAFKA_COMPRESSION").unwrap_or_else(|_| "none".to_owned()),
+            queue_size: parse("KAFKA_QUEUE_SIZE", 100_000)?.max(1) as usize,
+            truncate: FieldBudget::from_env()?,
+        }))
+    }
+}
+
+/// Counts messages which the brokers did not acknowledge, the opaque of a message is its
+/// slot
+struct DeliveryContext {
+    errors: Arc<AtomicU64>,
+    acks: Arc<AckTracker>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = usize;
+
+    fn delivery(&self, result: &DeliveryResult<'_>, slot: Self::DeliveryOpaque) {
+        match result {
+            Ok(_) => self.acks.acked(slot as u64),
+            Err((error, _message)) => {
+                let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+                if errors % 10_000 == 1 {
+                    error!("kafka: delivery failed: {error}, {errors} failed in total");
+                }
+            }
+        }
+    }
+}
+
+/// Message key of `Partitioner::Key`, account updates are partitioned by pubkey,
+/// transactions by signature and slot updates by slot. Other updates have no key and are
+/// spread over partitions.
+fn message_key(update: &UpdateOneof) -> Option<String> {
+    match update {
+        UpdateOneof::Account(update) => update
+            .account
+            .as_ref()
+            .map(|account| bs58::encode(&account.pubkey).into_string()),
+        UpdateOneof::Transaction(update) => update
+            .transaction
+            .as_ref()
+            .map(|tx| bs58::encode(&tx.signature).into_string()),
+        UpdateOneof::TransactionStatus(update) => {
+            Some(bs58::encode(&update.signature).into_string())
+        }
+        UpdateOneof::Slot(update) => Some(update.slot.to_string()),
+        _ => None,
+    }
+}
+
+/// Create missing topics, existing topics are left as they are
+async fn create_topics(
+    brokers: &str,
+    mut names: Vec<&str>,
+    new_topics: NewTopics,
+) -> anyhow::Result<()> {
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .create()?;
+    names.sort_unstable();
+    names.dedup();
+    let topics = names
+        .iter()
+        .map(|name| {
+            NewTopic::new(
+                name,
+                new_topics.partitions,
+                TopicReplication::Fixed(new_topics.replication),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let options = AdminOptions::new().operation_timeout(Some(METADATA_TIMEOUT));
+    for result in admin.create_topics(topics.iter(), &options).await? {
+        match result {
+            Ok(topic) => info!(
+                "kafka: created topic {topic} with {} partitions, replication {}",
+                new_topics.partitions, new_topics.replication
+            ),
+            Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => {}
+            Err((topic, error)) => anyhow::bail!("kafka: failed to create topic {topic}: {error}"),
+        }
+    }
+    Ok(())
+}
+
+/// Number of partitions by topic, blocks on metadata requests
+fn partition_counts(
+    producer: &ThreadedProducer<DeliveryContext>,
+    topics: Vec<String>,
+) -> anyhow::Result<HashMap<String, i32>> {
+    let mut partitions = HashMap::new();
+    for topic in topics {
+        let metadata = producer
+            .client()
+            .fetch_metadata(Some(&topic), Timeout::After(METADATA_TIMEOUT))
+            .map_err(|error| {
+                anyhow::anyhow!("kafka: failed to fetch metadata of topic {topic}: {error}")
+            })?;
+        let count = metadata
+            .topics()
+            .first()
+            .map_or(0, |metadata| metadata.partitions().len() as i32);
+        anyhow::ensure!(
+            count > 0,
+            "kafka: topic {topic} has no partitions, round_robin needs an existing topic"
+        );
+        partitions.insert(topic, count);
+    }
+    Ok(partitions)
+}
+
+/// Publishes updates as protobuf-encoded `SubscribeUpdate` to a topic per update type.
+///
+/// Messages are queued by librdkafka and sent in batches by its background thread, if the
+/// queue is full new messages are dropped so the gRPC stream is never blocked by brokers.
+///
+/// Updates with fields over the `TRUNCATE_*` budget are published truncated, with header
+/// `truncated: true` and `original_len.<field>` with the original length in bytes. With
+/// `TRACE_IDS=true` every message has header `trace_id`.
+///
+/// With `KAFKA_DRY_RUN=true` messages are built, routed and checked against the message size
+/// limit but not produced, topics are not created.
+///
+/// With `KAFKA_NAMESPACE_BY_FILTER=true` every filter group has its own topics, created (with
+/// `KAFKA_CREATE_TOPICS=true`) before their first message.
+pub struct KafkaSink {
+    producer: Arc<ThreadedProducer<DeliveryContext>>,
+    brokers: String,
+    routes: HashMap<&'static str, Route>,
+    namespaces: Namespaces,
+    create_topics: Option<NewTopics>,
+    /// Number of partitions of topics with `Partitioner::RoundRobin`
+    partitions: HashMap<String, i32>,
+    /// Topics of filter groups used so far, with their number of partitions for round robin
+    group_topics: RwLock<HashMap<String, Option<i32>>>,
+    round_robin: AtomicU64,
+    dropped: AtomicU64,
+    /// Messages not acknowledged by the brokers
+    errors: Arc<AtomicU64>,
+    acks: Arc<AckTracker>,
+    truncate: FieldBudget,
+    trace_ids: bool,
+    dry_run: Option<DryRun>,
+}
+
+impl KafkaSink {
+    pub async fn spawn(config: KafkaConfig) -> anyhow::Result<Self> {
+        let dry_run = DryRun::from_env("KAFKA_", "kafka")?;
+        match config.create_topics {
+            Some(_) if dry_run.is_some() => info!("kafka: topics are not created in dry run"),
+            Some(new_topics) => {
+                let names = config.routes.values().map(|route| route.topic.as_str());
+                create_topics(&config.brokers, names.collect(), new_topics).await?
+            }
+            None => {}
+        }
+
+        let errors = Arc::new(AtomicU64::new(0));
+        let acks = Arc::new(AckTracker::default());
+        let producer: ThreadedProducer<_> = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("acks", &config.acks)
+            .set("linger.ms", config.linger_ms.to_string())
+            .set("batch.num.messages", config.batch_size.to_string())
+            .set("compression.type", &config.compression)
+            .set(
+                "queue.buffering.max.messages",
+                config.queue_size.to_string(),
+            )
+            .create_with_context(DeliveryContext {
+                errors: Arc::clone(&errors),
+                acks: Arc::clone(&acks),
+            })?;
+        info!(
+            "kafka sink created, brokers: {}, acks: {}",
+            config.brokers, config.acks
+        );
+
+        let producer = Arc::new(producer);
+        let round_robin_topics = config
+            .routes
+            .values()
+            .filter(|route| route.partitioner == Partitioner::RoundRobin)
+            .map(|route| route.topic.clone())
+            .collect::<Vec<_>>();
+        let partitions = if round_robin_topics.is_empty() {
+            HashMap::new()
+        } else {
+            let producer = Arc::clone(&producer);
+            tokio::task::spawn_blocking(move || partition_counts(&producer, round_robin_topics))
+                .await??
+        };
+
+        Ok(Self {
+            producer,
+            brokers: config.brokers,
+            routes: config.routes,
+            namespaces: config.namespaces,
+            create_topics: config.create_topics,
+            partitions,
+            group_topics: RwLock::default(),
+            round_robin: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            errors,
+            acks,
+            truncate: config.truncate,
+            trace_ids: trace::enabled(),
+            dry_run,
+        })
+    }
+
+    /// Topic of `route` for a filter group with its number of partitions for round robin,
+    /// the topic is created and its partitions counted on first use
+    fn group_topic(&self, route: &Route, namespace: &str) -> (String, Option<i32>) {
+        let topic = format!("{}.{namespace}", route.topic);
+        if let Some(partitions) = self.group_topics.read().expect("poisoned").get(&topic) {
+            return (topic, *partitions);
+        }
+        // Once per topic, the worker waits for the brokers
+        let partitions = tokio::task::block_in_place(|| {
+            if let (Some(new_topics), None) = (self.create_topics, self.dry_run.as_ref()) {
+                let created = Handle::current().block_on(create_topics(
+                    &self.brokers,
+                    vec![topic.as_str()],
+                    new_topics,
+                ));
+                if let Err(error) = created {
+                    error!("{error}");
+                }
+            }
+            if route.partitioner != Partitioner::RoundRobin {
+                return None;
+            }
+            match partition_counts(&self.producer, vec![topic.clone()]) {
+                Ok(counts) => counts.get(&topic).copied(),
+                Err(error) => {
+                    warn!("{error}, messages are assigned to partitions by librdkafka");
+                    None
+                }
+            }
+        });
+        self.group_topics
+            .write()
+            .expect("poisoned")
+            .insert(topic.clone(), partitions);
+        (topic, partitions)
+    }
+}
+
+impl UpdateSink for KafkaSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(update) = msg.update_oneof.as_ref() else {
+            return Ok(());
+        };
+        let Some(route) = self.routes.get(update_kind(update)) else {
+            return Ok(());
+        };
+
+        // All published update types have a slot
+        let slot = update_slot(msg).unwrap_or_default();
+        let mut headers = None;
+        if let Some(trace) = self.trace_ids.then(|| trace_id(msg)).flatten() {
+            headers = Some(OwnedHeaders::new().insert(Header {
+                key: "trace_id",
+                value: Some(&trace),
+            }));
+        }
+        let payload = match self.truncate.apply(msg) {
+            Some((msg, truncated)) => {
+                let mut truncated_headers =
+                    headers.unwrap_or_else(OwnedHeaders::new).insert(Header {
+                        key: "truncated",
+                        value: Some("true"),
+                    });
+                for (field, len) in truncated.0 {
+                    truncated_headers = truncated_headers.insert(Header {
+                        key: &format!("original_len.{field}"),
+                        value: Some(&len.to_string()),
+                    });
+                }
+                headers = Some(truncated_headers);
+                msg.encode_to_vec()
+            }
+            None => msg.encode_to_vec(),
+        };
+        let key = match route.partitioner {
+            Partitioner::Key => message_key(update),
+            Partitioner::SlotBucket(size) => Some((slot / size).to_string()),
+            Partitioner::RoundRobin => None,
+        };
+        for namespace in self.namespaces.of(msg) {
+            let (topic, partitions) = match namespace {
+                Some(namespace) => {
+                    let (topic, partitions) = self.group_topic(route, &namespace);
+                    (Cow::Owned(topic), partitions)
+                }
+                None => (
+                    Cow::Borrowed(route.topic.as_str()),
+                    self.partitions.get(&route.topic).copied(),
+                ),
+            };
+            let partition = partitions.map(|partitions| {
+                let next = self.round_robin.fetch_add(1, Ordering::Relaxed);
+                (next % partitions as u64) as i32
+            });
+            if let Some(dry_run) = self.dry_run.as_ref() {
+                let len = payload.len() + key.as_ref().map_or(0, String::len);
+                if len > MAX_MESSAGE_BYTES {
+                    dry_run.invalid(
+                        &topic,
+                        format_args!("message of {len} bytes is over {MAX_MESSAGE_BYTES} bytes"),
+                    );
+                } else {
+                    dry_run.sent(&topic, len, || {
+                        format!(
+                            "key {key:?}, partition {}, {} headers",
+                            partition.map_or_else(|| "by key".to_owned(), |p| p.to_string()),
+                            headers.as_ref().map_or(0, |headers| headers.count())
+                        )
+                    });
+                }
+                continue;
+            }
+            let mut record = BaseRecord::with_opaque_to(&topic, slot as usize).payload(&payload);
+            if let Some(key) = key.as_ref() {
+                record = record.key(key);
+            }
+            if let Some(headers) = headers.clone() {
+                record = record.headers(headers);
+            }
+            if let Some(partition) = partition {
+                record = record.partition(partition);
+            }
+            self.acks.sent(slot);
+            if self.producer.send(record).is_err() {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped % 10_000 == 1 {
+                    warn!("kafka: queue is full, {dropped} messages dropped in total");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "kafka",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: self.dry_run.as_ref().map(DryRun::stats),
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        self.dry_run.is_none().then_some(self.acks.as_ref())
+    }
+
+    /// Brokers should be reachable and every topic accessible. Topics of update types which
+    /// are not subscribed may not exist, so unknown topics are only warned about; fetching
+    /// metadata creates them when the brokers allow auto creation.
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        let producer = Arc::clone(&self.producer);
+        let mut topics = self
+            .routes
+            .values()
+            .map(|route| route.topic.clone())
+            .collect::<Vec<_>>();
+        topics.sort();
+        topics.dedup();
+        async move {
+            tokio::task::spawn_blocking(move || {
+                for topic in topics {
+                    let metadata = producer
+                        .client()
+                        .fetch_metadata(Some(&topic), Timeout::After(METADATA_TIMEOUT))
+                        .map_err(|error| {
+                            anyhow::anyhow!(
+                                "failed to fetch metadata of topic {topic}: {error}, check KAFKA_BROKERS"
+                            )
+                        })?;
+                    let Some(metadata) = metadata.topics().first() else {
+                        anyhow::bail!("topic {topic} not found");
+                    };
+                    match metadata.error().map(RDKafkaErrorCode::from) {
+                        None => {}
+                        Some(RDKafkaErrorCode::UnknownTopicOrPartition) => {
+                            warn!("kafka: topic {topic} does not exist, messages to it will fail");
+                        }
+                        Some(error) => anyhow::bail!("topic {topic} is not available: {error}"),
+                    }
+                }
+                Ok(())
+            })
+            .await?
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        let producer = Arc::clone(&self.producer);
+        async move {
+            // Flush blocks until queued messages are delivered
+            let result =
+                tokio::task::spawn_blocking(move || producer.flush(Timeout::After(FLUSH_TIMEOUT)))
+                    .await;
+            match result {
+                Ok(Ok(())) => info!("kafka sink stopped"),
+                Ok(Err(error)) => error!("kafka: failed to flush queued messages: {error}"),
+                Err(error) => error!("kafka sink flush failed: {error}"),
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,615 @@
+use {
+    crate::{
+        forks::SlotRollback,
+        gaps::Gap,
+        sink::{DryRun, SinkHealth, UpdateSink},
+        tags::{format_tags, FilterTags, Tags},
+        trace::{self, trace_id},
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    serde_json::json,
+    std::{
+        borrow::Cow,
+        collections::{BTreeMap, HashMap, HashSet},
+        env,
+        fmt::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex as StdMutex,
+        },
+        time::{Duration, Instant},
+    },
+    tokio::{
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::{interval, MissedTickBehavior},
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const QUEUE_SIZE: usize = 10_000;
+/// Expired alerts without recovery condition are removed when there are more keys
+const MAX_ALERT_KEYS: usize = 10_000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum NotifyTarget {
+    Slack { webhook_url: String },
+    Telegram { bot_token: String, chat_id: String },
+}
+
+impl NotifyTarget {
+    /// Longest text accepted in one message, in characters
+    fn max_message_len(&self) -> usize {
+        match self {
+            Self::Slack { .. } => 40_000,
+            Self::Telegram { .. } => 4_096,
+        }
+    }
+}
+
+/// Cut the text to `max` characters, a longer message would be rejected as a whole
+fn truncate_message(text: &str, max: usize) -> Cow<'_, str> {
+    if text.chars().count() <= max {
+        return Cow::Borrowed(text);
+    }
+    let marker = format!("\n... (truncated, {} bytes)", text.len());
+    let mut truncated = text
+        .chars()
+        .take(max.saturating_sub(marker.len()))
+        .collect::<String>();
+    truncated.push_str(&marker);
+    Cow::Owned(truncated)
+}
+
+#[derive(Debug, Clone)]
+pub struct NotifyConfig {
+    pub target: NotifyTarget,
+    /// Notify only about updates matched by these filters, all if empty
+    pub filters: HashSet<String>,
+    /// Send one summary per window instead of a message per update
+    pub digest: Option<Duration>,
+    pub digest_samples: usize,
+    /// Account updates alert only while lamports are below, and recover above
+    pub lamports_below: Option<u64>,
+    /// Same alert key is sent at most once per cooldown while it is active
+    pub cooldown: Option<Duration>,
+}
+
+impl NotifyConfig {
+    /// Returns `None` if neither `NOTIFY_SLACK_WEBHOOK_URL` nor `NOTIFY_TELEGRAM_BOT_TOKEN` is
+    /// set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let target = match (
+            env::var("NOTIFY_SLACK_WEBHOOK_URL"),
+            env::var("NOTIFY_TELEGRAM_BOT_TOKEN"),
+        ) {
+            (Ok(webhook_url), _) => NotifyTarget::Slack { webhook_url },
+            (Err(_), Ok(bot_token)) => NotifyTarget::Telegram {
+                bot_token,
+                chat_id: env::var("NOTIFY_TELEGRAM_CHAT_ID").map_err(|_| {
+                    anyhow::anyhow!(
+                        "NOTIFY_TELEGRAM_CHAT_ID is required with NOTIFY_TELEGRAM_BOT_TOKEN"
+                    )
+                })?,
+            },
+            (Err(_), Err(_)) => return Ok(None),
+        };
+
+        let parse_u64 = |key: &str| -> anyhow::Result<Option<u64>> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+
+        Ok(Some(Self {
+            target,
+            filters: env::var("NOTIFY_FILTERS")
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|name| name.trim().to_owned())
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            digest: parse_u64("NOTIFY_DIGEST_SECS")?
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            digest_samples: parse_u64("NOTIFY_DIGEST_SAMPLES")?.unwrap_or(5) as usize,
+            lamports_below: parse_u64("NOTIFY_LAMPORTS_BELOW")?,
+            cooldown: parse_u64("NOTIFY_COOLDOWN_SECS")?
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct Event {
+    kind: &'static str,
+    filters: Vec<String>,
+    tags: Tags,
+    text: String,
+    /// Alerts with the same key are deduplicated, accounts by pubkey, other updates by kind
+    /// and filters
+    key: String,
+    /// `false` if the alert condition is not met, sent only as recovery of an active alert
+    firing: bool,
+    /// Alerts with the same key suppressed since the previous message
+    suppressed: u64,
+}
+
+impl Event {
+    fn from_update(msg: &SubscribeUpdate, lamports_below: Option<u64>) -> Option<Self> {
+        let (kind, text, key, firing) = match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                let pubkey = bs58::encode(&account.pubkey).into_string();
+                let text = format!(
+                    "account {pubkey} slot {} lamports {}",
+                    update.slot, account.lamports
+                );
+                let firing = lamports_below.is_none_or(|threshold| account.lamports < threshold);
+                ("account", text, Some(pubkey), firing)
+            }
+            UpdateOneof::Transaction(update) => {
+                let tx = update.transaction.as_ref()?;
+                let failed = tx.meta.as_ref().is_some_and(|meta| meta.err.is_some());
+                let text = format!(
+                    "transaction {} slot {}{}",
+                    bs58::encode(&tx.signature).into_string(),
+                    update.slot,
+                    if failed { " failed" } else { "" }
+                );
+                ("transaction", text, None, true)
+            }
+            UpdateOneof::TransactionStatus(status) => {
+                let text = format!(
+                    "transaction status {} slot {}{}",
+                    bs58::encode(&status.signature).into_string(),
+                    status.slot,
+                    if status.err.is_some() { " failed" } else { "" }
+                );
+                ("transaction status", text, None, true)
+            }
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            filters: msg.filters.clone(),
+            tags: Tags::new(),
+            key: match key {
+                Some(key) => format!("{kind} {key}"),
+                None => format!("{kind} {}", msg.filters.join(",")),
+            },
+            text,
+            firing,
+            suppressed: 0,
+        })
+    }
+
+    fn to_message(&self) -> String {
+        let mut message = format!("[{}] ", self.filters.join(","));
+        if !self.tags.is_empty() {
+            let _ = write!(message, "{{{}}} ", format_tags(&self.tags));
+        }
+        if !self.firing {
+            message.push_str("recovered: ");
+        }
+        message.push_str(&self.text);
+        if self.suppressed > 0 {
+            let _ = write!(message, " ({} repeated alerts suppressed)", self.suppressed);
+        }
+        message
+    }
+}
+
+#[derive(Debug)]
+struct Alert {
+    sent: Instant,
+    suppressed: u64,
+    /// Removed only by recovery, otherwise when cooldown is over
+    recoverable: bool,
+}
+
+/// Active alerts by key
+#[derive(Debug, Default)]
+struct Alerts {
+    active: HashMap<String, Alert>,
+}
+
+impl Alerts {
+    /// Returns `true` if the event should be sent
+    fn check(&mut self, event: &mut Event, cooldown: Option<Duration>, recoverable: bool) -> bool {
+        let now = Instant::now();
+        if !event.firing {
+            return match self.active.remove(&event.key) {
+                Some(alert) => {
+                    event.suppressed = alert.suppressed;
+                    true
+                }
+                None => false,
+            };
+        }
+
+        let in_cooldown = |alert: &Alert| {
+            cooldown.is_some_and(|cooldown| now.duration_since(alert.sent) < cooldown)
+        };
+        if let Some(alert) = self.active.get_mut(&event.key) {
+            if in_cooldown(alert) {
+                alert.suppressed += 1;
+                return false;
+            }
+            event.suppressed = alert.suppressed;
+        }
+
+        if self.active.len() >= MAX_ALERT_KEYS {
+            self.active
+                .retain(|_, alert| alert.recoverable || in_cooldown(alert));
+        }
+        self.active.insert(
+            event.key.clone(),
+            Alert {
+                sent: now,
+                suppressed: 0,
+                recoverable,
+            },
+        );
+        true
+    }
+}
+
+/// Events collected during one digest window
+#[derive(Debug, Default)]
+struct Digest {
+    count: u64,
+    recovered: u64,
+    kinds: BTreeMap<&'static str, u64>,
+    filters: BTreeMap<String, u64>,
+    samples: Vec<String>,
+}
+
+impl Digest {
+    fn push(&mut self, event: Event, max_samples: usize) {
+        self.count += 1;
+        if !event.firing {
+            self.recovered += 1;
+        }
+        *self.kinds.entry(event.kind).or_default() += 1;
+        for filter in event.filters.iter() {
+            *self.filters.entry(filter.clone()).or_default() += 1;
+        }
+        if self.samples.len() < max_samples {
+            self.samples.push(event.to_message());
+        }
+    }
+
+    fn to_message(&self, window: Duration) -> String {
+        let mut message = format!("{} updates in the last {window:?}", self.count);
+        if self.recovered > 0 {
+            let _ = write!(message, "\nrecovered: {}", self.recovered);
+        }
+        for (kind, count) in self.kinds.iter() {
+            let _ = write!(message, "\n{kind}: {count}");
+        }
+        for (filter, count) in self.filters.iter() {
+            let _ = write!(message, "\nfilter {filter}: {count}");
+        }
+        for sample in self.samples.iter() {
+            let _ = write!(message, "\n- {sample}");
+        }
+        let more = self.count - self.samples.len() as u64;
+        if more > 0 {
+            let _ = write!(message, "\n... and {more} more");
+        }
+        message
+    }
+}
+
+/// Sends matched account and transaction updates to Slack or Telegram.
+///
+/// Events are queued and sent by a background task, if the queue is full new events are
+/// dropped. With `NOTIFY_DIGEST_SECS` events are summarized into one message per window.
+///
+/// With `NOTIFY_LAMPORTS_BELOW` or `NOTIFY_COOLDOWN_SECS` alerts are deduplicated by key: an
+/// active key is sent again only after the cooldown, and a recovered account is reported once.
+pub struct NotifySink {
+    http: reqwest::Client,
+    target: NotifyTarget,
+    filters: HashSet<String>,
+    lamports_below: Option<u64>,
+    cooldown: Option<Duration>,
+    /// Only tracked with cooldown or recovery condition
+    alerts: Option<StdMutex<Alerts>>,
+    tags: Arc<FilterTags>,
+    /// Append the trace ID to the text of updates
+    trace_ids: bool,
+    tx: mpsc::Sender<Event>,
+    dropped: AtomicU64,
+    /// Failed requests
+    errors: Arc<AtomicU64>,
+    /// Messages are built but not sent with `NOTIFY_DRY_RUN=true`
+    dry_run: Option<Arc<DryRun>>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl NotifySink {
+    pub fn spawn(config: NotifyConfig, tags: Arc<FilterTags>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        match config.digest {
+            Some(window) => info!("notify sink started, digest every {window:?}"),
+            None => info!("notify sink started"),
+        }
+
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let shutdown = Arc::new(Notify::new());
+        let filters = config.filters.clone();
+        let (lamports_below, cooldown) = (config.lamports_below, config.cooldown);
+        let errors = Arc::new(AtomicU64::new(0));
+        let dry_run = DryRun::from_env("NOTIFY_", "notify")?.map(Arc::new);
+        let target = config.target.clone();
+        let task = tokio::spawn(Self::run(
+            http.clone(),
+            config,
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+            dry_run.clone(),
+        ));
+
+        Ok(Self {
+            http,
+            target,
+            filters,
+            lamports_below,
+            cooldown,
+            alerts: (lamports_below.is_some() || cooldown.is_some()).then(StdMutex::default),
+            tags,
+            trace_ids: trace::enabled(),
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            dry_run,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        http: reqwest::Client,
+        config: NotifyConfig,
+        mut rx: mpsc::Receiver<Event>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+        dry_run: Option<Arc<DryRun>>,
+    ) {
+        let dry_run = dry_run.as_deref();
+        let mut ticker = interval(config.digest.unwrap_or(Duration::from_secs(3600)));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+
+        let mut digest = Digest::default();
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    if config.digest.is_some() {
+                        digest.push(event, config.digest_samples);
+                    } else {
+                        send(&http, &config.target, &errors, dry_run, &event.to_message()).await;
+                    }
+                }
+                _ = ticker.tick(), if config.digest.is_some() => {
+                    if digest.count > 0 {
+                        send(&http, &config.target, &errors, dry_run, &digest.to_message(ticker.period())).await;
+                        digest = Digest::default();
+                    }
+                }
+                () = shutdown.notified() => {
+                    // Stop accepting new events, already queued events are still received
+                    rx.close();
+                }
+            }
+        }
+
+        if digest.count > 0 {
+            send(
+                &http,
+                &config.target,
+                &errors,
+                dry_run,
+                &digest.to_message(ticker.period()),
+            )
+            .await;
+        }
+        info!("notify sink stopped");
+    }
+}
+
+/// Telegram is checked with `getChat`, which needs no message, Slack webhooks only accept
+/// messages so one test message is posted
+async fn preflight(http: &reqwest::Client, target: &NotifyTarget) -> anyhow::Result<()> {
+    let request = match target {
+        NotifyTarget::Slack { webhook_url } => http
+            .post(webhook_url)
+            .json(&json!({ "text": "yellowstone-grpc client started, notifications are enabled" })),
+        NotifyTarget::Telegram { bot_token, chat_id } => http
+            .post(format!("https://api.telegram.org/bot{bot_token}/getChat"))
+            .json(&json!({ "chat_id": chat_id })),
+    };
+    let response = request
+        .send()
+        .await
+        .map_err(|error| anyhow::anyhow!("request failed: {}", error.without_url()))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!(
+            "test request returned {status}: {}, check NOTIFY_SLACK_WEBHOOK_URL or \
+            NOTIFY_TELEGRAM_BOT_TOKEN and NOTIFY_TELEGRAM_CHAT_ID",
+            body.trim()
+        );
+    }
+    Ok(())
+}
+
+async fn send(
+    http: &reqwest::Client,
+    target: &NotifyTarget,
+    errors: &AtomicU64,
+    dry_run: Option<&DryRun>,
+    text: &str,
+) {
+    let text = truncate_message(text, target.max_message_len());
+    let (url, body) = match target {
+        NotifyTarget::Slack { webhook_url } => (webhook_url.clone(), json!({ "text": text })),
+        NotifyTarget::Telegram { bot_token, chat_id } => (
+            format!("https://api.telegram.org/bot{bot_token}/sendMessage"),
+            json!({ "chat_id": chat_id, "text": text }),
+        ),
+    };
+    if let Some(dry_run) = dry_run {
+        // Not the URL, it contains the webhook secret or the bot token
+        let name = match target {
+            NotifyTarget::Slack { .. } => "slack",
+            NotifyTarget::Telegram { .. } => "telegram",
+        };
+        let body = body.to_string();
+        dry_run.sent(name, body.len(), || body);
+        return;
+    }
+    if let Err(error) = http
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        errors.fetch_add(1, Ordering::Relaxed);
+        // URL of Telegram API contains the bot token
+        error!("notify: failed to send message: {}", error.without_url());
+    }
+}
+
+impl NotifySink {
+    fn push(&self, event: Event) {
+        if self.tx.try_send(event).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 1_000 == 1 {
+                warn!("notify: queue is full, {dropped} events dropped in total");
+            }
+        }
+    }
+}
+
+impl UpdateSink for NotifySink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        if !self.filters.is_empty() && !msg.filters.iter().any(|name| self.filters.contains(name)) {
+            return Ok(());
+        }
+        let Some(mut event) = Event::from_update(msg, self.lamports_below) else {
+            return Ok(());
+        };
+        let send = match self.alerts.as_ref() {
+            Some(alerts) => {
+                let recoverable = event.kind == "account" && self.lamports_below.is_some();
+                alerts
+                    .lock()
+                    .expect("poisoned")
+                    .check(&mut event, self.cooldown, recoverable)
+            }
+            None => event.firing,
+        };
+        if !send {
+            return Ok(());
+        }
+        event.tags = self.tags.update(msg);
+        if let Some(trace) = self.trace_ids.then(|| trace_id(msg)).flatten() {
+            let _ = write!(event.text, " trace {trace}");
+        }
+        self.push(event);
+        Ok(())
+    }
+
+    /// Sent regardless of `NOTIFY_FILTERS` and cooldown, rollbacks are rare and always matter
+    fn rollback(&self, rollback: &SlotRollback) {
+        self.push(Event {
+            kind: "slot rolled back",
+            filters: vec![],
+            tags: Tags::new(),
+            text: format!(
+                "slots rolled back, not ancestors of finalized slot {}: {}",
+                rollback.finalized,
+                rollback.describe()
+            ),
+            key: format!("slot rolled back {}", rollback.finalized),
+            firing: true,
+            suppressed: 0,
+        });
+    }
+
+    /// Sent regardless of `NOTIFY_FILTERS`, once per cooldown for every kind of gap
+    fn gap(&self, gap: &Gap) {
+        let mut event = Event {
+            kind: "gap",
+            filters: vec![],
+            tags: Tags::new(),
+            text: format!("gap: {}", gap.describe()),
+            key: format!("gap {}", gap.kind()),
+            firing: true,
+            suppressed: 0,
+        };
+        let send = match self.alerts.as_ref() {
+            Some(alerts) => {
+                alerts
+                    .lock()
+                    .expect("poisoned")
+                    .check(&mut event, self.cooldown, false)
+            }
+            None => true,
+        };
+        if send {
+            self.push(event);
+        }
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "notify",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: None,
+            dry_run: self.dry_run.as_deref().map(DryRun::stats),
+        }
+    }
+
+    /// Slack webhooks can only be checked with a message, which a dry run doesn't send
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        if self.dry_run.is_some() && matches!(self.target, NotifyTarget::Slack { .. }) {
+            info!("notify: Slack webhook is not checked in dry run");
+            return async { Ok(()) }.boxed();
+        }
+        preflight(&self.http, &self.target).boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("notify sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,770 @@
+use {
+    crate::sink::{AckTracker, Namespaces, SinkHealth, UpdateSink},
+    arrow_array::{
+        builder::{ListBuilder, StringBuilder},
+        ArrayRef, BinaryArray, BooleanArray, RecordBatch, StringArray, TimestampMillisecondArray,
+        UInt32Array, UInt64Array,
+    },
+    arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    chrono::{DateTime, Utc},
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    parquet::{
+        arrow::ArrowWriter,
+        basic::{Compression, ZstdLevel},
+        file::properties::WriterProperties,
+    },
+    std::{
+        collections::BTreeMap,
+        env,
+        fs::{self, File},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::{timeout_at, Instant},
+    },
+    yellowstone_grpc_proto::{
+        convert_from,
+        prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+    },
+};
+
+/// Files of this many newest partitions of a table are kept open for late updates
+const OPEN_PARTITIONS: usize = 2;
+
+/// How rows are split into directories
+#[derive(Debug, Clone, Copy)]
+pub enum Partitioning {
+    /// `slot_range=<first slot>`, ranges of this many slots
+    Slots(u64),
+    /// `window=<start>`, windows of receive time
+    Window(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct ParquetConfig {
+    pub dir: PathBuf,
+    pub partitioning: Partitioning,
+    pub compression: Compression,
+    /// Rows of a table written to its file at once, also the max row group size
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub queue_size: usize,
+    /// Files of a filter group are written to `<dir>/<filter>`
+    pub namespaces: Namespaces,
+}
+
+impl ParquetConfig {
+    /// Returns `None` if `PARQUET_DIR` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(dir) = env::var("PARQUET_DIR") else {
+            return Ok(None);
+        };
+
+        let parse = |key: &str| -> anyhow::Result<Option<u64>> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+        let partitioning = match (
+            parse("PARQUET_PARTITION_SLOTS")?,
+            parse("PARQUET_PARTITION_SECS")?,
+        ) {
+            (Some(_), Some(_)) => anyhow::bail!(
+                "PARQUET_PARTITION_SLOTS and PARQUET_PARTITION_SECS are mutually exclusive"
+            ),
+            (None, Some(secs)) => Partitioning::Window(Duration::from_secs(secs.max(1))),
+            (slots, None) => Partitioning::Slots(slots.unwrap_or(10_000).max(1)),
+        };
+        let compression = match env::var("PARQUET_COMPRESSION").as_deref() {
+            Ok("zstd") | Err(_) => Compression::ZSTD(ZstdLevel::default()),
+            Ok("snappy") => Compression::SNAPPY,
+            Ok("none") => Compression::UNCOMPRESSED,
+            Ok(_) => {
+                anyhow::bail!("invalid PARQUET_COMPRESSION, expected `zstd`, `snappy` or `none`")
+            }
+        };
+
+        Ok(Some(Self {
+            dir: PathBuf::from(dir),
+            partitioning,
+            compression,
+            batch_size: parse("PARQUET_BATCH_SIZE")?.unwrap_or(100_000).max(1) as usize,
+            flush_interval: Duration::from_millis(
+                parse("PARQUET_FLUSH_INTERVAL_MS")?.unwrap_or(1_000),
+            ),
+            queue_size: parse("PARQUET_QUEUE_SIZE")?.unwrap_or(1_000_000).max(1) as usize,
+            namespaces: Namespaces::from_env("PARQUET_")?,
+        }))
+    }
+
+    /// Directory name of the partition with the key returned by `partition`
+    fn partition_dir(&self, key: u64) -> String {
+        match self.partitioning {
+            Partitioning::Slots(_) => format!("slot_range={key}"),
+            Partitioning::Window(_) => {
+                let start = DateTime::from_timestamp(key as i64, 0).unwrap_or_default();
+                format!("window={}", start.format("%Y%m%dT%H%M%S"))
+            }
+        }
+    }
+
+    /// First slot of the range or start of the window, in seconds
+    fn partition(&self, slot: u64, received_at: i64) -> u64 {
+        match self.partitioning {
+            Partitioning::Slots(slots) => slot / slots * slots,
+            Partitioning::Window(window) => {
+                let secs = (received_at / 1_000).max(0) as u64;
+                secs / window.as_secs() * window.as_secs()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AccountRow {
+    slot: u64,
+    pubkey: String,
+    owner: String,
+    lamports: u64,
+    executable: bool,
+    rent_epoch: u64,
+    data: Vec<u8>,
+    write_version: u64,
+    txn_signature: Option<String>,
+    is_startup: bool,
+    /// Milliseconds since UNIX epoch
+    received_at: i64,
+}
+
+#[derive(Debug, Clone)]
+struct TransactionRow {
+    slot: u64,
+    signature: String,
+    tx_index: u64,
+    is_vote: bool,
+    err: Option<String>,
+    fee: u64,
+    compute_units_consumed: Option<u64>,
+    signer: String,
+    /// Static keys followed by keys loaded from lookup tables, writable first
+    account_keys: Vec<String>,
+    instructions: u32,
+    log_messages: Vec<String>,
+    received_at: i64,
+}
+
+#[derive(Debug, Clone)]
+enum Row {
+    Account(AccountRow),
+    Transaction(TransactionRow),
+}
+
+impl Row {
+    fn slot(&self) -> u64 {
+        match self {
+            Self::Account(row) => row.slot,
+            Self::Transaction(row) => row.slot,
+        }
+    }
+
+    fn received_at(&self) -> i64 {
+        match self {
+            Self::Account(row) => row.received_at,
+            Self::Transaction(row) => row.received_at,
+        }
+    }
+
+    fn from_update(msg: &SubscribeUpdate) -> Option<Self> {
+        let encode = |bytes: &[u8]| bs58::encode(bytes).into_string();
+        let received_at = Utc::now().timestamp_millis();
+        match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                Some(Self::Account(AccountRow {
+                    slot: update.slot,
+                    pubkey: encode(&account.pubkey),
+                    owner: encode(&account.owner),
+                    lamports: account.lamports,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                    data: account.data.clone(),
+                    write_version: account.write_version,
+                    txn_signature: account.txn_signature.as_deref().map(encode),
+                    is_startup: update.is_startup,
+                    received_at,
+                }))
+            }
+            UpdateOneof::Transaction(update) => {
+                let tx = update.transaction.as_ref()?;
+                let message = tx.transaction.as_ref().and_then(|tx| tx.message.as_ref());
+                let meta = tx.meta.as_ref();
+                let account_keys = message
+                    .map(|message| message.account_keys.iter())
+                    .into_iter()
+                    .flatten()
+                    .chain(
+                        meta.map(|meta| {
+                            meta.loaded_writable_addresses
+                                .iter()
+                                .chain(meta.loaded_readonly_addresses.iter())
+                        })
+                        .into_iter()
+                        .flatten(),
+                    )
+                    .map(|key| encode(key))
+                    .collect::<Vec<_>>();
+                Some(Self::Transaction(TransactionRow {
+                    slot: update.slot,
+                    signature: encode(&tx.signature),
+                    tx_index: tx.index,
+                    is_vote: tx.is_vote,
+                    err: convert_from::create_tx_error(meta.and_then(|meta| meta.err.as_ref()))
+                        .ok()
+                        .flatten()
+                        .map(|err| err.to_string()),
+                    fee: meta.map_or(0, |meta| meta.fee),
+                    compute_units_consumed: meta.and_then(|meta| meta.compute_units_consumed),
+                    signer: account_keys.first().cloned().unwrap_or_default(),
+                    account_keys,
+                    instructions: message.map_or(0, |message| message.instructions.len() as u32),
+                    log_messages: meta
+                        .map(|meta| meta.log_messages.clone())
+                        .unwrap_or_default(),
+                    received_at,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn received_at_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
+
+/// Same field as the one of `ListBuilder<StringBuilder>`
+fn string_list_type() -> DataType {
+    DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+}
+
+fn accounts_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("slot", DataType::UInt64, false),
+        Field::new("pubkey", DataType::Utf8, false),
+        Field::new("owner", DataType::Utf8, false),
+        Field::new("lamports", DataType::UInt64, false),
+        Field::new("executable", DataType::Boolean, false),
+        Field::new("rent_epoch", DataType::UInt64, false),
+        Field::new("data", DataType::Binary, false),
+        Field::new("write_version", DataType::UInt64, false),
+        Field::new("txn_signature", DataType::Utf8, true),
+        Field::new("is_startup", DataType::Boolean, false),
+        Field::new("received_at", received_at_type(), false),
+    ]))
+}
+
+fn transactions_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("slot", DataType::UInt64, false),
+        Field::new("signature", DataType::Utf8, false),
+        Field::new("tx_index", DataType::UInt64, false),
+        Field::new("is_vote", DataType::Boolean, false),
+        Field::new("err", DataType::Utf8, true),
+        Field::new("fee", DataType::UInt64, false),
+        Field::new("compute_units_consumed", DataType::UInt64, true),
+        Field::new("signer", DataType::Utf8, false),
+        Field::new("account_keys", string_list_type(), false),
+        Field::new("instructions", DataType::UInt32, false),
+        Field::new("log_messages", string_list_type(), false),
+        Field::new("received_at", received_at_type(), false),
+    ]))
+}
+
+fn string_list<'a>(rows: impl Iterator<Item = &'a Vec<String>>) -> ArrayRef {
+    let mut builder = ListBuilder::new(StringBuilder::new());
+    for values in rows {
+        for value in values {
+            builder.values().append_value(value);
+        }
+        builder.append(true);
+    }
+    Arc::new(builder.finish())
+}
+
+fn accounts_batch(schema: SchemaRef, rows: &[AccountRow]) -> anyhow::Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.slot),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.pubkey),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.owner),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.lamports),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|row| Some(row.executable)),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.rent_epoch),
+        )),
+        Arc::new(BinaryArray::from_iter_values(
+            rows.iter().map(|row| &row.data),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.write_version),
+        )),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|row| row.txn_signature.as_ref()),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|row| Some(row.is_startup)),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(rows.iter().map(|row| row.received_at))
+                .with_timezone("UTC"),
+        ),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn transactions_batch(schema: SchemaRef, rows: &[TransactionRow]) -> anyhow::Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.slot),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.signature),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.tx_index),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|row| Some(row.is_vote)),
+        )),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|row| row.err.as_ref()),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.fee),
+        )),
+        Arc::new(UInt64Array::from_iter(
+            rows.iter().map(|row| row.compute_units_consumed),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| &row.signer),
+        )),
+        string_list(rows.iter().map(|row| &row.account_keys)),
+        Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|row| row.instructions),
+        )),
+        string_list(rows.iter().map(|row| &row.log_messages)),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(rows.iter().map(|row| row.received_at))
+                .with_timezone("UTC"),
+        ),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// File being written, named `.tmp` until it is closed so readers only see complete files
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    tmp: PathBuf,
+    path: PathBuf,
+    /// Slots of written rows, acknowledged when the file is closed
+    slots: Vec<u64>,
+}
+
+impl OpenFile {
+    fn create(dir: &Path, schema: SchemaRef, props: WriterProperties) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir)?;
+        // Unique across restarts, a partition gets one file per process and open
+        let name = format!("part-{}.parquet", Utc::now().format("%Y%m%dT%H%M%S%.6f"));
+        let path = dir.join(name);
+        let tmp = path.with_extension("parquet.tmp");
+        let writer = ArrowWriter::try_new(File::create(&tmp)?, schema, Some(props))?;
+        Ok(Self {
+            writer,
+            tmp,
+            path,
+            slots: vec![],
+        })
+    }
+
+    /// Write the footer and move the file in place, returns the slots of its rows
+    fn close(self) -> anyhow::Result<Vec<u64>> {
+        self.writer.close()?;
+        fs::rename(&self.tmp, &self.path)?;
+        Ok(self.slots)
+    }
+}
+
+/// Open files of one table by partition
+struct TableFiles {
+    /// Directory of the table, partitions are its subdirectories
+    dir: PathBuf,
+    schema: SchemaRef,
+    open: BTreeMap<u64, OpenFile>,
+}
+
+impl TableFiles {
+    fn new(dir: PathBuf, schema: SchemaRef) -> Self {
+        Self {
+            dir,
+            schema,
+            open: BTreeMap::new(),
+        }
+    }
+
+    fn write(
+        &mut self,
+        config: &ParquetConfig,
+        partition: u64,
+        batch: RecordBatch,
+        slots: Vec<u64>,
+    ) -> anyhow::Result<()> {
+        if !self.open.contains_key(&partition) {
+            let dir = self.dir.join(config.partition_dir(partition));
+            let props = WriterProperties::builder()
+                .set_compression(config.compression)
+                .set_max_row_group_size(config.batch_size)
+                .build();
+            let file = OpenFile::create(&dir, Arc::clone(&self.schema), props)?;
+            self.open.insert(partition, file);
+        }
+        let file = self.open.get_mut(&partition).expect("opened above");
+        if let Err(error) = file.writer.write(&batch) {
+            // Rows of a broken file are lost, the next batch starts a new one
+            if let Some(file) = self.open.remove(&partition) {
+                let _ = fs::remove_file(&file.tmp);
+            }
+            return Err(error.into());
+        }
+        file.slots.extend(slots);
+        Ok(())
+    }
+
+    /// Close files of all but the newest `keep` partitions, returns the slots of closed rows
+    fn close_old(&mut self, keep: usize) -> (Vec<u64>, Vec<anyhow::Error>) {
+        let mut slots = vec![];
+        let mut errors = vec![];
+        while self.open.len() > keep {
+            let Some((partition, file)) = self.open.pop_first() else {
+                break;
+            };
+            let path = file.path.clone();
+            match file.close() {
+                Ok(closed) => slots.extend(closed),
+                Err(error) => {
+                    errors.push(anyhow::anyhow!(
+                        "failed to close {} of partition {partition}: {error}",
+                        path.display()
+                    ));
+                }
+            }
+        }
+        (slots, errors)
+    }
+}
+
+/// Files of the tables of one filter group
+struct Tables {
+    accounts: TableFiles,
+    transactions: TableFiles,
+}
+
+impl Tables {
+    fn new(dir: &Path) -> Self {
+        Self {
+            accounts: TableFiles::new(dir.join("accounts"), accounts_schema()),
+            transactions: TableFiles::new(dir.join("transactions"), transactions_schema()),
+        }
+    }
+}
+
+/// Writer state, moved to a blocking task for every batch
+struct Writers {
+    config: ParquetConfig,
+    /// Tables by filter group, `None` are the tables in `PARQUET_DIR`
+    groups: BTreeMap<Option<String>, Tables>,
+}
+
+impl Writers {
+    /// Tables of filter group `namespace`, created on its first row
+    fn tables<'a>(
+        groups: &'a mut BTreeMap<Option<String>, Tables>,
+        dir: &Path,
+        namespace: Option<String>,
+    ) -> &'a mut Tables {
+        groups
+            .entry(namespace)
+            .or_insert_with_key(|namespace| match namespace {
+                Some(namespace) => Tables::new(&dir.join(namespace)),
+                None => Tables::new(dir),
+            })
+    }
+
+    /// Write the rows to the files of their partitions and close files of partitions which
+    /// are no longer written, all of them with `close_all`. Returns the slots of rows in
+    /// closed files and the number of failures.
+    fn write(&mut self, rows: Vec<(Row, Option<String>)>, close_all: bool) -> (Vec<u64>, u64) {
+        let mut accounts = BTreeMap::<(Option<String>, u64), Vec<AccountRow>>::new();
+        let mut transactions = BTreeMap::<(Option<String>, u64), Vec<TransactionRow>>::new();
+        for (row, namespace) in rows {
+            let key = (
+                namespace,
+                self.config.partition(row.slot(), row.received_at()),
+            );
+            match row {
+                Row::Account(row) => accounts.entry(key).or_default().push(row),
+                Row::Transaction(row) => transactions.entry(key).or_default().push(row),
+            }
+        }
+
+        let mut failures = 0;
+        for ((namespace, partition), rows) in accounts {
+            let slots = rows.iter().map(|row| row.slot).collect();
+            let table = &mut Self::tables(&mut self.groups, &self.config.dir, namespace).accounts;
+            let result = accounts_batch(Arc::clone(&table.schema), &rows)
+                .and_then(|batch| table.write(&self.config, partition, batch, slots));
+            if let Err(error) = result {
+                failures += 1;
+                error!(
+                    "parquet: failed to write {} account rows: {error}",
+                    rows.len()
+                );
+            }
+        }
+        for ((namespace, partition), rows) in transactions {
+            let slots = rows.iter().map(|row| row.slot).collect();
+            let table =
+                &mut Self::tables(&mut self.groups, &self.config.dir, namespace).transactions;
+            let result = transactions_batch(Arc::clone(&table.schema), &rows)
+                .and_then(|batch| table.write(&self.config, partition, batch, slots));
+            if let Err(error) = result {
+                failures += 1;
+                error!(
+                    "parquet: failed to write {} transaction rows: {error}",
+                    rows.len()
+                );
+            }
+        }
+
+        let keep = if close_all { 0 } else { OPEN_PARTITIONS };
+        let mut acked = vec![];
+        let tables = self
+            .groups
+            .values_mut()
+            .flat_map(|tables| [&mut tables.accounts, &mut tables.transactions]);
+        for table in tables {
+            let (slots, errors) = table.close_old(keep);
+            acked.extend(slots);
+            for error in errors {
+                failures += 1;
+                error!("parquet: {error}");
+            }
+        }
+        (acked, failures)
+    }
+}
+
+/// Writes account updates and transactions to Parquet files, one row per update.
+///
+/// Files are partitioned by slot range or receive time into Hive-style directories, e.g.
+/// `accounts/slot_range=250000000/part-<time>.parquet`. Rows are queued in a bounded channel
+/// and written in batches by a background task, if the queue is full new rows are dropped so
+/// the gRPC stream is never blocked by the disk. Rows are acknowledged once their file is
+/// closed, which happens when newer partitions are written and on shutdown.
+///
+/// With `PARQUET_NAMESPACE_BY_FILTER=true` rows are written to the tables of every filter
+/// they matched, e.g. `tokens/accounts/slot_range=250000000/part-<time>.parquet`.
+pub struct ParquetSink {
+    dir: PathBuf,
+    namespaces: Namespaces,
+    /// Rows with their filter group
+    tx: mpsc::Sender<(Row, Option<String>)>,
+    dropped: AtomicU64,
+    /// Failed writes
+    errors: Arc<AtomicU64>,
+    acks: Arc<AckTracker>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ParquetSink {
+    pub fn spawn(config: ParquetConfig) -> anyhow::Result<Self> {
+        fs::create_dir_all(&config.dir).map_err(|error| {
+            anyhow::anyhow!("failed to create {}: {error}", config.dir.display())
+        })?;
+        info!(
+            "parquet sink writing to {}, partitioned by {:?}",
+            config.dir.display(),
+            config.partitioning
+        );
+
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let acks = Arc::new(AckTracker::default());
+        let dir = config.dir.clone();
+        let namespaces = config.namespaces;
+        let writers = Writers {
+            config,
+            groups: BTreeMap::new(),
+        };
+        let task = tokio::spawn(Self::run(
+            writers,
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+            Arc::clone(&acks),
+        ));
+
+        Ok(Self {
+            dir,
+            namespaces,
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            acks,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        mut writers: Writers,
+        mut rx: mpsc::Receiver<(Row, Option<String>)>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+        acks: Arc<AckTracker>,
+    ) {
+        let (batch_size, flush_interval) =
+            (writers.config.batch_size, writers.config.flush_interval);
+        loop {
+            let row = tokio::select! {
+                row = rx.recv() => row,
+                () = shutdown.notified() => {
+                    // Stop accepting new rows, already queued rows are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(row) = row else {
+                break;
+            };
+
+            let mut rows = vec![row];
+            let deadline = Instant::now() + flush_interval;
+            while rows.len() < batch_size {
+                match timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(row)) => rows.push(row),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            // Encoding and compression are CPU bound, file writes block
+            let result = tokio::task::spawn_blocking(move || {
+                let result = writers.write(rows, false);
+                (writers, result)
+            })
+            .await;
+            let (acked, failures) = match result {
+                Ok((returned, result)) => {
+                    writers = returned;
+                    result
+                }
+                Err(error) => {
+                    error!("parquet writer task failed: {error}");
+                    return;
+                }
+            };
+            errors.fetch_add(failures, Ordering::Relaxed);
+            for slot in acked {
+                acks.acked(slot);
+            }
+        }
+
+        let result = tokio::task::spawn_blocking(move || writers.write(vec![], true)).await;
+        match result {
+            Ok((acked, failures)) => {
+                errors.fetch_add(failures, Ordering::Relaxed);
+                for slot in acked {
+                    acks.acked(slot);
+                }
+            }
+            Err(error) => error!("parquet writer task failed: {error}"),
+        }
+        info!("parquet sink stopped");
+    }
+}
+
+impl UpdateSink for ParquetSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(row) = Row::from_update(msg) else {
+            return Ok(());
+        };
+        for namespace in self.namespaces.of(msg) {
+            self.acks.sent(row.slot());
+
+            if self.tx.try_send((row.clone(), namespace)).is_err() {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped % 10_000 == 1 {
+                    warn!("parquet: queue is full, {dropped} rows dropped in total");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "parquet",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: None,
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        Some(&self.acks)
+    }
+
+    /// Checks that files can be created in the directory
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async {
+            let probe = self.dir.join(".preflight");
+            fs::write(&probe, b"")
+                .and_then(|()| fs::remove_file(&probe))
+                .map_err(|error| anyhow::anyhow!("can't write to {}: {error}", self.dir.display()))
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("parquet sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,764 @@
+use {
+    crate::sink::{AckTracker, DryRun, Namespaces, SinkHealth, UpdateSink},
+    futures::future::{try_join_all, BoxFuture, FutureExt},
+    log::{error, info, warn},
+    std::{
+        collections::{BTreeMap, HashMap},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::{timeout_at, Instant},
+    },
+    tokio_postgres::{types::ToSql, Client, NoTls, Statement},
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+/// Schema versions in order, table names are substituted before applying. Released
+/// migrations must never change, schema changes are added as a new version.
+const MIGRATIONS: &[(i32, &str, &str)] = &[(
+    1,
+    "create tables",
+    include_str!("postgres/0001_create_tables.sql"),
+)];
+const MIGRATIONS_TABLE: &str = "yellowstone_client_migrations";
+
+#[derive(Debug, Clone)]
+pub struct PostgresConfig {
+    pub url: String,
+    pub accounts_table: String,
+    pub transactions_table: String,
+    pub batch_size: usize,
+    pub batch_max_delay: Duration,
+    pub queue_size: usize,
+    /// Apply pending migrations on start, disabled with `NO_MIGRATE=true`
+    pub migrate: bool,
+    /// Tables of a filter group are `<table>_<filter>`
+    pub namespaces: Namespaces,
+}
+
+impl PostgresConfig {
+    /// Returns `None` if `POSTGRES_URL` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = env::var("POSTGRES_URL") else {
+            return Ok(None);
+        };
+
+        let parse_usize = |key: &str, default: usize| -> anyhow::Result<usize> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+                .map(|value| value.unwrap_or(default))
+        };
+
+        let table = |key: &str, default: &str| -> anyhow::Result<String> {
+            let name = env::var(key).unwrap_or_else(|_| default.to_owned());
+            anyhow::ensure!(
+                is_table_name(&name),
+                "invalid {key}, expected `table` or `schema.table` of letters, digits and `_`"
+            );
+            Ok(name)
+        };
+
+        Ok(Some(Self {
+            url,
+            accounts_table: table("POSTGRES_ACCOUNTS_TABLE", "accounts")?,
+            transactions_table: table("POSTGRES_TRANSACTIONS_TABLE", "transactions")?,
+            batch_size: parse_usize("POSTGRES_BATCH_SIZE", 500)?.max(1),
+            batch_max_delay: Duration::from_millis(
+                parse_usize("POSTGRES_BATCH_MAX_DELAY_MS", 100)? as u64,
+            ),
+            queue_size: parse_usize("POSTGRES_QUEUE_SIZE", 100_000)?.max(1),
+            migrate: !env::var("NO_MIGRATE")
+                .ok()
+                .map(|value| value.parse::<bool>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid NO_MIGRATE"))?
+                .unwrap_or(false),
+            namespaces: Namespaces::from_env("POSTGRES_")?,
+        }))
+    }
+
+    fn accounts(&self) -> String {
+        quote_table(&self.accounts_table)
+    }
+
+    fn transactions(&self) -> String {
+        quote_table(&self.transactions_table)
+    }
+
+    /// Config of the tables of filter group `namespace`
+    fn namespaced(&self, namespace: &str) -> Self {
+        Self {
+            accounts_table: format!("{}_{namespace}", self.accounts_table),
+            transactions_table: format!("{}_{namespace}", self.transactions_table),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Row {
+    Account {
+        pubkey: String,
+        owner: String,
+        lamports: i64,
+        executable: bool,
+        rent_epoch: i64,
+        data: Vec<u8>,
+        slot: i64,
+        write_version: i64,
+        txn_signature: Option<String>,
+    },
+    TransactionStatus {
+        signature: String,
+        slot: i64,
+        is_vote: bool,
+        index: i64,
+        err: Option<String>,
+    },
+}
+
+impl Row {
+    fn slot(&self) -> u64 {
+        match self {
+            Self::Account { slot, .. } | Self::TransactionStatus { slot, .. } => *slot as u64,
+        }
+    }
+
+    fn table<'a>(&self, config: &'a PostgresConfig) -> &'a str {
+        match self {
+            Self::Account { .. } => &config.accounts_table,
+            Self::TransactionStatus { .. } => &config.transactions_table,
+        }
+    }
+
+    fn from_update(msg: &SubscribeUpdate) -> Option<Self> {
+        match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                Some(Self::Account {
+                    pubkey: bs58::encode(&account.pubkey).into_string(),
+                    owner: bs58::encode(&account.owner).into_string(),
+                    lamports: account.lamports as i64,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch as i64,
+                    data: account.data.clone(),
+                    slot: update.slot as i64,
+                    write_version: account.write_version as i64,
+                    txn_signature: account
+                        .txn_signature
+                        .as_ref()
+                        .map(|signature| bs58::encode(signature).into_string()),
+                })
+            }
+            UpdateOneof::TransactionStatus(status) => Some(Self::TransactionStatus {
+                signature: bs58::encode(&status.signature).into_string(),
+                slot: status.slot as i64,
+                is_vote: status.is_vote,
+                index: status.index as i64,
+                err: yellowstone_grpc_proto::convert_from::create_tx_error(status.err.as_ref())
+                    .ok()
+                    .flatten()
+                    .map(|err| err.to_string()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Writes account updates (latest state per pubkey) and transaction statuses to Postgres.
+///
+/// Rows are queued in a bounded channel and written in batches by a background task, if the
+/// queue is full new rows are dropped so the gRPC stream is never blocked by the database.
+///
+/// With `POSTGRES_NAMESPACE_BY_FILTER=true` rows are written to the tables of every filter
+/// they matched, `<table>_<filter>`. Their migrations are applied and statements prepared
+/// when the first row of the filter is written.
+///
+/// With `POSTGRES_DRY_RUN=true` the parameters of every row are serialized for the column
+/// types of the prepared statements but not executed, migrations are not applied.
+pub struct PostgresSink {
+    config: PostgresConfig,
+    statements: Statements,
+    dry_run: Option<DryRun>,
+    /// Rows with the filter group of their table
+    tx: mpsc::Sender<(Row, Option<String>)>,
+    dropped: AtomicU64,
+    /// Failed batch writes
+    errors: Arc<AtomicU64>,
+    /// Rows are acknowledged once their batch is committed
+    acks: Arc<AckTracker>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl PostgresSink {
+    pub async fn spawn(config: PostgresConfig) -> anyhow::Result<Self> {
+        let dry_run = DryRun::from_env("POSTGRES_", "postgres")?;
+        let mut client = connect(&config.url).await?;
+        if dry_run.is_none() {
+            migrate(&mut client, &config).await?;
+        } else {
+            info!("postgres: migrations are not applied in dry run");
+        }
+        let statements = Statements::prepare(&client, &config).await?;
+        info!(
+            "postgres sink connected, tables: {}, {}",
+            config.accounts_table, config.transactions_table
+        );
+
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let acks = Arc::new(AckTracker::default());
+        let task = tokio::spawn(Self::run(
+            client,
+            statements.clone(),
+            config.clone(),
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+            Arc::clone(&acks),
+        ));
+
+        Ok(Self {
+            config,
+            statements,
+            dry_run,
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            acks,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        mut client: Client,
+        statements: Statements,
+        config: PostgresConfig,
+        mut rx: mpsc::Receiver<(Row, Option<String>)>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+        acks: Arc<AckTracker>,
+    ) {
+        // Statements of the tables of filter groups
+        let mut groups = HashMap::new();
+        loop {
+            let row = tokio::select! {
+                row = rx.recv() => row,
+                () = shutdown.notified() => {
+                    // Stop accepting new rows, already queued rows are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some((row, namespace)) = row else {
+                break;
+            };
+
+            // Rows of the configured tables first, then by filter group
+            let mut batches = BTreeMap::<Option<String>, Vec<Row>>::new();
+            let mut rows = 1;
+            batches.entry(namespace).or_default().push(row);
+            let deadline = Instant::now() + config.batch_max_delay;
+            while rows < config.batch_size {
+                match timeout_at(deadline, rx.recv()).await {
+                    Ok(Some((row, namespace))) => {
+                        batches.entry(namespace).or_default().push(row);
+                        rows += 1;
+                    }
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            for (namespace, batch) in batches {
+                let statements = match namespace {
+                    None => statements.clone(),
+                    Some(namespace) => {
+                        match Self::group(&mut client, &config, &mut groups, &namespace).await {
+                            Ok(statements) => statements,
+                            Err(error) => {
+                                errors.fetch_add(1, Ordering::Relaxed);
+                                error!(
+                                    "postgres: failed to write {} rows of filter {namespace}: \
+                                    {error}",
+                                    batch.len()
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                };
+                match statements.write(&mut client, &batch).await {
+                    Ok(()) => {
+                        for row in batch.iter() {
+                            acks.acked(row.slot());
+                        }
+                    }
+                    Err(error) => {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                        error!("postgres: failed to write {} rows: {error}", batch.len());
+                    }
+                }
+            }
+        }
+        info!("postgres sink stopped");
+    }
+
+    /// Statements of the tables of filter group `namespace`, which are migrated and prepared
+    /// on first use
+    async fn group(
+        client: &mut Client,
+        config: &PostgresConfig,
+        groups: &mut HashMap<String, Statements>,
+        namespace: &str,
+    ) -> anyhow::Result<Statements> {
+        if let Some(statements) = groups.get(namespace) {
+            return Ok(statements.clone());
+        }
+        let config = config.namespaced(namespace);
+        migrate(client, &config).await?;
+        let statements = Statements::prepare(client, &config).await?;
+        info!(
+            "postgres: writing filter {namespace} to tables {}, {}",
+            config.accounts_table, config.transactions_table
+        );
+        groups.insert(namespace.to_owned(), statements.clone());
+        Ok(statements)
+    }
+}
+
+impl UpdateSink for PostgresSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(row) = Row::from_update(msg) else {
+            return Ok(());
+        };
+        for namespace in self.config.namespaces.of(msg) {
+            if let Some(dry_run) = self.dry_run.as_ref() {
+                // Tables of filter groups have the columns of the configured tables
+                let table = match namespace.as_deref() {
+                    Some(namespace) => format!("{}_{namespace}", row.table(&self.config)),
+                    None => row.table(&self.config).to_owned(),
+                };
+                match self.statements.check(&row) {
+                    Ok(len) => dry_run.sent(&table, len, || format!("{row:?}")),
+                    Err(error) => dry_run.invalid(&table, error),
+                }
+                continue;
+            }
+            self.acks.sent(row.slot());
+
+            if self.tx.try_send((row.clone(), namespace)).is_err() {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped % 10_000 == 1 {
+                    warn!("postgres: queue is full, {dropped} rows dropped in total");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "postgres",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: self.dry_run.as_ref().map(DryRun::stats),
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        self.dry_run.is_none().then_some(self.acks.as_ref())
+    }
+
+    /// Tables are checked by preparing statements on start, this checks the role can write
+    /// them, on a separate connection which is closed afterwards
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async {
+            let client = connect(&self.config.url).await?;
+            for (table, privileges) in [
+                (self.config.accounts(), "INSERT, UPDATE"),
+                (self.config.transactions(), "INSERT"),
+            ] {
+                let granted: bool = client
+                    .query_one(
+                        "SELECT has_table_privilege($1::text, $2::text)",
+                        &[&table, &privileges],
+                    )
+                    .await?
+                    .get(0);
+                anyhow::ensure!(
+                    granted,
+                    "role has no {privileges} privilege on table {table}"
+                );
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("postgres sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+async fn connect(url: &str) -> anyhow::Result<Client> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls)
+        .await
+        .map_err(|error| anyhow::anyhow!("failed to connect, check POSTGRES_URL: {error}"))?;
+    tokio::spawn(async move {
+        if let Err(error) = connection.await {
+            error!("postgres connection error: {error}");
+        }
+    });
+    Ok(client)
+}
+
+/// Whether `name` is `table` or `schema.table` of ASCII letters, digits and `_`, table names
+/// come from environment variables and are put into SQL
+fn is_table_name(name: &str) -> bool {
+    let parts = name.split('.').collect::<Vec<_>>();
+    parts.len() <= 2
+        && parts.iter().all(|part| {
+            part.len() <= 63
+                && part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+/// Quoted identifier of a table name checked by `is_table_name`, lowercase like an unquoted
+/// name is folded by Postgres
+fn quote_table(name: &str) -> String {
+    name.split('.')
+        .map(|part| format!("\"{}\"", part.to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Apply migrations newer than the recorded version of the configured tables. Concurrent
+/// clients wait for each other on an advisory lock, so every version is applied once. With
+/// `NO_MIGRATE=true` only checks that the recorded version is the latest.
+async fn migrate(client: &mut Client, config: &PostgresConfig) -> anyhow::Result<()> {
+    let tables = format!("{},{}", config.accounts_table, config.transactions_table);
+    let latest = MIGRATIONS.last().map_or(0, |(version, _, _)| *version);
+
+    if !config.migrate {
+        let version = client
+            .query_one(
+                &format!("SELECT max(version) FROM {MIGRATIONS_TABLE} WHERE tables = $1"),
+                &[&tables],
+            )
+            .await
+            .map_err(|error| {
+                anyhow::anyhow!(
+                    "postgres: failed to read the schema version of {tables} from \
+                    {MIGRATIONS_TABLE} with NO_MIGRATE=true: {}",
+                    db_message(&error)
+                )
+            })?
+            .get::<_, Option<i32>>(0)
+            .unwrap_or(0);
+        anyhow::ensure!(
+            version >= latest,
+            "postgres: schema of {tables} is at version {version}, latest is {latest}, \
+            apply the migrations or unset NO_MIGRATE"
+        );
+        return Ok(());
+    }
+
+    let transaction = client.transaction().await?;
+    // Taken before the table is created, two clients creating it at once on an empty
+    // database conflict in the catalog even with `IF NOT EXISTS`
+    transaction
+        .execute(
+            "SELECT pg_advisory_xact_lock(hashtext($1::text))",
+            &[&MIGRATIONS_TABLE],
+        )
+        .await
+        .map_err(|error| anyhow::anyhow!("failed to lock {MIGRATIONS_TABLE}: {error}"))?;
+    transaction
+        .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} ( \
+                tables TEXT NOT NULL, \
+                version INTEGER NOT NULL, \
+                name TEXT NOT NULL, \
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now(), \
+                PRIMARY KEY (tables, version) \
+            )"
+        ))
+        .await
+        .map_err(|error| anyhow::anyhow!("failed to create {MIGRATIONS_TABLE}: {error}"))?;
+    let version = transaction
+        .query_one(
+            &format!("SELECT max(version) FROM {MIGRATIONS_TABLE} WHERE tables = $1"),
+            &[&tables],
+        )
+        .await?
+        .get::<_, Option<i32>>(0)
+        .unwrap_or(0);
+
+    for (migration, name, sql) in MIGRATIONS.iter().filter(|(v, _, _)| *v > version) {
+        let sql = sql
+            .replace("{accounts_table}", &config.accounts())
+            .replace("{transactions_table}", &config.transactions());
+        transaction.batch_execute(&sql).await.map_err(|error| {
+            anyhow::anyhow!("failed to apply migration {migration} ({name}): {error}")
+        })?;
+        transaction
+            .execute(
+                &format!(
+                    "INSERT INTO {MIGRATIONS_TABLE} (tables, version, name) VALUES ($1, $2, $3)"
+                ),
+                &[&tables, migration, name],
+            )
+            .await?;
+        info!("postgres: applied migration {migration} ({name})");
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Message of the server, `Display` of the error is only "db error"
+fn db_message(error: &tokio_postgres::Error) -> String {
+    error
+        .as_db_error()
+        .map_or_else(|| error.to_string(), |error| error.message().to_owned())
+}
+
+/// Error of a statement which doesn't match the table, usually a missing table or column
+fn schema_error(table: &str, error: tokio_postgres::Error) -> anyhow::Error {
+    anyhow::anyhow!(
+        "table {table} doesn't match the expected schema (see README): {}",
+        db_message(&error)
+    )
+}
+
+#[derive(Clone)]
+struct Statements {
+    account: Statement,
+    transaction_status: Statement,
+}
+
+impl Statements {
+    async fn prepare(client: &Client, config: &PostgresConfig) -> anyhow::Result<Self> {
+        let account = client
+            .prepare(&format!(
+                "INSERT INTO {table} AS t \
+                    (pubkey, owner, lamports, executable, rent_epoch, data, slot, write_version, txn_signature) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+                ON CONFLICT (pubkey) DO UPDATE SET \
+                    owner = EXCLUDED.owner, \
+                    lamports = EXCLUDED.lamports, \
+                    executable = EXCLUDED.executable, \
+                    rent_epoch = EXCLUDED.rent_epoch, \
+                    data = EXCLUDED.data, \
+                    slot = EXCLUDED.slot, \
+                    write_version = EXCLUDED.write_version, \
+                    txn_signature = EXCLUDED.txn_signature \
+                WHERE t.write_version < EXCLUDED.write_version",
+                table = config.accounts()
+            ))
+            .await
+            .map_err(|error| schema_error(&config.accounts_table, error))?;
+
+        let transaction_status = client
+            .prepare(&format!(
+                "INSERT INTO {table} (signature, slot, is_vote, tx_index, err) \
+                VALUES ($1, $2, $3, $4, $5) \
+                ON CONFLICT DO NOTHING",
+                table = config.transactions()
+            ))
+            .await
+            .map_err(|error| schema_error(&config.transactions_table, error))?;
+
+        Ok(Self {
+            account,
+            transaction_status,
+        })
+    }
+
+    /// Statement of the row and its parameters
+    fn params<'a>(&'a self, row: &'a Row) -> (&'a Statement, Vec<&'a (dyn ToSql + Sync)>) {
+        match row {
+            Row::Account {
+                pubkey,
+                owner,
+                lamports,
+                executable,
+                rent_epoch,
+                data,
+                slot,
+                write_version,
+                txn_signature,
+            } => (
+                &self.account,
+                vec![
+                    pubkey,
+                    owner,
+                    lamports,
+                    executable,
+                    rent_epoch,
+                    data,
+                    slot,
+                    write_version,
+                    txn_signature,
+                ],
+            ),
+            Row::TransactionStatus {
+                signature,
+                slot,
+                is_vote,
+                index,
+                err,
+            } => (
+                &self.transaction_status,
+                vec![signature, slot, is_vote, index, err],
+            ),
+        }
+    }
+
+    /// Serialize the parameters of a row as they would be sent, returns their size. Fails
+    /// if a value doesn't fit the type of its column.
+    fn check(&self, row: &Row) -> anyhow::Result<usize> {
+        let (statement, params) = self.params(row);
+        // `BytesMut` of `tokio_postgres`, not a dependency of its own
+        let mut buf = Default::default();
+        for (param, ty) in params.iter().zip(statement.params()) {
+            param
+                .to_sql_checked(ty, &mut buf)
+                .map_err(|error| anyhow::anyhow!("column of type {ty}: {error}"))?;
+        }
+        Ok(buf.len())
+    }
+
+    async fn write(&self, client: &mut Client, batch: &[Row]) -> anyhow::Result<()> {
+        let transaction = client.transaction().await?;
+        let tx = &transaction;
+
+        let mut queries = Vec::with_capacity(batch.len());
+        for row in batch {
+            let (statement, params) = self.params(row);
+            queries.push(async move { tx.execute(statement, &params).await });
+        }
+        try_join_all(queries).await?;
+
+        transaction.commit().await.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, futures::future::join_all};
+
+    #[test]
+    fn migrations_are_ordered() {
+        for (index, (version, _, _)) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(*version, index as i32 + 1);
+        }
+    }
+
+    #[test]
+    fn table_names() {
+        assert!(is_table_name("accounts"));
+        assert!(is_table_name("geyser.accounts_2"));
+        assert!(is_table_name("_accounts"));
+        assert!(!is_table_name(""));
+        assert!(!is_table_name("1accounts"));
+        assert!(!is_table_name("a.b.c"));
+        assert!(!is_table_name("accounts; DROP TABLE accounts"));
+        assert!(!is_table_name("\"accounts\""));
+        assert!(!is_table_name(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn quotes_lowercase() {
+        assert_eq!(quote_table("accounts"), "\"accounts\"");
+        assert_eq!(quote_table("Geyser.Accounts"), "\"geyser\".\"accounts\"");
+    }
+
+    fn test_config(url: String, prefix: &str) -> PostgresConfig {
+        PostgresConfig {
+            url,
+            accounts_table: format!("{prefix}_accounts"),
+            transactions_table: format!("{prefix}_transactions"),
+            batch_size: 1,
+            batch_max_delay: Duration::from_millis(1),
+            queue_size: 1,
+            migrate: true,
+            namespaces: Namespaces::default(),
+        }
+    }
+
+    /// Clients starting at once on a database without the migrations table
+    #[tokio::test]
+    #[ignore = "needs a database in POSTGRES_TEST_URL"]
+    async fn concurrent_migrations() {
+        let url = env::var("POSTGRES_TEST_URL").expect("POSTGRES_TEST_URL");
+        let prefix = format!("test_{}", std::process::id());
+        let client = connect(&url).await.unwrap();
+        client
+            .batch_execute(&format!(
+                "DROP TABLE IF EXISTS {MIGRATIONS_TABLE}, {prefix}_accounts, \
+                {prefix}_transactions"
+            ))
+            .await
+            .unwrap();
+
+        let results = join_all((0..8).map(|_| {
+            let config = test_config(url.clone(), &prefix);
+            async move {
+                let mut client = connect(&config.url).await?;
+                migrate(&mut client, &config).await
+            }
+        }))
+        .await;
+        for result in results {
+            result.unwrap();
+        }
+        let applied: i64 = client
+            .query_one(
+                &format!("SELECT count(*) FROM {MIGRATIONS_TABLE} WHERE tables = $1"),
+                &[&format!("{prefix}_accounts,{prefix}_transactions")],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in POSTGRES_TEST_URL"]
+    async fn no_migrate_requires_latest_version() {
+        let url = env::var("POSTGRES_TEST_URL").expect("POSTGRES_TEST_URL");
+        let prefix = format!("test_nm_{}", std::process::id());
+        let mut config = test_config(url.clone(), &prefix);
+        let mut client = connect(&url).await.unwrap();
+
+        config.migrate = false;
+        assert!(migrate(&mut client, &config).await.is_err());
+        config.migrate = true;
+        migrate(&mut client, &config).await.unwrap();
+        config.migrate = false;
+        migrate(&mut client, &config).await.unwrap();
+    }
+}
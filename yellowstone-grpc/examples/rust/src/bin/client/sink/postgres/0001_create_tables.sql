@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS {accounts_table} (
+    pubkey TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    lamports BIGINT NOT NULL,
+    executable BOOLEAN NOT NULL,
+    rent_epoch BIGINT NOT NULL,
+    data BYTEA NOT NULL,
+    slot BIGINT NOT NULL,
+    write_version BIGINT NOT NULL,
+    txn_signature TEXT
+);
+
+CREATE TABLE IF NOT EXISTS {transactions_table} (
+    signature TEXT NOT NULL,
+    slot BIGINT NOT NULL,
+    is_vote BOOLEAN NOT NULL,
+    tx_index BIGINT NOT NULL,
+    err TEXT,
+    PRIMARY KEY (signature, slot)
+);
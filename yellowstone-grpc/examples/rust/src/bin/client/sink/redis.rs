@@ -0,0 +1,693 @@
+//! Publish updates to Redis, `REDIS_URL`.
+//!
+//! Every update of a type in `REDIS_TYPES` is encoded as JSON (see `json`) and, by
+//! `REDIS_MODE`, published with `PUBLISH` to the channel of its type, appended with `XADD` to
+//! the stream of its type, or both. Channels and streams are named `<REDIS_PREFIX><type>`,
+//! e.g. `geyser:account`, with `REDIS_NAMESPACE_BY_FILTER=true` one per filter group named
+//! `<REDIS_PREFIX><type>:<filter>`. Stream entries have the fields `slot` and `data` with the JSON
+//! document, streams are trimmed to about `REDIS_STREAM_MAXLEN` entries with `MAXLEN ~`.
+//!
+//! Commands are queued and sent in pipelined batches by a background task over a multiplexed
+//! connection of the `redis` crate. Updates of a batch which failed, because of the connection
+//! or an error reply to one of their commands, are sent again up to `REDIS_RETRIES` times with
+//! a growing delay, the connection is opened again before when it broke. Updates which still
+//! failed are appended to `REDIS_DEAD_LETTER_PATH` as JSON lines, or logged as lost without it.
+//!
+//! With `REDIS_DRY_RUN=true` the commands are encoded and checked against the argument size
+//! limit of Redis but not sent, the connection is only opened to check the credentials.
+//!
+//! `RedisKey` keeps a single value in a key over the same kind of connection, for state of the
+//! client like `PROGRESS_PATH`.
+
+use {
+    crate::{
+        forks::SlotRollback,
+        gaps::Gap,
+        json::{update_json, JsonOptions},
+        sink::{AckTracker, DryRun, Namespaces, SinkHealth, UpdateSink},
+        stats::{update_kind, update_slot},
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    redis::{
+        aio::MultiplexedConnection, AsyncConnectionConfig, Client, ConnectionAddr, ConnectionInfo,
+        Pipeline, RedisConnectionInfo, RedisError, RedisResult, Value,
+    },
+    std::{
+        collections::HashSet,
+        env,
+        fs::{File, OpenOptions},
+        io::Write,
+        path::PathBuf,
+        slice,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::sleep,
+    },
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+const DEFAULT_PORT: u16 = 6379;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first retry of a failed batch, doubled for every further one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Default `proto-max-bulk-len`, longest argument Redis accepts
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedisMode {
+    /// `PUBLISH` to a channel per update type
+    Publish,
+    /// `XADD` to a stream per update type
+    Stream,
+    Both,
+}
+
+impl RedisMode {
+    /// Commands sent per update
+    const fn commands(self) -> usize {
+        match self {
+            Self::Publish | Self::Stream => 1,
+            Self::Both => 2,
+        }
+    }
+}
+
+/// Server of a `redis://host:port/db` URL, credentials are `REDIS_USERNAME` and
+/// `REDIS_PASSWORD`
+#[derive(Debug, Clone)]
+pub struct RedisServer {
+    pub host: String,
+    pub port: u16,
+    pub db: u32,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl RedisServer {
+    /// Server of the URL in variable `key` and the rest of the URL path after the database
+    fn from_url(key: &str, url: &str) -> anyhow::Result<(Self, String)> {
+        let url = reqwest::Url::parse(url).map_err(|_| anyhow::anyhow!("invalid {key}"))?;
+        anyhow::ensure!(
+            url.scheme() == "redis",
+            "invalid {key}, expected `redis://host:port/db`, TLS is not supported"
+        );
+        anyhow::ensure!(
+            url.username().is_empty() && url.password().is_none(),
+            "set credentials with REDIS_USERNAME and REDIS_PASSWORD, not in {key}"
+        );
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("invalid {key}, no host"))?;
+        let (db, rest) = url
+            .path()
+            .trim_start_matches('/')
+            .split_once('/')
+            .unwrap_or((url.path().trim_start_matches('/'), ""));
+        let db = match db {
+            "" => 0,
+            db => db
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid {key}, database is not a number"))?,
+        };
+        let server = Self {
+            // Without the brackets of an IPv6 address
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned(),
+            port: url.port().unwrap_or(DEFAULT_PORT),
+            db,
+            username: env::var("REDIS_USERNAME").ok(),
+            password: env::var("REDIS_PASSWORD").ok(),
+        };
+        Ok((server, rest.to_owned()))
+    }
+
+    /// `host:port` for logs
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Open a connection, authenticate, select the database and ping
+    async fn connect(&self) -> RedisResult<MultiplexedConnection> {
+        let client = Client::open(ConnectionInfo {
+            addr: ConnectionAddr::Tcp(self.host.clone(), self.port),
+            redis: RedisConnectionInfo {
+                db: self.db.into(),
+                username: self.username.clone(),
+                password: self.password.clone(),
+                ..Default::default()
+            },
+        })?;
+        let config = AsyncConnectionConfig::new()
+            .set_connection_timeout(REQUEST_TIMEOUT)
+            .set_response_timeout(REQUEST_TIMEOUT);
+        let mut connection = client
+            .get_multiplexed_async_connection_with_config(&config)
+            .await?;
+        redis::cmd("PING").exec_async(&mut connection).await?;
+        Ok(connection)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RedisConfig {
+    pub server: RedisServer,
+    pub mode: RedisMode,
+    /// Prefix of channel and stream names
+    pub prefix: String,
+    pub namespaces: Namespaces,
+    /// Only updates of these types, all if empty
+    pub types: HashSet<String>,
+    /// Streams are trimmed to about this many entries, never if `None`
+    pub stream_maxlen: Option<u64>,
+    /// Commands sent in one pipeline
+    pub batch_size: usize,
+    pub queue_size: usize,
+    /// Times a failed update is sent again
+    pub retries: u32,
+    /// Updates which failed after all retries are appended here
+    pub dead_letter_path: Option<PathBuf>,
+}
+
+impl RedisConfig {
+    /// Returns `None` if `REDIS_URL` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = env::var("REDIS_URL") else {
+            return Ok(None);
+        };
+        let (server, rest) = RedisServer::from_url("REDIS_URL", &url)?;
+        anyhow::ensure!(
+            rest.is_empty(),
+            "invalid REDIS_URL, database is not a number"
+        );
+
+        let parse_u64 = |key: &str| -> anyhow::Result<Option<u64>> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+
+        Ok(Some(Self {
+            server,
+            mode: match env::var("REDIS_MODE").as_deref() {
+                Ok("publish") | Err(_) => RedisMode::Publish,
+                Ok("stream") => RedisMode::Stream,
+                Ok("both") => RedisMode::Both,
+                Ok(_) => {
+                    anyhow::bail!(
+                        "invalid REDIS_MODE value, expected `publish`, `stream` or `both`"
+                    )
+                }
+            },
+            prefix: env::var("REDIS_PREFIX").unwrap_or_else(|_| "geyser:".to_owned()),
+            namespaces: Namespaces::from_env("REDIS_")?,
+            types: env::var("REDIS_TYPES")
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|name| name.trim().to_owned())
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            stream_maxlen: match parse_u64("REDIS_STREAM_MAXLEN")?.unwrap_or(100_000) {
+                0 => None,
+                maxlen => Some(maxlen),
+            },
+            batch_size: parse_u64("REDIS_BATCH_SIZE")?.unwrap_or(1_000).max(1) as usize,
+            queue_size: parse_u64("REDIS_QUEUE_SIZE")?.unwrap_or(100_000).max(1) as usize,
+            retries: parse_u64("REDIS_RETRIES")?
+                .unwrap_or(3)
+                .min(u32::MAX as u64) as u32,
+            dead_letter_path: env::var_os("REDIS_DEAD_LETTER_PATH").map(PathBuf::from),
+        }))
+    }
+}
+
+/// Single value in a key of `redis://host:port/db/key`, the connection is opened on first use
+/// and again after a failure
+pub struct RedisKey {
+    server: RedisServer,
+    key: String,
+    connection: Mutex<Option<MultiplexedConnection>>,
+}
+
+impl RedisKey {
+    /// Key of the URL in variable `var`
+    pub fn from_url(var: &str, url: &str) -> anyhow::Result<Self> {
+        let (server, key) = RedisServer::from_url(var, url)?;
+        anyhow::ensure!(
+            !key.is_empty(),
+            "invalid {var}, expected `redis://host:port/db/key`"
+        );
+        Ok(Self {
+            server,
+            key,
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// Key and server for logs
+    pub fn name(&self) -> String {
+        format!("{} on {}/{}", self.key, self.server.addr(), self.server.db)
+    }
+
+    pub async fn get(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        self.run(redis::cmd("GET").arg(&self.key)).await
+    }
+
+    pub async fn set(&self, value: &[u8]) -> anyhow::Result<()> {
+        self.run(redis::cmd("SET").arg(&self.key).arg(value)).await
+    }
+
+    async fn run<T: redis::FromRedisValue>(&self, cmd: &redis::Cmd) -> anyhow::Result<T> {
+        let mut connection = self.connection.lock().await;
+        let current = match connection.as_mut() {
+            Some(current) => current,
+            None => connection.insert(self.server.connect().await?),
+        };
+        cmd.query_async(current).await.map_err(|error| {
+            if error.is_unrecoverable_error() {
+                *connection = None;
+            }
+            error.into()
+        })
+    }
+}
+
+/// Update encoded for Redis
+#[derive(Debug)]
+struct Entry {
+    kind: &'static str,
+    /// Filter group with namespaces
+    namespace: Option<String>,
+    /// Finalized slot for rollbacks, slot of the update revealing a gap
+    slot: u64,
+    /// Rollbacks and gaps are not acknowledged
+    ack: bool,
+    data: String,
+}
+
+impl Entry {
+    /// Channel and stream name
+    fn key(&self, config: &RedisConfig) -> String {
+        match self.namespace.as_ref() {
+            Some(namespace) => format!("{}{}:{namespace}", config.prefix, self.kind),
+            None => format!("{}{}", config.prefix, self.kind),
+        }
+    }
+}
+
+/// Publishes updates as JSON to Redis channels or streams, see the module docs.
+///
+/// Updates are queued in a bounded channel, if the queue is full new updates are dropped so
+/// the gRPC stream is never blocked by Redis.
+pub struct RedisSink {
+    config: Arc<RedisConfig>,
+    json: JsonOptions,
+    tx: mpsc::Sender<Entry>,
+    dropped: AtomicU64,
+    /// Failed batches and connections, every retry counts
+    errors: Arc<AtomicU64>,
+    /// Updates are acknowledged once Redis replied to their commands
+    acks: Arc<AckTracker>,
+    dry_run: Option<DryRun>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl RedisSink {
+    pub async fn spawn(config: RedisConfig) -> anyhow::Result<Self> {
+        let connection = config.server.connect().await.map_err(|error| {
+            anyhow::anyhow!(
+                "failed to connect to {}, check REDIS_URL: {error}",
+                config.server.addr()
+            )
+        })?;
+        let dead_letter = match config.dead_letter_path.as_ref() {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|error| {
+                        anyhow::anyhow!(
+                            "failed to open REDIS_DEAD_LETTER_PATH {}: {error}",
+                            path.display()
+                        )
+                    })?,
+            ),
+            None => None,
+        };
+        let mode = match config.mode {
+            RedisMode::Publish => "publish",
+            RedisMode::Stream => "stream",
+            RedisMode::Both => "publish and stream",
+        };
+        info!(
+            "redis sink connected to {}, {mode} with prefix {}",
+            config.server.addr(),
+            config.prefix
+        );
+
+        let config = Arc::new(config);
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let acks = Arc::new(AckTracker::default());
+        let task = tokio::spawn(Self::run(
+            Arc::clone(&config),
+            connection,
+            dead_letter,
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+            Arc::clone(&acks),
+        ));
+
+        Ok(Self {
+            config,
+            json: JsonOptions::from_env()?,
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            acks,
+            dry_run: DryRun::from_env("REDIS_", "redis")?,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        config: Arc<RedisConfig>,
+        connection: MultiplexedConnection,
+        mut dead_letter: Option<File>,
+        mut rx: mpsc::Receiver<Entry>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+        acks: Arc<AckTracker>,
+    ) {
+        let mut connection = Some(connection);
+        let max_entries = config.batch_size.div_ceil(config.mode.commands());
+        loop {
+            let entry = tokio::select! {
+                entry = rx.recv() => entry,
+                () = shutdown.notified() => {
+                    // Stop accepting new updates, already queued updates are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(entry) = entry else {
+                break;
+            };
+
+            // Everything queued up to the batch size goes into one pipeline
+            let mut entries = vec![entry];
+            while entries.len() < max_entries {
+                match rx.try_recv() {
+                    Ok(entry) => entries.push(entry),
+                    Err(_) => break,
+                }
+            }
+
+            // The queue fills up meanwhile and drops new updates, the batch is not lost
+            let mut retries = 0;
+            while let Err(error) = Self::send(&config, &mut connection, &acks, &mut entries).await {
+                errors.fetch_add(1, Ordering::Relaxed);
+                if retries == config.retries {
+                    Self::dead_letter(&config, dead_letter.as_mut(), &entries, &error);
+                    break;
+                }
+                let delay = RETRY_DELAY * 2u32.pow(retries.min(5));
+                retries += 1;
+                warn!(
+                    "redis: {error}, retrying {} updates in {delay:?}",
+                    entries.len()
+                );
+                sleep(delay).await;
+            }
+        }
+        info!("redis sink stopped");
+    }
+
+    /// Send the entries in one pipeline, the connection is opened first if it broke.
+    /// Entries Redis replied to are acknowledged and removed, the failed ones are kept.
+    async fn send(
+        config: &RedisConfig,
+        connection: &mut Option<MultiplexedConnection>,
+        acks: &AckTracker,
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), RedisError> {
+        let current = match connection.as_mut() {
+            Some(current) => current,
+            None => {
+                let opened = config.server.connect().await?;
+                info!("redis: reconnected to {}", config.server.addr());
+                connection.insert(opened)
+            }
+        };
+
+        let per_entry = config.mode.commands();
+        let pipe = Self::encode(config, entries);
+        // Error replies are values, an error here is one of the connection
+        let replies = match current
+            .send_packed_commands(&pipe, 0, entries.len() * per_entry)
+            .await
+        {
+            Ok(replies) => replies,
+            Err(error) => {
+                *connection = None;
+                return Err(error);
+            }
+        };
+
+        let mut replies = replies.into_iter();
+        let mut failed = None;
+        entries.retain(|entry| {
+            let error = replies
+                .by_ref()
+                .take(per_entry)
+                .find_map(|reply| match reply {
+                    Value::ServerError(error) => Some(error),
+                    _ => None,
+                });
+            match error {
+                None => {
+                    if entry.ack {
+                        acks.acked(entry.slot);
+                    }
+                    false
+                }
+                Some(error) => {
+                    failed.get_or_insert(error);
+                    true
+                }
+            }
+        });
+        match failed {
+            None => Ok(()),
+            Some(error) => Err(error.into()),
+        }
+    }
+
+    /// Append entries which failed after all retries to `REDIS_DEAD_LETTER_PATH` as JSON lines
+    /// with their channel or stream, or log them as lost. They are never acknowledged.
+    fn dead_letter(
+        config: &RedisConfig,
+        file: Option<&mut File>,
+        entries: &[Entry],
+        error: &RedisError,
+    ) {
+        let (Some(file), Some(path)) = (file, config.dead_letter_path.as_ref()) else {
+            error!(
+                "redis: {error}, {} updates lost after {} retries",
+                entries.len(),
+                config.retries
+            );
+            return;
+        };
+        let mut lines = String::new();
+        for entry in entries {
+            // The data is a JSON document already
+            lines.push_str(&format!(
+                "{{\"key\":{},\"slot\":{},\"error\":{},\"data\":{}}}\n",
+                serde_json::Value::String(entry.key(config)),
+                entry.slot,
+                serde_json::Value::String(error.to_string()),
+                entry.data
+            ));
+        }
+        // Rarely written, blocking the task is fine
+        match file.write_all(lines.as_bytes()) {
+            Ok(()) => warn!(
+                "redis: {error}, {} updates written to {} after {} retries",
+                entries.len(),
+                path.display(),
+                config.retries
+            ),
+            Err(write_error) => error!(
+                "redis: {error}, {} updates lost, failed to write {}: {write_error}",
+                entries.len(),
+                path.display()
+            ),
+        }
+    }
+
+    fn encode(config: &RedisConfig, entries: &[Entry]) -> Pipeline {
+        let mut pipe = redis::pipe();
+        for entry in entries {
+            let key = entry.key(config);
+            if config.mode != RedisMode::Stream {
+                pipe.cmd("PUBLISH").arg(&key).arg(&entry.data);
+            }
+            if config.mode != RedisMode::Publish {
+                pipe.cmd("XADD").arg(&key);
+                if let Some(maxlen) = config.stream_maxlen {
+                    pipe.arg("MAXLEN").arg("~").arg(maxlen);
+                }
+                pipe.arg("*")
+                    .arg("slot")
+                    .arg(entry.slot)
+                    .arg("data")
+                    .arg(&entry.data);
+            }
+        }
+        pipe
+    }
+
+    fn push(&self, entry: Entry) {
+        if let Some(dry_run) = self.dry_run.as_ref() {
+            let key = entry.key(&self.config);
+            if entry.data.len() > MAX_BULK_LEN {
+                dry_run.invalid(
+                    &key,
+                    format_args!("{} bytes are over {MAX_BULK_LEN} bytes", entry.data.len()),
+                );
+            } else {
+                let commands = Self::encode(&self.config, slice::from_ref(&entry));
+                dry_run.sent(&key, commands.get_packed_pipeline().len(), || entry.data);
+            }
+            return;
+        }
+        if entry.ack {
+            self.acks.sent(entry.slot);
+        }
+        if self.tx.try_send(entry).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 10_000 == 1 {
+                warn!("redis: queue is full, {dropped} updates dropped in total");
+            }
+        }
+    }
+
+    fn sends(&self, kind: &str) -> bool {
+        self.config.types.is_empty() || self.config.types.contains(kind)
+    }
+}
+
+impl UpdateSink for RedisSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(update) = msg.update_oneof.as_ref() else {
+            return Ok(());
+        };
+        let kind = update_kind(update);
+        if !self.sends(kind) {
+            return Ok(());
+        }
+        let Some(value) = update_json(msg, &self.json, None) else {
+            return Ok(());
+        };
+        let data = value.to_string();
+        for namespace in self.config.namespaces.of(msg) {
+            self.push(Entry {
+                kind,
+                namespace,
+                // All sent update types have a slot
+                slot: update_slot(msg).unwrap_or_default(),
+                ack: true,
+                data: data.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sent to `<REDIS_PREFIX>slot_rolled_back`, `REDIS_TYPES` can exclude it
+    fn rollback(&self, rollback: &SlotRollback) {
+        if self.sends("slot_rolled_back") {
+            self.push(Entry {
+                kind: "slot_rolled_back",
+                namespace: None,
+                slot: rollback.finalized,
+                ack: false,
+                data: rollback.to_json().to_string(),
+            });
+        }
+    }
+
+    /// Sent to `<REDIS_PREFIX>gap`, `REDIS_TYPES` can exclude it
+    fn gap(&self, gap: &Gap) {
+        if self.sends("gap") {
+            self.push(Entry {
+                kind: "gap",
+                namespace: None,
+                slot: gap.slot(),
+                ack: false,
+                data: gap.to_json().to_string(),
+            });
+        }
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "redis",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: self.dry_run.as_ref().map(DryRun::stats),
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        self.dry_run.is_none().then_some(self.acks.as_ref())
+    }
+
+    /// The connection is checked on start, this opens another one to check credentials and
+    /// the database are still accepted
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async {
+            self.config
+                .server
+                .connect()
+                .await
+                .map(|_| ())
+                .map_err(|error| {
+                    anyhow::anyhow!("can't connect to {}: {error}", self.config.server.addr())
+                })
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("redis sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,231 @@
+//! Stream updates as JSON lines over a Unix domain socket, `SOCKET_PATH`.
+//!
+//! Local consumers connect to the socket and read one JSON document per line (see `json`),
+//! without the HTTP and WebSocket framing of `Serve`. Every client receives every update
+//! matched by `SOCKET_FILTERS`, and a client which falls more than `SOCKET_CLIENT_BUFFER`
+//! lines behind skips the oldest ones. Nothing is encoded while no client is connected.
+
+use {
+    crate::{
+        forks::SlotRollback,
+        gaps::Gap,
+        json::{update_json, JsonOptions},
+        sink::{SinkHealth, UpdateSink},
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::{info, warn},
+    std::{
+        collections::HashSet,
+        env, fs, io,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    },
+    tokio::{
+        io::AsyncWriteExt,
+        net::{UnixListener, UnixStream},
+        sync::{
+            broadcast::{self, error::RecvError},
+            Mutex, Notify,
+        },
+        task::JoinHandle,
+    },
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+const DEFAULT_CLIENT_BUFFER: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub struct SocketConfig {
+    pub path: PathBuf,
+    /// Only updates matched by these filters, all if empty
+    pub filters: HashSet<String>,
+    /// Lines buffered per client
+    pub client_buffer: usize,
+}
+
+impl SocketConfig {
+    /// Returns `None` if `SOCKET_PATH` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(path) = env::var("SOCKET_PATH") else {
+            return Ok(None);
+        };
+        let client_buffer = match env::var("SOCKET_CLIENT_BUFFER") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|buffer| *buffer > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid SOCKET_CLIENT_BUFFER"))?,
+            Err(_) => DEFAULT_CLIENT_BUFFER,
+        };
+        Ok(Some(Self {
+            path: path.into(),
+            filters: env::var("SOCKET_FILTERS")
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|name| name.trim().to_owned())
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            client_buffer,
+        }))
+    }
+}
+
+/// Writes matching updates to the clients of a Unix domain socket
+pub struct SocketSink {
+    path: PathBuf,
+    filters: HashSet<String>,
+    json: JsonOptions,
+    tx: broadcast::Sender<Arc<str>>,
+    /// Lines skipped by clients which fell behind
+    skipped: Arc<AtomicU64>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SocketSink {
+    pub fn spawn(config: SocketConfig) -> anyhow::Result<Self> {
+        // A socket file left by a previous run would fail the bind
+        match fs::remove_file(&config.path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => anyhow::bail!("failed to remove {}: {error}", config.path.display()),
+        }
+        let listener = UnixListener::bind(&config.path).map_err(|error| {
+            anyhow::anyhow!(
+                "failed to bind SOCKET_PATH {}: {error}",
+                config.path.display()
+            )
+        })?;
+        info!("socket sink listening on {}", config.path.display());
+
+        let tx = broadcast::channel(config.client_buffer).0;
+        let skipped = Arc::new(AtomicU64::new(0));
+        let shutdown = Arc::new(Notify::new());
+        let task = tokio::spawn(Self::accept(
+            listener,
+            tx.clone(),
+            Arc::clone(&skipped),
+            Arc::clone(&shutdown),
+        ));
+
+        Ok(Self {
+            path: config.path,
+            filters: config.filters,
+            json: JsonOptions::from_env()?,
+            tx,
+            skipped,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn accept(
+        listener: UnixListener,
+        tx: broadcast::Sender<Arc<str>>,
+        skipped: Arc<AtomicU64>,
+        shutdown: Arc<Notify>,
+    ) {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(error) => {
+                        warn!("socket sink: failed to accept a client: {error}");
+                        continue;
+                    }
+                },
+                () = shutdown.notified() => break,
+            };
+            tokio::spawn(Self::serve_client(
+                stream,
+                tx.subscribe(),
+                Arc::clone(&skipped),
+            ));
+        }
+    }
+
+    async fn serve_client(
+        mut stream: UnixStream,
+        mut rx: broadcast::Receiver<Arc<str>>,
+        skipped: Arc<AtomicU64>,
+    ) {
+        info!("socket sink: client connected");
+        loop {
+            match rx.recv().await {
+                Ok(line) => {
+                    if stream.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(count)) => {
+                    skipped.fetch_add(count, Ordering::Relaxed);
+                    warn!("socket sink: client fell behind, {count} updates skipped");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+        info!("socket sink: client disconnected");
+    }
+
+    fn send(&self, mut line: String) {
+        line.push('\n');
+        // No connected clients
+        let _ = self.tx.send(line.into());
+    }
+}
+
+impl UpdateSink for SocketSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        if self.tx.receiver_count() == 0 {
+            return Ok(());
+        }
+        if !self.filters.is_empty() && !msg.filters.iter().any(|name| self.filters.contains(name)) {
+            return Ok(());
+        }
+        if let Some(value) = update_json(msg, &self.json, None) {
+            self.send(value.to_string());
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, rollback: &SlotRollback) {
+        if self.tx.receiver_count() > 0 {
+            self.send(rollback.to_json().to_string());
+        }
+    }
+
+    fn gap(&self, gap: &Gap) {
+        if self.tx.receiver_count() > 0 {
+            self.send(gap.to_json().to_string());
+        }
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "socket",
+            dropped: self.skipped.load(Ordering::Relaxed),
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    warn!("socket sink task failed: {error}");
+                }
+            }
+            let _ = fs::remove_file(&self.path);
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,539 @@
+use {
+    crate::sink::{AckTracker, SinkHealth, UpdateSink},
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    rusqlite::{params, Connection, TransactionBehavior},
+    std::{
+        env,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::{timeout_at, Instant},
+    },
+    yellowstone_grpc_proto::{
+        convert_from,
+        prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+    },
+};
+
+/// Schema versions in order, the applied version is the `user_version` of the database.
+/// Released migrations must never change, schema changes are added as a new version.
+const MIGRATIONS: &[(i32, &str, &str)] = &[(
+    1,
+    "create tables",
+    include_str!("sqlite/0001_create_tables.sql"),
+)];
+/// Readers of the database hold locks briefly, writes wait for them instead of failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct SqliteConfig {
+    pub path: PathBuf,
+    pub batch_size: usize,
+    pub batch_max_delay: Duration,
+    pub queue_size: usize,
+    /// `synchronous` pragma, `NORMAL` keeps the database consistent on power loss but may
+    /// lose the last batches, `FULL` syncs every batch
+    pub synchronous: &'static str,
+    /// Apply pending migrations on start, disabled with `NO_MIGRATE=true`
+    pub migrate: bool,
+}
+
+impl SqliteConfig {
+    /// Returns `None` if `SQLITE_PATH` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(path) = env::var("SQLITE_PATH") else {
+            return Ok(None);
+        };
+
+        let parse_usize = |key: &str, default: usize| -> anyhow::Result<usize> {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+                .map(|value| value.unwrap_or(default))
+        };
+
+        Ok(Some(Self {
+            path: PathBuf::from(path),
+            batch_size: parse_usize("SQLITE_BATCH_SIZE", 500)?.max(1),
+            batch_max_delay: Duration::from_millis(
+                parse_usize("SQLITE_BATCH_MAX_DELAY_MS", 100)? as u64
+            ),
+            queue_size: parse_usize("SQLITE_QUEUE_SIZE", 100_000)?.max(1),
+            synchronous: match env::var("SQLITE_SYNCHRONOUS").as_deref() {
+                Ok("normal") | Err(_) => "NORMAL",
+                Ok("full") => "FULL",
+                Ok(_) => anyhow::bail!("invalid SQLITE_SYNCHRONOUS, expected `normal` or `full`"),
+            },
+            migrate: !env::var("NO_MIGRATE")
+                .ok()
+                .map(|value| value.parse::<bool>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid NO_MIGRATE"))?
+                .unwrap_or(false),
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Row {
+    Account {
+        pubkey: String,
+        owner: String,
+        lamports: i64,
+        executable: bool,
+        rent_epoch: i64,
+        data: Vec<u8>,
+        slot: i64,
+        write_version: i64,
+        txn_signature: Option<String>,
+    },
+    /// Full transactions and transaction statuses, statuses have no fee and account keys
+    Transaction {
+        signature: String,
+        slot: i64,
+        is_vote: bool,
+        index: i64,
+        err: Option<String>,
+        fee: Option<i64>,
+        /// Static keys followed by keys loaded from lookup tables, the first is the signer
+        account_keys: Vec<String>,
+    },
+}
+
+impl Row {
+    fn slot(&self) -> u64 {
+        match self {
+            Self::Account { slot, .. } | Self::Transaction { slot, .. } => *slot as u64,
+        }
+    }
+
+    fn from_update(msg: &SubscribeUpdate) -> Option<Self> {
+        match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => {
+                let account = update.account.as_ref()?;
+                Some(Self::Account {
+                    pubkey: bs58::encode(&account.pubkey).into_string(),
+                    owner: bs58::encode(&account.owner).into_string(),
+                    lamports: account.lamports as i64,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch as i64,
+                    data: account.data.clone(),
+                    slot: update.slot as i64,
+                    write_version: account.write_version as i64,
+                    txn_signature: account
+                        .txn_signature
+                        .as_ref()
+                        .map(|signature| bs58::encode(signature).into_string()),
+                })
+            }
+            UpdateOneof::Transaction(update) => {
+                let tx = update.transaction.as_ref()?;
+                let meta = tx.meta.as_ref();
+                let account_keys = tx
+                    .transaction
+                    .as_ref()
+                    .and_then(|tx| tx.message.as_ref())
+                    .map(|message| message.account_keys.iter())
+                    .into_iter()
+                    .flatten()
+                    .chain(
+                        meta.map(|meta| {
+                            meta.loaded_writable_addresses
+                                .iter()
+                                .chain(meta.loaded_readonly_addresses.iter())
+                        })
+                        .into_iter()
+                        .flatten(),
+                    )
+                    .map(|key| bs58::encode(key).into_string())
+                    .collect();
+                Some(Self::Transaction {
+                    signature: bs58::encode(&tx.signature).into_string(),
+                    slot: update.slot as i64,
+                    is_vote: tx.is_vote,
+                    index: tx.index as i64,
+                    err: convert_from::create_tx_error(meta.and_then(|meta| meta.err.as_ref()))
+                        .ok()
+                        .flatten()
+                        .map(|err| err.to_string()),
+                    fee: meta.map(|meta| meta.fee as i64),
+                    account_keys,
+                })
+            }
+            UpdateOneof::TransactionStatus(status) => Some(Self::Transaction {
+                signature: bs58::encode(&status.signature).into_string(),
+                slot: status.slot as i64,
+                is_vote: status.is_vote,
+                index: status.index as i64,
+                err: convert_from::create_tx_error(status.err.as_ref())
+                    .ok()
+                    .flatten()
+                    .map(|err| err.to_string()),
+                fee: None,
+                account_keys: vec![],
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Open the database in WAL mode, so readers don't block the writer, and apply migrations
+fn open(config: &SqliteConfig) -> anyhow::Result<Connection> {
+    let mut conn = Connection::open(&config.path)
+        .map_err(|error| anyhow::anyhow!("failed to open {}: {error}", config.path.display()))?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    let mode =
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    anyhow::ensure!(
+        mode.eq_ignore_ascii_case("wal"),
+        "failed to enable WAL mode on {}, journal mode is {mode}",
+        config.path.display()
+    );
+    conn.pragma_update(None, "synchronous", config.synchronous)?;
+    migrate(&mut conn, config.migrate)
+        .map_err(|error| anyhow::anyhow!("sqlite: {}: {error}", config.path.display()))?;
+    Ok(conn)
+}
+
+/// Apply migrations newer than `user_version`, an immediate transaction makes concurrent
+/// clients wait for each other. Without `apply` (`NO_MIGRATE=true`) only checks that
+/// `user_version` is the latest.
+fn migrate(conn: &mut Connection, apply: bool) -> anyhow::Result<()> {
+    if !apply {
+        let latest = MIGRATIONS.last().map_or(0, |(version, _, _)| *version);
+        let version = conn
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0))
+            .map_err(|error| {
+                anyhow::anyhow!("failed to read the schema version with NO_MIGRATE=true: {error}")
+            })?;
+        anyhow::ensure!(
+            version >= latest,
+            "schema is at version {version}, latest is {latest}, apply the migrations or unset \
+            NO_MIGRATE"
+        );
+        return Ok(());
+    }
+
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = transaction.query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0))?;
+    for (migration, name, sql) in MIGRATIONS.iter().filter(|(v, _, _)| *v > version) {
+        transaction.execute_batch(sql).map_err(|error| {
+            anyhow::anyhow!("failed to apply migration {migration} ({name}): {error}")
+        })?;
+        transaction.pragma_update(None, "user_version", migration)?;
+        info!("sqlite: applied migration {migration} ({name})");
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Write a batch in one transaction
+fn write(conn: &mut Connection, batch: &[Row]) -> anyhow::Result<()> {
+    let transaction = conn.transaction()?;
+    {
+        let mut account = transaction.prepare_cached(
+            "INSERT INTO accounts AS t \
+                (pubkey, owner, lamports, executable, rent_epoch, data, slot, write_version, txn_signature) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) \
+            ON CONFLICT (pubkey) DO UPDATE SET \
+                owner = excluded.owner, \
+                lamports = excluded.lamports, \
+                executable = excluded.executable, \
+                rent_epoch = excluded.rent_epoch, \
+                data = excluded.data, \
+                slot = excluded.slot, \
+                write_version = excluded.write_version, \
+                txn_signature = excluded.txn_signature \
+            WHERE t.write_version < excluded.write_version",
+        )?;
+        // A status and the full transaction of the same signature fill one row
+        let mut transaction_row = transaction.prepare_cached(
+            "INSERT INTO transactions AS t (signature, slot, is_vote, tx_index, err, fee, signer) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+            ON CONFLICT (signature, slot) DO UPDATE SET \
+                fee = coalesce(excluded.fee, t.fee), \
+                signer = coalesce(excluded.signer, t.signer)",
+        )?;
+        let mut transaction_account = transaction.prepare_cached(
+            "INSERT INTO transaction_accounts (pubkey, slot, signature) VALUES (?1, ?2, ?3) \
+            ON CONFLICT DO NOTHING",
+        )?;
+
+        for row in batch {
+            match row {
+                Row::Account {
+                    pubkey,
+                    owner,
+                    lamports,
+                    executable,
+                    rent_epoch,
+                    data,
+                    slot,
+                    write_version,
+                    txn_signature,
+                } => {
+                    account.execute(params![
+                        pubkey,
+                        owner,
+                        lamports,
+                        executable,
+                        rent_epoch,
+                        data,
+                        slot,
+                        write_version,
+                        txn_signature,
+                    ])?;
+                }
+                Row::Transaction {
+                    signature,
+                    slot,
+                    is_vote,
+                    index,
+                    err,
+                    fee,
+                    account_keys,
+                } => {
+                    transaction_row.execute(params![
+                        signature,
+                        slot,
+                        is_vote,
+                        index,
+                        err,
+                        fee,
+                        account_keys.first(),
+                    ])?;
+                    for pubkey in account_keys {
+                        transaction_account.execute(params![pubkey, slot, signature])?;
+                    }
+                }
+            }
+        }
+    }
+    transaction.commit().map_err(Into::into)
+}
+
+/// Writes account updates (latest state per pubkey) and transactions to a SQLite file.
+///
+/// Rows are queued in a bounded channel and written in batches, one transaction per batch,
+/// by a background task, if the queue is full new rows are dropped so the gRPC stream is
+/// never blocked by the disk.
+pub struct SqliteSink {
+    path: PathBuf,
+    tx: mpsc::Sender<Row>,
+    dropped: AtomicU64,
+    /// Failed batch writes
+    errors: Arc<AtomicU64>,
+    /// Rows are acknowledged once their batch is committed
+    acks: Arc<AckTracker>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SqliteSink {
+    pub async fn spawn(config: SqliteConfig) -> anyhow::Result<Self> {
+        let conn = {
+            let config = config.clone();
+            tokio::task::spawn_blocking(move || open(&config)).await??
+        };
+        info!(
+            "sqlite sink writing to {}, synchronous {}",
+            config.path.display(),
+            config.synchronous
+        );
+
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let acks = Arc::new(AckTracker::default());
+        let task = tokio::spawn(Self::run(
+            conn,
+            config.clone(),
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+            Arc::clone(&acks),
+        ));
+
+        Ok(Self {
+            path: config.path,
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            acks,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        mut conn: Connection,
+        config: SqliteConfig,
+        mut rx: mpsc::Receiver<Row>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+        acks: Arc<AckTracker>,
+    ) {
+        loop {
+            let row = tokio::select! {
+                row = rx.recv() => row,
+                () = shutdown.notified() => {
+                    // Stop accepting new rows, already queued rows are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(row) = row else {
+                break;
+            };
+
+            let mut batch = vec![row];
+            let deadline = Instant::now() + config.batch_max_delay;
+            while batch.len() < config.batch_size {
+                match timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(row)) => batch.push(row),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            // Writes and syncs block
+            let result = tokio::task::spawn_blocking(move || {
+                let result = write(&mut conn, &batch);
+                (conn, batch, result)
+            })
+            .await;
+            let batch = match result {
+                Ok((returned, batch, Ok(()))) => {
+                    conn = returned;
+                    batch
+                }
+                Ok((returned, batch, Err(error))) => {
+                    conn = returned;
+                    errors.fetch_add(1, Ordering::Relaxed);
+                    error!("sqlite: failed to write {} rows: {error}", batch.len());
+                    continue;
+                }
+                Err(error) => {
+                    error!("sqlite writer task failed: {error}");
+                    return;
+                }
+            };
+            for row in batch.iter() {
+                acks.acked(row.slot());
+            }
+        }
+        info!("sqlite sink stopped");
+    }
+}
+
+impl UpdateSink for SqliteSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        let Some(row) = Row::from_update(msg) else {
+            return Ok(());
+        };
+        self.acks.sent(row.slot());
+
+        if self.tx.try_send(row).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 10_000 == 1 {
+                warn!("sqlite: queue is full, {dropped} rows dropped in total");
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "sqlite",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: self.acks.acked_slot(),
+            dry_run: None,
+        }
+    }
+
+    fn acks(&self) -> Option<&AckTracker> {
+        Some(&self.acks)
+    }
+
+    /// The database is opened and migrated, or its version checked, on start, this checks it
+    /// is not read-only, on a separate connection which is closed afterwards
+    fn preflight(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        let path = self.path.clone();
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let conn = Connection::open(&path)?;
+                conn.busy_timeout(BUSY_TIMEOUT)?;
+                conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+                    .map_err(|error| anyhow::anyhow!("can't write to {}: {error}", path.display()))
+            })
+            .await?
+        }
+        .boxed()
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("sqlite sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_migrate_requires_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(migrate(&mut conn, false).is_err());
+        migrate(&mut conn, true).unwrap();
+        migrate(&mut conn, false).unwrap();
+        // Nothing is applied twice
+        migrate(&mut conn, true).unwrap();
+    }
+
+    #[test]
+    fn migrations_are_applied_in_order() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(
+                pair[0].0 < pair[1].0,
+                "migration {} is out of order",
+                pair[1].0
+            );
+        }
+        assert_eq!(MIGRATIONS.first().map(|(version, _, _)| *version), Some(1));
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, true).unwrap();
+        let version: i32 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.last().unwrap().0);
+        for table in ["accounts", "transactions"] {
+            let count: i64 = conn
+                .query_row(
+                    "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                    [table],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(count, 1, "table {table}");
+        }
+    }
+}
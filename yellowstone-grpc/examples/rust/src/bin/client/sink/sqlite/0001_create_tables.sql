@@ -0,0 +1,33 @@
+CREATE TABLE IF NOT EXISTS accounts (
+    pubkey TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    lamports INTEGER NOT NULL,
+    executable INTEGER NOT NULL,
+    rent_epoch INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    slot INTEGER NOT NULL,
+    write_version INTEGER NOT NULL,
+    txn_signature TEXT
+);
+CREATE INDEX IF NOT EXISTS accounts_owner ON accounts (owner);
+CREATE INDEX IF NOT EXISTS accounts_slot ON accounts (slot);
+
+CREATE TABLE IF NOT EXISTS transactions (
+    signature TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    is_vote INTEGER NOT NULL,
+    tx_index INTEGER NOT NULL,
+    err TEXT,
+    fee INTEGER,
+    signer TEXT,
+    PRIMARY KEY (signature, slot)
+);
+CREATE INDEX IF NOT EXISTS transactions_slot ON transactions (slot);
+CREATE INDEX IF NOT EXISTS transactions_signer ON transactions (signer);
+
+CREATE TABLE IF NOT EXISTS transaction_accounts (
+    pubkey TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    PRIMARY KEY (pubkey, slot, signature)
+) WITHOUT ROWID;
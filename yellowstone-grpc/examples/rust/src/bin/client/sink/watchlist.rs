@@ -0,0 +1,305 @@
+//! Alerts of watched pubkeys, see `watchlist`.
+//!
+//! An alert is a JSON document with the pubkey, its label, the rule and the update which
+//! triggered it. It is posted to `WATCHLIST_WEBHOOK_URL` and passed on stdin to
+//! `sh -c <WATCHLIST_ALERT_CMD>`, at least one of them is required if the watchlist has
+//! alert rules. With `WATCHLIST_COOLDOWN_SECS` the same rule of a pubkey alerts at most
+//! once per cooldown.
+
+use {
+    crate::{
+        sink::{SinkHealth, UpdateSink},
+        watchlist::{AlertRule, Watchlist},
+    },
+    futures::future::{BoxFuture, FutureExt},
+    log::{error, info, warn},
+    serde_json::{json, Value},
+    std::{
+        collections::HashMap,
+        env,
+        process::Stdio,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex as StdMutex,
+        },
+        time::{Duration, Instant},
+    },
+    tokio::{
+        io::AsyncWriteExt,
+        process::Command,
+        sync::{mpsc, Mutex, Notify},
+        task::JoinHandle,
+        time::timeout,
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+const QUEUE_SIZE: usize = 10_000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+    pub webhook_url: Option<String>,
+    pub command: Option<String>,
+    /// Same rule of a pubkey alerts at most once per cooldown
+    pub cooldown: Option<Duration>,
+}
+
+impl AlertConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let cooldown = env::var("WATCHLIST_COOLDOWN_SECS")
+            .ok()
+            .map(|value| value.parse::<u64>())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("invalid WATCHLIST_COOLDOWN_SECS"))?;
+        let config = Self {
+            webhook_url: env::var("WATCHLIST_WEBHOOK_URL").ok(),
+            command: env::var("WATCHLIST_ALERT_CMD").ok(),
+            cooldown: cooldown.filter(|secs| *secs > 0).map(Duration::from_secs),
+        };
+        anyhow::ensure!(
+            config.webhook_url.is_some() || config.command.is_some(),
+            "watchlist has alert rules, WATCHLIST_WEBHOOK_URL or WATCHLIST_ALERT_CMD is required"
+        );
+        Ok(config)
+    }
+}
+
+/// Checks alert rules of watched pubkeys and sends alerts in background, if the queue is
+/// full new alerts are dropped
+pub struct WatchlistSink {
+    watchlist: Arc<Watchlist>,
+    cooldown: Option<Duration>,
+    /// Last lamports of watched accounts with `lamports_drop`
+    lamports: StdMutex<HashMap<String, u64>>,
+    /// When an alert was last sent, by pubkey and rule
+    sent: StdMutex<HashMap<(String, AlertRule), Instant>>,
+    tx: mpsc::Sender<Value>,
+    dropped: AtomicU64,
+    /// Failed requests and commands
+    errors: Arc<AtomicU64>,
+    shutdown: Arc<Notify>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WatchlistSink {
+    pub fn spawn(watchlist: Arc<Watchlist>, config: AlertConfig) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        info!("watchlist alerts started");
+
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let shutdown = Arc::new(Notify::new());
+        let errors = Arc::new(AtomicU64::new(0));
+        let cooldown = config.cooldown;
+        let task = tokio::spawn(Self::run(
+            http,
+            config,
+            rx,
+            Arc::clone(&shutdown),
+            Arc::clone(&errors),
+        ));
+
+        Ok(Self {
+            watchlist,
+            cooldown,
+            lamports: StdMutex::default(),
+            sent: StdMutex::default(),
+            tx,
+            dropped: AtomicU64::new(0),
+            errors,
+            shutdown,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    async fn run(
+        http: reqwest::Client,
+        config: AlertConfig,
+        mut rx: mpsc::Receiver<Value>,
+        shutdown: Arc<Notify>,
+        errors: Arc<AtomicU64>,
+    ) {
+        loop {
+            let alert = tokio::select! {
+                alert = rx.recv() => alert,
+                () = shutdown.notified() => {
+                    // Stop accepting new alerts, already queued alerts are still received
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(alert) = alert else {
+                break;
+            };
+
+            if let Some(url) = config.webhook_url.as_ref() {
+                if let Err(error) = http
+                    .post(url)
+                    .json(&alert)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                    // Webhook URLs contain secrets
+                    error!("watchlist: failed to post alert: {}", error.without_url());
+                }
+            }
+            if let Some(command) = config.command.as_ref() {
+                if let Err(error) = run_command(command, &alert.to_string()).await {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                    warn!("watchlist: alert command failed: {error}");
+                }
+            }
+        }
+        info!("watchlist alerts stopped");
+    }
+}
+
+/// Run the command with the alert as its stdin, it is killed after `REQUEST_TIMEOUT`
+async fn run_command(command: &str, payload: &str) -> anyhow::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("piped");
+    let run = async {
+        // A command which does not read its input closes stdin early, that is fine
+        let _ = stdin.write_all(payload.as_bytes()).await;
+        drop(stdin);
+        child.wait_with_output().await
+    };
+    let output = timeout(REQUEST_TIMEOUT, run)
+        .await
+        .map_err(|_| anyhow::anyhow!("killed after {REQUEST_TIMEOUT:?}"))??;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if !line.is_empty() {
+            info!("watchlist: {line}");
+        }
+    }
+    anyhow::ensure!(
+        output.status.success(),
+        "{}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
+
+impl WatchlistSink {
+    /// Returns `false` if the same rule of the pubkey alerted within the cooldown
+    fn check_cooldown(&self, pubkey: &str, rule: AlertRule) -> bool {
+        let Some(cooldown) = self.cooldown else {
+            return true;
+        };
+        let now = Instant::now();
+        let mut sent = self.sent.lock().expect("poisoned");
+        match sent.get_mut(&(pubkey.to_owned(), rule)) {
+            Some(last) if now.duration_since(*last) < cooldown => false,
+            Some(last) => {
+                *last = now;
+                true
+            }
+            None => {
+                sent.insert((pubkey.to_owned(), rule), now);
+                true
+            }
+        }
+    }
+
+    fn push(&self, alert: Value) {
+        if self.tx.try_send(alert).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped % 1_000 == 1 {
+                warn!("watchlist: queue is full, {dropped} alerts dropped in total");
+            }
+        }
+    }
+}
+
+impl UpdateSink for WatchlistSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        for watched in self.watchlist.touched(msg) {
+            for rule in watched.rules.iter().copied() {
+                let details = match (rule, msg.update_oneof.as_ref()) {
+                    (AlertRule::LamportsDrop(threshold), Some(UpdateOneof::Account(update))) => {
+                        let Some(account) = update.account.as_ref() else {
+                            continue;
+                        };
+                        let previous = self
+                            .lamports
+                            .lock()
+                            .expect("poisoned")
+                            .insert(watched.pubkey.clone(), account.lamports);
+                        let Some(previous) = previous else {
+                            continue;
+                        };
+                        if previous.saturating_sub(account.lamports) <= threshold {
+                            continue;
+                        }
+                        json!({
+                            "kind": "account",
+                            "slot": update.slot,
+                            "lamports": account.lamports,
+                            "previous_lamports": previous,
+                        })
+                    }
+                    (AlertRule::Transaction, Some(UpdateOneof::Transaction(update))) => {
+                        let Some(tx) = update.transaction.as_ref() else {
+                            continue;
+                        };
+                        json!({
+                            "kind": "transaction",
+                            "slot": update.slot,
+                            "signature": bs58::encode(&tx.signature).into_string(),
+                            "failed": tx.meta.as_ref().is_some_and(|meta| meta.err.is_some()),
+                        })
+                    }
+                    _ => continue,
+                };
+                if !self.check_cooldown(&watched.pubkey, rule) {
+                    continue;
+                }
+                let mut alert = json!({
+                    "pubkey": watched.pubkey,
+                    "label": watched.label,
+                    "alert": rule.to_string(),
+                    "filters": msg.filters,
+                });
+                if let (Some(alert), Value::Object(details)) = (alert.as_object_mut(), details) {
+                    alert.extend(details);
+                }
+                self.push(alert);
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "watchlist",
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        async {
+            self.shutdown.notify_one();
+            if let Some(task) = self.task.lock().await.take() {
+                if let Err(error) = task.await {
+                    error!("watchlist sink task failed: {error}");
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,242 @@
+//! Latency objectives of delivery to sinks, `LATENCY_SLO`.
+//!
+//! An objective is a share of the updates of one type which are passed to all sinks within
+//! a threshold, e.g. `account:99:150` is 99% of account updates within 150 ms. The protocol
+//! has no creation time of updates, latency starts when the update is received from the
+//! stream and includes waiting in the processing queue, time deliberately spent in
+//! `HOLD_UNTIL` and account coalescing is not counted. Compliance is tracked since start
+//! and over the last 5 minutes and hour, with the burn rate of the error budget: the share
+//! of late updates divided by the share allowed, above 1 the budget runs out before the
+//! window ends.
+
+use {
+    crate::stats::update_kind,
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, VecDeque},
+        env,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+/// Windows of compliance and burn rate, the objective is met over the longest
+const WINDOWS: [(&str, u64); 2] = [("5m", 300), ("1h", 3_600)];
+const KINDS: [&str; 7] = [
+    "account",
+    "slot",
+    "transaction",
+    "transaction_status",
+    "block",
+    "block_meta",
+    "entry",
+];
+
+/// Updates within the threshold and in total
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    good: u64,
+    total: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Self) {
+        self.good += other.good;
+        self.total += other.total;
+    }
+
+    fn compliance(self) -> Option<f64> {
+        (self.total > 0).then(|| self.good as f64 / self.total as f64)
+    }
+
+    /// Share within the threshold reaches `percent`, also without updates
+    fn meets(self, percent: f64) -> bool {
+        self.good as f64 * 100.0 >= percent * self.total as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct SloState {
+    since_start: Counts,
+    /// Counts by second since start, for the longest window
+    seconds: VecDeque<(u64, Counts)>,
+}
+
+#[derive(Debug)]
+struct Slo {
+    /// As configured, e.g. `account:99:150`
+    name: String,
+    /// `None` for all update types
+    kind: Option<&'static str>,
+    /// Percentage of updates within the threshold, e.g. 99
+    percent: f64,
+    threshold: Duration,
+    state: Mutex<SloState>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WindowStatus {
+    pub updates: u64,
+    pub late: u64,
+    pub compliance_percent: Option<f64>,
+    /// Share of late updates divided by the allowed share
+    pub burn_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SloStatus {
+    pub slo: String,
+    pub kind: &'static str,
+    pub target_percent: f64,
+    pub threshold_ms: u128,
+    pub updates: u64,
+    pub late: u64,
+    pub compliance_percent: Option<f64>,
+    pub windows: BTreeMap<&'static str, WindowStatus>,
+    /// Compliance over the last hour reaches the target, also without updates
+    pub met: bool,
+}
+
+#[derive(Debug)]
+pub struct LatencySlos {
+    started: Instant,
+    slos: Vec<Slo>,
+}
+
+impl LatencySlos {
+    /// Returns `None` if `LATENCY_SLO` is not set, it is a comma-separated list of
+    /// `<type>:<percent>:<ms>`, type `all` for updates of all types
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("LATENCY_SLO") else {
+            return Ok(None);
+        };
+        let slos = value
+            .split(',')
+            .map(str::trim)
+            .filter(|slo| !slo.is_empty())
+            .map(|slo| {
+                Slo::parse(slo).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "invalid LATENCY_SLO `{slo}`, expected <type>:<percent>:<ms>, e.g. \
+                        account:99:150"
+                    )
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(!slos.is_empty(), "invalid LATENCY_SLO, no objectives");
+        Ok(Some(Self {
+            started: Instant::now(),
+            slos,
+        }))
+    }
+
+    /// Update passed to all sinks `latency` after it was received
+    pub fn observe(&self, msg: &SubscribeUpdate, latency: Duration) {
+        let Some(update) = msg.update_oneof.as_ref() else {
+            return;
+        };
+        if matches!(update, UpdateOneof::Ping(_) | UpdateOneof::Pong(_)) {
+            return;
+        }
+        let kind = update_kind(update);
+        let second = self.started.elapsed().as_secs();
+        for slo in self.slos.iter() {
+            if slo.kind.is_none_or(|slo_kind| slo_kind == kind) {
+                slo.observe(second, latency);
+            }
+        }
+    }
+
+    pub fn status(&self) -> Vec<SloStatus> {
+        let second = self.started.elapsed().as_secs();
+        self.slos.iter().map(|slo| slo.status(second)).collect()
+    }
+}
+
+impl Slo {
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(':');
+        let kind = match parts.next()? {
+            "all" => None,
+            kind => Some(*KINDS.iter().find(|known| **known == kind)?),
+        };
+        let percent = parts
+            .next()?
+            .parse::<f64>()
+            .ok()
+            .filter(|percent| *percent > 0.0 && *percent < 100.0)?;
+        let threshold = Duration::from_millis(parts.next()?.parse().ok()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            name: value.to_owned(),
+            kind,
+            percent,
+            threshold,
+            state: Mutex::default(),
+        })
+    }
+
+    fn observe(&self, second: u64, latency: Duration) {
+        let counts = Counts {
+            good: u64::from(latency <= self.threshold),
+            total: 1,
+        };
+        let mut state = self.state.lock().expect("poisoned");
+        state.since_start.add(counts);
+        match state.seconds.back_mut() {
+            Some((last, bucket)) if *last == second => bucket.add(counts),
+            _ => state.seconds.push_back((second, counts)),
+        }
+        let (_, longest) = WINDOWS[WINDOWS.len() - 1];
+        while state
+            .seconds
+            .front()
+            .is_some_and(|(first, _)| first + longest <= second)
+        {
+            state.seconds.pop_front();
+        }
+    }
+
+    fn status(&self, second: u64) -> SloStatus {
+        let state = self.state.lock().expect("poisoned");
+        let budget = 1.0 - self.percent / 100.0;
+        let mut met = true;
+        let windows = WINDOWS
+            .iter()
+            .map(|(name, secs)| {
+                let mut counts = Counts::default();
+                for (_, bucket) in state
+                    .seconds
+                    .iter()
+                    .filter(|(start, _)| start + secs > second)
+                {
+                    counts.add(*bucket);
+                }
+                // The longest window is the last one
+                met = counts.meets(self.percent);
+                let compliance = counts.compliance();
+                let window = WindowStatus {
+                    updates: counts.total,
+                    late: counts.total - counts.good,
+                    compliance_percent: compliance.map(|share| share * 100.0),
+                    burn_rate: compliance.map(|share| (1.0 - share) / budget),
+                };
+                (*name, window)
+            })
+            .collect::<BTreeMap<_, _>>();
+        SloStatus {
+            slo: self.name.clone(),
+            kind: self.kind.unwrap_or("all"),
+            target_percent: self.percent,
+            threshold_ms: self.threshold.as_millis(),
+            updates: state.since_start.total,
+            late: state.since_start.total - state.since_start.good,
+            compliance_percent: state.since_start.compliance().map(|share| share * 100.0),
+            windows,
+            met,
+        }
+    }
+}
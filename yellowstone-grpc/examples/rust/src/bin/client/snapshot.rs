@@ -0,0 +1,293 @@
+//! Initial state of subscribed accounts from Solana JSON-RPC, `RPC_URL`.
+//!
+//! Before the stream is opened, every account filter of the subscription is fetched once:
+//! filters with accounts by `getMultipleAccounts`, filters with only owners by
+//! `getProgramAccounts` with the memcmp and data size conditions. The accounts are passed to
+//! the update queue as account updates with `is_startup` set and the slot of the RPC
+//! response, so they are processed before the first live update. An account matched by
+//! several filters is emitted once with all their names.
+//!
+//! Owner, memcmp and data size conditions are also checked locally, `TOKEN_ACCOUNT_STATE`
+//! can't be evaluated from RPC data and is ignored. Filters without accounts and owners
+//! would match every account and are skipped.
+
+use {
+    base64::{engine::general_purpose::STANDARD, Engine},
+    log::{info, warn},
+    serde::{de::DeserializeOwned, Deserialize},
+    serde_json::{json, Value},
+    std::{collections::BTreeMap, env, time::Duration},
+    yellowstone_grpc_proto::prelude::{
+        subscribe_request_filter_accounts_filter::Filter as AccountsFilterDataOneof,
+        subscribe_request_filter_accounts_filter_memcmp::Data as AccountsFilterMemcmpOneof,
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
+        SubscribeRequestFilterAccounts, SubscribeUpdate, SubscribeUpdateAccount,
+        SubscribeUpdateAccountInfo,
+    },
+};
+
+/// `getProgramAccounts` of large programs can take a while
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+/// Limit of `getMultipleAccounts`
+const MULTIPLE_ACCOUNTS_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcContext {
+    slot: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithContext<T> {
+    context: RpcContext,
+    value: T,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcAccount {
+    /// `[data, "base64"]`
+    data: (String, String),
+    executable: bool,
+    lamports: u64,
+    owner: String,
+    rent_epoch: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcKeyedAccount {
+    pubkey: String,
+    account: RpcAccount,
+}
+
+/// Decoded account matched by filters
+#[derive(Debug)]
+struct Fetched {
+    slot: u64,
+    info: SubscribeUpdateAccountInfo,
+    filters: Vec<String>,
+}
+
+/// Memcmp conditions of the filter as offset and bytes
+fn memcmp_conditions(
+    filter: &SubscribeRequestFilterAccounts,
+) -> anyhow::Result<Vec<(usize, Vec<u8>)>> {
+    let mut memcmp = vec![];
+    for condition in filter.filters.iter() {
+        if let Some(AccountsFilterDataOneof::Memcmp(condition)) = condition.filter.as_ref() {
+            let data = match condition.data.as_ref() {
+                Some(AccountsFilterMemcmpOneof::Bytes(data)) => data.clone(),
+                Some(AccountsFilterMemcmpOneof::Base58(data)) => bs58::decode(data).into_vec()?,
+                Some(AccountsFilterMemcmpOneof::Base64(data)) => STANDARD.decode(data)?,
+                None => continue,
+            };
+            memcmp.push((condition.offset as usize, data));
+        }
+    }
+    Ok(memcmp)
+}
+
+fn datasize(filter: &SubscribeRequestFilterAccounts) -> Option<u64> {
+    filter
+        .filters
+        .iter()
+        .find_map(|condition| match condition.filter {
+            Some(AccountsFilterDataOneof::Datasize(datasize)) => Some(datasize),
+            _ => None,
+        })
+}
+
+#[derive(Debug)]
+pub struct Snapshot {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl Snapshot {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = env::var("RPC_URL") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            url,
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+        }))
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> anyhow::Result<T> {
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| anyhow::anyhow!("{method} failed, check RPC_URL: {error}"))?
+            .json::<RpcResponse<T>>()
+            .await
+            .map_err(|error| anyhow::anyhow!("invalid {method} response: {error}"))?;
+        match (response.result, response.error) {
+            (_, Some(error)) => anyhow::bail!("{method} failed: {error}"),
+            (Some(result), None) => Ok(result),
+            (None, None) => anyhow::bail!("{method} returned no result"),
+        }
+    }
+
+    /// Fetch accounts of all account filters of the request, as startup updates
+    pub async fn fetch(&self, request: &SubscribeRequest) -> anyhow::Result<Vec<SubscribeUpdate>> {
+        let commitment = request
+            .commitment
+            .and_then(|commitment| CommitmentLevel::try_from(commitment).ok())
+            .unwrap_or(CommitmentLevel::Processed)
+            .as_str_name()
+            .to_lowercase();
+
+        let mut accounts = BTreeMap::<String, Fetched>::new();
+        for (name, filter) in request.accounts.iter() {
+            let fetched = if !filter.account.is_empty() {
+                self.multiple_accounts(&filter.account, &commitment).await?
+            } else if !filter.owner.is_empty() {
+                let mut fetched = vec![];
+                for owner in filter.owner.iter() {
+                    fetched.extend(self.program_accounts(owner, filter, &commitment).await?);
+                }
+                fetched
+            } else {
+                warn!("snapshot: filter {name} matches every account, skipped");
+                continue;
+            };
+
+            let memcmp = memcmp_conditions(filter)?;
+            let datasize = datasize(filter);
+            for (pubkey, slot, account) in fetched {
+                let owner = bs58::encode(&account.owner).into_string();
+                if (!filter.owner.is_empty() && !filter.owner.contains(&owner))
+                    || datasize.is_some_and(|datasize| account.data.len() as u64 != datasize)
+                    || !memcmp.iter().all(|(offset, bytes)| {
+                        account.data.get(*offset..*offset + bytes.len()) == Some(bytes.as_slice())
+                    })
+                {
+                    continue;
+                }
+                accounts
+                    .entry(pubkey)
+                    .or_insert_with(|| Fetched {
+                        slot,
+                        info: account,
+                        filters: vec![],
+                    })
+                    .filters
+                    .push(name.clone());
+            }
+        }
+
+        info!("snapshot: {} accounts fetched from RPC", accounts.len());
+        Ok(accounts
+            .into_values()
+            .map(|mut fetched| {
+                if !request.accounts_data_slice.is_empty() {
+                    fetched.info.data = request
+                        .accounts_data_slice
+                        .iter()
+                        .flat_map(|slice| {
+                            let start = (slice.offset as usize).min(fetched.info.data.len());
+                            let end = (start + slice.length as usize).min(fetched.info.data.len());
+                            fetched.info.data[start..end].to_vec()
+                        })
+                        .collect();
+                }
+                SubscribeUpdate {
+                    filters: fetched.filters,
+                    update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                        account: Some(fetched.info),
+                        slot: fetched.slot,
+                        is_startup: true,
+                    })),
+                }
+            })
+            .collect())
+    }
+
+    async fn multiple_accounts(
+        &self,
+        pubkeys: &[String],
+        commitment: &str,
+    ) -> anyhow::Result<Vec<(String, u64, SubscribeUpdateAccountInfo)>> {
+        let mut fetched = vec![];
+        for chunk in pubkeys.chunks(MULTIPLE_ACCOUNTS_LIMIT) {
+            let response = self
+                .call::<WithContext<Vec<Option<RpcAccount>>>>(
+                    "getMultipleAccounts",
+                    json!([chunk, { "encoding": "base64", "commitment": commitment }]),
+                )
+                .await?;
+            for (pubkey, account) in chunk.iter().zip(response.value) {
+                if let Some(account) = account {
+                    let info = decode(pubkey, account)?;
+                    fetched.push((pubkey.clone(), response.context.slot, info));
+                }
+            }
+        }
+        Ok(fetched)
+    }
+
+    async fn program_accounts(
+        &self,
+        owner: &str,
+        filter: &SubscribeRequestFilterAccounts,
+        commitment: &str,
+    ) -> anyhow::Result<Vec<(String, u64, SubscribeUpdateAccountInfo)>> {
+        let mut filters = memcmp_conditions(filter)?
+            .into_iter()
+            .map(|(offset, bytes)| {
+                json!({ "memcmp": { "offset": offset, "bytes": bs58::encode(bytes).into_string() } })
+            })
+            .collect::<Vec<_>>();
+        if let Some(datasize) = datasize(filter) {
+            filters.push(json!({ "dataSize": datasize }));
+        }
+
+        let response = self
+            .call::<WithContext<Vec<RpcKeyedAccount>>>(
+                "getProgramAccounts",
+                json!([owner, {
+                    "encoding": "base64",
+                    "commitment": commitment,
+                    "withContext": true,
+                    "filters": filters,
+                }]),
+            )
+            .await?;
+        response
+            .value
+            .into_iter()
+            .map(|keyed| {
+                let info = decode(&keyed.pubkey, keyed.account)?;
+                Ok((keyed.pubkey, response.context.slot, info))
+            })
+            .collect()
+    }
+}
+
+fn decode(pubkey: &str, account: RpcAccount) -> anyhow::Result<SubscribeUpdateAccountInfo> {
+    let invalid = || anyhow::anyhow!("invalid RPC account {pubkey}");
+    Ok(SubscribeUpdateAccountInfo {
+        pubkey: bs58::decode(pubkey).into_vec().map_err(|_| invalid())?,
+        lamports: account.lamports,
+        owner: bs58::decode(&account.owner)
+            .into_vec()
+            .map_err(|_| invalid())?,
+        executable: account.executable,
+        rent_epoch: account.rent_epoch,
+        data: STANDARD.decode(&account.data.0).map_err(|_| invalid())?,
+        write_version: 0,
+        txn_signature: None,
+    })
+}
@@ -0,0 +1,279 @@
+//! Full snapshots and diffs of account state by filter, `STATE_SNAPSHOT_SECS`.
+//!
+//! The latest state of every account matched by an account filter is kept per filter name.
+//! Every `STATE_SNAPSHOT_SECS` a `state_diff` event per filter lists the accounts updated and
+//! closed since the previous event of the filter, and every `STATE_SNAPSHOT_FULL_EVERY`th
+//! event is a `state_snapshot` with the full state instead, so a downstream service applies
+//! diffs to its copy and bootstraps or resynchronizes from the next snapshot without an RPC
+//! crawl. Events of a filter are numbered by `seq`, a diff names the `base` it applies to.
+//! At most `STATE_SNAPSHOT_CAPACITY` accounts are kept over all filters, new accounts over
+//! the limit are counted as dropped.
+
+use {
+    crate::sink::{SinkHealth, UpdateSink},
+    base64::{engine::general_purpose::STANDARD, Engine},
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        env,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    },
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, SubscribeUpdate, SubscribeUpdateAccountInfo,
+    },
+};
+
+const DEFAULT_FULL_EVERY: u64 = 10;
+const DEFAULT_CAPACITY: usize = 1_000_000;
+
+#[derive(Debug)]
+struct AccountState {
+    slot: u64,
+    info: SubscribeUpdateAccountInfo,
+}
+
+#[derive(Debug, Default)]
+struct FilterState {
+    accounts: HashMap<Vec<u8>, AccountState>,
+    /// Pubkeys updated or closed since the last event
+    changed: HashSet<Vec<u8>>,
+    /// Last event of the filter, diffs apply to it
+    seq: u64,
+    slot: u64,
+}
+
+/// Account in `state_snapshot` and `state_diff` events
+#[derive(Debug, Serialize)]
+pub struct AccountEntry {
+    /// Base58
+    pub pubkey: String,
+    pub owner: String,
+    pub lamports: u64,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    /// Base64
+    pub data: String,
+    pub slot: u64,
+    pub write_version: u64,
+}
+
+impl AccountEntry {
+    fn new(state: &AccountState) -> Self {
+        Self {
+            pubkey: bs58::encode(&state.info.pubkey).into_string(),
+            owner: bs58::encode(&state.info.owner).into_string(),
+            lamports: state.info.lamports,
+            executable: state.info.executable,
+            rent_epoch: state.info.rent_epoch,
+            data: STANDARD.encode(&state.info.data),
+            slot: state.slot,
+            write_version: state.info.write_version,
+        }
+    }
+}
+
+/// Event of one filter, a `state_snapshot` if `base` is not set
+#[derive(Debug, Serialize)]
+pub struct StateEvent {
+    pub filter: String,
+    pub seq: u64,
+    /// Sequence number of the event the diff applies to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<u64>,
+    /// Highest slot of the updates of the filter
+    pub slot: u64,
+    /// All accounts in a snapshot, updated ones in a diff
+    pub accounts: Vec<AccountEntry>,
+    /// Pubkeys closed since the base
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl StateEvent {
+    pub fn name(&self) -> &'static str {
+        match self.base {
+            Some(_) => "state_diff",
+            None => "state_snapshot",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StateSync {
+    pub interval: Duration,
+    /// Every `full_every`th event of a filter is a full snapshot
+    full_every: u64,
+    /// Only these filters are kept, all account filters if empty
+    filters: HashSet<String>,
+    capacity: usize,
+    state: Mutex<BTreeMap<String, FilterState>>,
+    accounts: AtomicU64,
+    /// New accounts not kept because `STATE_SNAPSHOT_CAPACITY` was reached
+    dropped: AtomicU64,
+}
+
+impl StateSync {
+    /// Returns `None` if `STATE_SNAPSHOT_SECS` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(value) = env::var("STATE_SNAPSHOT_SECS") else {
+            return Ok(None);
+        };
+        let interval = value
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .ok_or_else(|| anyhow::anyhow!("invalid STATE_SNAPSHOT_SECS"))?;
+        let full_every = match env::var("STATE_SNAPSHOT_FULL_EVERY") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|full_every| *full_every > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid STATE_SNAPSHOT_FULL_EVERY"))?,
+            Err(_) => DEFAULT_FULL_EVERY,
+        };
+        let filters = env::var("STATE_SNAPSHOT_FILTERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|filter| !filter.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let capacity = match env::var("STATE_SNAPSHOT_CAPACITY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("invalid STATE_SNAPSHOT_CAPACITY"))?,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        Ok(Some(Self {
+            interval,
+            full_every,
+            filters,
+            capacity,
+            state: Mutex::default(),
+            accounts: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    /// Accounts kept over all filters
+    pub fn accounts(&self) -> u64 {
+        self.accounts.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn observe(&self, msg: &SubscribeUpdate) {
+        let Some(UpdateOneof::Account(update)) = msg.update_oneof.as_ref() else {
+            return;
+        };
+        let Some(account) = update.account.as_ref() else {
+            return;
+        };
+        let mut state = self.state.lock().expect("poisoned");
+        for filter in msg.filters.iter() {
+            if !self.filters.is_empty() && !self.filters.contains(filter) {
+                continue;
+            }
+            let filter = state.entry(filter.clone()).or_default();
+            // Updates of one account can arrive out of order from several workers
+            if filter.accounts.get(&account.pubkey).is_some_and(|current| {
+                (current.slot, current.info.write_version) > (update.slot, account.write_version)
+            }) {
+                continue;
+            }
+            filter.slot = filter.slot.max(update.slot);
+            if account.lamports == 0 {
+                if filter.accounts.remove(&account.pubkey).is_some() {
+                    self.accounts.fetch_sub(1, Ordering::Relaxed);
+                    filter.changed.insert(account.pubkey.clone());
+                }
+                continue;
+            }
+            if !filter.accounts.contains_key(&account.pubkey) {
+                if self.accounts.load(Ordering::Relaxed) >= self.capacity as u64 {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                self.accounts.fetch_add(1, Ordering::Relaxed);
+            }
+            filter.changed.insert(account.pubkey.clone());
+            filter.accounts.insert(
+                account.pubkey.clone(),
+                AccountState {
+                    slot: update.slot,
+                    info: account.clone(),
+                },
+            );
+        }
+    }
+
+    /// Next event of every filter: a snapshot every `STATE_SNAPSHOT_FULL_EVERY`th event,
+    /// the first one included, otherwise a diff if anything changed
+    pub fn events(&self) -> Vec<StateEvent> {
+        let mut state = self.state.lock().expect("poisoned");
+        let mut events = vec![];
+        for (name, filter) in state.iter_mut() {
+            let snapshot = filter.seq % self.full_every == 0;
+            if !snapshot && filter.changed.is_empty() {
+                continue;
+            }
+            let base = (!snapshot).then_some(filter.seq);
+            filter.seq += 1;
+            let changed = std::mem::take(&mut filter.changed);
+            let (accounts, removed) = if snapshot {
+                let accounts = filter.accounts.values().map(AccountEntry::new).collect();
+                (accounts, vec![])
+            } else {
+                let removed = changed
+                    .iter()
+                    .filter(|pubkey| !filter.accounts.contains_key(*pubkey))
+                    .map(|pubkey| bs58::encode(pubkey).into_string())
+                    .collect::<BTreeSet<_>>();
+                let accounts = changed
+                    .iter()
+                    .filter_map(|pubkey| filter.accounts.get(pubkey))
+                    .map(AccountEntry::new)
+                    .collect();
+                (accounts, removed.into_iter().collect())
+            };
+            events.push(StateEvent {
+                filter: name.clone(),
+                seq: filter.seq,
+                base,
+                slot: filter.slot,
+                accounts,
+                removed,
+            });
+        }
+        events
+    }
+}
+
+/// Keeps account state of updates passed to sinks in `StateSync`
+pub struct StateSyncSink(pub Arc<StateSync>);
+
+impl UpdateSink for StateSyncSink {
+    fn handle(&self, msg: &SubscribeUpdate) -> anyhow::Result<()> {
+        self.0.observe(msg);
+        Ok(())
+    }
+
+    fn health(&self) -> SinkHealth {
+        SinkHealth {
+            name: "state_sync",
+            dropped: self.0.dropped(),
+            errors: 0,
+            acked_slot: None,
+            dry_run: None,
+        }
+    }
+}
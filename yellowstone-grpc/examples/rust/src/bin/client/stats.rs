@@ -0,0 +1,288 @@
+use {
+    crate::output::OutputFormat,
+    log::info,
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Instant,
+    },
+    yellowstone_grpc_proto::{
+        prelude::{subscribe_update::UpdateOneof, SubscribeRequest, SubscribeUpdate},
+        prost::Message,
+    },
+};
+
+/// Slot of the update, if the update has one
+pub fn update_slot(msg: &SubscribeUpdate) -> Option<u64> {
+    Some(match msg.update_oneof.as_ref()? {
+        UpdateOneof::Account(msg) => msg.slot,
+        UpdateOneof::Slot(msg) => msg.slot,
+        UpdateOneof::Transaction(msg) => msg.slot,
+        UpdateOneof::TransactionStatus(msg) => msg.slot,
+        UpdateOneof::Block(msg) => msg.slot,
+        UpdateOneof::BlockMeta(msg) => msg.slot,
+        UpdateOneof::Entry(msg) => msg.slot,
+        UpdateOneof::Ping(_) | UpdateOneof::Pong(_) => return None,
+    })
+}
+
+/// Name of the update type
+pub fn update_kind(update: &UpdateOneof) -> &'static str {
+    match update {
+        UpdateOneof::Account(_) => "account",
+        UpdateOneof::Slot(_) => "slot",
+        UpdateOneof::Transaction(_) => "transaction",
+        UpdateOneof::TransactionStatus(_) => "transaction_status",
+        UpdateOneof::Block(_) => "block",
+        UpdateOneof::BlockMeta(_) => "block_meta",
+        UpdateOneof::Entry(_) => "entry",
+        UpdateOneof::Ping(_) => "ping",
+        UpdateOneof::Pong(_) => "pong",
+    }
+}
+
+/// Totals of one filter name
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FilterStats {
+    pub messages: u64,
+    /// Encoded size of the messages
+    pub bytes: u64,
+    pub first_slot: Option<u64>,
+    pub last_slot: Option<u64>,
+    /// First message, `None` if the filter did not match anything yet
+    #[serde(skip)]
+    first_seen: Option<Instant>,
+}
+
+impl FilterStats {
+    fn add(&mut self, bytes: u64, slot: Option<u64>) {
+        self.messages += 1;
+        self.bytes += bytes;
+        if let Some(slot) = slot {
+            self.first_slot = Some(self.first_slot.map_or(slot, |first| first.min(slot)));
+            self.last_slot = self.last_slot.max(Some(slot));
+        }
+        self.first_seen.get_or_insert_with(Instant::now);
+    }
+}
+
+/// Filter totals with messages per second since the previous summary, or since the first
+/// message of the filter for the first summary
+#[derive(Debug, Serialize)]
+pub struct FilterSummaryRow {
+    filter: String,
+    #[serde(flatten)]
+    stats: FilterStats,
+    rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct FilterSummary(Vec<FilterSummaryRow>);
+
+impl FilterSummary {
+    /// Table in text mode, nothing if no filters were subscribed
+    pub fn print(&self, output: OutputFormat) {
+        if self.0.is_empty() {
+            return;
+        }
+        match output {
+            OutputFormat::Text | OutputFormat::Csv => info!("filters:\n{self}"),
+            OutputFormat::Json => output.print_event(
+                "filters",
+                &serde_json::to_value(self).expect("failed to serialize"),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for FilterSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|row| row.filter.len())
+            .chain([6])
+            .max()
+            .unwrap_or_default();
+        write!(
+            f,
+            "{:<width$} {:>12} {:>14} {:>12} {:>12} {:>10}",
+            "filter", "messages", "bytes", "first slot", "last slot", "rate/s"
+        )?;
+        let slot = |slot: Option<u64>| slot.map_or_else(|| "-".to_owned(), |slot| slot.to_string());
+        for row in self.0.iter() {
+            write!(
+                f,
+                "\n{:<width$} {:>12} {:>14} {:>12} {:>12} {:>10.1}",
+                row.filter,
+                row.stats.messages,
+                row.stats.bytes,
+                slot(row.stats.first_slot),
+                slot(row.stats.last_slot),
+                row.rate
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counters shared between reconnects
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    messages: AtomicU64,
+    last_slot: AtomicU64,
+    /// Totals per filter name from `SubscribeUpdate::filters`, and subscribed filters which
+    /// did not match anything yet
+    filters: Mutex<BTreeMap<String, FilterStats>>,
+    /// Time and messages per filter of the previous summary
+    summarized: Mutex<Option<(Instant, BTreeMap<String, u64>)>>,
+    /// Filters of the last sent request by name
+    subscription: Mutex<BTreeMap<String, SubscribedFilter>>,
+}
+
+/// Filter of the last sent request
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscribedFilter {
+    /// Field of the request, e.g. `accounts`
+    pub kind: &'static str,
+    /// The filter as sent, in `Debug` format
+    pub filter: String,
+}
+
+/// Filters of the request by name
+pub fn describe_filters(request: &SubscribeRequest) -> BTreeMap<String, SubscribedFilter> {
+    fn describe<'a, T: fmt::Debug>(
+        kind: &'static str,
+        filters: &'a HashMap<String, T>,
+    ) -> impl Iterator<Item = (String, SubscribedFilter)> + 'a {
+        filters.iter().map(move |(name, filter)| {
+            let filter = format!("{filter:?}");
+            (name.clone(), SubscribedFilter { kind, filter })
+        })
+    }
+    describe("accounts", &request.accounts)
+        .chain(describe("slots", &request.slots))
+        .chain(describe("transactions", &request.transactions))
+        .chain(describe(
+            "transactions_status",
+            &request.transactions_status,
+        ))
+        .chain(describe("blocks", &request.blocks))
+        .chain(describe("blocks_meta", &request.blocks_meta))
+        .chain(describe("entry", &request.entry))
+        .collect()
+}
+
+impl StreamStats {
+    pub fn observe(&self, msg: &SubscribeUpdate) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        if let Some(slot) = update_slot(msg) {
+            self.last_slot.fetch_max(slot, Ordering::Relaxed);
+        }
+        if !msg.filters.is_empty() {
+            let slot = update_slot(msg);
+            let bytes = msg.encoded_len() as u64;
+            let mut filters = self.filters.lock().expect("poisoned");
+            for filter in msg.filters.iter() {
+                // Names are cloned only for the first message of a filter
+                match filters.get_mut(filter) {
+                    Some(stats) => stats.add(bytes, slot),
+                    None => {
+                        let mut stats = FilterStats::default();
+                        stats.add(bytes, slot);
+                        filters.insert(filter.clone(), stats);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Show filters of the request in summaries even if they never match, and keep them for
+    /// `GET /filters` of the admin API
+    pub fn subscribed(&self, request: &SubscribeRequest) {
+        *self.subscription.lock().expect("poisoned") = describe_filters(request);
+
+        let names = request
+            .accounts
+            .keys()
+            .chain(request.slots.keys())
+            .chain(request.transactions.keys())
+            .chain(request.transactions_status.keys())
+            .chain(request.blocks.keys())
+            .chain(request.blocks_meta.keys())
+            .chain(request.entry.keys());
+        let mut filters = self.filters.lock().expect("poisoned");
+        for name in names {
+            filters.entry(name.clone()).or_default();
+        }
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    /// Highest received slot, `None` if no updates with slot were received
+    pub fn last_slot(&self) -> Option<u64> {
+        match self.last_slot.load(Ordering::Relaxed) {
+            0 => None,
+            slot => Some(slot),
+        }
+    }
+
+    /// Filters of the last sent request by name
+    pub fn subscription(&self) -> BTreeMap<String, SubscribedFilter> {
+        self.subscription.lock().expect("poisoned").clone()
+    }
+
+    /// Messages per filter name
+    pub fn filters(&self) -> BTreeMap<String, u64> {
+        self.filters
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .map(|(filter, stats)| (filter.clone(), stats.messages))
+            .collect()
+    }
+
+    /// Totals per filter, rates are since the previous call
+    pub fn filter_summary(&self) -> FilterSummary {
+        let filters = self.filters.lock().expect("poisoned").clone();
+        let now = Instant::now();
+        let previous = self.summarized.lock().expect("poisoned").replace((
+            now,
+            filters
+                .iter()
+                .map(|(filter, stats)| (filter.clone(), stats.messages))
+                .collect(),
+        ));
+        let rows = filters
+            .into_iter()
+            .map(|(filter, stats)| {
+                let (since, messages) = match previous.as_ref() {
+                    Some((at, counts)) => (
+                        stats.first_seen.map(|first| first.max(*at)),
+                        stats.messages - counts.get(&filter).copied().unwrap_or_default(),
+                    ),
+                    None => (stats.first_seen, stats.messages),
+                };
+                let elapsed = since.map_or(0.0, |since| (now - since).as_secs_f64());
+                FilterSummaryRow {
+                    filter,
+                    rate: if elapsed > 0.0 {
+                        messages as f64 / elapsed
+                    } else {
+                        0.0
+                    },
+                    stats,
+                }
+            })
+            .collect();
+        FilterSummary(rows)
+    }
+}
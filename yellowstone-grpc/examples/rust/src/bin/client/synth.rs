@@ -0,0 +1,507 @@
+//! Deterministic synthetic stream from a seed, `ACTION=Generate`.
+//!
+//! Writes a capture file (see `capture`) with `SYNTH_SLOTS` slots of Orca Whirlpool swaps
+//! between `SYNTH_WALLETS` wallets and `SYNTH_POOLS` pools, `SYNTH_SWAPS_PER_SLOT` swaps per
+//! slot. Every swap is a transaction with the real instruction layout (swap instruction of
+//! the Whirlpool program, two SPL token transfers as inner instructions), logs, fees and
+//! token balances, followed by the updates of the four token accounts it changed, which are
+//! valid 165 byte SPL token accounts with balances consistent across slots. Slots advance
+//! through processed, confirmed and finalized and end with block meta. Pubkeys, signatures
+//! and amounts come from `SYNTH_SEED`, so the same seed produces the same file with the same
+//! client version, with no real wallets in it. `ACTION=Replay` feeds the file through
+//! decoding and sinks for benchmarks and tests.
+
+use {
+    crate::{capture::CaptureWriter, stats::update_kind},
+    rand::{rngs::StdRng, Rng, SeedableRng},
+    serde_json::{json, Value},
+    solana_sdk::{pubkey, pubkey::Pubkey},
+    std::{collections::HashMap, env},
+    yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, BlockHeight, CommitmentLevel, CompiledInstruction,
+        InnerInstruction, InnerInstructions, Message, MessageHeader, SubscribeUpdate,
+        SubscribeUpdateAccount, SubscribeUpdateAccountInfo, SubscribeUpdateBlockMeta,
+        SubscribeUpdateSlot, SubscribeUpdateTransaction, SubscribeUpdateTransactionInfo,
+        TokenBalance, Transaction, TransactionStatusMeta, UiTokenAmount, UnixTimestamp,
+    },
+};
+
+const TOKEN_PROGRAM: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const WHIRLPOOL_PROGRAM: Pubkey = pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+/// Anchor discriminator of `swap`, first 8 bytes of `sha256("global:swap")`
+const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200];
+/// SPL token `Transfer` instruction
+const TOKEN_TRANSFER: u8 = 3;
+const TOKEN_ACCOUNT_LEN: usize = 165;
+const TOKEN_ACCOUNT_LAMPORTS: u64 = 2_039_280;
+const FEE: u64 = 5_000;
+/// Fee of the pools, in hundredths of a basis point like Whirlpool
+const POOL_FEE_RATE: u64 = 3_000;
+const FIRST_SLOT: u64 = 250_000_000;
+/// Timestamps of the capture, slot `FIRST_SLOT` starts at 2024-02-20 00:00:00 UTC
+const FIRST_TIMESTAMP_US: u64 = 1_708_387_200_000_000;
+const SLOT_US: u64 = 400_000;
+/// Slots between processed and finalized
+const FINALIZATION_SLOTS: u64 = 32;
+const FILTER: &str = "synth";
+
+#[derive(Debug, Clone, Copy)]
+pub struct SynthConfig {
+    seed: u64,
+    slots: u64,
+    swaps_per_slot: u64,
+    wallets: usize,
+    pools: usize,
+}
+
+impl SynthConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse = |key: &str, default: u64| match env::var(key) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid {key}")),
+            Err(_) => Ok(default),
+        };
+        let config = Self {
+            seed: parse("SYNTH_SEED", 0)?,
+            slots: parse("SYNTH_SLOTS", 100)?,
+            swaps_per_slot: parse("SYNTH_SWAPS_PER_SLOT", 20)?,
+            wallets: parse("SYNTH_WALLETS", 1_000)? as usize,
+            pools: parse("SYNTH_POOLS", 10)? as usize,
+        };
+        anyhow::ensure!(
+            config.slots > 0 && config.wallets > 0 && config.pools > 0,
+            "SYNTH_SLOTS, SYNTH_WALLETS and SYNTH_POOLS should be above 0"
+        );
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mint {
+    address: Pubkey,
+    decimals: u32,
+}
+
+#[derive(Debug)]
+struct Pool {
+    address: Pubkey,
+    mints: [Mint; 2],
+    vaults: [Pubkey; 2],
+    tick_arrays: [Pubkey; 3],
+    oracle: Pubkey,
+    /// Units of token B per unit of token A
+    price: f64,
+}
+
+#[derive(Debug)]
+struct TokenAccount {
+    address: Pubkey,
+    mint: Mint,
+    owner: Pubkey,
+    amount: u64,
+}
+
+impl TokenAccount {
+    /// SPL token account layout, initialized without delegate and close authority
+    fn data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(TOKEN_ACCOUNT_LEN);
+        data.extend_from_slice(self.mint.address.as_ref());
+        data.extend_from_slice(self.owner.as_ref());
+        data.extend_from_slice(&self.amount.to_le_bytes());
+        // delegate: COption<Pubkey>
+        data.extend_from_slice(&[0; 36]);
+        // state: Initialized
+        data.push(1);
+        // is_native: COption<u64>
+        data.extend_from_slice(&[0; 12]);
+        // delegated_amount
+        data.extend_from_slice(&[0; 8]);
+        // close_authority: COption<Pubkey>
+        data.extend_from_slice(&[0; 36]);
+        data
+    }
+
+    fn balance(&self, account_index: u32) -> TokenBalance {
+        let ui_amount = self.amount as f64 / 10f64.powi(self.mint.decimals as i32);
+        TokenBalance {
+            account_index,
+            mint: self.mint.address.to_string(),
+            ui_token_amount: Some(UiTokenAmount {
+                ui_amount,
+                decimals: self.mint.decimals,
+                amount: self.amount.to_string(),
+                ui_amount_string: ui_amount.to_string(),
+            }),
+            owner: self.owner.to_string(),
+            program_id: TOKEN_PROGRAM.to_string(),
+        }
+    }
+}
+
+struct Generator {
+    rng: StdRng,
+    wallets: Vec<(Pubkey, u64)>,
+    pools: Vec<Pool>,
+    /// Token accounts by address, wallets get one per mint on their first swap of it
+    accounts: HashMap<Pubkey, TokenAccount>,
+    wallet_accounts: HashMap<(usize, Pubkey), Pubkey>,
+    write_version: u64,
+}
+
+impl Generator {
+    fn new(config: SynthConfig) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let wallets = (0..config.wallets)
+            .map(|_| {
+                let lamports = rng.gen_range(100_000_000..100_000_000_000);
+                (Pubkey::new_from_array(rng.gen()), lamports)
+            })
+            .collect();
+        let mints = (0..config.pools + 1)
+            .map(|_| Mint {
+                address: Pubkey::new_from_array(rng.gen()),
+                decimals: [6, 9][rng.gen_range(0..2)],
+            })
+            .collect::<Vec<_>>();
+        let mut accounts = HashMap::new();
+        let pools = (0..config.pools)
+            .map(|index| {
+                let address = Pubkey::new_from_array(rng.gen());
+                // Pairs overlap, so wallets hold tokens of several pools
+                let pair = [mints[index], mints[index + 1]];
+                let vaults = pair.map(|mint| {
+                    let vault = TokenAccount {
+                        address: Pubkey::new_from_array(rng.gen()),
+                        mint,
+                        owner: address,
+                        amount: rng.gen_range(1_000_000..10_000_000) * 10u64.pow(mint.decimals),
+                    };
+                    let vault_address = vault.address;
+                    accounts.insert(vault_address, vault);
+                    vault_address
+                });
+                let price = accounts[&vaults[1]].amount as f64 / accounts[&vaults[0]].amount as f64;
+                Pool {
+                    address,
+                    mints: pair,
+                    vaults,
+                    tick_arrays: [(); 3].map(|()| Pubkey::new_from_array(rng.gen())),
+                    oracle: Pubkey::new_from_array(rng.gen()),
+                    price,
+                }
+            })
+            .collect();
+        Self {
+            rng,
+            wallets,
+            pools,
+            accounts,
+            wallet_accounts: HashMap::new(),
+            write_version: 0,
+        }
+    }
+
+    /// Token account of the wallet for the mint, created with a random balance
+    fn wallet_account(&mut self, wallet: usize, mint: Mint) -> Pubkey {
+        if let Some(address) = self.wallet_accounts.get(&(wallet, mint.address)) {
+            return *address;
+        }
+        let account = TokenAccount {
+            address: Pubkey::new_from_array(self.rng.gen()),
+            mint,
+            owner: self.wallets[wallet].0,
+            amount: self.rng.gen_range(1..10_000) * 10u64.pow(mint.decimals),
+        };
+        let address = account.address;
+        self.accounts.insert(address, account);
+        self.wallet_accounts.insert((wallet, mint.address), address);
+        address
+    }
+
+    fn account_update(&mut self, slot: u64, address: &Pubkey, signature: &[u8]) -> SubscribeUpdate {
+        self.write_version += 1;
+        let account = &self.accounts[address];
+        SubscribeUpdate {
+            filters: vec![FILTER.to_owned()],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: address.to_bytes().to_vec(),
+                    lamports: TOKEN_ACCOUNT_LAMPORTS,
+                    owner: TOKEN_PROGRAM.to_bytes().to_vec(),
+                    executable: false,
+                    rent_epoch: u64::MAX,
+                    data: account.data(),
+                    write_version: self.write_version,
+                    txn_signature: Some(signature.to_vec()),
+                }),
+                slot,
+                is_startup: false,
+            })),
+        }
+    }
+
+    /// Swap of a random wallet in a random pool, followed by updates of the changed token
+    /// accounts
+    fn swap(&mut self, slot: u64, index: u64, blockhash: &[u8]) -> Vec<SubscribeUpdate> {
+        let wallet = self.rng.gen_range(0..self.wallets.len());
+        let pool = self.rng.gen_range(0..self.pools.len());
+        let a_to_b = self.rng.gen_bool(0.5);
+        let (mints, vaults, price) = {
+            let pool = &self.pools[pool];
+            (pool.mints, pool.vaults, pool.price)
+        };
+        let owner_accounts = mints.map(|mint| self.wallet_account(wallet, mint));
+        let (input, output) = if a_to_b { (0, 1) } else { (1, 0) };
+
+        // Up to a tenth of the balance, so wallets don't run dry
+        let balance = self.accounts[&owner_accounts[input]].amount;
+        let amount_in = self
+            .rng
+            .gen_range(0..=balance / 10)
+            .clamp(1, balance.max(1));
+        let rate = if a_to_b { price } else { 1.0 / price };
+        let fee = 1.0 - POOL_FEE_RATE as f64 / 1_000_000.0;
+        let amount_out =
+            ((amount_in as f64 * rate * fee) as u64).min(self.accounts[&vaults[output]].amount / 2);
+        let threshold = amount_out - amount_out / 100;
+
+        let keys = [
+            self.wallets[wallet].0,
+            self.pools[pool].address,
+            owner_accounts[0],
+            vaults[0],
+            owner_accounts[1],
+            vaults[1],
+            self.pools[pool].tick_arrays[0],
+            self.pools[pool].tick_arrays[1],
+            self.pools[pool].tick_arrays[2],
+            self.pools[pool].oracle,
+            TOKEN_PROGRAM,
+            WHIRLPOOL_PROGRAM,
+        ];
+        // Indexes in `keys` of the wallet, the pool and the owner and vault accounts
+        let (wallet_index, pool_index) = (0u8, 1u8);
+        let owner_index = [2u8, 4];
+        let vault_index = [3u8, 5];
+        let (token_index, program_index) = (10u32, 11u32);
+        let balances = [2u32, 3, 4, 5];
+
+        let pre_token_balances = balances
+            .iter()
+            .map(|index| self.accounts[&keys[*index as usize]].balance(*index))
+            .collect::<Vec<_>>();
+        let pre_balances = self.balances(&keys, wallet);
+        let transfer = |amount: u64| {
+            let mut data = vec![TOKEN_TRANSFER];
+            data.extend_from_slice(&amount.to_le_bytes());
+            data
+        };
+        let inner = vec![
+            InnerInstruction {
+                program_id_index: token_index,
+                accounts: vec![owner_index[input], vault_index[input], wallet_index],
+                data: transfer(amount_in),
+                stack_height: Some(2),
+            },
+            InnerInstruction {
+                program_id_index: token_index,
+                accounts: vec![vault_index[output], owner_index[output], pool_index],
+                data: transfer(amount_out),
+                stack_height: Some(2),
+            },
+        ];
+        for (account, delta) in [
+            (owner_accounts[input], -(amount_in as i128)),
+            (vaults[input], amount_in as i128),
+            (vaults[output], -(amount_out as i128)),
+            (owner_accounts[output], amount_out as i128),
+        ] {
+            let account = self.accounts.get_mut(&account).expect("known account");
+            account.amount = (account.amount as i128 + delta) as u64;
+        }
+        self.wallets[wallet].1 = self.wallets[wallet].1.saturating_sub(FEE);
+        let post_token_balances = balances
+            .iter()
+            .map(|index| self.accounts[&keys[*index as usize]].balance(*index))
+            .collect::<Vec<_>>();
+        let post_balances = self.balances(&keys, wallet);
+
+        // amount, other_amount_threshold, sqrt_price_limit, amount_specified_is_input, a_to_b
+        let mut data = SWAP_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&threshold.to_le_bytes());
+        data.extend_from_slice(&0u128.to_le_bytes());
+        data.push(1);
+        data.push(u8::from(a_to_b));
+        let units = self.rng.gen_range(30_000..60_000);
+        let log_messages = vec![
+            format!("Program {WHIRLPOOL_PROGRAM} invoke [1]"),
+            "Program log: Instruction: Swap".to_owned(),
+            format!("Program {TOKEN_PROGRAM} invoke [2]"),
+            "Program log: Instruction: Transfer".to_owned(),
+            format!(
+                "Program {TOKEN_PROGRAM} consumed 4645 of {} compute units",
+                200_000 - units + 9_290
+            ),
+            format!("Program {TOKEN_PROGRAM} success"),
+            format!("Program {TOKEN_PROGRAM} invoke [2]"),
+            "Program log: Instruction: Transfer".to_owned(),
+            format!(
+                "Program {TOKEN_PROGRAM} consumed 4645 of {} compute units",
+                200_000 - units + 4_645
+            ),
+            format!("Program {TOKEN_PROGRAM} success"),
+            format!("Program {WHIRLPOOL_PROGRAM} consumed {units} of 200000 compute units"),
+            format!("Program {WHIRLPOOL_PROGRAM} success"),
+        ];
+
+        let mut signature = vec![0u8; 64];
+        self.rng.fill(&mut signature[..]);
+        let tx = SubscribeUpdate {
+            filters: vec![FILTER.to_owned()],
+            update_oneof: Some(UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                transaction: Some(SubscribeUpdateTransactionInfo {
+                    signature: signature.clone(),
+                    is_vote: false,
+                    transaction: Some(Transaction {
+                        signatures: vec![signature.clone()],
+                        message: Some(Message {
+                            // Oracle and the two programs are read-only
+                            header: Some(MessageHeader {
+                                num_required_signatures: 1,
+                                num_readonly_signed_accounts: 0,
+                                num_readonly_unsigned_accounts: 3,
+                            }),
+                            account_keys: keys.iter().map(|key| key.to_bytes().to_vec()).collect(),
+                            recent_blockhash: blockhash.to_vec(),
+                            instructions: vec![CompiledInstruction {
+                                program_id_index: program_index,
+                                // token_program, token_authority, whirlpool,
+                                // token_owner_account_a, token_vault_a, token_owner_account_b,
+                                // token_vault_b, tick_array_0..2, oracle
+                                accounts: vec![10, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+                                data,
+                            }],
+                            versioned: false,
+                            address_table_lookups: vec![],
+                        }),
+                    }),
+                    meta: Some(TransactionStatusMeta {
+                        err: None,
+                        fee: FEE,
+                        pre_balances,
+                        post_balances,
+                        inner_instructions: vec![InnerInstructions {
+                            index: 0,
+                            instructions: inner,
+                        }],
+                        log_messages,
+                        pre_token_balances,
+                        post_token_balances,
+                        compute_units_consumed: Some(units),
+                        ..Default::default()
+                    }),
+                    index,
+                }),
+                slot,
+            })),
+        };
+
+        let mut updates = vec![tx];
+        for account in [
+            owner_accounts[input],
+            vaults[input],
+            vaults[output],
+            owner_accounts[output],
+        ] {
+            updates.push(self.account_update(slot, &account, &signature));
+        }
+        updates
+    }
+
+    /// Lamports of the transaction accounts
+    fn balances(&self, keys: &[Pubkey], wallet: usize) -> Vec<u64> {
+        keys.iter()
+            .enumerate()
+            .map(|(index, key)| match index {
+                0 => self.wallets[wallet].1,
+                _ if self.accounts.contains_key(key) => TOKEN_ACCOUNT_LAMPORTS,
+                _ => 1_141_440,
+            })
+            .collect()
+    }
+}
+
+fn slot_update(slot: u64, status: CommitmentLevel) -> SubscribeUpdate {
+    SubscribeUpdate {
+        filters: vec![FILTER.to_owned()],
+        update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+            slot,
+            parent: slot.checked_sub(1),
+            status: status as i32,
+        })),
+    }
+}
+
+/// Write the generated stream to the capture file at `path`
+pub fn generate(config: SynthConfig, path: &str) -> anyhow::Result<Value> {
+    let mut writer = CaptureWriter::open(path)?;
+    let mut generator = Generator::new(config);
+    let mut counts = HashMap::<&str, u64>::new();
+    let mut parent_blockhash = generator.rng.gen::<[u8; 32]>();
+    for slot in FIRST_SLOT..FIRST_SLOT + config.slots {
+        let mut timestamp = FIRST_TIMESTAMP_US + (slot - FIRST_SLOT) * SLOT_US;
+        let blockhash = generator.rng.gen::<[u8; 32]>();
+        let mut updates = vec![slot_update(slot, CommitmentLevel::Processed)];
+        for index in 0..config.swaps_per_slot {
+            updates.extend(generator.swap(slot, index, &parent_blockhash));
+        }
+        updates.push(SubscribeUpdate {
+            filters: vec![FILTER.to_owned()],
+            update_oneof: Some(UpdateOneof::BlockMeta(SubscribeUpdateBlockMeta {
+                slot,
+                blockhash: bs58::encode(blockhash).into_string(),
+                block_time: Some(UnixTimestamp {
+                    timestamp: (timestamp / 1_000_000) as i64,
+                }),
+                block_height: Some(BlockHeight {
+                    block_height: slot - 18_000_000,
+                }),
+                parent_slot: slot - 1,
+                parent_blockhash: bs58::encode(parent_blockhash).into_string(),
+                executed_transaction_count: config.swaps_per_slot,
+                ..Default::default()
+            })),
+        });
+        if slot > FIRST_SLOT {
+            updates.push(slot_update(slot - 1, CommitmentLevel::Confirmed));
+        }
+        if slot >= FIRST_SLOT + FINALIZATION_SLOTS {
+            updates.push(slot_update(
+                slot - FINALIZATION_SLOTS,
+                CommitmentLevel::Finalized,
+            ));
+        }
+
+        // Spread the updates of the slot over its duration
+        let step = SLOT_US / updates.len() as u64;
+        for msg in updates {
+            if let Some(update) = msg.update_oneof.as_ref() {
+                *counts.entry(update_kind(update)).or_default() += 1;
+            }
+            writer.write_at(&msg, timestamp)?;
+            timestamp += step;
+        }
+        parent_blockhash = blockhash;
+    }
+    writer.flush()?;
+
+    Ok(json!({
+        "path": path,
+        "seed": config.seed,
+        "first_slot": FIRST_SLOT,
+        "last_slot": FIRST_SLOT + config.slots - 1,
+        "updates": counts,
+    }))
+}
@@ -0,0 +1,106 @@
+//! Static key-value tags of filters.
+//!
+//! `FILTER_TAGS_<name>=strategy=alpha1,env=prod` tags every update matched by the filter
+//! `<name>`, tags from the `tags` section of `FILTERS_PATH` are added on top and replaced on
+//! reload. An update matched by several filters gets the tags of all of them, different
+//! values of the same key are joined with `|`. Updates touching pubkeys of `WATCHLIST_PATH`
+//! get their labels as the tag `label`, see `watchlist`.
+
+use {
+    crate::watchlist::Watchlist,
+    std::{
+        collections::BTreeMap,
+        env,
+        sync::{Arc, RwLock},
+    },
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+pub type Tags = BTreeMap<String, String>;
+
+#[derive(Debug, Default)]
+pub struct FilterTags {
+    env: BTreeMap<String, Tags>,
+    file: RwLock<BTreeMap<String, Tags>>,
+    watchlist: Option<Arc<Watchlist>>,
+}
+
+impl FilterTags {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut env_tags = BTreeMap::new();
+        for (key, value) in env::vars() {
+            let Some(name) = key.strip_prefix("FILTER_TAGS_") else {
+                continue;
+            };
+            let tags = value
+                .split(',')
+                .filter(|pair| !pair.trim().is_empty())
+                .map(|pair| {
+                    pair.split_once('=')
+                        .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+                        .ok_or_else(|| anyhow::anyhow!("invalid {key}, expected `key=value,...`"))
+                })
+                .collect::<anyhow::Result<Tags>>()?;
+            env_tags.insert(name.to_owned(), tags);
+        }
+        Ok(Self {
+            env: env_tags,
+            file: RwLock::default(),
+            watchlist: Watchlist::from_env()?.map(Arc::new),
+        })
+    }
+
+    pub fn watchlist(&self) -> Option<&Arc<Watchlist>> {
+        self.watchlist.as_ref()
+    }
+
+    /// Replace tags from the filters file
+    pub fn set_file_tags(&self, tags: BTreeMap<String, Tags>) {
+        *self.file.write().expect("poisoned") = tags;
+    }
+
+    /// Tags of one filter
+    pub fn filter(&self, name: &str) -> Tags {
+        let mut tags = self.env.get(name).cloned().unwrap_or_default();
+        if let Some(file_tags) = self.file.read().expect("poisoned").get(name) {
+            tags.extend(file_tags.clone());
+        }
+        tags
+    }
+
+    /// Tags of all filters which matched the update, and labels of watched pubkeys
+    pub fn update(&self, msg: &SubscribeUpdate) -> Tags {
+        let mut tags = Tags::new();
+        for name in msg.filters.iter() {
+            for (key, value) in self.filter(name) {
+                add_tag(&mut tags, key, value);
+            }
+        }
+        if let Some(watchlist) = self.watchlist.as_ref() {
+            for watched in watchlist.touched(msg) {
+                add_tag(&mut tags, "label".to_owned(), watched.label.clone());
+            }
+        }
+        tags
+    }
+}
+
+/// Different values of the same key are joined with `|`
+fn add_tag(tags: &mut Tags, key: String, value: String) {
+    tags.entry(key)
+        .and_modify(|current| {
+            if current.split('|').all(|existing| existing != value) {
+                current.push('|');
+                current.push_str(&value);
+            }
+        })
+        .or_insert(value);
+}
+
+/// `key=value;key=value`, for single text fields
+pub fn format_tags(tags: &Tags) -> String {
+    tags.iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(";")
+}
@@ -0,0 +1,162 @@
+//! Updates printed with a template file, `OUTPUT_TEMPLATE`, with the `template` feature.
+//!
+//! Custom lines, chat messages or log formats without code changes: the template is rendered
+//! with MiniJinja for every update and printed to stdout instead of the update log line. It is
+//! parsed at startup so a mistake fails before subscribing:
+//!
+//! ```text
+//! {% if account %}{{ account.pubkey }} owned by {{ account.owner }}: {{ account.lamports }} lamports{% endif %}
+//! {% if transaction %}{{ transaction.signature }}{% if not transaction.transaction.meta.err %} ok{% endif %}{% endif %}
+//! slot {{ slot }}, filters{% for filter in filters %} {{ loop.index0 }}={{ filter }}{% endfor %}{% if tags.env %}, env {{ tags.env }}{% endif %}
+//! ```
+//!
+//! Variables are the JSON encoding of the update (see `json`, `JSON_*` options apply) with
+//! `tags` of its filters. Undefined variables render nothing, output is not escaped and
+//! `|tojson` prints a value as JSON. One trailing newline of the file is dropped and every
+//! update ends with a newline.
+
+use {
+    crate::{
+        json::{update_json, JsonOptions},
+        tags::Tags,
+    },
+    minijinja::Environment,
+    serde_json::json,
+    std::{env, fs},
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+/// Name of the template in the environment, without an extension which would enable escaping
+const NAME: &str = "update";
+
+#[derive(Debug)]
+pub struct OutputTemplate {
+    env: Environment<'static>,
+    json: JsonOptions,
+}
+
+impl OutputTemplate {
+    /// Returns `None` if `OUTPUT_TEMPLATE` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(path) = env::var("OUTPUT_TEMPLATE") else {
+            return Ok(None);
+        };
+        let source = fs::read_to_string(&path)
+            .map_err(|error| anyhow::anyhow!("invalid OUTPUT_TEMPLATE {path}: {error}"))?;
+        Ok(Some(Self {
+            env: environment(source)
+                .map_err(|error| anyhow::anyhow!("invalid OUTPUT_TEMPLATE {path}: {error:#}"))?,
+            json: JsonOptions::from_env()?,
+        }))
+    }
+
+    /// Text of the update, `None` for pings, pongs and updates of unknown types
+    pub fn render(&self, msg: &SubscribeUpdate, tags: &Tags) -> anyhow::Result<Option<String>> {
+        let Some(mut value) = update_json(msg, &self.json, None) else {
+            return Ok(None);
+        };
+        value["tags"] = json!(tags);
+        let text = self
+            .env
+            .get_template(NAME)?
+            .render(value)
+            .map_err(|error| anyhow::anyhow!("failed to render OUTPUT_TEMPLATE: {error:#}"))?;
+        Ok(Some(text))
+    }
+}
+
+fn environment(source: String) -> Result<Environment<'static>, minijinja::Error> {
+    let mut env = Environment::new();
+    env.add_template_owned(NAME, source)?;
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        yellowstone_grpc_proto::prelude::{
+            subscribe_update::UpdateOneof, SubscribeUpdateAccount, SubscribeUpdateAccountInfo,
+            SubscribeUpdatePing,
+        },
+    };
+
+    fn template(source: &str) -> OutputTemplate {
+        OutputTemplate {
+            env: environment(source.to_owned()).unwrap(),
+            json: JsonOptions::default(),
+        }
+    }
+
+    fn account() -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["usdc".to_owned()],
+            update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                account: Some(SubscribeUpdateAccountInfo {
+                    pubkey: vec![1; 32],
+                    lamports: 42,
+                    ..Default::default()
+                }),
+                slot: 7,
+                is_startup: false,
+            })),
+        }
+    }
+
+    #[test]
+    fn renders_update_and_tags() {
+        let template = template(
+            "{{ slot }} {{ account.lamports }}{% for filter in filters %} {{ filter }}{% endfor %} \
+            {{ tags.env }}\n",
+        );
+        let tags = Tags::from([("env".to_owned(), "prod".to_owned())]);
+        assert_eq!(
+            template.render(&account(), &tags).unwrap().as_deref(),
+            Some("7 42 usdc prod")
+        );
+    }
+
+    #[test]
+    fn undefined_is_empty_and_not_escaped() {
+        let undefined = template("{{ transaction }}|{{ tags.missing }}|{{ \"<&>\" }}");
+        assert_eq!(
+            undefined
+                .render(&account(), &Tags::new())
+                .unwrap()
+                .as_deref(),
+            Some("||<&>")
+        );
+        // Attributes of an undefined variable are an error, guarded with `if`
+        let attribute = template("{{ transaction.signature }}");
+        let error = attribute.render(&account(), &Tags::new()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("failed to render OUTPUT_TEMPLATE"));
+    }
+
+    #[test]
+    fn tojson_prints_values() {
+        let template = template("{{ filters|tojson }}");
+        assert_eq!(
+            template
+                .render(&account(), &Tags::new())
+                .unwrap()
+                .as_deref(),
+            Some(r#"["usdc"]"#)
+        );
+    }
+
+    #[test]
+    fn pings_are_not_rendered() {
+        let ping = SubscribeUpdate {
+            filters: vec![],
+            update_oneof: Some(UpdateOneof::Ping(SubscribeUpdatePing {})),
+        };
+        assert_eq!(template("x").render(&ping, &Tags::new()).unwrap(), None);
+    }
+
+    #[test]
+    fn syntax_errors_fail_on_parse() {
+        assert!(environment("{% if account %}".to_owned()).is_err());
+    }
+}
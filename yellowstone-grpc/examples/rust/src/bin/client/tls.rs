@@ -0,0 +1,98 @@
+//! TLS without server certificate verification, for self-signed test deployments.
+//!
+//! tonic does not allow to disable verification, so with `TLS_INSECURE` the channel is
+//! created with a connector doing the TLS handshake itself. tonic refuses `https://` URLs
+//! without its own TLS config, the connector gets the URL with `http://` and explicit port.
+
+use {
+    rustls::{
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, PrivateKey, ServerName,
+    },
+    rustls_pemfile::Item,
+    std::{fs::File, io::BufReader, sync::Arc, time::SystemTime},
+    tokio::net::TcpStream,
+    tokio_rustls::TlsConnector,
+    tower::service_fn,
+    yellowstone_grpc_proto::tonic::transport::{Channel, Endpoint, Uri},
+};
+
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn read_certificates(path: &str) -> anyhow::Result<Vec<Certificate>> {
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    anyhow::ensure!(!certificates.is_empty(), "no certificates found in {path}");
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &str) -> anyhow::Result<PrivateKey> {
+    rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("no private key found in {path}"))
+}
+
+/// `https://host` as `http://host:443`, the scheme is only used by tonic to choose TLS
+pub fn plain_url(url: &str) -> anyhow::Result<String> {
+    let uri: Uri = url.parse()?;
+    let authority = uri
+        .authority()
+        .ok_or_else(|| anyhow::anyhow!("endpoint without host: {url}"))?;
+    let port = authority.port_u16().unwrap_or(443);
+    let path = uri.path_and_query().map_or("", |path| path.as_str());
+    Ok(format!("http://{}:{port}{path}", authority.host()))
+}
+
+/// Connect `endpoint` over TLS accepting any server certificate, `identity` is the client
+/// certificate and key for mutual TLS
+pub async fn connect_insecure(
+    endpoint: &Endpoint,
+    domain_name: &str,
+    identity: Option<(&str, &str)>,
+) -> anyhow::Result<Channel> {
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(NoVerifier));
+    let mut config = match identity {
+        Some((certificate, key)) => {
+            builder.with_client_auth_cert(read_certificates(certificate)?, read_key(key)?)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    let connector = TlsConnector::from(Arc::new(config));
+    let server_name = ServerName::try_from(domain_name)
+        .map_err(|_| anyhow::anyhow!("invalid TLS domain name: {domain_name}"))?;
+    let channel = endpoint
+        .connect_with_connector(service_fn(move |uri: Uri| {
+            let connector = connector.clone();
+            let server_name = server_name.clone();
+            async move {
+                let host = uri.host().unwrap_or_default();
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let stream = TcpStream::connect((host, uri.port_u16().unwrap_or(443))).await?;
+                stream.set_nodelay(true)?;
+                connector.connect(server_name, stream).await
+            }
+        }))
+        .await?;
+    Ok(channel)
+}
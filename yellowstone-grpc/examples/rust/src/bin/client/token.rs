@@ -0,0 +1,172 @@
+//! Tokens which expire, `X_TOKEN_CMD`, `X_TOKEN_FILE` and `X_TOKEN_REFRESH_SECS`.
+//!
+//! Instead of a fixed `X_TOKEN` the token can be the stdout of a command (`sh -c`) or the
+//! content of a file, surrounding whitespace is trimmed. It is loaded again on every
+//! connect, so a reconnect always uses the current token. With `X_TOKEN_REFRESH_SECS` it is
+//! also reloaded at this interval while connected and the `x-token` header of every new
+//! call on the connection, including stream recoveries and health checks, carries it. A
+//! stream which is already open keeps the token it was opened with. A failed refresh is
+//! logged and the previous token is kept.
+
+use {
+    log::{info, warn},
+    std::{
+        env, fs,
+        path::PathBuf,
+        sync::{Arc, PoisonError, RwLock},
+        time::Duration,
+    },
+    tokio::{
+        process::Command,
+        time::{interval_at, timeout, Instant},
+    },
+    yellowstone_grpc_client::Interceptor,
+    yellowstone_grpc_proto::tonic::{metadata::AsciiMetadataValue, Request, Status},
+};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub enum TokenSource {
+    Static(Option<String>),
+    /// Shell command printing the token
+    Command(String),
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct XTokenConfig {
+    pub source: TokenSource,
+    /// Reload interval while connected, `None` reloads only on connect
+    pub refresh: Option<Duration>,
+    /// Variable of the source, for logs
+    name: String,
+}
+
+impl XTokenConfig {
+    /// Options are read from `<prefix>X_TOKEN`, `<prefix>X_TOKEN_CMD`, ...
+    pub fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let var = |key: &str| env::var(format!("{prefix}{key}")).ok();
+        let (source, name) = match (var("X_TOKEN"), var("X_TOKEN_CMD"), var("X_TOKEN_FILE")) {
+            (token, None, None) => (TokenSource::Static(token), "X_TOKEN"),
+            (None, Some(command), None) => (TokenSource::Command(command), "X_TOKEN_CMD"),
+            (None, None, Some(path)) => (TokenSource::File(path.into()), "X_TOKEN_FILE"),
+            _ => anyhow::bail!(
+                "only one of {prefix}X_TOKEN, {prefix}X_TOKEN_CMD and {prefix}X_TOKEN_FILE can be set"
+            ),
+        };
+        let refresh = var("X_TOKEN_REFRESH_SECS")
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| anyhow::anyhow!("invalid {prefix}X_TOKEN_REFRESH_SECS"))
+            })
+            .transpose()?;
+        if refresh.is_some() && matches!(source, TokenSource::Static(_)) {
+            anyhow::bail!(
+                "{prefix}X_TOKEN_REFRESH_SECS requires {prefix}X_TOKEN_CMD or {prefix}X_TOKEN_FILE"
+            );
+        }
+        Ok(Self {
+            source,
+            refresh,
+            name: format!("{prefix}{name}"),
+        })
+    }
+
+    /// Token can change, a rejected one is worth a reconnect
+    pub fn reloads(&self) -> bool {
+        !matches!(self.source, TokenSource::Static(_))
+    }
+
+    async fn load(&self) -> anyhow::Result<Option<AsciiMetadataValue>> {
+        let token = match &self.source {
+            TokenSource::Static(None) => return Ok(None),
+            TokenSource::Static(Some(token)) => token.clone(),
+            TokenSource::Command(command) => {
+                let output = timeout(
+                    COMMAND_TIMEOUT,
+                    Command::new("sh")
+                        .arg("-c")
+                        .arg(command)
+                        .kill_on_drop(true)
+                        .output(),
+                )
+                .await
+                .map_err(|_| anyhow::anyhow!("{} timed out", self.name))??;
+                anyhow::ensure!(
+                    output.status.success(),
+                    "{} failed: {}",
+                    self.name,
+                    output.status
+                );
+                String::from_utf8(output.stdout)
+                    .map_err(|_| anyhow::anyhow!("{} printed an invalid token", self.name))?
+            }
+            TokenSource::File(path) => fs::read_to_string(path).map_err(|error| {
+                anyhow::anyhow!("failed to read {} {}: {error}", self.name, path.display())
+            })?,
+        };
+        let token = token.trim();
+        anyhow::ensure!(!token.is_empty(), "{} returned an empty token", self.name);
+        // The token itself is not logged
+        let value = token
+            .parse()
+            .map_err(|_| anyhow::anyhow!("{} returned an invalid token", self.name))?;
+        Ok(Some(value))
+    }
+
+    /// Loads the token, with `refresh` it is reloaded until the interceptor and all its
+    /// clones are dropped with their client
+    pub async fn interceptor(&self) -> anyhow::Result<XTokenInterceptor> {
+        let token = Arc::new(RwLock::new(self.load().await?));
+        if let Some(refresh) = self.refresh {
+            let config = self.clone();
+            let shared = Arc::downgrade(&token);
+            tokio::spawn(async move {
+                let mut interval = interval_at(Instant::now() + refresh, refresh);
+                loop {
+                    interval.tick().await;
+                    if shared.strong_count() == 0 {
+                        break;
+                    }
+                    let value = match config.load().await {
+                        Ok(value) => value,
+                        Err(error) => {
+                            warn!("failed to refresh token, keeping the current one: {error}");
+                            continue;
+                        }
+                    };
+                    let Some(token) = shared.upgrade() else {
+                        break;
+                    };
+                    let mut current = token.write().unwrap_or_else(PoisonError::into_inner);
+                    if *current != value {
+                        *current = value;
+                        info!("token of {} changed", config.name);
+                    }
+                }
+            });
+        }
+        Ok(XTokenInterceptor { token })
+    }
+}
+
+/// Sets the `x-token` header to the current token
+#[derive(Debug, Clone)]
+pub struct XTokenInterceptor {
+    token: Arc<RwLock<Option<AsciiMetadataValue>>>,
+}
+
+impl Interceptor for XTokenInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = self.token.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(token) = token.as_ref() {
+            request.metadata_mut().insert("x-token", token.clone());
+        }
+        Ok(request)
+    }
+}
@@ -0,0 +1,70 @@
+//! Trace IDs of updates, `TRACE_IDS`.
+//!
+//! The ID is derived from what identifies the on-chain event, so the same event gets the
+//! same ID in every client instance, after reconnects and in replays: slot and signature
+//! for transactions and their statuses, slot, pubkey and write version for accounts, slot
+//! and status for slots, slot and blockhash for blocks and block metas, slot and index for
+//! entries. It is the first 16 bytes of the SHA-256 of these in hex, the format of a W3C
+//! trace context trace ID, so downstream systems can use it as is.
+
+use {
+    solana_sdk::hash::hashv,
+    std::env,
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+/// `TRACE_IDS=true`
+pub fn enabled() -> bool {
+    env::var("TRACE_IDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(false)
+}
+
+/// `None` for pings and pongs
+pub fn trace_id(msg: &SubscribeUpdate) -> Option<String> {
+    let hash = match msg.update_oneof.as_ref()? {
+        UpdateOneof::Account(update) => {
+            let account = update.account.as_ref()?;
+            hashv(&[
+                b"account",
+                &update.slot.to_le_bytes(),
+                &account.pubkey,
+                &account.write_version.to_le_bytes(),
+            ])
+        }
+        UpdateOneof::Slot(update) => hashv(&[
+            b"slot",
+            &update.slot.to_le_bytes(),
+            &update.status.to_le_bytes(),
+        ]),
+        UpdateOneof::Transaction(update) => {
+            let tx = update.transaction.as_ref()?;
+            hashv(&[b"transaction", &update.slot.to_le_bytes(), &tx.signature])
+        }
+        // Same ID as the transaction
+        UpdateOneof::TransactionStatus(update) => hashv(&[
+            b"transaction",
+            &update.slot.to_le_bytes(),
+            &update.signature,
+        ]),
+        UpdateOneof::Block(update) => hashv(&[
+            b"block",
+            &update.slot.to_le_bytes(),
+            update.blockhash.as_bytes(),
+        ]),
+        // Same ID as the block
+        UpdateOneof::BlockMeta(update) => hashv(&[
+            b"block",
+            &update.slot.to_le_bytes(),
+            update.blockhash.as_bytes(),
+        ]),
+        UpdateOneof::Entry(update) => hashv(&[
+            b"entry",
+            &update.slot.to_le_bytes(),
+            &update.index.to_le_bytes(),
+        ]),
+        UpdateOneof::Ping(_) | UpdateOneof::Pong(_) => return None,
+    };
+    Some(hex::encode(&hash.as_ref()[..16]))
+}
@@ -0,0 +1,99 @@
+//! Byte budgets of large fields for sinks with message size limits, `TRUNCATE_*`.
+//!
+//! A single account can hold 10MiB of data and a transaction thousands of log lines, which
+//! exceeds the message limits of Kafka brokers or WebSocket clients and fails the whole
+//! write. With `TRUNCATE_DATA_BYTES` account data is cut to the budget, with
+//! `TRUNCATE_LOGS_BYTES` transaction log messages are kept while they fit and the rest is
+//! dropped. A truncated update keeps all other fields and reports the original length in
+//! bytes of every truncated field, see `Truncated`.
+
+use {
+    std::{collections::BTreeMap, env},
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+/// Original length in bytes of the truncated fields, by field name
+#[derive(Debug, Default)]
+pub struct Truncated(pub BTreeMap<&'static str, usize>);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FieldBudget {
+    /// Account data
+    data: Option<usize>,
+    /// Sum of transaction log messages
+    logs: Option<usize>,
+}
+
+impl FieldBudget {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse = |key: &str| {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse::<usize>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+        Ok(Self {
+            data: parse("TRUNCATE_DATA_BYTES")?,
+            logs: parse("TRUNCATE_LOGS_BYTES")?,
+        })
+    }
+
+    /// Copy of the update with fields cut to the budget, `None` if every field fits
+    pub fn apply(&self, msg: &SubscribeUpdate) -> Option<(SubscribeUpdate, Truncated)> {
+        let exceeds = match msg.update_oneof.as_ref()? {
+            UpdateOneof::Account(update) => self.data.is_some_and(|budget| {
+                update
+                    .account
+                    .as_ref()
+                    .is_some_and(|account| account.data.len() > budget)
+            }),
+            UpdateOneof::Transaction(update) => self.logs.is_some_and(|budget| {
+                update
+                    .transaction
+                    .as_ref()
+                    .and_then(|tx| tx.meta.as_ref())
+                    .is_some_and(|meta| logs_len(&meta.log_messages) > budget)
+            }),
+            _ => false,
+        };
+        if !exceeds {
+            return None;
+        }
+
+        let mut msg = msg.clone();
+        let mut truncated = Truncated::default();
+        match msg.update_oneof.as_mut() {
+            Some(UpdateOneof::Account(update)) => {
+                if let (Some(account), Some(budget)) = (update.account.as_mut(), self.data) {
+                    truncated.0.insert("data", account.data.len());
+                    account.data.truncate(budget);
+                }
+            }
+            Some(UpdateOneof::Transaction(update)) => {
+                let meta = update.transaction.as_mut().and_then(|tx| tx.meta.as_mut());
+                if let (Some(meta), Some(budget)) = (meta, self.logs) {
+                    truncated
+                        .0
+                        .insert("log_messages", logs_len(&meta.log_messages));
+                    let mut len = 0;
+                    let keep = meta
+                        .log_messages
+                        .iter()
+                        .take_while(|message| {
+                            len += message.len();
+                            len <= budget
+                        })
+                        .count();
+                    meta.log_messages.truncate(keep);
+                }
+            }
+            _ => {}
+        }
+        Some((msg, truncated))
+    }
+}
+
+fn logs_len(messages: &[String]) -> usize {
+    messages.iter().map(String::len).sum()
+}
@@ -0,0 +1,122 @@
+//! Detect stalled or lagging streams.
+//!
+//! Slots are expected to advance at least once per `WATCHDOG_SLOT_MS`. The expected slot is
+//! anchored to the newest received slot whenever the stream keeps up, so with the default
+//! slot duration (a bit longer than the real one) lag only grows while the stream is stalled
+//! or delivers slots slower than the cluster produces them.
+
+use {
+    crate::stats::update_slot,
+    std::{env, time::Duration},
+    tokio::time::Instant,
+    yellowstone_grpc_proto::prelude::SubscribeUpdate,
+};
+
+const DEFAULT_SLOT_DURATION: Duration = Duration::from_millis(450);
+
+/// How often limits are checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchdogConfig {
+    /// Max time without a new slot
+    max_silence: Option<Duration>,
+    /// Max number of slots behind the expected slot
+    max_lag: Option<u64>,
+    slot_duration: Duration,
+}
+
+impl WatchdogConfig {
+    /// Returns `None` if neither `WATCHDOG_MAX_SILENCE_MS` nor `WATCHDOG_MAX_LAG_SLOTS` is set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let parse = |key: &str| {
+            env::var(key)
+                .ok()
+                .map(|value| value.parse::<u64>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid {key}"))
+        };
+        let max_silence = parse("WATCHDOG_MAX_SILENCE_MS")?.map(Duration::from_millis);
+        let max_lag = parse("WATCHDOG_MAX_LAG_SLOTS")?;
+        if max_silence.is_none() && max_lag.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            max_silence,
+            max_lag,
+            slot_duration: parse("WATCHDOG_SLOT_MS")?
+                .filter(|ms| *ms > 0)
+                .map_or(DEFAULT_SLOT_DURATION, Duration::from_millis),
+        }))
+    }
+}
+
+/// Watchdog state of a single stream, created on every (re)connect
+#[derive(Debug)]
+pub struct Watchdog {
+    config: WatchdogConfig,
+    newest_slot: Option<u64>,
+    /// Slot and time from which the expected slot is computed
+    anchor: Option<(u64, Instant)>,
+    last_progress: Instant,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            newest_slot: None,
+            anchor: None,
+            last_progress: Instant::now(),
+        }
+    }
+
+    pub fn observe(&mut self, msg: &SubscribeUpdate) {
+        let Some(slot) = update_slot(msg) else {
+            return;
+        };
+        if self.newest_slot.is_some_and(|newest| slot <= newest) {
+            return;
+        }
+
+        let now = Instant::now();
+        self.newest_slot = Some(slot);
+        self.last_progress = now;
+        if self.lag(now) == 0 {
+            self.anchor = Some((slot, now));
+        }
+    }
+
+    fn lag(&self, now: Instant) -> u64 {
+        let (Some((slot, since)), Some(newest)) = (self.anchor, self.newest_slot) else {
+            return 0;
+        };
+        let elapsed = now.duration_since(since).as_millis() / self.config.slot_duration.as_millis();
+        (slot + elapsed as u64).saturating_sub(newest)
+    }
+
+    /// Returns the reason to reconnect if a limit is exceeded
+    pub fn check(&self) -> Option<String> {
+        let now = Instant::now();
+        let silence = now.duration_since(self.last_progress);
+        if let Some(max_silence) = self.config.max_silence {
+            if silence > max_silence {
+                return Some(match self.newest_slot {
+                    Some(slot) => format!("no new slot for {silence:?}, newest slot {slot}"),
+                    None => format!("no slot received for {silence:?}"),
+                });
+            }
+        }
+        if let Some(max_lag) = self.config.max_lag {
+            let lag = self.lag(now);
+            if lag > max_lag {
+                return Some(format!(
+                    "stream is {lag} slots behind expected slot, newest slot {}",
+                    self.newest_slot.unwrap_or_default()
+                ));
+            }
+        }
+        None
+    }
+}
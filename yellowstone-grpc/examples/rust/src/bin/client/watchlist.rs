@@ -0,0 +1,196 @@
+//! Watched pubkeys with labels and alert rules, `WATCHLIST_PATH`.
+//!
+//! The file is JSON, an object mapping pubkeys to a label or to `{"label": .., "alerts":
+//! [..]}`, or CSV if the name ends with `.csv`, lines of `pubkey,label[,alerts]` with alerts
+//! separated by `;`. An update touches a pubkey if it is the updated account or one of the
+//! accounts of the transaction, such updates get the tag `label` with the labels of all
+//! touched pubkeys (see `tags`). Alert rules are checked by the watchlist sink:
+//! `lamports_drop:<lamports>` when the balance of the account falls by more than that
+//! between two updates, `transaction` for every transaction touching the pubkey.
+
+use {
+    log::info,
+    serde::Deserialize,
+    std::{collections::HashMap, env, fmt, fs},
+    yellowstone_grpc_proto::prelude::{subscribe_update::UpdateOneof, SubscribeUpdate},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertRule {
+    /// Lamports of the account fell by more than this since its previous update
+    LamportsDrop(u64),
+    /// Any transaction with the pubkey among its accounts
+    Transaction,
+}
+
+impl AlertRule {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().split_once(':') {
+            None if value.trim() == "transaction" => Ok(Self::Transaction),
+            Some(("lamports_drop", lamports)) => lamports
+                .trim()
+                .parse()
+                .map(Self::LamportsDrop)
+                .map_err(|_| anyhow::anyhow!("invalid lamports in alert `{value}`")),
+            _ => anyhow::bail!(
+                "invalid alert `{value}`, expected `lamports_drop:<lamports>` or `transaction`"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LamportsDrop(lamports) => write!(f, "lamports_drop:{lamports}"),
+            Self::Transaction => write!(f, "transaction"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WatchedKey {
+    /// Base58
+    pub pubkey: String,
+    pub label: String,
+    pub rules: Vec<AlertRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Label(String),
+    Entry {
+        label: String,
+        #[serde(default)]
+        alerts: Vec<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Watchlist {
+    /// By decoded pubkey
+    keys: HashMap<Vec<u8>, WatchedKey>,
+}
+
+impl Watchlist {
+    /// Returns `None` if `WATCHLIST_PATH` is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(path) = env::var("WATCHLIST_PATH") else {
+            return Ok(None);
+        };
+        let watchlist = Self::read(&path)
+            .map_err(|error| anyhow::anyhow!("invalid WATCHLIST_PATH {path}: {error}"))?;
+        info!(
+            "watchlist of {} pubkeys loaded from {path}",
+            watchlist.keys.len()
+        );
+        Ok(Some(watchlist))
+    }
+
+    fn read(path: &str) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let entries = if path.ends_with(".csv") {
+            Self::parse_csv(&content)?
+        } else {
+            serde_json::from_str::<HashMap<String, JsonEntry>>(&content)?
+                .into_iter()
+                .map(|(pubkey, entry)| match entry {
+                    JsonEntry::Label(label) => Ok((pubkey, label, vec![])),
+                    JsonEntry::Entry { label, alerts } => {
+                        let rules = alerts
+                            .iter()
+                            .map(|rule| AlertRule::parse(rule))
+                            .collect::<anyhow::Result<_>>()?;
+                        Ok((pubkey, label, rules))
+                    }
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+
+        let mut keys = HashMap::new();
+        for (pubkey, label, rules) in entries {
+            let decoded = bs58::decode(&pubkey)
+                .into_vec()
+                .ok()
+                .filter(|decoded| decoded.len() == 32)
+                .ok_or_else(|| anyhow::anyhow!("invalid pubkey: {pubkey}"))?;
+            anyhow::ensure!(!keys.contains_key(&decoded), "duplicate pubkey: {pubkey}");
+            let key = WatchedKey {
+                pubkey,
+                label,
+                rules,
+            };
+            keys.insert(decoded, key);
+        }
+        Ok(Self { keys })
+    }
+
+    /// `pubkey,label[,alerts]`, empty lines, `#` comments and a `pubkey,...` header are
+    /// skipped
+    fn parse_csv(content: &str) -> anyhow::Result<Vec<(String, String, Vec<AlertRule>)>> {
+        let mut entries = vec![];
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("pubkey,") {
+                continue;
+            }
+            let mut fields = line.splitn(3, ',').map(str::trim);
+            let (Some(pubkey), Some(label)) = (fields.next(), fields.next()) else {
+                anyhow::bail!("line {}: expected `pubkey,label[,alerts]`", number + 1);
+            };
+            let rules = fields
+                .next()
+                .into_iter()
+                .flat_map(|alerts| alerts.split(';'))
+                .filter(|rule| !rule.trim().is_empty())
+                .map(AlertRule::parse)
+                .collect::<anyhow::Result<_>>()
+                .map_err(|error| anyhow::anyhow!("line {}: {error}", number + 1))?;
+            entries.push((pubkey.to_owned(), label.to_owned(), rules));
+        }
+        Ok(entries)
+    }
+
+    pub fn has_alerts(&self) -> bool {
+        self.keys.values().any(|key| !key.rules.is_empty())
+    }
+
+    /// Watched pubkeys touched by the update
+    pub fn touched(&self, msg: &SubscribeUpdate) -> Vec<&WatchedKey> {
+        match msg.update_oneof.as_ref() {
+            Some(UpdateOneof::Account(update)) => update
+                .account
+                .as_ref()
+                .and_then(|account| self.keys.get(&account.pubkey))
+                .into_iter()
+                .collect(),
+            Some(UpdateOneof::Transaction(update)) => {
+                let Some(tx) = update.transaction.as_ref() else {
+                    return vec![];
+                };
+                let keys = tx
+                    .transaction
+                    .as_ref()
+                    .and_then(|tx| tx.message.as_ref())
+                    .map(|message| message.account_keys.iter())
+                    .into_iter()
+                    .flatten();
+                let loaded = tx
+                    .meta
+                    .as_ref()
+                    .map(|meta| {
+                        meta.loaded_writable_addresses
+                            .iter()
+                            .chain(meta.loaded_readonly_addresses.iter())
+                    })
+                    .into_iter()
+                    .flatten();
+                keys.chain(loaded)
+                    .filter_map(|key| self.keys.get(key))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
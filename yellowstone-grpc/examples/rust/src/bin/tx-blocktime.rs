@@ -1,5 +1,5 @@
 use {
-    chrono::{DateTime, NaiveDateTime, Utc},
+    chrono::{DateTime, Utc},
     clap::{Parser, ValueEnum},
     futures::{sink::SinkExt, stream::StreamExt},
     log::{error, info},
@@ -130,12 +130,9 @@ async fn main() -> anyhow::Result<()> {
                     }
                     Some(UpdateOneof::BlockMeta(block)) => {
                         let entry = messages.entry(block.slot).or_default();
-                        entry.0 = block.block_time.map(|obj| {
-                            DateTime::from_naive_utc_and_offset(
-                                NaiveDateTime::from_timestamp_opt(obj.timestamp, 0).unwrap(),
-                                Utc,
-                            )
-                        });
+                        entry.0 = block
+                            .block_time
+                            .map(|obj| DateTime::from_timestamp(obj.timestamp, 0).unwrap());
                         if let Some(timestamp) = entry.0 {
                             for sig in &entry.1 {
                                 info!("received txn {} at {}", sig, timestamp);
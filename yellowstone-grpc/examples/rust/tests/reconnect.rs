@@ -0,0 +1,292 @@
+//! The client binary against a Geyser server which ends every stream, it has to connect and
+//! subscribe again instead of exiting, with the current token when it is loaded from a file.
+
+use {
+    futures::stream::{self, Stream, StreamExt},
+    std::{
+        fs,
+        net::SocketAddr,
+        pin::Pin,
+        process::{Child, Command, Stdio},
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tempfile::TempDir,
+    tokio::{net::TcpListener, time::Instant},
+    tonic::{transport::Server, Request, Response, Status, Streaming},
+    yellowstone_grpc_proto::prelude::{
+        geyser_server::{Geyser, GeyserServer},
+        subscribe_update::UpdateOneof,
+        GetBlockHeightRequest, GetBlockHeightResponse, GetLatestBlockhashRequest,
+        GetLatestBlockhashResponse, GetSlotRequest, GetSlotResponse, GetVersionRequest,
+        GetVersionResponse, IsBlockhashValidRequest, IsBlockhashValidResponse, PingRequest,
+        PongResponse, SubscribeRequest, SubscribeUpdate, SubscribeUpdateSlot,
+    },
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// `MAX_DECODING_MESSAGE_SIZE` of the client for `StreamEnd::Oversized`
+const OVERSIZED: usize = 1024;
+
+/// How the server ends a stream after its first update
+#[derive(Debug, Clone, Copy, Default)]
+enum StreamEnd {
+    /// Finish the stream without an error
+    #[default]
+    Close,
+    /// Fail the stream with `UNAVAILABLE`
+    Fail,
+    /// Fail the stream with `UNAUTHENTICATED` once its token is no longer accepted
+    Expire,
+    /// Send an update over `OVERSIZED` bytes instead, nothing is received by the client
+    Oversized,
+}
+
+/// Sends one slot update on every stream, then ends it
+#[derive(Debug, Default)]
+struct EndingGeyser {
+    end: StreamEnd,
+    /// The only accepted `x-token`, any token is accepted without it
+    token: Option<Arc<Mutex<String>>>,
+    /// `x-token` of every accepted subscribe
+    subscribes: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+impl EndingGeyser {
+    fn accepts(&self, token: Option<&str>) -> bool {
+        self.token
+            .as_ref()
+            .is_none_or(|accepted| token == Some(accepted.lock().unwrap().as_str()))
+    }
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Geyser for EndingGeyser {
+    type SubscribeStream = UpdateStream;
+
+    async fn subscribe(
+        &self,
+        request: Request<Streaming<SubscribeRequest>>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let token = request
+            .metadata()
+            .get("x-token")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        if !self.accepts(token.as_deref()) {
+            return Err(Status::unauthenticated("invalid token"));
+        }
+        let slot = {
+            let mut subscribes = self.subscribes.lock().unwrap();
+            subscribes.push(token.clone());
+            subscribes.len() as u64
+        };
+        let update = SubscribeUpdate {
+            filters: vec!["client".to_owned()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: None,
+                status: 0,
+            })),
+        };
+        let updates = match self.end {
+            StreamEnd::Close => vec![Ok(update)],
+            StreamEnd::Oversized => vec![Ok(SubscribeUpdate {
+                filters: vec!["x".repeat(OVERSIZED)],
+                ..update
+            })],
+            StreamEnd::Fail => vec![Ok(update), Err(Status::unavailable("restarting"))],
+            StreamEnd::Expire => {
+                let accepted = self.token.clone().expect("token to expire");
+                let expired = async move {
+                    while token.as_deref() == Some(accepted.lock().unwrap().as_str()) {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                    Err(Status::unauthenticated("token expired"))
+                };
+                let updates = stream::iter([Ok(update)]).chain(stream::once(expired));
+                return Ok(Response::new(Box::pin(updates)));
+            }
+        };
+        Ok(Response::new(Box::pin(stream::iter(updates))))
+    }
+
+    async fn ping(&self, _request: Request<PingRequest>) -> Result<Response<PongResponse>, Status> {
+        Err(Status::unimplemented("ping"))
+    }
+
+    async fn get_latest_blockhash(
+        &self,
+        _request: Request<GetLatestBlockhashRequest>,
+    ) -> Result<Response<GetLatestBlockhashResponse>, Status> {
+        Err(Status::unimplemented("get_latest_blockhash"))
+    }
+
+    async fn get_block_height(
+        &self,
+        _request: Request<GetBlockHeightRequest>,
+    ) -> Result<Response<GetBlockHeightResponse>, Status> {
+        Err(Status::unimplemented("get_block_height"))
+    }
+
+    async fn get_slot(
+        &self,
+        _request: Request<GetSlotRequest>,
+    ) -> Result<Response<GetSlotResponse>, Status> {
+        Err(Status::unimplemented("get_slot"))
+    }
+
+    async fn is_blockhash_valid(
+        &self,
+        _request: Request<IsBlockhashValidRequest>,
+    ) -> Result<Response<IsBlockhashValidResponse>, Status> {
+        Err(Status::unimplemented("is_blockhash_valid"))
+    }
+
+    async fn get_version(
+        &self,
+        _request: Request<GetVersionRequest>,
+    ) -> Result<Response<GetVersionResponse>, Status> {
+        Err(Status::unimplemented("get_version"))
+    }
+}
+
+async fn serve(geyser: impl Geyser) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let incoming = stream::unfold(listener, |listener| async move {
+        let conn = listener.accept().await.map(|(stream, _)| stream);
+        Some((conn, listener))
+    });
+    tokio::spawn(
+        Server::builder()
+            .add_service(GeyserServer::new(geyser))
+            .serve_with_incoming(incoming),
+    );
+    addr
+}
+
+/// Client process subscribed to slots, killed on drop
+struct Client {
+    child: Child,
+    /// Empty working directory, a `.env` of the developer is not loaded
+    _dir: TempDir,
+}
+
+impl Client {
+    fn subscribe(addr: SocketAddr, env: &[(&str, &str)]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_client"))
+            .current_dir(dir.path())
+            .env_clear()
+            .env("ENDPOINT", format!("http://{addr}"))
+            .env("ACTION", "Subscribe")
+            .env("SUBSCRIBE_SLOTS", "true")
+            .env("RETRY_INITIAL_MS", "10")
+            .env("RETRY_MAX_MS", "10")
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Self { child, _dir: dir }
+    }
+
+    fn exited(&mut self) -> bool {
+        self.child.try_wait().unwrap().is_some()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits until the server accepted `count` subscribes, fails if the client exits before
+async fn wait_for_subscribes(
+    client: &mut Client,
+    subscribes: &Mutex<Vec<Option<String>>>,
+    count: usize,
+) {
+    let deadline = Instant::now() + TIMEOUT;
+    while subscribes.lock().unwrap().len() < count {
+        assert!(!client.exited(), "client exited after the stream ended");
+        assert!(
+            Instant::now() < deadline,
+            "no subscribe {count} in {TIMEOUT:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn subscribes_again_after_server_closed_stream() {
+    let geyser = EndingGeyser::default();
+    let subscribes = Arc::clone(&geyser.subscribes);
+    let addr = serve(geyser).await;
+    let mut client = Client::subscribe(addr, &[]);
+    wait_for_subscribes(&mut client, &subscribes, 3).await;
+}
+
+#[tokio::test]
+async fn subscribes_again_after_stream_error() {
+    let geyser = EndingGeyser {
+        end: StreamEnd::Fail,
+        ..Default::default()
+    };
+    let subscribes = Arc::clone(&geyser.subscribes);
+    let addr = serve(geyser).await;
+    let mut client = Client::subscribe(addr, &[]);
+    wait_for_subscribes(&mut client, &subscribes, 3).await;
+}
+
+#[tokio::test]
+async fn subscribes_with_reloaded_token_after_rejection_mid_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("token");
+    fs::write(&path, "old\n").unwrap();
+    let token = Arc::new(Mutex::new("old".to_owned()));
+    let geyser = EndingGeyser {
+        end: StreamEnd::Expire,
+        token: Some(Arc::clone(&token)),
+        ..Default::default()
+    };
+    let subscribes = Arc::clone(&geyser.subscribes);
+    let addr = serve(geyser).await;
+    let mut client = Client::subscribe(addr, &[("X_TOKEN_FILE", path.to_str().unwrap())]);
+    wait_for_subscribes(&mut client, &subscribes, 1).await;
+
+    // The open stream is rejected, the client reconnects instead of exiting as for a fixed
+    // X_TOKEN and loads the new token
+    fs::write(&path, "new\n").unwrap();
+    *token.lock().unwrap() = "new".to_owned();
+    wait_for_subscribes(&mut client, &subscribes, 2).await;
+    assert_eq!(
+        *subscribes.lock().unwrap(),
+        [Some("old".to_owned()), Some("new".to_owned())]
+    );
+}
+
+#[tokio::test]
+async fn reconnects_after_stream_recoveries_are_exhausted() {
+    let geyser = EndingGeyser {
+        end: StreamEnd::Oversized,
+        ..Default::default()
+    };
+    let subscribes = Arc::clone(&geyser.subscribes);
+    let addr = serve(geyser).await;
+    let limit = OVERSIZED.to_string();
+    let mut client = Client::subscribe(
+        addr,
+        &[
+            ("MAX_DECODING_MESSAGE_SIZE", &limit),
+            ("RETRY_STREAM_RECOVERIES", "1"),
+        ],
+    );
+    // Subscribe, recovery on the same connection, reconnect, recovery
+    wait_for_subscribes(&mut client, &subscribes, 4).await;
+}